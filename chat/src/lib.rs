@@ -0,0 +1,170 @@
+//! The member chat, on the P2P network and on the chain.
+//!
+//! The members chat through a DMS of the `chat` namespace, signing each message with their
+//! governance keys. The messages of a height are aggregated into a chat-log commit
+//! on the repository, whose Merkle root goes into the next block header.
+use serde::{Deserialize, Serialize};
+use simperby_common::*;
+use simperby_network::{
+    dms::{DistributedMessageSet as DMS, Message},
+    primitives::{GossipNetwork, MessageStore},
+    signer::Signer,
+    NetworkConfig, Peer, SharedKnownPeers,
+};
+
+pub type Error = anyhow::Error;
+
+pub mod message;
+pub mod private;
+pub mod review;
+
+/// The DMS key of the chat, to be prefixed by the network id.
+pub const CHAT_DMS_KEY: &str = "chat";
+/// The maximum length of a chat message, in bytes.
+pub const MAX_TEXT_LENGTH: usize = 4096;
+
+pub struct Chat<N: GossipNetwork, S: MessageStore> {
+    pub dms: DMS<N, S>,
+}
+
+fn get_timestamp() -> Timestamp {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+    since_the_epoch.as_millis() as Timestamp
+}
+
+impl<N: GossipNetwork, S: MessageStore> Chat<N, S> {
+    pub async fn open(dms: DMS<N, S>) -> Result<Self, Error> {
+        Ok(Self { dms })
+    }
+
+    /// Says the message, signed by the governance key of the signer.
+    pub async fn send(
+        &mut self,
+        network_config: &NetworkConfig,
+        known_peers: &[Peer],
+        text: String,
+        off_the_record: bool,
+        signer: &dyn Signer,
+    ) -> Result<(), Error> {
+        let message = message::encode(
+            ChatMessage {
+                author: signer.public_key(),
+                text,
+                timestamp: get_timestamp(),
+                off_the_record,
+            },
+            signer,
+            network_config,
+        )
+        .await?;
+        self.dms
+            .add_message(network_config, known_peers, message)
+            .await?;
+        Ok(())
+    }
+
+    /// Comments on a line of the diff of an agenda, as a message of the chat
+    /// (see `review::ReviewComment::to_chat_text()`).
+    pub async fn comment(
+        &mut self,
+        network_config: &NetworkConfig,
+        known_peers: &[Peer],
+        comment: review::ReviewComment,
+        off_the_record: bool,
+        signer: &dyn Signer,
+    ) -> Result<(), Error> {
+        comment.validate()?;
+        self.send(
+            network_config,
+            known_peers,
+            comment.to_chat_text(),
+            off_the_record,
+            signer,
+        )
+        .await
+    }
+
+    /// Says the message in the private channel, encrypted for its members.
+    pub async fn send_private(
+        &mut self,
+        network_config: &NetworkConfig,
+        known_peers: &[Peer],
+        channel: private::Channel,
+        text: String,
+        signer: &dyn Signer,
+    ) -> Result<(), Error> {
+        let encrypted = private::encrypt(channel, text, get_timestamp(), signer).await?;
+        let message = message::encode_private(encrypted, network_config)?;
+        self.dms
+            .add_message(network_config, known_peers, message)
+            .await?;
+        Ok(())
+    }
+
+    /// Reads the messages of the private channels that the private key is a member of.
+    pub async fn read_private(
+        &self,
+        reserved_state: &reserved::ReservedState,
+        private_key: &PrivateKey,
+    ) -> Result<Vec<private::PrivateChatMessage>, Error> {
+        let messages = self.dms.read_messages().await?;
+        Ok(message::read_private(
+            &messages,
+            reserved_state,
+            private_key,
+        ))
+    }
+
+    /// Reads the chat history of the current height, including the off-the-record messages.
+    pub async fn read(
+        &self,
+        reserved_state: &reserved::ReservedState,
+    ) -> Result<Vec<(ChatMessage, TypedSignature<ChatMessage>)>, Error> {
+        let messages = self.dms.read_messages().await?;
+        Ok(message::read(&messages, reserved_state))
+    }
+
+    /// Reads the review comments on the agenda said in the current height.
+    pub async fn read_comments(
+        &self,
+        reserved_state: &reserved::ReservedState,
+        agenda_hash: &Hash256,
+    ) -> Result<Vec<review::SignedReviewComment>, Error> {
+        let messages = self.read(reserved_state).await?;
+        Ok(review::read_comments(&messages, agenda_hash))
+    }
+
+    /// Aggregates the messages of the current height into a chat log, for the chat-log commit.
+    pub async fn create_chat_log(
+        &self,
+        reserved_state: &reserved::ReservedState,
+    ) -> Result<ChatLog, Error> {
+        let messages = self.dms.read_messages().await?;
+        Ok(message::collect(&messages, reserved_state))
+    }
+
+    /// Advances the block height, discarding all the messages
+    /// (which must have been recorded in a chat-log commit).
+    pub async fn advance(&mut self) -> Result<(), Error> {
+        self.dms.advance().await
+    }
+
+    pub async fn fetch(
+        &mut self,
+        network_config: &NetworkConfig,
+        known_peers: &[Peer],
+    ) -> Result<(), Error> {
+        self.dms.fetch(network_config, known_peers).await
+    }
+
+    /// Serves the chat indefinitely.
+    pub async fn serve(
+        self,
+        network_config: NetworkConfig,
+        rpc_port: u16,
+        peers: SharedKnownPeers,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
+        self.dms.serve(network_config, rpc_port, peers).await
+    }
+}
@@ -0,0 +1,237 @@
+//! The encoding of the chat messages as DMS messages, and the aggregation of them into a chat log.
+//!
+//! Each DMS message carries a single `ChatMessage` in JSON with the signature of its author,
+//! which must be the key of a member, or an encrypted message of a private channel (see `private`).
+//! The off-the-record and the private messages are shared on the network
+//! but never go into a chat log.
+use super::*;
+use private::{EncryptedMessage, PrivateChatMessage};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Envelope {
+    Public {
+        message: ChatMessage,
+        signature: TypedSignature<ChatMessage>,
+    },
+    Private(EncryptedMessage),
+}
+
+fn to_message(envelope: &Envelope, network_config: &NetworkConfig) -> Result<Message, Error> {
+    let data = serde_json::to_string(envelope)?;
+    let message = Message::new(
+        data.clone(),
+        TypedSignature::sign(&data, &network_config.private_key)?,
+    )?;
+    Ok(message)
+}
+
+/// Signs the chat message with the governance key and encodes it as a DMS message.
+pub async fn encode(
+    message: ChatMessage,
+    signer: &dyn Signer,
+    network_config: &NetworkConfig,
+) -> Result<Message, Error> {
+    if message.author != signer.public_key() {
+        return Err(anyhow::anyhow!("the author is not the signer"));
+    }
+    if message.text.len() > MAX_TEXT_LENGTH {
+        return Err(anyhow::anyhow!(
+            "the message is too long: {} bytes",
+            message.text.len()
+        ));
+    }
+    let signature = TypedSignature::new(
+        signer.sign(message.to_hash256()).await?,
+        signer.public_key(),
+    );
+    to_message(&Envelope::Public { message, signature }, network_config)
+}
+
+/// Encodes the encrypted message of a private channel as a DMS message.
+pub fn encode_private(
+    message: EncryptedMessage,
+    network_config: &NetworkConfig,
+) -> Result<Message, Error> {
+    to_message(&Envelope::Private(message), network_config)
+}
+
+/// Decodes and verifies a DMS message carrying a chat message,
+/// returning `None` for the messages of the private channels.
+pub fn decode(
+    message: &Message,
+    reserved_state: &reserved::ReservedState,
+) -> Result<Option<(ChatMessage, TypedSignature<ChatMessage>)>, Error> {
+    let (message, signature) = match serde_json::from_str(message.data())? {
+        Envelope::Public { message, signature } => (message, signature),
+        Envelope::Private(_) => return Ok(None),
+    };
+    let author = &message.author;
+    if !reserved_state
+        .members
+        .iter()
+        .any(|member| &member.public_key == author)
+    {
+        return Err(anyhow::anyhow!("{} is not a member", author));
+    }
+    if signature.signer() != author {
+        return Err(anyhow::anyhow!("the signer is not the author"));
+    }
+    if message.text.len() > MAX_TEXT_LENGTH {
+        return Err(anyhow::anyhow!("the message is too long"));
+    }
+    signature.verify(&message)?;
+    Ok(Some((message, signature)))
+}
+
+/// Decodes all the chat messages of the DMS, discarding the invalid ones and the duplicates,
+/// in the order of the timestamps and then of the hashes.
+pub fn read(
+    messages: &[Message],
+    reserved_state: &reserved::ReservedState,
+) -> Vec<(ChatMessage, TypedSignature<ChatMessage>)> {
+    let mut result = BTreeMap::new();
+    for message in messages {
+        match decode(message, reserved_state) {
+            Ok(Some((message, signature))) => {
+                result.insert(
+                    (message.timestamp, message.to_hash256()),
+                    (message, signature),
+                );
+            }
+            Ok(None) => (),
+            Err(e) => log::warn!("discarded an invalid chat message: {}", e),
+        }
+    }
+    result.into_values().collect()
+}
+
+/// Decrypts the messages of the private channels that the private key is a member of,
+/// in the order of the timestamps and then of the hashes.
+pub fn read_private(
+    messages: &[Message],
+    reserved_state: &reserved::ReservedState,
+    private_key: &PrivateKey,
+) -> Vec<PrivateChatMessage> {
+    let public_key = private_key.public_key();
+    let mut result = BTreeMap::new();
+    for message in messages {
+        let encrypted = match serde_json::from_str(message.data()) {
+            Ok(Envelope::Private(encrypted)) => encrypted,
+            _ => continue,
+        };
+        if !encrypted.content.channel.members().contains(&public_key) {
+            continue;
+        }
+        match private::verify(&encrypted, reserved_state)
+            .and_then(|_| private::decrypt(&encrypted, private_key))
+        {
+            Ok(decrypted) => {
+                result.insert(
+                    (encrypted.content.timestamp, encrypted.content.to_hash256()),
+                    decrypted,
+                );
+            }
+            Err(e) => log::warn!("discarded an invalid private chat message: {}", e),
+        }
+    }
+    result.into_values().collect()
+}
+
+/// Aggregates the chat messages of the DMS into a chat log, leaving out the off-the-record ones.
+pub fn collect(messages: &[Message], reserved_state: &reserved::ReservedState) -> ChatLog {
+    ChatLog {
+        messages: read(messages, reserved_state)
+            .into_iter()
+            .filter(|(message, _)| !message.off_the_record)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simperby_common::crypto::generate_keypair;
+    use simperby_common::test_util::genesis;
+    use simperby_network::signer::LocalSigner;
+
+    fn network_config() -> NetworkConfig {
+        let (public_key, private_key) = generate_keypair("network");
+        NetworkConfig {
+            network_id: "test".to_string(),
+            chain_id: Default::default(),
+            port: None,
+            members: Vec::new(),
+            public_key,
+            private_key,
+        }
+    }
+
+    async fn say(name: &str, text: &str, timestamp: Timestamp, off_the_record: bool) -> Message {
+        let signer = LocalSigner::new(generate_keypair(name).1);
+        encode(
+            ChatMessage {
+                author: signer.public_key(),
+                text: text.to_owned(),
+                timestamp,
+                off_the_record,
+            },
+            &signer,
+            &network_config(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn chat_log() {
+        let reserved_state = genesis(&["a", "b"]);
+        let messages = vec![
+            say("b", "second", 2, false),
+            say("a", "first", 1, false),
+            say("a", "first", 1, false),
+            say("a", "secret", 3, true),
+            say("c", "not a member", 4, false),
+        ];
+        let texts = |log: Vec<(ChatMessage, TypedSignature<ChatMessage>)>| {
+            log.into_iter()
+                .map(|(message, _)| message.text)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            texts(read(&messages, &reserved_state)),
+            vec!["first", "second", "secret"]
+        );
+        let log = collect(&messages, &reserved_state);
+        assert_eq!(texts(log.messages.clone()), vec!["first", "second"]);
+        assert_ne!(log.merkle_root(), Hash256::zero());
+    }
+
+    #[tokio::test]
+    async fn private_messages_not_in_chat_log() {
+        let reserved_state = genesis(&["a", "b", "c"]);
+        let channel = private::Channel::new(vec![generate_keypair("a").0, generate_keypair("b").0]);
+        let encrypted = private::encrypt(
+            channel,
+            "private".to_owned(),
+            2,
+            &LocalSigner::new(generate_keypair("a").1),
+        )
+        .await
+        .unwrap();
+        let messages = vec![
+            say("a", "public", 1, false),
+            encode_private(encrypted, &network_config()).unwrap(),
+        ];
+        assert_eq!(read(&messages, &reserved_state).len(), 1);
+        assert_eq!(collect(&messages, &reserved_state).messages.len(), 1);
+        let private_texts = |name: &str| {
+            read_private(&messages, &reserved_state, &generate_keypair(name).1)
+                .into_iter()
+                .map(|decrypted| decrypted.message.text)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(private_texts("b"), vec!["private"]);
+        assert!(private_texts("c").is_empty());
+    }
+}
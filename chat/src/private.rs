@@ -0,0 +1,266 @@
+//! The end-to-end encrypted channels among the members.
+//!
+//! A channel is the set of the members who can read its messages (including the sender).
+//! Each message is encrypted with a fresh content key, which is wrapped for each member of the channel
+//! with the X25519 key agreement between an ephemeral key and the member key,
+//! converted from Ed25519 to its Montgomery form.
+//! Sending thus needs only the signer, while reading needs the private key of a member.
+//!
+//! The encrypted messages are shared through the chat DMS like the others,
+//! but never go into a chat log.
+use super::*;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use curve25519_dalek::constants::X25519_BASEPOINT;
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use rand::RngCore;
+use sha2::{Digest, Sha512};
+
+const KEY_WRAP_CONTEXT: &str = "simperby 2023 chat private channel key wrap";
+
+/// The members of a private channel, sorted and deduplicated.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Channel {
+    members: Vec<PublicKey>,
+}
+
+impl Channel {
+    pub fn new(members: impl IntoIterator<Item = PublicKey>) -> Self {
+        let mut members: Vec<_> = members.into_iter().collect();
+        members.sort();
+        members.dedup();
+        Self { members }
+    }
+
+    pub fn members(&self) -> &[PublicKey] {
+        &self.members
+    }
+}
+
+/// An encrypted message, as signed by the sender.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedContent {
+    pub sender: PublicKey,
+    pub channel: Channel,
+    pub timestamp: Timestamp,
+    /// The ephemeral X25519 public key of the key agreement.
+    pub ephemeral_public_key: [u8; 32],
+    /// The content key wrapped for each member, in the order of `channel.members()`.
+    pub wrapped_keys: Vec<Vec<u8>>,
+    pub nonce: [u8; 12],
+    pub ciphertext: Vec<u8>,
+}
+
+impl ToHash256 for EncryptedContent {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedMessage {
+    pub content: EncryptedContent,
+    pub signature: TypedSignature<EncryptedContent>,
+}
+
+/// A decrypted message, with the channel it was sent to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivateChatMessage {
+    pub channel: Channel,
+    pub message: ChatMessage,
+}
+
+fn clamp(mut bytes: [u8; 32]) -> Scalar {
+    bytes[0] &= 248;
+    bytes[31] &= 127;
+    bytes[31] |= 64;
+    Scalar::from_bits(bytes)
+}
+
+/// Converts the Ed25519 private key to the X25519 one, as its expanded secret scalar.
+fn to_x25519_private_key(private_key: &PrivateKey) -> Scalar {
+    let hash = Sha512::digest(private_key.as_ref());
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(&hash[..32]);
+    clamp(bytes)
+}
+
+/// Converts the Ed25519 public key to the X25519 one, the Montgomery form of the point.
+fn to_x25519_public_key(public_key: &PublicKey) -> Result<MontgomeryPoint, Error> {
+    if public_key.as_ref().len() != 32 {
+        return Err(anyhow::anyhow!("invalid public key: {}", public_key));
+    }
+    let point = CompressedEdwardsY::from_slice(public_key.as_ref())
+        .decompress()
+        .ok_or_else(|| anyhow::anyhow!("invalid public key: {}", public_key))?;
+    Ok(point.to_montgomery())
+}
+
+fn wrapping_key(
+    shared_secret: &MontgomeryPoint,
+    ephemeral_public_key: &[u8; 32],
+    member: &PublicKey,
+) -> ChaCha20Poly1305 {
+    let mut material = shared_secret.to_bytes().to_vec();
+    material.extend_from_slice(ephemeral_public_key);
+    material.extend_from_slice(member.as_ref());
+    let key = blake3::derive_key(KEY_WRAP_CONTEXT, &material);
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+/// Encrypts the text for the channel and signs it with the governance key of the signer,
+/// who must be a member of the channel.
+pub async fn encrypt(
+    channel: Channel,
+    text: String,
+    timestamp: Timestamp,
+    signer: &dyn Signer,
+) -> Result<EncryptedMessage, Error> {
+    let sender = signer.public_key();
+    if !channel.members.contains(&sender) {
+        return Err(anyhow::anyhow!("the sender is not a member of the channel"));
+    }
+    if text.len() > MAX_TEXT_LENGTH {
+        return Err(anyhow::anyhow!(
+            "the message is too long: {} bytes",
+            text.len()
+        ));
+    }
+    let mut rng = rand::thread_rng();
+    let mut content_key = [0; 32];
+    rng.fill_bytes(&mut content_key);
+    let mut nonce = [0; 12];
+    rng.fill_bytes(&mut nonce);
+    let mut ephemeral_private_key = [0; 32];
+    rng.fill_bytes(&mut ephemeral_private_key);
+    let ephemeral_private_key = clamp(ephemeral_private_key);
+    let ephemeral_public_key = (X25519_BASEPOINT * ephemeral_private_key).to_bytes();
+
+    let mut wrapped_keys = Vec::new();
+    for member in &channel.members {
+        let shared_secret = to_x25519_public_key(member)? * ephemeral_private_key;
+        // The wrapping key is used only once, so the nonce can be fixed.
+        let wrapped = wrapping_key(&shared_secret, &ephemeral_public_key, member)
+            .encrypt(Nonce::from_slice(&[0; 12]), content_key.as_ref())
+            .map_err(|_| anyhow::anyhow!("failed to wrap the content key"))?;
+        wrapped_keys.push(wrapped);
+    }
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&content_key))
+        .encrypt(Nonce::from_slice(&nonce), text.as_bytes())
+        .map_err(|_| anyhow::anyhow!("failed to encrypt the message"))?;
+
+    let content = EncryptedContent {
+        sender: sender.clone(),
+        channel,
+        timestamp,
+        ephemeral_public_key,
+        wrapped_keys,
+        nonce,
+        ciphertext,
+    };
+    let signature = TypedSignature::new(signer.sign(content.to_hash256()).await?, sender);
+    Ok(EncryptedMessage { content, signature })
+}
+
+/// Verifies that the message was sent by a member of the chain to a channel of the members.
+pub fn verify(
+    message: &EncryptedMessage,
+    reserved_state: &reserved::ReservedState,
+) -> Result<(), Error> {
+    let content = &message.content;
+    for key in content.channel.members.iter().chain([&content.sender]) {
+        if !reserved_state
+            .members
+            .iter()
+            .any(|member| &member.public_key == key)
+        {
+            return Err(anyhow::anyhow!("{} is not a member", key));
+        }
+    }
+    if !content.channel.members.contains(&content.sender) {
+        return Err(anyhow::anyhow!("the sender is not a member of the channel"));
+    }
+    if content.wrapped_keys.len() != content.channel.members.len() {
+        return Err(anyhow::anyhow!("the wrapped keys don't match the channel"));
+    }
+    if message.signature.signer() != &content.sender {
+        return Err(anyhow::anyhow!("the signer is not the sender"));
+    }
+    message.signature.verify(content)?;
+    Ok(())
+}
+
+/// Decrypts the message with the private key of a member of the channel.
+pub fn decrypt(
+    message: &EncryptedMessage,
+    private_key: &PrivateKey,
+) -> Result<PrivateChatMessage, Error> {
+    let content = &message.content;
+    let public_key = private_key.public_key();
+    let index = content
+        .channel
+        .members
+        .iter()
+        .position(|member| member == &public_key)
+        .ok_or_else(|| anyhow::anyhow!("not a member of the channel"))?;
+    let wrapped = content
+        .wrapped_keys
+        .get(index)
+        .ok_or_else(|| anyhow::anyhow!("missing the wrapped key"))?;
+    let shared_secret =
+        MontgomeryPoint(content.ephemeral_public_key) * to_x25519_private_key(private_key);
+    let content_key = wrapping_key(&shared_secret, &content.ephemeral_public_key, &public_key)
+        .decrypt(Nonce::from_slice(&[0; 12]), wrapped.as_ref())
+        .map_err(|_| anyhow::anyhow!("failed to unwrap the content key"))?;
+    if content_key.len() != 32 {
+        return Err(anyhow::anyhow!("invalid content key"));
+    }
+    let text = ChaCha20Poly1305::new(Key::from_slice(&content_key))
+        .decrypt(
+            Nonce::from_slice(&content.nonce),
+            content.ciphertext.as_ref(),
+        )
+        .map_err(|_| anyhow::anyhow!("failed to decrypt the message"))?;
+    Ok(PrivateChatMessage {
+        channel: content.channel.clone(),
+        message: ChatMessage {
+            author: content.sender.clone(),
+            text: String::from_utf8(text)?,
+            timestamp: content.timestamp,
+            off_the_record: true,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_common::crypto::generate_keypair;
+    use simperby_network::signer::LocalSigner;
+
+    #[tokio::test]
+    async fn only_members_decrypt() {
+        let (a, a_private) = generate_keypair("a");
+        let (b, b_private) = generate_keypair("b");
+        let (_, c_private) = generate_keypair("c");
+        let channel = Channel::new(vec![b.clone(), a.clone()]);
+        let message = encrypt(
+            channel.clone(),
+            "hello".to_owned(),
+            1,
+            &LocalSigner::new(a_private.clone()),
+        )
+        .await
+        .unwrap();
+        assert!(message.signature.verify(&message.content).is_ok());
+        for private_key in [&a_private, &b_private] {
+            let decrypted = decrypt(&message, private_key).unwrap();
+            assert_eq!(decrypted.channel, channel);
+            assert_eq!(decrypted.message.author, a);
+            assert_eq!(decrypted.message.text, "hello");
+        }
+        assert!(decrypt(&message, &c_private).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! The review comments on the diffs of the agendas, for the deliberation before the votes.
+//!
+//! A comment is anchored to a line of a file changed by an agenda, and is said in the chat
+//! as a message of a fixed format (see `ReviewComment::to_chat_text()`), signed by the governance
+//! key of the member like any other message. So the comments live in the chat DMS of the height,
+//! and are recorded in the chat-log commit unless said off the record.
+use super::*;
+
+/// The first word of the chat message carrying a review comment.
+pub const PREFIX: &str = "review";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReviewComment {
+    /// The hash of the agenda commented on.
+    pub agenda_hash: Hash256,
+    /// The path of the file, relative to the root of the working tree.
+    pub path: String,
+    /// The line in the file as changed by the agenda, starting from 1.
+    pub line: u64,
+    pub text: String,
+}
+
+impl ReviewComment {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.path.is_empty() || self.path.contains('\n') {
+            return Err(anyhow::anyhow!("invalid path: {:?}", self.path));
+        }
+        if self.line == 0 {
+            return Err(anyhow::anyhow!("the lines start from 1"));
+        }
+        if self.text.trim().is_empty() {
+            return Err(anyhow::anyhow!("the comment is empty"));
+        }
+        if self.to_chat_text().len() > MAX_TEXT_LENGTH {
+            return Err(anyhow::anyhow!("the comment is too long"));
+        }
+        Ok(())
+    }
+
+    /// Formats the comment as the text of a chat message:
+    /// `review <agenda hash> <line> <path>` followed by the comment on the next lines.
+    pub fn to_chat_text(&self) -> String {
+        format!(
+            "{} {} {} {}\n{}",
+            PREFIX, self.agenda_hash, self.line, self.path, self.text
+        )
+    }
+
+    /// Parses the text of a chat message as a comment on the given agenda,
+    /// returning `None` if it's not one.
+    pub fn parse(text: &str, agenda_hash: &Hash256) -> Option<Self> {
+        let (header, text) = text.split_once('\n')?;
+        let mut words = header.splitn(4, ' ');
+        if words.next()? != PREFIX || words.next()? != agenda_hash.to_string() {
+            return None;
+        }
+        let line = words.next()?.parse().ok().filter(|line| *line > 0)?;
+        let path = words.next().filter(|path| !path.is_empty())?;
+        Some(Self {
+            agenda_hash: *agenda_hash,
+            path: path.to_owned(),
+            line,
+            text: text.to_owned(),
+        })
+    }
+}
+
+/// A review comment as read from the chat.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedReviewComment {
+    pub comment: ReviewComment,
+    pub author: PublicKey,
+    pub timestamp: Timestamp,
+    pub off_the_record: bool,
+    /// The signature of the author on the chat message.
+    pub signature: TypedSignature<ChatMessage>,
+}
+
+/// Picks the comments on the agenda out of the chat messages,
+/// in the order of the paths, the lines and then the timestamps.
+pub fn read_comments(
+    messages: &[(ChatMessage, TypedSignature<ChatMessage>)],
+    agenda_hash: &Hash256,
+) -> Vec<SignedReviewComment> {
+    let mut comments: Vec<_> = messages
+        .iter()
+        .filter_map(|(message, signature)| {
+            Some(SignedReviewComment {
+                comment: ReviewComment::parse(&message.text, agenda_hash)?,
+                author: message.author.clone(),
+                timestamp: message.timestamp,
+                off_the_record: message.off_the_record,
+                signature: signature.clone(),
+            })
+        })
+        .collect();
+    comments.sort_by(|a, b| {
+        (&a.comment.path, a.comment.line, a.timestamp).cmp(&(
+            &b.comment.path,
+            b.comment.line,
+            b.timestamp,
+        ))
+    });
+    comments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_common::crypto::generate_keypair;
+
+    #[test]
+    fn format() {
+        let agenda_hash = Hash256::hash("agenda");
+        let comment = ReviewComment {
+            agenda_hash,
+            path: "docs/a file.md".to_owned(),
+            line: 12,
+            text: "This contradicts\nthe section above.".to_owned(),
+        };
+        comment.validate().unwrap();
+        assert_eq!(
+            ReviewComment::parse(&comment.to_chat_text(), &agenda_hash),
+            Some(comment.clone())
+        );
+        assert_eq!(
+            ReviewComment::parse(&comment.to_chat_text(), &Hash256::hash("other")),
+            None
+        );
+        assert_eq!(
+            ReviewComment::parse("review of the agenda", &agenda_hash),
+            None
+        );
+        ReviewComment {
+            line: 0,
+            ..comment.clone()
+        }
+        .validate()
+        .unwrap_err();
+        ReviewComment {
+            text: " ".to_owned(),
+            ..comment
+        }
+        .validate()
+        .unwrap_err();
+    }
+
+    #[test]
+    fn read() {
+        let agenda_hash = Hash256::hash("agenda");
+        let (public_key, private_key) = generate_keypair("a");
+        let message = |text: String, timestamp| {
+            let message = ChatMessage {
+                author: public_key.clone(),
+                text,
+                timestamp,
+                off_the_record: false,
+            };
+            let signature = TypedSignature::sign(&message, &private_key).unwrap();
+            (message, signature)
+        };
+        let comment = |path: &str, line| ReviewComment {
+            agenda_hash,
+            path: path.to_owned(),
+            line,
+            text: "nit".to_owned(),
+        };
+        let messages = vec![
+            message(comment("b.md", 1).to_chat_text(), 0),
+            message("hello".to_owned(), 1),
+            message(comment("a.md", 7).to_chat_text(), 2),
+            message(comment("a.md", 3).to_chat_text(), 3),
+            message(
+                ReviewComment {
+                    agenda_hash: Hash256::hash("other"),
+                    ..comment("a.md", 1)
+                }
+                .to_chat_text(),
+                4,
+            ),
+        ];
+        let comments = read_comments(&messages, &agenda_hash);
+        assert_eq!(
+            comments
+                .iter()
+                .map(|x| (x.comment.path.as_str(), x.comment.line))
+                .collect::<Vec<_>>(),
+            vec![("a.md", 3), ("a.md", 7), ("b.md", 1)]
+        );
+        assert_eq!(comments[0].author, public_key);
+    }
+}
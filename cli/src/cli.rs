@@ -1,3 +1,4 @@
+use crate::output::Format;
 use clap::{Parser, Subcommand};
 
 /**
@@ -7,8 +8,220 @@ Welcome to the Simperby CLI!
 #[clap(name = "git")]
 #[clap(about = "A Simperby client CLI", long_about = None)]
 pub struct Cli {
+    /// The path to the configuration file of the node.
+    #[clap(long, global = true, default_value = "simperby.toml")]
+    pub config: String,
+    /// The chains file of a node running several chains (see `simperby chain`).
+    #[clap(long, global = true, default_value = "chains.toml")]
+    pub chains: String,
+    /// Selects the chain in the chains file, instead of `--config`.
+    #[clap(long, global = true)]
+    pub chain: Option<String>,
+    /// Overrides a configuration value, as `key.path=value` (e.g., `ports.governance=9201`).
+    ///
+    /// It takes precedence over the file and the `SIMPERBY_*` environment variables.
+    #[clap(long = "set", global = true)]
+    pub overrides: Vec<String>,
+    /// The format of the output of the query commands.
+    ///
+    /// `json` has stable field names, for scripts and explorers.
+    #[clap(long, global = true, value_enum, default_value = "human")]
+    pub format: Format,
     #[clap(subcommand)]
-    command: Commands,
+    pub command: Commands,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommands {
+    /// Load the configuration with the overrides and report the problems found.
+    Check,
+    /// Print the configuration with the overrides applied.
+    Show,
+}
+
+/// The genesis ceremony of the founding members.
+///
+/// Every founding member approves the same proposal, and then anyone holding all the approvals
+/// can finalize it; every participant who finalizes gets the same genesis commit.
+#[derive(Debug, Subcommand)]
+pub enum GenesisCommands {
+    /// Propose a genesis with the default parameters, to be reviewed and edited before sharing.
+    Init {
+        /// The path of the proposal file to create.
+        path: String,
+        #[clap(long)]
+        chain_name: String,
+        /// The JSON file of the founding members, in the consensus leader order.
+        #[clap(long)]
+        members: String,
+    },
+    /// Approve the proposal with the key of this node, which must be a founding member.
+    Approve {
+        proposal: String,
+        /// The path of the approval file to create.
+        output: String,
+    },
+    /// Verify and merge the approvals of the proposal into a single file.
+    Collect {
+        proposal: String,
+        /// The path of the merged approvals file, which may already exist.
+        output: String,
+        /// The approval files (or merged approvals files) to add.
+        approvals: Vec<String>,
+    },
+    /// Create the genesis commit in the repository directory of the node,
+    /// once every founding member has approved.
+    Finalize {
+        proposal: String,
+        approvals: String,
+        /// An existing repository (a path or a URL) of which a commit's tree becomes
+        /// the genesis working tree, without its history; every founding member must use the same.
+        #[clap(long, requires = "import_commit")]
+        import: Option<String>,
+        /// The commit of `--import` to snapshot, in full hex.
+        #[clap(long, requires = "import")]
+        import_commit: Option<String>,
+    },
+}
+
+/// The peers are kept in the peer directory of the node, and are updated by the discovery.
+#[derive(Debug, Subcommand)]
+pub enum PeerCommands {
+    /// Add a peer to contact in every discovery round, until removed.
+    Add {
+        /// The discovery endpoint, as `host:port` or a URL with them.
+        address: String,
+        /// The public key of the peer, in hex.
+        public_key: String,
+    },
+    /// Remove the peer and forget its record.
+    Remove {
+        /// The public key of the peer, in hex.
+        public_key: String,
+    },
+    /// List the known peers with their liveness, the last seen time and the height.
+    List,
+    /// Ping the peer, showing the round-trip time and its height.
+    Status {
+        /// The public key of a known peer (in hex), or a discovery endpoint.
+        target: String,
+    },
+}
+
+/// The chains of a node are listed in the chains file, sharing its keystore.
+#[derive(Debug, Subcommand)]
+pub enum ChainCommands {
+    /// Add a chain with its configuration file.
+    Add {
+        /// The local name to select the chain with `--chain`.
+        name: String,
+        /// The path to the configuration file of the chain.
+        config: String,
+        /// The shared keystore, required when the chains file doesn't exist yet.
+        #[clap(long)]
+        keystore: Option<String>,
+    },
+    /// Remove the chain from the chains file, leaving its storages intact.
+    Remove { name: String },
+    /// List the chains with their chain names.
+    List,
+}
+
+/// Onboarding a new member: a member invites, the newcomer applies,
+/// and a member submits the application for the vote in an agenda.
+#[derive(Debug, Subcommand)]
+pub enum MemberCommands {
+    /// Invite a new member, writing the invitation to send to the newcomer.
+    Invite {
+        /// The name of the new member.
+        name: String,
+        output: String,
+        #[clap(long, default_value = "1")]
+        governance_power: u64,
+        #[clap(long, default_value = "1")]
+        consensus_power: u64,
+        /// How long the invitation is valid, in hours.
+        #[clap(long, default_value = "168")]
+        valid_hours: u64,
+    },
+    /// Apply to the invitation with the key of the newcomer,
+    /// writing the application to send back to a member.
+    Apply {
+        invitation: String,
+        output: String,
+        /// The keystore of the newcomer, which needs no node yet.
+        #[clap(long)]
+        keystore: String,
+        /// Information for the reviewers, as `key=value` (e.g., `contact=me@example.org`).
+        #[clap(long = "meta")]
+        metadata: Vec<String>,
+    },
+    /// Submit the application as a transaction and create an agenda to vote on.
+    Submit { application: String },
+}
+
+/// A snapshot is a single file holding the finalized history, the reserved state,
+/// the finalization proofs and the DMS entries of a node.
+#[derive(Debug, Subcommand)]
+pub enum SnapshotCommands {
+    /// Export the snapshot of this node to the path, for a backup or a new machine.
+    Export { path: String },
+    /// Restore this node from the snapshot, verifying it.
+    ///
+    /// The directories of the node must not exist.
+    Import { path: String },
+}
+
+/// Authoring a transaction that changes the files of the repository,
+/// instead of `git add` and `git commit`.
+#[derive(Debug, Subcommand)]
+pub enum TxCommands {
+    /// Start a transaction on top of the `work` branch, checking it out.
+    Create {
+        /// The title of the transaction.
+        head: String,
+        #[clap(long, default_value = "")]
+        body: String,
+    },
+    /// Stage the changes of the files (including the deletions) for the transaction.
+    Add { paths: Vec<String> },
+    /// Show the staged diff and the problems that would prevent the commit.
+    Diff,
+    /// Create the transaction commit of the staged changes, signed with the key of this node.
+    ///
+    /// It shows the staged diff, and asks for a confirmation.
+    Commit {
+        /// Skip the confirmation.
+        #[clap(short, long, action)]
+        yes: bool,
+    },
+    /// Abandon the transaction, discarding the changes in the working tree.
+    Abort,
+}
+
+/// The drafts of the agendas, prepared locally and kept off the network until published.
+#[derive(Debug, Subcommand)]
+pub enum DraftCommands {
+    /// Park the transactions on the `work` branch as a new draft, clearing the `work` branch.
+    Create { name: String },
+    /// Bring the draft back onto the `work` branch to add transactions with `tx`;
+    /// park it again with `draft create` when done.
+    ///
+    /// A draft outdated by the finalized blocks is replayed on top of the last one.
+    Edit { name: String },
+    /// List the drafts with their transactions.
+    List,
+    /// Publish the draft as an agenda, signed with the key of this node.
+    Publish {
+        name: String,
+        /// The open agenda of this node (e.g., `a-3` or its hash) that the draft amends,
+        /// invalidating the votes on it.
+        #[clap(long)]
+        supersedes: Option<String>,
+        /// Skip the confirmation.
+        #[clap(short, long, action)]
+        yes: bool,
+    },
 }
 
 #[derive(Debug, Subcommand)]
@@ -32,6 +245,25 @@ pub enum CreateCommands {
     Agenda,
 }
 
+/// The passphrases are always prompted, never given as arguments.
+#[derive(Debug, Subcommand)]
+pub enum KeystoreCommands {
+    /// Create a keystore encrypting a newly generated private key.
+    Create {
+        /// The path of the keystore file to create.
+        path: String,
+        /// If specified, it encrypts the given private key (in hex) instead of generating one.
+        #[clap(long)]
+        import: Option<String>,
+    },
+    /// Check that the passphrase unlocks the keystore, and print its public key.
+    Unlock { path: String },
+    /// Print the decrypted private key in hex.
+    Export { path: String },
+    /// Re-encrypt the keystore with a new passphrase.
+    ChangePassphrase { path: String },
+}
+
 #[derive(Debug, Subcommand)]
 pub enum SignCommands {
     TxDelegate {
@@ -52,6 +284,25 @@ pub enum SignCommands {
 pub enum Commands {
     /// Initialize a new Simperby node in the current directory.
     Init,
+    /// Join an existing chain by cloning its repository from the URL.
+    ///
+    /// This verifies the whole history (or that from the checkpoint),
+    /// sets up the `main` and the `work` branches and the directories of the node,
+    /// and registers the origin as a bootstrap peer.
+    Clone {
+        url: String,
+        /// A block commit to trust instead of verifying the history from the genesis;
+        /// defaults to the one attested by the sources of the configured `checkpoint`, if any.
+        #[clap(long)]
+        checkpoint: Option<String>,
+        /// The peer-discovery endpoint (`host:port`) of the origin;
+        /// defaults to the host of the URL with the configured `ports.peer_discovery`.
+        #[clap(long)]
+        peer: Option<String>,
+    },
+    /// Run the genesis ceremony of a new chain.
+    #[command(subcommand)]
+    Genesis(GenesisCommands),
     /// Sync the `main` branch to the given commit.
     ///
     /// This will verify every commit along the way.
@@ -73,9 +324,22 @@ pub enum Commands {
     /// Create a new commit on top of the `work` branch.
     #[command(subcommand)]
     Create(CreateCommands),
+    /// Author a transaction changing the files of the repository.
+    #[command(subcommand)]
+    Tx(TxCommands),
+    /// Prepare the agendas locally before publishing them.
+    #[command(subcommand)]
+    Draft(DraftCommands),
     /// Vote on the agenda, broadcasting to the network.
     /// It will also leave a `vote` tag on the commit (with some postfix).
-    Vote { commit: String },
+    ///
+    /// It shows the diff of the agenda and the current tally, and asks for a confirmation.
+    Vote {
+        commit: String,
+        /// Skip the confirmation.
+        #[clap(short, long, action)]
+        yes: bool,
+    },
     /// Veto the round.
     ///
     /// It will be broadcasted to the network as a nil-vote
@@ -86,13 +350,53 @@ pub enum Commands {
         /// leaving a `veto` tag on the commit (with some postfix).
         /// It fails if the given commit is already set to `proposal`.
         /// If the given commit is already set to `veto`, it will be removed.
+        ///
+        /// It shows the diff of the block, and asks for a confirmation.
         commit: Option<String>,
+        /// Skip the confirmation.
+        #[clap(short, long, action)]
+        yes: bool,
+    },
+    /// Show the commit with its type, signatures and finalization status,
+    /// and the governance status if it is an agenda.
+    Show {
+        /// A finalized block height, a branch (e.g., the agenda `a-3`), a tag,
+        /// the hash of a pending agenda, or a commit hash prefix (tried in this order).
+        reference: String,
+        /// If specified, it shows who last changed each line of the given
+        /// reserved-state file as of the commit, instead.
+        #[clap(long)]
+        blame: Option<String>,
+    },
+    /// Show the review comments on the agenda, by the files and the lines of its diff.
+    ///
+    /// The comments are said in the chat through the API of a running node (`comment`).
+    Comments {
+        /// The agenda branch (e.g., `a-3`), the hash of the agenda, or a commit hash prefix.
+        reference: String,
     },
-    /// Show the governance status of the given agenda.
-    Show { commit: String },
     /// Run the Simperby node indefinitely. This is same as running `relay` while
     /// invoking `consensus` and `fetch` repeatedly.
     Run,
+    /// Run the node in the foreground as a daemon, for `systemd` or containers.
+    ///
+    /// It logs to stdout as JSON lines (or to the files of `logging.file`), and shuts down
+    /// cleanly on `SIGTERM`, removing the PID file and the health file.
+    Serve {
+        /// The file to keep the PID in while running.
+        #[clap(long)]
+        pid_file: Option<String>,
+        /// The file to refresh the status in while running, for the health checks.
+        #[clap(long)]
+        health_file: Option<String>,
+        /// The maximum level of the logs (`error`, `warn`, `info`, `debug` or `trace`),
+        /// overriding `logging.level` of the configuration.
+        #[clap(long)]
+        log_level: Option<log::LevelFilter>,
+        /// Run every chain in the chains file, instead of a single one.
+        #[clap(long, action)]
+        all_chains: bool,
+    },
     /// Make a progress on the consensus.
     ///
     /// The node may broadcast the proposal or consensus messages depending on the
@@ -105,6 +409,33 @@ pub enum Commands {
         #[clap(long, action)]
         show: bool,
     },
+    /// Export the archived consensus votes of the heights as JSON, with the equivocations
+    /// found among them, to see why a height finalized or stalled.
+    ///
+    /// The votes are archived only if `vote_archive_directory` is configured.
+    Votes {
+        /// The first height to export.
+        from: u64,
+        /// The last height to export; defaults to `from`.
+        #[clap(long)]
+        to: Option<u64>,
+        /// The file to write to, instead of the standard output.
+        #[clap(long)]
+        output: Option<String>,
+    },
+    /// Show the status of the chain as of the last finalized block.
+    Status,
+    /// Show the agendas pending on top of the last finalized block.
+    Agendas,
+    /// Show the finalized blocks, from the latest.
+    History {
+        /// The maximum number of blocks to show.
+        #[clap(long)]
+        limit: Option<usize>,
+    },
+    /// Manage the peers known to this node.
+    #[command(subcommand)]
+    Peer(PeerCommands),
     /// Show the current status of the p2p network.
     Network,
     /// Serve the gossip protocol indefinitely, relaying the incoming packets to other peers.
@@ -123,6 +454,45 @@ pub enum Commands {
         #[clap(short, long, action)]
         interactive: bool,
     },
+    /// Render the finalized history as a static HTML site (blocks, agendas, members,
+    /// votes and the reserved-state history) into the directory, to publish as a chain explorer.
+    Explorer { output: String },
+    /// Write the attestation of the latest block with a finalization proof as JSON,
+    /// to be served for the nodes joining from a checkpoint.
+    Checkpoint { output: String },
+    /// Onboard a new member.
+    #[command(subcommand)]
+    Member(MemberCommands),
+    /// Manage the chains run by this node.
+    #[command(subcommand)]
+    Chain(ChainCommands),
+    /// Export or import a snapshot of the node.
+    #[command(subcommand)]
+    Snapshot(SnapshotCommands),
+    /// Recover the repository whose finalized branch got rewound or corrupted,
+    /// re-cloning it from the repositories of the peers.
+    ///
+    /// It requires more than half of the sources to serve the same valid history,
+    /// and moves the broken repository aside instead of deleting it.
+    Recover {
+        /// The repository URLs of the peers; defaults to the configured `recovery_sources`.
+        sources: Vec<String>,
+        /// Replace the repository even if no problem is found.
+        #[clap(long, action)]
+        force: bool,
+    },
+    /// Diagnose the node and suggest how to fix the problems found.
+    ///
+    /// This checks the configuration, the keystore, the integrity of the repository
+    /// (the Git object store and the invariants of a Simperby repository),
+    /// the branch conventions, the reachability of the peers, and the clock skew.
+    Doctor,
+    /// Manage the configuration of the node.
+    #[command(subcommand)]
+    Config(ConfigCommands),
+    /// Manage the encrypted keystore of the private key.
+    #[command(subcommand)]
+    Keystore(KeystoreCommands),
     /// Sign a message with the configured private key.
     #[command(subcommand)]
     Sign(SignCommands),
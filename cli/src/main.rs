@@ -1,10 +1,560 @@
 mod cli;
+mod output;
 
 use clap::Parser;
+use cli::{
+    ChainCommands, Commands, ConfigCommands, DraftCommands, GenesisCommands, MemberCommands,
+    PeerCommands, SnapshotCommands, TxCommands,
+};
+use simperby_node::authoring;
+use simperby_node::bootstrap;
+use simperby_node::chains::{self, ChainsFile};
+use simperby_node::checkpoint;
+use simperby_node::daemon::{self, DaemonOptions};
+use simperby_node::doctor;
+use simperby_node::drafts;
+use simperby_node::explorer;
+use simperby_node::genesis::{self, Approval};
+use simperby_node::logging::{LogFormat, LoggingConfig};
+use simperby_node::membership;
+use simperby_node::node::Node;
+use simperby_node::peers;
+use simperby_node::query;
+use simperby_node::recovery;
+use simperby_node::simperby_common::genesis::GenesisProposal;
+use simperby_node::simperby_network::gossip_network::TcpGossipNetwork;
+use simperby_node::simperby_network::message_store::SledMessageStore;
+use simperby_node::simperby_repository::format::GenesisProvenance;
+use simperby_node::simperby_repository::raw::RawRepositoryImpl;
+use simperby_node::snapshot;
+use simperby_node::{keystore, review, SimperbyApi};
+use std::path::PathBuf;
+
+/// Loads the configuration of the node, or of the chain selected by `--chain`.
+async fn load_config(args: &cli::Cli) -> anyhow::Result<simperby_node::Config> {
+    match &args.chain {
+        Some(chain) => chains::load(&args.chains, chain, &args.overrides).await,
+        None => simperby_node::config::load(&args.config, &args.overrides).await,
+    }
+}
+
+type CliNode = Node<TcpGossipNetwork, SledMessageStore, RawRepositoryImpl>;
+
+/// Creates the node of the configuration, unlocking its keystore.
+async fn open_node(config: simperby_node::Config) -> anyhow::Result<CliNode> {
+    let private_key = keystore::unlock(&config.keystore_path).await?;
+    CliNode::with_private_key(config, private_key)
+}
+
+/// Installs the logging of the daemon, which writes JSON lines to stdout unless a file is configured.
+fn install_logging(
+    mut logging: LoggingConfig,
+    level: Option<log::LevelFilter>,
+) -> anyhow::Result<()> {
+    if let Some(level) = level {
+        logging.level = level.to_string().to_lowercase();
+    }
+    if logging.file.is_none() {
+        logging.format = LogFormat::Json;
+    }
+    simperby_node::logging::install(&logging)
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = cli::Cli::parse();
-    println!("{:?}", args);
+    match &args.command {
+        Commands::Chain(ChainCommands::Add {
+            name,
+            config,
+            keystore,
+        }) => {
+            let exists = std::path::Path::new(&args.chains).exists();
+            let mut chains_file = match (exists, keystore) {
+                (true, None) => ChainsFile::read(&args.chains).await?,
+                (true, Some(_)) => {
+                    return Err(anyhow::anyhow!(
+                        "{} already exists; the keystore is shared by its chains",
+                        args.chains
+                    ))
+                }
+                (false, Some(keystore)) => ChainsFile {
+                    keystore_path: keystore.clone(),
+                    chains: Vec::new(),
+                },
+                (false, None) => {
+                    return Err(anyhow::anyhow!(
+                        "{} doesn't exist; give --keystore to create it",
+                        args.chains
+                    ))
+                }
+            };
+            chains_file.add(name, config)?;
+            chains_file.write(&args.chains).await?;
+            println!("added the chain `{}` to {}", name, args.chains);
+        }
+        Commands::Chain(ChainCommands::Remove { name }) => {
+            let mut chains_file = ChainsFile::read(&args.chains).await?;
+            chains_file.remove(name)?;
+            chains_file.write(&args.chains).await?;
+            println!("removed the chain `{}`; its storages are left intact", name);
+        }
+        Commands::Chain(ChainCommands::List) => {
+            let chains_file = ChainsFile::read(&args.chains).await?;
+            for chain in &chains_file.chains {
+                match chains::load(&args.chains, &chain.name, &args.overrides).await {
+                    Ok(config) => {
+                        println!("{}\t{}\t{}", chain.name, config.chain_name, chain.config)
+                    }
+                    Err(e) => println!("{}\t(invalid: {})\t{}", chain.name, e, chain.config),
+                }
+            }
+        }
+        Commands::Config(ConfigCommands::Check) => {
+            load_config(&args).await?;
+            println!("{} is valid", args.chain.as_ref().unwrap_or(&args.config));
+        }
+        Commands::Config(ConfigCommands::Show) => {
+            let config = load_config(&args).await?;
+            println!("{}", serde_json::to_string_pretty(&config)?);
+        }
+        Commands::Status => {
+            let config = load_config(&args).await?;
+            output::print(
+                args.format,
+                &query::status::<RawRepositoryImpl>(&config).await?,
+            )?;
+        }
+        Commands::Agendas => {
+            let config = load_config(&args).await?;
+            output::print(
+                args.format,
+                &query::agendas::<RawRepositoryImpl>(&config).await?,
+            )?;
+        }
+        Commands::Recover { sources, force } => {
+            let config = load_config(&args).await?;
+            let sources = if sources.is_empty() {
+                &config.recovery_sources
+            } else {
+                sources
+            };
+            let recovery = recovery::recover::<RawRepositoryImpl>(&config, sources, *force).await?;
+            match (&recovery.problem, &recovery.archived) {
+                (None, None) => println!(
+                    "no problem found; the repository agrees with the sources at height {}",
+                    recovery.height
+                ),
+                (problem, archived) => {
+                    if let Some(problem) = problem {
+                        println!("found a problem: {}", problem);
+                    }
+                    println!("recovered the repository at height {}", recovery.height);
+                    if let Some(archived) = archived {
+                        println!("the previous repository is archived at {}", archived);
+                    }
+                }
+            }
+        }
+        Commands::Votes { from, to, output } => {
+            let config = load_config(&args).await?;
+            let votes = query::votes(&config, *from, to.unwrap_or(*from)).await?;
+            let content = serde_json::to_string_pretty(&votes)?;
+            match output {
+                Some(path) => {
+                    tokio::fs::write(path, content).await?;
+                    println!("exported the votes of {} heights to {}", votes.len(), path);
+                }
+                None => println!("{}", content),
+            }
+        }
+        Commands::History { limit } => {
+            let config = load_config(&args).await?;
+            let blocks = query::history::<RawRepositoryImpl>(&config, *limit).await?;
+            output::print(args.format, &blocks)?;
+        }
+        Commands::Peer(PeerCommands::Add {
+            address,
+            public_key,
+        }) => {
+            let config = load_config(&args).await?;
+            let public_key = peers::parse_public_key(public_key)?;
+            let peer = peers::add(&config, address, public_key).await?;
+            println!("added the peer at {}", peer.address);
+        }
+        Commands::Peer(PeerCommands::Remove { public_key }) => {
+            let config = load_config(&args).await?;
+            if !peers::remove(&config, &peers::parse_public_key(public_key)?).await? {
+                return Err(anyhow::anyhow!("peer {} is not known", public_key));
+            }
+        }
+        Commands::Peer(PeerCommands::List) => {
+            let config = load_config(&args).await?;
+            output::print(args.format, &query::peers(&config).await?)?;
+        }
+        Commands::Peer(PeerCommands::Status { target }) => {
+            let config = load_config(&args).await?;
+            let result = peers::ping::<RawRepositoryImpl>(&config, target).await?;
+            output::print(args.format, &result)?;
+        }
+        Commands::Show { reference, blame } => {
+            let config = load_config(&args).await?;
+            match blame {
+                Some(path) => {
+                    let lines = query::blame::<RawRepositoryImpl>(&config, path, reference).await?;
+                    output::print(args.format, &lines)?;
+                }
+                None => {
+                    let commit =
+                        query::show::<SledMessageStore, RawRepositoryImpl>(&config, reference)
+                            .await?;
+                    output::print(args.format, &commit)?;
+                }
+            }
+        }
+        Commands::Comments { reference } => {
+            let config = load_config(&args).await?;
+            let comments =
+                query::review_comments::<SledMessageStore, RawRepositoryImpl>(&config, reference)
+                    .await?;
+            output::print(args.format, &comments)?;
+        }
+        Commands::Clone {
+            url,
+            checkpoint,
+            peer,
+        } => {
+            let config = load_config(&args).await?;
+            let checkpoint = checkpoint.as_deref().map(str::parse).transpose()?;
+            let peer = peer
+                .clone()
+                .or_else(|| bootstrap::origin_peer(url, config.ports.peer_discovery))
+                .ok_or_else(|| anyhow::anyhow!("can't find the host of {}; give --peer", url))?;
+            let header = if checkpoint.is_none() && config.checkpoint.is_some() {
+                bootstrap::clone_from_checkpoint::<SledMessageStore, RawRepositoryImpl>(
+                    &config, url, &peer,
+                )
+                .await?
+            } else {
+                bootstrap::clone::<SledMessageStore, RawRepositoryImpl>(
+                    &config, url, checkpoint, &peer,
+                )
+                .await?
+            };
+            println!("cloned the chain at height {}", header.height);
+        }
+        Commands::Genesis(GenesisCommands::Init {
+            path,
+            chain_name,
+            members,
+        }) => {
+            let proposal = GenesisProposal {
+                chain_name: chain_name.clone(),
+                members: genesis::read_json(members).await?,
+                version: simperby_node::PROTOCOL_VERSION.to_owned(),
+                governance_params: Default::default(),
+                consensus_params: Default::default(),
+                bootstrap_peers: Vec::new(),
+                parameters: Default::default(),
+                timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            };
+            proposal.header().map_err(|e| anyhow::anyhow!(e))?;
+            genesis::write_json(path, &proposal).await?;
+            println!("proposed the genesis in {}", path);
+        }
+        Commands::Genesis(GenesisCommands::Approve { proposal, output }) => {
+            let config = load_config(&args).await?;
+            let proposal: GenesisProposal = genesis::read_json(proposal).await?;
+            let private_key = keystore::unlock(&config.keystore_path).await?;
+            let approval =
+                genesis::approve(&proposal, keystore::signer(&config, private_key).as_ref())
+                    .await?;
+            genesis::write_json(output, &approval).await?;
+            println!(
+                "approved the genesis of {} in {}",
+                proposal.chain_name, output
+            );
+        }
+        Commands::Genesis(GenesisCommands::Collect {
+            proposal,
+            output,
+            approvals,
+        }) => {
+            let proposal: GenesisProposal = genesis::read_json(proposal).await?;
+            let mut collected: Vec<Approval> = if std::path::Path::new(output).exists() {
+                genesis::read_json(output).await?
+            } else {
+                Vec::new()
+            };
+            for path in approvals {
+                match genesis::read_json::<Vec<Approval>>(path).await {
+                    Ok(merged) => collected.extend(merged),
+                    Err(_) => collected.push(genesis::read_json(path).await?),
+                }
+            }
+            let collected = genesis::filter_approvals(&proposal, &collected)?;
+            genesis::write_json(output, &collected).await?;
+            let missing: Vec<_> = proposal
+                .members
+                .iter()
+                .filter(|m| !collected.iter().any(|x| x.signer() == &m.public_key))
+                .map(|m| m.name.as_str())
+                .collect();
+            if missing.is_empty() {
+                println!("collected the approvals of every founding member");
+            } else {
+                println!("waiting for the approvals of {}", missing.join(", "));
+            }
+        }
+        Commands::Genesis(GenesisCommands::Finalize {
+            proposal,
+            approvals,
+            import,
+            import_commit,
+        }) => {
+            let config = load_config(&args).await?;
+            let proposal: GenesisProposal = genesis::read_json(proposal).await?;
+            let approvals: Vec<Approval> = genesis::read_json(approvals).await?;
+            let import = match (import, import_commit) {
+                (Some(source), Some(commit)) => Some(GenesisProvenance {
+                    source: source.clone(),
+                    commit: commit.parse()?,
+                }),
+                _ => None,
+            };
+            let commit_hash = genesis::finalize::<RawRepositoryImpl>(
+                &proposal,
+                &approvals,
+                &config.repository_directory,
+                import.as_ref(),
+            )
+            .await?;
+            println!("created the genesis commit {}", commit_hash);
+        }
+        Commands::Checkpoint { output } => {
+            let config = load_config(&args).await?;
+            let attestation = checkpoint::attest::<RawRepositoryImpl>(&config).await?;
+            genesis::write_json(output, &attestation).await?;
+            println!(
+                "attested height {} into {}",
+                attestation.header.height, output
+            );
+        }
+        Commands::Explorer { output } => {
+            let config = load_config(&args).await?;
+            let count = explorer::generate::<RawRepositoryImpl>(&config, output).await?;
+            println!("rendered {} files into {}", count, output);
+        }
+        Commands::Member(MemberCommands::Invite {
+            name,
+            output,
+            governance_power,
+            consensus_power,
+            valid_hours,
+        }) => {
+            let config = load_config(&args).await?;
+            let private_key = keystore::unlock(&config.keystore_path).await?;
+            let signer = keystore::signer(&config, private_key);
+            let invitation = membership::invite(
+                &config,
+                signer.as_ref(),
+                name.clone(),
+                *governance_power,
+                *consensus_power,
+                std::time::Duration::from_secs(valid_hours * 3600),
+            )
+            .await?;
+            genesis::write_json(output, &invitation).await?;
+            println!("wrote the invitation of {} to {}", name, output);
+        }
+        Commands::Member(MemberCommands::Apply {
+            invitation,
+            output,
+            keystore: keystore_path,
+            metadata,
+        }) => {
+            let invitation = genesis::read_json(invitation).await?;
+            let private_key = keystore::unlock(keystore_path).await?;
+            let application = membership::apply(
+                invitation,
+                &private_key,
+                membership::parse_metadata(metadata)?,
+            )?;
+            genesis::write_json(output, &application).await?;
+            println!("wrote the application to {}", output);
+        }
+        Commands::Member(MemberCommands::Submit { application }) => {
+            let config = load_config(&args).await?;
+            let application = genesis::read_json(application).await?;
+            let (transaction, agenda) =
+                membership::submit::<RawRepositoryImpl>(&config, &application).await?;
+            println!(
+                "created the transaction {} and the agenda {}",
+                hex::encode(transaction.hash),
+                hex::encode(agenda.hash)
+            );
+        }
+        Commands::Snapshot(SnapshotCommands::Export { path }) => {
+            let config = load_config(&args).await?;
+            let header =
+                snapshot::export::<SledMessageStore, RawRepositoryImpl>(&config, path).await?;
+            println!(
+                "exported the snapshot at height {} to {}",
+                header.height, path
+            );
+        }
+        Commands::Snapshot(SnapshotCommands::Import { path }) => {
+            let config = load_config(&args).await?;
+            let header =
+                snapshot::import::<SledMessageStore, RawRepositoryImpl>(&config, path).await?;
+            println!("restored the node at height {}", header.height);
+        }
+        Commands::Doctor => {
+            let diagnosis =
+                doctor::diagnose::<RawRepositoryImpl>(&args.config, &args.overrides).await;
+            output::print(args.format, &diagnosis)?;
+            if !diagnosis.is_healthy() {
+                return Err(anyhow::anyhow!("the node is not healthy"));
+            }
+        }
+        Commands::Tx(TxCommands::Create { head, body }) => {
+            let config = load_config(&args).await?;
+            authoring::begin::<RawRepositoryImpl>(&config, head.clone(), body.clone()).await?;
+            println!("started the transaction; edit the files and run `tx add`");
+        }
+        Commands::Tx(TxCommands::Add { paths }) => {
+            let config = load_config(&args).await?;
+            authoring::stage::<RawRepositoryImpl>(&config, paths).await?;
+        }
+        Commands::Tx(TxCommands::Diff) => {
+            let config = load_config(&args).await?;
+            let preview = authoring::preview::<RawRepositoryImpl>(&config).await?;
+            output::print(args.format, &preview)?;
+        }
+        Commands::Tx(TxCommands::Commit { yes }) => {
+            let config = load_config(&args).await?;
+            let preview = authoring::preview::<RawRepositoryImpl>(&config).await?;
+            println!("{}", output::Human::human(&preview));
+            if !preview.is_ok() {
+                return Err(anyhow::anyhow!("fix the problems before committing"));
+            }
+            if !yes && !review::confirm("Commit this transaction?").await? {
+                return Ok(());
+            }
+            let private_key = keystore::unlock(&config.keystore_path).await?;
+            let (commit_hash, _) = authoring::commit::<RawRepositoryImpl>(
+                &config,
+                keystore::signer(&config, private_key).as_ref(),
+            )
+            .await?;
+            println!("created the transaction {}", hex::encode(commit_hash.hash));
+        }
+        Commands::Tx(TxCommands::Abort) => {
+            let config = load_config(&args).await?;
+            authoring::abort::<RawRepositoryImpl>(&config).await?;
+        }
+        Commands::Draft(DraftCommands::Create { name }) => {
+            let config = load_config(&args).await?;
+            let draft = drafts::create::<RawRepositoryImpl>(&config, name).await?;
+            output::print(args.format, &draft)?;
+        }
+        Commands::Draft(DraftCommands::Edit { name }) => {
+            let config = load_config(&args).await?;
+            drafts::edit::<RawRepositoryImpl>(&config, name).await?;
+            println!(
+                "loaded the draft onto the `work` branch; run `draft create {}` when done",
+                name
+            );
+        }
+        Commands::Draft(DraftCommands::List) => {
+            let config = load_config(&args).await?;
+            let drafts = drafts::list::<RawRepositoryImpl>(&config).await?;
+            output::print(args.format, &drafts)?;
+        }
+        Commands::Draft(DraftCommands::Publish {
+            name,
+            supersedes,
+            yes,
+        }) => {
+            let config = load_config(&args).await?;
+            let draft = drafts::list::<RawRepositoryImpl>(&config)
+                .await?
+                .into_iter()
+                .find(|draft| draft.name == *name)
+                .ok_or_else(|| anyhow::anyhow!("draft {} does not exist", name))?;
+            println!("{}", output::Human::human(&draft));
+            if let Some(superseded) = supersedes {
+                println!("superseding the agenda {}", superseded);
+            }
+            if !yes && !review::confirm("Publish this draft as an agenda?").await? {
+                return Ok(());
+            }
+            let private_key = keystore::unlock(&config.keystore_path).await?;
+            let agenda_commit = drafts::publish::<RawRepositoryImpl>(
+                &config,
+                keystore::signer(&config, private_key).as_ref(),
+                name,
+                supersedes.as_deref(),
+            )
+            .await?;
+            println!("published the agenda {}", hex::encode(agenda_commit.hash));
+        }
+        Commands::Vote { commit, yes } => {
+            let config = load_config(&args).await?;
+            let commit = commit.parse()?;
+            let node = open_node(config).await?;
+            if review::vote(&node, commit, *yes).await? {
+                println!("voted for the agenda {}", hex::encode(commit.hash));
+            }
+        }
+        Commands::Veto { commit, yes } => {
+            let config = load_config(&args).await?;
+            let commit = commit.as_deref().map(str::parse).transpose()?;
+            let node = open_node(config).await?;
+            if review::veto(&node, commit, *yes).await? {
+                match commit {
+                    Some(commit) => println!("vetoed the block {}", hex::encode(commit.hash)),
+                    None => println!("vetoed the current round"),
+                }
+            }
+        }
+        Commands::Serve {
+            pid_file,
+            health_file,
+            log_level,
+            all_chains,
+        } => {
+            let options = DaemonOptions {
+                pid_file: pid_file.as_ref().map(PathBuf::from),
+                health_file: health_file.as_ref().map(PathBuf::from),
+                ..Default::default()
+            };
+            if *all_chains {
+                let configs = chains::load_all(&args.chains, &args.overrides).await?;
+                // The chains share the process, so the logging of the first one applies.
+                let logging = configs
+                    .first()
+                    .map(|(_, config)| config.logging.clone())
+                    .unwrap_or_default();
+                install_logging(logging, *log_level)?;
+                log::info!("loaded {} chains from {}", configs.len(), args.chains);
+                let mut nodes = Vec::new();
+                for (name, config) in configs {
+                    nodes.push((name, open_node(config).await?));
+                }
+                let nodes: Vec<(&str, &(dyn SimperbyApi + Sync))> = nodes
+                    .iter()
+                    .map(|(name, node)| (name.as_str(), node as &(dyn SimperbyApi + Sync)))
+                    .collect();
+                daemon::serve_chains(&nodes, options).await?;
+            } else {
+                let config = load_config(&args).await?;
+                install_logging(config.logging.clone(), *log_level)?;
+                log::info!("loaded the configuration {}", args.config);
+                daemon::serve(&open_node(config).await?, options).await?;
+            }
+        }
+        _ => return Err(anyhow::anyhow!("the command is not implemented yet")),
+    }
     Ok(())
 }
@@ -0,0 +1,296 @@
+//! The output of the query commands, as human-readable text or as JSON (`--format json`).
+use clap::ValueEnum;
+use serde::Serialize;
+use simperby_node::doctor::{Diagnosis, Status};
+use simperby_node::peers::PingResult;
+use simperby_node::query::*;
+use simperby_node::simperby_chat::review::SignedReviewComment;
+use simperby_node::simperby_repository::authoring::TransactionPreview;
+use simperby_node::simperby_repository::drafts::Draft;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+/// The human-readable rendering of a query result.
+pub trait Human {
+    fn human(&self) -> String;
+}
+
+pub fn print<T: Serialize + Human>(format: Format, value: &T) -> anyhow::Result<()> {
+    match format {
+        Format::Human => println!("{}", value.human()),
+        Format::Json => println!("{}", serde_json::to_string_pretty(value)?),
+    }
+    Ok(())
+}
+
+/// Renders the rows as a table with the columns aligned.
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<_> = headers.iter().map(|x| x.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let line = |cells: Vec<String>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_owned()
+    };
+    std::iter::once(line(headers.iter().map(|x| x.to_string()).collect()))
+        .chain(rows.into_iter().map(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl Human for ChainStatus {
+    fn human(&self) -> String {
+        format!(
+            "chain: {}\nprotocol version: {}\nheight: {}\nlast block: {}\nlast block time: {}\nvalidators: {} of {} members",
+            self.chain_name,
+            self.protocol_version,
+            self.height,
+            self.last_block_hash,
+            self.last_block_timestamp,
+            self.validators,
+            self.members
+        )
+    }
+}
+
+impl Human for Vec<AgendaEntry> {
+    fn human(&self) -> String {
+        table(
+            &["COMMIT", "HASH"],
+            self.iter()
+                .map(|x| vec![x.commit.clone(), x.hash.to_string()])
+                .collect(),
+        )
+    }
+}
+
+impl Human for Vec<BlockEntry> {
+    fn human(&self) -> String {
+        table(
+            &["HEIGHT", "COMMIT", "AUTHOR", "TIMESTAMP"],
+            self.iter()
+                .map(|x| {
+                    vec![
+                        x.height.to_string(),
+                        x.commit.clone(),
+                        x.author.clone(),
+                        x.timestamp.to_string(),
+                    ]
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Human for Vec<PeerEntry> {
+    fn human(&self) -> String {
+        table(
+            &[
+                "PUBLIC KEY",
+                "ADDRESS",
+                "ALIVE",
+                "LAST SEEN",
+                "HEIGHT",
+                "RELAY",
+            ],
+            self.iter()
+                .map(|x| {
+                    vec![
+                        format!("{}{}", x.public_key, if x.pinned { " *" } else { "" }),
+                        x.address.clone(),
+                        if x.alive { "yes" } else { "no" }.to_owned(),
+                        x.recently_seen_timestamp.to_string(),
+                        x.height.map(|x| x.to_string()).unwrap_or_default(),
+                        x.relay.clone().unwrap_or_default(),
+                    ]
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Human for PingResult {
+    fn human(&self) -> String {
+        format!(
+            "{} at {}: {} ms, height {}, last seen {}",
+            self.public_key,
+            self.address,
+            self.round_trip_ms,
+            self.height
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "unknown".to_owned()),
+            self.last_seen
+        )
+    }
+}
+
+impl Human for CommitEntry {
+    fn human(&self) -> String {
+        let mut lines = vec![
+            format!("commit {}", self.commit),
+            format!("type: {:?}", self.kind),
+            format!("title: {}", self.title),
+        ];
+        if let Some(height) = self.height {
+            lines.push(format!("height: {}", height));
+        }
+        if let Some(author) = &self.author {
+            lines.push(format!("author: {}", author));
+        }
+        if let Some(timestamp) = self.timestamp {
+            lines.push(format!("timestamp: {}", timestamp));
+        }
+        lines.push(format!(
+            "status: {}",
+            if self.finalized {
+                "finalized"
+            } else {
+                "pending"
+            }
+        ));
+        if let Some(tally) = &self.tally {
+            lines.push(format!(
+                "votes: {} approving, {} vetoing of {}",
+                tally.approving, tally.vetoing, tally.total
+            ));
+        }
+        lines.push(format!("signatures ({}):", self.signers.len()));
+        lines.extend(self.signers.iter().map(|x| format!("  {}", x)));
+        lines.push(format!("changed files ({}):", self.changed_files.len()));
+        lines.extend(
+            self.changed_files
+                .iter()
+                .map(|(path, size)| format!("  {} ({} bytes)", path, size)),
+        );
+        lines.join("\n")
+    }
+}
+
+impl Human for TransactionPreview {
+    fn human(&self) -> String {
+        let mut lines = vec![format!("transaction: {}", self.draft.head)];
+        if !self.draft.body.is_empty() {
+            lines.push(String::new());
+            lines.push(self.draft.body.clone());
+        }
+        lines.push(String::new());
+        lines.extend(self.changes.iter().map(|(path, size)| match size {
+            Some(size) => format!("  {} ({} bytes)", path, size),
+            None => format!("  {} (deleted)", path),
+        }));
+        lines.push(String::new());
+        lines.push(self.diff.clone());
+        lines.extend(self.problems.iter().map(|x| format!("problem: {}", x)));
+        lines.join("\n")
+    }
+}
+
+impl Human for Draft {
+    fn human(&self) -> String {
+        let mut lines = vec![format!(
+            "draft {} ({} transactions{})",
+            self.name,
+            self.transactions.len(),
+            if self.outdated { ", outdated" } else { "" }
+        )];
+        lines.extend(self.transactions.iter().map(|x| format!("  {}", x)));
+        lines.join("\n")
+    }
+}
+
+impl Human for Vec<Draft> {
+    fn human(&self) -> String {
+        self.iter().map(Human::human).collect::<Vec<_>>().join("\n")
+    }
+}
+
+impl Human for Vec<SignedReviewComment> {
+    fn human(&self) -> String {
+        let mut lines = Vec::new();
+        for x in self {
+            lines.push(format!(
+                "{}:{} by {} at {}{}",
+                x.comment.path,
+                x.comment.line,
+                hex::encode(&x.author),
+                x.timestamp,
+                if x.off_the_record {
+                    " (off the record)"
+                } else {
+                    ""
+                }
+            ));
+            lines.extend(x.comment.text.lines().map(|x| format!("  {}", x)));
+        }
+        lines.join("\n")
+    }
+}
+
+impl Human for Diagnosis {
+    fn human(&self) -> String {
+        let mut lines = Vec::new();
+        for finding in &self.findings {
+            let status = match finding.status {
+                Status::Ok => "ok",
+                Status::Warning => "warning",
+                Status::Failure => "FAILURE",
+                Status::Skipped => "skipped",
+            };
+            lines.push(format!(
+                "[{}] {}: {}",
+                status, finding.check, finding.detail
+            ));
+            if let Some(remedy) = &finding.remedy {
+                lines.push(format!("    -> {}", remedy));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+impl Human for Vec<simperby_node::simperby_repository::raw::BlameLine> {
+    fn human(&self) -> String {
+        self.iter()
+            .map(|x| {
+                format!(
+                    "{} {} {:>4} {}",
+                    &hex::encode(x.commit_hash.hash)[0..8],
+                    x.author,
+                    x.line_number,
+                    x.content
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligned() {
+        let rendered = table(
+            &["A", "LONG"],
+            vec![
+                vec!["xyz".to_owned(), "1".to_owned()],
+                vec!["w".to_owned(), "".to_owned()],
+            ],
+        );
+        assert_eq!(rendered, "A    LONG\nxyz  1\nw");
+    }
+}
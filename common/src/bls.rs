@@ -0,0 +1,182 @@
+//! BLS12-381 signatures, which can be aggregated into a constant-size signature.
+//!
+//! It uses the `min_pk` variant (48-byte public keys, 96-byte signatures) with the
+//! proof-of-possession scheme; a key must be registered with `BlsPublicKey::verify_possession()`
+//! checked, to prevent rogue-key attacks on the aggregation.
+use crate::*;
+use blst::min_pk;
+use blst::BLST_ERROR;
+use serde::{Deserialize, Serialize};
+
+const SIGNATURE_DST: &[u8] = b"BLS_SIG_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+const POSSESSION_DST: &[u8] = b"BLS_POP_BLS12381G2_XMD:SHA-256_SSWU_RO_POP_";
+
+type Error = CryptoError;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize, Hash)]
+pub struct BlsPublicKey {
+    key: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize, Hash)]
+pub struct BlsPrivateKey {
+    key: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize, Hash)]
+pub struct BlsSignature {
+    signature: Vec<u8>,
+}
+
+impl BlsPublicKey {
+    fn to_blst(&self) -> Result<min_pk::PublicKey, Error> {
+        min_pk::PublicKey::key_validate(&self.key)
+            .map_err(|e| Error::InvalidFormat(format!("BLS public key: {:?}", e)))
+    }
+
+    /// Verifies the proof of possession of the private key.
+    pub fn verify_possession(&self, proof: &BlsSignature) -> Result<(), Error> {
+        verify(proof, &self.key, POSSESSION_DST, &self.to_blst()?)
+    }
+}
+
+impl BlsPrivateKey {
+    pub fn public_key(&self) -> BlsPublicKey {
+        BlsPublicKey {
+            key: self.to_blst().sk_to_pk().to_bytes().to_vec(),
+        }
+    }
+
+    fn to_blst(&self) -> min_pk::SecretKey {
+        min_pk::SecretKey::from_bytes(&self.key).expect("BLS private key is invalid")
+    }
+
+    pub fn sign(&self, data: Hash256) -> BlsSignature {
+        BlsSignature {
+            signature: self
+                .to_blst()
+                .sign(data.as_ref(), SIGNATURE_DST, &[])
+                .to_bytes()
+                .to_vec(),
+        }
+    }
+
+    /// Creates the proof of possession, which must accompany the public key when registered.
+    pub fn prove_possession(&self) -> BlsSignature {
+        BlsSignature {
+            signature: self
+                .to_blst()
+                .sign(&self.public_key().key, POSSESSION_DST, &[])
+                .to_bytes()
+                .to_vec(),
+        }
+    }
+}
+
+impl BlsSignature {
+    fn to_blst(&self) -> Result<min_pk::Signature, Error> {
+        min_pk::Signature::sig_validate(&self.signature, true)
+            .map_err(|e| Error::InvalidFormat(format!("BLS signature: {:?}", e)))
+    }
+
+    pub fn verify(&self, data: Hash256, public_key: &BlsPublicKey) -> Result<(), Error> {
+        verify(self, data.as_ref(), SIGNATURE_DST, &public_key.to_blst()?)
+    }
+
+    /// Aggregates the signatures on the same data into one.
+    pub fn aggregate(signatures: &[BlsSignature]) -> Result<Self, Error> {
+        let signatures = signatures
+            .iter()
+            .map(|x| x.to_blst())
+            .collect::<Result<Vec<_>, _>>()?;
+        let aggregate =
+            min_pk::AggregateSignature::aggregate(&signatures.iter().collect::<Vec<_>>(), false)
+                .map_err(|e| Error::InvalidFormat(format!("BLS aggregation: {:?}", e)))?;
+        Ok(BlsSignature {
+            signature: aggregate.to_signature().to_bytes().to_vec(),
+        })
+    }
+
+    /// Verifies the aggregate signature of the given public keys on the same data.
+    ///
+    /// The public keys must have had their possession verified.
+    pub fn verify_aggregate(
+        &self,
+        data: Hash256,
+        public_keys: &[BlsPublicKey],
+    ) -> Result<(), Error> {
+        let public_keys = public_keys
+            .iter()
+            .map(|x| x.to_blst())
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = self.to_blst()?.fast_aggregate_verify(
+            true,
+            data.as_ref(),
+            SIGNATURE_DST,
+            &public_keys.iter().collect::<Vec<_>>(),
+        );
+        if result != BLST_ERROR::BLST_SUCCESS {
+            return Err(Error::VerificationFailed);
+        }
+        Ok(())
+    }
+}
+
+fn verify(
+    signature: &BlsSignature,
+    message: &[u8],
+    dst: &[u8],
+    public_key: &min_pk::PublicKey,
+) -> Result<(), Error> {
+    let result = signature
+        .to_blst()?
+        .verify(true, message, dst, &[], public_key, false);
+    if result != BLST_ERROR::BLST_SUCCESS {
+        return Err(Error::VerificationFailed);
+    }
+    Ok(())
+}
+
+/// Generates a BLS key pair from the seed.
+pub fn generate_bls_keypair(seed: impl AsRef<[u8]>) -> (BlsPublicKey, BlsPrivateKey) {
+    // `key_gen()` requires at least 32 bytes of input keying material.
+    let ikm = Hash256::hash(seed);
+    let private_key = BlsPrivateKey {
+        key: min_pk::SecretKey::key_gen(ikm.as_ref(), &[])
+            .expect("input keying material is 32 bytes")
+            .to_bytes()
+            .to_vec(),
+    };
+    (private_key.public_key(), private_key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn aggregate() {
+        let data = Hash256::hash("block");
+        let keys = (0..4)
+            .map(|i| generate_bls_keypair(format!("{}", i)))
+            .collect::<Vec<_>>();
+        for (public_key, private_key) in &keys {
+            public_key
+                .verify_possession(&private_key.prove_possession())
+                .unwrap();
+        }
+        let signatures = keys
+            .iter()
+            .map(|(_, private_key)| private_key.sign(data))
+            .collect::<Vec<_>>();
+        let aggregate = BlsSignature::aggregate(&signatures).unwrap();
+        let public_keys = keys.iter().map(|(x, _)| x.clone()).collect::<Vec<_>>();
+        aggregate.verify_aggregate(data, &public_keys).unwrap();
+        aggregate
+            .verify_aggregate(Hash256::hash("other"), &public_keys)
+            .unwrap_err();
+        aggregate
+            .verify_aggregate(data, &public_keys[1..])
+            .unwrap_err();
+    }
+}
@@ -0,0 +1,162 @@
+//! Deterministic serialization of the consensus-relevant types.
+//!
+//! Every hash or signature over a structured value must be calculated on its canonical encoding,
+//! which is a JSON text with
+//! - no whitespace,
+//! - object keys sorted in byte order,
+//! - integers only (floating point numbers are rejected, as their textual form is not stable).
+//!
+//! This makes the hashes identical across platforms and across versions of `serde_json`.
+use crate::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+#[derive(Error, Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum CanonicalError {
+    #[error("failed to serialize: {0}")]
+    Serialization(String),
+    #[error("floating point numbers are not allowed")]
+    FloatingPoint,
+}
+
+type Error = CanonicalError;
+
+/// Serializes the value into its canonical encoding.
+pub fn to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    let value = serde_json::to_value(value).map_err(|e| Error::Serialization(e.to_string()))?;
+    let mut buffer = Vec::new();
+    write_value(&value, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Hashes the canonical encoding of the value.
+pub fn try_to_hash256<T: Serialize>(value: &T) -> Result<Hash256, Error> {
+    to_vec(value).map(Hash256::hash)
+}
+
+/// Hashes the canonical encoding of the value.
+///
+/// Panics if the value can't be encoded, which never happens for the types of this crate.
+pub fn to_hash256<T: Serialize>(value: &T) -> Hash256 {
+    try_to_hash256(value).expect("failed to encode a value canonically")
+}
+
+fn write_value(value: &Value, buffer: &mut Vec<u8>) -> Result<(), Error> {
+    match value {
+        Value::Null | Value::Bool(_) | Value::String(_) => {
+            // The JSON text of these is already unique.
+            serde_json::to_writer(&mut *buffer, value)
+                .map_err(|e| Error::Serialization(e.to_string()))?;
+        }
+        Value::Number(number) => {
+            if let Some(x) = number.as_u64() {
+                buffer.extend_from_slice(x.to_string().as_bytes());
+            } else if let Some(x) = number.as_i64() {
+                buffer.extend_from_slice(x.to_string().as_bytes());
+            } else {
+                return Err(Error::FloatingPoint);
+            }
+        }
+        Value::Array(array) => {
+            buffer.push(b'[');
+            for (i, item) in array.iter().enumerate() {
+                if i > 0 {
+                    buffer.push(b',');
+                }
+                write_value(item, buffer)?;
+            }
+            buffer.push(b']');
+        }
+        Value::Object(object) => {
+            // Sorts explicitly; the order of `serde_json::Map` depends on its features.
+            let mut entries = object.iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+            buffer.push(b'{');
+            for (i, (key, item)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    buffer.push(b',');
+                }
+                serde_json::to_writer(&mut *buffer, key)
+                    .map_err(|e| Error::Serialization(e.to_string()))?;
+                buffer.push(b':');
+                write_value(item, buffer)?;
+            }
+            buffer.push(b'}');
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn sorted_keys() {
+        let mut map = HashMap::new();
+        map.insert("b".to_owned(), 2u64);
+        map.insert("a".to_owned(), 1u64);
+        map.insert("c".to_owned(), 3u64);
+        assert_eq!(to_vec(&map).unwrap(), br#"{"a":1,"b":2,"c":3}"#.to_vec());
+    }
+
+    #[test]
+    fn nested() {
+        #[derive(Serialize)]
+        struct Inner {
+            y: i64,
+            x: Option<String>,
+        }
+        #[derive(Serialize)]
+        struct Outer {
+            list: Vec<Inner>,
+            flag: bool,
+        }
+        let value = Outer {
+            list: vec![Inner {
+                y: -1,
+                x: Some("\"quoted\"".to_owned()),
+            }],
+            flag: true,
+        };
+        assert_eq!(
+            to_vec(&value).unwrap(),
+            br#"{"flag":true,"list":[{"x":"\"quoted\"","y":-1}]}"#.to_vec()
+        );
+    }
+
+    #[test]
+    fn reject_float() {
+        assert_eq!(to_vec(&vec![1.5f64]), Err(Error::FloatingPoint));
+    }
+
+    #[test]
+    fn stable_hash() {
+        let mut a = HashMap::new();
+        let mut b = HashMap::new();
+        for i in 0..32u64 {
+            a.insert(i.to_string(), i);
+            b.insert((31 - i).to_string(), 31 - i);
+        }
+        assert_eq!(to_hash256(&a), to_hash256(&b));
+    }
+
+    #[test]
+    fn agenda_without_optional_fields() {
+        let agenda = crate::Agenda {
+            author: crate::generate_keypair("a").0,
+            timestamp: 0,
+            hash: crate::Hash256::zero(),
+            expiration_height: None,
+            expiration_timestamp: None,
+            supersedes: None,
+            amendment_proof: None,
+        };
+        // Omitted, so that the fields added later don't change the hash of an agenda.
+        let value: serde_json::Value = serde_json::from_slice(&to_vec(&agenda).unwrap()).unwrap();
+        let keys: Vec<_> = value.as_object().unwrap().keys().cloned().collect();
+        assert_eq!(keys, vec!["author", "hash", "timestamp"]);
+    }
+}
@@ -49,6 +49,30 @@ impl Hash256 {
     }
 }
 
+/// Hashes the data given in pieces, without holding the whole of it.
+///
+/// The result is the same as `Hash256::hash()` of the concatenation of the pieces.
+#[derive(Clone, Default)]
+pub struct Hash256Hasher {
+    hasher: blake3::Hasher,
+}
+
+impl Hash256Hasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.hasher.update(data.as_ref());
+    }
+
+    pub fn finalize(&self) -> Hash256 {
+        Hash256 {
+            hash: *self.hasher.finalize().as_bytes(),
+        }
+    }
+}
+
 impl std::convert::AsRef<[u8]> for Hash256 {
     fn as_ref(&self) -> &[u8] {
         &self.hash
@@ -99,11 +123,54 @@ impl Signature {
             signature: bytes.to_vec(),
         }
     }
+
+    /// Verifies the signatures of the given data and public keys together,
+    /// which is faster than one by one for many signatures.
+    ///
+    /// If the batch fails, the signatures are verified one by one to identify the invalid one:
+    /// the error comes with the index of the first.
+    pub fn verify_batch(
+        signatures: &[(Hash256, &Signature, &PublicKey)],
+    ) -> Result<(), (usize, Error)> {
+        let mut messages = Vec::with_capacity(signatures.len());
+        let mut parsed_signatures = Vec::with_capacity(signatures.len());
+        let mut public_keys = Vec::with_capacity(signatures.len());
+        for (index, (data, signature, public_key)) in signatures.iter().enumerate() {
+            messages.push(data.as_ref());
+            parsed_signatures.push(
+                ed25519_dalek::Signature::from_bytes(&signature.signature).map_err(|_| {
+                    (
+                        index,
+                        Error::InvalidFormat(format!("signature: {}", signature)),
+                    )
+                })?,
+            );
+            public_keys.push(
+                ed25519_dalek::PublicKey::from_bytes(&public_key.key).map_err(|_| {
+                    (
+                        index,
+                        Error::InvalidFormat(format!("public_key: {}", public_key)),
+                    )
+                })?,
+            );
+        }
+        if ed25519_dalek::verify_batch(&messages, &parsed_signatures, &public_keys).is_ok() {
+            return Ok(());
+        }
+        // The batch may also reject a signature that is valid alone, which is accepted here.
+        for (index, (data, signature, public_key)) in signatures.iter().enumerate() {
+            signature
+                .verify(*data, public_key)
+                .map_err(|e| (index, e))?;
+        }
+        Ok(())
+    }
 }
 
 /// A signature that is explicitly marked with the type of the signed data.
 ///
-/// This implies that the signature is created on `Hash256::hash(serde_json::to_vec(T).unwrap())`.
+/// This implies that the signature is created on `T::to_hash256()`, which is calculated
+/// on the canonical encoding of `T` (see `canonical`).
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize, Hash)]
 pub struct TypedSignature<T> {
     signature: Signature,
@@ -134,11 +201,26 @@ impl<T: ToHash256> TypedSignature<T> {
         &self.signer
     }
 
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
     /// Verifies the signature against the given data and public key.
     pub fn verify(&self, data: &T) -> Result<(), Error> {
         let data = data.to_hash256();
         self.signature.verify(data, &self.signer)
     }
+
+    /// Verifies the signatures of the same data together (see `Signature::verify_batch()`).
+    pub fn verify_batch(signatures: &[Self], data: &T) -> Result<(), (usize, Error)> {
+        let data = data.to_hash256();
+        Signature::verify_batch(
+            &signatures
+                .iter()
+                .map(|signature| (data, &signature.signature, &signature.signer))
+                .collect::<Vec<_>>(),
+        )
+    }
 }
 
 impl std::convert::AsRef<[u8]> for Signature {
@@ -181,6 +263,60 @@ impl PublicKey {
     }
 }
 
+/// A k-of-n multisig key, which is satisfied by the signatures of `threshold` distinct signers.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize, Hash)]
+pub struct MultisigPublicKey {
+    pub signers: Vec<PublicKey>,
+    pub threshold: usize,
+}
+
+impl MultisigPublicKey {
+    pub fn new(signers: Vec<PublicKey>, threshold: usize) -> Result<Self, Error> {
+        if threshold == 0 || threshold > signers.len() {
+            return Err(Error::InvalidFormat(format!(
+                "threshold {} for {} signers",
+                threshold,
+                signers.len()
+            )));
+        }
+        Ok(Self { signers, threshold })
+    }
+
+    /// Returns whether the given signers satisfy the threshold, ignoring unknown ones.
+    ///
+    /// Note that this doesn't verify any signature.
+    pub fn is_satisfied_by<'a>(&self, signers: impl IntoIterator<Item = &'a PublicKey>) -> bool {
+        let mut satisfied = std::collections::BTreeSet::new();
+        for signer in signers {
+            if self.signers.contains(signer) {
+                satisfied.insert(signer);
+            }
+        }
+        satisfied.len() >= self.threshold
+    }
+
+    /// Verifies the signatures of the signers, and checks whether they satisfy the threshold.
+    pub fn verify(
+        &self,
+        data: Hash256,
+        signatures: &[(PublicKey, Signature)],
+    ) -> Result<(), Error> {
+        for (signer, signature) in signatures {
+            if !self.signers.contains(signer) {
+                return Err(Error::InvalidFormat(format!(
+                    "{} is not a signer of the multisig",
+                    signer
+                )));
+            }
+            signature.verify(data, signer)?;
+        }
+        if !self.is_satisfied_by(signatures.iter().map(|(signer, _)| signer)) {
+            return Err(Error::VerificationFailed);
+        }
+        Ok(())
+    }
+}
+
 /// A private key.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize, Hash)]
 pub struct PrivateKey {
@@ -236,3 +372,36 @@ pub fn generate_keypair(seed: impl AsRef<[u8]>) -> (PublicKey, PrivateKey) {
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_batch() {
+        let keys: Vec<_> = (0..4)
+            .map(|i| generate_keypair(format!("signer{}", i)))
+            .collect();
+        let data = Hash256::hash("data");
+        let mut signatures: Vec<_> = keys
+            .iter()
+            .map(|(_, private_key)| Signature::sign(data, private_key).unwrap())
+            .collect();
+        let batch = |signatures: &[Signature]| {
+            Signature::verify_batch(
+                &signatures
+                    .iter()
+                    .zip(&keys)
+                    .map(|(signature, (public_key, _))| (data, signature, public_key))
+                    .collect::<Vec<_>>(),
+            )
+        };
+        batch(&signatures).unwrap();
+        batch(&[]).unwrap();
+
+        signatures[2] = Signature::sign(Hash256::hash("other"), &keys[2].1).unwrap();
+        assert_eq!(batch(&signatures).unwrap_err().0, 2);
+        signatures[1] = Signature::from_bytes(&[0; 3]);
+        assert_eq!(batch(&signatures).unwrap_err().0, 1);
+    }
+}
@@ -0,0 +1,172 @@
+//! The multi-party genesis ceremony.
+//!
+//! 1. A founder drafts a `GenesisProposal` and shares it with the founding members.
+//! 2. Each founding member reviews it and signs its genesis header (`GenesisProposal::approve()`).
+//! 3. Anyone who has collected the approvals of *every* founding member
+//!    produces the genesis reserved state (`GenesisProposal::finalize()`).
+//!
+//! Everything is derived from the proposal and the set of approvals,
+//! so every participant produces the same genesis regardless of the order of collection.
+use crate::reserved::*;
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct GenesisProposal {
+    pub chain_name: String,
+    /// The founding members, whose order is the consensus leader order.
+    pub members: Vec<Member>,
+    pub version: String,
+    pub governance_params: GovernanceParams,
+    pub consensus_params: ConsensusParams,
+    pub bootstrap_peers: Vec<String>,
+    /// The initial chain parameters.
+    #[serde(default)]
+    pub parameters: ChainParameters,
+    /// The timestamp of the genesis block.
+    pub timestamp: Timestamp,
+}
+
+impl ToHash256 for GenesisProposal {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+impl GenesisProposal {
+    fn reserved_state(&self, genesis_info: GenesisInfo) -> ReservedState {
+        ReservedState {
+            genesis_info,
+            members: self.members.clone(),
+            consensus_leader_order: (0..self.members.len()).collect(),
+            version: self.version.clone(),
+            governance_params: self.governance_params.clone(),
+            consensus_params: self.consensus_params.clone(),
+            bootstrap_peers: self.bootstrap_peers.clone(),
+            parameters: self.parameters.clone(),
+            external_events: Default::default(),
+            processed_evidences: Default::default(),
+        }
+    }
+
+    /// Returns the genesis header, which the founding members sign.
+    ///
+    /// It commits to the whole proposal through `repository_merkle_root`,
+    /// so an approval is valid only for the exact proposal.
+    pub fn header(&self) -> Result<BlockHeader, String> {
+        if self.chain_name.is_empty() {
+            return Err("the chain name is empty".to_string());
+        }
+        let author = self
+            .members
+            .first()
+            .ok_or_else(|| "there is no member".to_string())?
+            .public_key
+            .clone();
+        let mut header = BlockHeader {
+            author,
+            prev_block_finalization_proof: Vec::new(),
+            previous_hash: Hash256::zero(),
+            height: 0,
+            timestamp: self.timestamp,
+            commit_hash: Hash256::zero(),
+            tx_merkle_root: Hash256::zero(),
+            chat_merkle_root: Hash256::zero(),
+            repository_merkle_root: self.to_hash256(),
+            validator_set: Vec::new(),
+            version: self.version.clone(),
+        };
+        let state = self.reserved_state(GenesisInfo {
+            header: header.clone(),
+            genesis_proof: Vec::new(),
+            chain_name: self.chain_name.clone(),
+        });
+        state.validate()?;
+        header.validator_set = state.create_validator_set()?;
+        if header.validator_set.is_empty() {
+            return Err("there is no validator".to_string());
+        }
+        Ok(header)
+    }
+
+    /// Approves the proposal as a founding member.
+    pub fn approve(&self, private_key: &PrivateKey) -> Result<TypedSignature<BlockHeader>, String> {
+        let public_key = private_key.public_key();
+        if !self.members.iter().any(|m| m.public_key == public_key) {
+            return Err(format!("{} is not a founding member", public_key));
+        }
+        TypedSignature::sign(&self.header()?, private_key).map_err(|e| e.to_string())
+    }
+
+    /// Verifies the approvals and produces the genesis reserved state.
+    ///
+    /// Every founding member must have approved; duplicate approvals are ignored,
+    /// and the approvals of non-members or for another proposal are rejected.
+    pub fn finalize(
+        &self,
+        approvals: &[TypedSignature<BlockHeader>],
+    ) -> Result<ReservedState, String> {
+        let header = self.header()?;
+        let mut collected = BTreeMap::new();
+        for approval in approvals {
+            if !self
+                .members
+                .iter()
+                .any(|m| &m.public_key == approval.signer())
+            {
+                return Err(format!("{} is not a founding member", approval.signer()));
+            }
+            approval
+                .verify(&header)
+                .map_err(|e| format!("invalid approval of {}: {}", approval.signer(), e))?;
+            collected.insert(approval.signer().clone(), approval.clone());
+        }
+        let missing: Vec<_> = self
+            .members
+            .iter()
+            .filter(|m| !collected.contains_key(&m.public_key))
+            .map(|m| m.name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("missing the approvals of {}", missing.join(", ")));
+        }
+        let state = self.reserved_state(GenesisInfo {
+            header,
+            genesis_proof: collected.into_values().collect(),
+            chain_name: self.chain_name.clone(),
+        });
+        state.validate()?;
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::genesis_proposal;
+
+    #[test]
+    fn ceremony() {
+        let proposal = genesis_proposal(&["a", "b", "c"]);
+        let approvals: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|x| proposal.approve(&generate_keypair(x).1).unwrap())
+            .collect();
+
+        assert!(proposal.finalize(&approvals[..2]).is_err());
+        let state = proposal.finalize(&approvals).unwrap();
+        // The order of the collection doesn't matter.
+        let mut reversed = approvals.clone();
+        reversed.reverse();
+        reversed.push(approvals[0].clone());
+        assert_eq!(proposal.finalize(&reversed).unwrap(), state);
+        assert_eq!(state.genesis_info.header.validator_set.len(), 3);
+
+        // An approval is only for the exact proposal.
+        let mut other = proposal.clone();
+        other.timestamp = 1;
+        assert!(other.finalize(&approvals).is_err());
+        assert!(proposal.approve(&generate_keypair("d").1).is_err());
+    }
+}
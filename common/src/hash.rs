@@ -8,49 +8,121 @@ impl ToHash256 for String {
 
 impl ToHash256 for Member {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_json::to_vec(self).unwrap())
+        canonical::to_hash256(self)
     }
 }
 
 impl ToHash256 for BlockHeader {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_json::to_vec(self).unwrap())
+        canonical::to_hash256(self)
     }
 }
 
 impl ToHash256 for Transaction {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_json::to_vec(self).unwrap())
+        canonical::to_hash256(self)
     }
 }
 
 impl ToHash256 for Agenda {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_json::to_vec(self).unwrap())
+        canonical::to_hash256(self)
     }
 }
 
 impl ToHash256 for AgendaProof {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_json::to_vec(self).unwrap())
+        canonical::to_hash256(self)
     }
 }
 
 impl ToHash256 for ExtraAgendaTransaction {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_json::to_vec(self).unwrap())
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for ExternalEvent {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for ChatMessage {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
     }
 }
 
 impl ToHash256 for ChatLog {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_json::to_vec(self).unwrap())
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for ConsensusPayload {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for Evidence {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
     }
 }
 
 impl ToHash256 for GenesisInfo {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_json::to_vec(self).unwrap())
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for (Hash256, PublicKey, PublicKey, BlockHeight) {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for (PublicKey, PublicKey, bool, BlockHeight) {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for (Hash256, Hash256) {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for (Hash256, VoteExtension) {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for (MemberName, BlockHeight) {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for (MemberName, String, BlockHeight) {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for (PublicKey, BlockHeight) {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+impl ToHash256 for reserved::ReservedState {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
     }
 }
 
@@ -101,9 +173,11 @@ impl BlockHeader {
         merkle_tree.root()
     }
 
-    pub fn calculate_chat_merkle_root(&self, _chat_logs: &[ChatLog]) -> Hash256 {
-        // TODO
-        Hash256::zero()
+    pub fn calculate_chat_merkle_root(&self, chat_logs: &[ChatLog]) -> Hash256 {
+        let merkle_tree = crate::merkle_tree::OneshotMerkleTree::create(
+            chat_logs.iter().map(|x| x.merkle_root()).collect(),
+        );
+        merkle_tree.root()
     }
 
     // note that `repository_merkle_root` is calculated from `simperby-repository`.
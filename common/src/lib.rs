@@ -1,8 +1,14 @@
+pub mod bls;
+pub mod canonical;
 pub mod crypto;
+pub mod genesis;
 pub mod hash;
-pub mod light_client;
+pub mod membership;
 pub mod merkle_tree;
 pub mod reserved;
+pub mod signature_cache;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 pub mod types;
 pub mod verify;
 
@@ -0,0 +1,251 @@
+//! The onboarding of a new member: invite, apply and approve.
+//!
+//! 1. A member invites the newcomer, fixing the name and the voting powers (`Invitation`).
+//! 2. The newcomer applies with its keys and metadata, signing the application with the new key
+//!    as the proof of possession (`Application`).
+//! 3. A member submits the application as a transaction adding the member
+//!    (`SignedApplication::to_transaction()`), which the members review and vote on in an agenda
+//!    as usual. The member is added once the agenda is approved and finalized.
+use crate::bls::{BlsPublicKey, BlsSignature};
+use crate::reserved::*;
+use crate::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Invitation {
+    pub chain_name: String,
+    /// The name of the new member, which must not be taken when the application is submitted.
+    pub name: MemberName,
+    pub governance_voting_power: VotingPower,
+    pub consensus_voting_power: VotingPower,
+    /// The invitation can't be submitted after this.
+    pub expires_at: Timestamp,
+    /// The member who invites.
+    pub inviter: PublicKey,
+}
+
+impl ToHash256 for Invitation {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SignedInvitation {
+    pub invitation: Invitation,
+    pub signature: TypedSignature<Invitation>,
+}
+
+impl Invitation {
+    /// Signs the invitation as the inviter.
+    pub fn sign(self, private_key: &PrivateKey) -> Result<SignedInvitation, String> {
+        if private_key.public_key() != self.inviter {
+            return Err("the key is not of the inviter".to_string());
+        }
+        let signature = TypedSignature::sign(&self, private_key).map_err(|e| e.to_string())?;
+        Ok(SignedInvitation {
+            invitation: self,
+            signature,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Application {
+    pub invitation: SignedInvitation,
+    pub public_key: PublicKey,
+    /// The BLS key for the aggregate finalization proofs, with its proof of possession.
+    pub bls_public_key: Option<(BlsPublicKey, BlsSignature)>,
+    /// The information of the applicant for the review (e.g., `contact`).
+    pub metadata: BTreeMap<String, String>,
+    pub timestamp: Timestamp,
+}
+
+impl ToHash256 for Application {
+    fn to_hash256(&self) -> Hash256 {
+        canonical::to_hash256(self)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SignedApplication {
+    pub application: Application,
+    pub signature: TypedSignature<Application>,
+}
+
+impl Application {
+    /// Signs the application with the key of the applicant.
+    pub fn sign(self, private_key: &PrivateKey) -> Result<SignedApplication, String> {
+        if private_key.public_key() != self.public_key {
+            return Err("the key is not of the applicant".to_string());
+        }
+        let signature = TypedSignature::sign(&self, private_key).map_err(|e| e.to_string())?;
+        Ok(SignedApplication {
+            application: self,
+            signature,
+        })
+    }
+}
+
+impl SignedApplication {
+    /// Verifies the application against the reserved state, returning the member to add.
+    ///
+    /// `now` is checked against the expiration of the invitation.
+    pub fn verify(&self, reserved_state: &ReservedState, now: Timestamp) -> Result<Member, String> {
+        let application = &self.application;
+        let SignedInvitation {
+            invitation,
+            signature,
+        } = &application.invitation;
+        if invitation.chain_name != reserved_state.genesis_info.chain_name {
+            return Err(format!("the invitation is for {}", invitation.chain_name));
+        }
+        if now > invitation.expires_at {
+            return Err("the invitation has expired".to_string());
+        }
+        if signature.signer() != &invitation.inviter {
+            return Err("the invitation is not signed by the inviter".to_string());
+        }
+        signature
+            .verify(invitation)
+            .map_err(|e| format!("invalid signature of the invitation: {}", e))?;
+        if !reserved_state
+            .members
+            .iter()
+            .any(|m| m.public_key == invitation.inviter)
+        {
+            return Err(format!(
+                "the inviter {} is not a member",
+                invitation.inviter
+            ));
+        }
+        if self.signature.signer() != &application.public_key {
+            return Err("the application is not signed by the applicant".to_string());
+        }
+        self.signature
+            .verify(application)
+            .map_err(|e| format!("invalid signature of the application: {}", e))?;
+        if let Some((bls_public_key, proof)) = &application.bls_public_key {
+            bls_public_key
+                .verify_possession(proof)
+                .map_err(|e| format!("invalid proof of possession of the BLS key: {}", e))?;
+        }
+        Ok(Member {
+            public_key: application.public_key.clone(),
+            name: invitation.name.clone(),
+            governance_voting_power: invitation.governance_voting_power,
+            consensus_voting_power: invitation.consensus_voting_power,
+            governance_delegations: None,
+            consensus_delegations: None,
+            previous_public_keys: Vec::new(),
+            multisig_public_key: None,
+            bls_public_key: application.bls_public_key.clone(),
+        })
+    }
+
+    /// Creates the transaction adding the member, to be committed in the block of `height`.
+    ///
+    /// The application is kept in the body, so that the reviewers can verify it.
+    pub fn to_transaction(
+        &self,
+        reserved_state: &ReservedState,
+        height: BlockHeight,
+        author: PublicKey,
+        timestamp: Timestamp,
+    ) -> Result<Transaction, String> {
+        let member = self.verify(reserved_state, timestamp)?;
+        let head = format!("add member: {}", member.name);
+        let change = ReservedStateChange::AddMember(member);
+        let next_state = reserved_state.apply(&change, height)?;
+        Ok(Transaction {
+            author,
+            timestamp,
+            head,
+            body: serde_json::to_string_pretty(self).map_err(|e| e.to_string())?,
+            diff: Diff::Reserved(Box::new(next_state), vec![change]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::genesis;
+
+    fn apply(
+        name: &str,
+        inviter_key: &PrivateKey,
+        applicant_key: &PrivateKey,
+    ) -> SignedApplication {
+        let invitation = Invitation {
+            chain_name: "test".to_string(),
+            name: name.to_string(),
+            governance_voting_power: 1,
+            consensus_voting_power: 0,
+            expires_at: 100,
+            inviter: inviter_key.public_key(),
+        }
+        .sign(inviter_key)
+        .unwrap();
+        Application {
+            invitation,
+            public_key: applicant_key.public_key(),
+            bls_public_key: None,
+            metadata: BTreeMap::from([("contact".to_string(), "b@example.org".to_string())]),
+            timestamp: 10,
+        }
+        .sign(applicant_key)
+        .unwrap()
+    }
+
+    #[test]
+    fn onboarding() {
+        let (_, a_key) = generate_keypair("a");
+        let (b, b_key) = generate_keypair("b");
+        let (_, c_key) = generate_keypair("c");
+        let state = genesis(&["a"]);
+
+        let application = apply("b", &a_key, &b_key);
+        let tx = application
+            .to_transaction(&state, 1, a_key.public_key(), 50)
+            .unwrap();
+        match tx.diff {
+            Diff::Reserved(next_state, _) => {
+                assert_eq!(next_state.members.len(), 2);
+                assert_eq!(next_state.members[1].public_key, b);
+                assert_eq!(next_state.members[1].name, "b");
+            }
+            _ => panic!("not a reserved-state diff"),
+        }
+        let decoded: SignedApplication = serde_json::from_str(&tx.body).unwrap();
+        assert_eq!(decoded, application);
+
+        // Expired.
+        assert!(application.verify(&state, 101).is_err());
+        // The name is taken.
+        assert!(apply("a", &a_key, &b_key)
+            .to_transaction(&state, 1, a_key.public_key(), 50)
+            .is_err());
+        // Invited by a non-member.
+        assert!(apply("b", &c_key, &b_key).verify(&state, 50).is_err());
+        // Tampered after signing.
+        let mut tampered = application;
+        tampered
+            .application
+            .invitation
+            .invitation
+            .consensus_voting_power = 10;
+        assert!(tampered.verify(&state, 50).is_err());
+        // Signed by another key.
+        assert!(Application {
+            invitation: apply("b", &a_key, &b_key).application.invitation,
+            public_key: b,
+            bls_public_key: None,
+            metadata: BTreeMap::new(),
+            timestamp: 10,
+        }
+        .sign(&c_key)
+        .is_err());
+    }
+}
@@ -141,6 +141,9 @@ pub enum MerkleProofError {
     /// When the root doesn't match
     #[error("unmatched string: expected {0} but found {1}")]
     UnmatchedRoot(String, String),
+    /// When the data to prove is not in the tree.
+    #[error("not included: {0}")]
+    NotIncluded(String),
 }
 
 impl MerkleProof {
@@ -169,6 +172,45 @@ impl MerkleProof {
     }
 }
 
+/// Creates the proof that the transaction is included in the block.
+///
+/// `transactions` must be all the transactions of the block, in order.
+pub fn prove_transaction(
+    header: &BlockHeader,
+    transactions: &[Transaction],
+    transaction: &Transaction,
+) -> Result<MerkleProof, MerkleProofError> {
+    let merkle_tree =
+        OneshotMerkleTree::create(transactions.iter().map(|x| x.to_hash256()).collect());
+    if merkle_tree.root() != header.tx_merkle_root {
+        return Err(MerkleProofError::UnmatchedRoot(
+            hex::encode(header.tx_merkle_root.hash),
+            hex::encode(merkle_tree.root().hash),
+        ));
+    }
+    merkle_tree
+        .create_merkle_proof(transaction.to_hash256())
+        .ok_or_else(|| {
+            MerkleProofError::NotIncluded(format!(
+                "transaction {} in block {}",
+                transaction.to_hash256(),
+                header.height
+            ))
+        })
+}
+
+/// Verifies that the transaction is included in the block of the given header.
+pub fn verify_transaction_proof(
+    header: &BlockHeader,
+    transaction: &Transaction,
+    proof: &MerkleProof,
+) -> Result<(), MerkleProofError> {
+    // The leaves are `to_hash256()` of the transactions, which is the hash of the canonical encoding.
+    let data = canonical::to_vec(transaction)
+        .map_err(|e| MerkleProofError::MalformedProof(e.to_string()))?;
+    proof.verify(header.tx_merkle_root, &data)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -265,4 +307,26 @@ mod test {
         assert!(root_hash != OneshotMerkleTree::EMPTY_HASH);
         assert!(MerkleProof::verify(&merkle_proof.unwrap(), root_hash, &[10]).is_ok());
     }
+
+    #[test]
+    /// Test if a transaction proof is verified against the header.
+    fn transaction_proof() {
+        let (author, _) = generate_keypair("author");
+        let transactions: Vec<Transaction> = (0..5)
+            .map(|i| Transaction {
+                author: author.clone(),
+                timestamp: i,
+                head: format!("tx {}", i),
+                body: String::new(),
+                diff: Diff::None,
+            })
+            .collect();
+        let mut header = crate::test_util::header(1);
+        header.tx_merkle_root = header.calculate_tx_merkle_root(&transactions);
+
+        let proof = prove_transaction(&header, &transactions, &transactions[3]).unwrap();
+        verify_transaction_proof(&header, &transactions[3], &proof).unwrap();
+        assert!(verify_transaction_proof(&header, &transactions[2], &proof).is_err());
+        assert!(prove_transaction(&header, &transactions[..4], &transactions[3]).is_err());
+    }
 }
@@ -1,5 +1,106 @@
 use crate::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// A change of the reserved state.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum ReservedStateChange {
+    AddMember(Member),
+    /// Removes the member of the given name.
+    RemoveMember(MemberName),
+    Delegate(TxDelegate),
+    Undelegate(TxUndelegate),
+    RotateKey(TxRotateKey),
+    /// Bumps the protocol version to the given one, which must be higher.
+    BumpVersion(String),
+    /// Imposes the penalty of the chain (see `ChainParameters::report_penalty()`)
+    /// on the offenders of an evidence, once per evidence.
+    Penalize(Penalization),
+    /// Replaces the bootstrap peers with the given ones.
+    SetBootstrapPeers(Vec<String>),
+    /// Removes the member, verifying the approvals against the emergency threshold.
+    EmergencyExpel(TxEmergencyExpel),
+    /// Sets the chain parameters of the given keys, removing those given `None`.
+    ///
+    /// There is no extra-agenda transaction for this, so it takes an approved agenda.
+    SetParameters(BTreeMap<String, Option<ParameterValue>>),
+    /// Records the attestation, accepting the event once it reaches the threshold.
+    Attest(TxAttest),
+}
+
+impl ReservedStateChange {
+    /// Converts the extra-agenda transaction into the change it makes,
+    /// which takes effect once the transaction is in a finalized block.
+    ///
+    /// `validator_set_at` returns the validator set for the given height, if known;
+    /// the evidence of a `TxReport` is verified against the one for its height.
+    pub fn from_extra_agenda_transaction(
+        tx: &ExtraAgendaTransaction,
+        reserved_state: &ReservedState,
+        height: BlockHeight,
+        validator_set_at: impl Fn(BlockHeight) -> Option<Vec<(PublicKey, VotingPower)>>,
+    ) -> Result<Self, String> {
+        Ok(match tx {
+            ExtraAgendaTransaction::Delegate(tx) => Self::Delegate(tx.clone()),
+            ExtraAgendaTransaction::Undelegate(tx) => Self::Undelegate(tx.clone()),
+            ExtraAgendaTransaction::Report(tx) => {
+                let evidence_height = tx.evidence.height();
+                let validator_set = validator_set_at(evidence_height).ok_or(format!(
+                    "the validator set of the evidence height {} is unknown",
+                    evidence_height
+                ))?;
+                let offenders = crate::verify::verify_evidence(&tx.evidence, &validator_set)
+                    .map_err(|e| format!("invalid evidence: {}", e))?;
+                Self::Penalize(Penalization {
+                    evidence_hash: tx.evidence.misbehavior_hash(),
+                    height: evidence_height,
+                    offenders,
+                })
+            }
+            ExtraAgendaTransaction::RotateKey(tx) => Self::RotateKey(tx.clone()),
+            ExtraAgendaTransaction::RemoveMember(tx) => {
+                let data = (tx.name.clone(), height);
+                let mut approvers = BTreeSet::new();
+                for approval in &tx.approvals {
+                    approval
+                        .verify(&data)
+                        .map_err(|e| format!("invalid approval of {}: {}", approval.signer(), e))?;
+                    approvers.insert(approval.signer().clone());
+                }
+                let total: VotingPower = reserved_state
+                    .governance_voting_powers()
+                    .iter()
+                    .map(|(_, power)| power)
+                    .sum();
+                let approving = reserved_state.approved_governance_voting_power(&approvers);
+                if !reserved_state
+                    .governance_params
+                    .approval_threshold
+                    .is_exceeded_by(approving, total)
+                {
+                    return Err(format!(
+                        "the approvals ({} of {}) don't exceed the approval threshold",
+                        approving, total
+                    ));
+                }
+                Self::RemoveMember(tx.name.clone())
+            }
+            ExtraAgendaTransaction::EmergencyExpel(tx) => Self::EmergencyExpel(tx.clone()),
+            ExtraAgendaTransaction::Attest(tx) => Self::Attest(tx.clone()),
+        })
+    }
+}
+
+/// The penalty on the offenders of a verified evidence (see `TxReport`).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Penalization {
+    /// `Evidence::misbehavior_hash()` of the evidence, which can be processed only once.
+    pub evidence_hash: Hash256,
+    /// The height of the misbehavior.
+    pub height: BlockHeight,
+    pub offenders: Vec<PublicKey>,
+}
 
 /// The partial set of the blockchain state which is reserved and protected.
 ///
@@ -15,9 +116,472 @@ pub struct ReservedState {
     pub consensus_leader_order: Vec<usize>,
     /// The semantic version of Simperby protocol for this network.
     pub version: String,
+    pub governance_params: GovernanceParams,
+    pub consensus_params: ConsensusParams,
+    /// The endpoints (`host:port`) of the peer discovery to contact first,
+    /// so that a new member can join with nothing but the repository.
+    #[serde(default)]
+    pub bootstrap_peers: Vec<String>,
+    /// The governance-managed parameters of the chain.
+    #[serde(default)]
+    pub parameters: ChainParameters,
+    /// The events of the external chains attested by the members.
+    #[serde(default)]
+    pub external_events: ExternalEvents,
+    /// The `Evidence::misbehavior_hash()` of the evidences whose offenders have been penalized.
+    #[serde(default)]
+    pub processed_evidences: BTreeSet<Hash256>,
+}
+
+/// The events of the external chains, pending or accepted by the k-of-n rule of the attestations
+/// (k is `ChainParameters::ATTESTATION_THRESHOLD`, n is the number of the members).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+pub struct ExternalEvents {
+    /// The events not accepted yet, with the names of the members who attested them.
+    pub pending: Vec<(ExternalEvent, Vec<MemberName>)>,
+    /// The accepted events, with the heights of the blocks where they were accepted.
+    pub accepted: Vec<(ExternalEvent, BlockHeight)>,
+}
+
+impl ExternalEvents {
+    /// The maximum number of the pending events that a member may have attested to,
+    /// which bounds the pending events by the number of the members.
+    pub const MAX_PENDING_ATTESTATIONS_PER_MEMBER: usize = 16;
+
+    /// Returns the accepted event of the given identifier on the chain, if any.
+    pub fn find_accepted(&self, chain: &str, id: &str) -> Option<&ExternalEvent> {
+        self.accepted
+            .iter()
+            .map(|(event, _)| event)
+            .find(|event| event.chain == chain && event.id == id)
+    }
+}
+
+/// A fraction, for the thresholds which must be hashed deterministically
+/// (floating point numbers are not allowed; see `canonical`).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct Fraction {
+    pub numerator: u64,
+    pub denominator: u64,
+}
+
+impl Fraction {
+    pub const fn new(numerator: u64, denominator: u64) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// Returns whether `part / total` is strictly larger than this fraction.
+    pub fn is_exceeded_by(&self, part: VotingPower, total: VotingPower) -> bool {
+        part as u128 * self.denominator as u128 > total as u128 * self.numerator as u128
+    }
+
+    fn validate(&self, name: &str) -> Result<(), String> {
+        if self.denominator == 0 || self.numerator >= self.denominator {
+            return Err(format!(
+                "{} must be in [0, 1): {}/{}",
+                name, self.numerator, self.denominator
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct GovernanceParams {
+    /// An agenda is approved once the approving governance voting power exceeds this fraction.
+    pub approval_threshold: Fraction,
+    /// An agenda is rejected once the vetoing governance voting power exceeds this fraction.
+    pub veto_threshold: Fraction,
+    /// The number of blocks that an agenda can be voted for, if limited.
+    ///
+    /// This is the expiry policy of agendas; see `Agenda::expiration_height`.
+    pub voting_period_blocks: Option<BlockHeight>,
+    /// The time in milliseconds that an agenda can be voted for, if limited.
+    ///
+    /// This is the expiry policy of agendas; see `Agenda::expiration_timestamp`.
+    pub voting_period_ms: Option<Timestamp>,
+    /// A `TxEmergencyExpel` takes effect once its approvals exceed this fraction.
+    ///
+    /// It must not be lower than the approval threshold, since it bypasses the agenda.
+    #[serde(default = "GovernanceParams::default_emergency_threshold")]
+    pub emergency_threshold: Fraction,
+    /// The maximum number of blocks between a `TxRotateKey` and its rotation height,
+    /// which bounds how long the rotated-out key stays valid.
+    #[serde(default = "GovernanceParams::default_key_rotation_grace")]
+    pub key_rotation_grace: BlockHeight,
+}
+
+impl Default for GovernanceParams {
+    fn default() -> Self {
+        Self {
+            approval_threshold: Fraction::new(1, 2),
+            veto_threshold: Fraction::new(1, 3),
+            voting_period_blocks: None,
+            voting_period_ms: None,
+            emergency_threshold: Self::default_emergency_threshold(),
+            key_rotation_grace: Self::default_key_rotation_grace(),
+        }
+    }
+}
+
+impl GovernanceParams {
+    fn default_emergency_threshold() -> Fraction {
+        Fraction::new(2, 3)
+    }
+
+    fn default_key_rotation_grace() -> BlockHeight {
+        10
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.approval_threshold.validate("approval threshold")?;
+        self.veto_threshold.validate("veto threshold")?;
+        // Otherwise an agenda could be approved and vetoed at the same time.
+        let (a, b) = (
+            self.approval_threshold.numerator as u128,
+            self.approval_threshold.denominator as u128,
+        );
+        let (c, d) = (
+            self.veto_threshold.numerator as u128,
+            self.veto_threshold.denominator as u128,
+        );
+        // `a * d + c * b >= b * d`, without overflowing.
+        if a * d >= b * d - c * b {
+            return Err("approval threshold + veto threshold must be less than 1".to_string());
+        }
+        self.emergency_threshold.validate("emergency threshold")?;
+        let (e, f) = (
+            self.emergency_threshold.numerator as u128,
+            self.emergency_threshold.denominator as u128,
+        );
+        if e * b < a * f {
+            return Err(
+                "emergency threshold must not be lower than approval threshold".to_string(),
+            );
+        }
+        if self.voting_period_blocks == Some(0) || self.voting_period_ms == Some(0) {
+            return Err("voting period must be positive".to_string());
+        }
+        if self.key_rotation_grace == 0 {
+            return Err("key rotation grace must be positive".to_string());
+        }
+        Ok(())
+    }
+
+    /// Calculates the expiration of an agenda created on top of the given last finalized height,
+    /// at the given time.
+    pub fn agenda_expiration(
+        &self,
+        height: BlockHeight,
+        timestamp: Timestamp,
+    ) -> (Option<BlockHeight>, Option<Timestamp>) {
+        (
+            self.voting_period_blocks
+                .map(|x| height.saturating_add(x.saturating_sub(1))),
+            self.voting_period_ms.map(|x| timestamp.saturating_add(x)),
+        )
+    }
+}
+
+/// The parameters of the consensus (see `vetomint::ConsensusParams`).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ConsensusParams {
+    /// The timeout of the propose step in round 0.
+    pub timeout_propose_ms: u64,
+    /// The timeout of the prevote step in round 0.
+    pub timeout_prevote_ms: u64,
+    /// The timeout of the precommit step in round 0.
+    pub timeout_precommit_ms: u64,
+    /// How much the timeouts increase for each round, in percent.
+    pub timeout_increase_percent: u64,
+    /// The number of rounds that the first leader keeps proposing.
+    pub repeat_round_for_first_leader: u64,
+    /// Whether to raise the timeouts according to the observed latencies between the validators.
+    ///
+    /// The timeouts above are still the minimums.
+    pub adaptive_timeout: bool,
+    /// The policy to select the proposer of each round, chosen at genesis.
+    pub proposer_selection: ProposerSelection,
+    /// The maximum size of a vote extension in bytes (see `VoteExtension`).
+    pub max_vote_extension_size: u64,
+}
+
+/// See `vetomint::ProposerSelection`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum ProposerSelection {
+    RoundRobin,
+    /// Seeded from the hash of the previous block.
+    WeightedRoundRobin,
+    /// Seeded from the hash of the previous block.
+    StakeProportional,
+}
+
+impl Default for ConsensusParams {
+    fn default() -> Self {
+        Self {
+            timeout_propose_ms: 3000,
+            timeout_prevote_ms: 1000,
+            timeout_precommit_ms: 1000,
+            timeout_increase_percent: 50,
+            repeat_round_for_first_leader: 1,
+            adaptive_timeout: false,
+            proposer_selection: ProposerSelection::RoundRobin,
+            max_vote_extension_size: 1024,
+        }
+    }
+}
+
+impl ConsensusParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.timeout_propose_ms == 0
+            || self.timeout_prevote_ms == 0
+            || self.timeout_precommit_ms == 0
+        {
+            return Err("consensus timeouts must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A value of a chain parameter.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+pub enum ParameterValue {
+    Integer(u64),
+    Boolean(bool),
+    Text(String),
+}
+
+impl fmt::Display for ParameterValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(x) => write!(f, "{}", x),
+            Self::Boolean(x) => write!(f, "{}", x),
+            Self::Text(x) => write!(f, "{:?}", x),
+        }
+    }
+}
+
+/// The governance-managed parameters of the chain ("chain config").
+///
+/// Unlike the local configuration of a node, these are agreed by the members:
+/// they change only with a `ReservedStateChange::SetParameters` in an approved agenda,
+/// taking effect from the block which the agenda is committed in.
+/// A subsystem falls back to its own default for a parameter which is not set.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+#[serde(transparent)]
+pub struct ChainParameters(pub BTreeMap<String, ParameterValue>);
+
+/// A change of a chain parameter; `None` means that it is not set.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ParameterChange {
+    pub key: String,
+    pub old: Option<ParameterValue>,
+    pub new: Option<ParameterValue>,
+}
+
+impl ChainParameters {
+    /// The hint of the interval between the blocks, in milliseconds.
+    pub const BLOCK_INTERVAL_MS: &'static str = "block_interval_ms";
+    /// The maximum size of a commit in bytes.
+    pub const MAX_COMMIT_SIZE: &'static str = "max_commit_size";
+    /// The base timeout of the consensus steps, in milliseconds.
+    pub const CONSENSUS_TIMEOUT_MS: &'static str = "consensus_timeout_ms";
+    /// The number of blocks that the chat messages are kept for.
+    pub const CHAT_RETENTION_BLOCKS: &'static str = "chat_retention_blocks";
+    /// The number of blocks that the DMS messages are kept for.
+    pub const DMS_RETENTION_BLOCKS: &'static str = "dms_retention_blocks";
+    /// How far the timestamp of a block may be from the median of the validators' clocks,
+    /// in milliseconds (see `verify::verify_timestamp()`).
+    pub const MAX_CLOCK_SKEW_MS: &'static str = "max_clock_skew_ms";
+    /// The number of the members whose attestations accept an external event (see `TxAttest`);
+    /// if not set, no attestation is accepted.
+    pub const ATTESTATION_THRESHOLD: &'static str = "attestation_threshold";
+    /// The comma-separated names of the external chains that the members attest to.
+    pub const ATTESTATION_CHAINS: &'static str = "attestation_chains";
+    /// The maximum size of the files changed by a transaction, in bytes.
+    pub const MAX_TRANSACTION_DIFF_SIZE: &'static str = "max_transaction_diff_size";
+    /// The maximum number of the transactions in an agenda.
+    pub const MAX_AGENDA_TRANSACTIONS: &'static str = "max_agenda_transactions";
+    /// The maximum size of the files changed by the commits of a block, in bytes.
+    pub const MAX_BLOCK_BODY_SIZE: &'static str = "max_block_body_size";
+    /// The consensus voting power slashed from each offender of a reported evidence;
+    /// if not set, the offenders are expelled (see `report_penalty()`).
+    pub const REPORT_SLASH_AMOUNT: &'static str = "report_slash_amount";
+
+    pub const DEFAULT_MAX_CLOCK_SKEW_MS: u64 = 10_000;
+
+    /// The well-known parameters, which must be positive integers.
+    const POSITIVE_INTEGERS: [&'static str; 11] = [
+        Self::BLOCK_INTERVAL_MS,
+        Self::MAX_COMMIT_SIZE,
+        Self::CONSENSUS_TIMEOUT_MS,
+        Self::CHAT_RETENTION_BLOCKS,
+        Self::DMS_RETENTION_BLOCKS,
+        Self::MAX_CLOCK_SKEW_MS,
+        Self::ATTESTATION_THRESHOLD,
+        Self::MAX_TRANSACTION_DIFF_SIZE,
+        Self::MAX_AGENDA_TRANSACTIONS,
+        Self::MAX_BLOCK_BODY_SIZE,
+        Self::REPORT_SLASH_AMOUNT,
+    ];
+
+    pub fn get(&self, key: &str) -> Option<&ParameterValue> {
+        self.0.get(key)
+    }
+
+    /// Returns the penalty on the offenders of a reported evidence, which is up to the chain,
+    /// never to the reporter.
+    pub fn report_penalty(&self) -> Penalty {
+        match self.get_integer(Self::REPORT_SLASH_AMOUNT) {
+            Some(amount) => Penalty::Slash(amount),
+            None => Penalty::Expel,
+        }
+    }
+
+    pub fn get_integer(&self, key: &str) -> Option<u64> {
+        match self.0.get(key) {
+            Some(ParameterValue::Integer(x)) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn get_boolean(&self, key: &str) -> Option<bool> {
+        match self.0.get(key) {
+            Some(ParameterValue::Boolean(x)) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn get_text(&self, key: &str) -> Option<&str> {
+        match self.0.get(key) {
+            Some(ParameterValue::Text(x)) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn max_clock_skew_ms(&self) -> u64 {
+        self.get_integer(Self::MAX_CLOCK_SKEW_MS)
+            .unwrap_or(Self::DEFAULT_MAX_CLOCK_SKEW_MS)
+    }
+
+    pub fn attestation_chains(&self) -> Vec<&str> {
+        self.get_text(Self::ATTESTATION_CHAINS)
+            .map(|chains| chains.split(',').map(str::trim).collect())
+            .unwrap_or_default()
+    }
+
+    /// Checks a proposal against the limits of the sizes, given the diff sizes of
+    /// its transactions and the diff size of all of its commits since the last block.
+    ///
+    /// A limit which is not set is not enforced.
+    pub fn check_proposal(
+        &self,
+        transaction_diff_sizes: &[u64],
+        body_size: u64,
+    ) -> Result<(), String> {
+        if let Some(max) = self.get_integer(Self::MAX_TRANSACTION_DIFF_SIZE) {
+            if let Some((index, size)) = transaction_diff_sizes
+                .iter()
+                .enumerate()
+                .find(|(_, size)| **size > max)
+            {
+                return Err(format!(
+                    "transaction #{} changes {} bytes, exceeding {} of {}",
+                    index,
+                    size,
+                    Self::MAX_TRANSACTION_DIFF_SIZE,
+                    max
+                ));
+            }
+        }
+        if let Some(max) = self.get_integer(Self::MAX_AGENDA_TRANSACTIONS) {
+            if transaction_diff_sizes.len() as u64 > max {
+                return Err(format!(
+                    "{} transactions, exceeding {} of {}",
+                    transaction_diff_sizes.len(),
+                    Self::MAX_AGENDA_TRANSACTIONS,
+                    max
+                ));
+            }
+        }
+        if let Some(max) = self.get_integer(Self::MAX_BLOCK_BODY_SIZE) {
+            if body_size > max {
+                return Err(format!(
+                    "the body changes {} bytes, exceeding {} of {}",
+                    body_size,
+                    Self::MAX_BLOCK_BODY_SIZE,
+                    max
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (key, value) in &self.0 {
+            if key.is_empty()
+                || !key
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '.')
+            {
+                return Err(format!("invalid parameter key: {:?}", key));
+            }
+            if Self::POSITIVE_INTEGERS.contains(&key.as_str())
+                && !matches!(value, ParameterValue::Integer(x) if *x > 0)
+            {
+                return Err(format!("{} must be a positive integer: {}", key, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the changes from this to the given parameters, in the order of the keys.
+    pub fn diff(&self, next: &Self) -> Vec<ParameterChange> {
+        self.0
+            .keys()
+            .chain(next.0.keys())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter_map(|key| {
+                let (old, new) = (self.0.get(key), next.0.get(key));
+                (old != new).then(|| ParameterChange {
+                    key: key.clone(),
+                    old: old.cloned(),
+                    new: new.cloned(),
+                })
+            })
+            .collect()
+    }
+
+    fn set(&mut self, changes: &BTreeMap<String, Option<ParameterValue>>) -> Result<(), String> {
+        if changes.is_empty() {
+            return Err("no parameter to set".to_string());
+        }
+        for (key, value) in changes {
+            match value {
+                Some(value) => {
+                    self.0.insert(key.clone(), value.clone());
+                }
+                None => {
+                    self.0
+                        .remove(key)
+                        .ok_or(format!("parameter {} is not set", key))?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl ReservedState {
+    /// Returns the effective (delegation-applied) validator set, in the consensus leader order.
+    ///
+    /// A member who delegated its consensus voting power is not a validator; its power is added
+    /// to the delegatee, which must be in the leader order for the power to count.
     pub fn create_validator_set(&self) -> Result<Vec<(PublicKey, VotingPower)>, String> {
         let mut validator_set = Vec::new();
         for leader in &self.consensus_leader_order {
@@ -26,16 +590,1085 @@ impl ReservedState {
             consensus_leader_order: {}",
                 leader
             ))?;
-            validator_set.push((member.public_key.clone(), member.consensus_voting_power));
+            if member.consensus_delegations.is_some() {
+                continue;
+            }
+            let power = self.effective_voting_power(member, |m| {
+                (m.consensus_voting_power, &m.consensus_delegations)
+            });
+            if power > 0 {
+                validator_set.push((member.public_key.clone(), power));
+            }
         }
         Ok(validator_set)
     }
 
-    pub fn apply_delegate(&mut self, _tx: &TxDelegate) -> Result<Self, String> {
-        unimplemented!()
+    /// Returns the effective (delegation-applied) governance voting power of each member
+    /// who didn't delegate it.
+    pub fn governance_voting_powers(&self) -> Vec<(&Member, VotingPower)> {
+        self.members
+            .iter()
+            .filter(|member| member.governance_delegations.is_none())
+            .map(|member| {
+                (
+                    member,
+                    self.effective_voting_power(member, |m| {
+                        (m.governance_voting_power, &m.governance_delegations)
+                    }),
+                )
+            })
+            .collect()
     }
 
-    pub fn apply_undelegate(&mut self, _tx: &TxUndelegate) -> Result<Self, String> {
-        unimplemented!()
+    /// Sums up the effective governance voting power of the members approved by the given keys.
+    ///
+    /// Each key counts once: the key of a member counts only for the member itself,
+    /// never toward the threshold of a multisig member that it is also a signer of.
+    pub fn approved_governance_voting_power<'a>(
+        &self,
+        voters: impl IntoIterator<Item = &'a PublicKey>,
+    ) -> VotingPower {
+        let member_keys: BTreeSet<_> = self.members.iter().map(|m| &m.public_key).collect();
+        let voters: BTreeSet<_> = voters.into_iter().collect();
+        self.governance_voting_powers()
+            .into_iter()
+            .filter(|(member, _)| match &member.multisig_public_key {
+                Some(multisig) => multisig.is_satisfied_by(
+                    voters
+                        .iter()
+                        .copied()
+                        .filter(|voter| !member_keys.contains(voter)),
+                ),
+                None => voters.contains(&member.public_key),
+            })
+            .map(|(_, power)| power)
+            .sum()
+    }
+
+    /// The own power of the member plus those delegated to it.
+    ///
+    /// Delegations never chain (see `validate()`), so a single level is enough.
+    fn effective_voting_power(
+        &self,
+        member: &Member,
+        power_and_delegation: impl Fn(&Member) -> (VotingPower, &Option<PublicKey>),
+    ) -> VotingPower {
+        let delegated: VotingPower = self
+            .members
+            .iter()
+            .filter_map(|m| {
+                let (power, delegation) = power_and_delegation(m);
+                (delegation.as_ref() == Some(&member.public_key)).then(|| power)
+            })
+            .sum();
+        power_and_delegation(member).0 + delegated
+    }
+
+    /// Applies the change, returning the next state.
+    ///
+    /// This is the only way that a reserved state should be changed; every layer validating
+    /// a reserved-state diff must reproduce it with this.
+    /// `height` is the height of the block which the change is committed in.
+    pub fn apply(&self, change: &ReservedStateChange, height: BlockHeight) -> Result<Self, String> {
+        let mut state = self.clone();
+        match change {
+            ReservedStateChange::AddMember(member) => state.add_member(member)?,
+            ReservedStateChange::RemoveMember(name) => state.remove_member(name)?,
+            ReservedStateChange::Delegate(tx) => state.delegate(tx, height)?,
+            ReservedStateChange::Undelegate(tx) => state.undelegate(tx, height)?,
+            ReservedStateChange::RotateKey(tx) => state.rotate_key(tx, height)?,
+            ReservedStateChange::BumpVersion(version) => state.bump_version(version)?,
+            ReservedStateChange::Penalize(penalization) => state.penalize(penalization)?,
+            ReservedStateChange::SetBootstrapPeers(peers) => state.bootstrap_peers = peers.clone(),
+            ReservedStateChange::EmergencyExpel(tx) => state.expel(tx, height)?,
+            ReservedStateChange::SetParameters(changes) => {
+                state.parameters.set(changes)?;
+                // Checked only when set, since the members may decrease below it afterwards
+                // (see `attest()`).
+                if let Some(threshold) = state
+                    .parameters
+                    .get_integer(ChainParameters::ATTESTATION_THRESHOLD)
+                {
+                    if threshold > state.members.len() as u64 {
+                        return Err(format!(
+                            "the attestation threshold {} exceeds the number of the members",
+                            threshold
+                        ));
+                    }
+                }
+            }
+            ReservedStateChange::Attest(tx) => state.attest(tx, height)?,
+        }
+        state.validate()?;
+        Ok(state)
+    }
+
+    /// Decodes and validates a reserved state from untrusted JSON.
+    pub fn from_json(data: &[u8]) -> Result<Self, String> {
+        let state: Self =
+            serde_json::from_slice(data).map_err(|e| format!("invalid reserved state: {}", e))?;
+        state.validate()?;
+        Ok(state)
+    }
+
+    /// Checks the invariants of the reserved state.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.members.is_empty() {
+            return Err("there is no member".to_string());
+        }
+        // So that no sum of the voting powers overflows (e.g., `effective_voting_power()`).
+        for (name, power) in [
+            (
+                "governance",
+                (|member: &Member| member.governance_voting_power) as fn(&Member) -> VotingPower,
+            ),
+            ("consensus", |member: &Member| member.consensus_voting_power),
+        ] {
+            self.members
+                .iter()
+                .try_fold(0 as VotingPower, |total, member| {
+                    total.checked_add(power(member))
+                })
+                .ok_or(format!("the total {} voting power overflows", name))?;
+        }
+        let mut names = BTreeSet::new();
+        let mut keys = BTreeSet::new();
+        for member in &self.members {
+            if !names.insert(&member.name) {
+                return Err(format!("duplicate member name: {}", member.name));
+            }
+            for key in std::iter::once(&member.public_key)
+                .chain(member.previous_public_keys.iter().map(|(key, _)| key))
+            {
+                if !keys.insert(key) {
+                    return Err(format!("duplicate key: {}", key));
+                }
+            }
+            if let Some(multisig) = &member.multisig_public_key {
+                if multisig.threshold == 0 || multisig.threshold > multisig.signers.len() {
+                    return Err(format!("invalid multisig threshold of {}", member.name));
+                }
+            }
+            for delegatee in [
+                &member.governance_delegations,
+                &member.consensus_delegations,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                let delegatee = self
+                    .members
+                    .iter()
+                    .find(|m| &m.public_key == delegatee)
+                    .ok_or(format!("{} delegates to a non-member", member.name))?;
+                if delegatee.name == member.name {
+                    return Err(format!("{} delegates to itself", member.name));
+                }
+                if delegatee.governance_delegations.is_some()
+                    || delegatee.consensus_delegations.is_some()
+                {
+                    return Err(format!(
+                        "{} delegates to {}, who delegates too",
+                        member.name, delegatee.name
+                    ));
+                }
+            }
+        }
+        let mut leaders = BTreeSet::new();
+        for leader in &self.consensus_leader_order {
+            if *leader >= self.members.len() || !leaders.insert(leader) {
+                return Err(format!("invalid consensus_leader_order: {}", leader));
+            }
+        }
+        semver::Version::parse(&self.version)
+            .map_err(|e| format!("invalid version {}: {}", self.version, e))?;
+        self.governance_params.validate()?;
+        self.consensus_params.validate()?;
+        self.parameters.validate()?;
+        let mut bootstrap_peers = BTreeSet::new();
+        for peer in &self.bootstrap_peers {
+            let valid = match peer.rsplit_once(':') {
+                Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+                None => false,
+            };
+            if !valid || !bootstrap_peers.insert(peer) {
+                return Err(format!("invalid bootstrap peer: {}", peer));
+            }
+        }
+        Ok(())
+    }
+
+    fn add_member(&mut self, member: &Member) -> Result<(), String> {
+        if !member.previous_public_keys.is_empty()
+            || member.governance_delegations.is_some()
+            || member.consensus_delegations.is_some()
+        {
+            return Err(format!(
+                "new member {} must not have previous keys or delegations",
+                member.name
+            ));
+        }
+        // Duplicate names and keys are caught by `validate()`.
+        self.members.push(member.clone());
+        Ok(())
+    }
+
+    fn remove_member(&mut self, name: &str) -> Result<(), String> {
+        let index = self
+            .members
+            .iter()
+            .position(|member| member.name == name)
+            .ok_or(format!("no member named {}", name))?;
+        let removed = self.members.remove(index);
+        self.consensus_leader_order = self
+            .consensus_leader_order
+            .iter()
+            .filter(|leader| **leader != index)
+            .map(|leader| if *leader > index { leader - 1 } else { *leader })
+            .collect();
+        // The delegations to the removed member are revoked.
+        for member in self.members.iter_mut() {
+            for delegation in [
+                &mut member.governance_delegations,
+                &mut member.consensus_delegations,
+            ] {
+                if delegation.as_ref() == Some(&removed.public_key) {
+                    *delegation = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the member if the approvals exceed the emergency threshold.
+    ///
+    /// The voting powers are counted after the removal, so that the delegations
+    /// to the expelled member count for the delegators themselves.
+    fn expel(&mut self, tx: &TxEmergencyExpel, height: BlockHeight) -> Result<(), String> {
+        let expelled = self
+            .members
+            .iter()
+            .find(|member| member.name == tx.name)
+            .ok_or(format!("no member named {}", tx.name))?
+            .public_key
+            .clone();
+        self.remove_member(&tx.name)?;
+        let data = (tx.name.clone(), tx.reason.clone(), height);
+        let mut approvers = BTreeSet::new();
+        for approval in &tx.approvals {
+            if approval.signer() == &expelled {
+                continue;
+            }
+            approval
+                .verify(&data)
+                .map_err(|e| format!("invalid approval of {}: {}", approval.signer(), e))?;
+            approvers.insert(approval.signer().clone());
+        }
+        let powers = self.governance_voting_powers();
+        let total: VotingPower = powers.iter().map(|(_, power)| power).sum();
+        let approving: VotingPower = powers
+            .iter()
+            .filter(|(member, _)| approvers.contains(&member.public_key))
+            .map(|(_, power)| power)
+            .sum();
+        if !self
+            .governance_params
+            .emergency_threshold
+            .is_exceeded_by(approving, total)
+        {
+            return Err(format!(
+                "the approvals ({} of {}) don't exceed the emergency threshold",
+                approving, total
+            ));
+        }
+        Ok(())
+    }
+
+    /// Records the attestation of the member, accepting the event once attested by
+    /// `ChainParameters::ATTESTATION_THRESHOLD` of the current members,
+    /// or by all of them if there are fewer.
+    ///
+    /// The other pending events of the same identifier, which conflict with the accepted one,
+    /// are dropped, as are the pending events attested only by those removed from the members.
+    fn attest(&mut self, tx: &TxAttest, height: BlockHeight) -> Result<(), String> {
+        let threshold = self
+            .parameters
+            .get_integer(ChainParameters::ATTESTATION_THRESHOLD)
+            .ok_or("the attestations are not enabled")?
+            .min(self.members.len() as u64);
+        let event = &tx.event;
+        if !self
+            .parameters
+            .attestation_chains()
+            .contains(&event.chain.as_str())
+        {
+            return Err(format!("chain {} is not attested to", event.chain));
+        }
+        if self
+            .external_events
+            .find_accepted(&event.chain, &event.id)
+            .is_some()
+        {
+            return Err(format!(
+                "event {} of {} is accepted already",
+                event.id, event.chain
+            ));
+        }
+        let attester = self
+            .find_member_by_key(tx.proof.signer(), height)
+            .ok_or(format!(
+                "the attester {} is not a member",
+                tx.proof.signer()
+            ))?
+            .name
+            .clone();
+        tx.proof
+            .verify(event)
+            .map_err(|e| format!("invalid attestation: {}", e))?;
+        let members = &self.members;
+        let is_member = |name: &MemberName| members.iter().any(|member| &member.name == name);
+        let pending = &mut self.external_events.pending;
+        pending.retain(|(_, attesters)| attesters.iter().any(is_member));
+        let index = match pending.iter().position(|(e, _)| e == event) {
+            Some(index) => index,
+            None => {
+                let attested = pending
+                    .iter()
+                    .filter(|(_, attesters)| attesters.contains(&attester))
+                    .count();
+                if attested >= ExternalEvents::MAX_PENDING_ATTESTATIONS_PER_MEMBER {
+                    return Err(format!(
+                        "{} has attested to {} pending events already",
+                        attester, attested
+                    ));
+                }
+                pending.push((event.clone(), Vec::new()));
+                pending.len() - 1
+            }
+        };
+        let attesters = &mut pending[index].1;
+        if attesters.contains(&attester) {
+            return Err(format!("{} attested to the event already", attester));
+        }
+        attesters.push(attester);
+        // Those removed from the members since they attested don't count.
+        let count = attesters.iter().filter(|name| is_member(name)).count();
+        if count as u64 >= threshold {
+            let (event, _) = pending.remove(index);
+            pending.retain(|(e, _)| e.chain != event.chain || e.id != event.id);
+            self.external_events.accepted.push((event, height));
+        }
+        Ok(())
+    }
+
+    fn penalize(&mut self, penalization: &Penalization) -> Result<(), String> {
+        if penalization.offenders.is_empty() {
+            return Err("the evidence has no offender".to_string());
+        }
+        if !self.processed_evidences.insert(penalization.evidence_hash) {
+            return Err(format!(
+                "the evidence {} has already been processed",
+                penalization.evidence_hash
+            ));
+        }
+        let penalty = self.parameters.report_penalty();
+        for offender in &penalization.offenders {
+            let name = self
+                .find_member_by_key(offender, penalization.height)
+                .ok_or(format!("no member with key {}", offender))?
+                .name
+                .clone();
+            match penalty {
+                Penalty::Slash(amount) => {
+                    let member = self
+                        .members
+                        .iter_mut()
+                        .find(|member| member.name == name)
+                        .expect("member must exist");
+                    member.consensus_voting_power =
+                        member.consensus_voting_power.saturating_sub(amount);
+                }
+                Penalty::Expel => self.remove_member(&name)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn find_member_mut(&mut self, key: &PublicKey) -> Result<&mut Member, String> {
+        self.members
+            .iter_mut()
+            .find(|member| &member.public_key == key)
+            .ok_or(format!("no member has key {}", key))
+    }
+
+    fn delegate(&mut self, tx: &TxDelegate, height: BlockHeight) -> Result<(), String> {
+        if tx.proof.signer() != &tx.delegator {
+            return Err("the proof is not signed by the delegator".to_string());
+        }
+        tx.proof
+            .verify(&(
+                tx.delegator.clone(),
+                tx.delegatee.clone(),
+                tx.governance,
+                height,
+            ))
+            .map_err(|e| format!("invalid proof: {}", e))?;
+        let delegator = self.find_member_mut(&tx.delegator)?;
+        delegator.consensus_delegations = Some(tx.delegatee.clone());
+        if tx.governance {
+            delegator.governance_delegations = Some(tx.delegatee.clone());
+        }
+        Ok(())
+    }
+
+    fn undelegate(&mut self, tx: &TxUndelegate, height: BlockHeight) -> Result<(), String> {
+        if tx.proof.signer() != &tx.delegator {
+            return Err("the proof is not signed by the delegator".to_string());
+        }
+        tx.proof
+            .verify(&(tx.delegator.clone(), height))
+            .map_err(|e| format!("invalid proof: {}", e))?;
+        let delegator = self.find_member_mut(&tx.delegator)?;
+        if delegator.consensus_delegations.is_none() && delegator.governance_delegations.is_none() {
+            return Err("nothing is delegated".to_string());
+        }
+        delegator.consensus_delegations = None;
+        delegator.governance_delegations = None;
+        Ok(())
+    }
+
+    /// Replaces the key of the member, keeping the old one as a previous key
+    /// until the rotation height, which is at most `GovernanceParams::key_rotation_grace`
+    /// blocks away.
+    fn rotate_key(&mut self, tx: &TxRotateKey, height: BlockHeight) -> Result<(), String> {
+        let data = (
+            self.genesis_info.header.to_hash256(),
+            tx.old_key.clone(),
+            tx.new_key.clone(),
+            tx.rotation_height,
+        );
+        if tx.proof.signer() != &tx.old_key || tx.new_key_proof.signer() != &tx.new_key {
+            return Err("the proofs are not signed by the rotated keys".to_string());
+        }
+        let grace = self.governance_params.key_rotation_grace;
+        if tx.rotation_height <= height || tx.rotation_height - height > grace {
+            return Err(format!(
+                "rotation height {} must be within {} blocks after the current height {}",
+                tx.rotation_height, grace, height
+            ));
+        }
+        tx.proof
+            .verify(&data)
+            .map_err(|e| format!("invalid proof: {}", e))?;
+        tx.new_key_proof
+            .verify(&data)
+            .map_err(|e| format!("invalid new key proof: {}", e))?;
+        // A key must never be reused, even after rotated out; `validate()` checks it.
+        let member = self.find_member_mut(&tx.old_key)?;
+        member
+            .previous_public_keys
+            .push((tx.old_key.clone(), tx.rotation_height));
+        member.public_key = tx.new_key.clone();
+        for member in self.members.iter_mut() {
+            for delegation in [
+                &mut member.governance_delegations,
+                &mut member.consensus_delegations,
+            ] {
+                if delegation.as_ref() == Some(&tx.old_key) {
+                    *delegation = Some(tx.new_key.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn bump_version(&mut self, version: &str) -> Result<(), String> {
+        let current = semver::Version::parse(&self.version)
+            .map_err(|e| format!("invalid version {}: {}", self.version, e))?;
+        let next = semver::Version::parse(version)
+            .map_err(|e| format!("invalid version {}: {}", version, e))?;
+        if next <= current {
+            return Err(format!(
+                "version must increase: {} -> {}",
+                self.version, version
+            ));
+        }
+        self.version = version.to_string();
+        Ok(())
+    }
+
+    /// Finds the member whose signature by the given key is valid for a commit at the given height.
+    ///
+    /// A previous key of a member is valid only below its rotation height.
+    pub fn find_member_by_key(&self, key: &PublicKey, height: BlockHeight) -> Option<&Member> {
+        self.members.iter().find(|member| {
+            member.public_key == *key
+                || member
+                    .previous_public_keys
+                    .iter()
+                    .any(|(previous, rotation_height)| previous == key && height < *rotation_height)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::reserved_state as state;
+
+    fn member(name: &str) -> (Member, PrivateKey) {
+        (crate::test_util::member(name), generate_keypair(name).1)
+    }
+
+    #[test]
+    fn add_and_remove_member() {
+        let state = state(vec![member("a").0, member("b").0, member("c").0]);
+        let state = state
+            .apply(&ReservedStateChange::RemoveMember("b".to_string()), 1)
+            .unwrap();
+        assert_eq!(state.consensus_leader_order, vec![0, 1]);
+        assert_eq!(state.members[1].name, "c");
+
+        state
+            .apply(&ReservedStateChange::AddMember(member("a").0), 1)
+            .unwrap_err();
+        let state = state
+            .apply(&ReservedStateChange::AddMember(member("d").0), 1)
+            .unwrap();
+        assert_eq!(state.members.len(), 3);
+        // Not a validator until it's in the leader order.
+        assert_eq!(state.create_validator_set().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn remove_member_transaction() {
+        let members: Vec<_> = ["a", "b", "c", "d"].iter().map(|x| member(x)).collect();
+        let state = state(members.iter().map(|(m, _)| m.clone()).collect());
+        let tx = |signers: &[usize], height| {
+            ExtraAgendaTransaction::RemoveMember(TxRemoveMember {
+                name: "b".to_string(),
+                approvals: signers
+                    .iter()
+                    .map(|i| {
+                        TypedSignature::sign(&("b".to_string(), height), &members[*i].1).unwrap()
+                    })
+                    .collect(),
+            })
+        };
+        let change = ReservedStateChange::from_extra_agenda_transaction(
+            &tx(&[0, 2, 3], 2),
+            &state,
+            2,
+            |_| None,
+        )
+        .unwrap();
+        assert_eq!(change, ReservedStateChange::RemoveMember("b".to_string()));
+        // A single member can't remove another.
+        ReservedStateChange::from_extra_agenda_transaction(&tx(&[0], 2), &state, 2, |_| None)
+            .unwrap_err();
+        // 2 of 4 don't exceed 2/3.
+        ReservedStateChange::from_extra_agenda_transaction(&tx(&[0, 2], 2), &state, 2, |_| None)
+            .unwrap_err();
+        // The approvals are bound to the height.
+        ReservedStateChange::from_extra_agenda_transaction(&tx(&[0, 2, 3], 2), &state, 3, |_| None)
+            .unwrap_err();
+        // Approvals of non-members don't count.
+        let (_, e_key) = member("e");
+        let mut with_outsider = tx(&[0, 2], 2);
+        if let ExtraAgendaTransaction::RemoveMember(tx) = &mut with_outsider {
+            tx.approvals
+                .push(TypedSignature::sign(&("b".to_string(), 2), &e_key).unwrap());
+        }
+        ReservedStateChange::from_extra_agenda_transaction(&with_outsider, &state, 2, |_| None)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn emergency_expel() {
+        let members: Vec<_> = ["a", "b", "c", "d"].iter().map(|x| member(x)).collect();
+        let mut state = state(members.iter().map(|(m, _)| m.clone()).collect());
+        // `a` delegates to `d`, the one to be expelled.
+        state.members[0].governance_delegations = Some(members[3].0.public_key.clone());
+        let tx = |signers: &[usize]| {
+            ReservedStateChange::EmergencyExpel(TxEmergencyExpel {
+                name: "d".to_string(),
+                reason: "compromised key".to_string(),
+                approvals: signers
+                    .iter()
+                    .map(|i| {
+                        TypedSignature::sign(
+                            &("d".to_string(), "compromised key".to_string(), 5),
+                            &members[*i].1,
+                        )
+                        .unwrap()
+                    })
+                    .collect(),
+            })
+        };
+        // 2 of the 3 remaining powers don't exceed 2/3; the own approval of `d` doesn't count.
+        state.apply(&tx(&[0, 1, 3]), 5).unwrap_err();
+        // `a` counts for itself, since its delegation to `d` is revoked.
+        let expelled = state.apply(&tx(&[0, 1, 2]), 5).unwrap();
+        assert_eq!(expelled.members.len(), 3);
+        assert_eq!(expelled.members[0].governance_delegations, None);
+        assert_eq!(expelled.consensus_leader_order, vec![0, 1, 2]);
+        // The approvals are bound to the height.
+        state.apply(&tx(&[0, 1, 2]), 6).unwrap_err();
+    }
+
+    #[test]
+    fn delegate_and_undelegate() {
+        let (a, a_key) = member("a");
+        let (b, _) = member("b");
+        let state = state(vec![a.clone(), b.clone()]);
+        let proof = TypedSignature::sign(
+            &(a.public_key.clone(), b.public_key.clone(), true, 3),
+            &a_key,
+        )
+        .unwrap();
+        let tx = TxDelegate {
+            delegator: a.public_key.clone(),
+            delegatee: b.public_key.clone(),
+            governance: true,
+            proof,
+        };
+        // The proof is bound to the height.
+        state
+            .apply(&ReservedStateChange::Delegate(tx.clone()), 4)
+            .unwrap_err();
+        let state = state.apply(&ReservedStateChange::Delegate(tx), 3).unwrap();
+        assert_eq!(state.members[0].consensus_delegations, Some(b.public_key));
+
+        let tx = TxUndelegate {
+            delegator: a.public_key.clone(),
+            proof: TypedSignature::sign(&(a.public_key.clone(), 5), &a_key).unwrap(),
+        };
+        let state = state
+            .apply(&ReservedStateChange::Undelegate(tx), 5)
+            .unwrap();
+        assert_eq!(state.members[0].consensus_delegations, None);
+        assert_eq!(state.members[0].governance_delegations, None);
+    }
+
+    #[test]
+    fn rotate_key() {
+        let (a, a_key) = member("a");
+        let (b, b_key) = member("b");
+        let state = state(vec![a.clone(), member("c").0]);
+        let tx = |genesis_hash: Hash256, rotation_height| {
+            let data = (
+                genesis_hash,
+                a.public_key.clone(),
+                b.public_key.clone(),
+                rotation_height,
+            );
+            ReservedStateChange::RotateKey(TxRotateKey {
+                old_key: a.public_key.clone(),
+                new_key: b.public_key.clone(),
+                rotation_height,
+                proof: TypedSignature::sign(&data, &a_key).unwrap(),
+                new_key_proof: TypedSignature::sign(&data, &b_key).unwrap(),
+            })
+        };
+        let genesis_hash = state.genesis_info.header.to_hash256();
+        let rotated = state.apply(&tx(genesis_hash, 5), 3).unwrap();
+        assert_eq!(rotated.members[0].public_key, b.public_key);
+        assert_eq!(
+            rotated.find_member_by_key(&a.public_key, 4).unwrap().name,
+            "a"
+        );
+        assert!(rotated.find_member_by_key(&a.public_key, 5).is_none());
+        // The old key can't stay valid beyond the grace period.
+        let grace = state.governance_params.key_rotation_grace;
+        state.apply(&tx(genesis_hash, 3 + grace), 3).unwrap();
+        state.apply(&tx(genesis_hash, 4 + grace), 3).unwrap_err();
+        state.apply(&tx(genesis_hash, 3), 3).unwrap_err();
+        // Signed for another chain.
+        state.apply(&tx(Hash256::hash("other"), 5), 3).unwrap_err();
+    }
+
+    #[test]
+    fn effective_voting_power() {
+        let (mut a, _) = member("a");
+        let (b, _) = member("b");
+        let (mut c, _) = member("c");
+        a.consensus_delegations = Some(b.public_key.clone());
+        c.consensus_voting_power = 5;
+        c.governance_delegations = Some(b.public_key.clone());
+        let state = state(vec![a, b.clone(), c.clone()]);
+        state.validate().unwrap();
+        assert_eq!(
+            state.create_validator_set().unwrap(),
+            vec![(b.public_key.clone(), 2), (c.public_key, 5)]
+        );
+        let governance = state
+            .governance_voting_powers()
+            .into_iter()
+            .map(|(member, power)| (member.name.clone(), power))
+            .collect::<Vec<_>>();
+        assert_eq!(governance, vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+    }
+
+    #[test]
+    fn bump_version() {
+        let state = state(vec![member("a").0]);
+        state
+            .apply(&ReservedStateChange::BumpVersion("0.0.9".to_string()), 1)
+            .unwrap_err();
+        let state = state
+            .apply(&ReservedStateChange::BumpVersion("0.2.0".to_string()), 1)
+            .unwrap();
+        assert_eq!(state.version, "0.2.0");
+    }
+
+    #[test]
+    fn set_bootstrap_peers() {
+        let state = state(vec![member("a").0]);
+        let peers = vec![
+            "127.0.0.1:7000".to_string(),
+            "node.example.com:7000".to_string(),
+        ];
+        let state = state
+            .apply(&ReservedStateChange::SetBootstrapPeers(peers.clone()), 1)
+            .unwrap();
+        assert_eq!(state.bootstrap_peers, peers);
+        for invalid in ["127.0.0.1", ":7000", "127.0.0.1:port"] {
+            state
+                .apply(
+                    &ReservedStateChange::SetBootstrapPeers(vec![invalid.to_string()]),
+                    1,
+                )
+                .unwrap_err();
+        }
+    }
+
+    #[test]
+    fn set_parameters() {
+        let state = state(vec![member("a").0]);
+        let change = ReservedStateChange::SetParameters(BTreeMap::from([
+            (
+                ChainParameters::MAX_COMMIT_SIZE.to_string(),
+                Some(ParameterValue::Integer(1 << 20)),
+            ),
+            (
+                "indexer.enabled".to_string(),
+                Some(ParameterValue::Boolean(true)),
+            ),
+        ]));
+        let next = state.apply(&change, 1).unwrap();
+        assert_eq!(
+            next.parameters
+                .get_integer(ChainParameters::MAX_COMMIT_SIZE),
+            Some(1 << 20)
+        );
+        assert_eq!(next.parameters.get_boolean("indexer.enabled"), Some(true));
+        assert_eq!(next.parameters.get_text("indexer.enabled"), None);
+
+        let change = ReservedStateChange::SetParameters(BTreeMap::from([
+            ("indexer.enabled".to_string(), None),
+            (
+                ChainParameters::MAX_COMMIT_SIZE.to_string(),
+                Some(ParameterValue::Integer(1 << 10)),
+            ),
+        ]));
+        let last = next.apply(&change, 2).unwrap();
+        assert_eq!(
+            next.parameters.diff(&last.parameters),
+            vec![
+                ParameterChange {
+                    key: "indexer.enabled".to_string(),
+                    old: Some(ParameterValue::Boolean(true)),
+                    new: None,
+                },
+                ParameterChange {
+                    key: ChainParameters::MAX_COMMIT_SIZE.to_string(),
+                    old: Some(ParameterValue::Integer(1 << 20)),
+                    new: Some(ParameterValue::Integer(1 << 10)),
+                },
+            ]
+        );
+        assert!(last.parameters.diff(&last.parameters).is_empty());
+
+        for (key, value) in [
+            (
+                ChainParameters::BLOCK_INTERVAL_MS,
+                Some(ParameterValue::Integer(0)),
+            ),
+            (
+                ChainParameters::BLOCK_INTERVAL_MS,
+                Some(ParameterValue::Boolean(true)),
+            ),
+            ("Invalid Key", Some(ParameterValue::Integer(1))),
+            ("unset", None),
+        ] {
+            let change =
+                ReservedStateChange::SetParameters(BTreeMap::from([(key.to_string(), value)]));
+            state.apply(&change, 1).unwrap_err();
+        }
+        state
+            .apply(&ReservedStateChange::SetParameters(BTreeMap::new()), 1)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn proposal_limits() {
+        let unlimited = ChainParameters::default();
+        unlimited
+            .check_proposal(&[u64::MAX; 100], u64::MAX)
+            .unwrap();
+
+        let limits = ChainParameters(BTreeMap::from([
+            (
+                ChainParameters::MAX_TRANSACTION_DIFF_SIZE.to_string(),
+                ParameterValue::Integer(100),
+            ),
+            (
+                ChainParameters::MAX_AGENDA_TRANSACTIONS.to_string(),
+                ParameterValue::Integer(2),
+            ),
+            (
+                ChainParameters::MAX_BLOCK_BODY_SIZE.to_string(),
+                ParameterValue::Integer(250),
+            ),
+        ]));
+        limits.validate().unwrap();
+        limits.check_proposal(&[100, 100], 250).unwrap();
+        limits.check_proposal(&[], 0).unwrap();
+        limits.check_proposal(&[100, 101], 201).unwrap_err();
+        limits.check_proposal(&[10, 10, 10], 30).unwrap_err();
+        // The body includes the commits other than the transactions (e.g., the chat log).
+        limits.check_proposal(&[100, 100], 251).unwrap_err();
+    }
+
+    #[test]
+    fn attest() {
+        let members: Vec<_> = ["a", "b", "c"].iter().map(|x| member(x)).collect();
+        let mut state = state(members.iter().map(|(m, _)| m.clone()).collect());
+        let event = |data: &str| ExternalEvent {
+            chain: "ethereum".to_string(),
+            id: "0x1234:0".to_string(),
+            block_height: 100,
+            data: data.to_string(),
+        };
+        let attest = |event: &ExternalEvent, i: usize| {
+            ReservedStateChange::Attest(TxAttest {
+                event: event.clone(),
+                proof: TypedSignature::sign(event, &members[i].1).unwrap(),
+            })
+        };
+        let deposit = event("deposit 100");
+        // Not enabled yet.
+        state.apply(&attest(&deposit, 0), 1).unwrap_err();
+        state.parameters.0.insert(
+            ChainParameters::ATTESTATION_THRESHOLD.to_string(),
+            ParameterValue::Integer(2),
+        );
+        state.parameters.0.insert(
+            ChainParameters::ATTESTATION_CHAINS.to_string(),
+            ParameterValue::Text("cosmos, ethereum".to_string()),
+        );
+
+        let state = state.apply(&attest(&deposit, 0), 1).unwrap();
+        // A conflicting observation by another member.
+        let state = state.apply(&attest(&event("deposit 999"), 1), 1).unwrap();
+        assert_eq!(state.external_events.pending.len(), 2);
+        assert!(state.external_events.accepted.is_empty());
+        // The same member can't attest twice.
+        state.apply(&attest(&deposit, 0), 2).unwrap_err();
+        // Nor a non-member.
+        let (_, stranger) = member("d");
+        state
+            .apply(
+                &ReservedStateChange::Attest(TxAttest {
+                    event: deposit.clone(),
+                    proof: TypedSignature::sign(&deposit, &stranger).unwrap(),
+                }),
+                2,
+            )
+            .unwrap_err();
+
+        let state = state.apply(&attest(&deposit, 2), 2).unwrap();
+        assert_eq!(
+            state.external_events.find_accepted("ethereum", "0x1234:0"),
+            Some(&deposit)
+        );
+        assert_eq!(state.external_events.accepted[0].1, 2);
+        assert!(state.external_events.pending.is_empty());
+        state.apply(&attest(&deposit, 1), 3).unwrap_err();
+        state
+            .apply(&attest(&event("deposit 999"), 2), 3)
+            .unwrap_err();
+
+        // The threshold can't exceed the number of the members.
+        let change = ReservedStateChange::SetParameters(BTreeMap::from([(
+            ChainParameters::ATTESTATION_THRESHOLD.to_string(),
+            Some(ParameterValue::Integer(4)),
+        )]));
+        state.apply(&change, 3).unwrap_err();
+
+        // The threshold of all the members doesn't keep a member from being removed,
+        // and then all the remaining members accept an event.
+        let change = ReservedStateChange::SetParameters(BTreeMap::from([(
+            ChainParameters::ATTESTATION_THRESHOLD.to_string(),
+            Some(ParameterValue::Integer(3)),
+        )]));
+        let state = state.apply(&change, 3).unwrap();
+        let withdrawal = ExternalEvent {
+            id: "0x5678:0".to_string(),
+            ..event("withdrawal 10")
+        };
+        let state = state.apply(&attest(&withdrawal, 2), 3).unwrap();
+        let state = state
+            .apply(&ReservedStateChange::RemoveMember("c".to_string()), 4)
+            .unwrap();
+        let state = state.apply(&attest(&withdrawal, 0), 4).unwrap();
+        // The attestation of the removed member has been dropped with its event.
+        assert_eq!(
+            state.external_events.pending,
+            vec![(withdrawal.clone(), vec!["a".to_string()])]
+        );
+        let state = state.apply(&attest(&withdrawal, 1), 4).unwrap();
+        assert_eq!(
+            state.external_events.find_accepted("ethereum", "0x5678:0"),
+            Some(&withdrawal)
+        );
+    }
+
+    #[test]
+    fn pending_attestations() {
+        let members: Vec<_> = ["a", "b"].iter().map(|x| member(x)).collect();
+        let mut state = state(members.iter().map(|(m, _)| m.clone()).collect());
+        state.parameters.0.insert(
+            ChainParameters::ATTESTATION_THRESHOLD.to_string(),
+            ParameterValue::Integer(2),
+        );
+        state.parameters.0.insert(
+            ChainParameters::ATTESTATION_CHAINS.to_string(),
+            ParameterValue::Text("ethereum".to_string()),
+        );
+        let attest = |id: usize, i: usize| {
+            let event = ExternalEvent {
+                chain: "ethereum".to_string(),
+                id: format!("0x{:x}:0", id),
+                block_height: 100,
+                data: "deposit 1".to_string(),
+            };
+            ReservedStateChange::Attest(TxAttest {
+                proof: TypedSignature::sign(&event, &members[i].1).unwrap(),
+                event,
+            })
+        };
+        for id in 0..ExternalEvents::MAX_PENDING_ATTESTATIONS_PER_MEMBER {
+            state = state.apply(&attest(id, 0), 1).unwrap();
+        }
+        let full = ExternalEvents::MAX_PENDING_ATTESTATIONS_PER_MEMBER;
+        state.apply(&attest(full, 0), 1).unwrap_err();
+        // Others can still attest, and an accepted event frees the slot.
+        let state = state.apply(&attest(full, 1), 1).unwrap();
+        let state = state.apply(&attest(0, 1), 1).unwrap();
+        state.apply(&attest(full, 0), 1).unwrap();
+    }
+
+    #[test]
+    fn report() {
+        let (a, a_key) = member("a");
+        let mut state = state(vec![a, member("b").0]);
+        state.members[0].consensus_voting_power = 10;
+        let validator_set = state.create_validator_set().unwrap();
+        let sign = |block_hash| {
+            let payload = ConsensusPayload {
+                height: 1,
+                round: 0,
+                kind: ConsensusMessageKind::PreCommit,
+                block_hash,
+            };
+            let signature = TypedSignature::sign(&payload, &a_key).unwrap();
+            SignedConsensusPayload { payload, signature }
+        };
+        let report = |evidence: Evidence, state: &ReservedState| {
+            ReservedStateChange::from_extra_agenda_transaction(
+                &ExtraAgendaTransaction::Report(TxReport { evidence }),
+                state,
+                2,
+                |height| (height == 1).then(|| validator_set.clone()),
+            )
+        };
+        let evidence = Evidence::DoubleVote(sign(None), sign(Some(Hash256::hash("block"))));
+
+        // Expelled by default.
+        let expelled = state
+            .apply(&report(evidence.clone(), &state).unwrap(), 2)
+            .unwrap();
+        assert_eq!(expelled.members.len(), 1);
+        assert_eq!(expelled.members[0].name, "b");
+
+        state.parameters.0.insert(
+            ChainParameters::REPORT_SLASH_AMOUNT.to_string(),
+            ParameterValue::Integer(3),
+        );
+        let slashed = state
+            .apply(&report(evidence.clone(), &state).unwrap(), 2)
+            .unwrap();
+        assert_eq!(slashed.members[0].consensus_voting_power, 7);
+        // Never twice, even in the other order.
+        let swapped = match &evidence {
+            Evidence::DoubleVote(x, y) => Evidence::DoubleVote(y.clone(), x.clone()),
+            _ => unreachable!(),
+        };
+        slashed
+            .apply(&report(swapped, &slashed).unwrap(), 2)
+            .unwrap_err();
+
+        let invalid = Evidence::DoubleVote(sign(None), sign(None));
+        report(invalid, &state).unwrap_err();
+        // The validator set of the evidence height is unknown.
+        let mut other_height = sign(None);
+        other_height.payload.height = 5;
+        other_height.signature = TypedSignature::sign(&other_height.payload, &a_key).unwrap();
+        let mut other_vote = sign(Some(Hash256::hash("block")));
+        other_vote.payload.height = 5;
+        other_vote.signature = TypedSignature::sign(&other_vote.payload, &a_key).unwrap();
+        report(Evidence::DoubleVote(other_height, other_vote), &state).unwrap_err();
+    }
+
+    #[test]
+    fn agenda_expiration() {
+        let params = GovernanceParams {
+            voting_period_blocks: Some(2),
+            voting_period_ms: Some(1000),
+            ..Default::default()
+        };
+        let (expiration_height, expiration_timestamp) = params.agenda_expiration(10, 5000);
+        let agenda = Agenda {
+            author: member("a").0.public_key,
+            timestamp: 5000,
+            hash: Hash256::zero(),
+            expiration_height,
+            expiration_timestamp,
+            supersedes: None,
+            amendment_proof: None,
+        };
+        assert!(!agenda.is_expired(10, 5000));
+        assert!(!agenda.is_expired(11, 6000));
+        assert!(agenda.is_expired(12, 6000));
+        assert!(agenda.is_expired(11, 6001));
+        let (expiration_height, expiration_timestamp) =
+            GovernanceParams::default().agenda_expiration(10, 5000);
+        assert_eq!((expiration_height, expiration_timestamp), (None, None));
+    }
+
+    #[test]
+    fn from_json() {
+        let mut state = state(vec![member("a").0, member("b").0]);
+        let json = serde_json::to_vec(&state).unwrap();
+        assert_eq!(ReservedState::from_json(&json).unwrap(), state);
+        ReservedState::from_json(&json[..json.len() / 2]).unwrap_err();
+
+        state.members[0].consensus_voting_power = VotingPower::MAX;
+        ReservedState::from_json(&serde_json::to_vec(&state).unwrap()).unwrap_err();
+        state.members[0].consensus_voting_power = 1;
+        state.governance_params.approval_threshold = Fraction::new(u64::MAX - 1, u64::MAX);
+        state.governance_params.veto_threshold = Fraction::new(u64::MAX - 1, u64::MAX);
+        ReservedState::from_json(&serde_json::to_vec(&state).unwrap()).unwrap_err();
     }
 }
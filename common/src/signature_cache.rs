@@ -0,0 +1,302 @@
+//! A cache of the verified signatures, so that the same signature is not verified twice
+//! (e.g., the history re-verified on every sync or restart).
+//!
+//! An entry is keyed by the signed hash and the signer, and holds the hash of the signature,
+//! so that another signature for the same key is still verified (and not trusted).
+//! Only the successful verifications are cached.
+//!
+//! The least recently used entries are evicted beyond the capacity.
+//! The cache is in memory; `entries()` and `extend()` are for persisting it.
+//!
+//! The verification of the commits, the proofs, the DMS messages and the votes
+//! all go through the one `shared()` cache of the process.
+use crate::crypto::*;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_SIGNATURE_CACHE_CAPACITY: usize = 100_000;
+
+static SHARED: Lazy<Arc<SignatureCache>> = Lazy::new(Default::default);
+
+/// Returns the cache shared in the process.
+pub fn shared() -> Arc<SignatureCache> {
+    Arc::clone(&SHARED)
+}
+
+/// A signature that has been verified.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifiedSignature {
+    pub hash: Hash256,
+    pub signer: PublicKey,
+    /// The hash of the signature.
+    pub signature: Hash256,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// The hash of the signature and the last use of each key.
+    map: HashMap<(Hash256, PublicKey), (Hash256, u64)>,
+    /// The keys by their last use.
+    order: BTreeMap<u64, (Hash256, PublicKey)>,
+    clock: u64,
+    stats: SignatureCacheStats,
+}
+
+impl Entries {
+    fn touch(&mut self, key: &(Hash256, PublicKey)) {
+        self.clock += 1;
+        let clock = self.clock;
+        if let Some((_, used)) = self.map.get_mut(key) {
+            self.order.remove(used);
+            *used = clock;
+            self.order.insert(clock, key.clone());
+        }
+    }
+
+    fn insert(&mut self, key: (Hash256, PublicKey), signature: Hash256, capacity: usize) {
+        self.clock += 1;
+        if let Some((_, used)) = self.map.insert(key.clone(), (signature, self.clock)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.clock, key);
+        while self.map.len() > capacity {
+            let oldest = *self
+                .order
+                .keys()
+                .next()
+                .expect("the order is never empty here");
+            let key = self.order.remove(&oldest).expect("just found");
+            self.map.remove(&key);
+        }
+    }
+}
+
+pub struct SignatureCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl Default for SignatureCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIGNATURE_CACHE_CAPACITY)
+    }
+}
+
+impl SignatureCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Default::default(),
+        }
+    }
+
+    /// Verifies the signature, unless the same one has been verified already.
+    pub fn verify(
+        &self,
+        hash: Hash256,
+        signature: &Signature,
+        signer: &PublicKey,
+    ) -> Result<(), CryptoError> {
+        let key = (hash, signer.clone());
+        let digest = Hash256::hash(signature);
+        {
+            let mut entries = self.entries.lock().expect("poisoned");
+            if matches!(entries.map.get(&key), Some((cached, _)) if *cached == digest) {
+                entries.touch(&key);
+                entries.stats.hits += 1;
+                return Ok(());
+            }
+            entries.stats.misses += 1;
+        }
+        // Verifies without holding the lock.
+        signature.verify(hash, signer)?;
+        self.entries
+            .lock()
+            .expect("poisoned")
+            .insert(key, digest, self.capacity);
+        Ok(())
+    }
+
+    /// Verifies the typed signature, unless the same one has been verified already.
+    pub fn verify_typed<T: ToHash256>(
+        &self,
+        signature: &TypedSignature<T>,
+        data: &T,
+    ) -> Result<(), CryptoError> {
+        self.verify(data.to_hash256(), signature.signature(), signature.signer())
+    }
+
+    /// Verifies the signatures together (see `Signature::verify_batch()`),
+    /// except the ones that have been verified already.
+    pub fn verify_batch(
+        &self,
+        signatures: &[(Hash256, &Signature, &PublicKey)],
+    ) -> Result<(), (usize, CryptoError)> {
+        let keys: Vec<_> = signatures
+            .iter()
+            .map(|(hash, signature, signer)| ((*hash, (*signer).clone()), Hash256::hash(signature)))
+            .collect();
+        let mut missed = Vec::new();
+        {
+            let mut entries = self.entries.lock().expect("poisoned");
+            for (index, (key, digest)) in keys.iter().enumerate() {
+                if matches!(entries.map.get(key), Some((cached, _)) if cached == digest) {
+                    entries.touch(key);
+                    entries.stats.hits += 1;
+                } else {
+                    entries.stats.misses += 1;
+                    missed.push(index);
+                }
+            }
+        }
+        if missed.is_empty() {
+            return Ok(());
+        }
+        Signature::verify_batch(
+            &missed
+                .iter()
+                .map(|index| signatures[*index])
+                .collect::<Vec<_>>(),
+        )
+        .map_err(|(index, e)| (missed[index], e))?;
+        let mut entries = self.entries.lock().expect("poisoned");
+        for index in missed {
+            let (key, digest) = keys[index].clone();
+            entries.insert(key, digest, self.capacity);
+        }
+        Ok(())
+    }
+
+    /// Verifies the typed signatures of the same data together, except the cached ones.
+    pub fn verify_batch_typed<T: ToHash256>(
+        &self,
+        signatures: &[TypedSignature<T>],
+        data: &T,
+    ) -> Result<(), (usize, CryptoError)> {
+        let data = data.to_hash256();
+        self.verify_batch(
+            &signatures
+                .iter()
+                .map(|signature| (data, signature.signature(), signature.signer()))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().expect("poisoned").map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> SignatureCacheStats {
+        self.entries.lock().expect("poisoned").stats
+    }
+
+    /// Returns the entries, from the least recently used.
+    pub fn entries(&self) -> Vec<VerifiedSignature> {
+        let entries = self.entries.lock().expect("poisoned");
+        entries
+            .order
+            .values()
+            .map(|(hash, signer)| VerifiedSignature {
+                hash: *hash,
+                signer: signer.clone(),
+                signature: entries.map[&(*hash, signer.clone())].0,
+            })
+            .collect()
+    }
+
+    /// Adds the entries as the most recently used, in order (e.g., loaded from `entries()`).
+    ///
+    /// They are trusted as given, so they must come from a trusted source.
+    pub fn extend(&self, verified: impl IntoIterator<Item = VerifiedSignature>) {
+        let mut entries = self.entries.lock().expect("poisoned");
+        for entry in verified {
+            entries.insert((entry.hash, entry.signer), entry.signature, self.capacity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache() {
+        let cache = SignatureCache::new(2);
+        let (public_key, private_key) = generate_keypair("signer");
+        let sign = |data: &str| {
+            (
+                Hash256::hash(data),
+                Signature::sign(Hash256::hash(data), &private_key).unwrap(),
+            )
+        };
+        let (a, signature_a) = sign("a");
+        let (b, signature_b) = sign("b");
+        let (c, signature_c) = sign("c");
+
+        cache.verify(a, &signature_a, &public_key).unwrap();
+        cache.verify(a, &signature_a, &public_key).unwrap();
+        assert_eq!(cache.stats(), SignatureCacheStats { hits: 1, misses: 1 });
+        // Another signature for a cached key is still verified.
+        cache.verify(a, &signature_b, &public_key).unwrap_err();
+        // A wrong signature is not cached.
+        assert_eq!(cache.len(), 1);
+
+        cache.verify(b, &signature_b, &public_key).unwrap();
+        cache.verify(a, &signature_a, &public_key).unwrap();
+        // Evicts `b`, the least recently used.
+        cache.verify(c, &signature_c, &public_key).unwrap();
+        assert_eq!(
+            cache
+                .entries()
+                .into_iter()
+                .map(|entry| entry.hash)
+                .collect::<Vec<_>>(),
+            vec![a, c]
+        );
+
+        let restored = SignatureCache::new(2);
+        restored.extend(cache.entries());
+        assert_eq!(restored.entries(), cache.entries());
+    }
+
+    #[test]
+    fn batch() {
+        let cache = SignatureCache::default();
+        let (public_key, private_key) = generate_keypair("signer");
+        let hashes: Vec<_> = ["a", "b", "c"].iter().map(Hash256::hash).collect();
+        let signatures: Vec<_> = hashes
+            .iter()
+            .map(|hash| Signature::sign(*hash, &private_key).unwrap())
+            .collect();
+        let batch: Vec<_> = hashes
+            .iter()
+            .zip(&signatures)
+            .map(|(hash, signature)| (*hash, signature, &public_key))
+            .collect();
+
+        cache
+            .verify(hashes[0], &signatures[0], &public_key)
+            .unwrap();
+        cache.verify_batch(&batch).unwrap();
+        assert_eq!(cache.stats(), SignatureCacheStats { hits: 1, misses: 3 });
+        cache.verify_batch(&batch).unwrap();
+        assert_eq!(cache.stats(), SignatureCacheStats { hits: 4, misses: 3 });
+
+        // The index of an invalid signature is of the whole batch.
+        let mut invalid = batch.clone();
+        invalid[2].1 = &signatures[1];
+        assert_eq!(cache.verify_batch(&invalid).unwrap_err().0, 2);
+    }
+}
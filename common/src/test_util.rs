@@ -0,0 +1,120 @@
+//! The fixtures shared by the tests of the crates, available with the `test-util` feature.
+use crate::genesis::GenesisProposal;
+use crate::reserved::*;
+use crate::*;
+
+/// Returns the member of the key `generate_keypair(name)`, with the voting powers of 1.
+pub fn member(name: &str) -> Member {
+    Member {
+        public_key: generate_keypair(name).0,
+        name: name.to_owned(),
+        governance_voting_power: 1,
+        consensus_voting_power: 1,
+        governance_delegations: None,
+        consensus_delegations: None,
+        previous_public_keys: Vec::new(),
+        multisig_public_key: None,
+        bls_public_key: None,
+    }
+}
+
+/// Returns a header of the height authored by the member `a`, with the other fields empty.
+pub fn header(height: BlockHeight) -> BlockHeader {
+    BlockHeader {
+        author: generate_keypair("a").0,
+        prev_block_finalization_proof: Vec::new(),
+        previous_hash: Hash256::zero(),
+        height,
+        timestamp: 0,
+        commit_hash: Hash256::zero(),
+        tx_merkle_root: Hash256::zero(),
+        chat_merkle_root: Hash256::zero(),
+        repository_merkle_root: Hash256::zero(),
+        validator_set: Vec::new(),
+        version: "0.1.0".to_owned(),
+    }
+}
+
+/// Returns a valid header on top of `last` for the commits, authored by `b`,
+/// with the finalization proof of `last` signed by the members of the names.
+pub fn next_header(
+    last: &BlockHeader,
+    reserved_state: &ReservedState,
+    commits: &[Commit],
+    signers: &[&str],
+) -> BlockHeader {
+    let header = BlockHeader {
+        author: generate_keypair("b").0,
+        prev_block_finalization_proof: signers
+            .iter()
+            .map(|name| TypedSignature::sign(last, &generate_keypair(name).1).unwrap())
+            .collect(),
+        previous_hash: last.to_hash256(),
+        height: last.height + 1,
+        timestamp: last.timestamp + 1,
+        validator_set: reserved_state.create_validator_set().unwrap(),
+        version: reserved_state.version.clone(),
+        ..self::header(last.height + 1)
+    };
+    let transactions: Vec<_> = commits
+        .iter()
+        .filter_map(|commit| match commit {
+            Commit::Transaction(transaction) => Some(transaction.clone()),
+            _ => None,
+        })
+        .collect();
+    BlockHeader {
+        commit_hash: header.calculate_commit_hash(commits),
+        tx_merkle_root: header.calculate_tx_merkle_root(&transactions),
+        chat_merkle_root: header.calculate_chat_merkle_root(&[]),
+        ..header
+    }
+}
+
+/// Returns a reserved state of the members with an unsigned genesis,
+/// for the tests that don't verify the genesis.
+pub fn reserved_state(members: Vec<Member>) -> ReservedState {
+    ReservedState {
+        genesis_info: GenesisInfo {
+            header: BlockHeader {
+                author: generate_keypair("genesis").0,
+                ..header(0)
+            },
+            genesis_proof: Vec::new(),
+            chain_name: "test".to_owned(),
+        },
+        consensus_leader_order: (0..members.len()).collect(),
+        members,
+        version: "0.1.0".to_owned(),
+        governance_params: GovernanceParams::default(),
+        consensus_params: ConsensusParams::default(),
+        bootstrap_peers: Vec::new(),
+        parameters: ChainParameters::default(),
+        external_events: ExternalEvents::default(),
+        processed_evidences: Default::default(),
+    }
+}
+
+/// Returns the genesis proposal of the chain `test` with the members of the names.
+pub fn genesis_proposal(names: &[&str]) -> GenesisProposal {
+    GenesisProposal {
+        chain_name: "test".to_owned(),
+        members: names.iter().map(|name| member(name)).collect(),
+        version: "0.1.0".to_owned(),
+        governance_params: GovernanceParams::default(),
+        consensus_params: ConsensusParams::default(),
+        bootstrap_peers: Vec::new(),
+        parameters: ChainParameters::default(),
+        timestamp: 0,
+    }
+}
+
+/// Returns the genesis reserved state of `genesis_proposal(names)`, approved by every member.
+pub fn genesis(names: &[&str]) -> ReservedState {
+    let proposal = genesis_proposal(names);
+    let approvals: Vec<_> = names
+        .iter()
+        .map(|name| proposal.approve(&generate_keypair(name).1).unwrap())
+        .collect();
+    proposal.finalize(&approvals).unwrap()
+}
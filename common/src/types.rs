@@ -1,4 +1,8 @@
-use crate::{crypto::*, reserved::ReservedState};
+use crate::{
+    canonical,
+    crypto::*,
+    reserved::{ReservedState, ReservedStateChange},
+};
 use serde::{Deserialize, Serialize};
 
 pub type VotingPower = u64;
@@ -8,6 +12,36 @@ pub type Timestamp = u64;
 pub type BlockHeight = u64;
 pub type ConsensusRound = u64;
 pub type FinalizationProof = Vec<TypedSignature<BlockHeader>>;
+
+/// A constant-size alternative to `FinalizationProof`, aggregating the BLS signatures.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct AggregateFinalizationProof {
+    /// Whether each validator in `validator_set` of the header signed, in the same order.
+    pub signers: Vec<bool>,
+    pub signature: crate::bls::BlsSignature,
+}
+/// An application-defined payload that a validator attaches to its precommit
+/// (e.g., price oracle data or attestations of external chains).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct VoteExtension {
+    pub data: Vec<u8>,
+}
+
+/// A vote extension, signed together with the hash of the precommitted block.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SignedVoteExtension {
+    pub extension: VoteExtension,
+    pub signature: TypedSignature<(Hash256, VoteExtension)>,
+}
+
+/// A finalization proof with the vote extensions of the precommits aggregated.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ExtendedFinalizationProof {
+    pub proof: FinalizationProof,
+    /// At most one for each validator who signed `proof`.
+    pub extensions: Vec<SignedVoteExtension>,
+}
+
 pub type MemberName = String;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -20,6 +54,18 @@ pub struct Member {
     pub consensus_voting_power: VotingPower,
     pub governance_delegations: Option<PublicKey>,
     pub consensus_delegations: Option<PublicKey>,
+    /// The keys that this member used before, with the heights at which they were rotated.
+    ///
+    /// A signature by a previous key is accepted only for the commits
+    /// created before its rotation height.
+    pub previous_public_keys: Vec<(PublicKey, BlockHeight)>,
+    /// If set, the governance approvals of this member are made by the multisig,
+    /// not by `public_key`. It counts as a single vote once the threshold is satisfied.
+    pub multisig_public_key: Option<MultisigPublicKey>,
+    /// The BLS key for the aggregate finalization proofs, with its proof of possession.
+    ///
+    /// The proofs are aggregated only if every validator has one.
+    pub bls_public_key: Option<(crate::bls::BlsPublicKey, crate::bls::BlsSignature)>,
     // TODO: add various conditions for each delegation.
     // - Unlock-Automatically-After-N-Blocks
     // - Unlock-Automatically-After-T-Seconds
@@ -49,6 +95,9 @@ pub struct BlockHeader {
     pub repository_merkle_root: Hash256,
     /// The effective validator set (delegation-applied) for the next block.
     ///
+    /// That is, a change of the validator set committed in this block takes effect
+    /// from the next height (see `verify::ValidatorSetSchedule`).
+    ///
     /// The order here is the consensus leader selection order.
     pub validator_set: Vec<(PublicKey, VotingPower)>,
     /// The protocol version that must be used from next block.
@@ -62,11 +111,79 @@ pub struct Agenda {
     pub author: PublicKey,
     pub timestamp: Timestamp,
     pub hash: Hash256,
+    /// The last finalized height at which this agenda can still be voted for, if limited.
+    ///
+    /// Both expirations must be `GovernanceParams::agenda_expiration()` of the agenda
+    /// as of its creation, which the verifier checks.
+    ///
+    /// The optional fields are omitted when absent, so that the hash of an agenda
+    /// without them stays the same as before they were introduced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiration_height: Option<BlockHeight>,
+    /// The last timestamp at which this agenda can still be voted for, if limited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expiration_timestamp: Option<Timestamp>,
+    /// The hash of the open agenda of the same author that this one amends, if any.
+    ///
+    /// The votes on the superseded agenda are invalidated, so it can never be approved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supersedes: Option<Hash256>,
+    /// The signature of the author on `(supersedes, hash)`, which authenticates the amendment
+    /// (see `Agenda::is_amendment_of()`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amendment_proof: Option<TypedSignature<(Hash256, Hash256)>>,
+}
+
+impl Agenda {
+    /// Checks whether the agenda has expired, given the last finalized height and the current time.
+    pub fn is_expired(&self, height: BlockHeight, now: Timestamp) -> bool {
+        self.expiration_height.map_or(false, |h| height > h)
+            || self.expiration_timestamp.map_or(false, |t| now > t)
+    }
+
+    /// Checks whether this is an amendment of the given agenda by its author,
+    /// authenticated by `amendment_proof`.
+    pub fn is_amendment_of(&self, superseded: &Agenda) -> bool {
+        let superseded_hash = superseded.to_hash256();
+        self.supersedes == Some(superseded_hash)
+            && self.author == superseded.author
+            && self.amendment_proof.as_ref().map_or(false, |proof| {
+                proof.signer() == &self.author
+                    && proof.verify(&(superseded_hash, self.hash)).is_ok()
+            })
+    }
+}
+
+/// A message of the member chat.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ChatMessage {
+    pub author: PublicKey,
+    pub text: String,
+    pub timestamp: Timestamp,
+    /// If set, it is shared on the network but never recorded on the chain.
+    #[serde(default)]
+    pub off_the_record: bool,
 }
 
+/// The chat messages recorded on the chain, signed by the governance keys of the authors.
+///
+/// The messages are in the order of the timestamps, and then of the hashes.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct ChatLog {
-    // TODO
+    pub messages: Vec<(ChatMessage, TypedSignature<ChatMessage>)>,
+}
+
+impl ChatLog {
+    /// The Merkle root of the messages, which goes into `BlockHeader::chat_merkle_root`.
+    pub fn merkle_root(&self) -> Hash256 {
+        crate::merkle_tree::OneshotMerkleTree::create(
+            self.messages
+                .iter()
+                .map(|(message, _)| message.to_hash256())
+                .collect(),
+        )
+        .root()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -83,10 +200,22 @@ pub enum Diff {
     ///
     /// The actual content of the diff is not covered by this crate; see `simperby-repository`.
     General(Hash256),
-    /// Changes the reserved area. Contains the new reserved state and the hash of the diff.
+    /// Changes the reserved area. Contains the new reserved state and the changes that make it,
+    /// which the verifier replays to check the new reserved state (see `Diff::hash()`).
     /// It holds the reserved state as a `Box` to flatten the variant size.
     /// (see https://rust-lang.github.io/rust-clippy/master/index.html#large_enum_variant)
-    Reserved(Box<ReservedState>, Hash256),
+    Reserved(Box<ReservedState>, Vec<ReservedStateChange>),
+}
+
+impl Diff {
+    /// Returns the hash of the diff, which is of the changes for a reserved one.
+    pub fn hash(&self) -> Option<Hash256> {
+        match self {
+            Diff::None => None,
+            Diff::General(hash) => Some(*hash),
+            Diff::Reserved(_, changes) => Some(canonical::to_hash256(changes)),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -103,6 +232,10 @@ pub enum ExtraAgendaTransaction {
     Delegate(TxDelegate),
     Undelegate(TxUndelegate),
     Report(TxReport),
+    RotateKey(TxRotateKey),
+    RemoveMember(TxRemoveMember),
+    EmergencyExpel(TxEmergencyExpel),
+    Attest(TxAttest),
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -120,9 +253,155 @@ pub struct TxUndelegate {
     pub proof: TypedSignature<(PublicKey, BlockHeight)>,
 }
 
+/// Replaces the key of a member, keeping the member in the member set.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct TxRotateKey {
+    pub old_key: PublicKey,
+    pub new_key: PublicKey,
+    /// The height from which the new key is used,
+    /// within `GovernanceParams::key_rotation_grace` blocks.
+    pub rotation_height: BlockHeight,
+    /// The signature by the old key on `(genesis hash, old key, new key, rotation height)`,
+    /// so that it can't be replayed on another chain.
+    pub proof: TypedSignature<(Hash256, PublicKey, PublicKey, BlockHeight)>,
+    /// The signature by the new key on the same, proving its possession.
+    pub new_key_proof: TypedSignature<(Hash256, PublicKey, PublicKey, BlockHeight)>,
+}
+
+/// Removes a member once the transaction is in a finalized block.
+///
+/// The approvals must exceed `GovernanceParams::approval_threshold` of the governance voting
+/// power, as an agenda would need; the removed member counts like the others.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct TxRemoveMember {
+    pub name: MemberName,
+    /// The signatures of the approving members on `(name, height)`.
+    pub approvals: Vec<TypedSignature<(MemberName, BlockHeight)>>,
+}
+
+/// Expels a member immediately, without waiting for an agenda.
+///
+/// The approvals must exceed `GovernanceParams::emergency_threshold` of the governance voting
+/// power as it is without the expelled member, so the expelled member can't block it.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct TxEmergencyExpel {
+    pub name: MemberName,
+    /// Why the member is expelled, for the record.
+    pub reason: String,
+    /// The signatures of the approving members on `(name, reason, height)`.
+    pub approvals: Vec<TypedSignature<(MemberName, String, BlockHeight)>>,
+}
+
+/// An event observed on an external chain (e.g., a deposit to the treasury address of the DAO).
+///
+/// The attestations are counted per identical event, so the observers must agree
+/// on the exact content.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ExternalEvent {
+    /// The external chain, which must be one of `ChainParameters::ATTESTATION_CHAINS`.
+    pub chain: String,
+    /// The identifier of the event on the chain (e.g., a transaction hash with a log index).
+    pub id: String,
+    /// The height of the block of the external chain that includes the event.
+    pub block_height: u64,
+    /// The content of the event, in the format agreed for the chain.
+    pub data: String,
+}
+
+/// Attests to an event observed on an external chain, signed by the attesting member.
+///
+/// The event is accepted once attested by `ChainParameters::ATTESTATION_THRESHOLD` members
+/// (see `reserved::ExternalEvents`).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct TxAttest {
+    pub event: ExternalEvent,
+    pub proof: TypedSignature<ExternalEvent>,
+}
+
+/// Reports a misbehavior of validators.
+///
+/// Anyone may submit it; the evidence itself is the authorization. It takes effect
+/// once the transaction is in a finalized block, imposing the penalty of the chain
+/// (see `ChainParameters::report_penalty()`) on the offenders.
+/// The evidence is verified against the validator set for its height,
+/// and each misbehavior is penalized only once (see `Evidence::misbehavior_hash()`).
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct TxReport {
-    // TODO
+    pub evidence: Evidence,
+}
+
+/// A penalty imposed on the offenders of an `Evidence`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum Penalty {
+    /// Reduces the consensus voting power of each offender by the given amount.
+    Slash(VotingPower),
+    /// Removes the offenders from the member set.
+    Expel,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum ConsensusMessageKind {
+    Proposal,
+    PreVote,
+    PreCommit,
+}
+
+/// The content of a consensus message that a validator signs.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct ConsensusPayload {
+    pub height: BlockHeight,
+    pub round: ConsensusRound,
+    pub kind: ConsensusMessageKind,
+    /// The hash of the proposed or voted block; `None` for a nil vote.
+    pub block_hash: Option<Hash256>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct SignedConsensusPayload {
+    pub payload: ConsensusPayload,
+    pub signature: TypedSignature<ConsensusPayload>,
+}
+
+/// A proof of a misbehavior, verifiable solely from the signed payloads
+/// (see `verify::verify_evidence()`).
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum Evidence {
+    /// Two different pre-votes or pre-commits by the same validator for the same height and round.
+    DoubleVote(SignedConsensusPayload, SignedConsensusPayload),
+    /// Two different proposals by the same validator for the same height and round.
+    DoubleProposal(SignedConsensusPayload, SignedConsensusPayload),
+    /// Two different blocks of the same height, both finalized.
+    ///
+    /// The offenders are the validators who signed both.
+    ConflictingFinalization {
+        first: (BlockHeader, FinalizationProof),
+        second: (BlockHeader, FinalizationProof),
+    },
+}
+
+impl Evidence {
+    /// Returns the height at which the misbehavior happened.
+    pub fn height(&self) -> BlockHeight {
+        match self {
+            Evidence::DoubleVote(x, _) | Evidence::DoubleProposal(x, _) => x.payload.height,
+            Evidence::ConflictingFinalization { first, .. } => first.0.height,
+        }
+    }
+
+    /// Returns the hash identifying the misbehavior, regardless of the order of the two parts
+    /// (and of the signers of the finalization proofs), so that it is penalized only once.
+    pub fn misbehavior_hash(&self) -> Hash256 {
+        let (first, second) = match self {
+            Evidence::DoubleVote(x, y) | Evidence::DoubleProposal(x, y) => {
+                (canonical::to_hash256(x), canonical::to_hash256(y))
+            }
+            Evidence::ConflictingFinalization { first, second } => (
+                canonical::to_hash256(&first.0),
+                canonical::to_hash256(&second.0),
+            ),
+        };
+        std::cmp::min(first, second).aggregate(&std::cmp::max(first, second))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -1,4 +1,6 @@
+use crate::signature_cache::{self, SignatureCache};
 use crate::*;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
 use thiserror::Error;
 
@@ -19,6 +21,22 @@ pub enum Error {
 /// 2. finalization proof
 /// 3. protocol version of the node binary.
 pub fn verify_header_to_header(h1: &BlockHeader, h2: &BlockHeader) -> Result<(), Error> {
+    verify_header_to_header_except_signatures(h1, h2)?;
+    let cache = signature_cache::shared();
+    for signature in &h2.prev_block_finalization_proof {
+        cache.verify_typed(signature, h1).map_err(|e| {
+            Error::CryptoError("Invalid prev_block_finalization_proof".to_string(), e)
+        })?;
+    }
+    Ok(())
+}
+
+/// Verifies `h2` against `h1` like `verify_header_to_header()`,
+/// except the signatures of `h2.prev_block_finalization_proof`.
+fn verify_header_to_header_except_signatures(
+    h1: &BlockHeader,
+    h2: &BlockHeader,
+) -> Result<(), Error> {
     if h2.height != h1.height + 1 {
         return Err(Error::InvalidArgument(format!(
             "Invalid height: expected {}, got {}",
@@ -33,7 +51,8 @@ pub fn verify_header_to_header(h1: &BlockHeader, h2: &BlockHeader) -> Result<(),
             h2.previous_hash
         )));
     }
-    if !h2.validator_set.iter().any(|(pk, _)| pk == &h1.author) {
+    // The author of `h2` must be in the validator set for its height, which `h1` carries.
+    if !h1.validator_set.iter().any(|(pk, _)| pk == &h2.author) {
         return Err(Error::InvalidArgument(format!(
             "Invalid author: got {}",
             h2.author
@@ -45,30 +64,103 @@ pub fn verify_header_to_header(h1: &BlockHeader, h2: &BlockHeader) -> Result<(),
             h1.timestamp, h2.timestamp
         )));
     }
-    for signature in &h2.prev_block_finalization_proof {
-        signature.verify(h1).map_err(|e| {
-            Error::CryptoError("Invalid prev_block_finalization_proof".to_string(), e)
-        })?;
+    Ok(())
+}
+
+/// Returns the median of the values weighted by the voting power,
+/// which is the smallest one that more than half of the total voting power is at or below.
+///
+/// It is used for the median of the clocks of the validators, which no minority can move.
+pub fn weighted_median<T: Ord + Copy>(values: &[(T, VotingPower)]) -> Option<T> {
+    let mut values = values.to_vec();
+    values.sort_by_key(|(value, _)| *value);
+    let total: VotingPower = values.iter().map(|(_, power)| power).sum();
+    let mut accumulated = 0;
+    for (value, power) in values {
+        accumulated += power;
+        if accumulated * 2 > total {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Verifies the timestamp of `header`, whose parent is `previous`.
+///
+/// 1. It must be larger than that of the parent (monotonic).
+/// 2. It must be within `max_clock_skew_ms` of `median_clock`, the median of the clocks
+///    of the validators (see `weighted_median()`) at the time of the verification.
+///
+/// The second one depends on the time, so it is checked only by the validators
+/// when they vote for a proposal, not when verifying a finalized block.
+pub fn verify_timestamp(
+    previous: &BlockHeader,
+    header: &BlockHeader,
+    median_clock: Timestamp,
+    max_clock_skew_ms: u64,
+) -> Result<(), Error> {
+    if header.timestamp <= previous.timestamp {
+        return Err(Error::InvalidArgument(format!(
+            "Invalid timestamp: expected larger than {}, got {}",
+            previous.timestamp, header.timestamp
+        )));
+    }
+    let skew = if header.timestamp > median_clock {
+        header.timestamp - median_clock
+    } else {
+        median_clock - header.timestamp
+    };
+    if skew > max_clock_skew_ms {
+        return Err(Error::InvalidArgument(format!(
+            "Invalid timestamp: {} is {} ms away from the median clock {}, exceeding {} ms",
+            header.timestamp, skew, median_clock, max_clock_skew_ms
+        )));
     }
     Ok(())
 }
 
 /// Verifies the finalization proof of the given block header.
+///
+/// `validator_set` must be the one for the height of the header (see `validator_set_at()`),
+/// which is NOT `header.validator_set` except for the genesis block.
 pub fn verify_finalization_proof(
     header: &BlockHeader,
     block_finalization_proof: &FinalizationProof,
+    validator_set: &[(PublicKey, VotingPower)],
 ) -> Result<(), Error> {
-    let total_voting_power: VotingPower = header.validator_set.iter().map(|(_, v)| v).sum();
+    verify_finalization_signatures(header, block_finalization_proof)?;
+    verify_finalization_voting_power(block_finalization_proof, validator_set)
+}
+
+fn verify_finalization_signatures(
+    header: &BlockHeader,
+    block_finalization_proof: &FinalizationProof,
+) -> Result<(), Error> {
+    signature_cache::shared()
+        .verify_batch_typed(block_finalization_proof, header)
+        .map_err(|(index, e)| {
+            Error::CryptoError(
+                format!(
+                    "Invalid finalization proof by {}",
+                    block_finalization_proof[index].signer()
+                ),
+                e,
+            )
+        })
+}
+
+/// Verifies the finalization proof like `verify_finalization_proof()`, except the signatures.
+fn verify_finalization_voting_power(
+    block_finalization_proof: &FinalizationProof,
+    validator_set: &[(PublicKey, VotingPower)],
+) -> Result<(), Error> {
+    let total_voting_power: VotingPower = validator_set.iter().map(|(_, v)| v).sum();
     // TODO: change to `HashSet` after `PublicKey` supports `Hash`.
-    let mut voted_validators = BTreeSet::new();
-    for signature in block_finalization_proof {
-        signature
-            .verify(header)
-            .map_err(|e| Error::CryptoError("Invalid finalization proof".to_string(), e))?;
-        voted_validators.insert(signature.signer().clone());
-    }
-    let voted_voting_power: VotingPower = header
-        .validator_set
+    let voted_validators: BTreeSet<_> = block_finalization_proof
+        .iter()
+        .map(|signature| signature.signer().clone())
+        .collect();
+    let voted_voting_power: VotingPower = validator_set
         .iter()
         .filter(|(v, _)| voted_validators.contains(v))
         .map(|(_, power)| power)
@@ -82,20 +174,1453 @@ pub fn verify_finalization_proof(
     Ok(())
 }
 
+/// Verifies the finalization proof with the vote extensions of the given block header.
+///
+/// `validator_set` must be the one for the height of the header (see `validator_set_at()`).
+/// Each extension must be at most `max_extension_size` bytes, signed by a validator who
+/// precommitted, and accepted by `verify_extension`, the application-defined check.
+pub fn verify_extended_finalization_proof(
+    header: &BlockHeader,
+    proof: &ExtendedFinalizationProof,
+    validator_set: &[(PublicKey, VotingPower)],
+    max_extension_size: usize,
+    verify_extension: impl Fn(&PublicKey, &VoteExtension) -> Result<(), String>,
+) -> Result<(), Error> {
+    verify_finalization_proof(header, &proof.proof, validator_set)?;
+    let block_hash = header.to_hash256();
+    let mut signers = BTreeSet::new();
+    for signed in &proof.extensions {
+        let signer = signed.signature.signer();
+        if signed.extension.data.len() > max_extension_size {
+            return Err(Error::InvalidArgument(format!(
+                "the vote extension of {} exceeds the size limit: {} > {}",
+                signer,
+                signed.extension.data.len(),
+                max_extension_size
+            )));
+        }
+        if !proof.proof.iter().any(|s| s.signer() == signer) {
+            return Err(Error::InvalidArgument(format!(
+                "{} has a vote extension without a precommit",
+                signer
+            )));
+        }
+        if !signers.insert(signer) {
+            return Err(Error::InvalidArgument(format!(
+                "{} has more than one vote extension",
+                signer
+            )));
+        }
+        signature_cache::shared()
+            .verify_typed(&signed.signature, &(block_hash, signed.extension.clone()))
+            .map_err(|e| Error::CryptoError("Invalid vote extension".to_string(), e))?;
+        verify_extension(signer, &signed.extension).map_err(|e| {
+            Error::InvalidArgument(format!("invalid vote extension of {}: {}", signer, e))
+        })?;
+    }
+    Ok(())
+}
+
+/// Verifies the aggregate finalization proof of the given block header.
+///
+/// `validator_set` must be the one for the height of the header (see `validator_set_at()`),
+/// and `reserved_state` provides the BLS keys of the validators.
+pub fn verify_aggregate_finalization_proof(
+    header: &BlockHeader,
+    proof: &AggregateFinalizationProof,
+    validator_set: &[(PublicKey, VotingPower)],
+    reserved_state: &reserved::ReservedState,
+) -> Result<(), Error> {
+    if proof.signers.len() != validator_set.len() {
+        return Err(Error::InvalidProof(format!(
+            "Invalid aggregate finalization proof - expected {} signer flags, got {}",
+            validator_set.len(),
+            proof.signers.len()
+        )));
+    }
+    let total_voting_power: VotingPower = validator_set.iter().map(|(_, v)| v).sum();
+    let mut voted_voting_power = 0;
+    let mut public_keys = Vec::new();
+    for ((validator, power), signed) in validator_set.iter().zip(&proof.signers) {
+        if !signed {
+            continue;
+        }
+        let (bls_public_key, possession_proof) = reserved_state
+            .members
+            .iter()
+            .find(|member| &member.public_key == validator)
+            .and_then(|member| member.bls_public_key.as_ref())
+            .ok_or_else(|| {
+                Error::InvalidArgument(format!("Validator {} has no BLS key", validator))
+            })?;
+        bls_public_key
+            .verify_possession(possession_proof)
+            .map_err(|e| Error::CryptoError("Invalid BLS proof of possession".to_string(), e))?;
+        public_keys.push(bls_public_key.clone());
+        voted_voting_power += power;
+    }
+    proof
+        .signature
+        .verify_aggregate(header.to_hash256(), &public_keys)
+        .map_err(|e| Error::CryptoError("Invalid aggregate finalization proof".to_string(), e))?;
+    if voted_voting_power * 3 <= total_voting_power * 2 {
+        return Err(Error::InvalidProof(format!(
+            "Invalid aggregate finalization proof - voted voting power is too low: {} / {}",
+            voted_voting_power, total_voting_power
+        )));
+    }
+    Ok(())
+}
+
+/// Verifies the agenda proof against the governance rules of the reserved state
+/// at the height of the agenda.
+pub fn verify_agenda_proof(
+    agenda: &Agenda,
+    proof: &AgendaProof,
+    reserved_state: &reserved::ReservedState,
+) -> Result<(), Error> {
+    verify_agenda_approval(agenda, proof, reserved_state)?;
+    verify_agenda_signatures(agenda, proof)
+}
+
+fn verify_agenda_signatures(agenda: &Agenda, proof: &AgendaProof) -> Result<(), Error> {
+    let cache = signature_cache::shared();
+    for (_, signature) in &proof.proof {
+        cache
+            .verify_typed(signature, agenda)
+            .map_err(|e| Error::CryptoError("Invalid agenda proof".to_string(), e))?;
+    }
+    Ok(())
+}
+
+/// Verifies the agenda proof like `verify_agenda_proof()`, except the signatures.
+fn verify_agenda_approval(
+    agenda: &Agenda,
+    proof: &AgendaProof,
+    reserved_state: &reserved::ReservedState,
+) -> Result<(), Error> {
+    if proof.agenda_hash != agenda.to_hash256() {
+        return Err(Error::InvalidArgument(format!(
+            "Invalid agenda hash: expected {}, got {}",
+            agenda.to_hash256(),
+            proof.agenda_hash
+        )));
+    }
+    let mut voters = BTreeSet::new();
+    for (voter, signature) in &proof.proof {
+        if signature.signer() != voter {
+            return Err(Error::InvalidProof(format!(
+                "Invalid agenda proof - signed by {} instead of {}",
+                signature.signer(),
+                voter
+            )));
+        }
+        voters.insert(voter.clone());
+    }
+    let total_voting_power: VotingPower = reserved_state
+        .governance_voting_powers()
+        .iter()
+        .map(|(_, power)| power)
+        .sum();
+    let voted_voting_power = reserved_state.approved_governance_voting_power(&voters);
+    if !reserved_state
+        .governance_params
+        .approval_threshold
+        .is_exceeded_by(voted_voting_power, total_voting_power)
+    {
+        return Err(Error::InvalidProof(format!(
+            "Invalid agenda proof - voted voting power is too low: {} / {}",
+            voted_voting_power, total_voting_power
+        )));
+    }
+    Ok(())
+}
+
+/// The validator sets of a chain, indexed by the heights from which they are active.
+///
+/// A validator set change committed in block `N` (i.e., `validator_set` of the header `N`)
+/// takes effect from height `N + 1`. The genesis block is finalized by its own validator set.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ValidatorSetSchedule {
+    /// `(activation height, validator set)`, in ascending order of the heights.
+    entries: Vec<(BlockHeight, Vec<(PublicKey, VotingPower)>)>,
+    last_height: BlockHeight,
+}
+
+impl ValidatorSetSchedule {
+    pub fn new(genesis_header: &BlockHeader) -> Self {
+        Self {
+            entries: vec![(genesis_header.height, genesis_header.validator_set.clone())],
+            last_height: genesis_header.height,
+        }
+    }
+
+    /// Records the header that has been finalized right after the last recorded one.
+    pub fn record(&mut self, header: &BlockHeader) -> Result<(), Error> {
+        if header.height != self.last_height + 1 {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid height: expected {}, got {}",
+                self.last_height + 1,
+                header.height
+            )));
+        }
+        self.last_height = header.height;
+        let (_, current) = self.entries.last().expect("never empty");
+        if current != &header.validator_set {
+            self.entries
+                .push((header.height + 1, header.validator_set.clone()));
+        }
+        Ok(())
+    }
+
+    /// Returns the validator set that finalizes the block of the given height.
+    ///
+    /// It's known up to the height right after the last recorded header.
+    pub fn validator_set_at(&self, height: BlockHeight) -> Option<&[(PublicKey, VotingPower)]> {
+        if height > self.last_height + 1 {
+            return None;
+        }
+        self.entries
+            .iter()
+            .rev()
+            .find(|(activation_height, _)| *activation_height <= height)
+            .map(|(_, validator_set)| validator_set.as_slice())
+    }
+}
+
+/// Verifies the signature of a member on the data of a commit at the given height.
+///
+/// The signature may be made by a rotated-out key if the commit precedes the rotation.
+pub fn verify_member_signature<'a, T: ToHash256>(
+    reserved_state: &'a reserved::ReservedState,
+    data: &T,
+    signature: &TypedSignature<T>,
+    height: BlockHeight,
+) -> Result<&'a Member, Error> {
+    let member = reserved_state
+        .find_member_by_key(signature.signer(), height)
+        .ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "{} is not a valid key of any member at height {}",
+                signature.signer(),
+                height
+            ))
+        })?;
+    signature
+        .verify(data)
+        .map_err(|e| Error::CryptoError("Invalid member signature".to_string(), e))?;
+    Ok(member)
+}
+
+/// Verifies the evidence and returns the offenders.
+///
+/// `validator_set` must be the one for the height of the evidence (see `validator_set_at()`);
+/// only the validators in it can be the offenders.
+pub fn verify_evidence(
+    evidence: &Evidence,
+    validator_set: &[(PublicKey, VotingPower)],
+) -> Result<Vec<PublicKey>, Error> {
+    match evidence {
+        Evidence::DoubleVote(first, second) => {
+            verify_equivocation(first, second, false, validator_set)
+        }
+        Evidence::DoubleProposal(first, second) => {
+            verify_equivocation(first, second, true, validator_set)
+        }
+        Evidence::ConflictingFinalization { first, second } => {
+            if first.0.height != second.0.height {
+                return Err(Error::InvalidArgument(format!(
+                    "the blocks are of different heights: {} and {}",
+                    first.0.height, second.0.height
+                )));
+            }
+            if first.0.to_hash256() == second.0.to_hash256() {
+                return Err(Error::InvalidArgument(
+                    "the blocks are not conflicting".to_string(),
+                ));
+            }
+            verify_finalization_proof(&first.0, &first.1, validator_set)?;
+            verify_finalization_proof(&second.0, &second.1, validator_set)?;
+            let first_signers = first
+                .1
+                .iter()
+                .map(|signature| signature.signer())
+                .collect::<BTreeSet<_>>();
+            let offenders = validator_set
+                .iter()
+                .map(|(validator, _)| validator)
+                .filter(|validator| {
+                    first_signers.contains(validator)
+                        && second.1.iter().any(|s| s.signer() == *validator)
+                })
+                .cloned()
+                .collect::<Vec<_>>();
+            Ok(offenders)
+        }
+    }
+}
+
+fn verify_equivocation(
+    first: &SignedConsensusPayload,
+    second: &SignedConsensusPayload,
+    proposal: bool,
+    validator_set: &[(PublicKey, VotingPower)],
+) -> Result<Vec<PublicKey>, Error> {
+    let (a, b) = (&first.payload, &second.payload);
+    if (a.kind == ConsensusMessageKind::Proposal) != proposal || a.kind != b.kind {
+        return Err(Error::InvalidArgument(format!(
+            "invalid message kinds: {:?} and {:?}",
+            a.kind, b.kind
+        )));
+    }
+    if a.height != b.height || a.round != b.round {
+        return Err(Error::InvalidArgument(
+            "the messages are of different heights or rounds".to_string(),
+        ));
+    }
+    if a.block_hash == b.block_hash {
+        return Err(Error::InvalidArgument(
+            "the messages are not conflicting".to_string(),
+        ));
+    }
+    let offender = first.signature.signer();
+    if second.signature.signer() != offender {
+        return Err(Error::InvalidArgument(
+            "the messages are signed by different keys".to_string(),
+        ));
+    }
+    if !validator_set
+        .iter()
+        .any(|(validator, _)| validator == offender)
+    {
+        return Err(Error::InvalidArgument(format!(
+            "{} is not a validator",
+            offender
+        )));
+    }
+    for signed in [first, second] {
+        signed
+            .signature
+            .verify(&signed.payload)
+            .map_err(|e| Error::CryptoError("Invalid evidence".to_string(), e))?;
+    }
+    Ok(vec![offender.clone()])
+}
+
+/// Makes an evidence if the two messages conflict; i.e., they are of the same kind, height
+/// and round, signed by the same key, but for different blocks.
+///
+/// The signatures are not verified here; see `verify_evidence()`.
+pub fn find_equivocation(
+    first: &SignedConsensusPayload,
+    second: &SignedConsensusPayload,
+) -> Option<Evidence> {
+    let (a, b) = (&first.payload, &second.payload);
+    if first.signature.signer() != second.signature.signer()
+        || a.kind != b.kind
+        || a.height != b.height
+        || a.round != b.round
+        || a.block_hash == b.block_hash
+    {
+        return None;
+    }
+    let (first, second) = (first.clone(), second.clone());
+    Some(match a.kind {
+        ConsensusMessageKind::Proposal => Evidence::DoubleProposal(first, second),
+        ConsensusMessageKind::PreVote | ConsensusMessageKind::PreCommit => {
+            Evidence::DoubleVote(first, second)
+        }
+    })
+}
+
+/// Makes an evidence if the two finalized blocks conflict; i.e., they are different
+/// blocks of the same height.
+///
+/// The finalization proofs are not verified here; see `verify_evidence()`.
+pub fn find_conflicting_finalization(
+    first: (&BlockHeader, &FinalizationProof),
+    second: (&BlockHeader, &FinalizationProof),
+) -> Option<Evidence> {
+    if first.0.height != second.0.height || first.0.to_hash256() == second.0.to_hash256() {
+        return None;
+    }
+    Some(Evidence::ConflictingFinalization {
+        first: (first.0.clone(), first.1.clone()),
+        second: (second.0.clone(), second.1.clone()),
+    })
+}
+
+/// A signature to verify, detached from the data it signs, so that the signatures of
+/// many commits can be verified in parallel before the commits are applied in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureCheck {
+    pub hash: Hash256,
+    pub signature: Signature,
+    pub signer: PublicKey,
+}
+
+impl SignatureCheck {
+    pub fn new<T: ToHash256>(hash: Hash256, signature: &TypedSignature<T>) -> Self {
+        Self {
+            hash,
+            signature: signature.signature().clone(),
+            signer: signature.signer().clone(),
+        }
+    }
+
+    pub fn verify(&self) -> Result<(), Error> {
+        self.signature
+            .verify(self.hash, &self.signer)
+            .map_err(|e| Error::CryptoError(format!("Invalid signature of {}", self.signer), e))
+    }
+
+    /// Verifies the signature, unless it is in the cache.
+    pub fn verify_cached(&self, cache: &SignatureCache) -> Result<(), Error> {
+        cache
+            .verify(self.hash, &self.signature, &self.signer)
+            .map_err(|e| Error::CryptoError(format!("Invalid signature of {}", self.signer), e))
+    }
+}
+
+/// Returns the signatures in the commit that can be verified without the state of the chain:
+/// the finalization proof of the previous block in a block header, and the votes in an agenda proof.
+///
+/// Whether the signers may sign is not checked here.
+/// These are the signatures that `CommitSequenceVerifier::apply_checked_commit()` skips.
+pub fn signature_checks(commit: &Commit) -> Vec<SignatureCheck> {
+    match commit {
+        Commit::Block(header) => header
+            .prev_block_finalization_proof
+            .iter()
+            .map(|signature| SignatureCheck::new(header.previous_hash, signature))
+            .collect(),
+        Commit::AgendaProof(proof) => proof
+            .proof
+            .iter()
+            .map(|(_, signature)| SignatureCheck::new(proof.agenda_hash, signature))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The kind of the last commit applied to `CommitSequenceVerifier`,
+/// which determines the kinds of the commits that may follow.
+///
+/// The commits of a block are in the order of
+/// transactions, agenda, agenda proof, extra-agenda transactions and chat log (see `docs/git.md`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Phase {
+    Block,
+    Transaction,
+    Agenda(Agenda),
+    AgendaProof,
+    ExtraAgendaTransaction,
+    ChatLog,
+}
+
 /// Verifies whether the given sequence of commits can be a subset of a finalized chain.
 ///
 /// It may accept sequences that contain more than one `BlockHeader`.
+/// The start header and the reserved state as of it are trusted; the finalization proof
+/// of every following header is verified against the validator set of its previous one.
 #[derive(Debug, Clone)]
 pub struct CommitSequenceVerifier {
-    // TODO
+    header: BlockHeader,
+    /// The validator set that finalizes `header`, unless it is the trusted start header.
+    finalizing_validator_set: Option<Vec<(PublicKey, VotingPower)>>,
+    phase: Phase,
+    /// The reserved state as of the last applied commit, except those of an unapproved agenda.
+    reserved_state: reserved::ReservedState,
+    /// The reserved state of the transactions, which takes effect once the agenda is approved.
+    pending_reserved_state: Option<reserved::ReservedState>,
+    /// The commits after `header`.
+    commits: Vec<Commit>,
+    /// The open agendas on top of `header`, which may supersede the one of an agenda proof.
+    open_agendas: Vec<Agenda>,
 }
 
 impl CommitSequenceVerifier {
-    pub fn new(_start_header: BlockHeader) -> Result<Self, Error> {
-        unimplemented!()
+    /// Creates a verifier starting from the header and the reserved state as of it.
+    pub fn new(
+        start_header: BlockHeader,
+        reserved_state: reserved::ReservedState,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            header: start_header,
+            finalizing_validator_set: None,
+            phase: Phase::Block,
+            reserved_state,
+            pending_reserved_state: None,
+            commits: Vec::new(),
+            open_agendas: Vec::new(),
+        })
+    }
+
+    /// Sets the agendas open on top of the last applied header.
+    ///
+    /// An agenda proof is rejected if its agenda is superseded by one of them
+    /// (see `Agenda::is_amendment_of()`). They are cleared once a block is applied.
+    pub fn set_open_agendas(&mut self, agendas: Vec<Agenda>) {
+        self.open_agendas = agendas;
+    }
+
+    /// Returns the last applied header.
+    pub fn get_header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    /// Returns the reserved state as of the last applied commit.
+    ///
+    /// The reserved state of the transactions of an agenda is not included until the agenda proof.
+    pub fn get_reserved_state(&self) -> &reserved::ReservedState {
+        &self.reserved_state
+    }
+
+    pub fn apply_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        self.apply(commit, false)
+    }
+
+    /// Applies the commit like `apply_commit()`, except verifying the signatures of
+    /// `signature_checks()` of the commit, which the caller must have verified
+    /// (e.g., in parallel for many commits).
+    ///
+    /// The rest of the proofs (e.g., the signers and their voting power) is still verified.
+    pub fn apply_checked_commit(&mut self, commit: &Commit) -> Result<(), Error> {
+        self.apply(commit, true)
+    }
+
+    fn apply(&mut self, commit: &Commit, signatures_checked: bool) -> Result<(), Error> {
+        // The height of the block which the commit will be in.
+        let height = self.header.height + 1;
+        match (commit, &self.phase) {
+            (
+                Commit::Block(header),
+                Phase::Block | Phase::AgendaProof | Phase::ExtraAgendaTransaction | Phase::ChatLog,
+            ) => {
+                self.verify_block(header, signatures_checked)?;
+                self.finalizing_validator_set = Some(self.header.validator_set.clone());
+                self.header = header.clone();
+                self.phase = Phase::Block;
+                self.commits.clear();
+                self.open_agendas.clear();
+                return Ok(());
+            }
+            (Commit::Transaction(transaction), Phase::Block | Phase::Transaction) => {
+                if let Diff::Reserved(next_state, changes) = &transaction.diff {
+                    // Replays the changes on top of those of the previous transactions.
+                    let mut state = self
+                        .pending_reserved_state
+                        .clone()
+                        .unwrap_or_else(|| self.reserved_state.clone());
+                    for change in changes {
+                        state = state.apply(change, height).map_err(|e| {
+                            Error::InvalidArgument(format!("invalid reserved state change: {}", e))
+                        })?;
+                    }
+                    if state != **next_state {
+                        return Err(Error::InvalidArgument(
+                            "the reserved state of the transaction is not the one of its changes"
+                                .to_string(),
+                        ));
+                    }
+                    self.pending_reserved_state = Some(state);
+                }
+                self.phase = Phase::Transaction;
+            }
+            (Commit::Agenda(agenda), Phase::Block | Phase::Transaction) => {
+                let transactions = self.transactions();
+                let expected = Agenda::calculate_hash(height, &transactions);
+                if agenda.hash != expected {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid agenda hash: expected {}, got {}",
+                        expected, agenda.hash
+                    )));
+                }
+                if self
+                    .reserved_state
+                    .find_member_by_key(&agenda.author, height)
+                    .is_none()
+                {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid agenda author: {} is not a member",
+                        agenda.author
+                    )));
+                }
+                let expected = self
+                    .reserved_state
+                    .governance_params
+                    .agenda_expiration(self.header.height, agenda.timestamp);
+                if (agenda.expiration_height, agenda.expiration_timestamp) != expected {
+                    return Err(Error::InvalidArgument(format!(
+                        "Invalid agenda expiration: expected {:?}, got {:?}",
+                        expected,
+                        (agenda.expiration_height, agenda.expiration_timestamp)
+                    )));
+                }
+                if agenda
+                    .expiration_height
+                    .map_or(false, |h| self.header.height > h)
+                {
+                    return Err(Error::InvalidArgument(format!(
+                        "the agenda has expired at height {}",
+                        self.header.height
+                    )));
+                }
+                self.phase = Phase::Agenda(agenda.clone());
+            }
+            (Commit::AgendaProof(proof), Phase::Agenda(agenda)) => {
+                if let Some(amendment) = self
+                    .open_agendas
+                    .iter()
+                    .find(|amendment| amendment.is_amendment_of(agenda))
+                {
+                    return Err(Error::InvalidArgument(format!(
+                        "the agenda is superseded by {}",
+                        amendment.to_hash256()
+                    )));
+                }
+                verify_agenda_approval(agenda, proof, &self.reserved_state)?;
+                if !signatures_checked {
+                    verify_agenda_signatures(agenda, proof)?;
+                }
+                if let Some(next_state) = self.pending_reserved_state.take() {
+                    self.reserved_state = next_state;
+                }
+                self.phase = Phase::AgendaProof;
+            }
+            (
+                Commit::ExtraAgendaTransaction(tx),
+                Phase::Block | Phase::AgendaProof | Phase::ExtraAgendaTransaction,
+            ) => {
+                let change = reserved::ReservedStateChange::from_extra_agenda_transaction(
+                    tx,
+                    &self.reserved_state,
+                    height,
+                    |height| self.validator_set_at(height),
+                )
+                .map_err(|e| {
+                    Error::InvalidArgument(format!("Invalid extra-agenda transaction: {}", e))
+                })?;
+                self.reserved_state = self.reserved_state.apply(&change, height).map_err(|e| {
+                    Error::InvalidArgument(format!("Invalid extra-agenda transaction: {}", e))
+                })?;
+                self.phase = Phase::ExtraAgendaTransaction;
+            }
+            (
+                Commit::ChatLog(chat_log),
+                Phase::Block | Phase::AgendaProof | Phase::ExtraAgendaTransaction,
+            ) => {
+                for (message, signature) in &chat_log.messages {
+                    if message.off_the_record {
+                        return Err(Error::InvalidArgument(
+                            "an off-the-record message is recorded".to_string(),
+                        ));
+                    }
+                    verify_member_signature(&self.reserved_state, message, signature, height)?;
+                }
+                self.phase = Phase::ChatLog;
+            }
+            (commit, phase) => {
+                return Err(Error::InvalidArgument(format!(
+                    "a commit of {} can't follow {:?}",
+                    commit_kind(commit),
+                    phase
+                )));
+            }
+        }
+        self.commits.push(commit.clone());
+        Ok(())
+    }
+
+    /// Returns the validator set for the given height, if known: that of the block
+    /// in progress, or of the last header unless it is the trusted start header
+    /// other than the genesis.
+    fn validator_set_at(&self, height: BlockHeight) -> Option<Vec<(PublicKey, VotingPower)>> {
+        if height == self.header.height + 1 {
+            Some(self.header.validator_set.clone())
+        } else if height == self.header.height {
+            self.finalizing_validator_set.clone().or_else(|| {
+                (self.header == self.reserved_state.genesis_info.header)
+                    .then(|| self.header.validator_set.clone())
+            })
+        } else {
+            None
+        }
+    }
+
+    fn transactions(&self) -> Vec<Transaction> {
+        self.commits
+            .iter()
+            .filter_map(|commit| match commit {
+                Commit::Transaction(transaction) => Some(transaction.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Verifies the header against the last one and the commits after it.
+    fn verify_block(&self, header: &BlockHeader, signatures_checked: bool) -> Result<(), Error> {
+        verify_header_to_header_except_signatures(&self.header, header)?;
+        if !signatures_checked {
+            verify_finalization_signatures(&self.header, &header.prev_block_finalization_proof)?;
+        }
+        if let Some(validator_set) = &self.finalizing_validator_set {
+            verify_finalization_voting_power(&header.prev_block_finalization_proof, validator_set)?;
+        }
+        // The approved agenda must not have expired by the time of the block,
+        // which is also the latest time it may claim to be created at.
+        let approved_agenda = self
+            .commits
+            .iter()
+            .any(|commit| matches!(commit, Commit::AgendaProof(_)))
+            .then(|| {
+                self.commits.iter().find_map(|commit| match commit {
+                    Commit::Agenda(agenda) => Some(agenda),
+                    _ => None,
+                })
+            })
+            .flatten();
+        if let Some(agenda) = approved_agenda {
+            if agenda.timestamp > header.timestamp {
+                return Err(Error::InvalidArgument(format!(
+                    "the agenda is created at {}, after the block at {}",
+                    agenda.timestamp, header.timestamp
+                )));
+            }
+            if agenda.is_expired(self.header.height, header.timestamp) {
+                return Err(Error::InvalidArgument(format!(
+                    "the agenda has expired by the block at {}",
+                    header.timestamp
+                )));
+            }
+        }
+        let chat_logs: Vec<_> = self
+            .commits
+            .iter()
+            .filter_map(|commit| match commit {
+                Commit::ChatLog(chat_log) => Some(chat_log.clone()),
+                _ => None,
+            })
+            .collect();
+        for (name, expected, actual) in [
+            (
+                "commit hash",
+                header.calculate_commit_hash(&self.commits),
+                header.commit_hash,
+            ),
+            (
+                "tx merkle root",
+                header.calculate_tx_merkle_root(&self.transactions()),
+                header.tx_merkle_root,
+            ),
+            (
+                "chat merkle root",
+                header.calculate_chat_merkle_root(&chat_logs),
+                header.chat_merkle_root,
+            ),
+        ] {
+            if expected != actual {
+                return Err(Error::InvalidArgument(format!(
+                    "Invalid {}: expected {}, got {}",
+                    name, expected, actual
+                )));
+            }
+        }
+        let validator_set = self
+            .reserved_state
+            .create_validator_set()
+            .map_err(Error::InvalidArgument)?;
+        if header.validator_set != validator_set {
+            return Err(Error::InvalidArgument(
+                "Invalid validator set: it doesn't match the reserved state".to_string(),
+            ));
+        }
+        if header.version != self.reserved_state.version {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid version: expected {}, got {}",
+                self.reserved_state.version, header.version
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn commit_kind(commit: &Commit) -> &'static str {
+    match commit {
+        Commit::Block(_) => "block",
+        Commit::Transaction(_) => "transaction",
+        Commit::Agenda(_) => "agenda",
+        Commit::AgendaProof(_) => "agenda proof",
+        Commit::ExtraAgendaTransaction(_) => "extra-agenda transaction",
+        Commit::ChatLog(_) => "chat log",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(height: BlockHeight, validators: &[&str]) -> BlockHeader {
+        BlockHeader {
+            validator_set: validators
+                .iter()
+                .map(|name| (generate_keypair(name).0, 1))
+                .collect(),
+            ..crate::test_util::header(height)
+        }
+    }
+
+    #[test]
+    fn validator_set_schedule() {
+        let genesis = header(0, &["a"]);
+        let mut schedule = ValidatorSetSchedule::new(&genesis);
+        schedule.record(&header(1, &["a"])).unwrap();
+        // Committed in block 2, effective from block 3.
+        schedule.record(&header(2, &["a", "b"])).unwrap();
+        schedule.record(&header(4, &["a", "b"])).unwrap_err();
+
+        assert_eq!(schedule.validator_set_at(0).unwrap().len(), 1);
+        assert_eq!(schedule.validator_set_at(2).unwrap().len(), 1);
+        assert_eq!(schedule.validator_set_at(3).unwrap().len(), 2);
+        assert!(schedule.validator_set_at(4).is_none());
+    }
+
+    #[test]
+    fn extended_finalization_proof() {
+        let names = ["a", "b", "c"];
+        let validator_set = header(0, &names).validator_set;
+        let block = header(1, &names);
+        let extend = |name: &str, data: Vec<u8>| {
+            let extension = VoteExtension { data };
+            let signature = TypedSignature::sign(
+                &(block.to_hash256(), extension.clone()),
+                &generate_keypair(name).1,
+            )
+            .unwrap();
+            SignedVoteExtension {
+                extension,
+                signature,
+            }
+        };
+        let mut proof = ExtendedFinalizationProof {
+            proof: names
+                .iter()
+                .map(|name| TypedSignature::sign(&block, &generate_keypair(name).1).unwrap())
+                .collect(),
+            extensions: vec![extend("a", vec![1, 2]), extend("b", vec![3])],
+        };
+        let accept_all = |_: &PublicKey, _: &VoteExtension| Ok(());
+        verify_extended_finalization_proof(&block, &proof, &validator_set, 2, accept_all).unwrap();
+        // Too large.
+        verify_extended_finalization_proof(&block, &proof, &validator_set, 1, accept_all)
+            .unwrap_err();
+        // Rejected by the application.
+        verify_extended_finalization_proof(&block, &proof, &validator_set, 2, |_, extension| {
+            if extension.data.len() == 1 {
+                Ok(())
+            } else {
+                Err("unexpected data".to_string())
+            }
+        })
+        .unwrap_err();
+        // Duplicated.
+        proof.extensions.push(extend("a", vec![4]));
+        verify_extended_finalization_proof(&block, &proof, &validator_set, 2, accept_all)
+            .unwrap_err();
+        // Not precommitted.
+        proof.extensions.pop();
+        proof.extensions.push(extend("d", vec![4]));
+        verify_extended_finalization_proof(&block, &proof, &validator_set, 2, accept_all)
+            .unwrap_err();
+    }
+
+    fn signed_payload(
+        name: &str,
+        kind: ConsensusMessageKind,
+        block_hash: Option<Hash256>,
+    ) -> SignedConsensusPayload {
+        let payload = ConsensusPayload {
+            height: 1,
+            round: 0,
+            kind,
+            block_hash,
+        };
+        let signature = TypedSignature::sign(&payload, &generate_keypair(name).1).unwrap();
+        SignedConsensusPayload { payload, signature }
+    }
+
+    #[test]
+    fn double_vote_evidence() {
+        let validator_set = header(0, &["a", "b"]).validator_set;
+        let first = signed_payload("a", ConsensusMessageKind::PreVote, None);
+        let second = signed_payload(
+            "a",
+            ConsensusMessageKind::PreVote,
+            Some(Hash256::hash("block")),
+        );
+        let evidence = find_equivocation(&first, &second).unwrap();
+        assert!(matches!(evidence, Evidence::DoubleVote(_, _)));
+        assert_eq!(
+            verify_evidence(&evidence, &validator_set).unwrap(),
+            vec![generate_keypair("a").0]
+        );
+        // Not a validator.
+        verify_evidence(&evidence, &header(0, &["b"]).validator_set).unwrap_err();
+
+        // Not conflicting.
+        assert!(find_equivocation(&first, &first).is_none());
+        let other = signed_payload("b", ConsensusMessageKind::PreVote, None);
+        assert!(find_equivocation(&first, &other).is_none());
+        verify_evidence(&Evidence::DoubleVote(first.clone(), other), &validator_set).unwrap_err();
+        // Wrong kind.
+        verify_evidence(&Evidence::DoubleProposal(first, second), &validator_set).unwrap_err();
+    }
+
+    #[test]
+    fn detached_signatures() {
+        let (_, key) = generate_keypair("a");
+        let previous = header(0, &["a"]);
+        let mut block = header(1, &["a"]);
+        block.previous_hash = previous.to_hash256();
+        block.prev_block_finalization_proof = vec![TypedSignature::sign(&previous, &key).unwrap()];
+        let checks = signature_checks(&Commit::Block(block.clone()));
+        assert_eq!(checks.len(), 1);
+        assert!(checks[0].verify().is_ok());
+
+        block.prev_block_finalization_proof = vec![TypedSignature::sign(&block, &key).unwrap()];
+        assert!(signature_checks(&Commit::Block(block))[0].verify().is_err());
+    }
+
+    #[test]
+    fn median_of_clocks() {
+        assert_eq!(weighted_median::<Timestamp>(&[]), None);
+        assert_eq!(weighted_median(&[(5, 1)]), Some(5));
+        assert_eq!(weighted_median(&[(30, 1), (10, 1), (20, 1)]), Some(20));
+        // A minority can't move the median, however far its clock is.
+        assert_eq!(
+            weighted_median(&[(10, 1), (20, 1), (1_000_000, 1)]),
+            Some(20)
+        );
+        assert_eq!(weighted_median(&[(10, 3), (20, 1), (30, 1)]), Some(10));
+        assert_eq!(weighted_median(&[(-10i64, 1), (10, 1)]), Some(10));
+    }
+
+    #[test]
+    fn timestamp() {
+        let previous = header(0, &["a"]);
+        let mut next = header(1, &["a"]);
+        next.timestamp = 100_000;
+        verify_timestamp(&previous, &next, 100_000, 1_000).unwrap();
+        verify_timestamp(&previous, &next, 99_000, 1_000).unwrap();
+        verify_timestamp(&previous, &next, 101_000, 1_000).unwrap();
+        verify_timestamp(&previous, &next, 98_999, 1_000).unwrap_err();
+        verify_timestamp(&previous, &next, 101_001, 1_000).unwrap_err();
+        // Not monotonic.
+        next.timestamp = previous.timestamp;
+        verify_timestamp(&previous, &next, next.timestamp, 1_000).unwrap_err();
+    }
+
+    #[test]
+    fn conflicting_finalization_evidence() {
+        let names = ["a", "b", "c", "d"];
+        let validator_set = header(0, &names).validator_set;
+        let finalize = |header: &BlockHeader, signers: &[&str]| -> FinalizationProof {
+            signers
+                .iter()
+                .map(|name| TypedSignature::sign(header, &generate_keypair(name).1).unwrap())
+                .collect()
+        };
+        let first = header(1, &names);
+        let mut second = header(1, &names);
+        second.timestamp = 1;
+        let first_proof = finalize(&first, &["a", "b", "c"]);
+        let second_proof = finalize(&second, &["b", "c", "d"]);
+        let evidence =
+            find_conflicting_finalization((&first, &first_proof), (&second, &second_proof))
+                .unwrap();
+        assert_eq!(
+            verify_evidence(&evidence, &validator_set).unwrap(),
+            vec![generate_keypair("b").0, generate_keypair("c").0]
+        );
+        assert!(
+            find_conflicting_finalization((&first, &first_proof), (&first, &first_proof)).is_none()
+        );
+        // Not finalized.
+        let evidence = find_conflicting_finalization(
+            (&first, &first_proof),
+            (&second, &finalize(&second, &["d"])),
+        )
+        .unwrap();
+        verify_evidence(&evidence, &validator_set).unwrap_err();
+    }
+
+    /// Returns the next header of the commits applied to the verifier, authored by `b`
+    /// and with the previous block finalized by the signers.
+    fn next_header(
+        verifier: &CommitSequenceVerifier,
+        commits: &[Commit],
+        signers: &[&str],
+    ) -> BlockHeader {
+        crate::test_util::next_header(
+            verifier.get_header(),
+            verifier.get_reserved_state(),
+            commits,
+            signers,
+        )
+    }
+
+    #[test]
+    fn commit_sequence() {
+        let genesis = crate::test_util::genesis(&["a", "b", "c"]);
+        let mut verifier =
+            CommitSequenceVerifier::new(genesis.genesis_info.header.clone(), genesis.clone())
+                .unwrap();
+        let (a, a_key) = generate_keypair("a");
+        let b = generate_keypair("b").0;
+
+        // An agenda of a transaction upgrading the version.
+        let mut upgraded = genesis.clone();
+        upgraded.version = "0.2.0".to_owned();
+        let transaction = Transaction {
+            author: a.clone(),
+            timestamp: 0,
+            head: "Upgrade".to_owned(),
+            body: String::new(),
+            diff: Diff::Reserved(
+                Box::new(upgraded.clone()),
+                vec![reserved::ReservedStateChange::BumpVersion(
+                    "0.2.0".to_owned(),
+                )],
+            ),
+        };
+        let agenda = Agenda {
+            author: a.clone(),
+            timestamp: 0,
+            hash: Agenda::calculate_hash(1, &[transaction.clone()]),
+            expiration_height: None,
+            expiration_timestamp: None,
+            supersedes: None,
+            amendment_proof: None,
+        };
+        let proof = |names: &[&str]| AgendaProof {
+            agenda_hash: agenda.to_hash256(),
+            proof: names
+                .iter()
+                .map(|name| {
+                    let (public_key, private_key) = generate_keypair(name);
+                    (
+                        public_key,
+                        TypedSignature::sign(&agenda, &private_key).unwrap(),
+                    )
+                })
+                .collect(),
+        };
+        // `a` delegates the consensus voting power to `b` ex officio.
+        let delegate = |height: BlockHeight| {
+            Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Delegate(TxDelegate {
+                delegator: a.clone(),
+                delegatee: b.clone(),
+                governance: false,
+                proof: TypedSignature::sign(&(a.clone(), b.clone(), false, height), &a_key)
+                    .unwrap(),
+            }))
+        };
+        let commits = vec![
+            Commit::Transaction(transaction.clone()),
+            Commit::Agenda(agenda.clone()),
+            Commit::AgendaProof(proof(&["a", "b"])),
+            delegate(1),
+        ];
+
+        // Not approved.
+        let mut invalid = verifier.clone();
+        invalid.apply_commit(&commits[0]).unwrap();
+        invalid
+            .apply_commit(&Commit::Block(next_header(&invalid, &commits[..1], &[])))
+            .unwrap_err();
+        invalid.apply_commit(&commits[1]).unwrap();
+        invalid
+            .apply_commit(&Commit::AgendaProof(proof(&["a"])))
+            .unwrap_err();
+        // Signed for another height.
+        let mut invalid = verifier.clone();
+        invalid.apply_commit(&delegate(2)).unwrap_err();
+        // The reserved state doesn't follow the changes.
+        let mut tampered = upgraded.clone();
+        tampered.members[0].governance_voting_power = 10;
+        verifier
+            .clone()
+            .apply_commit(&Commit::Transaction(Transaction {
+                diff: Diff::Reserved(
+                    Box::new(tampered),
+                    vec![reserved::ReservedStateChange::BumpVersion(
+                        "0.2.0".to_owned(),
+                    )],
+                ),
+                ..transaction.clone()
+            }))
+            .unwrap_err();
+
+        for commit in &commits[..2] {
+            verifier.apply_commit(commit).unwrap();
+        }
+        assert_eq!(verifier.get_reserved_state().version, "0.1.0");
+        verifier.apply_commit(&commits[2]).unwrap();
+        assert_eq!(verifier.get_reserved_state().version, "0.2.0");
+        verifier.apply_commit(&commits[3]).unwrap();
+        assert_eq!(
+            verifier.get_reserved_state().members[0].consensus_delegations,
+            Some(b.clone())
+        );
+        // Transactions must precede the agenda.
+        verifier.clone().apply_commit(&commits[0]).unwrap_err();
+
+        let header = next_header(&verifier, &commits, &[]);
+        assert_eq!(
+            header.validator_set,
+            vec![(b.clone(), 2), (generate_keypair("c").0, 1)]
+        );
+        // The validator set must follow the delegation.
+        verifier
+            .clone()
+            .apply_commit(&Commit::Block(BlockHeader {
+                validator_set: genesis.genesis_info.header.validator_set.clone(),
+                ..header.clone()
+            }))
+            .unwrap_err();
+        verifier.apply_commit(&Commit::Block(header)).unwrap();
+
+        // The block 1 is finalized by the genesis validator set.
+        verifier
+            .clone()
+            .apply_commit(&Commit::Block(next_header(&verifier, &[], &["a"])))
+            .unwrap_err();
+        let header = next_header(&verifier, &[], &["a", "b", "c"]);
+        verifier
+            .apply_commit(&Commit::Block(header.clone()))
+            .unwrap();
+        assert_eq!(verifier.get_header(), &header);
+    }
+
+    #[test]
+    fn agenda_expiration() {
+        let mut genesis = crate::test_util::genesis(&["a", "b", "c"]);
+        genesis.governance_params.voting_period_blocks = Some(2);
+        genesis.governance_params.voting_period_ms = Some(1000);
+        let mut verifier =
+            CommitSequenceVerifier::new(genesis.genesis_info.header.clone(), genesis).unwrap();
+        let a = generate_keypair("a").0;
+        let transaction = Transaction {
+            author: a.clone(),
+            timestamp: 0,
+            head: "Empty".to_owned(),
+            body: String::new(),
+            diff: Diff::None,
+        };
+        let agenda = Agenda {
+            author: a,
+            timestamp: 100,
+            hash: Agenda::calculate_hash(1, &[transaction.clone()]),
+            expiration_height: Some(1),
+            expiration_timestamp: Some(1100),
+            supersedes: None,
+            amendment_proof: None,
+        };
+        verifier
+            .apply_commit(&Commit::Transaction(transaction.clone()))
+            .unwrap();
+        // Not following the governance parameters.
+        verifier
+            .clone()
+            .apply_commit(&Commit::Agenda(Agenda {
+                expiration_timestamp: None,
+                ..agenda.clone()
+            }))
+            .unwrap_err();
+        let proof = AgendaProof {
+            agenda_hash: agenda.to_hash256(),
+            proof: ["a", "b"]
+                .iter()
+                .map(|name| {
+                    let (public_key, private_key) = generate_keypair(name);
+                    (
+                        public_key,
+                        TypedSignature::sign(&agenda, &private_key).unwrap(),
+                    )
+                })
+                .collect(),
+        };
+        let commits = vec![
+            Commit::Transaction(transaction),
+            Commit::Agenda(agenda),
+            Commit::AgendaProof(proof),
+        ];
+        for commit in &commits[1..] {
+            verifier.apply_commit(commit).unwrap();
+        }
+        let header = next_header(&verifier, &commits, &[]);
+        // Expired by the time of the block.
+        verifier
+            .clone()
+            .apply_commit(&Commit::Block(BlockHeader {
+                timestamp: 1101,
+                ..header.clone()
+            }))
+            .unwrap_err();
+        // Created after the block.
+        verifier
+            .clone()
+            .apply_commit(&Commit::Block(BlockHeader {
+                timestamp: 99,
+                ..header.clone()
+            }))
+            .unwrap_err();
+        verifier
+            .apply_commit(&Commit::Block(BlockHeader {
+                timestamp: 1100,
+                ..header
+            }))
+            .unwrap();
+    }
+
+    #[test]
+    fn superseded_agenda_proof() {
+        let genesis = crate::test_util::genesis(&["a", "b", "c"]);
+        let mut verifier =
+            CommitSequenceVerifier::new(genesis.genesis_info.header.clone(), genesis).unwrap();
+        let (a, a_key) = generate_keypair("a");
+        let agenda = Agenda {
+            author: a.clone(),
+            timestamp: 0,
+            hash: Agenda::calculate_hash(1, &[]),
+            expiration_height: None,
+            expiration_timestamp: None,
+            supersedes: None,
+            amendment_proof: None,
+        };
+        let proof = AgendaProof {
+            agenda_hash: agenda.to_hash256(),
+            proof: ["a", "b", "c"]
+                .iter()
+                .map(|name| {
+                    let (public_key, private_key) = generate_keypair(name);
+                    (
+                        public_key,
+                        TypedSignature::sign(&agenda, &private_key).unwrap(),
+                    )
+                })
+                .collect(),
+        };
+        let amendment = |key: &PrivateKey| {
+            let hash = Hash256::hash("amended");
+            Agenda {
+                timestamp: 1,
+                hash,
+                supersedes: Some(agenda.to_hash256()),
+                amendment_proof: Some(
+                    TypedSignature::sign(&(agenda.to_hash256(), hash), key).unwrap(),
+                ),
+                ..agenda.clone()
+            }
+        };
+        verifier
+            .apply_commit(&Commit::Agenda(agenda.clone()))
+            .unwrap();
+
+        // An amendment not signed by the author doesn't supersede it.
+        let mut unauthenticated = verifier.clone();
+        unauthenticated.set_open_agendas(vec![agenda.clone(), amendment(&generate_keypair("b").1)]);
+        unauthenticated
+            .apply_commit(&Commit::AgendaProof(proof.clone()))
+            .unwrap();
+
+        verifier.set_open_agendas(vec![amendment(&a_key), agenda]);
+        verifier
+            .apply_commit(&Commit::AgendaProof(proof))
+            .unwrap_err();
+    }
+
+    #[test]
+    fn checked_signatures() {
+        let genesis = crate::test_util::genesis(&["a", "b", "c"]);
+        let mut verifier =
+            CommitSequenceVerifier::new(genesis.genesis_info.header.clone(), genesis).unwrap();
+        let agenda = Agenda {
+            author: generate_keypair("a").0,
+            timestamp: 0,
+            hash: Agenda::calculate_hash(1, &[]),
+            expiration_height: None,
+            expiration_timestamp: None,
+            supersedes: None,
+            amendment_proof: None,
+        };
+        // `c` signs another agenda.
+        let other = Agenda {
+            timestamp: 1,
+            ..agenda.clone()
+        };
+        let proof = |names: &[&str]| AgendaProof {
+            agenda_hash: agenda.to_hash256(),
+            proof: names
+                .iter()
+                .map(|name| {
+                    let (public_key, private_key) = generate_keypair(name);
+                    let signed = if *name == "c" { &other } else { &agenda };
+                    (
+                        public_key,
+                        TypedSignature::sign(signed, &private_key).unwrap(),
+                    )
+                })
+                .collect(),
+        };
+        verifier
+            .apply_commit(&Commit::Agenda(agenda.clone()))
+            .unwrap();
+        let forged = Commit::AgendaProof(proof(&["a", "b", "c"]));
+        verifier.clone().apply_commit(&forged).unwrap_err();
+        // It is for `signature_checks()` to catch, which the checked commit is not verified against again.
+        assert!(signature_checks(&forged)
+            .iter()
+            .any(|check| check.verify().is_err()));
+        // The voting power is still verified.
+        verifier
+            .clone()
+            .apply_checked_commit(&Commit::AgendaProof(proof(&["a"])))
+            .unwrap_err();
+        verifier.apply_checked_commit(&forged).unwrap();
+
+        let commits = vec![Commit::Agenda(agenda), forged];
+        verifier
+            .apply_commit(&Commit::Block(next_header(&verifier, &commits, &[])))
+            .unwrap();
+        // `c` signs another header.
+        let mut header = next_header(&verifier, &[], &["a", "b"]);
+        header
+            .prev_block_finalization_proof
+            .push(TypedSignature::sign(&header, &generate_keypair("c").1).unwrap());
+        let forged = Commit::Block(header.clone());
+        verifier.clone().apply_commit(&forged).unwrap_err();
+        verifier
+            .clone()
+            .apply_checked_commit(&Commit::Block(BlockHeader {
+                prev_block_finalization_proof: header.prev_block_finalization_proof[..1].to_vec(),
+                ..header
+            }))
+            .unwrap_err();
+        verifier.apply_checked_commit(&forged).unwrap();
+    }
+
+    #[test]
+    fn shared_signature_cache() {
+        let genesis = crate::test_util::genesis(&["a", "b", "c"]);
+        let agenda = Agenda {
+            author: generate_keypair("a").0,
+            timestamp: 0,
+            hash: Agenda::calculate_hash(1, &[]),
+            expiration_height: Some(12345),
+            expiration_timestamp: None,
+            supersedes: None,
+            amendment_proof: None,
+        };
+        let proof = AgendaProof {
+            agenda_hash: agenda.to_hash256(),
+            proof: ["a", "b", "c"]
+                .iter()
+                .map(|name| {
+                    let (public_key, private_key) = generate_keypair(name);
+                    (
+                        public_key,
+                        TypedSignature::sign(&agenda, &private_key).unwrap(),
+                    )
+                })
+                .collect(),
+        };
+        verify_agenda_proof(&agenda, &proof, &genesis).unwrap();
+        let entries = signature_cache::shared().entries();
+        for (public_key, _) in &proof.proof {
+            assert!(entries
+                .iter()
+                .any(|entry| entry.hash == agenda.to_hash256() && entry.signer == *public_key));
+        }
+    }
+
+    #[test]
+    fn agenda_proof_with_overlapping_key() {
+        // `a` is both a member and a signer of the multisig member `m`.
+        let multisig =
+            MultisigPublicKey::new(vec![generate_keypair("a").0, generate_keypair("d").0], 1)
+                .unwrap();
+        let reserved_state = crate::test_util::reserved_state(vec![
+            crate::test_util::member("a"),
+            crate::test_util::member("b"),
+            crate::test_util::member("c"),
+            Member {
+                multisig_public_key: Some(multisig),
+                ..crate::test_util::member("m")
+            },
+        ]);
+        let agenda = Agenda {
+            author: generate_keypair("a").0,
+            timestamp: 0,
+            hash: Hash256::hash("agenda"),
+            expiration_height: None,
+            expiration_timestamp: None,
+            supersedes: None,
+            amendment_proof: None,
+        };
+        let proof = |names: &[&str]| AgendaProof {
+            agenda_hash: agenda.to_hash256(),
+            proof: names
+                .iter()
+                .map(|name| {
+                    let (public_key, private_key) = generate_keypair(name);
+                    (
+                        public_key,
+                        TypedSignature::sign(&agenda, &private_key).unwrap(),
+                    )
+                })
+                .collect(),
+        };
+        // `a` counts only for itself, so `m` is not satisfied: 2 / 4.
+        verify_agenda_proof(&agenda, &proof(&["a", "b"]), &reserved_state).unwrap_err();
+        // `d` satisfies `m`: 3 / 4.
+        verify_agenda_proof(&agenda, &proof(&["a", "b", "d"]), &reserved_state).unwrap();
     }
 
-    pub fn apply_commit(&mut self, _commit: &Commit) -> Result<(), Error> {
-        unimplemented!()
+    #[test]
+    fn attestation() {
+        let mut genesis = crate::test_util::genesis(&["a", "b", "c"]);
+        genesis.parameters.0.insert(
+            reserved::ChainParameters::ATTESTATION_THRESHOLD.to_string(),
+            reserved::ParameterValue::Integer(2),
+        );
+        genesis.parameters.0.insert(
+            reserved::ChainParameters::ATTESTATION_CHAINS.to_string(),
+            reserved::ParameterValue::Text("ethereum".to_string()),
+        );
+        let mut verifier =
+            CommitSequenceVerifier::new(genesis.genesis_info.header.clone(), genesis).unwrap();
+        let event = ExternalEvent {
+            chain: "ethereum".to_string(),
+            id: "0x1234:0".to_string(),
+            block_height: 100,
+            data: "deposit 100".to_string(),
+        };
+        let attest = |name: &str| {
+            Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Attest(TxAttest {
+                event: event.clone(),
+                proof: TypedSignature::sign(&event, &generate_keypair(name).1).unwrap(),
+            }))
+        };
+        verifier.apply_commit(&attest("a")).unwrap();
+        assert_eq!(
+            verifier.get_reserved_state().external_events.pending.len(),
+            1
+        );
+        verifier.apply_commit(&attest("a")).unwrap_err();
+        verifier.apply_commit(&attest("b")).unwrap();
+        let external_events = &verifier.get_reserved_state().external_events;
+        assert_eq!(external_events.accepted, vec![(event, 1)]);
+        assert!(external_events.pending.is_empty());
     }
 }
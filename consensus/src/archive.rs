@@ -0,0 +1,239 @@
+//! The archive of the consensus votes, for the audit of the heights.
+//!
+//! The consensus DMS keeps only the messages of the height in progress (see `Consensus::prune()`),
+//! so the node copies every received message into the archive as it arrives.
+//! It keeps a JSON file of the messages for each height, sorted by the round and the validator,
+//! from which an operator can reconstruct why a height finalized or stalled,
+//! and produce the evidence of the equivocations later (see `find_equivocations()`).
+use super::*;
+use simperby_network::dms::MessageReader;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often the consensus DMS is copied into the archive.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The archived messages of a height.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedHeight {
+    pub height: BlockHeight,
+    /// Sorted by the round and the validator.
+    pub messages: Vec<SignedConsensusPayload>,
+    /// The equivocations found among the messages.
+    pub evidences: Vec<Evidence>,
+}
+
+/// A directory of `<height>.json` files.
+///
+/// It is written only by the node, but can be read while the node is running.
+#[derive(Debug, Clone)]
+pub struct VoteArchive {
+    directory: PathBuf,
+}
+
+impl VoteArchive {
+    /// Opens the archive in the given directory, creating it if not exists.
+    pub async fn open(directory: impl AsRef<Path>) -> Result<Self, Error> {
+        tokio::fs::create_dir_all(directory.as_ref()).await?;
+        Ok(Self {
+            directory: directory.as_ref().to_owned(),
+        })
+    }
+
+    fn path(&self, height: BlockHeight) -> PathBuf {
+        self.directory.join(format!("{}.json", height))
+    }
+
+    /// Adds the messages, ignoring the duplicates and those with an invalid signature.
+    /// Returns the number of the messages newly added.
+    ///
+    /// The messages must have been verified to be of the validators (see `message::decode()`).
+    pub async fn record(&self, messages: &[SignedConsensusPayload]) -> Result<usize, Error> {
+        let mut by_height: BTreeMap<BlockHeight, Vec<&SignedConsensusPayload>> = BTreeMap::new();
+        for signed in messages {
+            if signed.signature.verify(&signed.payload).is_err() {
+                log::warn!(
+                    "discarded a consensus message of {} with an invalid signature",
+                    signed.signature.signer()
+                );
+                continue;
+            }
+            by_height
+                .entry(signed.payload.height)
+                .or_default()
+                .push(signed);
+        }
+        let mut added = 0;
+        for (height, new) in by_height {
+            let mut archived = self.read(height).await?;
+            let mut keys: HashSet<_> = archived.iter().map(message::dedup_key).collect();
+            let count = archived.len();
+            archived.extend(
+                new.into_iter()
+                    .filter(|signed| keys.insert(message::dedup_key(signed)))
+                    .cloned(),
+            );
+            if archived.len() == count {
+                continue;
+            }
+            added += archived.len() - count;
+            archived.sort_by(|a, b| {
+                (a.payload.round, a.signature.signer())
+                    .cmp(&(b.payload.round, b.signature.signer()))
+            });
+            // Replaces the file at once, so that a reader never sees a partial one.
+            let path = self.path(height);
+            let temporary = path.with_extension("json.tmp");
+            tokio::fs::write(&temporary, serde_json::to_string_pretty(&archived)?).await?;
+            tokio::fs::rename(&temporary, &path).await?;
+        }
+        Ok(added)
+    }
+
+    /// Reads the messages of the height, sorted by the round and the validator.
+    pub async fn read(&self, height: BlockHeight) -> Result<Vec<SignedConsensusPayload>, Error> {
+        match tokio::fs::read_to_string(self.path(height)).await {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads the messages of the given round (or every round) of the height,
+    /// by the given validator (or every validator).
+    pub async fn query(
+        &self,
+        height: BlockHeight,
+        round: Option<ConsensusRound>,
+        validator: Option<&PublicKey>,
+    ) -> Result<Vec<SignedConsensusPayload>, Error> {
+        Ok(self
+            .read(height)
+            .await?
+            .into_iter()
+            .filter(|signed| round.map_or(true, |round| signed.payload.round == round))
+            .filter(|signed| validator.map_or(true, |v| signed.signature.signer() == v))
+            .collect())
+    }
+
+    /// Returns the archived heights in ascending order.
+    pub async fn heights(&self) -> Result<Vec<BlockHeight>, Error> {
+        let mut heights = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(height) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|height| height.parse().ok())
+            {
+                heights.push(height);
+            }
+        }
+        heights.sort_unstable();
+        Ok(heights)
+    }
+
+    /// Exports the archived heights in the range, with the equivocations found in each.
+    pub async fn export(
+        &self,
+        from: BlockHeight,
+        to: BlockHeight,
+    ) -> Result<Vec<ArchivedHeight>, Error> {
+        let mut result = Vec::new();
+        for height in self.heights().await? {
+            if height < from || height > to {
+                continue;
+            }
+            let messages = self.read(height).await?;
+            result.push(ArchivedHeight {
+                height,
+                evidences: find_equivocations(&messages),
+                messages,
+            });
+        }
+        Ok(result)
+    }
+}
+
+/// Copies the messages of the consensus DMS into the archive indefinitely,
+/// every `POLL_INTERVAL` of the clock.
+pub async fn observe<S: MessageStore>(
+    reader: MessageReader<S>,
+    archive: VoteArchive,
+    clock: Arc<dyn clock::Clock>,
+) -> Result<(), Error> {
+    loop {
+        let messages: Vec<_> = reader
+            .read_messages()
+            .await?
+            .iter()
+            .filter_map(|message| serde_json::from_str(message.data()).ok())
+            .collect();
+        let added = archive.record(&messages).await?;
+        if added > 0 {
+            log::debug!("archived {} consensus messages", added);
+        }
+        clock.sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_common::{
+        crypto::generate_keypair, ConsensusMessageKind, ConsensusPayload, TypedSignature,
+    };
+
+    fn vote(name: &str, round: ConsensusRound, block: &str) -> SignedConsensusPayload {
+        let payload = ConsensusPayload {
+            height: 3,
+            round,
+            kind: ConsensusMessageKind::PreVote,
+            block_hash: Some(Hash256::hash(block)),
+        };
+        SignedConsensusPayload {
+            signature: TypedSignature::sign(&payload, &generate_keypair(name).1).unwrap(),
+            payload,
+        }
+    }
+
+    #[tokio::test]
+    async fn record_and_export() {
+        let directory = tempfile::tempdir().unwrap();
+        let archive = VoteArchive::open(directory.path()).await.unwrap();
+        let votes = vec![vote("b", 1, "x"), vote("a", 0, "x"), vote("a", 1, "x")];
+        assert_eq!(archive.record(&votes).await.unwrap(), 3);
+        assert_eq!(archive.record(&votes).await.unwrap(), 0);
+
+        let mut forged = vote("c", 0, "x");
+        forged.payload.round = 2;
+        assert_eq!(
+            archive.record(&[vote("a", 1, "y"), forged]).await.unwrap(),
+            1
+        );
+        assert_eq!(archive.heights().await.unwrap(), vec![3]);
+
+        let rounds: Vec<_> = archive
+            .read(3)
+            .await
+            .unwrap()
+            .iter()
+            .map(|signed| signed.payload.round)
+            .collect();
+        assert_eq!(rounds, vec![0, 1, 1, 1]);
+        let a = generate_keypair("a").0;
+        assert_eq!(archive.query(3, Some(1), Some(&a)).await.unwrap().len(), 2);
+        assert_eq!(archive.query(3, None, None).await.unwrap().len(), 4);
+        assert!(archive.read(4).await.unwrap().is_empty());
+
+        let exported = archive.export(0, 10).await.unwrap();
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].messages.len(), 4);
+        // `a` pre-voted both `x` and `y` in round 1.
+        assert_eq!(exported[0].evidences.len(), 1);
+        assert!(archive.export(4, 10).await.unwrap().is_empty());
+    }
+}
@@ -1,14 +1,23 @@
+pub mod archive;
+pub mod message;
+pub mod telemetry;
+
 use serde::{Deserialize, Serialize};
 use simperby_common::{
+    bls,
     crypto::{Hash256, PublicKey},
-    BlockHeight, ConsensusRound, Timestamp, VotingPower,
+    reserved, signature_cache, verify, AggregateFinalizationProof, BlockHeader, BlockHeight,
+    ConsensusRound, Evidence, ExtendedFinalizationProof, FinalizationProof, SignedConsensusPayload,
+    SignedVoteExtension, Timestamp, ToHash256, TypedSignature, VoteExtension, VotingPower,
 };
 use simperby_network::{
     dms::DistributedMessageSet as DMS,
-    primitives::{GossipNetwork, Storage},
+    primitives::{GossipNetwork, MessageStore},
+    signer::Signer,
     *,
 };
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 pub type Error = anyhow::Error;
 
@@ -27,13 +36,257 @@ pub enum ProgressResult {
     NilPreVoted(ConsensusRound, Timestamp),
     NilPreComitted(ConsensusRound, Timestamp),
     Finalized(Timestamp),
+    /// The block of the height in progress was finalized by others (see `verify_catch_up()`),
+    /// so the consensus skipped to the next height.
+    CaughtUp(BlockHeight),
+    /// A misbehavior of a validator was detected from the received messages.
+    ///
+    /// It should be submitted to the repository as a report transaction.
+    EvidenceFound(Evidence),
+}
+
+/// Finds all the equivocations among the received consensus messages.
+///
+/// The messages must have been verified already.
+pub fn find_equivocations(messages: &[SignedConsensusPayload]) -> Vec<Evidence> {
+    let mut evidences = Vec::new();
+    for (i, first) in messages.iter().enumerate() {
+        for second in &messages[i + 1..] {
+            if let Some(evidence) = verify::find_equivocation(first, second) {
+                evidences.push(evidence);
+            }
+        }
+    }
+    evidences
+}
+
+/// Aggregates the BLS pre-commits into a constant-size finalization proof.
+///
+/// `validator_set` is the one for the height of the header.
+/// Returns `None` if any validator doesn't have a BLS key, in which case
+/// the ordinary `FinalizationProof` must be used.
+pub fn aggregate_finalization_proof(
+    header: &BlockHeader,
+    validator_set: &[(PublicKey, VotingPower)],
+    reserved_state: &reserved::ReservedState,
+    signatures: &[(PublicKey, bls::BlsSignature)],
+) -> Result<Option<AggregateFinalizationProof>, Error> {
+    let all_have_bls_keys = validator_set.iter().all(|(validator, _)| {
+        reserved_state
+            .members
+            .iter()
+            .any(|member| &member.public_key == validator && member.bls_public_key.is_some())
+    });
+    if !all_have_bls_keys {
+        return Ok(None);
+    }
+    let mut signers = vec![false; validator_set.len()];
+    let mut selected = Vec::new();
+    for (index, (validator, _)) in validator_set.iter().enumerate() {
+        if let Some((_, signature)) = signatures.iter().find(|(signer, _)| signer == validator) {
+            signers[index] = true;
+            selected.push(signature.clone());
+        }
+    }
+    let proof = AggregateFinalizationProof {
+        signers,
+        signature: bls::BlsSignature::aggregate(&selected)?,
+    };
+    verify::verify_aggregate_finalization_proof(header, &proof, validator_set, reserved_state)?;
+    Ok(Some(proof))
+}
+
+/// Converts the consensus parameters of the reserved state into those of `vetomint`,
+/// applying the adaptive timeouts if enabled.
+pub fn vetomint_params(
+    params: &reserved::ConsensusParams,
+    adaptive_timeout: &vetomint::AdaptiveTimeout,
+) -> vetomint::ConsensusParams {
+    let result = vetomint::ConsensusParams {
+        timeout_propose_ms: params.timeout_propose_ms,
+        timeout_prevote_ms: params.timeout_prevote_ms,
+        timeout_precommit_ms: params.timeout_precommit_ms,
+        timeout_increase_percent: params.timeout_increase_percent,
+        repeat_round_for_first_leader: params.repeat_round_for_first_leader as usize,
+        proposer_selection: match params.proposer_selection {
+            reserved::ProposerSelection::RoundRobin => vetomint::ProposerSelection::RoundRobin,
+            reserved::ProposerSelection::WeightedRoundRobin => {
+                vetomint::ProposerSelection::WeightedRoundRobin
+            }
+            reserved::ProposerSelection::StakeProportional => {
+                vetomint::ProposerSelection::StakeProportional
+            }
+        },
+    };
+    if params.adaptive_timeout {
+        adaptive_timeout.adjust(&result)
+    } else {
+        result
+    }
 }
 
-pub struct Consensus<N: GossipNetwork, S: Storage> {
+/// Verifies a proposed block of the height in progress, before pre-voting for it.
+///
+/// Its timestamp must be within `max_clock_skew_ms` (`ChainParameters::max_clock_skew_ms()`)
+/// of `median_clock`, the median of the clocks of the validators as estimated by this node
+/// (see `verify::verify_timestamp()`). A validator pre-votes nil for a proposal violating it,
+/// so that no proposer can manipulate the timestamps.
+pub fn verify_proposal(
+    last_header: &BlockHeader,
+    header: &BlockHeader,
+    median_clock: Timestamp,
+    max_clock_skew_ms: u64,
+) -> Result<(), Error> {
+    verify::verify_header_to_header(last_header, header)?;
+    verify::verify_timestamp(last_header, header, median_clock, max_clock_skew_ms)?;
+    Ok(())
+}
+
+/// Verifies a block of the height in progress which was finalized without this node,
+/// received while running the consensus.
+///
+/// If it's valid, this node has fallen behind; instead of going through the rounds, it should
+/// sync the repository to the block (see `DistributedRepository::finalize()`)
+/// and resume the consensus from the next height.
+pub fn verify_catch_up(
+    height_in_progress: BlockHeight,
+    last_header: &BlockHeader,
+    header: &BlockHeader,
+    proof: &FinalizationProof,
+) -> Result<(), Error> {
+    if header.height != height_in_progress {
+        return Err(anyhow::anyhow!(
+            "the height in progress is {}, but got a block of {}",
+            height_in_progress,
+            header.height
+        ));
+    }
+    verify::verify_header_to_header(last_header, header)?;
+    verify::verify_finalization_proof(header, proof, &last_header.validator_set)?;
+    Ok(())
+}
+
+/// The application-defined handling of the vote extensions.
+pub trait VoteExtensionHandler: Send + Sync {
+    /// Makes the extension to attach to the precommit for the given block.
+    fn extend(&self, header: &BlockHeader) -> VoteExtension;
+
+    /// Checks the extension of another validator; the invalid ones are not aggregated.
+    fn verify(&self, validator: &PublicKey, extension: &VoteExtension) -> Result<(), String>;
+}
+
+/// Signs the vote extension for the precommit on the given block.
+pub async fn sign_vote_extension(
+    header: &BlockHeader,
+    extension: VoteExtension,
+    signer: &dyn Signer,
+) -> Result<SignedVoteExtension, Error> {
+    let data = (header.to_hash256(), extension.clone());
+    let signature = signer.sign(data.to_hash256()).await?;
+    Ok(SignedVoteExtension {
+        extension,
+        signature: TypedSignature::new(signature, signer.public_key()),
+    })
+}
+
+/// Aggregates the vote extensions received with the precommits into the finalization proof.
+///
+/// The extensions of the validators who didn't precommit, the oversized ones and
+/// the ones rejected by the handler are left out.
+pub fn aggregate_vote_extensions(
+    header: &BlockHeader,
+    proof: FinalizationProof,
+    extensions: &[SignedVoteExtension],
+    params: &reserved::ConsensusParams,
+    handler: &dyn VoteExtensionHandler,
+) -> ExtendedFinalizationProof {
+    let block_hash = header.to_hash256();
+    let mut aggregated: Vec<SignedVoteExtension> = Vec::new();
+    for signed in extensions {
+        let signer = signed.signature.signer();
+        let valid = signed.extension.data.len() as u64 <= params.max_vote_extension_size
+            && proof.iter().any(|s| s.signer() == signer)
+            && !aggregated.iter().any(|x| x.signature.signer() == signer)
+            && signature_cache::shared()
+                .verify_typed(&signed.signature, &(block_hash, signed.extension.clone()))
+                .is_ok()
+            && handler.verify(signer, &signed.extension).is_ok();
+        if valid {
+            aggregated.push(signed.clone());
+        } else {
+            log::warn!("discarded an invalid vote extension of {}", signer);
+        }
+    }
+    ExtendedFinalizationProof {
+        proof,
+        extensions: aggregated,
+    }
+}
+
+/// Derives the seed of the proposer selection (`vetomint::HeightInfo::seed`)
+/// from the hash of the previous block.
+pub fn proposer_seed(previous_hash: &Hash256) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&previous_hash.hash[0..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// An event from the consensus state machine, for observability.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsensusEvent {
+    pub height: BlockHeight,
+    pub event: vetomint::trace::TraceEvent,
+}
+
+pub struct Consensus<N: GossipNetwork, S: MessageStore> {
     pub dms: DMS<N, S>,
+    events: tokio::sync::broadcast::Sender<ConsensusEvent>,
+    /// The source of the timestamps of the progresses, from which the timeouts are measured.
+    clock: Arc<dyn clock::Clock>,
 }
 
-impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
+impl<N: GossipNetwork, S: MessageStore> Consensus<N, S> {
+    /// Sets the clock of the consensus and its DMS, which is the system clock by default.
+    ///
+    /// The timeouts of the rounds are measured by it, so a `clock::SimulatedClock`
+    /// makes them testable without waiting.
+    pub fn set_clock(&mut self, clock: Arc<dyn clock::Clock>) {
+        self.dms.set_clock(Arc::clone(&clock));
+        self.clock = clock;
+    }
+
+    /// Returns the current time of the clock of the consensus.
+    pub fn now(&self) -> Timestamp {
+        self.clock.now()
+    }
+
+    /// Subscribes to the events of the state machine (see `vetomint::trace`).
+    ///
+    /// The metrics of the events are recorded regardless of the subscribers (see `telemetry`).
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<ConsensusEvent> {
+        self.events.subscribe()
+    }
+
+    /// Records and publishes the trace of a progress of the state machine
+    /// (see `vetomint::ConsensusState::progress_traced()`).
+    pub fn publish_trace(&self, height: BlockHeight, trace: Vec<vetomint::trace::TraceEvent>) {
+        let _span = tracing::debug_span!("consensus", height).entered();
+        for event in trace {
+            let round = match &event {
+                vetomint::trace::TraceEvent::RoundStarted { round }
+                | vetomint::trace::TraceEvent::ProposalReceived { round, .. }
+                | vetomint::trace::TraceEvent::Broadcasted { round, .. }
+                | vetomint::trace::TraceEvent::PrecommitQuorum { round, .. }
+                | vetomint::trace::TraceEvent::TimeoutFired { round, .. } => Some(*round),
+                _ => None,
+            };
+            tracing::debug!(round = ?round, event = ?event, "consensus event");
+            telemetry::record(height, &event);
+            // It fails only if there is no subscriber, which is fine.
+            let _ = self.events.send(ConsensusEvent { height, event });
+        }
+    }
+
     pub async fn create(
         _dms: DMS<N, S>,
         _height: BlockHeight,
@@ -54,6 +307,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
         &mut self,
         _network_config: NetworkConfig,
         _known_peers: &[Peer],
+        _signer: &dyn Signer,
         _block_hash: Hash256,
     ) -> Result<(), Error> {
         unimplemented!()
@@ -67,6 +321,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
         &mut self,
         _network_config: NetworkConfig,
         _known_peers: &[Peer],
+        _signer: &dyn Signer,
         _round: ConsensusRound,
     ) -> Result<(), Error> {
         unimplemented!()
@@ -79,17 +334,32 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
     /// 2. broadcast a pre-vote.
     /// 3. broadcast a pre-commit.
     /// 4. finalize the block and advance the height.
+    /// 5. report the equivocations found in the received messages.
     ///
     /// For the case 4, it will clear the storage and will leave the finalization proof
     /// of the previous (just finalized) block.
+    ///
+    /// The votes are signed by the given signer.
     pub async fn progress(
         &mut self,
         _network_config: NetworkConfig,
         _known_peers: &[Peer],
+        _signer: &dyn Signer,
     ) -> Result<Vec<ProgressResult>, Error> {
         unimplemented!()
     }
 
+    /// Drops the messages for the heights other than the given one (i.e., the current one),
+    /// such as the late votes for the finalized heights. Returns the number of them.
+    pub async fn prune(&mut self, height: BlockHeight) -> Result<usize, Error> {
+        self.dms
+            .retain(|message| {
+                serde_json::from_str::<SignedConsensusPayload>(message.data())
+                    .map_or(false, |signed| signed.payload.height == height)
+            })
+            .await
+    }
+
     pub async fn fetch(
         &mut self,
         _network_config: NetworkConfig,
@@ -106,6 +376,7 @@ impl<N: GossipNetwork, S: Storage> Consensus<N, S> {
         self,
         _network_config: NetworkConfig,
         _peers: SharedKnownPeers,
+        _signer: std::sync::Arc<dyn Signer>,
     ) -> Result<
         (
             tokio::sync::mpsc::Receiver<ProgressResult>,
@@ -0,0 +1,204 @@
+//! The encoding of the consensus messages as DMS messages.
+//!
+//! Each DMS message carries a single `SignedConsensusPayload` in JSON, signed by the validator key.
+//! (The DMS message itself is signed by the network key of the node that added it.)
+//!
+//! - The payload binds the height and the round, so a message can't be replayed
+//! in another height; the messages of other heights are rejected on receipt.
+//! - The same message may arrive more than once through the gossip; `collect()` deduplicates them
+//! by `dedup_key()`. Conflicting messages are not duplicates; they become evidence.
+use super::*;
+use simperby_common::{ConsensusMessageKind, ConsensusPayload};
+use simperby_network::dms::Message;
+
+/// Signs the payload with the validator key and encodes it as a DMS message.
+pub async fn encode(
+    payload: ConsensusPayload,
+    signer: &dyn Signer,
+    network_config: &NetworkConfig,
+) -> Result<Message, Error> {
+    let signature = TypedSignature::new(
+        signer.sign(payload.to_hash256()).await?,
+        signer.public_key(),
+    );
+    let data = serde_json::to_string(&SignedConsensusPayload { payload, signature })?;
+    let message = Message::new(
+        data.clone(),
+        TypedSignature::sign(&data, &network_config.private_key)?,
+    )?;
+    Ok(message)
+}
+
+/// Decodes and verifies a DMS message received for the given height.
+///
+/// The signer must be in `validator_set`, the one for the height.
+pub fn decode(
+    message: &Message,
+    height: BlockHeight,
+    validator_set: &[(PublicKey, VotingPower)],
+) -> Result<SignedConsensusPayload, Error> {
+    let signed: SignedConsensusPayload = serde_json::from_str(message.data())?;
+    if signed.payload.height != height {
+        return Err(anyhow::anyhow!(
+            "the message is for height {}, not {}",
+            signed.payload.height,
+            height
+        ));
+    }
+    let signer = signed.signature.signer();
+    if !validator_set
+        .iter()
+        .any(|(validator, _)| validator == signer)
+    {
+        return Err(anyhow::anyhow!("{} is not a validator", signer));
+    }
+    signature_cache::shared().verify_typed(&signed.signature, &signed.payload)?;
+    Ok(signed)
+}
+
+/// The key that identifies the same message, regardless of how many times it was delivered.
+pub fn dedup_key(signed: &SignedConsensusPayload) -> Hash256 {
+    simperby_common::canonical::to_hash256(&(signed.signature.signer(), &signed.payload))
+}
+
+/// Decodes all the messages of the DMS for the given height, discarding the invalid ones
+/// and the duplicates. Returns the messages with the equivocations found among them.
+pub fn collect(
+    messages: &[Message],
+    height: BlockHeight,
+    validator_set: &[(PublicKey, VotingPower)],
+) -> (Vec<SignedConsensusPayload>, Vec<Evidence>) {
+    let mut keys = HashSet::new();
+    let mut result = Vec::new();
+    for message in messages {
+        match decode(message, height, validator_set) {
+            Ok(signed) => {
+                if keys.insert(dedup_key(&signed)) {
+                    result.push(signed);
+                }
+            }
+            Err(e) => log::warn!("discarded an invalid consensus message: {}", e),
+        }
+    }
+    let evidences = find_equivocations(&result);
+    (result, evidences)
+}
+
+/// Converts the message into the event for `vetomint`.
+///
+/// `block_identifier` maps a block hash to its identifier of the height;
+/// returns `None` if the block is unknown.
+pub fn into_event(
+    signed: &SignedConsensusPayload,
+    validator_set: &[(PublicKey, VotingPower)],
+    block_identifier: impl Fn(&Hash256) -> Option<vetomint::BlockIdentifier>,
+    time: vetomint::Timestamp,
+) -> Option<vetomint::ConsensusEvent> {
+    let signer = validator_set
+        .iter()
+        .position(|(validator, _)| validator == signed.signature.signer())?;
+    let round = signed.payload.round as vetomint::Round;
+    let proposal = match &signed.payload.block_hash {
+        Some(hash) => Some(block_identifier(hash)?),
+        None => None,
+    };
+    Some(match (signed.payload.kind, proposal) {
+        (ConsensusMessageKind::Proposal, Some(proposal)) => {
+            vetomint::ConsensusEvent::BlockProposalReceived {
+                proposal,
+                proposer: signer,
+                round,
+                time,
+            }
+        }
+        (ConsensusMessageKind::Proposal, None) => return None,
+        (ConsensusMessageKind::PreVote, Some(proposal)) => vetomint::ConsensusEvent::Prevote {
+            proposal,
+            signer,
+            round,
+            time,
+        },
+        (ConsensusMessageKind::PreVote, None) => vetomint::ConsensusEvent::NilPrevote {
+            signer,
+            round,
+            time,
+        },
+        (ConsensusMessageKind::PreCommit, Some(proposal)) => vetomint::ConsensusEvent::Precommit {
+            proposal,
+            signer,
+            round,
+            time,
+        },
+        (ConsensusMessageKind::PreCommit, None) => vetomint::ConsensusEvent::NilPrecommit {
+            signer,
+            round,
+            time,
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simperby_common::crypto::generate_keypair;
+    use simperby_network::signer::LocalSigner;
+
+    fn network_config(name: &str) -> NetworkConfig {
+        let (public_key, private_key) = generate_keypair(format!("network-{}", name));
+        NetworkConfig {
+            network_id: "test".to_string(),
+            chain_id: Default::default(),
+            port: None,
+            members: Vec::new(),
+            public_key,
+            private_key,
+        }
+    }
+
+    fn payload(height: BlockHeight, block_hash: Option<Hash256>) -> ConsensusPayload {
+        ConsensusPayload {
+            height,
+            round: 0,
+            kind: ConsensusMessageKind::PreVote,
+            block_hash,
+        }
+    }
+
+    #[tokio::test]
+    async fn encode_and_collect() {
+        let validator_set = vec![(generate_keypair("a").0, 1), (generate_keypair("b").0, 1)];
+        let a = LocalSigner::new(generate_keypair("a").1);
+        let c = LocalSigner::new(generate_keypair("c").1);
+        let config = network_config("a");
+        let block = Hash256::hash("block");
+
+        let vote = encode(payload(1, Some(block)), &a, &config).await.unwrap();
+        let conflicting = encode(payload(1, None), &a, &config).await.unwrap();
+        let old = encode(payload(0, Some(block)), &a, &config).await.unwrap();
+        let not_validator = encode(payload(1, Some(block)), &c, &config).await.unwrap();
+
+        decode(&vote, 1, &validator_set).unwrap();
+        decode(&old, 1, &validator_set).unwrap_err();
+        decode(&not_validator, 1, &validator_set).unwrap_err();
+
+        let (messages, evidences) = collect(
+            &[vote.clone(), vote.clone(), old, not_validator, conflicting],
+            1,
+            &validator_set,
+        );
+        assert_eq!(messages.len(), 2);
+        assert_eq!(evidences.len(), 1);
+
+        let event = into_event(&messages[0], &validator_set, |_| Some(7), 10).unwrap();
+        assert_eq!(
+            event,
+            vetomint::ConsensusEvent::Prevote {
+                proposal: 7,
+                signer: 0,
+                round: 0,
+                time: 10,
+            }
+        );
+        assert!(into_event(&messages[0], &validator_set, |_| None, 10).is_none());
+    }
+}
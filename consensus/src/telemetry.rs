@@ -0,0 +1,48 @@
+//! Metrics of the consensus, reported through the `metrics` facade.
+//!
+//! This crate only records them; installing an exporter is up to the node.
+use vetomint::trace::{Step, TraceEvent};
+
+/// A gauge of the height in progress.
+pub const HEIGHT: &str = "simperby_consensus_height";
+/// A gauge of the round in progress.
+pub const ROUND: &str = "simperby_consensus_round";
+/// A counter of the rounds started.
+pub const ROUNDS_STARTED: &str = "simperby_consensus_rounds_started_total";
+/// A counter of the proposals received.
+pub const PROPOSALS_RECEIVED: &str = "simperby_consensus_proposals_received_total";
+/// A counter of the proposals and votes broadcasted, labeled by `step`.
+pub const BROADCASTS: &str = "simperby_consensus_broadcasts_total";
+/// A counter of the precommit quorums reached.
+pub const PRECOMMIT_QUORUMS: &str = "simperby_consensus_precommit_quorums_total";
+/// A counter of the finalized heights.
+pub const HEIGHTS_FINALIZED: &str = "simperby_consensus_heights_finalized_total";
+/// A counter of the timeouts fired, labeled by `step`.
+pub const TIMEOUTS: &str = "simperby_consensus_timeouts_total";
+
+fn step_label(step: &Step) -> &'static str {
+    match step {
+        Step::Propose => "propose",
+        Step::Prevote => "prevote",
+        Step::Precommit => "precommit",
+    }
+}
+
+pub(crate) fn record(height: simperby_common::BlockHeight, event: &TraceEvent) {
+    metrics::gauge!(HEIGHT, height as f64);
+    match event {
+        TraceEvent::RoundStarted { round } => {
+            metrics::gauge!(ROUND, *round as f64);
+            metrics::increment_counter!(ROUNDS_STARTED);
+        }
+        TraceEvent::ProposalReceived { .. } => metrics::increment_counter!(PROPOSALS_RECEIVED),
+        TraceEvent::Broadcasted { step, .. } => {
+            metrics::increment_counter!(BROADCASTS, "step" => step_label(step))
+        }
+        TraceEvent::PrecommitQuorum { .. } => metrics::increment_counter!(PRECOMMIT_QUORUMS),
+        TraceEvent::HeightFinalized { .. } => metrics::increment_counter!(HEIGHTS_FINALIZED),
+        TraceEvent::TimeoutFired { step, .. } => {
+            metrics::increment_counter!(TIMEOUTS, "step" => step_label(step))
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Parses an arbitrary commit message as a Simperby commit, which must fail without a panic.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use simperby_common::*;
+use simperby_repository::format::from_semantic_commit;
+use simperby_repository::raw::SemanticCommit;
+
+fuzz_target!(|data: &[u8]| {
+    let message = String::from_utf8_lossy(data);
+    let (title, body) = message.split_once('\n').unwrap_or((&message, ""));
+    let last_header = BlockHeader {
+        author: generate_keypair("fuzz").0,
+        prev_block_finalization_proof: Vec::new(),
+        previous_hash: Hash256::zero(),
+        height: 0,
+        timestamp: 0,
+        commit_hash: Hash256::zero(),
+        tx_merkle_root: Hash256::zero(),
+        chat_merkle_root: Hash256::zero(),
+        repository_merkle_root: Hash256::zero(),
+        validator_set: Vec::new(),
+        version: "0.0.0".to_owned(),
+    };
+    let _ = from_semantic_commit(
+        SemanticCommit {
+            title: title.to_owned(),
+            body: body.to_owned(),
+            reserved_state: None,
+        },
+        &last_header,
+    );
+});
@@ -0,0 +1,12 @@
+//! Decodes an arbitrary reserved state, and uses it if valid; neither may panic.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use simperby_common::reserved::ReservedState;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(state) = ReservedState::from_json(data) {
+        let _ = state.create_validator_set();
+        let _ = state.governance_voting_powers();
+        let _ = state.parameters.max_clock_skew_ms();
+    }
+});
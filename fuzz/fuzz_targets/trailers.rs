@@ -0,0 +1,12 @@
+//! Splits the trailers off an arbitrary body, which must reassemble the body.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use simperby_repository::format::parse_trailers;
+
+fuzz_target!(|body: &str| {
+    let (text, trailers) = parse_trailers(body);
+    assert!(body.starts_with(text));
+    for (key, value) in trailers {
+        assert!(body.contains(&format!("{}: {}", key, value)));
+    }
+});
@@ -2,7 +2,8 @@ use serde::{Deserialize, Serialize};
 use simperby_common::*;
 use simperby_network::{
     dms::{DistributedMessageSet as DMS, Message},
-    primitives::{GossipNetwork, Storage},
+    primitives::{GossipNetwork, MessageStore},
+    signer::Signer,
     NetworkConfig, Peer, SharedKnownPeers,
 };
 use std::collections::{HashMap, HashSet};
@@ -16,18 +17,70 @@ pub struct GovernanceState {
     pub height: BlockHeight,
 }
 
+pub mod message;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct Vote {
+pub struct Vote {
     pub agenda_hash: Hash256,
     pub voter: PublicKey,
+    /// The signature on the agenda hash, or on `message::veto_hash()` of it for a veto.
     pub signature: Signature,
+    #[serde(default)]
+    pub veto: bool,
 }
 
-pub struct Governance<N: GossipNetwork, S: Storage> {
+pub struct Governance<N: GossipNetwork, S: MessageStore> {
     pub dms: DMS<N, S>,
 }
 
-impl<N: GossipNetwork, S: Storage> Governance<N, S> {
+/// Sums up the effective governance voting power of the members who approved,
+/// given the voters of an agenda.
+///
+/// A multisig member is counted once, only if its signers among the voters satisfy the threshold;
+/// a key that is also of a member counts only for that member
+/// (see `ReservedState::approved_governance_voting_power()`).
+/// The power delegated to a member follows its vote; the vote of a member who delegated is ignored.
+pub fn tally(reserved_state: &reserved::ReservedState, voters: &HashSet<PublicKey>) -> VotingPower {
+    reserved_state.approved_governance_voting_power(voters)
+}
+
+/// Returns whether the agenda is approved by the voters, under the thresholds of the reserved state.
+pub fn is_approved(reserved_state: &reserved::ReservedState, voters: &HashSet<PublicKey>) -> bool {
+    reserved_state
+        .governance_params
+        .approval_threshold
+        .is_exceeded_by(
+            tally(reserved_state, voters),
+            total_voting_power(reserved_state),
+        )
+}
+
+/// Returns whether the agenda is rejected by the vetoers, under the thresholds of the reserved state.
+pub fn is_vetoed(reserved_state: &reserved::ReservedState, vetoers: &HashSet<PublicKey>) -> bool {
+    reserved_state
+        .governance_params
+        .veto_threshold
+        .is_exceeded_by(
+            tally(reserved_state, vetoers),
+            total_voting_power(reserved_state),
+        )
+}
+
+fn total_voting_power(reserved_state: &reserved::ReservedState) -> VotingPower {
+    reserved_state
+        .governance_voting_powers()
+        .into_iter()
+        .map(|(_, power)| power)
+        .sum()
+}
+
+fn get_timestamp() -> Timestamp {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+    since_the_epoch.as_millis() as Timestamp
+}
+
+impl<N: GossipNetwork, S: MessageStore> Governance<N, S> {
     pub async fn create(_dms: DMS<N, S>, _height: BlockHeight) -> Result<(), Error> {
         unimplemented!()
     }
@@ -40,24 +93,38 @@ impl<N: GossipNetwork, S: Storage> Governance<N, S> {
         unimplemented!()
     }
 
+    /// Votes for the given agenda.
+    ///
+    /// It refuses to vote if the agenda has expired at the given last finalized height.
     pub async fn vote(
         &mut self,
         network_config: &NetworkConfig,
         known_peers: &[Peer],
-        agenda_hash: Hash256,
-        private_key: &PrivateKey,
+        agenda: &Agenda,
+        last_finalized_height: BlockHeight,
+        signer: &dyn Signer,
     ) -> Result<(), Error> {
-        let data = serde_json::to_string(&Vote {
-            agenda_hash,
-            voter: private_key.public_key(),
-            signature: Signature::sign(agenda_hash, private_key)?,
-        })
-        .unwrap();
-        let message = Message::new(
-            data.clone(),
-            TypedSignature::sign(&data, &network_config.private_key)?,
-        )?;
+        if agenda.is_expired(last_finalized_height, get_timestamp()) {
+            return Err(anyhow::anyhow!("the agenda has expired"));
+        }
+        let message = message::encode(agenda.to_hash256(), false, signer, network_config).await?;
+        self.dms
+            .add_message(network_config, known_peers, message)
+            .await?;
+        Ok(())
+    }
 
+    /// Vetoes the given agenda.
+    ///
+    /// Note that a key that both votes for and vetoes an agenda is counted as neither.
+    pub async fn veto(
+        &mut self,
+        network_config: &NetworkConfig,
+        known_peers: &[Peer],
+        agenda: &Agenda,
+        signer: &dyn Signer,
+    ) -> Result<(), Error> {
+        let message = message::encode(agenda.to_hash256(), true, signer, network_config).await?;
         self.dms
             .add_message(network_config, known_peers, message)
             .await?;
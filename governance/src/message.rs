@@ -0,0 +1,331 @@
+//! The encoding of the governance votes as DMS messages, and the offline tally of them.
+//!
+//! Each DMS message carries a single `Vote` in JSON, signed by a governance key:
+//! the key of a member, or of a signer of a multisig member.
+//!
+//! - An approval signs the agenda hash itself, so the approvals can be put into
+//! the `AgendaProof` as they are. A veto signs `veto_hash()` of the agenda hash instead,
+//! so that it can never be taken as an approval.
+//! - If a key both approves and vetoes the same agenda, neither of them counts.
+//! This makes the tally independent of the order in which the votes arrive.
+use super::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The hash that a veto signs, for the given agenda hash.
+pub fn veto_hash(agenda_hash: Hash256) -> Hash256 {
+    agenda_hash.aggregate(&Hash256::hash("veto"))
+}
+
+/// Signs the vote with the governance key and encodes it as a DMS message.
+pub async fn encode(
+    agenda_hash: Hash256,
+    veto: bool,
+    signer: &dyn Signer,
+    network_config: &NetworkConfig,
+) -> Result<Message, Error> {
+    let signed_hash = if veto {
+        veto_hash(agenda_hash)
+    } else {
+        agenda_hash
+    };
+    let data = serde_json::to_string(&Vote {
+        agenda_hash,
+        voter: signer.public_key(),
+        signature: signer.sign(signed_hash).await?,
+        veto,
+    })?;
+    let message = Message::new(
+        data.clone(),
+        TypedSignature::sign(&data, &network_config.private_key)?,
+    )?;
+    Ok(message)
+}
+
+/// Returns whether the key may vote: the key of a member, or of a signer of a multisig member.
+pub fn is_governance_key(reserved_state: &reserved::ReservedState, key: &PublicKey) -> bool {
+    reserved_state.members.iter().any(|member| {
+        member.public_key == *key
+            || member
+                .multisig_public_key
+                .as_ref()
+                .map_or(false, |multisig| multisig.signers.contains(key))
+    })
+}
+
+/// Decodes and verifies a DMS message carrying a vote.
+pub fn decode(message: &Message, reserved_state: &reserved::ReservedState) -> Result<Vote, Error> {
+    let vote: Vote = serde_json::from_str(message.data())?;
+    if !is_governance_key(reserved_state, &vote.voter) {
+        return Err(anyhow::anyhow!("{} is not a governance key", vote.voter));
+    }
+    let signed_hash = if vote.veto {
+        veto_hash(vote.agenda_hash)
+    } else {
+        vote.agenda_hash
+    };
+    signature_cache::shared().verify(signed_hash, &vote.signature, &vote.voter)?;
+    Ok(vote)
+}
+
+/// The votes collected from the DMS, by agenda hash.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VoteTally {
+    pub approvals: BTreeMap<Hash256, BTreeMap<PublicKey, Signature>>,
+    pub vetoes: BTreeMap<Hash256, BTreeSet<PublicKey>>,
+    /// The keys that both approved and vetoed the agenda; counted as neither.
+    pub contradictory: BTreeMap<Hash256, BTreeSet<PublicKey>>,
+}
+
+impl VoteTally {
+    pub fn approvers(&self, agenda_hash: &Hash256) -> HashSet<PublicKey> {
+        self.approvals
+            .get(agenda_hash)
+            .map(|approvals| approvals.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn vetoers(&self, agenda_hash: &Hash256) -> HashSet<PublicKey> {
+        self.vetoes
+            .get(agenda_hash)
+            .map(|vetoes| vetoes.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Discards the votes on the agendas superseded by the others among the given ones.
+    ///
+    /// Only an amendment authenticated by the author counts (see `Agenda::is_amendment_of()`).
+    pub fn discard_superseded(&mut self, agendas: &[Agenda]) {
+        for superseded in agendas {
+            if agendas
+                .iter()
+                .any(|amendment| amendment.is_amendment_of(superseded))
+            {
+                let superseded = superseded.to_hash256();
+                self.approvals.remove(&superseded);
+                self.vetoes.remove(&superseded);
+                self.contradictory.remove(&superseded);
+            }
+        }
+    }
+}
+
+/// Decodes all the votes of the DMS, discarding the invalid ones and the duplicates,
+/// and applies the rule for the contradictory votes.
+pub fn collect(messages: &[Message], reserved_state: &reserved::ReservedState) -> VoteTally {
+    let mut tally = VoteTally::default();
+    for message in messages {
+        let vote = match decode(message, reserved_state) {
+            Ok(vote) => vote,
+            Err(e) => {
+                log::warn!("discarded an invalid governance vote: {}", e);
+                continue;
+            }
+        };
+        if vote.veto {
+            tally
+                .vetoes
+                .entry(vote.agenda_hash)
+                .or_default()
+                .insert(vote.voter);
+        } else {
+            tally
+                .approvals
+                .entry(vote.agenda_hash)
+                .or_default()
+                .insert(vote.voter, vote.signature);
+        }
+    }
+    for (agenda_hash, vetoers) in &mut tally.vetoes {
+        let approvals = match tally.approvals.get_mut(agenda_hash) {
+            Some(approvals) => approvals,
+            None => continue,
+        };
+        let both = vetoers
+            .iter()
+            .filter(|voter| approvals.contains_key(voter))
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        for voter in &both {
+            log::warn!("{} both approved and vetoed {}", voter, agenda_hash);
+            approvals.remove(voter);
+            vetoers.remove(voter);
+        }
+        if !both.is_empty() {
+            tally.contradictory.insert(*agenda_hash, both);
+        }
+    }
+    tally.approvals.retain(|_, approvals| !approvals.is_empty());
+    tally.vetoes.retain(|_, vetoers| !vetoers.is_empty());
+    tally
+}
+
+/// Creates the proof for the agenda if it is approved and not vetoed by the collected votes.
+///
+/// The votes are discarded if the agenda is superseded by one of the other open agendas
+/// (see `VoteTally::discard_superseded()`).
+/// The proof consists of all the counted approvals in the order of the keys,
+/// so every node creates the same agenda-proof commit from the same votes.
+pub fn create_agenda_proof(
+    agenda: &Agenda,
+    open_agendas: &[Agenda],
+    tally: &VoteTally,
+    reserved_state: &reserved::ReservedState,
+) -> Option<AgendaProof> {
+    let mut agendas = open_agendas.to_vec();
+    agendas.push(agenda.clone());
+    let mut tally = tally.clone();
+    tally.discard_superseded(&agendas);
+    let agenda_hash = agenda.to_hash256();
+    if !is_approved(reserved_state, &tally.approvers(&agenda_hash))
+        || is_vetoed(reserved_state, &tally.vetoers(&agenda_hash))
+    {
+        return None;
+    }
+    let proof = tally
+        .approvals
+        .get(&agenda_hash)?
+        .iter()
+        .map(|(voter, signature)| {
+            (
+                voter.clone(),
+                TypedSignature::new(signature.clone(), voter.clone()),
+            )
+        })
+        .collect();
+    Some(AgendaProof { agenda_hash, proof })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use simperby_common::crypto::generate_keypair;
+    use simperby_common::test_util::genesis;
+    use simperby_network::signer::LocalSigner;
+
+    fn network_config() -> NetworkConfig {
+        let (public_key, private_key) = generate_keypair("network");
+        NetworkConfig {
+            network_id: "test".to_string(),
+            chain_id: Default::default(),
+            port: None,
+            members: Vec::new(),
+            public_key,
+            private_key,
+        }
+    }
+
+    fn agenda() -> Agenda {
+        Agenda {
+            author: generate_keypair("a").0,
+            timestamp: 0,
+            hash: Hash256::hash("transactions"),
+            expiration_height: None,
+            expiration_timestamp: None,
+            supersedes: None,
+            amendment_proof: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn collect_and_create_proof() {
+        let state = genesis(&["a", "b", "c", "d"]);
+        let config = network_config();
+        let agenda = agenda();
+        let agenda_hash = agenda.to_hash256();
+        let signer = |name: &str| LocalSigner::new(generate_keypair(name).1);
+
+        let mut messages = Vec::new();
+        for name in ["a", "b", "c"] {
+            let message = encode(agenda_hash, false, &signer(name), &config)
+                .await
+                .unwrap();
+            messages.push(message.clone());
+            messages.push(message);
+        }
+        // Not a member.
+        messages.push(
+            encode(agenda_hash, false, &signer("e"), &config)
+                .await
+                .unwrap(),
+        );
+        decode(messages.last().unwrap(), &state).unwrap_err();
+
+        let tally = collect(&messages, &state);
+        assert_eq!(tally.approvers(&agenda_hash).len(), 3);
+        let proof = create_agenda_proof(&agenda, &[], &tally, &state).unwrap();
+        simperby_common::verify::verify_agenda_proof(&agenda, &proof, &state).unwrap();
+
+        // The proof doesn't depend on the order of the votes.
+        messages.reverse();
+        assert_eq!(
+            create_agenda_proof(&agenda, &[], &collect(&messages, &state), &state).unwrap(),
+            proof
+        );
+
+        // `c` contradicts itself, so only two out of four approve.
+        messages.push(
+            encode(agenda_hash, true, &signer("c"), &config)
+                .await
+                .unwrap(),
+        );
+        let tally = collect(&messages, &state);
+        assert_eq!(tally.approvers(&agenda_hash).len(), 2);
+        assert!(tally.vetoers(&agenda_hash).is_empty());
+        assert_eq!(tally.contradictory[&agenda_hash].len(), 1);
+        assert!(create_agenda_proof(&agenda, &[], &tally, &state).is_none());
+    }
+
+    #[tokio::test]
+    async fn superseded_agenda() {
+        let state = genesis(&["a", "b", "c", "d"]);
+        let config = network_config();
+        let agenda = agenda();
+        let amend = |signer: &str| {
+            let hash = Hash256::hash("revised transactions");
+            Agenda {
+                hash,
+                supersedes: Some(agenda.to_hash256()),
+                amendment_proof: Some(
+                    TypedSignature::sign(&(agenda.to_hash256(), hash), &generate_keypair(signer).1)
+                        .unwrap(),
+                ),
+                ..agenda.clone()
+            }
+        };
+        let amendment = amend("a");
+        let mut messages = Vec::new();
+        for name in ["a", "b", "c", "d"] {
+            let signer = LocalSigner::new(generate_keypair(name).1);
+            messages.push(
+                encode(agenda.to_hash256(), false, &signer, &config)
+                    .await
+                    .unwrap(),
+            );
+        }
+        messages.push(
+            encode(
+                amendment.to_hash256(),
+                false,
+                &LocalSigner::new(generate_keypair("a").1),
+                &config,
+            )
+            .await
+            .unwrap(),
+        );
+        let mut tally = collect(&messages, &state);
+        assert!(create_agenda_proof(&agenda, &[], &tally, &state).is_some());
+        assert!(create_agenda_proof(&agenda, &[amendment.clone()], &tally, &state).is_none());
+
+        // Not signed by the author.
+        let forged = amend("b");
+        assert!(!forged.is_amendment_of(&agenda));
+        assert!(create_agenda_proof(&agenda, &[forged.clone()], &tally, &state).is_some());
+        tally.discard_superseded(&[agenda.clone(), forged]);
+        assert_eq!(tally.approvers(&agenda.to_hash256()).len(), 4);
+
+        tally.discard_superseded(&[agenda.clone(), amendment.clone()]);
+        assert!(tally.approvers(&agenda.to_hash256()).is_empty());
+        assert!(create_agenda_proof(&agenda, &[], &tally, &state).is_none());
+        assert_eq!(tally.approvers(&amendment.to_hash256()).len(), 1);
+    }
+}
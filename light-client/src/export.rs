@@ -0,0 +1,151 @@
+//! The export of the finalized headers, for a light client elsewhere (e.g., a Cosmos or EVM contract).
+//!
+//! A `HeaderExport` carries a header with its finalization proof, and the validator set
+//! updates since a checkpoint that the verifier already trusts (e.g., the genesis header,
+//! or the header of a previous export). Only the headers changing the validator set are
+//! included, instead of every header in between: each of them is finalized by the validator
+//! set of the previous one, so that the verifier follows the validator set to the exported header.
+//!
+//! Skipping the headers with no change relies on the usual assumption of the light clients,
+//! that more than two thirds of a validator set never finalize a conflicting header,
+//! even after they are replaced; the verifier should refuse a checkpoint too old for it.
+//!
+//! Everything is encoded canonically (see `simperby_common::canonical`), so that a verifier
+//! on another platform hashes and checks the same bytes.
+use super::*;
+
+/// A header with its finalization proof, by the validator set of its previous header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FinalizedHeader {
+    pub header: BlockHeader,
+    pub proof: FinalizationProof,
+}
+
+/// The changes of the validator set from a checkpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidatorSetUpdateProof {
+    /// The trusted header to start from, which must match the hash the verifier holds.
+    pub checkpoint: BlockHeader,
+    /// The headers that change the validator set after the checkpoint, in order.
+    pub updates: Vec<FinalizedHeader>,
+}
+
+impl ValidatorSetUpdateProof {
+    /// Verifies the updates from the trusted checkpoint, returning the last validator set
+    /// with the height it was set at.
+    pub fn verify(
+        &self,
+        trusted_checkpoint: &Hash256,
+    ) -> Result<(BlockHeight, &[(PublicKey, VotingPower)]), Error> {
+        if &self.checkpoint.to_hash256() != trusted_checkpoint {
+            return Err(Error::InvalidProof(
+                "the checkpoint is not the trusted one".to_owned(),
+            ));
+        }
+        let mut height = self.checkpoint.height;
+        let mut validator_set = &self.checkpoint.validator_set;
+        for update in &self.updates {
+            if update.header.height <= height {
+                return Err(Error::InvalidProof(format!(
+                    "the update at height {} is not after {}",
+                    update.header.height, height
+                )));
+            }
+            if &update.header.validator_set == validator_set {
+                return Err(Error::InvalidProof(format!(
+                    "the update at height {} doesn't change the validator set",
+                    update.header.height
+                )));
+            }
+            verify::verify_finalization_proof(&update.header, &update.proof, validator_set)
+                .map_err(|e| Error::InvalidProof(e.to_string()))?;
+            height = update.header.height;
+            validator_set = &update.header.validator_set;
+        }
+        Ok((height, validator_set))
+    }
+}
+
+/// A finalized header, provable from a trusted checkpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeaderExport {
+    pub chain_name: String,
+    pub validator_set_updates: ValidatorSetUpdateProof,
+    pub header: FinalizedHeader,
+}
+
+impl HeaderExport {
+    /// Builds the export of the last of the headers following the checkpoint.
+    ///
+    /// The proofs are taken from the next headers (`prev_block_finalization_proof`),
+    /// except that of the last one, which is given.
+    pub fn build(
+        chain_name: String,
+        checkpoint: &BlockHeader,
+        headers: &[BlockHeader],
+        last_proof: FinalizationProof,
+    ) -> Result<Self, Error> {
+        let (last, _) = headers
+            .split_last()
+            .ok_or_else(|| Error::InvalidHeader("no header after the checkpoint".to_owned()))?;
+        let mut previous = checkpoint;
+        let mut updates = Vec::new();
+        for (i, header) in headers.iter().enumerate() {
+            verify::verify_header_to_header(previous, header)
+                .map_err(|e| Error::InvalidHeader(e.to_string()))?;
+            // The last header itself is exported, even if it changes the validator set.
+            if i + 1 < headers.len() && header.validator_set != previous.validator_set {
+                updates.push(FinalizedHeader {
+                    header: header.clone(),
+                    proof: headers[i + 1].prev_block_finalization_proof.clone(),
+                });
+            }
+            previous = header;
+        }
+        let export = Self {
+            chain_name,
+            validator_set_updates: ValidatorSetUpdateProof {
+                checkpoint: checkpoint.clone(),
+                updates,
+            },
+            header: FinalizedHeader {
+                header: last.clone(),
+                proof: last_proof,
+            },
+        };
+        export.verify(&checkpoint.to_hash256())?;
+        Ok(export)
+    }
+
+    /// Verifies the export from the trusted checkpoint, returning the exported header.
+    pub fn verify(&self, trusted_checkpoint: &Hash256) -> Result<&BlockHeader, Error> {
+        let (height, validator_set) = self.validator_set_updates.verify(trusted_checkpoint)?;
+        let header = &self.header.header;
+        if header.height <= height {
+            return Err(Error::InvalidHeader(format!(
+                "the header at height {} is not after the updates (height {})",
+                header.height, height
+            )));
+        }
+        verify::verify_finalization_proof(header, &self.header.proof, validator_set)
+            .map_err(|e| Error::InvalidHeader(e.to_string()))?;
+        Ok(header)
+    }
+
+    /// Encodes the export canonically.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        canonical::to_vec(self).map_err(|e| Error::InvalidProof(e.to_string()))
+    }
+
+    /// Decodes the export, which must be in the canonical encoding.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let export: Self =
+            serde_json::from_slice(bytes).map_err(|e| Error::InvalidProof(e.to_string()))?;
+        if export.to_bytes()? != bytes {
+            return Err(Error::InvalidProof(
+                "the export is not in the canonical encoding".to_owned(),
+            ));
+        }
+        Ok(export)
+    }
+}
@@ -0,0 +1,163 @@
+//! A light client of a Simperby chain.
+//!
+//! Starting from a trusted genesis reserved state, it verifies a stream of block headers
+//! with their finalization proofs, following the validator set changes recorded in the headers.
+//! It keeps only the Merkle roots of the verified blocks, so it never needs the repository.
+//!
+//! This crate (and `simperby-common`) must stay buildable for `wasm32-unknown-unknown`;
+//! never depend on `tokio`, `git2` or anything touching the OS here.
+//! With the `wasm` feature, it's exposed to JavaScript (see `wasm`).
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use simperby_common::merkle_tree::{self, MerkleProof};
+use simperby_common::reserved::ReservedState;
+use simperby_common::*;
+use thiserror::Error;
+
+pub mod export;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[derive(Error, Debug, Clone)]
+pub enum Error {
+    #[error("invalid genesis: {0}")]
+    InvalidGenesis(String),
+    #[error("invalid header: {0}")]
+    InvalidHeader(String),
+    #[error("unknown height: {0}")]
+    UnknownHeight(BlockHeight),
+    #[error("invalid proof: {0}")]
+    InvalidProof(String),
+    #[error("source error: {0}")]
+    Source(String),
+}
+
+/// A source of the finalized block headers (e.g., a shallow Git fetch or an RPC endpoint).
+#[async_trait]
+pub trait HeaderSource {
+    /// Returns the header at the given height with its finalization proof,
+    /// or `None` if it's not available yet.
+    async fn fetch(
+        &mut self,
+        height: BlockHeight,
+    ) -> Result<Option<(BlockHeader, FinalizationProof)>, Error>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightClient {
+    chain_name: String,
+    /// The Merkle roots of the transactions, indexed by `height - genesis height`.
+    tx_roots: Vec<Hash256>,
+    /// The Merkle roots of the repository, indexed by `height - genesis height`.
+    repository_roots: Vec<Hash256>,
+    genesis_height: BlockHeight,
+    last_header: BlockHeader,
+}
+
+impl LightClient {
+    /// Initializes the light client with the trusted genesis reserved state.
+    pub fn new(genesis: &ReservedState) -> Result<Self, Error> {
+        let info = &genesis.genesis_info;
+        let validator_set = genesis
+            .create_validator_set()
+            .map_err(Error::InvalidGenesis)?;
+        if info.header.validator_set != validator_set {
+            return Err(Error::InvalidGenesis(
+                "the validator set doesn't match the members".to_string(),
+            ));
+        }
+        verify::verify_finalization_proof(
+            &info.header,
+            &info.genesis_proof,
+            &info.header.validator_set,
+        )
+        .map_err(|e| Error::InvalidGenesis(e.to_string()))?;
+        Ok(Self {
+            chain_name: info.chain_name.clone(),
+            tx_roots: vec![info.header.tx_merkle_root],
+            repository_roots: vec![info.header.repository_merkle_root],
+            genesis_height: info.header.height,
+            last_header: info.header.clone(),
+        })
+    }
+
+    pub fn chain_name(&self) -> &str {
+        &self.chain_name
+    }
+
+    /// Returns the last verified header.
+    pub fn latest_finalized(&self) -> &BlockHeader {
+        &self.last_header
+    }
+
+    /// Verifies whether the header is the finalized next one, without applying it.
+    ///
+    /// The proof is checked against the validator set of the last header,
+    /// which the header itself may change for the blocks after it.
+    pub fn verify_header(
+        &self,
+        header: &BlockHeader,
+        proof: &FinalizationProof,
+    ) -> Result<(), Error> {
+        verify::verify_header_to_header(&self.last_header, header)
+            .map_err(|e| Error::InvalidHeader(e.to_string()))?;
+        verify::verify_finalization_proof(header, proof, &self.last_header.validator_set)
+            .map_err(|e| Error::InvalidHeader(e.to_string()))
+    }
+
+    /// Verifies and applies the next header.
+    pub fn update(&mut self, header: BlockHeader, proof: &FinalizationProof) -> Result<(), Error> {
+        self.verify_header(&header, proof)?;
+        self.tx_roots.push(header.tx_merkle_root);
+        self.repository_roots.push(header.repository_merkle_root);
+        self.last_header = header;
+        Ok(())
+    }
+
+    /// Applies all the headers available from the source, returning the number of them.
+    pub async fn sync(&mut self, source: &mut dyn HeaderSource) -> Result<usize, Error> {
+        let mut count = 0;
+        while let Some((header, proof)) = source.fetch(self.last_header.height + 1).await? {
+            self.update(header, &proof)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn root_at(&self, roots: &[Hash256], height: BlockHeight) -> Result<Hash256, Error> {
+        height
+            .checked_sub(self.genesis_height)
+            .and_then(|index| roots.get(index as usize))
+            .copied()
+            .ok_or(Error::UnknownHeight(height))
+    }
+
+    /// Verifies that the transaction is included in the verified block of the given height.
+    pub fn verify_transaction(
+        &self,
+        height: BlockHeight,
+        transaction: &Transaction,
+        proof: &MerkleProof,
+    ) -> Result<(), Error> {
+        let data =
+            canonical::to_vec(transaction).map_err(|e| Error::InvalidProof(e.to_string()))?;
+        proof
+            .verify(self.root_at(&self.tx_roots, height)?, &data)
+            .map_err(|e| Error::InvalidProof(e.to_string()))
+    }
+
+    /// Verifies that the data is committed in the repository as of the verified block of the given height.
+    pub fn verify_commitment(
+        &self,
+        height: BlockHeight,
+        data: &[u8],
+        proof: &MerkleProof,
+    ) -> Result<(), Error> {
+        proof
+            .verify(self.root_at(&self.repository_roots, height)?, data)
+            .map_err(|e| Error::InvalidProof(e.to_string()))
+    }
+}
+
+/// Re-exported for the proof producers.
+pub use merkle_tree::{prove_transaction, verify_transaction_proof};
@@ -0,0 +1,86 @@
+//! A thin `wasm-bindgen` wrapper of the light client.
+//!
+//! Every value crosses the boundary as a JSON string of the corresponding Rust type.
+use super::*;
+use wasm_bindgen::prelude::*;
+
+fn parse<T: serde::de::DeserializeOwned>(json: &str) -> Result<T, JsValue> {
+    serde_json::from_str(json).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+fn to_js_error(error: Error) -> JsValue {
+    JsValue::from_str(&error.to_string())
+}
+
+#[wasm_bindgen(js_name = LightClient)]
+pub struct WasmLightClient {
+    inner: LightClient,
+}
+
+#[wasm_bindgen(js_class = LightClient)]
+impl WasmLightClient {
+    /// Takes the trusted genesis `ReservedState`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(genesis: &str) -> Result<WasmLightClient, JsValue> {
+        Ok(Self {
+            inner: LightClient::new(&parse(genesis)?).map_err(to_js_error)?,
+        })
+    }
+
+    /// Restores a light client saved by `save()`.
+    pub fn load(state: &str) -> Result<WasmLightClient, JsValue> {
+        Ok(Self {
+            inner: parse(state)?,
+        })
+    }
+
+    pub fn save(&self) -> String {
+        serde_json::to_string(&self.inner).expect("light client is always serializable")
+    }
+
+    #[wasm_bindgen(js_name = latestFinalized)]
+    pub fn latest_finalized(&self) -> String {
+        serde_json::to_string(self.inner.latest_finalized()).expect("header is always serializable")
+    }
+
+    /// Takes a `BlockHeader` and a `FinalizationProof`.
+    #[wasm_bindgen(js_name = verifyHeader)]
+    pub fn verify_header(&self, header: &str, proof: &str) -> Result<(), JsValue> {
+        self.inner
+            .verify_header(&parse(header)?, &parse(proof)?)
+            .map_err(to_js_error)
+    }
+
+    /// Takes a `BlockHeader` and a `FinalizationProof`.
+    pub fn update(&mut self, header: &str, proof: &str) -> Result<(), JsValue> {
+        self.inner
+            .update(parse(header)?, &parse(proof)?)
+            .map_err(to_js_error)
+    }
+
+    /// Takes a `Transaction` and a `MerkleProof`.
+    #[wasm_bindgen(js_name = verifyTransaction)]
+    pub fn verify_transaction(
+        &self,
+        height: BlockHeight,
+        transaction: &str,
+        proof: &str,
+    ) -> Result<(), JsValue> {
+        self.inner
+            .verify_transaction(height, &parse(transaction)?, &parse(proof)?)
+            .map_err(to_js_error)
+    }
+
+    /// Takes the committed data and a `MerkleProof`.
+    #[wasm_bindgen(js_name = verifyCommitment)]
+    pub fn verify_commitment(
+        &self,
+        height: BlockHeight,
+        data: &[u8],
+        proof: &str,
+    ) -> Result<(), JsValue> {
+        self.inner
+            .verify_commitment(height, data, &parse(proof)?)
+            .map_err(to_js_error)
+    }
+}
@@ -0,0 +1,120 @@
+use simperby_common::test_util;
+use simperby_common::*;
+use simperby_light_client::*;
+
+fn next_header(prev: &BlockHeader, prev_proof: FinalizationProof) -> BlockHeader {
+    BlockHeader {
+        author: prev.validator_set[0].0.clone(),
+        prev_block_finalization_proof: prev_proof,
+        previous_hash: prev.to_hash256(),
+        height: prev.height + 1,
+        timestamp: prev.timestamp + 1,
+        commit_hash: Hash256::zero(),
+        tx_merkle_root: Hash256::zero(),
+        chat_merkle_root: Hash256::zero(),
+        repository_merkle_root: Hash256::zero(),
+        validator_set: prev.validator_set.clone(),
+        version: prev.version.clone(),
+    }
+}
+
+fn sign(header: &BlockHeader, keys: &[(PublicKey, PrivateKey)]) -> FinalizationProof {
+    keys.iter()
+        .map(|(_, private_key)| TypedSignature::sign(header, private_key).unwrap())
+        .collect()
+}
+
+fn genesis_header(keys: &[(PublicKey, PrivateKey)]) -> BlockHeader {
+    BlockHeader {
+        author: keys[0].0.clone(),
+        validator_set: keys
+            .iter()
+            .map(|(public_key, _)| (public_key.clone(), 1))
+            .collect(),
+        ..test_util::header(0)
+    }
+}
+
+#[test]
+fn follow_chain() {
+    let names = ["a", "b", "c", "d"];
+    let keys: Vec<_> = names.iter().map(|x| generate_keypair(x)).collect();
+    let genesis = test_util::genesis(&names);
+    let genesis_header = genesis.genesis_info.header.clone();
+    let genesis_proof = genesis.genesis_info.genesis_proof.clone();
+
+    let mut light_client = LightClient::new(&genesis).unwrap();
+    let header1 = next_header(&genesis_header, genesis_proof);
+    let proof1 = sign(&header1, &keys);
+
+    // Two out of four is not enough.
+    light_client
+        .verify_header(&header1, &sign(&header1, &keys[..2]))
+        .unwrap_err();
+    light_client.update(header1.clone(), &proof1).unwrap();
+    assert_eq!(light_client.latest_finalized(), &header1);
+
+    // A header that skips a height is rejected.
+    let header2 = next_header(&header1, proof1);
+    let header3 = next_header(&header2, sign(&header2, &keys));
+    light_client
+        .update(header3.clone(), &sign(&header3, &keys))
+        .unwrap_err();
+}
+
+#[test]
+fn export_across_validator_set_update() {
+    let old_keys = (0..4)
+        .map(|i| generate_keypair(format!("old-{}", i)))
+        .collect::<Vec<_>>();
+    let new_keys = (0..4)
+        .map(|i| generate_keypair(format!("new-{}", i)))
+        .collect::<Vec<_>>();
+    let genesis_header = genesis_header(&old_keys);
+
+    // The set changes at height 2; from height 3, the new set finalizes the headers.
+    let header1 = next_header(&genesis_header, sign(&genesis_header, &old_keys));
+    let mut header2 = next_header(&header1, sign(&header1, &old_keys));
+    header2.validator_set = new_keys
+        .iter()
+        .map(|(public_key, _)| (public_key.clone(), 1))
+        .collect();
+    let header3 = next_header(&header2, sign(&header2, &old_keys));
+    let header4 = next_header(&header3, sign(&header3, &new_keys));
+    let headers = vec![header1, header2.clone(), header3, header4.clone()];
+
+    // The old set can't finalize the exported header anymore.
+    export::HeaderExport::build(
+        "test".to_string(),
+        &genesis_header,
+        &headers,
+        sign(&header4, &old_keys),
+    )
+    .unwrap_err();
+    let export = export::HeaderExport::build(
+        "test".to_string(),
+        &genesis_header,
+        &headers,
+        sign(&header4, &new_keys),
+    )
+    .unwrap();
+    assert_eq!(export.validator_set_updates.updates.len(), 1);
+    assert_eq!(export.validator_set_updates.updates[0].header, header2);
+
+    let bytes = export.to_bytes().unwrap();
+    let decoded = export::HeaderExport::from_bytes(&bytes).unwrap();
+    assert_eq!(
+        decoded.verify(&genesis_header.to_hash256()).unwrap(),
+        &header4
+    );
+    // Only from the trusted checkpoint.
+    decoded.verify(&header4.to_hash256()).unwrap_err();
+    // Only in the canonical encoding.
+    let pretty = serde_json::to_vec_pretty(&export).unwrap();
+    export::HeaderExport::from_bytes(&pretty).unwrap_err();
+
+    // Dropping the update leaves the exported header unprovable.
+    let mut skipped = export;
+    skipped.validator_set_updates.updates.clear();
+    skipped.verify(&genesis_header.to_hash256()).unwrap_err();
+}
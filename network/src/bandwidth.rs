@@ -0,0 +1,243 @@
+//! The accounting of the bandwidth, per peer and per subsystem.
+//!
+//! The quotas are soft: a peer over its quota is not fetched from until the window ends,
+//! but the transfers in progress and those initiated by the peer are not cut.
+use super::*;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Subsystem {
+    Consensus,
+    Governance,
+    Chat,
+    RepositoryFetch,
+    PeerDiscovery,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteCounter {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl ByteCounter {
+    pub fn total(&self) -> u64 {
+        self.sent + self.received
+    }
+
+    fn add(&mut self, direction: Direction, bytes: u64) {
+        match direction {
+            Direction::Sent => self.sent += bytes,
+            Direction::Received => self.received += bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandwidthQuota {
+    /// The window that the quotas are for; the counters of the window are reset when it ends.
+    pub window: Duration,
+    /// The quota of each peer, in bytes (sent and received) per window.
+    pub per_peer: Option<u64>,
+    /// The quota of each subsystem, in bytes (sent and received) per window.
+    #[serde(default)]
+    pub per_subsystem: BTreeMap<Subsystem, u64>,
+}
+
+impl Default for BandwidthQuota {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(24 * 60 * 60),
+            per_peer: None,
+            per_subsystem: BTreeMap::new(),
+        }
+    }
+}
+
+/// The usage of the bandwidth, since the node started and in the current window.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandwidthReport {
+    pub window_start: Timestamp,
+    pub total_by_peer: Vec<(PublicKey, ByteCounter)>,
+    pub total_by_subsystem: BTreeMap<Subsystem, ByteCounter>,
+    pub window_by_peer: Vec<(PublicKey, ByteCounter)>,
+    pub window_by_subsystem: BTreeMap<Subsystem, ByteCounter>,
+    /// The peers and the subsystems over their quotas in the current window.
+    pub peers_over_quota: Vec<PublicKey>,
+    pub subsystems_over_quota: Vec<Subsystem>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Counters {
+    by_peer: HashMap<PublicKey, ByteCounter>,
+    by_subsystem: BTreeMap<Subsystem, ByteCounter>,
+}
+
+impl Counters {
+    fn add(&mut self, peer: &PublicKey, subsystem: Subsystem, direction: Direction, bytes: u64) {
+        self.by_peer
+            .entry(peer.clone())
+            .or_default()
+            .add(direction, bytes);
+        self.by_subsystem
+            .entry(subsystem)
+            .or_default()
+            .add(direction, bytes);
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    total: Counters,
+    window: Counters,
+    window_start: Timestamp,
+}
+
+impl State {
+    fn roll_window(&mut self, quota: &BandwidthQuota, now: Timestamp) {
+        if now >= self.window_start + quota.window.as_millis() as Timestamp {
+            self.window = Counters::default();
+            self.window_start = now;
+        }
+    }
+}
+
+/// The byte counters of a node, shared among its subsystems.
+#[derive(Debug, Clone, Default)]
+pub struct BandwidthMeter {
+    quota: BandwidthQuota,
+    state: Arc<RwLock<State>>,
+}
+
+fn get_timestamp() -> Timestamp {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+    since_the_epoch.as_millis() as Timestamp
+}
+
+fn sorted(counters: &HashMap<PublicKey, ByteCounter>) -> Vec<(PublicKey, ByteCounter)> {
+    let mut result: Vec<_> = counters
+        .iter()
+        .map(|(peer, counter)| (peer.clone(), *counter))
+        .collect();
+    result.sort_by(|(a, _), (b, _)| a.cmp(b));
+    result
+}
+
+impl BandwidthMeter {
+    pub fn new(quota: BandwidthQuota) -> Self {
+        Self {
+            quota,
+            state: Default::default(),
+        }
+    }
+
+    pub async fn record(
+        &self,
+        peer: &PublicKey,
+        subsystem: Subsystem,
+        direction: Direction,
+        bytes: u64,
+    ) {
+        let mut state = self.state.write().await;
+        state.roll_window(&self.quota, get_timestamp());
+        state.total.add(peer, subsystem, direction, bytes);
+        state.window.add(peer, subsystem, direction, bytes);
+    }
+
+    /// Returns whether the peer or the subsystem is over its quota in the current window.
+    pub async fn is_over_quota(&self, peer: &PublicKey, subsystem: Subsystem) -> bool {
+        let mut state = self.state.write().await;
+        state.roll_window(&self.quota, get_timestamp());
+        let peer_over = match (self.quota.per_peer, state.window.by_peer.get(peer)) {
+            (Some(quota), Some(counter)) => counter.total() > quota,
+            _ => false,
+        };
+        let subsystem_over = match (
+            self.quota.per_subsystem.get(&subsystem),
+            state.window.by_subsystem.get(&subsystem),
+        ) {
+            (Some(quota), Some(counter)) => counter.total() > *quota,
+            _ => false,
+        };
+        peer_over || subsystem_over
+    }
+
+    pub async fn report(&self) -> BandwidthReport {
+        let mut state = self.state.write().await;
+        state.roll_window(&self.quota, get_timestamp());
+        let peers_over_quota = match self.quota.per_peer {
+            Some(quota) => sorted(&state.window.by_peer)
+                .into_iter()
+                .filter(|(_, counter)| counter.total() > quota)
+                .map(|(peer, _)| peer)
+                .collect(),
+            None => Vec::new(),
+        };
+        let subsystems_over_quota = state
+            .window
+            .by_subsystem
+            .iter()
+            .filter(|(subsystem, counter)| {
+                self.quota
+                    .per_subsystem
+                    .get(subsystem)
+                    .map_or(false, |quota| counter.total() > *quota)
+            })
+            .map(|(subsystem, _)| *subsystem)
+            .collect();
+        BandwidthReport {
+            window_start: state.window_start,
+            total_by_peer: sorted(&state.total.by_peer),
+            total_by_subsystem: state.total.by_subsystem.clone(),
+            window_by_peer: sorted(&state.window.by_peer),
+            window_by_subsystem: state.window.by_subsystem.clone(),
+            peers_over_quota,
+            subsystems_over_quota,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn quotas() {
+        let meter = BandwidthMeter::new(BandwidthQuota {
+            window: Duration::from_secs(3600),
+            per_peer: Some(100),
+            per_subsystem: vec![(Subsystem::Chat, 50)].into_iter().collect(),
+        });
+        let a = generate_keypair("a").0;
+        let b = generate_keypair("b").0;
+        meter
+            .record(&a, Subsystem::Consensus, Direction::Received, 80)
+            .await;
+        meter.record(&a, Subsystem::Chat, Direction::Sent, 30).await;
+        meter
+            .record(&b, Subsystem::Chat, Direction::Received, 30)
+            .await;
+        assert!(meter.is_over_quota(&a, Subsystem::Consensus).await);
+        assert!(meter.is_over_quota(&b, Subsystem::Chat).await);
+        assert!(!meter.is_over_quota(&b, Subsystem::Governance).await);
+
+        let report = meter.report().await;
+        assert_eq!(report.peers_over_quota, vec![a.clone()]);
+        assert_eq!(report.subsystems_over_quota, vec![Subsystem::Chat]);
+        assert_eq!(
+            report.total_by_subsystem[&Subsystem::Chat],
+            ByteCounter {
+                sent: 30,
+                received: 30
+            }
+        );
+    }
+}
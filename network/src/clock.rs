@@ -0,0 +1,152 @@
+//! The source of the time, so that the timeouts and the timestamps can be simulated.
+//!
+//! The components that wait or stamp (e.g., the DMS, the consensus and the repository) take
+//! an `Arc<dyn Clock>`, which is `SystemClock` unless set otherwise. A `SimulatedClock` moves
+//! only by `advance()`, so the timeouts can be unit-tested and a simulation is deterministic.
+use async_trait::async_trait;
+use simperby_common::Timestamp;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+#[async_trait]
+pub trait Clock: Send + Sync + 'static {
+    /// Returns the current time in milliseconds since the UNIX epoch.
+    fn now(&self) -> Timestamp;
+
+    /// Waits for the duration.
+    async fn sleep(&self, duration: Duration);
+}
+
+/// The clock of the system, with the sleeps of the runtime.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        let now = std::time::SystemTime::now();
+        let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+        since_the_epoch.as_millis() as Timestamp
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// Returns the system clock as the default of the components.
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+#[derive(Default)]
+struct SimulatedState {
+    now: Timestamp,
+    /// The sleeps by their deadlines, ordered by their arrival among the same deadlines.
+    sleepers: BTreeMap<(Timestamp, u64), oneshot::Sender<()>>,
+    sequence: u64,
+}
+
+/// A clock that moves only by `advance()`, waking the sleeps due in their order.
+///
+/// The time is in milliseconds, so a sleep shorter than a millisecond still waits for
+/// the next advance.
+#[derive(Default)]
+pub struct SimulatedClock {
+    state: Mutex<SimulatedState>,
+}
+
+impl SimulatedClock {
+    pub fn new(now: Timestamp) -> Self {
+        Self {
+            state: Mutex::new(SimulatedState {
+                now,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Moves the time forward, waking the sleeps due.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().expect("poisoned");
+        state.now += duration.as_millis() as Timestamp;
+        let now = state.now;
+        let pending = state.sleepers.split_off(&(now + 1, 0));
+        let due = std::mem::replace(&mut state.sleepers, pending);
+        drop(state);
+        for (_, sleeper) in due {
+            // The sleep may have been cancelled, which is fine.
+            let _ = sleeper.send(());
+        }
+    }
+
+    /// Returns the number of the sleeps waiting.
+    pub fn sleepers(&self) -> usize {
+        let mut state = self.state.lock().expect("poisoned");
+        state.sleepers.retain(|_, sleeper| !sleeper.is_closed());
+        state.sleepers.len()
+    }
+}
+
+#[async_trait]
+impl Clock for SimulatedClock {
+    fn now(&self) -> Timestamp {
+        self.state.lock().expect("poisoned").now
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        let receiver = {
+            let mut state = self.state.lock().expect("poisoned");
+            let deadline = state.now + (duration.as_nanos() as Timestamp + 999_999) / 1_000_000;
+            if deadline == state.now {
+                return;
+            }
+            let (sender, receiver) = oneshot::channel();
+            state.sequence += 1;
+            let sequence = state.sequence;
+            state.sleepers.insert((deadline, sequence), sender);
+            receiver
+        };
+        // The clock outlives the sleep, so the sender is never dropped unsent.
+        let _ = receiver.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn simulated() {
+        let clock = Arc::new(SimulatedClock::new(1000));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for (name, millis) in [("b", 200), ("a", 100), ("c", 300)] {
+            let clock = Arc::clone(&clock);
+            let order = Arc::clone(&order);
+            tasks.push(tokio::spawn(async move {
+                clock.sleep(Duration::from_millis(millis)).await;
+                order.lock().unwrap().push((name, clock.now()));
+            }));
+        }
+        while clock.sleepers() < 3 {
+            tokio::task::yield_now().await;
+        }
+        clock.advance(Duration::from_millis(200));
+        for task in tasks.drain(..2) {
+            task.await.unwrap();
+        }
+        assert_eq!(clock.sleepers(), 1);
+        // A zero sleep doesn't wait.
+        clock.sleep(Duration::ZERO).await;
+        clock.advance(Duration::from_millis(100));
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let mut order = order.lock().unwrap().clone();
+        order[..2].sort();
+        assert_eq!(order, vec![("a", 1200), ("b", 1200), ("c", 1300)]);
+    }
+}
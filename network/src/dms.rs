@@ -1,8 +1,15 @@
-use super::Storage;
 use super::*;
+use super::{MessageStore, StoreOperation};
+use crate::bandwidth::{BandwidthMeter, Direction, Subsystem};
+use crate::clock::{system_clock, Clock};
+use crate::gossip::{select_peers, GossipConfig, GossipState};
+use crate::peer_score::{Offense, PeerScoreConfig, PeerScores};
+use crate::pipeline::{Inbound, Pipeline, Processed};
+use crate::reconciliation::{self, DigestRange, RangeResponse, RangeSummary};
+use crate::sentry::{LinkPush, SentryConfig, SignedLinkPush};
 use anyhow::anyhow;
 use async_trait::async_trait;
-use futures::future::join_all;
+use futures::future::{join_all, BoxFuture};
 use futures::prelude::*;
 use futures::try_join;
 use serde_tc::http::*;
@@ -12,6 +19,7 @@ use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock;
+use tracing::Instrument;
 
 const STATE_FILE_PATH: &str = "_state.json";
 
@@ -23,7 +31,7 @@ pub struct Message {
 
 impl Message {
     pub fn new(data: String, signature: TypedSignature<String>) -> Result<Self, CryptoError> {
-        signature.verify(&data)?;
+        signature_cache::shared().verify_typed(&signature, &data)?;
         Ok(Self { data, signature })
     }
 
@@ -38,7 +46,7 @@ impl Message {
 
 impl ToHash256 for Message {
     fn to_hash256(&self) -> Hash256 {
-        Hash256::hash(serde_json::to_vec(self).unwrap())
+        simperby_common::canonical::to_hash256(self)
     }
 }
 
@@ -49,6 +57,13 @@ struct RawMessage {
     pub signature: TypedSignature<String>,
 }
 
+/// A message as stored, with the time it was received.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredMessage {
+    pub message: RawMessage,
+    pub received_at: Timestamp,
+}
+
 impl RawMessage {
     pub fn into_message(self) -> anyhow::Result<Message> {
         Message::new(self.data, self.signature).map_err(|e| anyhow!(e))
@@ -60,6 +75,43 @@ impl RawMessage {
             signature: message.signature().to_owned(),
         }
     }
+
+    /// Verifies the messages in a batch (see `SignatureCache::verify_batch()`),
+    /// falling back to one by one to tell the invalid ones if the batch fails.
+    pub fn into_messages(messages: Vec<RawMessage>) -> Vec<anyhow::Result<Message>> {
+        let hashes: Vec<_> = messages
+            .iter()
+            .map(|message| message.data.to_hash256())
+            .collect();
+        let valid = signature_cache::shared()
+            .verify_batch(
+                &messages
+                    .iter()
+                    .zip(&hashes)
+                    .map(|(message, hash)| {
+                        (
+                            *hash,
+                            message.signature.signature(),
+                            message.signature.signer(),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .is_ok();
+        if valid {
+            messages
+                .into_iter()
+                .map(|message| {
+                    Ok(Message {
+                        data: message.data,
+                        signature: message.signature,
+                    })
+                })
+                .collect()
+        } else {
+            messages.into_iter().map(RawMessage::into_message).collect()
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,26 +122,103 @@ pub struct State {
 
 pub struct StorageWrapper<S> {
     storage: Arc<RwLock<S>>,
+    chain_id: ChainId,
+    serve_as_relay: bool,
+    gossip: Arc<RwLock<GossipState>>,
+    forward_rounds: usize,
+    /// The validators whose pushes over the private link are accepted (see `sentry`).
+    validators: Vec<PublicKey>,
+    clock: Arc<dyn Clock>,
 }
 
 /// The interface that will be wrapped into an HTTP RPC server for the peers.
+///
+/// Every request carries the chain identifier of the caller, and fails if it is of another chain.
 #[serde_tc_full]
 trait DistributedMessageSetRpcInterface: Send + Sync + 'static {
     /// Returns the messages except `knowns`.
+    ///
+    /// `reconcile()` is preferred, which doesn't send all the known digests.
     async fn get_message(
         &self,
+        chain_id: ChainId,
         height: BlockHeight,
         knowns: Vec<Hash256>,
     ) -> Result<Vec<RawMessage>, String>;
+
+    /// Adds the messages of a peer that is not reachable; accepted only by a relay.
+    async fn push_messages(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        messages: Vec<RawMessage>,
+    ) -> Result<(), String>;
+
+    /// Adds the messages of a validator behind this sentry, forwarding them to the network
+    /// as if they were gossiped; accepted only if signed by one of `SentryConfig::validators`.
+    async fn push_private(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        messages: Vec<RawMessage>,
+        push: SignedLinkPush,
+    ) -> Result<(), String>;
+
+    /// Compares the summaries of the ranges of the digests (see `reconciliation`),
+    /// responding for those that mismatch.
+    async fn reconcile(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        summaries: Vec<RangeSummary>,
+    ) -> Result<Vec<RangeResponse>, String>;
+
+    /// Returns the messages of the given digests, skipping those unknown.
+    async fn get_messages_by_digests(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        digests: Vec<Hash256>,
+    ) -> Result<Vec<RawMessage>, String>;
+
+    /// Receives the digests of the messages that the peer has (`IHAVE`),
+    /// returning those that this node wants (`IWANT`).
+    async fn ihave(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        digests: Vec<Hash256>,
+    ) -> Result<Vec<Hash256>, String>;
+
+    /// Receives the messages asked for in `ihave()`, forwarding them in the next gossip rounds.
+    async fn gossip(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        messages: Vec<RawMessage>,
+    ) -> Result<(), String>;
+}
+
+async fn check_height(storage: &impl MessageStore, height: BlockHeight) -> Result<(), String> {
+    let height_ = read_state(storage).await.map_err(|e| e.to_string())?.height;
+    if height != height_ {
+        return Err(format!(
+            "height mismatch: requested {}, but {}",
+            height, height_
+        ));
+    }
+    Ok(())
 }
 
 #[async_trait]
-impl<S: Storage> DistributedMessageSetRpcInterface for StorageWrapper<S> {
+impl<S: MessageStore> DistributedMessageSetRpcInterface for StorageWrapper<S> {
     async fn get_message(
         &self,
+        chain_id: ChainId,
         height: BlockHeight,
         knowns: Vec<Hash256>,
     ) -> Result<Vec<RawMessage>, String> {
+        self.chain_id.check(&chain_id)?;
         let mut messages = read_messages(&(*self.storage.read().await))
             .await
             .map_err(|e| e.to_string())?;
@@ -111,93 +240,558 @@ impl<S: Storage> DistributedMessageSetRpcInterface for StorageWrapper<S> {
             .collect();
         Ok(messages)
     }
+
+    async fn push_messages(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        messages: Vec<RawMessage>,
+    ) -> Result<(), String> {
+        self.chain_id.check(&chain_id)?;
+        if !self.serve_as_relay {
+            return Err("this node is not a relay".to_owned());
+        }
+        let mut storage = self.storage.write().await;
+        check_height(&*storage, height).await?;
+        for message in RawMessage::into_messages(messages) {
+            let message = message.map_err(|e| e.to_string())?;
+            add_message_but_not_broadcast(&mut *storage, message, self.clock.now())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    async fn push_private(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        messages: Vec<RawMessage>,
+        push: SignedLinkPush,
+    ) -> Result<(), String> {
+        self.chain_id.check(&chain_id)?;
+        let mut storage = self.storage.write().await;
+        let state = read_state(&*storage).await.map_err(|e| e.to_string())?;
+        push.verify(
+            &self.validators,
+            &self.chain_id,
+            &state.key,
+            state.height,
+            simperby_common::canonical::to_hash256(&messages),
+            self.clock.now(),
+        )?;
+        check_height(&*storage, height).await?;
+        for message in RawMessage::into_messages(messages) {
+            let message = message.map_err(|e| e.to_string())?;
+            let digest = message.to_hash256();
+            if add_message_but_not_broadcast(&mut *storage, message, self.clock.now())
+                .await
+                .map_err(|e| e.to_string())?
+            {
+                self.gossip
+                    .write()
+                    .await
+                    .insert(digest, self.forward_rounds);
+            }
+        }
+        Ok(())
+    }
+
+    async fn reconcile(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        summaries: Vec<RangeSummary>,
+    ) -> Result<Vec<RangeResponse>, String> {
+        self.chain_id.check(&chain_id)?;
+        let storage = self.storage.read().await;
+        check_height(&*storage, height).await?;
+        let digests = read_sorted_digests(&*storage)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(reconciliation::respond(&digests, &summaries))
+    }
+
+    async fn get_messages_by_digests(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        digests: Vec<Hash256>,
+    ) -> Result<Vec<RawMessage>, String> {
+        self.chain_id.check(&chain_id)?;
+        let storage = self.storage.read().await;
+        check_height(&*storage, height).await?;
+        let mut messages = Vec::new();
+        for digest in digests {
+            if let Some(data) = storage
+                .read(&message_file_name(&digest))
+                .await
+                .map_err(|e| e.to_string())?
+            {
+                let stored: StoredMessage =
+                    serde_json::from_str(&data).map_err(|e| e.to_string())?;
+                messages.push(stored.message);
+            }
+        }
+        Ok(messages)
+    }
+
+    async fn ihave(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        digests: Vec<Hash256>,
+    ) -> Result<Vec<Hash256>, String> {
+        self.chain_id.check(&chain_id)?;
+        let storage = self.storage.read().await;
+        check_height(&*storage, height).await?;
+        let mut wanted = Vec::new();
+        for digest in digests {
+            if storage
+                .read(&message_file_name(&digest))
+                .await
+                .map_err(|e| e.to_string())?
+                .is_none()
+            {
+                wanted.push(digest);
+            }
+        }
+        Ok(wanted)
+    }
+
+    async fn gossip(
+        &self,
+        chain_id: ChainId,
+        height: BlockHeight,
+        messages: Vec<RawMessage>,
+    ) -> Result<(), String> {
+        self.chain_id.check(&chain_id)?;
+        let mut storage = self.storage.write().await;
+        check_height(&*storage, height).await?;
+        for message in RawMessage::into_messages(messages) {
+            let message = message.map_err(|e| e.to_string())?;
+            let digest = message.to_hash256();
+            if add_message_but_not_broadcast(&mut *storage, message, self.clock.now())
+                .await
+                .map_err(|e| e.to_string())?
+            {
+                self.gossip
+                    .write()
+                    .await
+                    .insert(digest, self.forward_rounds);
+            }
+        }
+        Ok(())
+    }
 }
 
-async fn read_messages(storage: &impl Storage) -> Result<Vec<Message>, Error> {
-    let files = storage.list_files().await?;
-    let tasks = files
-        .into_iter()
-        .map(|f| async move { storage.read_file(&f).await });
-    let data = future::join_all(tasks)
-        .await
+/// Reads the stored messages with their file names and sizes.
+async fn read_stored_messages(
+    storage: &impl MessageStore,
+) -> Result<Vec<(String, StoredMessage, u64)>, Error> {
+    let messages = storage
+        .read_all()
+        .await?
         .into_iter()
+        .filter(|(f, _)| f != STATE_FILE_PATH)
+        .map(|(f, d)| {
+            let size = d.len() as u64;
+            serde_json::from_str(&d).map(|m| (f, m, size))
+        })
         .collect::<Result<Vec<_>, _>>()?;
-    let messages = data
+    Ok(messages)
+}
+
+async fn read_messages(storage: &impl MessageStore) -> Result<Vec<Message>, Error> {
+    let messages = read_stored_messages(storage)
+        .await?
         .into_iter()
-        .map(|d| serde_json::from_str(&d))
-        .collect::<Result<Vec<RawMessage>, _>>()?;
-    let messages = messages
+        .map(|(_, m, _)| m.message)
+        .collect();
+    let messages = RawMessage::into_messages(messages)
         .into_iter()
-        .map(|d| d.into_message())
         .collect::<Result<Vec<_>, _>>()?;
     Ok(messages)
 }
 
+async fn read_sorted_digests(storage: &impl MessageStore) -> Result<Vec<Hash256>, Error> {
+    let mut digests: Vec<_> = read_messages(storage)
+        .await?
+        .iter()
+        .map(|m| m.to_hash256())
+        .collect();
+    digests.sort();
+    Ok(digests)
+}
+
+fn message_file_name(digest: &Hash256) -> String {
+    format!("{}.json", digest)
+}
+
+/// Adds the message received at the given time, returning whether it is new.
 async fn add_message_but_not_broadcast(
-    storage: &mut impl Storage,
+    storage: &mut impl MessageStore,
     message: Message,
-) -> Result<(), Error> {
+    received_at: Timestamp,
+) -> Result<bool, Error> {
+    let name = message_file_name(&message.to_hash256());
+    // Keep the time it was first received, which the retention policy relies on.
+    if storage.read(&name).await?.is_some() {
+        return Ok(false);
+    }
     storage
-        .add_or_overwrite_file(
-            &format!("{}.json", message.to_hash256()),
-            message.data().to_owned(),
-        )
+        .write_batch(vec![StoreOperation::Put(
+            name,
+            serde_json::to_string(&StoredMessage {
+                message: RawMessage::from_message(message),
+                received_at,
+            })?,
+        )])
         .await?;
-    Ok(())
+    Ok(true)
+}
+
+/// Chooses the messages to drop under the retention policy, given their names,
+/// received times and sizes. The oldest messages are dropped first.
+fn select_messages_to_drop(
+    mut messages: Vec<(String, Timestamp, u64)>,
+    policy: &RetentionPolicy,
+    now: Timestamp,
+) -> Vec<String> {
+    messages.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+    let mut count = messages.len();
+    let mut bytes = messages.iter().map(|(_, _, size)| size).sum::<u64>();
+    let mut result = Vec::new();
+    for (name, received_at, size) in messages {
+        let expired = policy.ttl.map_or(false, |ttl| {
+            received_at + (ttl.as_millis() as Timestamp) < now
+        });
+        let too_many = policy.max_messages.map_or(false, |max| count > max);
+        let too_large = policy.max_bytes.map_or(false, |max| bytes > max);
+        if !(expired || too_many || too_large) {
+            break;
+        }
+        count -= 1;
+        bytes -= size;
+        result.push(name);
+    }
+    result
+}
+
+async fn compact(
+    storage: &mut impl MessageStore,
+    policy: &RetentionPolicy,
+    now: Timestamp,
+) -> Result<usize, Error> {
+    let messages = read_stored_messages(storage)
+        .await?
+        .into_iter()
+        .map(|(name, m, size)| (name, m.received_at, size))
+        .collect();
+    let to_drop = select_messages_to_drop(messages, policy, now);
+    let count = to_drop.len();
+    if count > 0 {
+        storage
+            .write_batch(to_drop.into_iter().map(StoreOperation::Remove).collect())
+            .await?;
+    }
+    Ok(count)
 }
 
-async fn read_state(storage: &impl Storage) -> Result<State, Error> {
-    let state: State = serde_json::from_str(&storage.read_file(STATE_FILE_PATH).await?)?;
-    Ok(state)
+async fn read_state(storage: &impl MessageStore) -> Result<State, Error> {
+    let state = storage
+        .read(STATE_FILE_PATH)
+        .await?
+        .ok_or_else(|| anyhow!("the message set is not created"))?;
+    Ok(serde_json::from_str(&state)?)
 }
 
-async fn write_state(storage: &mut impl Storage, state: State) -> Result<(), Error> {
+/// Discards all the messages and writes the state, at once.
+async fn reset(storage: &mut impl MessageStore, state: State) -> Result<(), Error> {
     storage
-        .add_or_overwrite_file(STATE_FILE_PATH, serde_json::to_string(&state)?)
+        .write_batch(vec![
+            StoreOperation::RemoveAll,
+            StoreOperation::Put(STATE_FILE_PATH.to_owned(), serde_json::to_string(&state)?),
+        ])
         .await?;
     Ok(())
 }
 
-async fn fetch<S: Storage>(
+/// The URL of the RPC server for the DMS of the given key on the peer.
+fn rpc_url(peer: &Peer, port_key: &str) -> Result<String, Error> {
+    Ok(format!(
+        "http://{}/{}",
+        peer.address.ip(),
+        peer.ports
+            .get(port_key)
+            .ok_or_else(|| anyhow!("can't find port key: {}", port_key))?
+    ))
+}
+
+/// Pushes all the messages to the relay, for a node that is not reachable.
+async fn push_to_relay<S: MessageStore>(
     storage: Arc<RwLock<S>>,
-    _network_config: &NetworkConfig,
+    chain_id: &ChainId,
     known_peers: &[Peer],
+    relay: &PublicKey,
 ) -> Result<(), Error> {
-    let mut tasks = Vec::new();
-    let messages = read_messages(&*storage.read().await).await?;
-    let known_messages = messages
+    let peer = known_peers
+        .iter()
+        .find(|peer| &peer.public_key == relay)
+        .ok_or_else(|| anyhow!("the relay {} is not a known peer", relay))?;
+    let state = read_state(&*storage.read().await).await?;
+    let messages = read_messages(&*storage.read().await)
+        .await?
         .into_iter()
-        .map(|m| m.to_hash256())
-        .collect::<Vec<_>>();
+        .map(RawMessage::from_message)
+        .collect();
+    let stub = DistributedMessageSetRpcInterfaceStub::new(Box::new(HttpClient::new(
+        rpc_url(peer, &state.key)?,
+        reqwest::Client::new(),
+    )));
+    stub.push_messages(chain_id.clone(), state.height, messages)
+        .await?
+        .map_err(|e| anyhow!(e))
+}
+
+/// Pushes all the messages to the sentries over the private link, for a hidden validator.
+async fn push_to_sentries<S: MessageStore>(
+    storage: Arc<RwLock<S>>,
+    network_config: &NetworkConfig,
+    known_peers: &[Peer],
+    sentries: &[PublicKey],
+    now: Timestamp,
+) -> Result<(), Error> {
+    let state = read_state(&*storage.read().await).await?;
+    let messages: Vec<_> = read_messages(&*storage.read().await)
+        .await?
+        .into_iter()
+        .map(RawMessage::from_message)
+        .collect();
+    let push = SignedLinkPush::sign(
+        LinkPush {
+            chain_id: network_config.chain_id.clone(),
+            dms_key: state.key.clone(),
+            height: state.height,
+            timestamp: now,
+            messages_hash: simperby_common::canonical::to_hash256(&messages),
+        },
+        &network_config.private_key,
+    )?;
+    for sentry in sentries {
+        let result = async {
+            let peer = known_peers
+                .iter()
+                .find(|peer| &peer.public_key == sentry)
+                .ok_or_else(|| anyhow!("the sentry is not a known peer"))?;
+            let stub = DistributedMessageSetRpcInterfaceStub::new(Box::new(HttpClient::new(
+                rpc_url(peer, &state.key)?,
+                reqwest::Client::new(),
+            )));
+            stub.push_private(
+                network_config.chain_id.clone(),
+                state.height,
+                messages.clone(),
+                push.clone(),
+            )
+            .await?
+            .map_err(|e| anyhow!(e))
+        }
+        .await;
+        if let Err(e) = result {
+            log::warn!(
+                "failed to push the messages to the sentry {}: {}",
+                sentry,
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Verifies and stores a message from the pipeline, unless the height has advanced since.
+async fn store_inbound<S: MessageStore>(
+    storage: &RwLock<S>,
+    inbound: Inbound,
+    clock: &dyn Clock,
+) -> Result<Processed, Error> {
+    let mut storage = storage.write().await;
+    if read_state(&*storage).await?.height != inbound.height {
+        return Ok(Processed::Stale);
+    }
+    let message = serde_json::from_slice::<RawMessage>(&inbound.data)?.into_message()?;
+    add_message_but_not_broadcast(&mut *storage, message, clock.now()).await?;
+    Ok(Processed::Stored)
+}
+
+/// Announces the digests to the peer, sending the messages it asks for.
+async fn push_gossip<S: MessageStore>(
+    storage: Arc<RwLock<S>>,
+    chain_id: &ChainId,
+    peer: &Peer,
+    digests: Vec<Hash256>,
+) -> Result<(), Error> {
+    let state = read_state(&*storage.read().await).await?;
+    let stub = DistributedMessageSetRpcInterfaceStub::new(Box::new(HttpClient::new(
+        rpc_url(peer, &state.key)?,
+        reqwest::Client::new(),
+    )));
+    let wanted = stub
+        .ihave(chain_id.clone(), state.height, digests)
+        .await?
+        .map_err(|e| anyhow!(e))?;
+    if wanted.is_empty() {
+        return Ok(());
+    }
+    let mut messages = Vec::new();
+    {
+        let storage = storage.read().await;
+        for digest in wanted {
+            // It may have been dropped by the compaction in the meantime.
+            if let Some(data) = storage.read(&message_file_name(&digest)).await? {
+                messages.push(serde_json::from_str::<StoredMessage>(&data)?.message);
+            }
+        }
+    }
+    stub.gossip(chain_id.clone(), state.height, messages)
+        .await?
+        .map_err(|e| anyhow!(e))
+}
+
+/// Runs a gossip round, announcing the recently learned messages to random peers.
+async fn gossip_round<S: MessageStore>(
+    storage: Arc<RwLock<S>>,
+    chain_id: &ChainId,
+    gossip: &RwLock<GossipState>,
+    known_peers: &[Peer],
+    fanout: usize,
+) {
+    let digests = gossip.write().await.next_round();
+    if digests.is_empty() {
+        return;
+    }
+    let peers = select_peers(known_peers, fanout);
+    let tasks = peers
+        .iter()
+        .map(|peer| push_gossip(Arc::clone(&storage), chain_id, peer, digests.clone()));
+    for (result, peer) in join_all(tasks).await.into_iter().zip(peers.iter()) {
+        if let Err(e) = result {
+            log::warn!("failed to gossip to {}: {}", peer.public_key, e);
+        }
+    }
+}
+
+async fn fetch<S: MessageStore>(
+    storage: Arc<RwLock<S>>,
+    network_config: &NetworkConfig,
+    known_peers: &[Peer],
+    relay: Option<&PublicKey>,
+    sentry: &SentryConfig,
+    scores: &PeerScores,
+    bandwidth: Option<&(BandwidthMeter, Subsystem)>,
+    clock: &dyn Clock,
+) -> Result<(), Error> {
+    let chain_id = &network_config.chain_id;
+    if let Some(relay) = relay {
+        if let Err(e) = push_to_relay(Arc::clone(&storage), chain_id, known_peers, relay).await {
+            log::warn!("failed to push the messages to the relay: {}", e);
+        }
+    }
+    if sentry.is_hidden() {
+        push_to_sentries(
+            Arc::clone(&storage),
+            network_config,
+            known_peers,
+            &sentry.sentries,
+            clock.now(),
+        )
+        .await?;
+    }
+    let known_peers = &sentry.restrict(known_peers);
+    let mut tasks = Vec::new();
+    let known_messages = read_sorted_digests(&*storage.read().await).await?;
     let state = read_state(&*storage.read().await).await?;
     let height = state.height;
 
     for peer in known_peers {
         let storage = Arc::clone(&storage);
         let port_key = state.key.clone();
-        let known_messages_ = known_messages.clone();
+        let known_messages = &known_messages;
         let task = async move {
+            if scores.is_banned(&peer.public_key).await {
+                return Ok(());
+            }
+            if let Some((meter, subsystem)) = bandwidth {
+                if meter.is_over_quota(&peer.public_key, *subsystem).await {
+                    log::info!(
+                        "skipped fetching from {}: over the bandwidth quota",
+                        peer.public_key
+                    );
+                    return Ok(());
+                }
+            }
             let stub = DistributedMessageSetRpcInterfaceStub::new(Box::new(HttpClient::new(
-                format!(
-                    "http://{}/{}",
-                    peer.address.ip(),
-                    peer.ports
-                        .get(&port_key)
-                        .ok_or_else(|| anyhow!("can't find port key: {}", port_key))?
-                ),
+                rpc_url(peer, &port_key)?,
                 reqwest::Client::new(),
             )));
+            let mut wanted = Vec::new();
+            let mut summaries = vec![reconciliation::summarize(
+                known_messages,
+                DigestRange::full(),
+            )];
+            let mut rounds = 0;
+            while !summaries.is_empty() {
+                rounds += 1;
+                if rounds > reconciliation::MAX_ROUNDS {
+                    scores
+                        .report(&peer.public_key, Offense::ProtocolViolation)
+                        .await;
+                    return Err(anyhow!("the reconciliation doesn't converge"));
+                }
+                let responses = stub
+                    .reconcile(chain_id.clone(), height, summaries)
+                    .await?
+                    .map_err(|e| anyhow!(e))?;
+                summaries = reconciliation::process(known_messages, responses, &mut wanted);
+            }
+            if wanted.is_empty() {
+                return Ok(());
+            }
             let messages = stub
-                .get_message(height, known_messages_)
+                .get_messages_by_digests(chain_id.clone(), height, wanted)
                 .await?
                 .map_err(|e| anyhow!(e))?;
+            let size = serde_json::to_vec(&messages)?.len() as u64;
+            if let Some((meter, subsystem)) = bandwidth {
+                meter
+                    .record(&peer.public_key, *subsystem, Direction::Received, size)
+                    .await;
+            }
+            if !scores.record_bytes(&peer.public_key, size).await {
+                return Err(anyhow!("throttled for exceeding the bandwidth budget"));
+            }
             let mut storage = storage.write().await;
-            for message in messages {
-                let message = message.into_message()?;
-                add_message_but_not_broadcast(&mut *storage, message).await?;
+            for message in RawMessage::into_messages(messages) {
+                match message {
+                    Ok(message) => {
+                        add_message_but_not_broadcast(&mut *storage, message, clock.now()).await?;
+                        scores.reward(&peer.public_key).await;
+                    }
+                    Err(_) => {
+                        scores
+                            .report(&peer.public_key, Offense::InvalidMessage)
+                            .await
+                    }
+                }
             }
             Result::<(), Error>::Ok(())
-        };
+        }
+        .instrument(tracing::debug_span!(
+            "fetch",
+            dms = %state.key,
+            height,
+            peer = %peer.public_key
+        ));
         tasks.push(task);
     }
     let results = future::join_all(tasks).await;
@@ -209,7 +803,7 @@ async fn fetch<S: Storage>(
     Ok(())
 }
 
-/// A **cumulative** set that is shared in the p2p network, backed by the local file system.
+/// A **cumulative** set that is shared in the p2p network, backed by a `MessageStore`.
 ///
 /// One of the notable characteristics of blockchain is that it is based on heights;
 /// The key idea here is that we retain an instance (both in memory or on disk)
@@ -221,9 +815,42 @@ async fn fetch<S: Storage>(
 /// - It locks the storage.
 /// - If the given directory is locked (possibly by another instance of `DistributedMessageSet`),
 /// it will `await` until the lock is released.
+/// A read-only handle to the messages of a `DistributedMessageSet`,
+/// which stays valid after `DistributedMessageSet::serve()` consumes the message set.
+pub struct MessageReader<S> {
+    storage: Arc<RwLock<S>>,
+}
+
+// Not derived, since it would require `S: Clone`.
+impl<S> Clone for MessageReader<S> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: Arc::clone(&self.storage),
+        }
+    }
+}
+
+impl<S: MessageStore> MessageReader<S> {
+    /// Opens the storage of a DMS only for reading, without the network.
+    pub fn open(storage: S) -> Self {
+        Self {
+            storage: Arc::new(RwLock::new(storage)),
+        }
+    }
+
+    pub async fn read_messages(&self) -> Result<Vec<Message>, Error> {
+        read_messages(&*self.storage.read().await).await
+    }
+}
+
 pub struct DistributedMessageSet<N, S> {
     storage: Arc<RwLock<S>>,
     config: Config,
+    scores: PeerScores,
+    gossip: Arc<RwLock<GossipState>>,
+    bandwidth: Option<(BandwidthMeter, Subsystem)>,
+    pipeline: Option<(Pipeline, Subsystem)>,
+    clock: Arc<dyn Clock>,
     _marker: std::marker::PhantomData<N>,
 }
 
@@ -234,9 +861,51 @@ pub struct Config {
     pub broadcast_interval: Option<Duration>,
     /// The interval of the direct-peer fetch. If none, it will fetch only in `fetch()`, not in `serve()`.
     pub fetch_interval: Option<Duration>,
+    /// The retention policy of the messages.
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    /// The interval of the compaction. If none, it will compact only in `compact()`, not in `serve()`.
+    #[serde(default)]
+    pub compaction_interval: Option<Duration>,
+    /// Whether to accept the messages pushed by the peers that are not reachable (see `nat`).
+    #[serde(default)]
+    pub serve_as_relay: bool,
+    /// The member to push the messages to on every fetch, if this node is not reachable.
+    #[serde(default)]
+    pub relay: Option<PublicKey>,
+    /// The limits on the peers, to throttle or ban those flooding the node.
+    #[serde(default)]
+    pub peer_score: PeerScoreConfig,
+    /// The epidemic gossip, which scales better than the full-mesh broadcast
+    /// `broadcast_interval` in a large network. If none, it doesn't gossip in `serve()`.
+    #[serde(default)]
+    pub gossip: Option<GossipConfig>,
+    /// The sentry/validator split; a validator behind sentries talks only to them.
+    #[serde(default)]
+    pub sentry: SentryConfig,
+}
+
+/// Limits on the messages that a message set keeps, so that a long-running node doesn't exhaust the disk.
+///
+/// Each instance of `DistributedMessageSet` (i.e., the governance, the consensus or the chat)
+/// has its own policy. The messages are dropped from the oldest received.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Drops the messages received longer ago than this.
+    pub ttl: Option<Duration>,
+    /// Keeps at most this number of the messages (e.g., to cap the chat history).
+    pub max_messages: Option<usize>,
+    /// Keeps the total size of the stored messages under this number of bytes.
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageUsage {
+    pub messages: usize,
+    pub bytes: u64,
 }
 
-impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
+impl<N, S: MessageStore> DistributedMessageSet<N, S> {
     /// Creates a new and empty storage with the given directory.
     /// If there is already a directory, it discards everything and creates a new one.
     /// You should try `open()` first!
@@ -244,8 +913,7 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
     /// - `dms_key`: The unique key for distinguishing the DMS instance
     /// among the networks and among the types (e.g. governance, consensus, ...).
     pub async fn create(mut storage: S, height: u64, dms_key: String) -> Result<(), Error> {
-        storage.remove_all_files().await?;
-        write_state(
+        reset(
             &mut storage,
             State {
                 height,
@@ -255,7 +923,9 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
         .await?;
         Ok(())
     }
+}
 
+impl<N: GossipNetwork, S: MessageStore> DistributedMessageSet<N, S> {
     /// Opens an existing storage with the given directory.
     pub async fn open(storage: S, config: Config) -> Result<Self, Error>
     where
@@ -263,6 +933,11 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
     {
         Ok(Self {
             storage: Arc::new(RwLock::new(storage)),
+            scores: PeerScores::new(config.peer_score.clone()),
+            gossip: Default::default(),
+            bandwidth: None,
+            pipeline: None,
+            clock: system_clock(),
             config,
             _marker: std::marker::PhantomData,
         })
@@ -274,23 +949,69 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
         _network_config: &NetworkConfig,
         known_peers: &[Peer],
     ) -> Result<(), Error> {
-        fetch(Arc::clone(&self.storage), _network_config, known_peers).await
+        fetch(
+            Arc::clone(&self.storage),
+            _network_config,
+            known_peers,
+            self.config.relay.as_ref(),
+            &self.config.sentry,
+            &self.scores,
+            self.bandwidth.as_ref(),
+            self.clock.as_ref(),
+        )
+        .await
+    }
+
+    /// Accounts the bytes transferred by this message set to the subsystem in the meter,
+    /// skipping the peers over the quotas in `fetch()`.
+    pub fn set_bandwidth_meter(&mut self, meter: BandwidthMeter, subsystem: Subsystem) {
+        self.bandwidth = Some((meter, subsystem));
+    }
+
+    /// Queues the messages received from the gossip network in the lane of the subsystem
+    /// in `serve()`, instead of storing them at once (see `pipeline`).
+    pub fn set_pipeline(&mut self, pipeline: Pipeline, subsystem: Subsystem) {
+        self.pipeline = Some((pipeline, subsystem));
+    }
+
+    /// Sets the clock that stamps the received messages for the retention policy
+    /// and paces the tasks of `serve()`, which is the system clock by default.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Returns the scores of the peers, which are shared with `serve()`.
+    pub fn peer_scores(&self) -> PeerScores {
+        self.scores.clone()
     }
 
     /// Adds the given message to the storage, immediately broadcasting it to the network.
     ///
     /// Note that it is guaranteed that the message will not be broadcasted unless it
     /// is successfully added to the storage. (but it is not guaranteed for the other way around)
+    ///
+    /// If the gossip is enabled, the message is also announced in the next gossip rounds.
     pub async fn add_message(
         &mut self,
         network_config: &NetworkConfig,
         known_peers: &[Peer],
         message: Message,
     ) -> Result<(), Error> {
-        add_message_but_not_broadcast(&mut *self.storage.write().await, message.clone()).await?;
+        let new = add_message_but_not_broadcast(
+            &mut *self.storage.write().await,
+            message.clone(),
+            self.clock.now(),
+        )
+        .await?;
+        if let (true, Some(gossip)) = (new, &self.config.gossip) {
+            self.gossip
+                .write()
+                .await
+                .insert(message.to_hash256(), gossip.forward_rounds);
+        }
         N::broadcast(
             network_config,
-            known_peers,
+            &self.config.sentry.restrict(known_peers),
             serde_json::to_vec(&message).unwrap(),
         )
         .await?;
@@ -303,6 +1024,51 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
         Ok(result)
     }
 
+    /// Returns a read-only handle to the messages, for observing them while serving.
+    pub fn reader(&self) -> MessageReader<S> {
+        MessageReader {
+            storage: Arc::clone(&self.storage),
+        }
+    }
+
+    /// Reports how much storage the messages take.
+    pub async fn usage(&self) -> Result<StorageUsage, Error> {
+        let messages = read_stored_messages(&*self.storage.read().await).await?;
+        Ok(StorageUsage {
+            messages: messages.len(),
+            bytes: messages.iter().map(|(_, _, size)| size).sum(),
+        })
+    }
+
+    /// Drops the messages that the retention policy doesn't allow, returning the number of them.
+    pub async fn compact(&mut self) -> Result<usize, Error> {
+        compact(
+            &mut *self.storage.write().await,
+            &self.config.retention,
+            self.clock.now(),
+        )
+        .await
+    }
+
+    /// Drops the messages that don't satisfy the predicate, returning the number of them.
+    ///
+    /// This is for the rules that depend on the content (e.g., the consensus messages of
+    /// the heights already finalized).
+    pub async fn retain(&mut self, predicate: impl Fn(&Message) -> bool) -> Result<usize, Error> {
+        let mut storage = self.storage.write().await;
+        let mut batch = Vec::new();
+        for (name, stored, _) in read_stored_messages(&*storage).await? {
+            if !predicate(&stored.message.into_message()?) {
+                batch.push(StoreOperation::Remove(name));
+            }
+        }
+        let count = batch.len();
+        if count > 0 {
+            storage.write_batch(batch).await?;
+        }
+        Ok(count)
+    }
+
     /// Reads the height from the storage.
     pub async fn read_height(&self) -> Result<BlockHeight, Error> {
         let state = read_state(&*self.storage.read().await).await?;
@@ -313,8 +1079,7 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
     pub async fn advance(&mut self) -> Result<(), Error> {
         let state = read_state(&*self.storage.read().await).await?;
         let mut storage = self.storage.write().await;
-        storage.remove_all_files().await?;
-        write_state(
+        reset(
             &mut *storage,
             State {
                 height: state.height + 1,
@@ -322,6 +1087,7 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
             },
         )
         .await?;
+        self.gossip.write().await.clear();
         Ok(())
     }
 
@@ -333,9 +1099,35 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
         peers: SharedKnownPeers,
     ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
         let mut recv = N::serve(network_config.clone(), peers.clone()).await?;
+        if let Some((pipeline, subsystem)) = &self.pipeline {
+            let storage_ = Arc::clone(&self.storage);
+            let clock_ = Arc::clone(&self.clock);
+            pipeline
+                .register(
+                    *subsystem,
+                    Arc::new(
+                        move |inbound: Inbound| -> BoxFuture<'static, Result<Processed, Error>> {
+                            let storage = Arc::clone(&storage_);
+                            let clock = Arc::clone(&clock_);
+                            Box::pin(async move {
+                                store_inbound(&storage, inbound, clock.as_ref()).await
+                            })
+                        },
+                    ),
+                )
+                .await;
+        }
+        let pipeline = self.pipeline.clone();
         let storage_ = Arc::clone(&self.storage);
         let peers_ = peers.clone();
         let network_config_ = network_config.clone();
+        let anti_entropy_network_config = network_config.clone();
+        let chain_id = network_config.chain_id.clone();
+        let relay = self.config.relay.clone();
+        let sentry = self.config.sentry.clone();
+        let scores = self.scores.clone();
+        let bandwidth = self.bandwidth.clone();
+        let clock = Arc::clone(&self.clock);
         let fetch_task = async move {
             let interval = if let Some(x) = self.config.fetch_interval {
                 x
@@ -344,12 +1136,24 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
             };
             loop {
                 let peers = peers_.read().await;
-                fetch(Arc::clone(&storage_), &network_config_, &peers).await?;
-                tokio::time::sleep(interval).await;
+                fetch(
+                    Arc::clone(&storage_),
+                    &network_config_,
+                    &peers,
+                    relay.as_ref(),
+                    &sentry,
+                    &scores,
+                    bandwidth.as_ref(),
+                    clock.as_ref(),
+                )
+                .await?;
+                clock.sleep(interval).await;
             }
         };
         let storage_ = Arc::clone(&self.storage);
         let peers_ = peers.clone();
+        let sentry = self.config.sentry.clone();
+        let clock = Arc::clone(&self.clock);
         let broadcast_task = async move {
             let interval = if let Some(x) = self.config.broadcast_interval {
                 x
@@ -357,7 +1161,7 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
                 return Result::<(), Error>::Ok(());
             };
             loop {
-                let peers = peers_.read().await;
+                let peers = sentry.restrict(&peers_.read().await);
                 let messages = read_messages(&*storage_.read().await).await?;
                 let tasks = messages.into_iter().map(|message| {
                     let network_config = network_config.clone();
@@ -373,17 +1177,27 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
                     }
                 });
                 join_all(tasks).await;
-                tokio::time::sleep(interval).await;
+                clock.sleep(interval).await;
             }
         };
         let storage_ = Arc::clone(&self.storage);
+        let clock = Arc::clone(&self.clock);
         let gossip_serve_task = async move {
             while let Some(m) = recv.0.recv().await {
+                if let Some((pipeline, subsystem)) = &pipeline {
+                    let height = read_state(&*storage_.read().await).await?.height;
+                    pipeline.push(*subsystem, Inbound { height, data: m }).await;
+                    continue;
+                }
                 match serde_json::from_slice::<RawMessage>(&m) {
                     Ok(raw_message) => {
                         let message = raw_message.into_message()?;
-                        add_message_but_not_broadcast(&mut *storage_.write().await, message)
-                            .await?;
+                        add_message_but_not_broadcast(
+                            &mut *storage_.write().await,
+                            message,
+                            clock.now(),
+                        )
+                        .await?;
                     }
                     Err(e) => {
                         log::warn!("failed to parse message from the gossip network: {}", e);
@@ -392,13 +1206,103 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
             }
             Result::<(), Error>::Ok(())
         };
+        let storage_ = Arc::clone(&self.storage);
+        let retention = self.config.retention.clone();
+        let clock = Arc::clone(&self.clock);
+        let compaction_task = async move {
+            let interval = if let Some(x) = self.config.compaction_interval {
+                x
+            } else {
+                return Result::<(), Error>::Ok(());
+            };
+            loop {
+                let dropped =
+                    compact(&mut *storage_.write().await, &retention, clock.now()).await?;
+                if dropped > 0 {
+                    log::info!("dropped {} messages by the retention policy", dropped);
+                }
+                clock.sleep(interval).await;
+            }
+        };
+        let storage_ = Arc::clone(&self.storage);
+        let gossip_state = Arc::clone(&self.gossip);
+        let peers_ = peers.clone();
+        let gossip_config = self.config.gossip.clone();
+        let gossip_chain_id = chain_id.clone();
+        let sentry = self.config.sentry.clone();
+        let clock = Arc::clone(&self.clock);
+        let gossip_task = async move {
+            let gossip_config = if let Some(x) = gossip_config {
+                x
+            } else {
+                return Result::<(), Error>::Ok(());
+            };
+            loop {
+                let peers = sentry.restrict(&peers_.read().await);
+                gossip_round(
+                    Arc::clone(&storage_),
+                    &gossip_chain_id,
+                    &gossip_state,
+                    &peers,
+                    gossip_config.fanout,
+                )
+                .await;
+                clock.sleep(gossip_config.interval).await;
+            }
+        };
+        let storage_ = Arc::clone(&self.storage);
+        let peers_ = peers.clone();
+        let gossip_config = self.config.gossip.clone();
+        let scores = self.scores.clone();
+        let bandwidth = self.bandwidth.clone();
+        let sentry = self.config.sentry.clone();
+        let clock = Arc::clone(&self.clock);
+        let anti_entropy_task = async move {
+            let interval = if let Some(x) = gossip_config {
+                x.anti_entropy_interval
+            } else {
+                return Result::<(), Error>::Ok(());
+            };
+            loop {
+                clock.sleep(interval).await;
+                // Reconciles the whole set with a random peer.
+                let peers = select_peers(&sentry.restrict(&peers_.read().await), 1);
+                fetch(
+                    Arc::clone(&storage_),
+                    &anti_entropy_network_config,
+                    &peers,
+                    None,
+                    &SentryConfig::default(),
+                    &scores,
+                    bandwidth.as_ref(),
+                    clock.as_ref(),
+                )
+                .await?;
+            }
+        };
         let storage = Arc::clone(&self.storage);
+        let gossip = Arc::clone(&self.gossip);
+        let forward_rounds = self
+            .config
+            .gossip
+            .as_ref()
+            .map_or(0, |gossip| gossip.forward_rounds);
+        let validators = self.config.sentry.validators.clone();
+        let clock = Arc::clone(&self.clock);
         let rpc_task = async move {
             run_server(
                 rpc_port,
                 [(
                     "x".to_owned(),
-                    create_http_object(Arc::new(StorageWrapper { storage })
+                    create_http_object(Arc::new(StorageWrapper {
+                        storage,
+                        chain_id,
+                        serve_as_relay: self.config.serve_as_relay,
+                        gossip,
+                        forward_rounds,
+                        validators,
+                        clock,
+                    })
                         as Arc<dyn DistributedMessageSetRpcInterface>),
                 )]
                 .iter()
@@ -409,7 +1313,15 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
             Ok(())
         };
         Ok(tokio::spawn(async move {
-            let x = try_join!(rpc_task, gossip_serve_task, broadcast_task, fetch_task);
+            let x = try_join!(
+                rpc_task,
+                gossip_serve_task,
+                broadcast_task,
+                fetch_task,
+                compaction_task,
+                gossip_task,
+                anti_entropy_task
+            );
             match x {
                 Ok(_) => Ok(()),
                 Err(e) => Err(e),
@@ -417,3 +1329,39 @@ impl<N: GossipNetwork, S: Storage> DistributedMessageSet<N, S> {
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<(String, Timestamp, u64)> {
+        (0..5)
+            .map(|i| (format!("{}.json", i), i * 1000, 10))
+            .collect()
+    }
+
+    #[test]
+    fn retention() {
+        let policy = RetentionPolicy::default();
+        assert!(select_messages_to_drop(messages(), &policy, 10_000).is_empty());
+
+        let policy = RetentionPolicy {
+            ttl: Some(Duration::from_secs(2)),
+            ..Default::default()
+        };
+        assert_eq!(
+            select_messages_to_drop(messages(), &policy, 4500),
+            vec!["0.json", "1.json", "2.json"]
+        );
+
+        let policy = RetentionPolicy {
+            max_messages: Some(3),
+            max_bytes: Some(25),
+            ..Default::default()
+        };
+        assert_eq!(
+            select_messages_to_drop(messages(), &policy, 0),
+            vec!["0.json", "1.json", "2.json"]
+        );
+    }
+}
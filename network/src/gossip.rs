@@ -0,0 +1,94 @@
+//! The epidemic gossip for the propagation of the DMS messages.
+//!
+//! Instead of broadcasting every message to every peer, in each round a node
+//! 1. picks `fanout` peers at random,
+//! 2. tells them the digests of the messages it learned recently (`IHAVE`),
+//! 3. and sends the messages that they ask for (`IWANT`).
+//!
+//! A message is forwarded for `forward_rounds` rounds after it is learned.
+//! Those missed by the gossip are caught up by the anti-entropy rounds,
+//! which reconcile the whole set with a random peer.
+use super::*;
+use rand::seq::SliceRandom;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GossipConfig {
+    /// The number of the peers to gossip to in each round.
+    pub fanout: usize,
+    /// The interval of the gossip rounds.
+    pub interval: Duration,
+    /// The number of the rounds that a message is forwarded for.
+    pub forward_rounds: usize,
+    /// The interval of the anti-entropy rounds.
+    pub anti_entropy_interval: Duration,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            fanout: 4,
+            interval: Duration::from_secs(1),
+            forward_rounds: 3,
+            anti_entropy_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The messages to forward, with the number of the remaining rounds.
+#[derive(Debug, Clone, Default)]
+pub struct GossipState {
+    recent: HashMap<Hash256, usize>,
+}
+
+impl GossipState {
+    /// Marks a message as newly learned, to be forwarded in the next rounds.
+    pub fn insert(&mut self, digest: Hash256, forward_rounds: usize) {
+        self.recent.entry(digest).or_insert(forward_rounds);
+    }
+
+    /// Starts a round, returning the digests to announce.
+    pub fn next_round(&mut self) -> Vec<Hash256> {
+        let mut digests: Vec<_> = self.recent.keys().cloned().collect();
+        digests.sort();
+        for rounds in self.recent.values_mut() {
+            *rounds -= 1;
+        }
+        self.recent.retain(|_, rounds| *rounds > 0);
+        digests
+    }
+
+    pub fn clear(&mut self) {
+        self.recent.clear();
+    }
+}
+
+/// Picks at most `count` peers at random.
+pub fn select_peers(peers: &[Peer], count: usize) -> Vec<Peer> {
+    peers
+        .choose_multiple(&mut rand::thread_rng(), count)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forward_for_the_rounds() {
+        let mut state = GossipState::default();
+        let a = Hash256::hash("a");
+        let b = Hash256::hash("b");
+        state.insert(a, 2);
+        assert_eq!(state.next_round(), vec![a]);
+        state.insert(b, 2);
+        // Already known; the remaining rounds are not reset.
+        state.insert(a, 2);
+        let mut expected = vec![a, b];
+        expected.sort();
+        assert_eq!(state.next_round(), expected);
+        assert_eq!(state.next_round(), vec![b]);
+        assert!(state.next_round().is_empty());
+    }
+}
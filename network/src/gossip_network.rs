@@ -0,0 +1,160 @@
+//! The `GossipNetwork` over TCP (see `transport::TcpTransport`).
+//!
+//! A node listens on `NetworkConfig::port`, and a message is sent to the port of
+//! `GOSSIP_PORT_KEY` of every known peer. A peer relays the message to its own known peers
+//! when it receives the message for the first time,
+//! so that it reaches those unknown to the sender.
+use super::*;
+use crate::transport::{Handler, TcpTransport, Transport};
+use futures::future::{join_all, BoxFuture};
+use std::collections::{HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tokio::sync::mpsc;
+
+/// The key of `Peer::ports` for the port of the gossip network.
+pub const GOSSIP_PORT_KEY: &str = "gossip";
+/// The number of the recent messages remembered, not to relay them again.
+const SEEN_CAPACITY: usize = 65536;
+/// The number of the received messages that may be left unread before the receiving waits.
+const INBOX_CAPACITY: usize = 1024;
+
+pub struct TcpGossipNetwork;
+
+/// The hashes of the recently received messages, the oldest forgotten first.
+#[derive(Default)]
+struct Seen {
+    set: HashSet<Hash256>,
+    order: VecDeque<Hash256>,
+}
+
+impl Seen {
+    /// Returns whether the message is new.
+    fn insert(&mut self, message: &[u8]) -> bool {
+        let hash = Hash256::hash(message);
+        if !self.set.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.set.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Sends the message to the known peers that have the gossip port, logging the failures.
+async fn send(known_peers: &[Peer], message: &[u8]) {
+    join_all(known_peers.iter().filter_map(|peer| {
+        let port = *peer.ports.get(GOSSIP_PORT_KEY)?;
+        let address = SocketAddr::from(SocketAddrV4::new(*peer.address.ip(), port));
+        Some(async move {
+            if let Err(e) = TcpTransport.request(address, message.to_vec()).await {
+                log::warn!("failed to gossip to {}: {}", peer.public_key, e);
+            }
+        })
+    }))
+    .await;
+}
+
+#[async_trait]
+impl GossipNetwork for TcpGossipNetwork {
+    async fn broadcast(
+        _config: &NetworkConfig,
+        known_peers: &[Peer],
+        message: Vec<u8>,
+    ) -> Result<(), Error> {
+        send(known_peers, &message).await;
+        Ok(())
+    }
+
+    async fn serve(
+        config: NetworkConfig,
+        peers: SharedKnownPeers,
+    ) -> Result<
+        (
+            mpsc::Receiver<Vec<u8>>,
+            tokio::task::JoinHandle<Result<(), Error>>,
+        ),
+        Error,
+    > {
+        let port = config
+            .port
+            .ok_or_else(|| anyhow::anyhow!("the port of the gossip network is not configured"))?;
+        let (sender, receiver) = mpsc::channel(INBOX_CAPACITY);
+        let seen = Arc::new(Mutex::new(Seen::default()));
+        let handler: Handler = Arc::new(move |message: Vec<u8>| -> BoxFuture<'static, Vec<u8>> {
+            let sender = sender.clone();
+            let peers = peers.clone();
+            let seen = Arc::clone(&seen);
+            Box::pin(async move {
+                if seen.lock().expect("poisoned").insert(&message) {
+                    let relayed = message.clone();
+                    tokio::spawn(async move { send(&peers.read().await, &relayed).await });
+                    if sender.send(message).await.is_err() {
+                        log::debug!("dropped a gossip message; the receiver is closed");
+                    }
+                }
+                Vec::new()
+            })
+        });
+        let handle = TcpTransport.serve(port, handler).await?;
+        Ok((receiver, handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(port: u16) -> NetworkConfig {
+        let (public_key, private_key) = generate_keypair(format!("node-{}", port));
+        NetworkConfig {
+            network_id: "test".to_owned(),
+            chain_id: ChainId::default(),
+            port: Some(port),
+            members: Vec::new(),
+            public_key,
+            private_key,
+        }
+    }
+
+    fn peer(port: u16) -> Peer {
+        Peer {
+            public_key: generate_keypair(format!("node-{}", port)).0,
+            address: "127.0.0.1:0".parse().unwrap(),
+            ports: [(GOSSIP_PORT_KEY.to_owned(), port)].into_iter().collect(),
+            message: String::new(),
+            recently_seen_timestamp: 0,
+            relay: None,
+            height: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn relay() {
+        // `a` knows only `b`, which relays to `c`.
+        let (mut b, _) =
+            TcpGossipNetwork::serve(config(56301), SharedKnownPeers::new(vec![peer(56302)]))
+                .await
+                .unwrap();
+        let (mut c, _) = TcpGossipNetwork::serve(config(56302), SharedKnownPeers::new(Vec::new()))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        for _ in 0..2 {
+            TcpGossipNetwork::broadcast(&config(56300), &[peer(56301)], vec![1, 2, 3])
+                .await
+                .unwrap();
+        }
+        assert_eq!(b.recv().await.unwrap(), vec![1, 2, 3]);
+        assert_eq!(c.recv().await.unwrap(), vec![1, 2, 3]);
+        // The duplicate is not received again.
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(b.try_recv().is_err());
+        assert!(c.try_recv().is_err());
+    }
+}
@@ -1,12 +1,26 @@
+pub mod bandwidth;
+pub mod clock;
 pub mod dms;
-mod peer_discovery;
+pub mod gossip;
+pub mod gossip_network;
+pub mod mdns;
+pub mod message_store;
+pub mod nat;
+pub mod peer_discovery;
+pub mod peer_score;
+pub mod pipeline;
 pub mod primitives;
+pub mod reconciliation;
+pub mod sentry;
+pub mod signer;
 pub mod storage;
+pub mod streaming;
+pub mod transport;
 
 use async_trait::async_trait;
 use primitives::*;
 use serde::{Deserialize, Serialize};
-use simperby_common::{crypto::*, Timestamp};
+use simperby_common::{crypto::*, BlockHeight, Timestamp};
 use std::collections::HashMap;
 use std::{net::SocketAddrV4, sync::Arc};
 use tokio::sync::RwLock;
@@ -24,12 +38,52 @@ pub struct Peer {
     pub ports: HashMap<String, u16>,
     pub message: String,
     pub recently_seen_timestamp: Timestamp,
+    /// The member relaying the messages of this peer, if it is not reachable (see `nat`).
+    #[serde(default)]
+    pub relay: Option<PublicKey>,
+    /// The last finalized height that the peer reported, if any.
+    #[serde(default)]
+    pub height: Option<BlockHeight>,
+}
+
+/// Identifies the chain that the network is of, attached to every handshake and DMS request
+/// so that the nodes of different chains can't talk to each other by accident.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChainId {
+    pub chain_name: String,
+    /// The hash of the genesis commit.
+    pub genesis_hash: Hash256,
+}
+
+impl Default for ChainId {
+    fn default() -> Self {
+        Self {
+            chain_name: String::new(),
+            genesis_hash: Hash256::zero(),
+        }
+    }
+}
+
+impl ChainId {
+    /// Checks that the remote is of the same chain.
+    pub fn check(&self, remote: &ChainId) -> Result<(), String> {
+        if self != remote {
+            return Err(format!(
+                "rejected a cross-chain peer: this node is of the chain {} (genesis {}), \
+                but the peer is of the chain {} (genesis {})",
+                self.chain_name, self.genesis_hash, remote.chain_name, remote.genesis_hash
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
     /// The unique id for distinguishing the network.
     pub network_id: String,
+    /// The chain that the network is of.
+    pub chain_id: ChainId,
     /// The port that will be used during a network operation.
     pub port: Option<u16>,
     /// The set of the members of the network.
@@ -48,6 +102,13 @@ pub struct SharedKnownPeers {
 }
 
 impl SharedKnownPeers {
+    /// Creates a fixed set of the known peers, not updated by any discovery (e.g., for tests).
+    pub fn new(peers: Vec<Peer>) -> Self {
+        Self {
+            lock: Arc::new(RwLock::new(peers)),
+        }
+    }
+
     pub async fn read(&self) -> Vec<Peer> {
         self.lock.read().await.clone()
     }
@@ -0,0 +1,135 @@
+//! The discovery of the peers on the local network with mDNS, for the development clusters.
+//!
+//! Each node advertises its discovery and DMS endpoints with its chain identifier,
+//! and adds the nodes of the same chain that it finds to the bootstrap addresses of the peer discovery.
+//! It must be enabled only in the development mode; mDNS is unauthenticated,
+//! though the peers found are still verified by the peer discovery with the member keys.
+use super::*;
+use crate::peer_discovery::PeerDiscoveryImpl;
+use std::net::Ipv4Addr;
+
+pub const SERVICE_TYPE: &str = "_simperby._tcp.local.";
+
+/// What a node advertises on the local network.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Advertisement {
+    /// The identifier of the chain; the nodes of the other chains are ignored.
+    pub chain: String,
+    pub public_key: PublicKey,
+    pub discovery_port: u16,
+    pub dms_port: Option<u16>,
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    if hex.len() % 2 != 0 {
+        return Err(anyhow::anyhow!("invalid hex: {}", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
+
+impl Advertisement {
+    fn to_properties(&self) -> HashMap<String, String> {
+        let mut properties = HashMap::new();
+        properties.insert("chain".to_owned(), self.chain.clone());
+        properties.insert(
+            "public_key".to_owned(),
+            encode_hex(self.public_key.as_ref()),
+        );
+        properties.insert("discovery".to_owned(), self.discovery_port.to_string());
+        if let Some(port) = self.dms_port {
+            properties.insert("dms".to_owned(), port.to_string());
+        }
+        properties
+    }
+
+    fn from_properties(get: impl Fn(&str) -> Option<String>) -> Result<Self, Error> {
+        let get_ = |key: &str| get(key).ok_or_else(|| anyhow::anyhow!("missing {}", key));
+        Ok(Self {
+            chain: get_("chain")?,
+            public_key: PublicKey::from_bytes(&decode_hex(&get_("public_key")?)?)?,
+            discovery_port: get_("discovery")?.parse()?,
+            dms_port: get("dms").map(|port| port.parse()).transpose()?,
+        })
+    }
+}
+
+/// Advertises this node and browses the others indefinitely,
+/// adding those of the same chain to the bootstrap addresses in the storage of the peer discovery.
+pub async fn serve(
+    peer_directory: String,
+    advertisement: Advertisement,
+) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
+    let daemon = mdns_sd::ServiceDaemon::new()?;
+    let instance_name = format!(
+        "simperby-{}",
+        &encode_hex(advertisement.public_key.as_ref())[..16]
+    );
+    let service = mdns_sd::ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance_name,
+        &format!("simperby-{}.local.", advertisement.discovery_port),
+        "",
+        advertisement.discovery_port,
+        Some(advertisement.to_properties()),
+    )?
+    .enable_addr_auto();
+    daemon.register(service)?;
+    let receiver = daemon.browse(SERVICE_TYPE)?;
+    Ok(tokio::spawn(async move {
+        // Keeps the daemon alive while browsing.
+        let _daemon = daemon;
+        while let Ok(event) = receiver.recv_async().await {
+            if let mdns_sd::ServiceEvent::ServiceResolved(info) = event {
+                let found = match Advertisement::from_properties(|key| {
+                    info.get_property_val_str(key).map(|value| value.to_owned())
+                }) {
+                    Ok(found) => found,
+                    Err(e) => {
+                        log::warn!("invalid mDNS advertisement {}: {}", info.get_fullname(), e);
+                        continue;
+                    }
+                };
+                if found.chain != advertisement.chain
+                    || found.public_key == advertisement.public_key
+                {
+                    continue;
+                }
+                let addresses: Vec<_> = info
+                    .get_addresses()
+                    .iter()
+                    .map(|ip: &Ipv4Addr| SocketAddrV4::new(*ip, found.discovery_port))
+                    .collect();
+                log::info!("found a local peer {} at {:?}", found.public_key, addresses);
+                PeerDiscoveryImpl::add_bootstrap_addresses(&peer_directory, addresses).await?;
+            }
+        }
+        Ok(())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn properties() {
+        let advertisement = Advertisement {
+            chain: "devnet".to_owned(),
+            public_key: generate_keypair("a").0,
+            discovery_port: 5000,
+            dms_port: Some(5001),
+        };
+        let properties = advertisement.to_properties();
+        assert_eq!(
+            Advertisement::from_properties(|key| properties.get(key).cloned()).unwrap(),
+            advertisement
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::primitives::{MessageStore, StorageError, StoreOperation};
+use async_trait::async_trait;
+
+/// The message store backed by `sled`, an embedded database.
+///
+/// Every batch is applied atomically and flushed before returning,
+/// so the store recovers to the last completed batch after an unclean shutdown.
+///
+/// Unlike `StorageImpl`, opening a store that is already open fails instead of waiting.
+pub struct SledMessageStore {
+    db: sled::Db,
+}
+
+fn to_storage_error(error: sled::Error) -> StorageError {
+    std::io::Error::new(std::io::ErrorKind::Other, error)
+}
+
+fn to_string(value: sled::IVec) -> Result<String, StorageError> {
+    String::from_utf8(value.to_vec())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+#[async_trait]
+impl MessageStore for SledMessageStore {
+    async fn create(storage_directory: &str) -> Result<(), StorageError> {
+        let _ = tokio::fs::remove_dir_all(storage_directory).await;
+        let db = sled::open(storage_directory).map_err(to_storage_error)?;
+        db.flush_async().await.map_err(to_storage_error)?;
+        Ok(())
+    }
+
+    async fn open(storage_directory: &str) -> Result<Self, StorageError> {
+        let storage_directory = storage_directory.to_owned();
+        let db = tokio::task::spawn_blocking(move || sled::open(storage_directory))
+            .await?
+            .map_err(to_storage_error)?;
+        Ok(Self { db })
+    }
+
+    async fn read(&self, key: &str) -> Result<Option<String>, StorageError> {
+        self.db
+            .get(key)
+            .map_err(to_storage_error)?
+            .map(to_string)
+            .transpose()
+    }
+
+    async fn read_all(&self) -> Result<Vec<(String, String)>, StorageError> {
+        self.db
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry.map_err(to_storage_error)?;
+                Ok((to_string(key)?, to_string(value)?))
+            })
+            .collect()
+    }
+
+    async fn write_batch(&mut self, batch: Vec<StoreOperation>) -> Result<(), StorageError> {
+        let mut sled_batch = sled::Batch::default();
+        // `sled::Batch` keeps only the last operation for each key.
+        for operation in batch {
+            match operation {
+                StoreOperation::Put(key, value) => {
+                    sled_batch.insert(key.as_bytes(), value.as_bytes())
+                }
+                StoreOperation::Remove(key) => sled_batch.remove(key.as_bytes()),
+                StoreOperation::RemoveAll => {
+                    sled_batch = sled::Batch::default();
+                    for key in self.db.iter().keys() {
+                        sled_batch.remove(key.map_err(to_storage_error)?);
+                    }
+                }
+            }
+        }
+        self.db.apply_batch(sled_batch).map_err(to_storage_error)?;
+        self.db.flush_async().await.map_err(to_storage_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage_directory(name: &str) -> String {
+        format!(
+            "{}/simperby-message-store-{}-{}",
+            std::env::temp_dir().to_str().unwrap(),
+            name,
+            std::process::id()
+        )
+    }
+
+    #[tokio::test]
+    async fn batch_and_reopen() {
+        let dir = storage_directory("batch");
+        SledMessageStore::create(&dir).await.unwrap();
+        let mut store = SledMessageStore::open(&dir).await.unwrap();
+        store
+            .write_batch(vec![
+                StoreOperation::Put("a".to_owned(), "1".to_owned()),
+                StoreOperation::Put("b".to_owned(), "2".to_owned()),
+            ])
+            .await
+            .unwrap();
+        store
+            .write_batch(vec![
+                StoreOperation::RemoveAll,
+                StoreOperation::Put("a".to_owned(), "3".to_owned()),
+            ])
+            .await
+            .unwrap();
+        drop(store);
+
+        let store = SledMessageStore::open(&dir).await.unwrap();
+        assert_eq!(store.read("a").await.unwrap(), Some("3".to_owned()));
+        assert_eq!(store.read("b").await.unwrap(), None);
+        assert_eq!(store.read_all().await.unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,152 @@
+//! NAT traversal for the nodes behind a NAT.
+//!
+//! On startup, a node tries to map its port on the gateway, first with UPnP and then with NAT-PMP.
+//! If both fail, it can still take part through a relay: a publicly reachable member
+//! that accepts the DMS messages pushed by the node (see `dms::Config::relay`).
+use super::*;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// How long a mapping lasts; it must be renewed before then.
+pub const MAPPING_LIFETIME: Duration = Duration::from_secs(3600);
+const NAT_PMP_PORT: u16 = 5351;
+const NAT_PMP_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NatConfig {
+    /// Whether to try mapping the port on startup.
+    pub port_mapping: bool,
+    /// The gateway for NAT-PMP. If none, only UPnP is tried.
+    pub nat_pmp_gateway: Option<Ipv4Addr>,
+    /// The member to relay the messages through, if the port can't be mapped.
+    pub relay: Option<PublicKey>,
+    /// Whether to relay the messages for the others; only for a publicly reachable member.
+    pub serve_as_relay: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MappingMethod {
+    Upnp,
+    NatPmp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NatStatus {
+    /// Reachable without any mapping.
+    Public,
+    Mapped {
+        method: MappingMethod,
+        external_address: SocketAddrV4,
+    },
+    /// Not reachable; the messages are relayed by the member.
+    Relayed { relay: PublicKey },
+}
+
+/// Maps the TCP port on the gateway to the same port, returning the external address.
+pub async fn map_port(
+    port: u16,
+    nat_pmp_gateway: Option<Ipv4Addr>,
+) -> Result<(MappingMethod, SocketAddrV4), Error> {
+    let upnp_error = match map_port_with_upnp(port).await {
+        Ok(address) => return Ok((MappingMethod::Upnp, address)),
+        Err(e) => e,
+    };
+    match nat_pmp_gateway {
+        Some(gateway) => map_port_with_nat_pmp(gateway, port)
+            .await
+            .map(|address| (MappingMethod::NatPmp, address))
+            .map_err(|e| anyhow::anyhow!("UPnP: {}, NAT-PMP: {}", upnp_error, e)),
+        None => Err(upnp_error),
+    }
+}
+
+async fn map_port_with_upnp(port: u16) -> Result<SocketAddrV4, Error> {
+    let gateway = igd::aio::search_gateway(Default::default()).await?;
+    let local_ip = local_ip_towards(gateway.addr.into()).await?;
+    gateway
+        .add_port(
+            igd::PortMappingProtocol::TCP,
+            port,
+            SocketAddrV4::new(local_ip, port),
+            MAPPING_LIFETIME.as_secs() as u32,
+            "simperby",
+        )
+        .await?;
+    let external_ip = gateway.get_external_ip().await?;
+    Ok(SocketAddrV4::new(external_ip, port))
+}
+
+/// Finds the local address that the packets to the given address are sent from.
+async fn local_ip_towards(address: SocketAddr) -> Result<Ipv4Addr, Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(address).await?;
+    match socket.local_addr()? {
+        SocketAddr::V4(local) => Ok(*local.ip()),
+        SocketAddr::V6(local) => Err(anyhow::anyhow!("not an IPv4 address: {}", local)),
+    }
+}
+
+/// Sends a NAT-PMP request (RFC 6886), returning the response of the expected opcode.
+async fn nat_pmp_request(gateway: Ipv4Addr, request: &[u8]) -> Result<Vec<u8>, Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket
+        .connect(SocketAddrV4::new(gateway, NAT_PMP_PORT))
+        .await?;
+    socket.send(request).await?;
+    let mut buffer = [0u8; 16];
+    let size = tokio::time::timeout(NAT_PMP_TIMEOUT, socket.recv(&mut buffer)).await??;
+    let response = &buffer[..size];
+    if size < 8 || response[0] != 0 || response[1] != request[1] + 128 {
+        return Err(anyhow::anyhow!("invalid NAT-PMP response"));
+    }
+    let result = u16::from_be_bytes([response[2], response[3]]);
+    if result != 0 {
+        return Err(anyhow::anyhow!(
+            "NAT-PMP failed with the result code {}",
+            result
+        ));
+    }
+    Ok(response.to_vec())
+}
+
+async fn map_port_with_nat_pmp(gateway: Ipv4Addr, port: u16) -> Result<SocketAddrV4, Error> {
+    let response = nat_pmp_request(gateway, &[0, 0]).await?;
+    if response.len() < 12 {
+        return Err(anyhow::anyhow!("invalid NAT-PMP response"));
+    }
+    let external_ip = Ipv4Addr::new(response[8], response[9], response[10], response[11]);
+
+    let mut request = vec![0, 2, 0, 0];
+    request.extend(port.to_be_bytes());
+    request.extend(port.to_be_bytes());
+    request.extend((MAPPING_LIFETIME.as_secs() as u32).to_be_bytes());
+    let response = nat_pmp_request(gateway, &request).await?;
+    if response.len() < 16 {
+        return Err(anyhow::anyhow!("invalid NAT-PMP response"));
+    }
+    let external_port = u16::from_be_bytes([response[10], response[11]]);
+    Ok(SocketAddrV4::new(external_ip, external_port))
+}
+
+/// Sets up the NAT traversal for the port, on startup.
+pub async fn setup(config: &NatConfig, port: u16) -> Result<NatStatus, Error> {
+    if !config.port_mapping {
+        return Ok(NatStatus::Public);
+    }
+    match map_port(port, config.nat_pmp_gateway).await {
+        Ok((method, external_address)) => Ok(NatStatus::Mapped {
+            method,
+            external_address,
+        }),
+        Err(e) => match &config.relay {
+            Some(relay) => {
+                log::warn!("failed to map the port; falling back to the relay: {}", e);
+                Ok(NatStatus::Relayed {
+                    relay: relay.clone(),
+                })
+            }
+            None => Err(e),
+        },
+    }
+}
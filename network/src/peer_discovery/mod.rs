@@ -1,26 +1,365 @@
 mod primitive;
+pub mod record;
 #[cfg(test)]
 mod tests;
 
 use super::*;
 use async_trait::async_trait;
+use record::*;
+use serde_tc::http::*;
+use serde_tc::{serde_tc_full, StubCall};
+use std::collections::BTreeMap;
+use std::time::Duration;
 
+const STATE_FILE_PATH: &str = "discovery.json";
+/// The interval of exchanging the records with the known peers.
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(10);
+/// How recently a peer must have been seen to be regarded as alive (three discovery intervals).
+pub const LIVENESS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A peer added by the operator, contacted in every discovery round until removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaticPeer {
+    pub public_key: PublicKey,
+    /// The address of the discovery protocol.
+    pub address: SocketAddrV4,
+}
+
+/// What a node keeps for the discovery protocol.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct DiscoveryState {
+    /// The addresses that this node advertises in its own record.
+    advertised_addresses: Vec<SocketAddrV4>,
+    advertised_ports: BTreeMap<String, u16>,
+    /// The addresses to contact first, whose keys may not be known yet.
+    bootstrap_addresses: Vec<SocketAddrV4>,
+    /// The member relaying the messages of this node, if it is not reachable.
+    #[serde(default)]
+    relay: Option<PublicKey>,
+    #[serde(default)]
+    static_peers: Vec<StaticPeer>,
+    /// The last finalized height of this node, advertised in its own record.
+    #[serde(default)]
+    height: Option<BlockHeight>,
+    book: PeerBook,
+}
+
+async fn read_state(storage_directory: &str) -> Result<DiscoveryState, Error> {
+    let data =
+        tokio::fs::read_to_string(format!("{}/{}", storage_directory, STATE_FILE_PATH)).await?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+async fn write_state(storage_directory: &str, state: &DiscoveryState) -> Result<(), Error> {
+    tokio::fs::write(
+        format!("{}/{}", storage_directory, STATE_FILE_PATH),
+        serde_json::to_string(state)?,
+    )
+    .await?;
+    Ok(())
+}
+
+fn get_timestamp() -> Timestamp {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+    since_the_epoch.as_millis() as Timestamp
+}
+
+/// The interface that will be wrapped into an HTTP RPC server for the peers.
+#[serde_tc_full]
+trait PeerDiscoveryRpcInterface: Send + Sync + 'static {
+    /// Takes the records that the caller knows, returning those that this node knows.
+    ///
+    /// It fails if the caller is of another chain.
+    async fn exchange(
+        &self,
+        chain_id: ChainId,
+        records: Vec<SignedPeerRecord>,
+    ) -> Result<Vec<SignedPeerRecord>, String>;
+
+    /// Returns the own record of this node.
+    ///
+    /// It fails if the caller is of another chain.
+    async fn ping(&self, chain_id: ChainId) -> Result<SignedPeerRecord, String>;
+}
+
+struct PeerBookWrapper {
+    public_key: PublicKey,
+    book: Arc<RwLock<PeerBook>>,
+    members: Vec<PublicKey>,
+    chain_id: ChainId,
+}
+
+#[async_trait]
+impl PeerDiscoveryRpcInterface for PeerBookWrapper {
+    async fn exchange(
+        &self,
+        chain_id: ChainId,
+        records: Vec<SignedPeerRecord>,
+    ) -> Result<Vec<SignedPeerRecord>, String> {
+        self.chain_id.check(&chain_id)?;
+        let mut book = self.book.write().await;
+        for record in records {
+            book.insert(record, &self.members);
+        }
+        Ok(book.records())
+    }
+
+    async fn ping(&self, chain_id: ChainId) -> Result<SignedPeerRecord, String> {
+        self.chain_id.check(&chain_id)?;
+        self.book
+            .read()
+            .await
+            .get(&self.public_key)
+            .cloned()
+            .ok_or_else(|| "the own record is not ready yet".to_owned())
+    }
+}
+
+/// Exchanges the records with a peer at the given address.
+async fn exchange(
+    address: SocketAddrV4,
+    chain_id: &ChainId,
+    records: Vec<SignedPeerRecord>,
+) -> Result<Vec<SignedPeerRecord>, Error> {
+    let stub = PeerDiscoveryRpcInterfaceStub::new(Box::new(HttpClient::new(
+        format!("http://{}/discovery", address),
+        reqwest::Client::new(),
+    )));
+    stub.exchange(chain_id.clone(), records)
+        .await?
+        .map_err(|e| anyhow::anyhow!(e))
+}
+
+/// Exchanges the records with the known peers periodically, keeping the storage
+/// and the shared known peers up to date.
+async fn discover(
+    storage_directory: String,
+    network_config: NetworkConfig,
+    mut state: DiscoveryState,
+    book: Arc<RwLock<PeerBook>>,
+    known_peers: SharedKnownPeers,
+) -> Result<(), Error> {
+    let members = &network_config.members;
+    loop {
+        // The settings may have been changed on the storage while serving (e.g., by the CLI).
+        let stored = read_state(&storage_directory).await?;
+        state = DiscoveryState {
+            book: state.book,
+            ..stored
+        };
+        let own_record = SignedPeerRecord::sign(
+            PeerRecord {
+                public_key: network_config.public_key.clone(),
+                addresses: state.advertised_addresses.clone(),
+                ports: state.advertised_ports.clone(),
+                last_seen: get_timestamp(),
+                relay: state.relay.clone(),
+                height: state.height,
+            },
+            &network_config.private_key,
+        )?;
+        book.write().await.insert(own_record, members);
+
+        let records = book.read().await.records();
+        let mut targets = state.bootstrap_addresses.clone();
+        targets.extend(state.static_peers.iter().map(|peer| peer.address));
+        for record in &records {
+            if record.record.public_key != network_config.public_key {
+                targets.extend(record.record.addresses.iter().cloned());
+            }
+        }
+        for target in targets {
+            match exchange(target, &network_config.chain_id, records.clone()).await {
+                Ok(received) => {
+                    let mut book = book.write().await;
+                    for record in received {
+                        book.insert(record, members);
+                    }
+                }
+                Err(e) => log::warn!("failed to exchange records with {}: {}", target, e),
+            }
+        }
+
+        state.book = book.read().await.clone();
+        write_state(&storage_directory, &state).await?;
+        *known_peers.lock.write().await = state
+            .book
+            .peers()
+            .into_iter()
+            .filter(|peer| peer.public_key != network_config.public_key)
+            .collect();
+        tokio::time::sleep(DISCOVERY_INTERVAL).await;
+    }
+}
+
+/// The peer discovery with the signed peer records.
+///
+/// Each node signs its own `PeerRecord` with the network key and exchanges all the records it knows
+/// with the peers it knows, so that a node can find every member starting from a single bootstrap peer.
+/// The records are accepted only from the members of the network (`NetworkConfig::members`).
 pub struct PeerDiscoveryImpl {}
 
+impl PeerDiscoveryImpl {
+    /// Sets the addresses and ports that this node advertises in its own record.
+    pub async fn set_advertisement(
+        storage_directory: &str,
+        addresses: Vec<SocketAddrV4>,
+        ports: BTreeMap<String, u16>,
+    ) -> Result<(), Error> {
+        let mut state = read_state(storage_directory).await?;
+        state.advertised_addresses = addresses;
+        state.advertised_ports = ports;
+        write_state(storage_directory, &state).await
+    }
+
+    /// Sets the member relaying the messages of this node (see `nat`).
+    pub async fn set_relay(storage_directory: &str, relay: Option<PublicKey>) -> Result<(), Error> {
+        let mut state = read_state(storage_directory).await?;
+        state.relay = relay;
+        write_state(storage_directory, &state).await
+    }
+
+    /// Sets the last finalized height that this node advertises in its own record.
+    pub async fn set_height(storage_directory: &str, height: BlockHeight) -> Result<(), Error> {
+        let mut state = read_state(storage_directory).await?;
+        state.height = Some(height);
+        write_state(storage_directory, &state).await
+    }
+
+    /// Adds a peer to contact in every discovery round, replacing the one with the same key.
+    pub async fn add_peer(storage_directory: &str, peer: StaticPeer) -> Result<(), Error> {
+        let mut state = read_state(storage_directory).await?;
+        state
+            .static_peers
+            .retain(|x| x.public_key != peer.public_key);
+        state.static_peers.push(peer);
+        write_state(storage_directory, &state).await
+    }
+
+    /// Removes the peer added with `add_peer()` and forgets its record.
+    /// Returns whether it was known.
+    ///
+    /// Note that a member is discovered again as long as the other peers know it,
+    /// and that the discovery being served keeps the records it holds in memory.
+    pub async fn remove_peer(
+        storage_directory: &str,
+        public_key: &PublicKey,
+    ) -> Result<bool, Error> {
+        let mut state = read_state(storage_directory).await?;
+        let count = state.static_peers.len();
+        state.static_peers.retain(|x| &x.public_key != public_key);
+        let removed = state.book.remove(public_key) || state.static_peers.len() != count;
+        write_state(storage_directory, &state).await?;
+        Ok(removed)
+    }
+
+    /// Reads the peers added with `add_peer()`.
+    pub async fn read_static_peers(storage_directory: &str) -> Result<Vec<StaticPeer>, Error> {
+        Ok(read_state(storage_directory).await?.static_peers)
+    }
+
+    /// Asks the peer at the address for its own record, returning it with the round-trip time.
+    ///
+    /// The record is verified to be signed by the key it describes.
+    pub async fn ping(
+        address: SocketAddrV4,
+        chain_id: &ChainId,
+    ) -> Result<(SignedPeerRecord, Duration), Error> {
+        let stub = PeerDiscoveryRpcInterfaceStub::new(Box::new(HttpClient::new(
+            format!("http://{}/discovery", address),
+            reqwest::Client::new(),
+        )));
+        let start = std::time::Instant::now();
+        let record = stub
+            .ping(chain_id.clone())
+            .await?
+            .map_err(|e| anyhow::anyhow!(e))?;
+        let round_trip = start.elapsed();
+        record.verify(&[record.record.public_key.clone()])?;
+        Ok((record, round_trip))
+    }
+
+    /// Adds the addresses to contact first.
+    pub async fn add_bootstrap_addresses(
+        storage_directory: &str,
+        addresses: Vec<SocketAddrV4>,
+    ) -> Result<(), Error> {
+        let mut state = read_state(storage_directory).await?;
+        for address in addresses {
+            if !state.bootstrap_addresses.contains(&address) {
+                state.bootstrap_addresses.push(address);
+            }
+        }
+        write_state(storage_directory, &state).await
+    }
+}
+
 #[async_trait]
 impl PeerDiscovery for PeerDiscoveryImpl {
-    async fn create(_storage_directory: &str) -> Result<(), Error> {
-        unimplemented!();
+    async fn create(storage_directory: &str) -> Result<(), Error> {
+        if tokio::fs::metadata(storage_directory).await.is_ok() {
+            return Err(anyhow::anyhow!("{} already exists", storage_directory));
+        }
+        tokio::fs::create_dir_all(storage_directory).await?;
+        write_state(storage_directory, &DiscoveryState::default()).await
     }
 
     async fn serve(
-        _storage_directory: &str,
-        _network_config: &NetworkConfig,
+        storage_directory: &str,
+        network_config: &NetworkConfig,
     ) -> Result<(SharedKnownPeers, tokio::task::JoinHandle<Result<(), Error>>), Error> {
-        unimplemented!();
+        let mut state = read_state(storage_directory).await?;
+        let members = network_config.members.clone();
+        state.book.retain_members(&members);
+        let book = Arc::new(RwLock::new(state.book.clone()));
+        let known_peers = SharedKnownPeers {
+            lock: Arc::new(RwLock::new(state.book.peers())),
+        };
+
+        let rpc_task = {
+            let wrapper = PeerBookWrapper {
+                public_key: network_config.public_key.clone(),
+                book: Arc::clone(&book),
+                members: members.clone(),
+                chain_id: network_config.chain_id.clone(),
+            };
+            let port = network_config.port;
+            async move {
+                if let Some(port) = port {
+                    run_server(
+                        port,
+                        [(
+                            "discovery".to_owned(),
+                            create_http_object(
+                                Arc::new(wrapper) as Arc<dyn PeerDiscoveryRpcInterface>
+                            ),
+                        )]
+                        .iter()
+                        .cloned()
+                        .collect(),
+                    )
+                    .await;
+                }
+                Result::<(), Error>::Ok(())
+            }
+        };
+
+        let discovery_task = discover(
+            storage_directory.to_owned(),
+            network_config.clone(),
+            state,
+            book,
+            known_peers.clone(),
+        );
+        let handle = tokio::spawn(async move {
+            futures::try_join!(rpc_task, discovery_task)?;
+            Ok(())
+        });
+        Ok((known_peers, handle))
     }
 
-    async fn read_known_peers(_storage_directory: &str) -> Result<Vec<Peer>, Error> {
-        unimplemented!();
+    async fn read_known_peers(storage_directory: &str) -> Result<Vec<Peer>, Error> {
+        Ok(read_state(storage_directory).await?.book.peers())
     }
 }
@@ -0,0 +1,166 @@
+use crate::*;
+use simperby_common::{ToHash256, TypedSignature};
+use std::collections::BTreeMap;
+
+/// The information that a node publishes about itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub public_key: PublicKey,
+    /// The addresses for the discovery protocol.
+    pub addresses: Vec<SocketAddrV4>,
+    /// The ports of the other network services (see `Peer::ports`).
+    pub ports: BTreeMap<String, u16>,
+    pub last_seen: Timestamp,
+    /// The member relaying the messages of this node, if it is not reachable.
+    #[serde(default)]
+    pub relay: Option<PublicKey>,
+    /// The last finalized height of this node, if it reports.
+    #[serde(default)]
+    pub height: Option<BlockHeight>,
+}
+
+impl ToHash256 for PeerRecord {
+    fn to_hash256(&self) -> Hash256 {
+        simperby_common::canonical::to_hash256(self)
+    }
+}
+
+/// A peer record signed by the network key of the node it describes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedPeerRecord {
+    pub record: PeerRecord,
+    pub signature: TypedSignature<PeerRecord>,
+}
+
+impl SignedPeerRecord {
+    pub fn sign(record: PeerRecord, private_key: &PrivateKey) -> Result<Self, CryptoError> {
+        let signature = TypedSignature::sign(&record, private_key)?;
+        Ok(Self { record, signature })
+    }
+
+    /// Verifies that the record is signed by itself and that it is of a member.
+    pub fn verify(&self, members: &[PublicKey]) -> Result<(), Error> {
+        if self.signature.signer() != &self.record.public_key {
+            return Err(anyhow::anyhow!(
+                "the record of {} is signed by {}",
+                self.record.public_key,
+                self.signature.signer()
+            ));
+        }
+        if !members.contains(&self.record.public_key) {
+            return Err(anyhow::anyhow!(
+                "{} is not a member of the network",
+                self.record.public_key
+            ));
+        }
+        self.signature.verify(&self.record)?;
+        Ok(())
+    }
+
+    pub fn to_peer(&self) -> Option<Peer> {
+        Some(Peer {
+            public_key: self.record.public_key.clone(),
+            address: *self.record.addresses.first()?,
+            ports: self.record.ports.clone().into_iter().collect(),
+            message: String::new(),
+            recently_seen_timestamp: self.record.last_seen,
+            relay: self.record.relay.clone(),
+            height: self.record.height,
+        })
+    }
+}
+
+/// The latest known record of each peer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerBook {
+    records: BTreeMap<PublicKey, SignedPeerRecord>,
+}
+
+impl PeerBook {
+    /// Adds the record if it is valid and newer than the known one of the same peer.
+    /// Returns whether it was added.
+    pub fn insert(&mut self, record: SignedPeerRecord, members: &[PublicKey]) -> bool {
+        if let Err(e) = record.verify(members) {
+            log::warn!("discarded an invalid peer record: {}", e);
+            return false;
+        }
+        if let Some(known) = self.records.get(&record.record.public_key) {
+            if known.record.last_seen >= record.record.last_seen {
+                return false;
+            }
+        }
+        self.records
+            .insert(record.record.public_key.clone(), record);
+        true
+    }
+
+    pub fn get(&self, public_key: &PublicKey) -> Option<&SignedPeerRecord> {
+        self.records.get(public_key)
+    }
+
+    /// Forgets the record of the peer. Returns whether it was known.
+    pub fn remove(&mut self, public_key: &PublicKey) -> bool {
+        self.records.remove(public_key).is_some()
+    }
+
+    /// Discards the records of those who are no longer members.
+    pub fn retain_members(&mut self, members: &[PublicKey]) {
+        self.records.retain(|key, _| members.contains(key));
+    }
+
+    pub fn records(&self) -> Vec<SignedPeerRecord> {
+        self.records.values().cloned().collect()
+    }
+
+    pub fn peers(&self) -> Vec<Peer> {
+        self.records
+            .values()
+            .filter_map(SignedPeerRecord::to_peer)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, last_seen: Timestamp) -> SignedPeerRecord {
+        let (public_key, private_key) = generate_keypair(name);
+        SignedPeerRecord::sign(
+            PeerRecord {
+                public_key,
+                addresses: vec!["127.0.0.1:5000".parse().unwrap()],
+                ports: BTreeMap::new(),
+                last_seen,
+                relay: None,
+                height: None,
+            },
+            &private_key,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn insert_records() {
+        let members = vec![generate_keypair("a").0, generate_keypair("b").0];
+        let mut book = PeerBook::default();
+        assert!(book.insert(record("a", 1), &members));
+        assert!(!book.insert(record("a", 1), &members));
+        assert!(book.insert(record("a", 2), &members));
+        assert!(!book.insert(record("a", 0), &members));
+        assert!(!book.insert(record("c", 1), &members));
+
+        // Signed by another key.
+        let mut forged = record("b", 1);
+        forged.signature = record("a", 1).signature;
+        assert!(!book.insert(forged, &members));
+
+        assert_eq!(book.peers().len(), 1);
+        assert_eq!(book.peers()[0].recently_seen_timestamp, 2);
+        assert!(book.remove(&members[0]));
+        assert!(!book.remove(&members[0]));
+        assert!(book.insert(record("a", 1), &members));
+        book.retain_members(&members[1..]);
+        assert!(book.records().is_empty());
+    }
+}
@@ -110,6 +110,7 @@ impl TestNet {
         let dummy_port = Some(1);
         let default_network_config = NetworkConfig {
             network_id: format!("test-{}", thread_rng().gen::<u32>()),
+            chain_id: Default::default(),
             port: dummy_port,
             members: keystore
                 .store
@@ -204,6 +205,8 @@ impl TestNet {
                 message: String::new(),
                 ports: HashMap::new(),
                 recently_seen_timestamp: 0,
+                relay: None,
+                height: None,
             })
             .collect()
     }
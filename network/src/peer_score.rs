@@ -0,0 +1,212 @@
+//! Peer reputation, protecting the node from the accidental or malicious flooding.
+//!
+//! Every peer starts with the score of zero, which goes up with the valid messages
+//! and down with the offenses. A peer whose score falls to the threshold is banned for a while,
+//! and a peer that sends more than its bandwidth budget in a window is throttled
+//! (i.e., the rest of its data in the window is dropped).
+use super::*;
+use std::time::Duration;
+
+/// The score of a peer can't go above this, so that a long good record doesn't hide a later flood.
+const MAX_SCORE: i64 = 100;
+/// The score that a valid message earns.
+const REWARD: i64 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerScoreConfig {
+    /// The bandwidth budget of a peer, in bytes per window.
+    pub max_bytes_per_window: u64,
+    pub window: Duration,
+    /// A peer is banned when its score falls to this.
+    pub ban_threshold: i64,
+    pub ban_duration: Duration,
+}
+
+impl Default for PeerScoreConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes_per_window: 64 * 1024 * 1024,
+            window: Duration::from_secs(60),
+            ban_threshold: -100,
+            ban_duration: Duration::from_secs(600),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Offense {
+    /// A message with an invalid signature or format.
+    InvalidMessage,
+    /// More data than the bandwidth budget in a window.
+    ExcessiveBandwidth,
+    /// A response that doesn't follow the protocol (e.g., of a wrong height).
+    ProtocolViolation,
+}
+
+impl Offense {
+    pub fn penalty(&self) -> i64 {
+        match self {
+            Offense::InvalidMessage => 20,
+            Offense::ExcessiveBandwidth => 10,
+            Offense::ProtocolViolation => 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerScore {
+    pub score: i64,
+    pub invalid_messages: u64,
+    pub bandwidth_violations: u64,
+    pub protocol_violations: u64,
+    /// The bytes received in the current window.
+    pub window_bytes: u64,
+    pub window_start: Timestamp,
+    pub banned_until: Option<Timestamp>,
+}
+
+impl PeerScore {
+    pub fn is_banned(&self, now: Timestamp) -> bool {
+        self.banned_until.map_or(false, |until| now < until)
+    }
+
+    /// Applies the offense, banning the peer if the score falls to the threshold.
+    pub fn report(&mut self, offense: Offense, config: &PeerScoreConfig, now: Timestamp) {
+        match offense {
+            Offense::InvalidMessage => self.invalid_messages += 1,
+            Offense::ExcessiveBandwidth => self.bandwidth_violations += 1,
+            Offense::ProtocolViolation => self.protocol_violations += 1,
+        }
+        self.score -= offense.penalty();
+        if self.score <= config.ban_threshold {
+            self.banned_until = Some(now + config.ban_duration.as_millis() as Timestamp);
+            // The peer starts over after the ban.
+            self.score = 0;
+        }
+    }
+
+    pub fn reward(&mut self) {
+        self.score = (self.score + REWARD).min(MAX_SCORE);
+    }
+
+    /// Records the bytes received, returning `false` if the peer is over the budget of the window.
+    pub fn record_bytes(&mut self, bytes: u64, config: &PeerScoreConfig, now: Timestamp) -> bool {
+        if now >= self.window_start + config.window.as_millis() as Timestamp {
+            self.window_start = now;
+            self.window_bytes = 0;
+        }
+        self.window_bytes += bytes;
+        if self.window_bytes > config.max_bytes_per_window {
+            self.report(Offense::ExcessiveBandwidth, config, now);
+            false
+        } else {
+            true
+        }
+    }
+}
+
+/// The scores of the peers, shared among the tasks of a node.
+#[derive(Debug, Clone, Default)]
+pub struct PeerScores {
+    config: PeerScoreConfig,
+    scores: Arc<RwLock<HashMap<PublicKey, PeerScore>>>,
+}
+
+fn get_timestamp() -> Timestamp {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+    since_the_epoch.as_millis() as Timestamp
+}
+
+impl PeerScores {
+    pub fn new(config: PeerScoreConfig) -> Self {
+        Self {
+            config,
+            scores: Default::default(),
+        }
+    }
+
+    pub async fn is_banned(&self, peer: &PublicKey) -> bool {
+        self.scores
+            .read()
+            .await
+            .get(peer)
+            .map_or(false, |score| score.is_banned(get_timestamp()))
+    }
+
+    pub async fn report(&self, peer: &PublicKey, offense: Offense) {
+        log::warn!("peer {} committed an offense: {:?}", peer, offense);
+        self.scores
+            .write()
+            .await
+            .entry(peer.clone())
+            .or_default()
+            .report(offense, &self.config, get_timestamp());
+    }
+
+    pub async fn reward(&self, peer: &PublicKey) {
+        self.scores
+            .write()
+            .await
+            .entry(peer.clone())
+            .or_default()
+            .reward();
+    }
+
+    /// Records the bytes received from the peer, returning `false` if it must be throttled.
+    pub async fn record_bytes(&self, peer: &PublicKey, bytes: u64) -> bool {
+        self.scores
+            .write()
+            .await
+            .entry(peer.clone())
+            .or_default()
+            .record_bytes(bytes, &self.config, get_timestamp())
+    }
+
+    /// Reads the scores of all the peers that have any record, sorted by the key.
+    pub async fn read(&self) -> Vec<(PublicKey, PeerScore)> {
+        let mut scores: Vec<_> = self
+            .scores
+            .read()
+            .await
+            .iter()
+            .map(|(peer, score)| (peer.clone(), score.clone()))
+            .collect();
+        scores.sort_by(|(a, _), (b, _)| a.cmp(b));
+        scores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ban_and_throttle() {
+        let config = PeerScoreConfig {
+            max_bytes_per_window: 100,
+            window: Duration::from_millis(1000),
+            ban_threshold: -50,
+            ban_duration: Duration::from_millis(5000),
+        };
+        let mut score = PeerScore::default();
+        assert!(score.record_bytes(60, &config, 0));
+        assert!(!score.record_bytes(60, &config, 500));
+        assert_eq!(score.bandwidth_violations, 1);
+        // A new window.
+        assert!(score.record_bytes(60, &config, 1000));
+
+        score.report(Offense::InvalidMessage, &config, 1000);
+        assert!(!score.is_banned(1000));
+        score.report(Offense::InvalidMessage, &config, 1000);
+        assert!(score.is_banned(1000));
+        assert!(score.is_banned(5999));
+        assert!(!score.is_banned(6000));
+        assert_eq!(score.score, 0);
+
+        for _ in 0..1000 {
+            score.reward();
+        }
+        assert_eq!(score.score, MAX_SCORE);
+    }
+}
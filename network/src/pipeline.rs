@@ -0,0 +1,281 @@
+//! The bounded pipeline of the messages received from the gossip network,
+//! before they are verified and stored by the DMSes.
+//!
+//! Under load, the messages arrive faster than they can be verified and written.
+//! Instead of buffering them without a bound, the pipeline keeps a bounded queue (a lane)
+//! for each subsystem, and processes the lanes in priority: the consensus, the governance,
+//! and then the chat (the order of `Subsystem`).
+//!
+//! - A message that is already queued is merged into the queued one.
+//! - A message received at a height that the DMS has left is dropped as stale when processed.
+//! - If a lane is full, its oldest message is dropped for the new one.
+use super::*;
+use crate::bandwidth::Subsystem;
+use futures::future::BoxFuture;
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use tokio::sync::{Mutex, Notify};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PipelineConfig {
+    /// The maximum number of the messages queued in each lane.
+    pub lane_capacity: usize,
+    /// Overrides `lane_capacity` for the subsystems.
+    #[serde(default)]
+    pub capacities: BTreeMap<Subsystem, usize>,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            lane_capacity: 1024,
+            capacities: BTreeMap::new(),
+        }
+    }
+}
+
+impl PipelineConfig {
+    pub fn capacity(&self, subsystem: Subsystem) -> usize {
+        self.capacities
+            .get(&subsystem)
+            .copied()
+            .unwrap_or(self.lane_capacity)
+    }
+}
+
+/// A message received from the network, not yet verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inbound {
+    /// The height of the DMS when the message was received.
+    pub height: BlockHeight,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Queued,
+    /// The same message was already queued.
+    Merged,
+    /// Queued, dropping the oldest message of the lane.
+    Displaced,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Processed {
+    Stored,
+    /// Dropped, since the DMS has advanced since the message was received.
+    Stale,
+}
+
+/// Verifies and stores a message of a lane.
+pub type LaneHandler =
+    Arc<dyn Fn(Inbound) -> BoxFuture<'static, Result<Processed, Error>> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LaneStats {
+    /// The number of the messages queued now.
+    pub depth: usize,
+    pub stored: u64,
+    pub merged: u64,
+    pub displaced: u64,
+    pub stale: u64,
+    pub failed: u64,
+}
+
+#[derive(Default)]
+struct Lane {
+    queue: VecDeque<(Hash256, Inbound)>,
+    digests: HashSet<Hash256>,
+    handler: Option<LaneHandler>,
+    stats: LaneStats,
+}
+
+/// The lanes shared by the DMSes of a node (see `DistributedMessageSet::set_pipeline()`),
+/// processed by `run()`.
+#[derive(Clone)]
+pub struct Pipeline {
+    config: PipelineConfig,
+    lanes: Arc<Mutex<BTreeMap<Subsystem, Lane>>>,
+    notify: Arc<Notify>,
+}
+
+impl Pipeline {
+    pub fn new(config: PipelineConfig) -> Self {
+        Self {
+            config,
+            lanes: Default::default(),
+            notify: Default::default(),
+        }
+    }
+
+    /// Sets the handler of the lane, replacing the previous one.
+    ///
+    /// The messages of a lane without a handler stay queued.
+    pub async fn register(&self, subsystem: Subsystem, handler: LaneHandler) {
+        self.lanes
+            .lock()
+            .await
+            .entry(subsystem)
+            .or_default()
+            .handler = Some(handler);
+        self.notify.notify_one();
+    }
+
+    /// Queues the message in the lane of the subsystem.
+    pub async fn push(&self, subsystem: Subsystem, inbound: Inbound) -> Admission {
+        let digest = Hash256::hash(&inbound.data);
+        let mut lanes = self.lanes.lock().await;
+        let lane = lanes.entry(subsystem).or_default();
+        if lane.digests.contains(&digest) {
+            lane.stats.merged += 1;
+            return Admission::Merged;
+        }
+        let mut admission = Admission::Queued;
+        while lane.queue.len() >= self.config.capacity(subsystem).max(1) {
+            if let Some((dropped, _)) = lane.queue.pop_front() {
+                lane.digests.remove(&dropped);
+                lane.stats.displaced += 1;
+                admission = Admission::Displaced;
+            }
+        }
+        lane.digests.insert(digest);
+        lane.queue.push_back((digest, inbound));
+        lane.stats.depth = lane.queue.len();
+        drop(lanes);
+        self.notify.notify_one();
+        admission
+    }
+
+    /// Takes the oldest message of the lane of the highest priority that has a handler.
+    async fn pop(&self) -> Option<(Subsystem, Inbound, LaneHandler)> {
+        let mut lanes = self.lanes.lock().await;
+        for (subsystem, lane) in lanes.iter_mut() {
+            let handler = match &lane.handler {
+                Some(handler) => Arc::clone(handler),
+                None => continue,
+            };
+            if let Some((digest, inbound)) = lane.queue.pop_front() {
+                lane.digests.remove(&digest);
+                lane.stats.depth = lane.queue.len();
+                return Some((*subsystem, inbound, handler));
+            }
+        }
+        None
+    }
+
+    /// Processes a queued message, returning `false` if there is none to process.
+    pub async fn process_next(&self) -> bool {
+        let (subsystem, inbound, handler) = match self.pop().await {
+            Some(x) => x,
+            None => return false,
+        };
+        let result = handler(inbound).await;
+        let mut lanes = self.lanes.lock().await;
+        let stats = &mut lanes.entry(subsystem).or_default().stats;
+        match result {
+            Ok(Processed::Stored) => stats.stored += 1,
+            Ok(Processed::Stale) => stats.stale += 1,
+            Err(e) => {
+                stats.failed += 1;
+                log::warn!("failed to process a message of {:?}: {}", subsystem, e);
+            }
+        }
+        true
+    }
+
+    /// Processes the queued messages indefinitely.
+    pub async fn run(self) -> Result<(), Error> {
+        loop {
+            if !self.process_next().await {
+                self.notify.notified().await;
+            }
+        }
+    }
+
+    pub async fn stats(&self) -> BTreeMap<Subsystem, LaneStats> {
+        self.lanes
+            .lock()
+            .await
+            .iter()
+            .map(|(subsystem, lane)| (*subsystem, lane.stats))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inbound(height: BlockHeight, data: &str) -> Inbound {
+        Inbound {
+            height,
+            data: data.as_bytes().to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn lanes() {
+        let pipeline = Pipeline::new(PipelineConfig {
+            lane_capacity: 2,
+            capacities: Default::default(),
+        });
+        let processed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        for subsystem in [Subsystem::Chat, Subsystem::Consensus] {
+            let processed = Arc::clone(&processed);
+            pipeline
+                .register(
+                    subsystem,
+                    Arc::new(
+                        move |inbound: Inbound| -> BoxFuture<'static, Result<Processed, Error>> {
+                            processed
+                                .lock()
+                                .unwrap()
+                                .push(String::from_utf8(inbound.data).unwrap());
+                            let result = if inbound.height < 1 {
+                                Processed::Stale
+                            } else {
+                                Processed::Stored
+                            };
+                            Box::pin(async move { Ok(result) })
+                        },
+                    ),
+                )
+                .await;
+        }
+
+        assert_eq!(
+            pipeline.push(Subsystem::Chat, inbound(1, "c1")).await,
+            Admission::Queued
+        );
+        assert_eq!(
+            pipeline.push(Subsystem::Chat, inbound(1, "c1")).await,
+            Admission::Merged
+        );
+        pipeline.push(Subsystem::Chat, inbound(1, "c2")).await;
+        assert_eq!(
+            pipeline.push(Subsystem::Chat, inbound(1, "c3")).await,
+            Admission::Displaced
+        );
+        pipeline.push(Subsystem::Consensus, inbound(0, "v1")).await;
+        pipeline.push(Subsystem::Consensus, inbound(1, "v2")).await;
+        // Not processed, without a handler.
+        pipeline.push(Subsystem::Governance, inbound(1, "g1")).await;
+
+        while pipeline.process_next().await {}
+        assert_eq!(*processed.lock().unwrap(), vec!["v1", "v2", "c2", "c3"]);
+
+        let stats = pipeline.stats().await;
+        assert_eq!(
+            stats[&Subsystem::Chat],
+            LaneStats {
+                depth: 0,
+                stored: 2,
+                merged: 1,
+                displaced: 1,
+                stale: 0,
+                failed: 0,
+            }
+        );
+        assert_eq!(stats[&Subsystem::Consensus].stale, 1);
+        assert_eq!(stats[&Subsystem::Governance].depth, 1);
+    }
+}
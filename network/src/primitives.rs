@@ -36,6 +36,82 @@ pub trait Storage: Send + Sync + 'static {
     async fn remove_all_files(&mut self) -> Result<(), StorageError>;
 }
 
+/// An operation in a batch of `MessageStore::write_batch()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreOperation {
+    Put(String, String),
+    Remove(String),
+    /// Removes every entry, including those put earlier in the same batch.
+    RemoveAll,
+}
+
+/// A key-value storage of a distributed message set.
+#[async_trait]
+pub trait MessageStore: Send + Sync + 'static {
+    /// Creates a new and empty store in the given directory.
+    /// If there is already one, it just removes it and re-create.
+    async fn create(storage_directory: &str) -> Result<(), StorageError>;
+
+    /// Opens an existing store, locking it.
+    async fn open(storage_directory: &str) -> Result<Self, StorageError>
+    where
+        Self: Sized;
+
+    /// Reads the value of the given key, if any.
+    async fn read(&self, key: &str) -> Result<Option<String>, StorageError>;
+
+    /// Reads all the entries.
+    async fn read_all(&self) -> Result<Vec<(String, String)>, StorageError>;
+
+    /// Applies the operations in order.
+    ///
+    /// It is atomic unless noted by the implementation:
+    /// after a crash, either all or none of the operations are applied.
+    async fn write_batch(&mut self, batch: Vec<StoreOperation>) -> Result<(), StorageError>;
+}
+
+/// The file system storage as a message store, one file per key.
+///
+/// Note that the batches are applied one operation at a time, so they are NOT atomic.
+#[async_trait]
+impl<S: Storage> MessageStore for S {
+    async fn create(storage_directory: &str) -> Result<(), StorageError> {
+        <S as Storage>::create(storage_directory).await
+    }
+
+    async fn open(storage_directory: &str) -> Result<Self, StorageError> {
+        <S as Storage>::open(storage_directory).await
+    }
+
+    async fn read(&self, key: &str) -> Result<Option<String>, StorageError> {
+        match self.read_file(key).await {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn read_all(&self) -> Result<Vec<(String, String)>, StorageError> {
+        let mut result = Vec::new();
+        for file in self.list_files().await? {
+            let value = self.read_file(&file).await?;
+            result.push((file, value));
+        }
+        Ok(result)
+    }
+
+    async fn write_batch(&mut self, batch: Vec<StoreOperation>) -> Result<(), StorageError> {
+        for operation in batch {
+            match operation {
+                StoreOperation::Put(key, value) => self.add_or_overwrite_file(&key, value).await?,
+                StoreOperation::Remove(key) => self.remove_file(&key).await?,
+                StoreOperation::RemoveAll => self.remove_all_files().await?,
+            }
+        }
+        Ok(())
+    }
+}
+
 #[async_trait]
 pub trait PeerDiscoveryPrimitive: Send + Sync + 'static {
     /// Remains online on the network indefinitely,
@@ -0,0 +1,186 @@
+//! Range-based set reconciliation of the DMS messages.
+//!
+//! Instead of sending the digests of all the known messages, the client sends a summary
+//! (the count and the fingerprint) of a range of the digests.
+//! The server compares it with its own; if they differ, it either sends its digests in the range,
+//! if there are few, or splits the range into the sub-ranges and sends their summaries,
+//! which the client compares in turn. It repeats until every range matches or is resolved,
+//! so the cost is proportional to the difference, not to the size of the sets.
+use super::*;
+
+/// The server sends the digests in a range, instead of splitting it, if there are at most this many.
+pub const LEAF_SIZE: usize = 16;
+/// The number of the sub-ranges that a range is split into.
+pub const BRANCH_FACTOR: usize = 16;
+/// The maximum number of the rounds, which is enough for any set of a realistic size.
+pub const MAX_ROUNDS: usize = 32;
+
+/// A range of the digests, from `start` (inclusive) to `end` (exclusive, or the last if none).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestRange {
+    pub start: Hash256,
+    pub end: Option<Hash256>,
+}
+
+impl DigestRange {
+    pub fn full() -> Self {
+        Self {
+            start: Hash256::zero(),
+            end: None,
+        }
+    }
+
+    pub fn contains(&self, digest: &Hash256) -> bool {
+        &self.start <= digest && self.end.map_or(true, |end| digest < &end)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RangeSummary {
+    pub range: DigestRange,
+    pub count: usize,
+    /// The XOR of the digests in the range.
+    pub fingerprint: Hash256,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RangeResponse {
+    /// All the digests of the server in the range.
+    Digests(Vec<Hash256>),
+    /// The summaries of the server for the sub-ranges.
+    Split(Vec<RangeSummary>),
+}
+
+/// The XOR of the digests, which doesn't depend on the order.
+pub fn fingerprint<'a>(digests: impl IntoIterator<Item = &'a Hash256>) -> Hash256 {
+    let mut result = Hash256::zero();
+    for digest in digests {
+        for (x, y) in result.hash.iter_mut().zip(digest.hash.iter()) {
+            *x ^= y;
+        }
+    }
+    result
+}
+
+fn in_range<'a>(sorted: &'a [Hash256], range: &DigestRange) -> &'a [Hash256] {
+    let start = sorted.partition_point(|digest| digest < &range.start);
+    let end = match &range.end {
+        Some(end) => sorted.partition_point(|digest| digest < end),
+        None => sorted.len(),
+    };
+    &sorted[start..end.max(start)]
+}
+
+/// Summarizes the range of the sorted digests.
+pub fn summarize(sorted: &[Hash256], range: DigestRange) -> RangeSummary {
+    let digests = in_range(sorted, &range);
+    RangeSummary {
+        count: digests.len(),
+        fingerprint: fingerprint(digests),
+        range,
+    }
+}
+
+/// Responds to the summaries of the client, for the mismatching ranges (the server side).
+pub fn respond(sorted: &[Hash256], summaries: &[RangeSummary]) -> Vec<RangeResponse> {
+    let mut responses = Vec::new();
+    for summary in summaries {
+        let digests = in_range(sorted, &summary.range);
+        if digests.len() == summary.count && fingerprint(digests) == summary.fingerprint {
+            continue;
+        }
+        let response = if digests.len() <= LEAF_SIZE {
+            RangeResponse::Digests(digests.to_vec())
+        } else {
+            // Splits at the digests of the server, into the ranges of about the same size.
+            let step = (digests.len() + BRANCH_FACTOR - 1) / BRANCH_FACTOR;
+            let mut boundaries = vec![summary.range.start];
+            boundaries.extend(digests.iter().skip(step).step_by(step).cloned());
+            let mut split = Vec::new();
+            for (i, start) in boundaries.iter().enumerate() {
+                let end = boundaries.get(i + 1).cloned().or(summary.range.end);
+                split.push(summarize(sorted, DigestRange { start: *start, end }));
+            }
+            RangeResponse::Split(split)
+        };
+        responses.push(response);
+    }
+    responses
+}
+
+/// Processes the responses of the server (the client side),
+/// adding the digests missing locally to `wanted` and returning the summaries for the next round.
+pub fn process(
+    sorted: &[Hash256],
+    responses: Vec<RangeResponse>,
+    wanted: &mut Vec<Hash256>,
+) -> Vec<RangeSummary> {
+    let mut next = Vec::new();
+    for response in responses {
+        match response {
+            RangeResponse::Digests(digests) => wanted.extend(
+                digests
+                    .into_iter()
+                    .filter(|digest| sorted.binary_search(digest).is_err()),
+            ),
+            RangeResponse::Split(summaries) => {
+                for summary in summaries {
+                    let local = summarize(sorted, summary.range.clone());
+                    if local != summary {
+                        next.push(local);
+                    }
+                }
+            }
+        }
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transfer_only_missing() {
+        let digests: Vec<_> = (0..1000).map(|i| Hash256::hash(format!("{}", i))).collect();
+        let mut server = digests.clone();
+        server.sort();
+        let mut client: Vec<_> = digests
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| i % 97 != 0 && *i != 500)
+            .map(|(_, digest)| *digest)
+            .collect();
+        // The client also has some that the server doesn't.
+        client.extend((0..10).map(|i| Hash256::hash(format!("client-{}", i))));
+        client.sort();
+
+        let mut expected: Vec<_> = server
+            .iter()
+            .filter(|digest| client.binary_search(digest).is_err())
+            .cloned()
+            .collect();
+        let mut wanted = Vec::new();
+        let mut summaries = vec![summarize(&client, DigestRange::full())];
+        let mut transferred = 0;
+        for _ in 0..MAX_ROUNDS {
+            if summaries.is_empty() {
+                break;
+            }
+            let responses = respond(&server, &summaries);
+            transferred += responses
+                .iter()
+                .map(|response| match response {
+                    RangeResponse::Digests(digests) => digests.len(),
+                    RangeResponse::Split(summaries) => summaries.len(),
+                })
+                .sum::<usize>();
+            summaries = process(&client, responses, &mut wanted);
+        }
+        assert!(summaries.is_empty());
+        wanted.sort();
+        expected.sort();
+        assert_eq!(wanted, expected);
+        assert!(transferred < server.len() / 2);
+    }
+}
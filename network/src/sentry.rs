@@ -0,0 +1,208 @@
+//! The sentry/validator split, which hides a validator from the public network.
+//!
+//! A validator may run behind one or more sentries: publicly reachable nodes that handle
+//! all the peer traffic for it. The validator talks only to its sentries (see `SentryConfig::restrict()`),
+//! and they to the rest of the network:
+//!
+//! - Outbound, the validator pushes its messages to every sentry on each fetch,
+//!   over the private link where each push is signed by the network key of the validator.
+//!   The sentry gossips and broadcasts them as its own.
+//! - Inbound, the validator fetches from the sentries only, which have what the network has.
+//!
+//! It applies to every DMS alike, so the consensus messages are forwarded both ways.
+//!
+//! The validator itself should be reachable only by its sentries (e.g., by a firewall),
+//! with them as its bootstrap peers.
+use super::*;
+use std::time::Duration;
+
+/// How long a signed push stays valid, to bound the replays of a captured one.
+pub const LINK_VALIDITY: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SentryConfig {
+    /// For a validator: the sentries to talk to, exclusively. If empty, it talks to every peer.
+    #[serde(default)]
+    pub sentries: Vec<PublicKey>,
+    /// For a sentry: the validators behind it, whose pushes over the private link it accepts.
+    #[serde(default)]
+    pub validators: Vec<PublicKey>,
+}
+
+impl SentryConfig {
+    /// Whether this node is a validator behind sentries.
+    pub fn is_hidden(&self) -> bool {
+        !self.sentries.is_empty()
+    }
+
+    /// Whether this node is a sentry for some validators.
+    pub fn is_sentry(&self) -> bool {
+        !self.validators.is_empty()
+    }
+
+    /// Returns the peers that this node may talk to: only the sentries for a hidden validator.
+    pub fn restrict(&self, peers: &[Peer]) -> Vec<Peer> {
+        if !self.is_hidden() {
+            return peers.to_vec();
+        }
+        peers
+            .iter()
+            .filter(|peer| self.sentries.contains(&peer.public_key))
+            .cloned()
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.is_hidden() && self.is_sentry() {
+            return Err("a node can't be both a sentry and a validator behind sentries".to_owned());
+        }
+        Ok(())
+    }
+}
+
+/// What the validator signs for a push over the private link.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkPush {
+    pub chain_id: ChainId,
+    /// The key of the DMS (see `DistributedMessageSet::create()`).
+    pub dms_key: String,
+    pub height: BlockHeight,
+    pub timestamp: Timestamp,
+    /// The hash of the pushed messages, in order.
+    pub messages_hash: Hash256,
+}
+
+impl ToHash256 for LinkPush {
+    fn to_hash256(&self) -> Hash256 {
+        simperby_common::canonical::to_hash256(self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedLinkPush {
+    pub push: LinkPush,
+    pub signature: TypedSignature<LinkPush>,
+}
+
+impl SignedLinkPush {
+    pub fn sign(push: LinkPush, private_key: &PrivateKey) -> Result<Self, CryptoError> {
+        Ok(Self {
+            signature: TypedSignature::sign(&push, private_key)?,
+            push,
+        })
+    }
+
+    /// Checks that the push is of one of the validators, for this DMS, of the given messages,
+    /// and recent enough at `now`.
+    pub fn verify(
+        &self,
+        validators: &[PublicKey],
+        chain_id: &ChainId,
+        dms_key: &str,
+        height: BlockHeight,
+        messages_hash: Hash256,
+        now: Timestamp,
+    ) -> Result<(), String> {
+        if !validators.contains(self.signature.signer()) {
+            return Err(format!(
+                "{} is not a validator behind this sentry",
+                self.signature.signer()
+            ));
+        }
+        self.signature
+            .verify(&self.push)
+            .map_err(|e| format!("invalid signature of the push: {}", e))?;
+        chain_id.check(&self.push.chain_id)?;
+        if self.push.dms_key != dms_key || self.push.height != height {
+            return Err(format!(
+                "the push is signed for {} at {}, but for {} at {}",
+                self.push.dms_key, self.push.height, dms_key, height
+            ));
+        }
+        if self.push.messages_hash != messages_hash {
+            return Err("the push is signed for other messages".to_owned());
+        }
+        let validity = LINK_VALIDITY.as_millis() as Timestamp;
+        if self.push.timestamp + validity < now || self.push.timestamp > now + validity {
+            return Err(format!(
+                "the push is signed at {}, too far from {}",
+                self.push.timestamp, now
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(name: &str) -> Peer {
+        Peer {
+            public_key: generate_keypair(name).0,
+            address: "127.0.0.1:9100".parse().unwrap(),
+            ports: Default::default(),
+            message: String::new(),
+            recently_seen_timestamp: 0,
+            relay: None,
+            height: None,
+        }
+    }
+
+    #[test]
+    fn restrict() {
+        let peers = vec![peer("a"), peer("b"), peer("c")];
+        assert_eq!(SentryConfig::default().restrict(&peers), peers);
+        let config = SentryConfig {
+            sentries: vec![generate_keypair("b").0],
+            validators: Vec::new(),
+        };
+        assert_eq!(config.restrict(&peers), vec![peer("b")]);
+        assert!(config.validate().is_ok());
+        let config = SentryConfig {
+            validators: vec![generate_keypair("c").0],
+            ..config
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn link_push() {
+        let (validator, private_key) = generate_keypair("validator");
+        let chain_id = ChainId::default();
+        let push = |timestamp| {
+            SignedLinkPush::sign(
+                LinkPush {
+                    chain_id: chain_id.clone(),
+                    dms_key: "consensus".to_owned(),
+                    height: 3,
+                    timestamp,
+                    messages_hash: Hash256::hash("messages"),
+                },
+                &private_key,
+            )
+            .unwrap()
+        };
+        let validators = vec![validator];
+        let verify = |push: &SignedLinkPush,
+                      validators: &[PublicKey],
+                      height: BlockHeight,
+                      messages: &str,
+                      now: Timestamp| {
+            push.verify(
+                validators,
+                &chain_id,
+                "consensus",
+                height,
+                Hash256::hash(messages),
+                now,
+            )
+        };
+        assert!(verify(&push(1_000), &validators, 3, "messages", 30_000).is_ok());
+        assert!(verify(&push(1_000), &[], 3, "messages", 30_000).is_err());
+        assert!(verify(&push(1_000), &validators, 4, "messages", 30_000).is_err());
+        assert!(verify(&push(1_000), &validators, 3, "others", 30_000).is_err());
+        assert!(verify(&push(1_000), &validators, 3, "messages", 100_000).is_err());
+        assert!(verify(&push(100_000), &validators, 3, "messages", 1_000).is_err());
+    }
+}
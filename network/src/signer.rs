@@ -0,0 +1,443 @@
+//! The abstraction over where the private key of a validator lives.
+//!
+//! `LocalSigner` holds the key in memory. `RemoteSigner` asks a signer process
+//! (possibly on a separate machine, or in front of an HSM) served by `serve_remote_signer()`.
+//!
+//! The remote signer is reached at a `SignerEndpoint`: a Unix domain socket for a signer
+//! on the same machine, or TCP wrapped in TLS otherwise. The TLS certificate of the signer is
+//! self-signed (see `SignerIdentity`), so the client pins it instead of trusting any CA.
+//!
+//! The remote protocol exchanges length-prefixed JSON frames. Each request is signed by
+//! the client's authentication key and carries a strictly increasing counter, so that
+//! the signer rejects unauthorized and replayed requests.
+use super::*;
+use anyhow::anyhow;
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// The maximum size of a frame of the remote signer protocol.
+const MAX_FRAME_SIZE: u32 = 64 * 1024;
+
+/// The server name in the TLS handshake; the certificate is pinned rather than verified by name.
+const SERVER_NAME: &str = "simperby-signer";
+
+#[async_trait]
+pub trait Signer: Send + Sync {
+    /// Returns the public key of the signing key.
+    fn public_key(&self) -> PublicKey;
+
+    /// Signs the given data.
+    async fn sign(&self, data: Hash256) -> Result<Signature, Error>;
+}
+
+/// A signer with the private key in memory.
+pub struct LocalSigner {
+    private_key: PrivateKey,
+}
+
+impl LocalSigner {
+    pub fn new(private_key: PrivateKey) -> Self {
+        Self { private_key }
+    }
+}
+
+#[async_trait]
+impl Signer for LocalSigner {
+    fn public_key(&self) -> PublicKey {
+        self.private_key.public_key()
+    }
+
+    async fn sign(&self, data: Hash256) -> Result<Signature, Error> {
+        Ok(Signature::sign(data, &self.private_key)?)
+    }
+}
+
+/// Where a remote signer is reached.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignerEndpoint {
+    /// The path of a Unix domain socket.
+    Unix(String),
+    /// A TCP address, over TLS.
+    Tls {
+        address: String,
+        /// The DER-encoded certificate of the signer (`SignerIdentity::certificate`),
+        /// which is the only one accepted.
+        certificate: Vec<u8>,
+    },
+}
+
+/// The self-signed TLS certificate of a remote signer and its private key, both DER-encoded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignerIdentity {
+    pub certificate: Vec<u8>,
+    pub private_key: Vec<u8>,
+}
+
+impl SignerIdentity {
+    pub fn generate() -> Result<Self, Error> {
+        let certificate = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_owned()])?;
+        Ok(Self {
+            certificate: certificate.serialize_der()?,
+            private_key: certificate.serialize_private_key_der(),
+        })
+    }
+}
+
+/// Accepts only the pinned certificate.
+struct PinnedServerVerification {
+    certificate: rustls::Certificate,
+}
+
+impl rustls::client::ServerCertVerifier for PinnedServerVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        if *end_entity == self.certificate {
+            Ok(rustls::client::ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificateData(
+                "not the pinned certificate of the signer".to_owned(),
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignRequestBody {
+    pub data: Hash256,
+    /// Must be larger than that of any previous request from the same client.
+    pub counter: u64,
+}
+
+impl ToHash256 for SignRequestBody {
+    fn to_hash256(&self) -> Hash256 {
+        simperby_common::canonical::to_hash256(self)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SignRequest {
+    body: SignRequestBody,
+    authentication: TypedSignature<SignRequestBody>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum SignResponse {
+    Signature(Signature),
+    Rejected(String),
+}
+
+async fn write_frame<T: Serialize>(
+    stream: &mut (impl AsyncWrite + Unpin),
+    value: &T,
+) -> Result<(), Error> {
+    let data = serde_json::to_vec(value)?;
+    stream.write_u32(data.len() as u32).await?;
+    stream.write_all(&data).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_frame<T: serde::de::DeserializeOwned>(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<T, Error> {
+    let len = stream.read_u32().await?;
+    if len > MAX_FRAME_SIZE {
+        return Err(anyhow!("frame too large: {} bytes", len));
+    }
+    let mut data = vec![0; len as usize];
+    stream.read_exact(&mut data).await?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// Sends the request and receives the response over a connected stream.
+async fn exchange(
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    request: &SignRequest,
+) -> Result<SignResponse, Error> {
+    write_frame(stream, request).await?;
+    read_frame(stream).await
+}
+
+/// A signer that delegates to a remote signer process.
+pub struct RemoteSigner {
+    endpoint: SignerEndpoint,
+    public_key: PublicKey,
+    authentication_key: PrivateKey,
+    counter: tokio::sync::Mutex<u64>,
+}
+
+impl RemoteSigner {
+    /// - `public_key`: the public key that the remote signer is expected to sign with.
+    /// - `authentication_key`: the key that authenticates this client to the remote signer.
+    pub fn new(
+        endpoint: SignerEndpoint,
+        public_key: PublicKey,
+        authentication_key: PrivateKey,
+    ) -> Self {
+        // Starts from the current time so that the counter keeps increasing across restarts.
+        let counter = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+        Self {
+            endpoint,
+            public_key,
+            authentication_key,
+            counter: tokio::sync::Mutex::new(counter),
+        }
+    }
+
+    async fn request(&self, request: &SignRequest) -> Result<SignResponse, Error> {
+        match &self.endpoint {
+            SignerEndpoint::Unix(path) => {
+                exchange(&mut UnixStream::connect(path).await?, request).await
+            }
+            SignerEndpoint::Tls {
+                address,
+                certificate,
+            } => {
+                let config = rustls::ClientConfig::builder()
+                    .with_safe_defaults()
+                    .with_custom_certificate_verifier(Arc::new(PinnedServerVerification {
+                        certificate: rustls::Certificate(certificate.clone()),
+                    }))
+                    .with_no_client_auth();
+                let stream = TcpStream::connect(address).await?;
+                let mut stream = TlsConnector::from(Arc::new(config))
+                    .connect(SERVER_NAME.try_into()?, stream)
+                    .await?;
+                exchange(&mut stream, request).await
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Signer for RemoteSigner {
+    fn public_key(&self) -> PublicKey {
+        self.public_key.clone()
+    }
+
+    async fn sign(&self, data: Hash256) -> Result<Signature, Error> {
+        let mut counter = self.counter.lock().await;
+        *counter += 1;
+        let body = SignRequestBody {
+            data,
+            counter: *counter,
+        };
+        let authentication = TypedSignature::sign(&body, &self.authentication_key)?;
+        match self
+            .request(&SignRequest {
+                body,
+                authentication,
+            })
+            .await?
+        {
+            SignResponse::Signature(signature) => {
+                // Never trust the remote side blindly.
+                signature.verify(data, &self.public_key)?;
+                Ok(signature)
+            }
+            SignResponse::Rejected(reason) => Err(anyhow!(
+                "the remote signer rejected the request: {}",
+                reason
+            )),
+        }
+    }
+}
+
+/// The state of a remote signer shared by the connections.
+#[derive(Clone)]
+struct SignerService {
+    signer: Arc<dyn Signer>,
+    authorized_clients: Vec<PublicKey>,
+    last_counters: Arc<tokio::sync::Mutex<HashMap<PublicKey, u64>>>,
+}
+
+impl SignerService {
+    async fn respond(&self, request: SignRequest) -> Result<SignResponse, Error> {
+        let client = request.authentication.signer().clone();
+        if !self.authorized_clients.contains(&client) {
+            return Ok(SignResponse::Rejected("unauthorized client".to_string()));
+        }
+        if let Err(e) = request.authentication.verify(&request.body) {
+            return Ok(SignResponse::Rejected(format!(
+                "invalid authentication: {}",
+                e
+            )));
+        }
+        let mut last_counters = self.last_counters.lock().await;
+        let last_counter = last_counters.entry(client).or_insert(0);
+        if request.body.counter <= *last_counter {
+            return Ok(SignResponse::Rejected("replayed request".to_string()));
+        }
+        *last_counter = request.body.counter;
+        Ok(SignResponse::Signature(
+            self.signer.sign(request.body.data).await?,
+        ))
+    }
+
+    async fn serve_stream(
+        &self,
+        stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    ) -> Result<(), Error> {
+        let request: SignRequest = read_frame(stream).await?;
+        let response = self.respond(request).await?;
+        write_frame(stream, &response).await
+    }
+}
+
+/// Serves as a remote signer indefinitely, signing only for the authorized clients.
+///
+/// `identity` is required for a TLS endpoint, whose `certificate` must be the one in it;
+/// it is ignored for a Unix domain socket.
+pub async fn serve_remote_signer(
+    endpoint: &SignerEndpoint,
+    identity: Option<&SignerIdentity>,
+    signer: Arc<dyn Signer>,
+    authorized_clients: Vec<PublicKey>,
+) -> Result<(), Error> {
+    let service = SignerService {
+        signer,
+        authorized_clients,
+        last_counters: Arc::new(tokio::sync::Mutex::new(HashMap::new())),
+    };
+    match endpoint {
+        SignerEndpoint::Unix(path) => {
+            let listener = UnixListener::bind(path)?;
+            loop {
+                let (mut stream, _) = listener.accept().await?;
+                let service = service.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = service.serve_stream(&mut stream).await {
+                        log::warn!("failed to serve a sign request: {}", e);
+                    }
+                });
+            }
+        }
+        SignerEndpoint::Tls {
+            address,
+            certificate,
+        } => {
+            let identity = identity
+                .ok_or_else(|| anyhow!("a TLS endpoint requires the identity of the signer"))?;
+            if &identity.certificate != certificate {
+                return Err(anyhow!(
+                    "the certificate of the endpoint doesn't match the identity"
+                ));
+            }
+            let config = rustls::ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![rustls::Certificate(identity.certificate.clone())],
+                    rustls::PrivateKey(identity.private_key.clone()),
+                )?;
+            let acceptor = TlsAcceptor::from(Arc::new(config));
+            let listener = TcpListener::bind(address).await?;
+            loop {
+                let (stream, peer) = listener.accept().await?;
+                let (service, acceptor) = (service.clone(), acceptor.clone());
+                tokio::spawn(async move {
+                    let result = async {
+                        let mut stream = acceptor.accept(stream).await?;
+                        service.serve_stream(&mut stream).await
+                    }
+                    .await;
+                    if let Err(e) = result {
+                        log::warn!("failed to serve a sign request from {}: {}", peer, e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_common::crypto::generate_keypair;
+
+    async fn sign_remotely(endpoint: SignerEndpoint, identity: Option<SignerIdentity>) {
+        let (public_key, private_key) = generate_keypair("validator");
+        let (client_public_key, client_private_key) = generate_keypair("client");
+        let server_endpoint = endpoint.clone();
+        tokio::spawn(async move {
+            serve_remote_signer(
+                &server_endpoint,
+                identity.as_ref(),
+                Arc::new(LocalSigner::new(private_key)),
+                vec![client_public_key],
+            )
+            .await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let data = Hash256::hash("data");
+        let signer = RemoteSigner::new(endpoint.clone(), public_key.clone(), client_private_key);
+        let signature = signer.sign(data).await.unwrap();
+        signature.verify(data, &public_key).unwrap();
+
+        let stranger = RemoteSigner::new(endpoint, public_key, generate_keypair("stranger").1);
+        stranger.sign(data).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn unix_socket() {
+        let path =
+            std::env::temp_dir().join(format!("simperby-signer-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        sign_remotely(
+            SignerEndpoint::Unix(path.to_str().unwrap().to_owned()),
+            None,
+        )
+        .await;
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn tls() {
+        let identity = SignerIdentity::generate().unwrap();
+        let endpoint = SignerEndpoint::Tls {
+            address: "127.0.0.1:36121".to_owned(),
+            certificate: identity.certificate.clone(),
+        };
+        sign_remotely(endpoint, Some(identity)).await;
+    }
+
+    #[tokio::test]
+    async fn tls_rejects_another_certificate() {
+        let identity = SignerIdentity::generate().unwrap();
+        let server_endpoint = SignerEndpoint::Tls {
+            address: "127.0.0.1:36122".to_owned(),
+            certificate: identity.certificate.clone(),
+        };
+        let (public_key, private_key) = generate_keypair("validator");
+        let (client_public_key, client_private_key) = generate_keypair("client");
+        tokio::spawn(async move {
+            serve_remote_signer(
+                &server_endpoint,
+                Some(&identity),
+                Arc::new(LocalSigner::new(private_key)),
+                vec![client_public_key],
+            )
+            .await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let endpoint = SignerEndpoint::Tls {
+            address: "127.0.0.1:36122".to_owned(),
+            certificate: SignerIdentity::generate().unwrap().certificate,
+        };
+        let signer = RemoteSigner::new(endpoint, public_key, client_private_key);
+        signer.sign(Hash256::hash("data")).await.unwrap_err();
+    }
+}
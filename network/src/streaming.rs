@@ -0,0 +1,411 @@
+//! The streaming transfer of large objects (e.g., the packfiles of the history) between the nodes.
+//!
+//! An object is a file served in chunks of `CHUNK_SIZE` bytes, described by its manifest
+//! which holds the hash of every chunk. Both ends read and write the file chunk by chunk,
+//! hashing it incrementally, so the memory is bounded regardless of the size of the object.
+//!
+//! Each chunk is verified as it arrives, so a corrupted one is fetched again alone,
+//! and an interrupted download resumes from the chunks already written.
+use super::*;
+use anyhow::anyhow;
+use serde_tc::http::*;
+use serde_tc::{serde_tc_full, StubCall};
+use std::future::Future;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+pub const CHUNK_SIZE: u64 = 1024 * 1024;
+/// How many times a chunk is fetched before giving up, if it doesn't match the manifest.
+const MAX_ATTEMPTS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectManifest {
+    /// The hash of the whole object, by which it is served.
+    pub hash: Hash256,
+    pub size: u64,
+    pub chunk_hashes: Vec<Hash256>,
+}
+
+impl ObjectManifest {
+    /// Reads the file chunk by chunk, hashing it.
+    pub async fn of_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut hasher = Hash256Hasher::new();
+        let mut chunk_hashes = Vec::new();
+        let mut size = 0;
+        let mut buffer = vec![0; CHUNK_SIZE as usize];
+        loop {
+            let read = read_chunk(&mut file, &mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            chunk_hashes.push(Hash256::hash(&buffer[..read]));
+            size += read as u64;
+        }
+        Ok(Self {
+            hash: hasher.finalize(),
+            size,
+            chunk_hashes,
+        })
+    }
+
+    fn chunk_length(&self, index: u64) -> usize {
+        (self.size - index * CHUNK_SIZE).min(CHUNK_SIZE) as usize
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let chunks = (self.size + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        if self.chunk_hashes.len() as u64 != chunks {
+            return Err(anyhow!(
+                "the manifest of {} has {} chunks for {} bytes",
+                self.hash,
+                self.chunk_hashes.len(),
+                self.size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Reads until the buffer is full or the file ends, returning the number of the bytes read.
+async fn read_chunk(file: &mut tokio::fs::File, buffer: &mut [u8]) -> Result<usize, Error> {
+    let mut read = 0;
+    while read < buffer.len() {
+        let n = file.read(&mut buffer[read..]).await?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+    }
+    Ok(read)
+}
+
+/// A directory of the objects to serve, each stored as `<hash>` with its manifest as `<hash>.json`.
+#[derive(Debug, Clone)]
+pub struct ObjectDirectory {
+    directory: PathBuf,
+}
+
+impl ObjectDirectory {
+    /// Opens the directory, creating it if not exists.
+    pub async fn open(directory: impl AsRef<Path>) -> Result<Self, Error> {
+        tokio::fs::create_dir_all(directory.as_ref()).await?;
+        Ok(Self {
+            directory: directory.as_ref().to_owned(),
+        })
+    }
+
+    fn path(&self, hash: &Hash256) -> PathBuf {
+        self.directory.join(hash.to_string())
+    }
+
+    fn manifest_path(&self, hash: &Hash256) -> PathBuf {
+        self.directory.join(format!("{}.json", hash))
+    }
+
+    /// Moves the file into the directory, returning its manifest.
+    pub async fn add(&self, path: impl AsRef<Path>) -> Result<ObjectManifest, Error> {
+        let manifest = ObjectManifest::of_file(path.as_ref()).await?;
+        let destination = self.path(&manifest.hash);
+        if tokio::fs::rename(path.as_ref(), &destination)
+            .await
+            .is_err()
+        {
+            // Possibly on another filesystem.
+            tokio::fs::copy(path.as_ref(), &destination).await?;
+            tokio::fs::remove_file(path.as_ref()).await?;
+        }
+        tokio::fs::write(
+            self.manifest_path(&manifest.hash),
+            serde_json::to_string(&manifest)?,
+        )
+        .await?;
+        Ok(manifest)
+    }
+
+    pub async fn manifest(&self, hash: &Hash256) -> Result<Option<ObjectManifest>, Error> {
+        match tokio::fs::read_to_string(self.manifest_path(hash)).await {
+            Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Reads the chunk of the object at the index.
+    pub async fn read_chunk(&self, hash: &Hash256, index: u64) -> Result<Vec<u8>, Error> {
+        let manifest = self
+            .manifest(hash)
+            .await?
+            .ok_or_else(|| anyhow!("unknown object {}", hash))?;
+        if index >= manifest.chunk_hashes.len() as u64 {
+            return Err(anyhow!("object {} has no chunk {}", hash, index));
+        }
+        let mut file = tokio::fs::File::open(self.path(hash)).await?;
+        file.seek(SeekFrom::Start(index * CHUNK_SIZE)).await?;
+        let mut buffer = vec![0; manifest.chunk_length(index)];
+        let read = read_chunk(&mut file, &mut buffer).await?;
+        buffer.truncate(read);
+        Ok(buffer)
+    }
+
+    pub async fn remove(&self, hash: &Hash256) -> Result<(), Error> {
+        tokio::fs::remove_file(self.manifest_path(hash)).await?;
+        tokio::fs::remove_file(self.path(hash)).await?;
+        Ok(())
+    }
+}
+
+/// The interface that will be wrapped into an HTTP RPC server for the peers.
+#[serde_tc_full]
+trait ObjectTransferRpcInterface: Send + Sync + 'static {
+    async fn get_manifest(
+        &self,
+        chain_id: ChainId,
+        hash: Hash256,
+    ) -> Result<ObjectManifest, String>;
+
+    async fn get_chunk(
+        &self,
+        chain_id: ChainId,
+        hash: Hash256,
+        index: u64,
+    ) -> Result<Vec<u8>, String>;
+}
+
+struct ObjectServer {
+    objects: ObjectDirectory,
+    chain_id: ChainId,
+}
+
+#[async_trait]
+impl ObjectTransferRpcInterface for ObjectServer {
+    async fn get_manifest(
+        &self,
+        chain_id: ChainId,
+        hash: Hash256,
+    ) -> Result<ObjectManifest, String> {
+        self.chain_id.check(&chain_id)?;
+        self.objects
+            .manifest(&hash)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("unknown object {}", hash))
+    }
+
+    async fn get_chunk(
+        &self,
+        chain_id: ChainId,
+        hash: Hash256,
+        index: u64,
+    ) -> Result<Vec<u8>, String> {
+        self.chain_id.check(&chain_id)?;
+        self.objects
+            .read_chunk(&hash, index)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Serves the objects in the directory indefinitely.
+pub async fn serve(port: u16, chain_id: ChainId, objects: ObjectDirectory) -> Result<(), Error> {
+    run_server(
+        port,
+        [(
+            "x".to_owned(),
+            create_http_object(
+                Arc::new(ObjectServer { objects, chain_id }) as Arc<dyn ObjectTransferRpcInterface>
+            ),
+        )]
+        .iter()
+        .cloned()
+        .collect(),
+    )
+    .await;
+    Ok(())
+}
+
+/// Downloads the object from the server at the URL into the file at the path,
+/// returning its manifest (see `receive()`).
+pub async fn download(
+    url: &str,
+    chain_id: &ChainId,
+    hash: &Hash256,
+    path: impl AsRef<Path>,
+) -> Result<ObjectManifest, Error> {
+    let stub = ObjectTransferRpcInterfaceStub::new(Box::new(HttpClient::new(
+        url.to_owned(),
+        reqwest::Client::new(),
+    )));
+    let manifest = stub
+        .get_manifest(chain_id.clone(), *hash)
+        .await?
+        .map_err(|e| anyhow!(e))?;
+    if manifest.hash != *hash {
+        return Err(anyhow!(
+            "asked for the object {}, but got the manifest of {}",
+            hash,
+            manifest.hash
+        ));
+    }
+    receive(&manifest, path, |index| {
+        let stub = &stub;
+        async move {
+            stub.get_chunk(chain_id.clone(), *hash, index)
+                .await?
+                .map_err(|e| anyhow!(e))
+        }
+    })
+    .await?;
+    Ok(manifest)
+}
+
+/// Writes the chunks of the object into the file at the path, verifying each of them
+/// and the whole against the manifest.
+///
+/// The chunks are written to `<path>.partial` first, and those already there
+/// (from an interrupted download) are kept if they match.
+pub async fn receive<F, Fut>(
+    manifest: &ObjectManifest,
+    path: impl AsRef<Path>,
+    mut fetch_chunk: F,
+) -> Result<(), Error>
+where
+    F: FnMut(u64) -> Fut,
+    Fut: Future<Output = Result<Vec<u8>, Error>>,
+{
+    manifest.check()?;
+    let mut partial = path.as_ref().as_os_str().to_owned();
+    partial.push(".partial");
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .open(&partial)
+        .await?;
+    let mut hasher = Hash256Hasher::new();
+    let mut buffer = vec![0; CHUNK_SIZE as usize];
+    let mut resuming = true;
+    for (index, expected) in manifest.chunk_hashes.iter().enumerate() {
+        let index = index as u64;
+        let length = manifest.chunk_length(index);
+        file.seek(SeekFrom::Start(index * CHUNK_SIZE)).await?;
+        if resuming {
+            let read = read_chunk(&mut file, &mut buffer[..length]).await?;
+            if read == length && Hash256::hash(&buffer[..length]) == *expected {
+                hasher.update(&buffer[..length]);
+                continue;
+            }
+            resuming = false;
+            file.seek(SeekFrom::Start(index * CHUNK_SIZE)).await?;
+        }
+        let mut attempts = 0;
+        let chunk = loop {
+            attempts += 1;
+            let chunk = fetch_chunk(index).await?;
+            if chunk.len() == length && Hash256::hash(&chunk) == *expected {
+                break chunk;
+            }
+            if attempts >= MAX_ATTEMPTS {
+                return Err(anyhow!(
+                    "chunk {} of {} doesn't match the manifest",
+                    index,
+                    manifest.hash
+                ));
+            }
+            log::warn!(
+                "chunk {} of {} doesn't match the manifest; fetching it again",
+                index,
+                manifest.hash
+            );
+        };
+        file.write_all(&chunk).await?;
+        hasher.update(&chunk);
+    }
+    file.set_len(manifest.size).await?;
+    file.sync_all().await?;
+    drop(file);
+    if hasher.finalize() != manifest.hash {
+        tokio::fs::remove_file(&partial).await?;
+        return Err(anyhow!("the object doesn't match {}", manifest.hash));
+    }
+    tokio::fs::rename(&partial, path.as_ref()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "simperby-streaming-{}-{}",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[tokio::test]
+    async fn transfer() {
+        let source = ObjectDirectory::open(directory("source")).await.unwrap();
+        let destination = directory("destination");
+        tokio::fs::create_dir_all(&destination).await.unwrap();
+
+        let content: Vec<u8> = (0..(CHUNK_SIZE * 2 + 10))
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let original = destination.join("original");
+        tokio::fs::write(&original, &content).await.unwrap();
+        let manifest = source.add(&original).await.unwrap();
+        assert_eq!(manifest.hash, Hash256::hash(&content));
+        assert_eq!(manifest.chunk_hashes.len(), 3);
+        assert_eq!(
+            source.manifest(&manifest.hash).await.unwrap(),
+            Some(manifest.clone())
+        );
+        assert_eq!(
+            source.read_chunk(&manifest.hash, 2).await.unwrap(),
+            content[(CHUNK_SIZE * 2) as usize..]
+        );
+
+        // An interrupted download, with the first chunk written.
+        let path = destination.join("received");
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        tokio::fs::write(&partial, &content[..CHUNK_SIZE as usize])
+            .await
+            .unwrap();
+        let mut fetched = Vec::new();
+        let mut corrupted = true;
+        receive(&manifest, &path, |index| {
+            fetched.push(index);
+            let corrupt = std::mem::replace(&mut corrupted, false);
+            let (source, hash) = (&source, manifest.hash);
+            async move {
+                let mut chunk = source.read_chunk(&hash, index).await?;
+                if corrupt {
+                    chunk[0] ^= 1;
+                }
+                Ok(chunk)
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(fetched, vec![1, 1, 2]);
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), content);
+
+        let mut forged = manifest.clone();
+        forged.chunk_hashes.pop();
+        assert!(receive(&forged, &path, |_| async { Ok(Vec::new()) })
+            .await
+            .is_err());
+
+        source.remove(&manifest.hash).await.unwrap();
+        assert_eq!(source.manifest(&manifest.hash).await.unwrap(), None);
+        tokio::fs::remove_dir_all(directory("source"))
+            .await
+            .unwrap();
+        tokio::fs::remove_dir_all(&destination).await.unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+//! The transports for the request-response traffic between the peers.
+//!
+//! - `TcpTransport`: a connection per request, with length-prefixed frames.
+//! - `QuicTransport`: a connection per peer, multiplexing the requests as streams,
+//! with 0-RTT reconnection and the built-in TLS.
+//! - `NoiseTcpTransport`: `TcpTransport` over the connections authenticated with the member keys
+//! and encrypted (see `noise`).
+//!
+//! `PeerTransport` chooses one for each peer: QUIC if the peer advertises a QUIC port,
+//! falling back to TCP if it fails.
+pub mod noise;
+mod quic;
+mod tcp;
+
+pub use noise::NoiseTcpTransport;
+pub use quic::QuicTransport;
+pub use tcp::TcpTransport;
+
+use super::*;
+use futures::future::BoxFuture;
+use std::net::SocketAddr;
+
+/// The maximum size of a request or a response.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+/// How long to wait for a QUIC request before falling back to TCP.
+const QUIC_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Handles a request, returning the response.
+pub type Handler = Arc<dyn Fn(Vec<u8>) -> BoxFuture<'static, Vec<u8>> + Send + Sync>;
+
+#[async_trait]
+pub trait Transport: Send + Sync + 'static {
+    /// Sends the request to the server at the address, returning the response.
+    async fn request(&self, address: SocketAddr, request: Vec<u8>) -> Result<Vec<u8>, Error>;
+
+    /// Serves the requests on the port indefinitely.
+    async fn serve(
+        &self,
+        port: u16,
+        handler: Handler,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error>;
+}
+
+/// The key of `Peer::ports` for the QUIC port of a service.
+pub fn quic_port_key(service: &str) -> String {
+    format!("{}-quic", service)
+}
+
+/// Chooses the transport for each peer.
+pub struct PeerTransport {
+    pub tcp: TcpTransport,
+    pub quic: QuicTransport,
+}
+
+impl PeerTransport {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Self {
+            tcp: TcpTransport,
+            quic: QuicTransport::new()?,
+        })
+    }
+
+    /// Sends the request to the service of the peer,
+    /// over QUIC if the peer advertises a QUIC port, and over TCP otherwise or if QUIC fails.
+    pub async fn request(
+        &self,
+        peer: &Peer,
+        service: &str,
+        request: Vec<u8>,
+    ) -> Result<Vec<u8>, Error> {
+        let ip = *peer.address.ip();
+        if let Some(port) = peer.ports.get(&quic_port_key(service)) {
+            let quic_request = self
+                .quic
+                .request(SocketAddrV4::new(ip, *port).into(), request.clone());
+            match tokio::time::timeout(QUIC_TIMEOUT, quic_request)
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out")))
+            {
+                Ok(response) => return Ok(response),
+                Err(e) => log::warn!(
+                    "QUIC request to {} failed; falling back to TCP: {}",
+                    peer.public_key,
+                    e
+                ),
+            }
+        }
+        let port = peer
+            .ports
+            .get(service)
+            .ok_or_else(|| anyhow::anyhow!("can't find port key: {}", service))?;
+        self.tcp
+            .request(SocketAddrV4::new(ip, *port).into(), request)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo() -> Handler {
+        Arc::new(|request: Vec<u8>| {
+            Box::pin(async move { request.into_iter().rev().collect() }) as BoxFuture<_>
+        })
+    }
+
+    fn peer(ports: &[(&str, u16)]) -> Peer {
+        Peer {
+            public_key: generate_keypair("peer").0,
+            address: "127.0.0.1:0".parse().unwrap(),
+            ports: ports
+                .iter()
+                .map(|(key, port)| (key.to_string(), *port))
+                .collect(),
+            message: String::new(),
+            recently_seen_timestamp: 0,
+            relay: None,
+            height: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn quic_with_tcp_fallback() {
+        let transport = PeerTransport::new().unwrap();
+        transport.tcp.serve(56101, echo()).await.unwrap();
+        transport.quic.serve(56102, echo()).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let tcp_only = peer(&[("dms", 56101)]);
+        let quic = peer(&[("dms", 56101), ("dms-quic", 56102)]);
+        // The QUIC port is wrong, so it falls back to TCP.
+        let broken_quic = peer(&[("dms", 56101), ("dms-quic", 56103)]);
+        for peer in [tcp_only, quic, broken_quic] {
+            let response = transport
+                .request(&peer, "dms", vec![1, 2, 3])
+                .await
+                .unwrap();
+            assert_eq!(response, vec![3, 2, 1]);
+        }
+    }
+}
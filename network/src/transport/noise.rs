@@ -0,0 +1,330 @@
+//! Mutually authenticated and encrypted connections, with the Noise XX handshake.
+//!
+//! Each node has a Noise static key for the session, which it signs with its Simperby key.
+//! The signature is sent in the handshake payload, binding the connection to the Simperby key;
+//! a peer whose key is not of a member is rejected, unless this node is a public read-only gateway.
+use super::tcp::{read_frame, write_frame};
+use super::*;
+use tokio::net::{TcpListener, TcpStream};
+
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+/// The maximum size of a Noise message.
+const MAX_NOISE_MESSAGE: usize = 65535;
+/// The size of the authentication tag appended to each encrypted message.
+const TAG_SIZE: usize = 16;
+
+/// The handshake payload, proving that the Noise static key belongs to the Simperby key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Identity {
+    /// Checked first, to reject a node of another chain with a clear error.
+    chain_id: ChainId,
+    public_key: PublicKey,
+    /// The signature on the hash of the Noise static public key.
+    signature: simperby_common::Signature,
+}
+
+#[derive(Clone)]
+pub struct NoiseConfig {
+    pub chain_id: ChainId,
+    pub public_key: PublicKey,
+    pub private_key: PrivateKey,
+    /// The members of the network; only they are accepted as the peers.
+    pub members: Vec<PublicKey>,
+}
+
+/// An established connection.
+pub struct SecureStream {
+    stream: TcpStream,
+    transport: snow::TransportState,
+    /// The key of the peer, if it is a member. `None` only for a client of a public gateway.
+    pub remote: Option<PublicKey>,
+}
+
+struct StaticKey {
+    private: Vec<u8>,
+    identity: Vec<u8>,
+}
+
+fn noise_params() -> Result<snow::params::NoiseParams, Error> {
+    Ok(NOISE_PARAMS.parse()?)
+}
+
+fn generate_static_key(config: &NoiseConfig) -> Result<StaticKey, Error> {
+    let keypair = snow::Builder::new(noise_params()?).generate_keypair()?;
+    let identity = Identity {
+        chain_id: config.chain_id.clone(),
+        public_key: config.public_key.clone(),
+        signature: simperby_common::Signature::sign(
+            Hash256::hash(&keypair.public),
+            &config.private_key,
+        )?,
+    };
+    Ok(StaticKey {
+        private: keypair.private,
+        identity: serde_json::to_vec(&identity)?,
+    })
+}
+
+/// Verifies the identity of the remote, returning its key if it is a member.
+fn verify_identity(
+    payload: &[u8],
+    remote_static: Option<&[u8]>,
+    config: &NoiseConfig,
+) -> Result<Option<PublicKey>, Error> {
+    let identity: Identity = serde_json::from_slice(payload)?;
+    config
+        .chain_id
+        .check(&identity.chain_id)
+        .map_err(|e| anyhow::anyhow!(e))?;
+    let remote_static =
+        remote_static.ok_or_else(|| anyhow::anyhow!("missing the remote static key"))?;
+    identity
+        .signature
+        .verify(Hash256::hash(remote_static), &identity.public_key)?;
+    if config.members.contains(&identity.public_key) {
+        Ok(Some(identity.public_key))
+    } else {
+        Ok(None)
+    }
+}
+
+async fn write_handshake_message(
+    stream: &mut TcpStream,
+    handshake: &mut snow::HandshakeState,
+    payload: &[u8],
+) -> Result<(), Error> {
+    let mut buffer = vec![0u8; MAX_NOISE_MESSAGE];
+    let size = handshake.write_message(payload, &mut buffer)?;
+    write_frame(stream, &buffer[..size]).await
+}
+
+async fn read_handshake_message(
+    stream: &mut TcpStream,
+    handshake: &mut snow::HandshakeState,
+) -> Result<Vec<u8>, Error> {
+    let message = read_frame(stream).await?;
+    let mut buffer = vec![0u8; MAX_NOISE_MESSAGE];
+    let size = handshake.read_message(&message, &mut buffer)?;
+    buffer.truncate(size);
+    Ok(buffer)
+}
+
+impl SecureStream {
+    /// Connects to a member, failing if the server is not one.
+    pub async fn connect(address: SocketAddr, config: &NoiseConfig) -> Result<Self, Error> {
+        let mut stream = TcpStream::connect(address).await?;
+        let static_key = generate_static_key(config)?;
+        let mut handshake = snow::Builder::new(noise_params()?)
+            .local_private_key(&static_key.private)
+            .build_initiator()?;
+        // -> e
+        write_handshake_message(&mut stream, &mut handshake, &[]).await?;
+        // <- e, ee, s, es
+        let payload = read_handshake_message(&mut stream, &mut handshake).await?;
+        let remote = verify_identity(&payload, handshake.get_remote_static(), config)?
+            .ok_or_else(|| anyhow::anyhow!("the server is not a member"))?;
+        // -> s, se
+        write_handshake_message(&mut stream, &mut handshake, &static_key.identity).await?;
+        Ok(Self {
+            stream,
+            transport: handshake.into_transport_mode()?,
+            remote: Some(remote),
+        })
+    }
+
+    /// Accepts a connection, rejecting a non-member unless `public_gateway` is set.
+    pub async fn accept(
+        mut stream: TcpStream,
+        config: &NoiseConfig,
+        public_gateway: bool,
+    ) -> Result<Self, Error> {
+        let static_key = generate_static_key(config)?;
+        let mut handshake = snow::Builder::new(noise_params()?)
+            .local_private_key(&static_key.private)
+            .build_responder()?;
+        // -> e
+        read_handshake_message(&mut stream, &mut handshake).await?;
+        // <- e, ee, s, es
+        write_handshake_message(&mut stream, &mut handshake, &static_key.identity).await?;
+        // -> s, se
+        let payload = read_handshake_message(&mut stream, &mut handshake).await?;
+        let remote = verify_identity(&payload, handshake.get_remote_static(), config)?;
+        if remote.is_none() && !public_gateway {
+            return Err(anyhow::anyhow!("the client is not a member"));
+        }
+        Ok(Self {
+            stream,
+            transport: handshake.into_transport_mode()?,
+            remote,
+        })
+    }
+
+    /// Sends the data, split into Noise messages and terminated by an empty one.
+    pub async fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_FRAME_SIZE {
+            return Err(anyhow::anyhow!("the data is too large: {}", data.len()));
+        }
+        let mut buffer = vec![0u8; MAX_NOISE_MESSAGE];
+        for chunk in data
+            .chunks(MAX_NOISE_MESSAGE - TAG_SIZE)
+            .chain(std::iter::once(&[][..]))
+        {
+            let size = self.transport.write_message(chunk, &mut buffer)?;
+            write_frame(&mut self.stream, &buffer[..size]).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn recv(&mut self) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        let mut buffer = vec![0u8; MAX_NOISE_MESSAGE];
+        loop {
+            let message = read_frame(&mut self.stream).await?;
+            let size = self.transport.read_message(&message, &mut buffer)?;
+            if size == 0 {
+                return Ok(data);
+            }
+            if data.len() + size > MAX_FRAME_SIZE {
+                return Err(anyhow::anyhow!("the data is too large"));
+            }
+            data.extend_from_slice(&buffer[..size]);
+        }
+    }
+}
+
+/// `TcpTransport` over the Noise connections.
+pub struct NoiseTcpTransport {
+    pub config: NoiseConfig,
+    /// If set, this node is a public gateway serving the non-members with this handler,
+    /// which must not accept anything that writes.
+    pub read_only_handler: Option<Handler>,
+}
+
+async fn accept(
+    listener: TcpListener,
+    config: NoiseConfig,
+    handler: Handler,
+    read_only_handler: Option<Handler>,
+) -> Result<(), Error> {
+    loop {
+        let (stream, address) = listener.accept().await?;
+        let config = config.clone();
+        let handler = Arc::clone(&handler);
+        let read_only_handler = read_only_handler.clone();
+        tokio::spawn(async move {
+            let result: Result<(), Error> = async {
+                let mut stream =
+                    SecureStream::accept(stream, &config, read_only_handler.is_some()).await?;
+                let handler = match (&stream.remote, read_only_handler) {
+                    (Some(_), _) => handler,
+                    (None, Some(read_only_handler)) => read_only_handler,
+                    (None, None) => unreachable!("a non-member is rejected in the handshake"),
+                };
+                let request = stream.recv().await?;
+                let response = handler(request).await;
+                stream.send(&response).await
+            }
+            .await;
+            if let Err(e) = result {
+                log::warn!("failed to serve a Noise request from {}: {}", address, e);
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl Transport for NoiseTcpTransport {
+    async fn request(&self, address: SocketAddr, request: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let mut stream = SecureStream::connect(address, &self.config).await?;
+        stream.send(&request).await?;
+        stream.recv().await
+    }
+
+    async fn serve(
+        &self,
+        port: u16,
+        handler: Handler,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+        Ok(tokio::spawn(accept(
+            listener,
+            self.config.clone(),
+            handler,
+            self.read_only_handler.clone(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(name: &str) -> NoiseConfig {
+        let (public_key, private_key) = generate_keypair(name);
+        NoiseConfig {
+            chain_id: Default::default(),
+            public_key,
+            private_key,
+            members: vec![generate_keypair("a").0, generate_keypair("b").0],
+        }
+    }
+
+    fn handler(response: &'static str) -> Handler {
+        Arc::new(move |_: Vec<u8>| {
+            Box::pin(async move { response.as_bytes().to_vec() }) as BoxFuture<_>
+        })
+    }
+
+    #[tokio::test]
+    async fn authenticate_members() {
+        let server = NoiseTcpTransport {
+            config: config("a"),
+            read_only_handler: None,
+        };
+        server.serve(56111, handler("member")).await.unwrap();
+        let gateway = NoiseTcpTransport {
+            config: config("a"),
+            read_only_handler: Some(handler("read-only")),
+        };
+        gateway.serve(56112, handler("member")).await.unwrap();
+        let non_member_server = NoiseTcpTransport {
+            config: config("c"),
+            read_only_handler: None,
+        };
+        non_member_server
+            .serve(56113, handler("member"))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let address = |port: u16| SocketAddr::from(([127, 0, 0, 1], port));
+        let member = NoiseTcpTransport {
+            config: config("b"),
+            read_only_handler: None,
+        };
+        let non_member = NoiseTcpTransport {
+            config: config("c"),
+            read_only_handler: None,
+        };
+        let large = vec![7u8; 200_000];
+        assert_eq!(
+            member.request(address(56111), large).await.unwrap(),
+            b"member"
+        );
+        non_member
+            .request(address(56111), Vec::new())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            non_member
+                .request(address(56112), Vec::new())
+                .await
+                .unwrap(),
+            b"read-only"
+        );
+        member
+            .request(address(56113), Vec::new())
+            .await
+            .unwrap_err();
+    }
+}
@@ -0,0 +1,141 @@
+use super::*;
+use std::collections::HashMap;
+use std::time::SystemTime;
+use tokio::sync::Mutex;
+
+/// The server name in the TLS handshake; the certificates are self-signed.
+const SERVER_NAME: &str = "simperby";
+
+/// A connection per peer, multiplexing the requests as streams.
+///
+/// The TLS certificates are self-signed and not verified; TLS is only for the encryption here.
+/// The peers are authenticated with their member keys by the upper layer.
+pub struct QuicTransport {
+    endpoint: quinn::Endpoint,
+    connections: Mutex<HashMap<SocketAddr, quinn::Connection>>,
+}
+
+struct SkipServerVerification;
+
+impl rustls::client::ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+fn client_config() -> quinn::ClientConfig {
+    let mut crypto = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(SkipServerVerification))
+        .with_no_client_auth();
+    crypto.enable_early_data = true;
+    quinn::ClientConfig::new(Arc::new(crypto))
+}
+
+fn server_config() -> Result<quinn::ServerConfig, Error> {
+    let certificate = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_owned()])?;
+    let mut crypto = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![rustls::Certificate(certificate.serialize_der()?)],
+            rustls::PrivateKey(certificate.serialize_private_key_der()),
+        )?;
+    // Accepts the 0-RTT data of the reconnecting clients.
+    crypto.max_early_data_size = u32::MAX;
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+impl QuicTransport {
+    pub fn new() -> Result<Self, Error> {
+        let mut endpoint = quinn::Endpoint::client("0.0.0.0:0".parse()?)?;
+        endpoint.set_default_client_config(client_config());
+        Ok(Self {
+            endpoint,
+            connections: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the connection to the address, reusing the existing one if it is alive.
+    async fn connect(&self, address: SocketAddr) -> Result<quinn::Connection, Error> {
+        let mut connections = self.connections.lock().await;
+        if let Some(connection) = connections.get(&address) {
+            if connection.close_reason().is_none() {
+                return Ok(connection.clone());
+            }
+        }
+        let connecting = self.endpoint.connect(address, SERVER_NAME)?;
+        // Reconnects in 0-RTT if this endpoint has connected to the server before.
+        let connection = match connecting.into_0rtt() {
+            Ok((connection, _)) => connection,
+            Err(connecting) => connecting.await?,
+        };
+        connections.insert(address, connection.clone());
+        Ok(connection)
+    }
+}
+
+async fn handle_connection(connecting: quinn::Connecting, handler: Handler) -> Result<(), Error> {
+    let connection = connecting.await?;
+    loop {
+        let (mut send, mut recv) = match connection.accept_bi().await {
+            Ok(stream) => stream,
+            Err(quinn::ConnectionError::ApplicationClosed(_)) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let handler = Arc::clone(&handler);
+        tokio::spawn(async move {
+            let result: Result<(), Error> = async {
+                let request = recv.read_to_end(MAX_FRAME_SIZE).await?;
+                let response = handler(request).await;
+                send.write_all(&response).await?;
+                send.finish().await?;
+                Ok(())
+            }
+            .await;
+            if let Err(e) = result {
+                log::warn!("failed to serve a QUIC request: {}", e);
+            }
+        });
+    }
+}
+
+async fn accept(endpoint: quinn::Endpoint, handler: Handler) -> Result<(), Error> {
+    while let Some(connecting) = endpoint.accept().await {
+        let handler = Arc::clone(&handler);
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(connecting, handler).await {
+                log::warn!("QUIC connection failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Transport for QuicTransport {
+    async fn request(&self, address: SocketAddr, request: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let connection = self.connect(address).await?;
+        let (mut send, mut recv) = connection.open_bi().await?;
+        send.write_all(&request).await?;
+        send.finish().await?;
+        Ok(recv.read_to_end(MAX_FRAME_SIZE).await?)
+    }
+
+    async fn serve(
+        &self,
+        port: u16,
+        handler: Handler,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
+        let endpoint = quinn::Endpoint::server(server_config()?, ([0, 0, 0, 0], port).into())?;
+        Ok(tokio::spawn(accept(endpoint, handler)))
+    }
+}
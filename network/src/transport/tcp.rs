@@ -0,0 +1,67 @@
+use super::*;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A connection per request, with length-prefixed frames.
+pub struct TcpTransport;
+
+pub(super) async fn write_frame(
+    stream: &mut (impl AsyncWrite + Unpin),
+    data: &[u8],
+) -> Result<(), Error> {
+    if data.len() > MAX_FRAME_SIZE {
+        return Err(anyhow::anyhow!("the frame is too large: {}", data.len()));
+    }
+    stream.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    stream.write_all(data).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+pub(super) async fn read_frame(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>, Error> {
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length).await?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(anyhow::anyhow!("the frame is too large: {}", length));
+    }
+    let mut data = vec![0u8; length];
+    stream.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+async fn accept(listener: TcpListener, handler: Handler) -> Result<(), Error> {
+    loop {
+        let (mut stream, address) = listener.accept().await?;
+        let handler = Arc::clone(&handler);
+        tokio::spawn(async move {
+            let result: Result<(), Error> = async {
+                let request = read_frame(&mut stream).await?;
+                let response = handler(request).await;
+                write_frame(&mut stream, &response).await
+            }
+            .await;
+            if let Err(e) = result {
+                log::warn!("failed to serve a TCP request from {}: {}", address, e);
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn request(&self, address: SocketAddr, request: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let mut stream = TcpStream::connect(address).await?;
+        write_frame(&mut stream, &request).await?;
+        read_frame(&mut stream).await
+    }
+
+    async fn serve(
+        &self,
+        port: u16,
+        handler: Handler,
+    ) -> Result<tokio::task::JoinHandle<Result<(), Error>>, Error> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+        Ok(tokio::spawn(accept(listener, handler)))
+    }
+}
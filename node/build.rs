@@ -0,0 +1,8 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto");
+        tonic_build::compile_protos("proto/simperby/node/v1/node.proto")
+            .expect("failed to compile the protos");
+    }
+}
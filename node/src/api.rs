@@ -0,0 +1,219 @@
+//! The HTTP API of the node, for the dashboards and the scripts.
+//!
+//! It is a `serde-tc` RPC server (JSON over HTTP) like the DMS, serving `NodeApiInterface`
+//! under the object key `api` (i.e., `http://host:port/api`);
+//! `NodeApiInterfaceStub` is its client.
+//! The mutating methods take the token of `ApiConfig`,
+//! and are disabled if no token is configured.
+use super::*;
+use serde_tc::http::*;
+use serde_tc::{serde_tc_full, StubCall};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiConfig {
+    /// The port of the server; if none, the API is not served.
+    pub port: Option<u16>,
+    /// The port of the WebSocket subscriptions to the events (see `events`);
+    /// if none, they are not served.
+    pub event_port: Option<u16>,
+    /// The port of the gRPC interface (see `grpc`), which needs the `grpc` feature;
+    /// if none, it is not served.
+    #[serde(default)]
+    pub grpc_port: Option<u16>,
+    /// The token for the mutating methods. Prefer giving it by `SIMPERBY_API__TOKEN`
+    /// (see `config`) to writing it in the file.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub protocol_version: String,
+    pub chain_name: String,
+    pub public_key: PublicKey,
+    pub last_finalized_height: BlockHeight,
+    /// The disk usage past the thresholds of the quotas (see `storage`).
+    #[serde(default)]
+    pub storage_warnings: Vec<String>,
+}
+
+#[serde_tc_full]
+pub trait NodeApiInterface: Send + Sync + 'static {
+    async fn status(&self) -> Result<NodeStatus, String>;
+
+    async fn last_finalized_block(&self) -> Result<BlockHeader, String>;
+
+    async fn reserved_state(&self) -> Result<ReservedState, String>;
+
+    /// Returns the reserved state as of the given height or commit of the finalized history.
+    async fn reserved_state_at(&self, point: FinalizedPoint) -> Result<ReservedState, String>;
+
+    /// Returns the agendas pending for the approval, with their hashes.
+    async fn pending_agendas(&self) -> Result<Vec<(CommitHash, Hash256)>, String>;
+
+    /// Returns the disk usage of the stores and the history retained by each branch.
+    async fn storage_usage(&self) -> Result<storage::StorageReport, String>;
+
+    /// Returns the review comments on the agenda (see `SimperbyApi::get_review_comments()`).
+    async fn review_comments(
+        &self,
+        agenda_commit: CommitHash,
+    ) -> Result<Vec<SignedReviewComment>, String>;
+
+    /// Creates a transaction commit on the `work` branch (see `SimperbyApi::create_transaction()`).
+    async fn submit_transaction(
+        &self,
+        token: String,
+        transaction: Transaction,
+    ) -> Result<CommitHash, String>;
+
+    /// Votes for the agenda and propagates the vote.
+    async fn vote(&self, token: String, agenda_commit: CommitHash) -> Result<(), String>;
+
+    /// Comments on a line of a file changed by the agenda
+    /// (see `SimperbyApi::comment_on_agenda()`).
+    async fn comment(
+        &self,
+        token: String,
+        agenda_commit: CommitHash,
+        path: String,
+        line: u64,
+        text: String,
+        off_the_record: bool,
+    ) -> Result<(), String>;
+}
+
+struct ApiServer {
+    node: Arc<dyn SimperbyApi + Send + Sync>,
+    config: Config,
+}
+
+/// Checks the token for the mutating methods, which is shared with the gRPC interface.
+pub(crate) fn authenticate(config: &ApiConfig, token: &str) -> Result<(), String> {
+    let expected = config.token.as_ref().ok_or_else(|| {
+        "the mutating methods are disabled without `api.token` configured".to_owned()
+    })?;
+    // Compares the digests, so that the time doesn't depend on the common prefix.
+    if Hash256::hash(token) != Hash256::hash(expected) {
+        return Err("invalid token".to_owned());
+    }
+    Ok(())
+}
+
+impl ApiServer {
+    fn authenticate(&self, token: &str) -> Result<(), String> {
+        authenticate(&self.config.api, token)
+    }
+}
+
+#[async_trait]
+impl NodeApiInterface for ApiServer {
+    async fn status(&self) -> Result<NodeStatus, String> {
+        let header = self
+            .node
+            .get_last_finalized_block_header()
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(NodeStatus {
+            protocol_version: PROTOCOL_VERSION.to_owned(),
+            chain_name: self.config.chain_name.clone(),
+            public_key: self.config.public_key.clone(),
+            last_finalized_height: header.height,
+            storage_warnings: storage::measure(&self.config)
+                .await
+                .map(|usage| self.config.storage.warnings(&usage))
+                .map_err(|e| e.to_string())?,
+        })
+    }
+
+    async fn last_finalized_block(&self) -> Result<BlockHeader, String> {
+        self.node
+            .get_last_finalized_block_header()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn reserved_state(&self) -> Result<ReservedState, String> {
+        self.node
+            .get_reserved_state()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn reserved_state_at(&self, point: FinalizedPoint) -> Result<ReservedState, String> {
+        self.node
+            .get_reserved_state_at(point)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn pending_agendas(&self) -> Result<Vec<(CommitHash, Hash256)>, String> {
+        self.node.get_agendas().await.map_err(|e| e.to_string())
+    }
+
+    async fn storage_usage(&self) -> Result<storage::StorageReport, String> {
+        self.node
+            .get_storage_usage()
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn review_comments(
+        &self,
+        agenda_commit: CommitHash,
+    ) -> Result<Vec<SignedReviewComment>, String> {
+        self.node
+            .get_review_comments(agenda_commit)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn submit_transaction(
+        &self,
+        token: String,
+        transaction: Transaction,
+    ) -> Result<CommitHash, String> {
+        self.authenticate(&token)?;
+        self.node
+            .create_transaction(transaction)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn vote(&self, token: String, agenda_commit: CommitHash) -> Result<(), String> {
+        self.authenticate(&token)?;
+        self.node
+            .vote(agenda_commit)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn comment(
+        &self,
+        token: String,
+        agenda_commit: CommitHash,
+        path: String,
+        line: u64,
+        text: String,
+        off_the_record: bool,
+    ) -> Result<(), String> {
+        self.authenticate(&token)?;
+        self.node
+            .comment_on_agenda(agenda_commit, path, line, text, off_the_record)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Serves the API of the node indefinitely, if `ApiConfig::port` is set.
+pub async fn serve(
+    node: Arc<dyn SimperbyApi + Send + Sync>,
+    config: Config,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let port = config.api.port?;
+    let object =
+        create_http_object(Arc::new(ApiServer { node, config }) as Arc<dyn NodeApiInterface>);
+    Some(tokio::spawn(async move {
+        run_server(port, [("api".to_owned(), object)].iter().cloned().collect()).await;
+    }))
+}
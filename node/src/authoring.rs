@@ -0,0 +1,61 @@
+//! The transaction authoring of the CLI (see `simperby_repository::authoring`),
+//! signing the transaction commits with the key of the node.
+use super::*;
+use simperby_network::signer::Signer;
+use simperby_repository::authoring::TransactionPreview;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+
+/// The namespace of the note holding the signature of the author on a transaction commit.
+pub const SIGNATURE_NOTE_NAMESPACE: &str = "transaction-signature";
+
+async fn open<R: RawRepository>(config: &Config) -> Result<DistributedRepository<R>> {
+    DistributedRepository::new(R::open(&config.repository_directory).await?).await
+}
+
+/// Starts a transaction authored by this node.
+pub async fn begin<R: RawRepository>(config: &Config, head: String, body: String) -> Result<()> {
+    open::<R>(config)
+        .await?
+        .begin_transaction(config.public_key.clone(), head, body)
+        .await
+}
+
+pub async fn stage<R: RawRepository>(config: &Config, paths: &[String]) -> Result<()> {
+    open::<R>(config).await?.stage_transaction(paths).await
+}
+
+pub async fn preview<R: RawRepository>(config: &Config) -> Result<TransactionPreview> {
+    open::<R>(config).await?.preview_transaction().await
+}
+
+/// Creates the transaction commit and signs it, attaching the signature as a note.
+pub async fn commit<R: RawRepository>(
+    config: &Config,
+    signer: &dyn Signer,
+) -> Result<(CommitHash, Transaction)> {
+    let mut repo = open::<R>(config).await?;
+    let author = repo.preview_transaction().await?.draft.author;
+    if signer.public_key() != author {
+        return Err(anyhow::anyhow!(
+            "the signer is not the author {} of the transaction",
+            author
+        ));
+    }
+    let (commit_hash, transaction) = repo.commit_transaction().await?;
+    let signature: TypedSignature<Transaction> = TypedSignature::new(
+        signer.sign(transaction.to_hash256()).await?,
+        signer.public_key(),
+    );
+    repo.add_note(
+        &commit_hash,
+        SIGNATURE_NOTE_NAMESPACE,
+        &serde_json::to_string(&signature)?,
+    )
+    .await?;
+    Ok((commit_hash, transaction))
+}
+
+pub async fn abort<R: RawRepository>(config: &Config) -> Result<()> {
+    open::<R>(config).await?.abort_transaction().await
+}
@@ -0,0 +1,121 @@
+//! Joining an existing chain: cloning its repository and setting up the storages of the node.
+use super::*;
+use simperby_network::dms::DistributedMessageSet;
+use simperby_network::peer_discovery::PeerDiscoveryImpl;
+use simperby_network::primitives::MessageStore;
+use simperby_network::PeerDiscovery;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+use std::net::SocketAddr;
+
+/// Returns the peer-discovery endpoint (`host:port`) of the host serving the repository URL,
+/// assuming that it serves the discovery on the given port.
+pub fn origin_peer(url: &str, discovery_port: u16) -> Option<String> {
+    let url = url::Url::parse(url).ok()?;
+    Some(format!("{}:{}", url.host_str()?, discovery_port))
+}
+
+/// Clones the chain from the URL into the directories of the configuration, which must not exist,
+/// returning the last finalized block.
+///
+/// The history is verified from the genesis, or from the checkpoint if given
+/// (see `DistributedRepository::clone_from()`). Then the storages of the peer discovery
+/// and the DMSes are created, with the given peer (`host:port`) registered as a bootstrap peer.
+pub async fn clone<S: MessageStore, R: RawRepository>(
+    config: &Config,
+    url: &str,
+    checkpoint: Option<CommitHash>,
+    peer: &str,
+) -> Result<BlockHeader> {
+    let repo =
+        DistributedRepository::<R>::clone_from(&config.repository_directory, url, checkpoint)
+            .await?;
+    let last_header = repo.get_last_finalized_block_header().await?;
+    drop(repo);
+    set_up_storages::<S>(config, &last_header, peer).await?;
+    Ok(last_header)
+}
+
+/// Like `clone()`, verifying the history from the checkpoint attested by
+/// the sources of `Config::checkpoint` (see `checkpoint`).
+pub async fn clone_from_checkpoint<S: MessageStore, R: RawRepository>(
+    config: &Config,
+    url: &str,
+    peer: &str,
+) -> Result<BlockHeader> {
+    let checkpoint_config = config
+        .checkpoint
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("`checkpoint` is not configured"))?;
+    let attestation = checkpoint::fetch(checkpoint_config).await?;
+    let repo = DistributedRepository::<R>::clone_from(
+        &config.repository_directory,
+        url,
+        Some(attestation.commit),
+    )
+    .await?;
+    // The commit is not signed, unlike the header.
+    if repo.read_block(&attestation.commit).await?.to_hash256() != attestation.header.to_hash256() {
+        return Err(anyhow::anyhow!(
+            "the commit {} is not the attested block of height {}",
+            attestation.commit,
+            attestation.header.height
+        ));
+    }
+    let last_header = repo.get_last_finalized_block_header().await?;
+    drop(repo);
+    set_up_storages::<S>(config, &last_header, peer).await?;
+    Ok(last_header)
+}
+
+/// Creates the storages of the peer discovery and the DMSes,
+/// with the given peer (`host:port`) registered as a bootstrap peer.
+async fn set_up_storages<S: MessageStore>(
+    config: &Config,
+    last_header: &BlockHeader,
+    peer: &str,
+) -> Result<()> {
+    for (directory, dms_key) in [
+        (&config.governance_directory, "governance"),
+        (&config.consensus_directory, "consensus"),
+        (&config.chat_directory, simperby_chat::CHAT_DMS_KEY),
+    ] {
+        S::create(directory).await?;
+        DistributedMessageSet::<(), S>::create(
+            S::open(directory).await?,
+            last_header.height,
+            dms_key.to_owned(),
+        )
+        .await?;
+    }
+
+    PeerDiscoveryImpl::create(&config.peer_directory).await?;
+    let addresses = tokio::net::lookup_host(peer)
+        .await
+        .map_err(|e| anyhow::anyhow!("failed to resolve the origin peer {}: {}", peer, e))?
+        .filter_map(|address| match address {
+            SocketAddr::V4(address) => Some(address),
+            SocketAddr::V6(_) => None,
+        })
+        .collect();
+    PeerDiscoveryImpl::add_bootstrap_addresses(&config.peer_directory, addresses).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn origin() {
+        assert_eq!(
+            origin_peer("http://seed.example.org:8080/chain.git", 9100).as_deref(),
+            Some("seed.example.org:9100")
+        );
+        assert_eq!(
+            origin_peer("git://10.0.0.1/chain", 9200).as_deref(),
+            Some("10.0.0.1:9200")
+        );
+        assert_eq!(origin_peer("not a url", 9100), None);
+    }
+}
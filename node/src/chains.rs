@@ -0,0 +1,218 @@
+//! Running several chains (e.g., one per DAO or per working group) under one node.
+//!
+//! The chains are listed in a chains file (`chains.toml` by default), each with its own
+//! configuration file. The keystore of the chains file is shared: it overrides
+//! the `keystore_path` of every chain, so that the member holds a single key.
+//!
+//! ```toml
+//! keystore_path = "keystore.json"
+//!
+//! [[chains]]
+//! name = "dao"
+//! config = "dao/simperby.toml"
+//! ```
+use super::*;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChainEntry {
+    /// The local name to select the chain with (e.g., `--chain <name>`).
+    pub name: String,
+    /// The path to the configuration file of the chain.
+    pub config: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct ChainsFile {
+    /// The keystore shared by the chains.
+    pub keystore_path: String,
+    #[serde(default)]
+    pub chains: Vec<ChainEntry>,
+}
+
+impl ChainsFile {
+    pub async fn read(path: &str) -> Result<Self> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to read the chains file {}: {}", path, e))?;
+        toml::from_str(&content).map_err(|e| anyhow::anyhow!("invalid chains file {}: {}", path, e))
+    }
+
+    pub async fn write(&self, path: &str) -> Result<()> {
+        tokio::fs::write(path, toml::to_string(self)?).await?;
+        Ok(())
+    }
+
+    pub fn find(&self, name: &str) -> Result<&ChainEntry> {
+        self.chains
+            .iter()
+            .find(|chain| chain.name == name)
+            .ok_or_else(|| anyhow::anyhow!("unknown chain `{}` (see `simperby chain list`)", name))
+    }
+
+    /// Adds the chain, failing if the name is taken.
+    pub fn add(&mut self, name: &str, config: &str) -> Result<()> {
+        if self.find(name).is_ok() {
+            return Err(anyhow::anyhow!("the chain `{}` already exists", name));
+        }
+        self.chains.push(ChainEntry {
+            name: name.to_owned(),
+            config: config.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Removes the chain from the list; its configuration and storages are left intact.
+    pub fn remove(&mut self, name: &str) -> Result<ChainEntry> {
+        let index = self
+            .chains
+            .iter()
+            .position(|chain| chain.name == name)
+            .ok_or_else(|| anyhow::anyhow!("unknown chain `{}`", name))?;
+        Ok(self.chains.remove(index))
+    }
+
+    /// The overrides applied to every chain, before the given ones.
+    fn overrides(&self, overrides: &[String]) -> Vec<String> {
+        let keystore_path = toml::Value::String(self.keystore_path.clone());
+        std::iter::once(format!("keystore_path={}", keystore_path))
+            .chain(overrides.iter().cloned())
+            .collect()
+    }
+}
+
+/// Loads the configuration of the chain, with the shared keystore (see `config::load()`).
+pub async fn load(chains_path: &str, name: &str, overrides: &[String]) -> Result<Config> {
+    let chains = ChainsFile::read(chains_path).await?;
+    let chain = chains.find(name)?;
+    config::load(&chain.config, &chains.overrides(overrides)).await
+}
+
+/// Loads the configurations of all the chains, failing if they conflict with each other.
+pub async fn load_all(chains_path: &str, overrides: &[String]) -> Result<Vec<(String, Config)>> {
+    let chains = ChainsFile::read(chains_path).await?;
+    let mut configs = Vec::new();
+    for chain in &chains.chains {
+        let config = config::load(&chain.config, &chains.overrides(overrides))
+            .await
+            .map_err(|e| anyhow::anyhow!("chain `{}`: {}", chain.name, e))?;
+        configs.push((chain.name.clone(), config));
+    }
+    let problems = validate(&configs);
+    if !problems.is_empty() {
+        return Err(anyhow::anyhow!(
+            "conflicting chains in {}:\n- {}",
+            chains_path,
+            problems.join("\n- ")
+        ));
+    }
+    Ok(configs)
+}
+
+/// Returns the conflicts between the chains, which can run together if there is none.
+///
+/// The chains must have distinct chain names, storage directories and ports,
+/// and the same public key (of the shared keystore).
+pub fn validate(configs: &[(String, Config)]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut chain_names = HashMap::new();
+    let mut directories = HashMap::new();
+    let mut ports = HashMap::new();
+    for (name, config) in configs {
+        if let Some(other) = chain_names.insert(&config.chain_name, name) {
+            problems.push(format!(
+                "`{}` and `{}` are the same chain `{}`",
+                other, name, config.chain_name
+            ));
+        }
+        for (key, directory) in config::directories(config) {
+            if let Some(other) = directories.insert(directory, name) {
+                problems.push(format!(
+                    "`{}` of `{}` ({}) is used by `{}`",
+                    key, name, directory, other
+                ));
+            }
+        }
+        for (key, port) in config::ports(config) {
+            if let Some(other) = ports.insert(port, name) {
+                problems.push(format!(
+                    "`{}` of `{}` ({}) is used by `{}`",
+                    key, name, port, other
+                ));
+            }
+        }
+        if let Some((first, first_config)) = configs.first() {
+            if first_config.public_key != config.public_key {
+                problems.push(format!(
+                    "`{}` has a public key different from `{}`",
+                    name, first
+                ));
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conflicts() {
+        let a = config::tests::config();
+        let mut b = a.clone();
+        assert_eq!(
+            validate(&[("a".to_owned(), a.clone()), ("b".to_owned(), b.clone())]).len(),
+            // The chain name, 5 directories and 4 ports.
+            10
+        );
+
+        b.chain_name = "other".to_owned();
+        for directory in [
+            &mut b.peer_directory,
+            &mut b.governance_directory,
+            &mut b.chat_directory,
+            &mut b.consensus_directory,
+            &mut b.repository_directory,
+        ] {
+            *directory = format!("other/{}", directory);
+        }
+        b.ports = Ports {
+            peer_discovery: 9200,
+            governance: 9201,
+            consensus: 9202,
+            chat: 9203,
+        };
+        let configs = [("a".to_owned(), a), ("b".to_owned(), b)];
+        assert!(validate(&configs).is_empty());
+
+        let mut configs = configs;
+        configs[1].1.public_key = generate_keypair("other").0;
+        assert_eq!(validate(&configs).len(), 1);
+    }
+
+    #[test]
+    fn chains_file() {
+        let mut chains = ChainsFile {
+            keystore_path: "shared/keystore.json".to_owned(),
+            chains: Vec::new(),
+        };
+        chains.add("dao", "dao/simperby.toml").unwrap();
+        chains.add("wg", "wg/simperby.toml").unwrap();
+        assert!(chains.add("dao", "x.toml").is_err());
+        assert_eq!(chains.find("wg").unwrap().config, "wg/simperby.toml");
+        assert_eq!(chains.remove("dao").unwrap().name, "dao");
+        assert!(chains.find("dao").is_err());
+
+        let overrides = chains.overrides(&["dev_mode=true".to_owned()]);
+        assert_eq!(
+            overrides,
+            vec![
+                "keystore_path=\"shared/keystore.json\"".to_owned(),
+                "dev_mode=true".to_owned()
+            ]
+        );
+        let parsed: ChainsFile = toml::from_str(&toml::to_string(&chains).unwrap()).unwrap();
+        assert_eq!(parsed, chains);
+    }
+}
@@ -0,0 +1,227 @@
+//! The checkpoint attestations, for joining a chain without verifying its whole history.
+//!
+//! An attestation is a finalized block with its finalization proof, published as JSON
+//! (see `attest()`) and served over HTTPS by anyone. A joining node fetches it from every URL of
+//! `CheckpointConfig`, keeps the ones signed by more than 2/3 of the trusted validator set,
+//! and takes the checkpoint only if more than half of the URLs attest the same block.
+//! Then the history is verified from the checkpoint (see `bootstrap::clone_from_checkpoint()`).
+use super::*;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+use std::time::Duration;
+
+/// The timeout of fetching an attestation.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustedValidator {
+    pub public_key: PublicKey,
+    pub voting_power: VotingPower,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointConfig {
+    /// The URLs serving the attestations.
+    pub urls: Vec<String>,
+    /// The validator set trusted to finalize the checkpoint,
+    /// which must be up to date with the chain.
+    pub validators: Vec<TrustedValidator>,
+}
+
+impl CheckpointConfig {
+    /// Checks the configuration, which takes only HTTPS URLs
+    /// since the sources are trusted to serve the attestations of the chain.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.urls.is_empty() || self.validators.is_empty() {
+            return Err("no URL or no validator".to_owned());
+        }
+        if let Some(url) = self
+            .urls
+            .iter()
+            .find(|url| url::Url::parse(url).map_or(true, |url| url.scheme() != "https"))
+        {
+            return Err(format!("invalid or non-HTTPS URL `{}`", url));
+        }
+        Ok(())
+    }
+
+    fn validator_set(&self) -> Vec<(PublicKey, VotingPower)> {
+        self.validators
+            .iter()
+            .map(|validator| (validator.public_key.clone(), validator.voting_power))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointAttestation {
+    /// The commit of the block.
+    pub commit: CommitHash,
+    pub header: BlockHeader,
+    /// The signatures of the validators on the header.
+    pub proof: FinalizationProof,
+}
+
+impl CheckpointAttestation {
+    /// Verifies that the block is finalized by the validator set.
+    ///
+    /// Note that the commit is not signed; it is checked against the header once cloned.
+    pub fn verify(&self, validator_set: &[(PublicKey, VotingPower)]) -> Result<()> {
+        verify::verify_finalization_proof(&self.header, &self.proof, validator_set)
+            .map_err(|e| anyhow::anyhow!("invalid proof of height {}: {}", self.header.height, e))
+    }
+}
+
+/// Creates the attestation of the last block of which the finalization proof is known,
+/// i.e., the one before the last finalized block.
+pub async fn attest<R: RawRepository>(config: &Config) -> Result<CheckpointAttestation> {
+    let repo = DistributedRepository::new(R::open(&config.repository_directory).await?).await?;
+    match repo.get_finalized_blocks(Some(2)).await?.as_slice() {
+        [(_, last), (commit, header)] => Ok(CheckpointAttestation {
+            commit: *commit,
+            header: header.clone(),
+            proof: last.prev_block_finalization_proof.clone(),
+        }),
+        _ => Err(anyhow::anyhow!("no block has a finalization proof yet")),
+    }
+}
+
+/// Chooses the checkpoint attested by more than half of the sources,
+/// among the attestations fetched from each of them.
+pub fn select(
+    config: &CheckpointConfig,
+    fetched: Vec<(String, Result<CheckpointAttestation>)>,
+) -> Result<CheckpointAttestation> {
+    let validator_set = config.validator_set();
+    let sources = fetched.len();
+    let mut votes: Vec<(CheckpointAttestation, usize)> = Vec::new();
+    for (url, attestation) in fetched {
+        let attestation = match attestation.and_then(|x| x.verify(&validator_set).map(|_| x)) {
+            Ok(attestation) => attestation,
+            Err(e) => {
+                log::warn!("ignored the checkpoint from {}: {}", url, e);
+                continue;
+            }
+        };
+        match votes.iter_mut().find(|(x, _)| {
+            x.commit == attestation.commit
+                && x.header.to_hash256() == attestation.header.to_hash256()
+        }) {
+            Some((_, count)) => *count += 1,
+            None => votes.push((attestation, 1)),
+        }
+    }
+    votes
+        .into_iter()
+        .find(|(_, count)| count * 2 > sources)
+        .map(|(attestation, _)| attestation)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "no checkpoint is attested by more than half of the {} sources",
+                sources
+            )
+        })
+}
+
+async fn fetch_one(client: &reqwest::Client, url: &str) -> Result<CheckpointAttestation> {
+    Ok(client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?)
+}
+
+/// Fetches the attestations from the sources of the configuration and chooses the checkpoint.
+pub async fn fetch(config: &CheckpointConfig) -> Result<CheckpointAttestation> {
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .https_only(true)
+        .build()?;
+    let fetched = futures::future::join_all(config.urls.iter().map(|url| {
+        let client = &client;
+        async move { (url.clone(), fetch_one(client, url).await) }
+    }))
+    .await;
+    select(config, fetched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attestation(keys: &[(PublicKey, PrivateKey)], signers: usize) -> CheckpointAttestation {
+        let header = simperby_common::test_util::header(10);
+        CheckpointAttestation {
+            commit: CommitHash { hash: [1; 20] },
+            proof: keys[..signers]
+                .iter()
+                .map(|(_, private_key)| TypedSignature::sign(&header, private_key).unwrap())
+                .collect(),
+            header,
+        }
+    }
+
+    #[test]
+    fn majority() {
+        let keys: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|x| generate_keypair(x))
+            .collect();
+        let config = CheckpointConfig {
+            urls: vec!["https://a.example.org/checkpoint".to_owned()],
+            validators: keys
+                .iter()
+                .map(|(public_key, _)| TrustedValidator {
+                    public_key: public_key.clone(),
+                    voting_power: 1,
+                })
+                .collect(),
+        };
+        config.validate().unwrap();
+        CheckpointConfig {
+            urls: vec!["http://a.example.org/checkpoint".to_owned()],
+            ..config.clone()
+        }
+        .validate()
+        .unwrap_err();
+        let valid = attestation(&keys, 3);
+        let forged = CheckpointAttestation {
+            commit: CommitHash { hash: [2; 20] },
+            ..valid.clone()
+        };
+
+        assert_eq!(
+            select(
+                &config,
+                vec![
+                    ("a".to_owned(), Ok(valid.clone())),
+                    ("b".to_owned(), Ok(valid.clone())),
+                    ("c".to_owned(), Ok(forged.clone())),
+                ]
+            )
+            .unwrap(),
+            valid
+        );
+        // Not more than half.
+        select(
+            &config,
+            vec![
+                ("a".to_owned(), Ok(valid.clone())),
+                ("b".to_owned(), Ok(forged)),
+                ("c".to_owned(), Err(anyhow::anyhow!("unreachable"))),
+            ],
+        )
+        .unwrap_err();
+        // Signed by not more than 2/3.
+        select(
+            &config,
+            vec![
+                ("a".to_owned(), Ok(attestation(&keys, 2))),
+                ("b".to_owned(), Ok(attestation(&keys, 2))),
+            ],
+        )
+        .unwrap_err();
+    }
+}
@@ -0,0 +1,173 @@
+//! The estimation of the clock skew of this node against the validators.
+//!
+//! The node pings the validators periodically and compares their clocks, found in the signed
+//! peer records, with its own. The median of the validators' clocks (weighted by the voting
+//! power, counting this node as having no skew) is what the timestamps of the proposals
+//! are checked against (see `simperby_consensus::verify_proposal()`).
+use super::*;
+use simperby_common::verify;
+use simperby_network::peer_discovery::PeerDiscoveryImpl;
+use simperby_network::{ChainId, SharedKnownPeers};
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+/// How often the clocks of the validators are measured.
+pub const MEASUREMENT_INTERVAL: Duration = Duration::from_secs(60);
+/// How long to wait for a validator to answer a ping.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A gauge of the estimated clock skew in milliseconds.
+pub const CLOCK_SKEW_MS: &str = "simperby_node_clock_skew_ms";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockEstimate {
+    /// The median of the validators' clocks minus the clock of this node, in milliseconds.
+    pub skew_ms: i64,
+    /// The number of the validators measured, excluding this node.
+    pub samples: usize,
+}
+
+impl ClockEstimate {
+    /// Estimates from the measured skews of the validators.
+    ///
+    /// Those not in `validator_set` are ignored, and this node counts as having no skew
+    /// if it is a validator.
+    pub fn new(
+        public_key: &PublicKey,
+        validator_set: &[(PublicKey, VotingPower)],
+        skews: &[(PublicKey, i64)],
+    ) -> Self {
+        let mut values = Vec::new();
+        for (validator, power) in validator_set {
+            if validator == public_key {
+                values.push((0, *power));
+            } else if let Some((_, skew)) = skews.iter().find(|(key, _)| key == validator) {
+                values.push((*skew, *power));
+            }
+        }
+        Self {
+            skew_ms: verify::weighted_median(&values).unwrap_or(0),
+            samples: values.len() - usize::from(validator_set.iter().any(|(v, _)| v == public_key)),
+        }
+    }
+
+    /// Returns the median of the validators' clocks at the given time of this node.
+    pub fn median_clock(&self, now: Timestamp) -> Timestamp {
+        (now as i64).saturating_add(self.skew_ms).max(0) as Timestamp
+    }
+}
+
+pub type SharedClock = Arc<RwLock<ClockEstimate>>;
+
+fn get_timestamp() -> Timestamp {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+    since_the_epoch.as_millis() as Timestamp
+}
+
+/// Pings the peers, returning the clock skew of each that answered,
+/// as its time minus the time of this node.
+///
+/// The time of a peer is compared with that of this node at the middle of the round trip.
+pub async fn measure(peers: &[Peer], chain_id: &ChainId) -> Vec<(PublicKey, i64)> {
+    let mut skews = Vec::new();
+    for peer in peers {
+        let start = get_timestamp();
+        match tokio::time::timeout(
+            PING_TIMEOUT,
+            PeerDiscoveryImpl::ping(peer.address, chain_id),
+        )
+        .await
+        {
+            Ok(Ok((record, round_trip))) => {
+                let middle = start as i64 + round_trip.as_millis() as i64 / 2;
+                skews.push((
+                    record.record.public_key,
+                    record.record.last_seen as i64 - middle,
+                ));
+            }
+            Ok(Err(e)) => log::debug!("failed to ping {}: {}", peer.address, e),
+            Err(_) => log::debug!("failed to ping {}: timed out", peer.address),
+        }
+    }
+    skews
+}
+
+/// Measures the clocks of the validators indefinitely, updating the estimate.
+///
+/// It warns if this node's clock is off by more than half of the allowed skew,
+/// since its proposals and votes would be rejected before long.
+pub async fn monitor<R: RawRepository>(
+    config: Config,
+    peers: SharedKnownPeers,
+    clock: SharedClock,
+) -> Result<()> {
+    loop {
+        let repo = DistributedRepository::new(R::open(&config.repository_directory).await?).await?;
+        let validator_set = repo.get_last_finalized_block_header().await?.validator_set;
+        let reserved_state = repo.get_reserved_state().await?;
+        drop(repo);
+        let validators: Vec<_> = peers
+            .read()
+            .await
+            .iter()
+            .filter(|peer| validator_set.iter().any(|(v, _)| v == &peer.public_key))
+            .cloned()
+            .collect();
+        let skews = measure(&validators, &peers::chain_id(&reserved_state)).await;
+        let estimate = ClockEstimate::new(&config.public_key, &validator_set, &skews);
+        metrics::gauge!(CLOCK_SKEW_MS, estimate.skew_ms as f64);
+        let max_clock_skew_ms = reserved_state.parameters.max_clock_skew_ms();
+        if estimate.skew_ms.unsigned_abs() * 2 > max_clock_skew_ms {
+            log::warn!(
+                "the clock is off by {} ms from the median of {} validators (allowed: {} ms); \
+                 synchronize it with NTP",
+                -estimate.skew_ms,
+                estimate.samples,
+                max_clock_skew_ms
+            );
+        }
+        *clock.write().await = estimate;
+        tokio::time::sleep(MEASUREMENT_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate() {
+        let key = |name: &str| generate_keypair(name).0;
+        let validator_set = vec![(key("a"), 1), (key("b"), 1), (key("c"), 1)];
+        let estimate = ClockEstimate::new(&key("a"), &validator_set, &[]);
+        assert_eq!(estimate, ClockEstimate::default());
+
+        let skews = [(key("b"), 3_000), (key("c"), 5_000), (key("d"), -60_000)];
+        let estimate = ClockEstimate::new(&key("a"), &validator_set, &skews);
+        assert_eq!(
+            estimate,
+            ClockEstimate {
+                skew_ms: 3_000,
+                samples: 2
+            }
+        );
+        assert_eq!(estimate.median_clock(10_000), 13_000);
+
+        // Not a validator.
+        let estimate = ClockEstimate::new(&key("d"), &validator_set, &skews);
+        assert_eq!(estimate.samples, 2);
+        assert_eq!(estimate.skew_ms, 5_000);
+        assert_eq!(
+            ClockEstimate {
+                skew_ms: -20_000,
+                samples: 1
+            }
+            .median_clock(10_000),
+            0
+        );
+    }
+}
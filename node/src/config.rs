@@ -0,0 +1,328 @@
+//! The loading of `Config` from a TOML file, overridden by the environment and the CLI flags.
+//!
+//! An override is a dotted key path and a TOML value (e.g., `ports.governance=9201`);
+//! a value that is not valid TOML is taken as a string.
+//! In the environment, the key path follows `SIMPERBY_` with `__` as the separator
+//! (e.g., `SIMPERBY_PORTS__GOVERNANCE=9201`). The CLI flags override the environment.
+use super::*;
+use std::collections::HashSet;
+
+pub const ENV_PREFIX: &str = "SIMPERBY_";
+
+/// Parses an override of the form `key.path=value`.
+pub fn parse_override(text: &str) -> Result<(String, toml::Value)> {
+    let (key, value) = text
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("invalid override `{}`: expected `key=value`", text))?;
+    let key = key.trim();
+    if key.is_empty() || key.split('.').any(|x| x.is_empty()) {
+        return Err(anyhow::anyhow!("invalid key `{}` in `{}`", key, text));
+    }
+    Ok((key.to_owned(), parse_value(value.trim())))
+}
+
+fn parse_value(text: &str) -> toml::Value {
+    toml::from_str::<toml::value::Table>(&format!("x = {}", text))
+        .ok()
+        .and_then(|mut table| table.remove("x"))
+        .unwrap_or_else(|| toml::Value::String(text.to_owned()))
+}
+
+/// Extracts the overrides from the environment variables.
+pub fn env_overrides(
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Vec<(String, toml::Value)> {
+    let mut overrides: Vec<_> = vars
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let key = name.strip_prefix(ENV_PREFIX)?;
+            Some((key.to_lowercase().replace("__", "."), parse_value(&value)))
+        })
+        .collect();
+    // Sorted for the determinism, since the environment is unordered.
+    overrides.sort_by(|(a, _), (b, _)| a.cmp(b));
+    overrides
+}
+
+fn apply(root: &mut toml::Value, key: &str, value: toml::Value) -> Result<()> {
+    let mut current = root;
+    let mut path = Vec::new();
+    let segments: Vec<_> = key.split('.').collect();
+    for (i, segment) in segments.iter().enumerate() {
+        path.push(*segment);
+        let table = current
+            .as_table_mut()
+            .ok_or_else(|| anyhow::anyhow!("`{}` is not a table", path[..i].join(".")))?;
+        if i + 1 == segments.len() {
+            table.insert(segment.to_string(), value);
+            return Ok(());
+        }
+        current = table
+            .entry(segment.to_string())
+            .or_insert_with(|| toml::Value::Table(Default::default()));
+    }
+    unreachable!("the key is never empty")
+}
+
+/// Layers the configuration file, the environment overrides, and the CLI overrides, in order.
+///
+/// It doesn't validate the result; see `validate()`.
+pub fn layer(
+    content: &str,
+    env: impl IntoIterator<Item = (String, String)>,
+    overrides: &[String],
+) -> Result<Config> {
+    let mut root: toml::Value = toml::from_str(content)?;
+    for (key, value) in env_overrides(env) {
+        apply(&mut root, &key, value)
+            .map_err(|e| anyhow::anyhow!("invalid environment override of `{}`: {}", key, e))?;
+    }
+    for text in overrides {
+        let (key, value) = parse_override(text)?;
+        apply(&mut root, &key, value)
+            .map_err(|e| anyhow::anyhow!("invalid override `{}`: {}", text, e))?;
+    }
+    Ok(root.try_into()?)
+}
+
+/// Loads the configuration from the file, overridden by the environment and the given overrides,
+/// and validates it.
+pub async fn load(path: &str, overrides: &[String]) -> Result<Config> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| anyhow::anyhow!("failed to read the configuration {}: {}", path, e))?;
+    let config = layer(&content, std::env::vars(), overrides)
+        .map_err(|e| anyhow::anyhow!("invalid configuration {}: {}", path, e))?;
+    let problems = validate(&config);
+    if !problems.is_empty() {
+        return Err(anyhow::anyhow!(
+            "invalid configuration {}:\n- {}",
+            path,
+            problems.join("\n- ")
+        ));
+    }
+    Ok(config)
+}
+
+/// The storage directories of the configuration, by the key.
+pub(crate) fn directories(config: &Config) -> [(&'static str, &String); 5] {
+    [
+        ("peer_directory", &config.peer_directory),
+        ("governance_directory", &config.governance_directory),
+        ("chat_directory", &config.chat_directory),
+        ("consensus_directory", &config.consensus_directory),
+        ("repository_directory", &config.repository_directory),
+    ]
+}
+
+/// The ports that the node listens on, by the key.
+pub(crate) fn ports(config: &Config) -> Vec<(&'static str, u16)> {
+    [
+        ("ports.peer_discovery", Some(config.ports.peer_discovery)),
+        ("ports.governance", Some(config.ports.governance)),
+        ("ports.consensus", Some(config.ports.consensus)),
+        ("ports.chat", Some(config.ports.chat)),
+        ("api.port", config.api.port),
+        ("api.event_port", config.api.event_port),
+        ("api.grpc_port", config.api.grpc_port),
+        (
+            "metrics.port",
+            Some(config.metrics.port).filter(|_| config.metrics.enabled),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, port)| port.map(|port| (name, port)))
+    .collect()
+}
+
+/// Returns the problems of the configuration, which is valid if there is none.
+pub fn validate(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+    if config.chain_name.is_empty() {
+        problems.push("`chain_name` is empty".to_owned());
+    }
+    if config.keystore_path.is_empty() {
+        problems.push("`keystore_path` is empty".to_owned());
+    } else if !std::path::Path::new(&config.keystore_path).exists() {
+        problems.push(format!(
+            "the keystore `{}` doesn't exist (see `simperby keystore create`)",
+            config.keystore_path
+        ));
+    }
+
+    let mut seen = HashSet::new();
+    for (name, directory) in directories(config) {
+        if directory.is_empty() {
+            problems.push(format!("`{}` is empty", name));
+        } else if !seen.insert(directory) {
+            problems.push(format!("`{}` is shared with another directory", name));
+        }
+    }
+
+    let mut seen = HashSet::new();
+    for (name, port) in ports(config) {
+        if port == 0 {
+            problems.push(format!("`{}` is zero", name));
+        } else if !seen.insert(port) {
+            problems.push(format!("`{}` ({}) is used twice", name, port));
+        }
+    }
+
+    for peer in &config.bootstrap_peers {
+        let valid = peer.rsplit_once(':').map_or(false, |(host, port)| {
+            !host.is_empty() && port.parse::<u16>().is_ok()
+        });
+        if !valid {
+            problems.push(format!(
+                "the bootstrap peer `{}` is not of the form `host:port`",
+                peer
+            ));
+        }
+    }
+
+    for (name, interval) in [
+        ("broadcast_interval_ms", config.broadcast_interval_ms),
+        ("fetch_interval_ms", config.fetch_interval_ms),
+    ] {
+        if interval == Some(0) {
+            problems.push(format!("`{}` is zero; omit it to disable", name));
+        }
+    }
+    if let Err(e) = config.sentry.validate() {
+        problems.push(format!("`sentry`: {}", e));
+    }
+    if let Err(e) = tracing_subscriber::EnvFilter::try_new(config.logging.directives()) {
+        problems.push(format!("`logging`: invalid filter: {}", e));
+    }
+    if config.logging.file.as_ref().map_or(false, |file| {
+        file.max_bytes == Some(0) || file.prefix.is_empty()
+    }) {
+        problems.push("`logging.file`: `max_bytes` is zero or `prefix` is empty".to_owned());
+    }
+    for notifier in &config.notifiers {
+        if let Err(e) = notifier.validate() {
+            problems.push(format!("`notifiers`: {}", e));
+        }
+    }
+    for (i, mirror) in config.mirrors.iter().enumerate() {
+        if let Err(e) = mirror.validate() {
+            problems.push(format!("`mirrors`: {}", e));
+        }
+        if config.mirrors[..i]
+            .iter()
+            .any(|other| other.name == mirror.name)
+        {
+            problems.push(format!("`mirrors`: duplicate name `{}`", mirror.name));
+        }
+    }
+    if let Err(e) = config.storage.validate() {
+        problems.push(format!("`storage`: {}", e));
+    }
+    if let Some(checkpoint) = &config.checkpoint {
+        if let Err(e) = checkpoint.validate() {
+            problems.push(format!("`checkpoint`: {}", e));
+        }
+    }
+    if let Some(settlement) = &config.settlement {
+        if let Err(e) = settlement.validate() {
+            problems.push(format!("`settlement`: {}", e));
+        }
+        if cfg!(not(feature = "settlement")) {
+            problems
+                .push("`settlement` needs the node built with the `settlement` feature".to_owned());
+        }
+    }
+    if config.sentry.is_hidden() && config.nat.relay.is_some() {
+        problems.push("a validator behind sentries can't use `nat.relay`".to_owned());
+    }
+    if config.api.grpc_port.is_some() && cfg!(not(feature = "grpc")) {
+        problems.push("`api.grpc_port` needs the node built with the `grpc` feature".to_owned());
+    }
+    if (config.api.port.is_some() || config.api.grpc_port.is_some()) && config.api.token.is_none() {
+        log::warn!("`api.token` is not set, so the mutating methods of the API are disabled");
+    }
+    if config.restart_policy.initial_backoff > config.restart_policy.max_backoff {
+        problems.push(
+            "`restart_policy.initial_backoff` is longer than `restart_policy.max_backoff`"
+                .to_owned(),
+        );
+    }
+    problems
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn config() -> Config {
+        Config {
+            public_key: generate_keypair("node").0,
+            keystore_path: "keystore.json".to_owned(),
+            remote_signer: None,
+            chain_name: "test".to_owned(),
+            peer_directory: "peer".to_owned(),
+            governance_directory: "governance".to_owned(),
+            chat_directory: "chat".to_owned(),
+            consensus_directory: "consensus".to_owned(),
+            repository_directory: "repository".to_owned(),
+            vote_archive_directory: None,
+            recovery_sources: Vec::new(),
+            broadcast_interval_ms: Some(1000),
+            fetch_interval_ms: None,
+            bootstrap_peers: vec!["seed.example.org:9100".to_owned()],
+            nat: Default::default(),
+            peer_score: Default::default(),
+            sentry: Default::default(),
+            gossip: None,
+            dev_mode: false,
+            bandwidth_quota: Default::default(),
+            pipeline: Default::default(),
+            ports: Default::default(),
+            restart_policy: Default::default(),
+            api: Default::default(),
+            metrics: Default::default(),
+            logging: Default::default(),
+            settlement: None,
+            notifiers: Vec::new(),
+            mirrors: Vec::new(),
+            checkpoint: None,
+            storage: Default::default(),
+        }
+    }
+
+    #[test]
+    fn layering() {
+        let content = toml::to_string(&toml::Value::try_from(config()).unwrap()).unwrap();
+        let env = vec![
+            ("SIMPERBY_PORTS__GOVERNANCE".to_owned(), "9201".to_owned()),
+            ("SIMPERBY_PORTS__CHAT".to_owned(), "9203".to_owned()),
+            ("SIMPERBY_CHAIN_NAME".to_owned(), "from-env".to_owned()),
+            ("HOME".to_owned(), "/root".to_owned()),
+        ];
+        let overrides = vec!["ports.chat=9303".to_owned(), "dev_mode=true".to_owned()];
+        let layered = layer(&content, env, &overrides).unwrap();
+        assert_eq!(layered.ports.governance, 9201);
+        assert_eq!(layered.ports.chat, 9303);
+        assert_eq!(layered.chain_name, "from-env");
+        assert!(layered.dev_mode);
+        assert_eq!(layered.broadcast_interval_ms, Some(1000));
+
+        assert!(layer(&content, vec![], &["ports.chat=\"x\"".to_owned()]).is_err());
+        assert!(layer(&content, vec![], &["chain_name.x=1".to_owned()]).is_err());
+        assert!(parse_override("no-value").is_err());
+    }
+
+    #[test]
+    fn validation() {
+        let mut config = config();
+        config.keystore_path = String::new();
+        config.chat_directory = "governance".to_owned();
+        config.ports.consensus = config.ports.governance;
+        config.bootstrap_peers.push("no-port".to_owned());
+        config.fetch_interval_ms = Some(0);
+        config.metrics.enabled = true;
+        config.metrics.port = config.ports.chat;
+        let problems = validate(&config);
+        assert_eq!(problems.len(), 6, "{:?}", problems);
+    }
+}
@@ -0,0 +1,203 @@
+//! Running the node as a daemon in the foreground, for `systemd` or containers.
+//!
+//! The logs are written to stdout as JSON lines (see `logging`), leaving the collection
+//! to the supervisor, unless `LoggingConfig::file` is configured.
+//! While running, the node keeps its PID in `DaemonOptions::pid_file`
+//! and refreshes its status in `DaemonOptions::health_file` (see `Health`);
+//! both are removed on the shutdown, which is triggered by `SIGTERM` (see `SimperbyApi::run()`).
+use super::*;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct DaemonOptions {
+    pub pid_file: Option<PathBuf>,
+    pub health_file: Option<PathBuf>,
+    /// How often the health file is refreshed.
+    pub health_interval: Duration,
+}
+
+impl Default for DaemonOptions {
+    fn default() -> Self {
+        Self {
+            pid_file: None,
+            health_file: None,
+            health_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DaemonStatus {
+    Starting,
+    Running,
+    Stopping,
+}
+
+/// The content of the health file.
+///
+/// A health check should regard the node unhealthy if `updated_at` is older than
+/// a few times `DaemonOptions::health_interval`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Health {
+    pub pid: u32,
+    pub status: DaemonStatus,
+    /// The time of the last refresh.
+    pub updated_at: Timestamp,
+}
+
+/// The PID file, removed when dropped.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the PID of this process, failing if the file belongs to a running process.
+    ///
+    /// A file left by a process that is gone (e.g., killed) is replaced.
+    pub async fn create(path: &Path) -> Result<Self> {
+        if let Ok(content) = tokio::fs::read_to_string(path).await {
+            if let Ok(pid) = content.trim().parse::<u32>() {
+                if pid != std::process::id() && is_alive(pid) {
+                    return Err(anyhow::anyhow!(
+                        "another node (PID {}) is running with {}",
+                        pid,
+                        path.display()
+                    ));
+                }
+            }
+            log::warn!("replacing the stale PID file {}", path.display());
+        }
+        write_atomically(path, &format!("{}\n", std::process::id())).await?;
+        Ok(Self {
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            log::warn!(
+                "failed to remove the PID file {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_alive(pid: u32) -> bool {
+    Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+#[cfg(not(unix))]
+fn is_alive(_pid: u32) -> bool {
+    // Conservatively assumes it's alive, so that the operator removes the file.
+    true
+}
+
+fn get_timestamp() -> Timestamp {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+    since_the_epoch.as_millis() as Timestamp
+}
+
+/// Writes the file through a temporary one, so that the readers never see a partial content.
+async fn write_atomically(path: &Path, content: &str) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    tokio::fs::write(&temporary, content).await?;
+    tokio::fs::rename(&temporary, path).await?;
+    Ok(())
+}
+
+pub async fn write_health(path: &Path, status: DaemonStatus) -> Result<()> {
+    let health = Health {
+        pid: std::process::id(),
+        status,
+        updated_at: get_timestamp(),
+    };
+    write_atomically(path, &serde_json::to_string(&health)?).await
+}
+
+/// Runs the node until the shutdown, maintaining the PID file and the health file.
+pub async fn serve(node: &(dyn SimperbyApi + Sync), options: DaemonOptions) -> Result<()> {
+    serve_chains(&[("", node)], options).await
+}
+
+/// Runs the nodes of several chains (see `chains`) together under one PID file
+/// and one health file, until all of them stop.
+///
+/// A chain that stops with an error doesn't stop the others; the first error is returned.
+pub async fn serve_chains(
+    nodes: &[(&str, &(dyn SimperbyApi + Sync))],
+    options: DaemonOptions,
+) -> Result<()> {
+    let pid_file = match &options.pid_file {
+        Some(path) => Some(PidFile::create(path).await?),
+        None => None,
+    };
+    let heartbeat = options.health_file.clone().map(|path| {
+        let interval = options.health_interval;
+        tokio::spawn(async move {
+            if let Err(e) = write_health(&path, DaemonStatus::Starting).await {
+                log::warn!("failed to write the health file {}: {}", path.display(), e);
+            }
+            loop {
+                tokio::time::sleep(interval).await;
+                if let Err(e) = write_health(&path, DaemonStatus::Running).await {
+                    log::warn!("failed to write the health file {}: {}", path.display(), e);
+                }
+            }
+        })
+    });
+    log::info!("started the node (PID {})", std::process::id());
+
+    let results = futures::future::join_all(nodes.iter().map(|(name, node)| async move {
+        let result = node.run().await;
+        match (&result, name.is_empty()) {
+            (Ok(()), true) => log::info!("shutting down the node"),
+            (Err(e), true) => log::error!("the node stopped: {}", e),
+            (Ok(()), false) => log::info!("shutting down the chain `{}`", name),
+            (Err(e), false) => log::error!("the chain `{}` stopped: {}", name, e),
+        }
+        result
+    }))
+    .await;
+    let result = results.into_iter().collect::<Result<Vec<_>>>().map(|_| ());
+
+    if let Some(heartbeat) = heartbeat {
+        heartbeat.abort();
+    }
+    if let Some(path) = &options.health_file {
+        if let Err(e) = write_health(path, DaemonStatus::Stopping).await {
+            log::warn!("failed to write the health file {}: {}", path.display(), e);
+        }
+        if let Err(e) = tokio::fs::remove_file(path).await {
+            log::warn!("failed to remove the health file {}: {}", path.display(), e);
+        }
+    }
+    drop(pid_file);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn pid_file() {
+        let path = std::env::temp_dir().join(format!("simperby-{}.pid", std::process::id()));
+        // A stale file of a process that is gone is replaced.
+        tokio::fs::write(&path, "4194305\n").await.unwrap();
+        let pid_file = PidFile::create(&path).await.unwrap();
+        assert_eq!(
+            tokio::fs::read_to_string(&path).await.unwrap().trim(),
+            std::process::id().to_string()
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,382 @@
+//! The diagnostics of a node for the operators, each with a suggestion to fix the problem found.
+use super::*;
+use simperby_network::peer_discovery::{PeerDiscoveryImpl, LIVENESS_TIMEOUT};
+use simperby_network::PeerDiscovery;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+use std::time::Duration;
+
+/// How long to wait for a peer to answer a ping.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+/// How far the clocks of this node and the others may differ.
+const CLOCK_SKEW_TOLERANCE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Status {
+    Ok,
+    Warning,
+    Failure,
+    /// The check couldn't be performed because an earlier one failed.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Finding {
+    /// One of `config`, `keystore`, `repository`, `branches`, `peers` and `clock`.
+    pub check: String,
+    pub status: Status,
+    pub detail: String,
+    /// What the operator can do about it.
+    pub remedy: Option<String>,
+}
+
+impl Finding {
+    fn new(check: &str, status: Status, detail: String, remedy: Option<&str>) -> Self {
+        Self {
+            check: check.to_owned(),
+            status,
+            detail,
+            remedy: remedy.map(str::to_owned),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnosis {
+    pub findings: Vec<Finding>,
+}
+
+impl Diagnosis {
+    /// Returns whether no check failed; the warnings are tolerated.
+    pub fn is_healthy(&self) -> bool {
+        !self.findings.iter().any(|x| x.status == Status::Failure)
+    }
+}
+
+fn get_timestamp() -> Timestamp {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+    since_the_epoch.as_millis() as Timestamp
+}
+
+/// Runs every check, continuing past the failures as far as possible.
+pub async fn diagnose<R: RawRepository>(config_path: &str, overrides: &[String]) -> Diagnosis {
+    let mut findings = Vec::new();
+    let skip = |findings: &mut Vec<Finding>, checks: &[&str], reason: &str| {
+        for check in checks {
+            findings.push(Finding::new(
+                check,
+                Status::Skipped,
+                reason.to_owned(),
+                None,
+            ));
+        }
+    };
+
+    let config = match config::load(config_path, overrides).await {
+        Ok(config) => {
+            findings.push(Finding::new(
+                "config",
+                Status::Ok,
+                format!("{} is valid", config_path),
+                None,
+            ));
+            config
+        }
+        Err(e) => {
+            findings.push(Finding::new(
+                "config",
+                Status::Failure,
+                e.to_string(),
+                Some("fix the problems listed; `simperby config show` prints the effective configuration"),
+            ));
+            skip(
+                &mut findings,
+                &["keystore", "repository", "branches", "peers", "clock"],
+                "the configuration is not valid",
+            );
+            return Diagnosis { findings };
+        }
+    };
+    findings.push(check_keystore(&config).await);
+
+    let repo = match R::open(&config.repository_directory).await {
+        Ok(raw) => DistributedRepository::new(raw).await,
+        Err(e) => Err(e.into()),
+    };
+    let repo = match repo {
+        Ok(repo) => repo,
+        Err(e) => {
+            findings.push(Finding::new(
+                "repository",
+                Status::Failure,
+                format!("failed to open {}: {}", config.repository_directory, e),
+                Some("check `repository_directory`, or join the chain with `simperby clone`"),
+            ));
+            skip(
+                &mut findings,
+                &["branches", "peers", "clock"],
+                "the repository can't be opened",
+            );
+            return Diagnosis { findings };
+        }
+    };
+    findings.push(match repo.check_integrity().await {
+        Ok(report) if report.is_ok() => Finding::new(
+            "repository",
+            Status::Ok,
+            "the repository is intact".to_owned(),
+            None,
+        ),
+        Ok(report) => Finding::new(
+            "repository",
+            Status::Failure,
+            format!("the repository is not intact: {:?}", report),
+            Some("run `git fsck` in the repository for the details, and clone the chain again if the objects are corrupt"),
+        ),
+        Err(e) => Finding::new(
+            "repository",
+            Status::Failure,
+            format!("failed to check the repository: {}", e),
+            None,
+        ),
+    });
+    findings.push(match repo.check_branch_conventions().await {
+        Ok(violations) if violations.is_empty() => Finding::new(
+            "branches",
+            Status::Ok,
+            "the branches follow the conventions".to_owned(),
+            None,
+        ),
+        Ok(violations) => Finding::new(
+            "branches",
+            Status::Warning,
+            violations.join("; "),
+            Some("delete the branches not managed by Simperby, and rebase `work` and `p` onto `main`"),
+        ),
+        Err(e) => Finding::new(
+            "branches",
+            Status::Failure,
+            format!("failed to read the branches: {}", e),
+            None,
+        ),
+    });
+
+    let state = match (
+        repo.get_last_finalized_block_header().await,
+        repo.get_reserved_state().await,
+    ) {
+        (Ok(header), Ok(reserved_state)) => (header, reserved_state),
+        (Err(e), _) | (_, Err(e)) => {
+            skip(
+                &mut findings,
+                &["peers", "clock"],
+                &format!("the last finalized state can't be read: {}", e),
+            );
+            return Diagnosis { findings };
+        }
+    };
+    drop(repo);
+    let (header, reserved_state) = state;
+    let (peers_finding, skews) = check_peers(&config, &reserved_state).await;
+    findings.push(peers_finding);
+    findings.push(check_clock(&header, &skews));
+    Diagnosis { findings }
+}
+
+async fn check_keystore(config: &Config) -> Finding {
+    match keystore::Keystore::load(&config.keystore_path).await {
+        Ok(keystore) if keystore.public_key == config.public_key => Finding::new(
+            "keystore",
+            Status::Ok,
+            format!(
+                "{} holds the key of this node (the passphrase is not checked)",
+                config.keystore_path
+            ),
+            None,
+        ),
+        Ok(keystore) => Finding::new(
+            "keystore",
+            Status::Failure,
+            format!(
+                "{} holds the key {}, not `public_key` {}",
+                config.keystore_path,
+                hex::encode(&keystore.public_key),
+                hex::encode(&config.public_key)
+            ),
+            Some("point `keystore_path` to the keystore of this node, or fix `public_key`"),
+        ),
+        Err(e) => Finding::new(
+            "keystore",
+            Status::Failure,
+            format!("failed to read {}: {}", config.keystore_path, e),
+            Some("create the keystore with `simperby keystore create`"),
+        ),
+    }
+}
+
+/// Pings every known peer, returning the finding with the clock skew (in milliseconds)
+/// of each peer that answered, as its time minus the time of this node.
+async fn check_peers(config: &Config, reserved_state: &ReservedState) -> (Finding, Vec<i64>) {
+    let known_peers = match PeerDiscoveryImpl::read_known_peers(&config.peer_directory).await {
+        Ok(known_peers) => known_peers,
+        Err(e) => {
+            let finding = Finding::new(
+                "peers",
+                Status::Failure,
+                format!("failed to read the known peers: {}", e),
+                Some("check `peer_directory`, or join the chain with `simperby clone`"),
+            );
+            return (finding, Vec::new());
+        }
+    };
+    let known_peers: Vec<_> = known_peers
+        .into_iter()
+        .filter(|peer| peer.public_key != config.public_key)
+        .collect();
+    if known_peers.is_empty() {
+        let finding = Finding::new(
+            "peers",
+            Status::Warning,
+            "no peer is known".to_owned(),
+            Some("add a peer with `simperby peer add`"),
+        );
+        return (finding, Vec::new());
+    }
+
+    let chain_id = peers::chain_id(reserved_state);
+    let mut unreachable = Vec::new();
+    let mut skews = Vec::new();
+    for peer in &known_peers {
+        match tokio::time::timeout(
+            PING_TIMEOUT,
+            PeerDiscoveryImpl::ping(peer.address, &chain_id),
+        )
+        .await
+        {
+            Ok(Ok((record, _))) => {
+                skews.push(record.record.last_seen as i64 - get_timestamp() as i64)
+            }
+            Ok(Err(e)) => unreachable.push(format!("{} ({})", peer.address, e)),
+            Err(_) => unreachable.push(format!("{} (timed out)", peer.address)),
+        }
+    }
+    let remedy = format!(
+        "check that the peers are running, and that the firewalls allow the peer discovery port ({})",
+        config.ports.peer_discovery
+    );
+    let remedy = Some(remedy.as_str());
+    let finding = if unreachable.is_empty() {
+        Finding::new(
+            "peers",
+            Status::Ok,
+            format!("all {} peers are reachable", known_peers.len()),
+            None,
+        )
+    } else if unreachable.len() == known_peers.len() {
+        Finding::new(
+            "peers",
+            Status::Failure,
+            format!("no peer is reachable: {}", unreachable.join(", ")),
+            remedy,
+        )
+    } else {
+        Finding::new(
+            "peers",
+            Status::Warning,
+            format!(
+                "{} of {} peers are unreachable: {}",
+                unreachable.len(),
+                known_peers.len(),
+                unreachable.join(", ")
+            ),
+            remedy,
+        )
+    };
+    (finding, skews)
+}
+
+/// Checks the clock against the last finalized block and the records of the peers.
+///
+/// A peer refreshes its record at most `LIVENESS_TIMEOUT` before, so its time may lag by that much.
+fn check_clock(last_header: &BlockHeader, skews: &[i64]) -> Finding {
+    let tolerance = CLOCK_SKEW_TOLERANCE.as_millis() as i64;
+    let remedy = Some("synchronize the clock with NTP (e.g., enable `systemd-timesyncd`)");
+    let now = get_timestamp();
+    if last_header.timestamp > now + tolerance as Timestamp {
+        return Finding::new(
+            "clock",
+            Status::Failure,
+            format!(
+                "the clock is behind the last finalized block by {} ms",
+                last_header.timestamp - now
+            ),
+            remedy,
+        );
+    }
+    let lag = LIVENESS_TIMEOUT.as_millis() as i64;
+    let ahead = skews.iter().filter(|skew| **skew > tolerance).count();
+    let behind = skews
+        .iter()
+        .filter(|skew| **skew < -(lag + tolerance))
+        .count();
+    if ahead * 2 > skews.len() {
+        Finding::new(
+            "clock",
+            Status::Warning,
+            format!("the clock is behind {} of {} peers", ahead, skews.len()),
+            remedy,
+        )
+    } else if behind * 2 > skews.len() {
+        Finding::new(
+            "clock",
+            Status::Warning,
+            format!("the clock is ahead of {} of {} peers", behind, skews.len()),
+            remedy,
+        )
+    } else {
+        Finding::new(
+            "clock",
+            Status::Ok,
+            format!(
+                "the clock agrees with {} peers",
+                skews.len() - ahead - behind
+            ),
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(timestamp: Timestamp) -> BlockHeader {
+        BlockHeader {
+            timestamp,
+            ..simperby_common::test_util::header(1)
+        }
+    }
+
+    #[test]
+    fn clock() {
+        let now = get_timestamp();
+        assert_eq!(check_clock(&header(now), &[]).status, Status::Ok);
+        assert_eq!(
+            check_clock(&header(now + 60_000), &[]).status,
+            Status::Failure
+        );
+        assert_eq!(
+            check_clock(&header(now), &[-10_000, 1_000, -20_000]).status,
+            Status::Ok
+        );
+        assert_eq!(
+            check_clock(&header(now), &[60_000, 60_000, 0]).status,
+            Status::Warning
+        );
+        assert_eq!(
+            check_clock(&header(now), &[-120_000, -120_000]).status,
+            Status::Warning
+        );
+    }
+}
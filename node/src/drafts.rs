@@ -0,0 +1,42 @@
+//! The drafts of the agendas of the CLI (see `simperby_repository::drafts`),
+//! which stay local until published.
+use super::*;
+use simperby_network::signer::Signer;
+use simperby_repository::drafts::Draft;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+
+async fn open<R: RawRepository>(config: &Config) -> Result<DistributedRepository<R>> {
+    DistributedRepository::new(R::open(&config.repository_directory).await?).await
+}
+
+/// Parks the transactions on the `work` branch as a new draft.
+pub async fn create<R: RawRepository>(config: &Config, name: &str) -> Result<Draft> {
+    open::<R>(config).await?.create_draft(name).await
+}
+
+/// Brings the draft back onto the `work` branch, to be edited with the transaction authoring.
+pub async fn edit<R: RawRepository>(config: &Config, name: &str) -> Result<CommitHash> {
+    open::<R>(config).await?.load_draft(name).await
+}
+
+pub async fn list<R: RawRepository>(config: &Config) -> Result<Vec<Draft>> {
+    open::<R>(config).await?.list_drafts().await
+}
+
+/// Publishes the draft as an agenda authored by the signer, amending the agenda of
+/// the reference (see `DistributedRepository::resolve()`) if given.
+pub async fn publish<R: RawRepository>(
+    config: &Config,
+    signer: &dyn Signer,
+    name: &str,
+    supersedes: Option<&str>,
+) -> Result<CommitHash> {
+    let mut repo = open::<R>(config).await?;
+    let superseded_commit = match supersedes {
+        Some(reference) => Some(repo.resolve(reference).await?),
+        None => None,
+    };
+    repo.publish_draft(name, signer, superseded_commit.as_ref())
+        .await
+}
@@ -0,0 +1,368 @@
+//! The events of the node, streamed to the subscribers over WebSocket.
+//!
+//! The events are found by observing the repository, the governance DMS, and the known peers
+//! periodically (see `Observer`), and published on the `EventBus` of the node.
+//! A subscriber connects to `ApiConfig::event_port` and receives each event as a JSON text frame.
+//!
+//! The subsystems of the node subscribe to the bus too; for example, one that reads
+//! the chain parameters reloads them on `NodeEvent::ParametersChanged`.
+use super::*;
+use futures::{SinkExt, StreamExt};
+use simperby_common::reserved::{ChainParameters, ParameterChange};
+use simperby_governance::Vote;
+use simperby_network::dms::MessageReader;
+use simperby_network::primitives::MessageStore;
+use simperby_network::SharedKnownPeers;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+/// How often the sources of the events are observed.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// The number of the events buffered for a slow subscriber before it lags.
+pub const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeEvent {
+    BlockFinalized {
+        height: BlockHeight,
+        hash: Hash256,
+    },
+    AgendaCreated {
+        commit: CommitHash,
+        hash: Hash256,
+    },
+    VoteReceived {
+        agenda_hash: Hash256,
+        voter: PublicKey,
+        veto: bool,
+    },
+    PeerConnected {
+        public_key: PublicKey,
+    },
+    PeerDisconnected {
+        public_key: PublicKey,
+    },
+    MemberAdded {
+        name: MemberName,
+        public_key: PublicKey,
+    },
+    /// The chain parameters changed by the block of `height`, where they take effect.
+    ParametersChanged {
+        height: BlockHeight,
+        changes: Vec<ParameterChange>,
+    },
+    /// The subscriber was too slow and missed this many events.
+    Lagged {
+        skipped: u64,
+    },
+}
+
+/// The channel of the events, shared by the publishers and the subscribers.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<NodeEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, events: impl IntoIterator<Item = NodeEvent>) {
+        for event in events {
+            // It fails only if there is no subscriber, which is fine.
+            let _ = self.sender.send(event);
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.sender.subscribe()
+    }
+}
+
+/// The last observed state of the sources, for finding what's new.
+#[derive(Debug, Default)]
+pub struct Observer {
+    height: Option<BlockHeight>,
+    agendas: BTreeSet<CommitHash>,
+    votes: BTreeSet<(Hash256, PublicKey, bool)>,
+    peers: BTreeSet<PublicKey>,
+    parameters: Option<ChainParameters>,
+    members: Option<BTreeSet<MemberName>>,
+}
+
+impl Observer {
+    pub fn observe_block(&mut self, header: &BlockHeader) -> Vec<NodeEvent> {
+        if self.height.map_or(false, |height| height >= header.height) {
+            return Vec::new();
+        }
+        self.height = Some(header.height);
+        vec![NodeEvent::BlockFinalized {
+            height: header.height,
+            hash: header.to_hash256(),
+        }]
+    }
+
+    /// Observes the chain parameters as of the last finalized block of `height`.
+    ///
+    /// The first observation is the baseline, which is not a change.
+    pub fn observe_parameters(
+        &mut self,
+        height: BlockHeight,
+        parameters: &ChainParameters,
+    ) -> Vec<NodeEvent> {
+        let changes = match &self.parameters {
+            Some(previous) => previous.diff(parameters),
+            None => Vec::new(),
+        };
+        self.parameters = Some(parameters.clone());
+        if changes.is_empty() {
+            return Vec::new();
+        }
+        vec![NodeEvent::ParametersChanged { height, changes }]
+    }
+
+    /// Observes the members as of the last finalized block.
+    ///
+    /// The first observation is the baseline, which adds no member.
+    pub fn observe_members(&mut self, members: &[Member]) -> Vec<NodeEvent> {
+        let events = match &self.members {
+            Some(previous) => members
+                .iter()
+                .filter(|member| !previous.contains(&member.name))
+                .map(|member| NodeEvent::MemberAdded {
+                    name: member.name.clone(),
+                    public_key: member.public_key.clone(),
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        self.members = Some(members.iter().map(|member| member.name.clone()).collect());
+        events
+    }
+
+    pub fn observe_agendas(&mut self, agendas: &[(CommitHash, Hash256)]) -> Vec<NodeEvent> {
+        let events = agendas
+            .iter()
+            .filter(|(commit, _)| !self.agendas.contains(commit))
+            .map(|(commit, hash)| NodeEvent::AgendaCreated {
+                commit: *commit,
+                hash: *hash,
+            })
+            .collect();
+        self.agendas = agendas.iter().map(|(commit, _)| *commit).collect();
+        events
+    }
+
+    pub fn observe_votes(&mut self, votes: &[Vote]) -> Vec<NodeEvent> {
+        let current: BTreeSet<_> = votes
+            .iter()
+            .map(|vote| (vote.agenda_hash, vote.voter.clone(), vote.veto))
+            .collect();
+        let events = current
+            .difference(&self.votes)
+            .map(|(agenda_hash, voter, veto)| NodeEvent::VoteReceived {
+                agenda_hash: *agenda_hash,
+                voter: voter.clone(),
+                veto: *veto,
+            })
+            .collect();
+        self.votes = current;
+        events
+    }
+
+    pub fn observe_peers(&mut self, peers: &[Peer]) -> Vec<NodeEvent> {
+        let current: BTreeSet<_> = peers.iter().map(|peer| peer.public_key.clone()).collect();
+        let mut events: Vec<_> = current
+            .difference(&self.peers)
+            .map(|public_key| NodeEvent::PeerConnected {
+                public_key: public_key.clone(),
+            })
+            .collect();
+        events.extend(self.peers.difference(&current).map(|public_key| {
+            NodeEvent::PeerDisconnected {
+                public_key: public_key.clone(),
+            }
+        }));
+        self.peers = current;
+        events
+    }
+}
+
+/// Observes the repository and the known peers indefinitely, publishing the events.
+pub async fn observe<R: RawRepository>(
+    repository_directory: String,
+    peers: SharedKnownPeers,
+    bus: EventBus,
+) -> Result<()> {
+    let mut observer = Observer::default();
+    loop {
+        let repo = DistributedRepository::new(R::open(&repository_directory).await?).await?;
+        let header = repo.get_last_finalized_block_header().await?;
+        bus.publish(observer.observe_block(&header));
+        let reserved_state = repo.get_reserved_state().await?;
+        bus.publish(observer.observe_parameters(header.height, &reserved_state.parameters));
+        bus.publish(observer.observe_members(&reserved_state.members));
+        bus.publish(observer.observe_agendas(&repo.get_agendas().await?));
+        drop(repo);
+        let known_peers = peers.read().await;
+        telemetry::record_known_peers(known_peers.len());
+        bus.publish(observer.observe_peers(&known_peers));
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Observes the votes in the governance DMS indefinitely, publishing the events.
+pub async fn observe_votes<S: MessageStore>(
+    reader: MessageReader<S>,
+    reserved_state: ReservedState,
+    bus: EventBus,
+) -> Result<()> {
+    let mut observer = Observer::default();
+    loop {
+        let votes: Vec<_> = reader
+            .read_messages()
+            .await?
+            .iter()
+            .filter_map(|message| {
+                simperby_governance::message::decode(message, &reserved_state).ok()
+            })
+            .collect();
+        bus.publish(observer.observe_votes(&votes));
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Serves the WebSocket subscriptions to the events indefinitely.
+pub async fn serve(port: u16, bus: EventBus) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+    loop {
+        let (stream, address) = listener.accept().await?;
+        let receiver = bus.subscribe();
+        tokio::spawn(async move {
+            if let Err(e) = stream_events(stream, receiver).await {
+                log::debug!("the event subscription of {} ended: {}", address, e);
+            }
+        });
+    }
+}
+
+async fn stream_events(
+    stream: tokio::net::TcpStream,
+    mut receiver: broadcast::Receiver<NodeEvent>,
+) -> Result<()> {
+    let mut socket = tokio_tungstenite::accept_async(stream).await?;
+    loop {
+        tokio::select! {
+            event = receiver.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => NodeEvent::Lagged { skipped },
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                };
+                socket.send(WsMessage::Text(serde_json::to_string(&event)?)).await?;
+            }
+            message = socket.next() => match message {
+                None | Some(Ok(WsMessage::Close(_))) => return Ok(()),
+                Some(Err(e)) => return Err(e.into()),
+                // The pings are answered by the library; the others are ignored.
+                Some(Ok(_)) => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_common::test_util::member;
+
+    fn peer(name: &str) -> Peer {
+        Peer {
+            public_key: generate_keypair(name).0,
+            address: "127.0.0.1:9100".parse().unwrap(),
+            ports: Default::default(),
+            message: String::new(),
+            recently_seen_timestamp: 0,
+            relay: None,
+            height: None,
+        }
+    }
+
+    #[test]
+    fn observe_peers() {
+        let mut observer = Observer::default();
+        assert_eq!(observer.observe_peers(&[peer("a"), peer("b")]).len(), 2);
+        assert!(observer.observe_peers(&[peer("a"), peer("b")]).is_empty());
+        let events = observer.observe_peers(&[peer("b"), peer("c")]);
+        assert_eq!(
+            events,
+            vec![
+                NodeEvent::PeerConnected {
+                    public_key: generate_keypair("c").0
+                },
+                NodeEvent::PeerDisconnected {
+                    public_key: generate_keypair("a").0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn observe_parameters() {
+        use simperby_common::reserved::ParameterValue;
+
+        let mut observer = Observer::default();
+        let mut parameters = ChainParameters::default();
+        assert!(observer.observe_parameters(1, &parameters).is_empty());
+        assert!(observer.observe_parameters(2, &parameters).is_empty());
+        parameters.0.insert(
+            ChainParameters::BLOCK_INTERVAL_MS.to_owned(),
+            ParameterValue::Integer(5000),
+        );
+        assert_eq!(
+            observer.observe_parameters(3, &parameters),
+            vec![NodeEvent::ParametersChanged {
+                height: 3,
+                changes: vec![ParameterChange {
+                    key: ChainParameters::BLOCK_INTERVAL_MS.to_owned(),
+                    old: None,
+                    new: Some(ParameterValue::Integer(5000)),
+                }],
+            }]
+        );
+        assert!(observer.observe_parameters(4, &parameters).is_empty());
+    }
+
+    #[test]
+    fn observe_members() {
+        let mut observer = Observer::default();
+        assert!(observer.observe_members(&[member("a")]).is_empty());
+        assert_eq!(
+            observer.observe_members(&[member("a"), member("b")]),
+            vec![NodeEvent::MemberAdded {
+                name: "b".to_owned(),
+                public_key: generate_keypair("b").0,
+            }]
+        );
+        assert!(observer.observe_members(&[member("b")]).is_empty());
+    }
+
+    #[test]
+    fn event_format() {
+        let event = NodeEvent::Lagged { skipped: 3 };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"lagged","skipped":3}"#
+        );
+    }
+}
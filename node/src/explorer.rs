@@ -0,0 +1,397 @@
+//! A static-site generator of the chain explorer, so that a chain can be browsed
+//! without running any server but a static file host.
+//!
+//! The site is rendered from the finalized history only. Each block and reserved state
+//! is also written as JSON next to its page, so that a visitor can verify the signatures.
+use super::*;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::{DistributedRepository, HistoryEntry};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A file of the site, with the path relative to the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub path: String,
+    pub content: String,
+}
+
+struct BlockView<'a> {
+    entry: &'a HistoryEntry,
+    header: BlockHeader,
+    /// The commits of the block, except the block commit itself.
+    commits: Vec<&'a HistoryEntry>,
+    /// The finalization proof, carried by the next block (or the genesis info).
+    proof: Option<FinalizationProof>,
+}
+
+struct AgendaView<'a> {
+    entry: &'a HistoryEntry,
+    agenda: &'a Agenda,
+    height: BlockHeight,
+    approvals: Vec<PublicKey>,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn format_timestamp(timestamp: Timestamp) -> String {
+    let time = std::time::UNIX_EPOCH + std::time::Duration::from_millis(timestamp);
+    chrono::DateTime::<chrono::Utc>::from(time).to_rfc3339()
+}
+
+fn layout(chain_name: &str, title: &str, root: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title} - {chain}</title>\n</head>\n<body>\n\
+         <nav><a href=\"{root}index.html\">Blocks</a> | <a href=\"{root}agendas.html\">Agendas</a> | \
+         <a href=\"{root}members.html\">Members</a> | <a href=\"{root}reserved.html\">Reserved state</a></nav>\n\
+         <h1>{title}</h1>\n{body}\n</body>\n</html>\n",
+        title = escape(title),
+        chain = escape(chain_name),
+        root = root,
+        body = body
+    )
+}
+
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut html = String::from("<table>\n<tr>");
+    for header in headers {
+        html.push_str(&format!("<th>{}</th>", header));
+    }
+    html.push_str("</tr>\n");
+    for row in rows {
+        html.push_str("<tr>");
+        for cell in row {
+            html.push_str(&format!("<td>{}</td>", cell));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>");
+    html
+}
+
+/// Renders the site from the finalized history, which starts from the genesis commit.
+pub fn render(history: &[HistoryEntry]) -> Result<Vec<Page>> {
+    let genesis_state = history
+        .first()
+        .and_then(|entry| entry.reserved_state.as_ref())
+        .ok_or_else(|| anyhow::anyhow!("the history doesn't start from the genesis commit"))?;
+    let chain_name = genesis_state.genesis_info.chain_name.as_str();
+
+    let mut blocks: Vec<BlockView> = Vec::new();
+    let mut agendas: Vec<AgendaView> = Vec::new();
+    let mut reserved_states: Vec<(BlockHeight, &HistoryEntry, &ReservedState)> = Vec::new();
+    let mut pending = Vec::new();
+    for entry in history {
+        let next_height = blocks.last().map_or(0, |block| block.header.height + 1);
+        if let Some(reserved_state) = &entry.reserved_state {
+            reserved_states.push((next_height, entry, reserved_state));
+        }
+        match &entry.commit {
+            None => blocks.push(BlockView {
+                entry,
+                header: genesis_state.genesis_info.header.clone(),
+                commits: Vec::new(),
+                proof: Some(genesis_state.genesis_info.genesis_proof.clone()),
+            }),
+            Some(Commit::Block(header)) => {
+                if let Some(last) = blocks.last_mut() {
+                    last.proof = Some(header.prev_block_finalization_proof.clone());
+                }
+                blocks.push(BlockView {
+                    entry,
+                    header: header.clone(),
+                    commits: std::mem::take(&mut pending),
+                    proof: None,
+                });
+            }
+            Some(Commit::Agenda(agenda)) => {
+                agendas.push(AgendaView {
+                    entry,
+                    agenda,
+                    height: next_height,
+                    approvals: Vec::new(),
+                });
+                pending.push(entry);
+            }
+            Some(Commit::AgendaProof(agenda_proof)) => {
+                if let Some(agenda) = agendas
+                    .iter_mut()
+                    .find(|x| x.agenda.to_hash256() == agenda_proof.agenda_hash)
+                {
+                    agenda.approvals = agenda_proof
+                        .proof
+                        .iter()
+                        .map(|(public_key, _)| public_key.clone())
+                        .collect();
+                }
+                pending.push(entry);
+            }
+            Some(_) => pending.push(entry),
+        }
+    }
+
+    let mut names = HashMap::new();
+    for (_, _, reserved_state) in &reserved_states {
+        for member in &reserved_state.members {
+            names.insert(member.public_key.clone(), member.name.clone());
+        }
+    }
+    let name = |public_key: &PublicKey| {
+        names
+            .get(public_key)
+            .map(|name| escape(name))
+            .unwrap_or_else(|| hex::encode(public_key))
+    };
+    let commit_hex = |entry: &HistoryEntry| hex::encode(entry.commit_hash.hash);
+    let mut pages = Vec::new();
+
+    let rows = blocks
+        .iter()
+        .rev()
+        .map(|block| {
+            vec![
+                format!("<a href=\"blocks/{0}.html\">{0}</a>", block.header.height),
+                format!("<code>{}</code>", block.header.to_hash256()),
+                name(&block.header.author),
+                format_timestamp(block.header.timestamp),
+                block.commits.len().to_string(),
+                match &block.proof {
+                    Some(proof) => format!("{} signatures", proof.len()),
+                    None => "pending".to_owned(),
+                },
+            ]
+        })
+        .collect();
+    pages.push(Page {
+        path: "index.html".to_owned(),
+        content: layout(
+            chain_name,
+            "Blocks",
+            "",
+            &table(
+                &[
+                    "Height",
+                    "Hash",
+                    "Author",
+                    "Time",
+                    "Commits",
+                    "Finalization",
+                ],
+                rows,
+            ),
+        ),
+    });
+
+    for block in &blocks {
+        let mut body = format!(
+            "<p>Commit <code>{}</code> by {} at {}</p>\n<p><a href=\"{}.json\">The header and the finalization proof in JSON</a></p>\n<h2>Commits</h2>\n",
+            commit_hex(block.entry),
+            name(&block.header.author),
+            format_timestamp(block.header.timestamp),
+            block.header.height
+        );
+        body.push_str(&table(
+            &["Commit", "Title"],
+            block
+                .commits
+                .iter()
+                .map(|entry| {
+                    vec![
+                        format!("<code>{}</code>", commit_hex(entry)),
+                        escape(&entry.title),
+                    ]
+                })
+                .collect(),
+        ));
+        body.push_str("\n<h2>Finalization</h2>\n");
+        match &block.proof {
+            Some(proof) => body.push_str(&format!(
+                "<p>Signed by {}</p>",
+                proof
+                    .iter()
+                    .map(|signature| name(signature.signer()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            None => body.push_str("<p>The proof will be carried by the next block.</p>"),
+        }
+        pages.push(Page {
+            path: format!("blocks/{}.html", block.header.height),
+            content: layout(
+                chain_name,
+                &format!("Block {}", block.header.height),
+                "../",
+                &body,
+            ),
+        });
+        pages.push(Page {
+            path: format!("blocks/{}.json", block.header.height),
+            content: serde_json::to_string_pretty(&(&block.header, &block.proof))?,
+        });
+    }
+
+    let rows = agendas
+        .iter()
+        .rev()
+        .map(|x| {
+            vec![
+                x.height.to_string(),
+                format!("<code>{}</code>", commit_hex(x.entry)),
+                name(&x.agenda.author),
+                format_timestamp(x.agenda.timestamp),
+                if x.approvals.is_empty() {
+                    "not approved".to_owned()
+                } else {
+                    x.approvals.iter().map(&name).collect::<Vec<_>>().join(", ")
+                },
+            ]
+        })
+        .collect();
+    pages.push(Page {
+        path: "agendas.html".to_owned(),
+        content: layout(
+            chain_name,
+            "Agendas",
+            "",
+            &table(&["Height", "Commit", "Author", "Time", "Approved by"], rows),
+        ),
+    });
+
+    let (_, _, current_state) = reserved_states
+        .last()
+        .expect("the genesis commit has the reserved state");
+    let rows = current_state
+        .members
+        .iter()
+        .map(|member| {
+            vec![
+                escape(&member.name),
+                format!("<code>{}</code>", hex::encode(&member.public_key)),
+                member.governance_voting_power.to_string(),
+                member.consensus_voting_power.to_string(),
+                member
+                    .governance_delegations
+                    .as_ref()
+                    .map(&name)
+                    .unwrap_or_default(),
+                member
+                    .consensus_delegations
+                    .as_ref()
+                    .map(&name)
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect();
+    pages.push(Page {
+        path: "members.html".to_owned(),
+        content: layout(
+            chain_name,
+            "Members",
+            "",
+            &table(
+                &[
+                    "Name",
+                    "Public key",
+                    "Governance power",
+                    "Consensus power",
+                    "Governance delegatee",
+                    "Consensus delegatee",
+                ],
+                rows,
+            ),
+        ),
+    });
+
+    let rows = reserved_states
+        .iter()
+        .rev()
+        .map(|(height, entry, reserved_state)| {
+            vec![
+                height.to_string(),
+                format!(
+                    "<a href=\"reserved/{0}.json\"><code>{0}</code></a>",
+                    commit_hex(entry)
+                ),
+                escape(&entry.title),
+                reserved_state.members.len().to_string(),
+                escape(&reserved_state.version),
+            ]
+        })
+        .collect();
+    pages.push(Page {
+        path: "reserved.html".to_owned(),
+        content: layout(
+            chain_name,
+            "Reserved state",
+            "",
+            &table(&["Height", "Commit", "Title", "Members", "Version"], rows),
+        ),
+    });
+    for (_, entry, reserved_state) in &reserved_states {
+        pages.push(Page {
+            path: format!("reserved/{}.json", commit_hex(entry)),
+            content: serde_json::to_string_pretty(reserved_state)?,
+        });
+    }
+    Ok(pages)
+}
+
+/// Renders the site of the finalized history into the directory, returning the number of files.
+pub async fn generate<R: RawRepository>(config: &Config, output_directory: &str) -> Result<usize> {
+    let history = DistributedRepository::new(R::open(&config.repository_directory).await?)
+        .await?
+        .read_finalized_history()
+        .await?;
+    let pages = render(&history)?;
+    for page in &pages {
+        let path = Path::new(output_directory).join(&page.path);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, &page.content).await?;
+    }
+    Ok(pages.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_common::test_util::genesis;
+    use simperby_repository::CommitHash;
+
+    #[test]
+    fn genesis_only() {
+        // The chain name is escaped in the pages.
+        let mut reserved_state = genesis(&["alice"]);
+        reserved_state.genesis_info.chain_name = "<dao>".to_owned();
+        let history = vec![HistoryEntry {
+            commit_hash: CommitHash { hash: [1; 20] },
+            title: "genesis: <dao>".to_owned(),
+            commit: None,
+            reserved_state: Some(reserved_state),
+        }];
+        let pages = render(&history).unwrap();
+        let paths: Vec<_> = pages.iter().map(|x| x.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "index.html",
+                "blocks/0.html",
+                "blocks/0.json",
+                "agendas.html",
+                "members.html",
+                "reserved.html",
+                "reserved/0101010101010101010101010101010101010101.json",
+            ]
+        );
+        assert!(pages[0].content.contains("&lt;dao&gt;"));
+        assert!(!pages[0].content.contains("<dao>"));
+        assert!(pages[4].content.contains("alice"));
+        assert!(render(&[]).is_err());
+    }
+}
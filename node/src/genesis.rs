@@ -0,0 +1,173 @@
+//! The genesis ceremony of the founding members (see `simperby_common::genesis`).
+//!
+//! The proposal and the approvals are exchanged as JSON files,
+//! or the approvals are propagated over a DMS of the founding members
+//! (see `encode_approval()` and `decode_approvals()`).
+use super::*;
+use simperby_common::genesis::GenesisProposal;
+use simperby_network::dms::Message;
+use simperby_network::signer::Signer;
+use simperby_network::NetworkConfig;
+use simperby_repository::format::GenesisProvenance;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+
+pub type Approval = TypedSignature<BlockHeader>;
+
+pub async fn read_json<T: serde::de::DeserializeOwned>(path: &str) -> Result<T> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| anyhow::anyhow!("failed to read {}: {}", path, e))?;
+    serde_json::from_str(&content).map_err(|e| anyhow::anyhow!("invalid {}: {}", path, e))
+}
+
+pub async fn write_json<T: Serialize>(path: &str, value: &T) -> Result<()> {
+    tokio::fs::write(path, serde_json::to_string_pretty(value)?)
+        .await
+        .map_err(|e| anyhow::anyhow!("failed to write {}: {}", path, e))
+}
+
+/// Approves the proposal with the signer of the node, which must be a founding member.
+pub async fn approve(proposal: &GenesisProposal, signer: &dyn Signer) -> Result<Approval> {
+    let public_key = signer.public_key();
+    if !proposal.members.iter().any(|m| m.public_key == public_key) {
+        return Err(anyhow::anyhow!("{} is not a founding member", public_key));
+    }
+    let header = proposal.header().map_err(|e| anyhow::anyhow!(e))?;
+    Ok(TypedSignature::new(
+        signer.sign(header.to_hash256()).await?,
+        public_key,
+    ))
+}
+
+/// Keeps the valid approvals of the proposal, one per founding member.
+pub fn filter_approvals(
+    proposal: &GenesisProposal,
+    approvals: &[Approval],
+) -> Result<Vec<Approval>> {
+    let header = proposal.header().map_err(|e| anyhow::anyhow!(e))?;
+    let mut result: Vec<Approval> = Vec::new();
+    for approval in approvals {
+        let is_member = proposal
+            .members
+            .iter()
+            .any(|m| &m.public_key == approval.signer());
+        if !is_member || approval.verify(&header).is_err() {
+            log::warn!("ignored an invalid approval of {}", approval.signer());
+            continue;
+        }
+        if !result.iter().any(|x| x.signer() == approval.signer()) {
+            result.push(approval.clone());
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+/// Encodes the approval as a DMS message.
+pub fn encode_approval(approval: &Approval, network_config: &NetworkConfig) -> Result<Message> {
+    let data = serde_json::to_string(approval)?;
+    Ok(Message::new(
+        data.clone(),
+        TypedSignature::sign(&data, &network_config.private_key)?,
+    )?)
+}
+
+/// Decodes the valid approvals of the proposal among the DMS messages.
+pub fn decode_approvals(proposal: &GenesisProposal, messages: &[Message]) -> Result<Vec<Approval>> {
+    let approvals: Vec<Approval> = messages
+        .iter()
+        .filter_map(|message| serde_json::from_str(message.data()).ok())
+        .collect();
+    filter_approvals(proposal, &approvals)
+}
+
+/// Verifies the approvals and creates the genesis commit in a new repository at the directory.
+///
+/// With `import`, the genesis working tree is the tree of a commit of an existing repository,
+/// which every founding member must import alike (see `DistributedRepository::init_from_existing()`).
+pub async fn finalize<R: RawRepository>(
+    proposal: &GenesisProposal,
+    approvals: &[Approval],
+    repository_directory: &str,
+    import: Option<&GenesisProvenance>,
+) -> Result<CommitHash> {
+    let reserved_state = proposal
+        .finalize(approvals)
+        .map_err(|e| anyhow::anyhow!("failed to finalize the genesis: {}", e))?;
+    let mut repo = DistributedRepository::new(R::init(repository_directory).await?).await?;
+    match import {
+        Some(provenance) => repo.init_from_existing(&reserved_state, provenance).await,
+        None => repo.create_genesis_commit(&reserved_state).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_common::test_util::genesis_proposal;
+    use simperby_network::signer::LocalSigner;
+    use simperby_repository::format::to_genesis_semantic_commit;
+    use simperby_repository::raw::{RawRepositoryImpl, RESERVED_STATE_PATH};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn approvals() {
+        let keys: Vec<_> = ["a", "b"].iter().map(|x| generate_keypair(x)).collect();
+        let proposal = genesis_proposal(&["a", "b"]);
+        let mut approvals = Vec::new();
+        for (_, private_key) in &keys {
+            let signer = LocalSigner::new(private_key.clone());
+            approvals.push(approve(&proposal, &signer).await.unwrap());
+        }
+        let outsider = LocalSigner::new(generate_keypair("c").1);
+        assert!(approve(&proposal, &outsider).await.is_err());
+
+        approvals.push(approvals[0].clone());
+        let filtered = filter_approvals(&proposal, &approvals).unwrap();
+        assert_eq!(filtered.len(), 2);
+        assert!(proposal.finalize(&filtered).is_ok());
+    }
+
+    #[tokio::test]
+    async fn genesis_repository() {
+        let names = ["a", "b"];
+        let proposal = genesis_proposal(&names);
+        let mut approvals = Vec::new();
+        for name in names {
+            let signer = LocalSigner::new(generate_keypair(name).1);
+            approvals.push(approve(&proposal, &signer).await.unwrap());
+        }
+        let directories = [TempDir::new().unwrap(), TempDir::new().unwrap()];
+        let path = |i: usize| directories[i].path().to_str().unwrap().to_owned();
+
+        finalize::<RawRepositoryImpl>(&proposal, &approvals[..1], &path(0), None)
+            .await
+            .unwrap_err();
+        let commit = finalize::<RawRepositoryImpl>(&proposal, &approvals, &path(0), None)
+            .await
+            .unwrap();
+        // Every founding member creates the same genesis commit.
+        assert_eq!(
+            finalize::<RawRepositoryImpl>(&proposal, &approvals, &path(1), None)
+                .await
+                .unwrap(),
+            commit
+        );
+        finalize::<RawRepositoryImpl>(&proposal, &approvals, &path(0), None)
+            .await
+            .unwrap_err();
+
+        let raw = RawRepositoryImpl::open(&path(0)).await.unwrap();
+        assert_eq!(raw.get_initial_commit().await.unwrap(), commit);
+        let mut branches = raw.list_branches().await.unwrap();
+        branches.sort();
+        assert_eq!(branches, vec!["main".to_owned(), "work".to_owned()]);
+        let reserved_state = proposal.finalize(&approvals).unwrap();
+        assert_eq!(
+            raw.read_semantic_commit(&commit).await.unwrap(),
+            to_genesis_semantic_commit(&reserved_state, None)
+        );
+        assert!(directories[0].path().join(RESERVED_STATE_PATH).exists());
+    }
+}
@@ -0,0 +1,338 @@
+//! The gRPC interface of the node, for the integrators preferring typed RPC.
+//!
+//! It serves the same operations as the HTTP API (see `api`), plus the subscription to the events,
+//! by the service generated from `proto/simperby/node/v1/node.proto`.
+//! The mutating methods take the token of `ApiConfig` in the `authorization` metadata.
+use super::*;
+use events::{EventBus, NodeEvent};
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("simperby.node.v1");
+}
+
+use proto::node_server::{Node as NodeService, NodeServer};
+
+fn internal(e: impl std::fmt::Display) -> Status {
+    Status::internal(e.to_string())
+}
+
+fn invalid(name: &str, e: impl std::fmt::Display) -> Status {
+    Status::invalid_argument(format!("invalid `{}`: {}", name, e))
+}
+
+fn commit_hash(name: &str, bytes: &[u8]) -> Result<CommitHash, Status> {
+    Ok(CommitHash {
+        hash: bytes
+            .try_into()
+            .map_err(|_| invalid(name, "a commit hash must be 20 bytes"))?,
+    })
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, Status> {
+    canonical::to_vec(value).map_err(internal)
+}
+
+impl From<&BlockHeader> for proto::BlockHeader {
+    fn from(header: &BlockHeader) -> Self {
+        Self {
+            author: header.author.as_ref().to_vec(),
+            prev_block_finalization_proof: header
+                .prev_block_finalization_proof
+                .iter()
+                .map(|signature| proto::FinalizationSignature {
+                    signer: signature.signer().as_ref().to_vec(),
+                    signature: signature.signature().as_ref().to_vec(),
+                })
+                .collect(),
+            previous_hash: header.previous_hash.hash.to_vec(),
+            height: header.height,
+            timestamp: header.timestamp,
+            commit_hash: header.commit_hash.hash.to_vec(),
+            tx_merkle_root: header.tx_merkle_root.hash.to_vec(),
+            chat_merkle_root: header.chat_merkle_root.hash.to_vec(),
+            repository_merkle_root: header.repository_merkle_root.hash.to_vec(),
+            validator_set: header
+                .validator_set
+                .iter()
+                .map(|(public_key, voting_power)| proto::Validator {
+                    public_key: public_key.as_ref().to_vec(),
+                    voting_power: *voting_power,
+                })
+                .collect(),
+            version: header.version.clone(),
+            hash: header.to_hash256().hash.to_vec(),
+        }
+    }
+}
+
+impl TryFrom<proto::Transaction> for Transaction {
+    type Error = Status;
+
+    fn try_from(transaction: proto::Transaction) -> Result<Self, Status> {
+        Ok(Self {
+            author: PublicKey::from_bytes(&transaction.author).map_err(|e| invalid("author", e))?,
+            timestamp: transaction.timestamp,
+            head: transaction.head,
+            body: transaction.body,
+            diff: serde_json::from_slice(&transaction.diff).map_err(|e| invalid("diff", e))?,
+        })
+    }
+}
+
+fn to_proto_event(event: NodeEvent) -> Result<proto::Event, Status> {
+    use proto::event::{self, Kind};
+    let kind = match event {
+        NodeEvent::BlockFinalized { height, hash } => Kind::BlockFinalized(event::BlockFinalized {
+            height,
+            hash: hash.hash.to_vec(),
+        }),
+        NodeEvent::AgendaCreated { commit, hash } => Kind::AgendaCreated(event::AgendaCreated {
+            commit: commit.hash.to_vec(),
+            hash: hash.hash.to_vec(),
+        }),
+        NodeEvent::VoteReceived {
+            agenda_hash,
+            voter,
+            veto,
+        } => Kind::VoteReceived(event::VoteReceived {
+            agenda_hash: agenda_hash.hash.to_vec(),
+            voter: voter.as_ref().to_vec(),
+            veto,
+        }),
+        NodeEvent::PeerConnected { public_key } => Kind::PeerConnected(event::PeerConnected {
+            public_key: public_key.as_ref().to_vec(),
+        }),
+        NodeEvent::PeerDisconnected { public_key } => {
+            Kind::PeerDisconnected(event::PeerDisconnected {
+                public_key: public_key.as_ref().to_vec(),
+            })
+        }
+        NodeEvent::MemberAdded { name, public_key } => Kind::MemberAdded(event::MemberAdded {
+            name,
+            public_key: public_key.as_ref().to_vec(),
+        }),
+        NodeEvent::ParametersChanged { height, changes } => {
+            Kind::ParametersChanged(event::ParametersChanged {
+                height,
+                changes: encode(&changes)?,
+            })
+        }
+        NodeEvent::Lagged { skipped } => Kind::Lagged(event::Lagged { skipped }),
+    };
+    Ok(proto::Event { kind: Some(kind) })
+}
+
+struct GrpcServer {
+    node: Arc<dyn SimperbyApi + Send + Sync>,
+    config: Config,
+    bus: EventBus,
+}
+
+impl GrpcServer {
+    fn authenticate<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("no `authorization: Bearer <token>`"))?;
+        api::authenticate(&self.config.api, token).map_err(Status::unauthenticated)
+    }
+}
+
+#[tonic::async_trait]
+impl NodeService for GrpcServer {
+    type SubscribeEventsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::Event, Status>> + Send + 'static>>;
+
+    async fn get_status(
+        &self,
+        _request: Request<proto::GetStatusRequest>,
+    ) -> Result<Response<proto::NodeStatus>, Status> {
+        let header = self
+            .node
+            .get_last_finalized_block_header()
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(proto::NodeStatus {
+            protocol_version: PROTOCOL_VERSION.to_owned(),
+            chain_name: self.config.chain_name.clone(),
+            public_key: self.config.public_key.as_ref().to_vec(),
+            last_finalized_height: header.height,
+            storage_warnings: storage::measure(&self.config)
+                .await
+                .map(|usage| self.config.storage.warnings(&usage))
+                .map_err(internal)?,
+        }))
+    }
+
+    async fn get_last_finalized_block(
+        &self,
+        _request: Request<proto::GetLastFinalizedBlockRequest>,
+    ) -> Result<Response<proto::BlockHeader>, Status> {
+        let header = self
+            .node
+            .get_last_finalized_block_header()
+            .await
+            .map_err(internal)?;
+        Ok(Response::new((&header).into()))
+    }
+
+    async fn get_reserved_state(
+        &self,
+        request: Request<proto::GetReservedStateRequest>,
+    ) -> Result<Response<proto::ReservedState>, Status> {
+        use proto::get_reserved_state_request::Point;
+        let reserved_state = match request.into_inner().point {
+            None => self.node.get_reserved_state().await,
+            Some(Point::Height(height)) => {
+                self.node
+                    .get_reserved_state_at(FinalizedPoint::Height(height))
+                    .await
+            }
+            Some(Point::Commit(commit)) => {
+                self.node
+                    .get_reserved_state_at(FinalizedPoint::Commit(commit_hash("commit", &commit)?))
+                    .await
+            }
+        }
+        .map_err(internal)?;
+        Ok(Response::new(proto::ReservedState {
+            canonical: encode(&reserved_state)?,
+        }))
+    }
+
+    async fn get_pending_agendas(
+        &self,
+        _request: Request<proto::GetPendingAgendasRequest>,
+    ) -> Result<Response<proto::PendingAgendas>, Status> {
+        let agendas = self.node.get_agendas().await.map_err(internal)?;
+        Ok(Response::new(proto::PendingAgendas {
+            agendas: agendas
+                .into_iter()
+                .map(|(commit, hash)| proto::PendingAgenda {
+                    commit: commit.hash.to_vec(),
+                    hash: hash.hash.to_vec(),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::SubmitTransactionRequest>,
+    ) -> Result<Response<proto::SubmitTransactionResponse>, Status> {
+        self.authenticate(&request)?;
+        let transaction = request
+            .into_inner()
+            .transaction
+            .ok_or_else(|| invalid("transaction", "missing"))?
+            .try_into()?;
+        let commit = self
+            .node
+            .create_transaction(transaction)
+            .await
+            .map_err(internal)?;
+        Ok(Response::new(proto::SubmitTransactionResponse {
+            commit: commit.hash.to_vec(),
+        }))
+    }
+
+    async fn vote(
+        &self,
+        request: Request<proto::VoteRequest>,
+    ) -> Result<Response<proto::VoteResponse>, Status> {
+        self.authenticate(&request)?;
+        let agenda_commit = commit_hash("agenda_commit", &request.into_inner().agenda_commit)?;
+        self.node.vote(agenda_commit).await.map_err(internal)?;
+        Ok(Response::new(proto::VoteResponse {}))
+    }
+
+    async fn subscribe_events(
+        &self,
+        _request: Request<proto::SubscribeEventsRequest>,
+    ) -> Result<Response<Self::SubscribeEventsStream>, Status> {
+        let stream = futures::stream::unfold(self.bus.subscribe(), |mut receiver| async move {
+            let event = match receiver.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => NodeEvent::Lagged { skipped },
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            Some((to_proto_event(event), receiver))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Serves the gRPC interface of the node indefinitely, if `ApiConfig::grpc_port` is set.
+pub async fn serve(
+    node: Arc<dyn SimperbyApi + Send + Sync>,
+    config: Config,
+    bus: EventBus,
+) -> Option<tokio::task::JoinHandle<()>> {
+    let port = config.api.grpc_port?;
+    let service = NodeServer::new(GrpcServer { node, config, bus });
+    Some(tokio::spawn(async move {
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve(([0, 0, 0, 0], port).into())
+            .await
+        {
+            log::error!("the gRPC server stopped: {}", e);
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events() {
+        let event = to_proto_event(NodeEvent::Lagged { skipped: 3 }).unwrap();
+        assert_eq!(
+            event.kind,
+            Some(proto::event::Kind::Lagged(proto::event::Lagged {
+                skipped: 3
+            }))
+        );
+        let (public_key, _) = generate_keypair("peer");
+        let event = to_proto_event(NodeEvent::PeerConnected {
+            public_key: public_key.clone(),
+        })
+        .unwrap();
+        assert_eq!(
+            event.kind,
+            Some(proto::event::Kind::PeerConnected(
+                proto::event::PeerConnected {
+                    public_key: public_key.as_ref().to_vec()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn transaction() {
+        let (author, _) = generate_keypair("author");
+        let transaction = proto::Transaction {
+            author: author.as_ref().to_vec(),
+            timestamp: 1,
+            head: "head".to_owned(),
+            body: "body".to_owned(),
+            diff: encode(&Diff::None).unwrap(),
+        };
+        let converted = Transaction::try_from(transaction.clone()).unwrap();
+        assert_eq!(converted.author, author);
+        assert_eq!(converted.diff, Diff::None);
+        Transaction::try_from(proto::Transaction {
+            diff: b"{".to_vec(),
+            ..transaction
+        })
+        .unwrap_err();
+    }
+}
@@ -0,0 +1,167 @@
+//! An encrypted on-disk store for the private key of a node.
+//!
+//! The key is encrypted with AES-256-GCM under a key derived from the passphrase by scrypt.
+//! The public key is bound as the associated data, so a keystore can't be tampered
+//! to claim a different public key.
+use super::*;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::anyhow;
+use rand::RngCore;
+use simperby_network::signer::{LocalSigner, RemoteSigner, Signer};
+use std::sync::Arc;
+
+/// The scrypt parameters, which are stored with the keystore so that they can be raised later.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct KdfParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    /// In hex.
+    pub salt: String,
+}
+
+impl Default for KdfParams {
+    /// The recommended parameters for interactive logins.
+    fn default() -> Self {
+        let mut salt = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut salt);
+        Self {
+            log_n: 15,
+            r: 8,
+            p: 1,
+            salt: hex::encode(salt),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Keystore {
+    pub public_key: PublicKey,
+    pub kdf: KdfParams,
+    /// In hex.
+    pub nonce: String,
+    /// In hex.
+    pub ciphertext: String,
+}
+
+fn derive_key(passphrase: &str, kdf: &KdfParams) -> Result<[u8; 32]> {
+    let params = scrypt::Params::new(kdf.log_n, kdf.r, kdf.p)
+        .map_err(|e| anyhow!("invalid scrypt parameters: {}", e))?;
+    let salt = hex::decode(&kdf.salt)?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(passphrase.as_bytes(), &salt, &params, &mut key)
+        .map_err(|e| anyhow!("failed to derive the key: {}", e))?;
+    Ok(key)
+}
+
+impl Keystore {
+    /// Encrypts the private key with the passphrase.
+    pub fn encrypt(private_key: &PrivateKey, passphrase: &str) -> Result<Self> {
+        let public_key = private_key.public_key();
+        let kdf = KdfParams::default();
+        let cipher = Aes256Gcm::new_from_slice(&derive_key(passphrase, &kdf)?)
+            .map_err(|e| anyhow!("invalid key length: {}", e))?;
+        let mut nonce = [0u8; 12];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: private_key.as_ref(),
+                    aad: public_key.as_ref(),
+                },
+            )
+            .map_err(|_| anyhow!("failed to encrypt the private key"))?;
+        Ok(Self {
+            public_key,
+            kdf,
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        })
+    }
+
+    /// Decrypts the private key with the passphrase.
+    pub fn decrypt(&self, passphrase: &str) -> Result<PrivateKey> {
+        let cipher = Aes256Gcm::new_from_slice(&derive_key(passphrase, &self.kdf)?)
+            .map_err(|e| anyhow!("invalid key length: {}", e))?;
+        let nonce = hex::decode(&self.nonce)?;
+        if nonce.len() != 12 {
+            return Err(anyhow!("invalid nonce length: {}", nonce.len()));
+        }
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &hex::decode(&self.ciphertext)?,
+                    aad: self.public_key.as_ref(),
+                },
+            )
+            .map_err(|_| anyhow!("wrong passphrase or corrupted keystore"))?;
+        let private_key = PrivateKey::from_bytes(&plaintext)?;
+        if private_key.public_key() != self.public_key {
+            return Err(anyhow!("the private key doesn't match the public key"));
+        }
+        Ok(private_key)
+    }
+
+    /// Re-encrypts the private key with a new passphrase.
+    pub fn change_passphrase(&self, old_passphrase: &str, new_passphrase: &str) -> Result<Self> {
+        Self::encrypt(&self.decrypt(old_passphrase)?, new_passphrase)
+    }
+
+    pub async fn load(path: &str) -> Result<Self> {
+        Ok(serde_json::from_str(
+            &tokio::fs::read_to_string(path).await?,
+        )?)
+    }
+
+    /// Saves the keystore, replacing the existing file atomically.
+    pub async fn save(&self, path: &str) -> Result<()> {
+        let temp_path = format!("{}.tmp", path);
+        tokio::fs::write(&temp_path, serde_json::to_string_pretty(self)?).await?;
+        tokio::fs::rename(&temp_path, path).await?;
+        Ok(())
+    }
+}
+
+/// Loads the keystore and decrypts it with the passphrase prompted on the terminal,
+/// which is not echoed.
+pub async fn unlock(path: &str) -> Result<PrivateKey> {
+    let keystore = Keystore::load(path).await?;
+    let prompt = format!("Passphrase for {}: ", path);
+    let passphrase =
+        tokio::task::spawn_blocking(move || rpassword::prompt_password(prompt)).await??;
+    keystore.decrypt(&passphrase)
+}
+
+/// Returns the signer of the node, given the private key unlocked from its keystore.
+///
+/// With `Config::remote_signer`, the key is the authentication key for the remote signer.
+pub fn signer(config: &Config, private_key: PrivateKey) -> Arc<dyn Signer> {
+    match &config.remote_signer {
+        Some(endpoint) => Arc::new(RemoteSigner::new(
+            endpoint.clone(),
+            config.public_key.clone(),
+            private_key,
+        )),
+        None => Arc::new(LocalSigner::new(private_key)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let (_, private_key) = generate_keypair("keystore");
+        let keystore = Keystore::encrypt(&private_key, "passphrase").unwrap();
+        assert_eq!(keystore.decrypt("passphrase").unwrap(), private_key);
+        assert!(keystore.decrypt("wrong").is_err());
+
+        let keystore = keystore.change_passphrase("passphrase", "new").unwrap();
+        assert!(keystore.decrypt("passphrase").is_err());
+        assert_eq!(keystore.decrypt("new").unwrap(), private_key);
+    }
+}
@@ -1,31 +1,173 @@
+pub mod api;
+pub mod authoring;
+pub mod bootstrap;
+pub mod chains;
+pub mod checkpoint;
+pub mod clock;
+pub mod config;
+pub mod daemon;
+pub mod doctor;
+pub mod drafts;
+pub mod events;
+pub mod explorer;
+pub mod genesis;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod keystore;
+pub mod logging;
+pub mod membership;
+pub mod mirror;
 pub mod node;
+pub mod notify;
+pub mod peers;
+pub mod query;
+pub mod recovery;
+pub mod review;
+pub mod runtime;
+pub mod settlement;
+pub mod snapshot;
+pub mod storage;
+pub mod telemetry;
+pub mod webhook;
 
+pub use simperby_chat;
+use simperby_chat::{private::PrivateChatMessage, review::SignedReviewComment, Chat};
 pub use simperby_common;
 use simperby_governance::Governance;
 pub use simperby_network;
+pub use simperby_repository;
 
 use anyhow::Result;
+use api::ApiConfig;
 use async_trait::async_trait;
+use checkpoint::CheckpointConfig;
+use logging::LoggingConfig;
+use mirror::MirrorConfig;
+use notify::NotifierConfig;
+use runtime::RestartPolicy;
 use serde::{Deserialize, Serialize};
+use settlement::SettlementConfig;
 use simperby_common::crypto::*;
+use simperby_common::reserved::ReservedState;
 use simperby_common::*;
-use simperby_repository::CommitHash;
+use simperby_network::bandwidth::{BandwidthQuota, BandwidthReport};
+use simperby_network::gossip::GossipConfig;
+use simperby_network::nat::{NatConfig, NatStatus};
+use simperby_network::peer_score::{PeerScore, PeerScoreConfig};
+use simperby_network::pipeline::PipelineConfig;
+use simperby_network::sentry::SentryConfig;
+use simperby_network::signer::SignerEndpoint;
+use simperby_network::Peer;
+use simperby_repository::{raw::BlameLine, CommitHash, FinalizedPoint, IntegrityReport};
+use storage::StorageQuota;
+use telemetry::MetricsConfig;
 
 pub const PROTOCOL_VERSION: &str = "0.0.0";
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub public_key: PublicKey,
-    pub private_key: PrivateKey,
+    /// The path to the keystore holding the encrypted private key (see `keystore`).
+    pub keystore_path: String,
+    /// The remote signer holding the private key of `public_key`
+    /// (see `simperby_network::signer`); if set, the key in the keystore only authenticates
+    /// this node to the remote signer.
+    #[serde(default)]
+    pub remote_signer: Option<SignerEndpoint>,
     pub chain_name: String,
 
     pub peer_directory: String,
     pub governance_directory: String,
+    pub chat_directory: String,
+    /// The directory of the consensus storage, which also holds
+    /// the write-ahead log of the consensus state (see `vetomint::wal`).
     pub consensus_directory: String,
     pub repository_directory: String,
+    /// The directory of the archive of the consensus votes, for the audit
+    /// (see `simperby_consensus::archive`); if none, the votes of the past heights are dropped.
+    #[serde(default)]
+    pub vote_archive_directory: Option<String>,
+    /// The repository URLs of the peers to recover the repository from, automatically on
+    /// startup, if its finalized branch is found rewound or corrupted (see `recovery`).
+    #[serde(default)]
+    pub recovery_sources: Vec<String>,
 
     pub broadcast_interval_ms: Option<u64>,
     pub fetch_interval_ms: Option<u64>,
+    /// The locally configured endpoints (`host:port`) of the peer discovery,
+    /// in addition to `ReservedState::bootstrap_peers`.
+    #[serde(default)]
+    pub bootstrap_peers: Vec<String>,
+    #[serde(default)]
+    pub nat: NatConfig,
+    #[serde(default)]
+    pub peer_score: PeerScoreConfig,
+    /// The sentry/validator split: for a validator, the sentries to talk to exclusively,
+    /// and for a sentry, the validators behind it (see `simperby_network::sentry`).
+    #[serde(default)]
+    pub sentry: SentryConfig,
+    /// The epidemic gossip for the DMS propagation; if none, only the full-mesh broadcast is used.
+    #[serde(default)]
+    pub gossip: Option<GossipConfig>,
+    /// Enables the features only for the development clusters, such as the mDNS discovery.
+    #[serde(default)]
+    pub dev_mode: bool,
+    /// The soft quotas for the metered connections.
+    #[serde(default)]
+    pub bandwidth_quota: BandwidthQuota,
+    /// The bounded lanes of the messages received from the gossip network
+    /// (see `simperby_network::pipeline`).
+    #[serde(default)]
+    pub pipeline: PipelineConfig,
+    /// The ports that `SimperbyApi::run()` serves the protocols on.
+    #[serde(default)]
+    pub ports: Ports,
+    /// The supervision of the tasks of `SimperbyApi::run()`.
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    /// The HTTP API of the node (see `api`).
+    #[serde(default)]
+    pub api: ApiConfig,
+    /// The Prometheus metrics of the node (see `telemetry`).
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    /// The logs of the node (see `logging`).
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// The relay of the finalized blocks to an EVM chain (see `settlement`).
+    #[serde(default)]
+    pub settlement: Option<SettlementConfig>,
+    /// The notifications of the events to the operators (see `notify`).
+    #[serde(default)]
+    pub notifiers: Vec<NotifierConfig>,
+    /// The external git remotes the finalized branch is pushed to (see `mirror`).
+    #[serde(default)]
+    pub mirrors: Vec<MirrorConfig>,
+    /// The sources of the checkpoint to join the chain from (see `checkpoint`).
+    #[serde(default)]
+    pub checkpoint: Option<CheckpointConfig>,
+    /// The disk quotas of the stores (see `storage`).
+    #[serde(default)]
+    pub storage: StorageQuota,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Ports {
+    pub peer_discovery: u16,
+    pub governance: u16,
+    pub consensus: u16,
+    pub chat: u16,
+}
+
+impl Default for Ports {
+    fn default() -> Self {
+        Self {
+            peer_discovery: 9100,
+            governance: 9101,
+            consensus: 9102,
+            chat: 9103,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -35,7 +177,12 @@ pub struct ConsensusStatus {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct NetworkStatus {
-    // TODO
+    /// How this node is reachable, as set up on startup.
+    pub nat: Option<NatStatus>,
+    /// The known peers, with the relays of those not reachable.
+    pub peers: Vec<Peer>,
+    /// The reputation of the peers, including those banned.
+    pub peer_scores: Vec<(PublicKey, PeerScore)>,
 }
 
 /// The API for the Simperby node.
@@ -64,19 +211,47 @@ pub trait SimperbyApi {
     /// Creates a block commit on the `main` branch.
     async fn create_agenda(&self) -> Result<()>;
 
+    /// Creates a transaction commit on the `work` branch.
+    ///
+    /// Only the transactions without a diff or changing the reserved state are supported.
+    async fn create_transaction(&self, tx: Transaction) -> Result<CommitHash>;
+
+    /// Gets the header of the last finalized block.
+    async fn get_last_finalized_block_header(&self) -> Result<BlockHeader>;
+
+    /// Gets the reserved state of the last finalized block.
+    async fn get_reserved_state(&self) -> Result<ReservedState>;
+
+    /// Gets the reserved state as of the given point of the finalized history.
+    async fn get_reserved_state_at(&self, point: FinalizedPoint) -> Result<ReservedState>;
+
+    /// Gets the agendas pending for the approval, with their hashes.
+    async fn get_agendas(&self) -> Result<Vec<(CommitHash, Hash256)>>;
+
     /// Creates an extra-agenda transaction on the `main` branch.
     async fn create_extra_agenda_transaction(&self, tx: ExtraAgendaTransaction) -> Result<()>;
 
-    /// Votes and propagates.
+    /// Votes and propagates, leaving a `vote` tag on the agenda commit.
     async fn vote(&self, agenda_commit: CommitHash) -> Result<()>;
 
     /// Vetos the current round.
     async fn veto_round(&self) -> Result<()>;
 
-    /// Vetos the given block.
+    /// Vetos the given block, leaving a `veto` tag on the block commit.
+    ///
+    /// If the block is already vetoed by this node, it only removes the tag.
     async fn veto_block(&self, block_commit: CommitHash) -> Result<()>;
 
-    /// Runs indefinitely updating everything.
+    /// Gets the agenda with its diff and the current tally of the votes, for a review.
+    async fn review_agenda(&self, agenda_commit: CommitHash) -> Result<review::AgendaReview>;
+
+    /// Gets the block with its diff, for a review.
+    async fn review_block(&self, block_commit: CommitHash) -> Result<review::BlockReview>;
+
+    /// Runs indefinitely updating everything, until `SIGTERM` or Ctrl-C.
+    ///
+    /// It opens and verifies the repository, joins the network, and then starts the consensus,
+    /// each as a supervised task that restarts on failures (see `runtime`).
     async fn run(&self) -> Result<()>;
 
     /// Makes a progress for the consensus, returning the result.
@@ -88,6 +263,19 @@ pub trait SimperbyApi {
     /// Gets the current status of the p2p network.
     async fn get_network_status(&self) -> Result<NetworkStatus>;
 
+    /// Adds a peer by its discovery endpoint (`host:port` or a URL with them),
+    /// to contact in every discovery round until removed.
+    async fn add_peer(&self, address: String, public_key: PublicKey) -> Result<()>;
+
+    /// Removes the peer, returning whether it was known.
+    async fn remove_peer(&self, public_key: PublicKey) -> Result<bool>;
+
+    /// Pings the peer given by its public key (in hex) or by its address.
+    async fn ping_peer(&self, target: String) -> Result<peers::PingResult>;
+
+    /// Gets the bytes transferred per peer and per subsystem, with those over the quotas.
+    async fn get_bandwidth_usage(&self) -> Result<BandwidthReport>;
+
     /// Serves indefinitely relaying network messages.
     async fn relay(&self) -> Result<()>;
 
@@ -97,5 +285,46 @@ pub trait SimperbyApi {
     /// Notifies that there was a git push. This is not intended to be used by the user.
     async fn notify_git_push(&self) -> Result<String>;
 
-    // TODO: Add chat-related methods.
+    /// Shows who last changed each line of the reserved-state file as of the given commit.
+    async fn blame(&self, commit: CommitHash, path: String) -> Result<Vec<BlameLine>>;
+
+    /// Checks the integrity of the repository storage.
+    async fn check_integrity(&self) -> Result<IntegrityReport>;
+
+    /// Gets the disk usage of the stores and the history retained by each branch,
+    /// with the warnings of the quotas.
+    async fn get_storage_usage(&self) -> Result<storage::StorageReport>;
+
+    /// Says the message in the chat, which is recorded on the chain unless off-the-record.
+    async fn chat(&self, message: String, off_the_record: bool) -> Result<()>;
+
+    /// Reads the chat of the current height, including the off-the-record messages.
+    async fn read_chat(&self) -> Result<Vec<ChatMessage>>;
+
+    /// Says the message in the private channel of the given members (including this node),
+    /// encrypted end-to-end and never recorded on the blockchain.
+    async fn chat_private(&self, members: Vec<PublicKey>, message: String) -> Result<()>;
+
+    /// Reads the messages of the private channels that this node is a member of.
+    async fn read_private_chat(&self) -> Result<Vec<PrivateChatMessage>>;
+
+    /// Comments on a line of a file changed by the agenda, in the chat of the current height
+    /// (see `simperby_chat::review`), which is recorded on the chain unless off-the-record.
+    async fn comment_on_agenda(
+        &self,
+        agenda_commit: CommitHash,
+        path: String,
+        line: u64,
+        text: String,
+        off_the_record: bool,
+    ) -> Result<()>;
+
+    /// Reads the review comments on the agenda, in the order of the paths and the lines.
+    async fn get_review_comments(
+        &self,
+        agenda_commit: CommitHash,
+    ) -> Result<Vec<SignedReviewComment>>;
+
+    /// Creates a chat-log commit of the current height on the `work` branch.
+    async fn create_chat_commit(&self) -> Result<CommitHash>;
 }
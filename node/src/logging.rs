@@ -0,0 +1,271 @@
+//! The logs of the node, with `tracing`.
+//!
+//! The records of the `log` facade, which most of the crates use, are captured as well,
+//! inside the spans that they are emitted in (e.g., `task` with the name of a supervised task,
+//! `fetch` with the peer, or `consensus` with the height and the round).
+//!
+//! The levels are filtered per module (see `LoggingConfig::modules`), and the logs are written
+//! to stdout, or to a file rotated by its size and by the time (see `RotatingFile`).
+use super::*;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, with the fields of the spans.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+    Never,
+    Hourly,
+    Daily,
+}
+
+impl Rotation {
+    /// Returns the period in seconds, if any.
+    fn period(&self) -> Option<i64> {
+        match self {
+            Rotation::Never => None,
+            Rotation::Hourly => Some(60 * 60),
+            Rotation::Daily => Some(24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogFileConfig {
+    pub directory: String,
+    /// The name of the current file is `<prefix>.log`, and of the rotated ones
+    /// `<prefix>.<timestamp>.log`.
+    pub prefix: String,
+    /// Rotates the file once it grows beyond this number of bytes.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Rotates the file on every period of the UTC time.
+    pub rotation: Rotation,
+    /// Keeps at most this number of the rotated files, removing the oldest.
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// The maximum level of the logs (`error`, `warn`, `info`, `debug` or `trace`).
+    pub level: String,
+    /// The maximum levels per module, overriding `level`
+    /// (e.g., `simperby_network::dms = "debug"`).
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+    pub format: LogFormat,
+    /// Writes to the file instead of stdout, if given.
+    #[serde(default)]
+    pub file: Option<LogFileConfig>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_owned(),
+            modules: BTreeMap::new(),
+            format: LogFormat::Text,
+            file: None,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Returns the filter directives, in the syntax of `RUST_LOG`.
+    pub fn directives(&self) -> String {
+        std::iter::once(self.level.clone())
+            .chain(
+                self.modules
+                    .iter()
+                    .map(|(module, level)| format!("{}={}", module, level)),
+            )
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Installs the subscriber for the process; it fails if one is already installed.
+pub fn install(config: &LoggingConfig) -> Result<()> {
+    let filter = EnvFilter::try_new(config.directives())
+        .map_err(|e| anyhow::anyhow!("invalid log filter `{}`: {}", config.directives(), e))?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match (&config.file, config.format) {
+        (None, LogFormat::Text) => builder.try_init(),
+        (None, LogFormat::Json) => builder.json().try_init(),
+        (Some(file), LogFormat::Text) => builder
+            .with_ansi(false)
+            .with_writer(Mutex::new(RotatingFile::open(file.clone())?))
+            .try_init(),
+        (Some(file), LogFormat::Json) => builder
+            .json()
+            .with_writer(Mutex::new(RotatingFile::open(file.clone())?))
+            .try_init(),
+    };
+    result.map_err(|e| anyhow::anyhow!("failed to install the logger: {}", e))
+}
+
+/// A log file that rotates by its size and by the time.
+pub struct RotatingFile {
+    config: LogFileConfig,
+    file: File,
+    size: u64,
+    /// The period of the time that the file was opened in.
+    period: Option<i64>,
+}
+
+fn current_period(rotation: Rotation) -> Option<i64> {
+    rotation
+        .period()
+        .map(|period| chrono::Utc::now().timestamp() / period)
+}
+
+impl RotatingFile {
+    pub fn open(config: LogFileConfig) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&config.directory)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::current_path(&config))?;
+        Ok(Self {
+            size: file.metadata()?.len(),
+            period: current_period(config.rotation),
+            file,
+            config,
+        })
+    }
+
+    fn current_path(config: &LogFileConfig) -> PathBuf {
+        Path::new(&config.directory).join(format!("{}.log", config.prefix))
+    }
+
+    /// Returns the rotated files with their stamps, from the oldest.
+    fn rotated(config: &LogFileConfig) -> std::io::Result<Vec<(i64, PathBuf)>> {
+        let prefix = format!("{}.", config.prefix);
+        let mut files: Vec<_> = std::fs::read_dir(&config.directory)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter_map(|path| {
+                let stamp = path
+                    .file_name()?
+                    .to_str()?
+                    .strip_prefix(&prefix)?
+                    .strip_suffix(".log")?
+                    .parse()
+                    .ok()?;
+                Some((stamp, path))
+            })
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+
+    /// Returns the rotated files, from the oldest.
+    pub fn rotated_files(config: &LogFileConfig) -> std::io::Result<Vec<PathBuf>> {
+        Ok(Self::rotated(config)?
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        let rotated = Self::rotated(&self.config)?;
+        // Later than any rotated file, even within the same millisecond.
+        let stamp = rotated
+            .last()
+            .map_or(0, |(stamp, _)| stamp + 1)
+            .max(chrono::Utc::now().timestamp_millis());
+        std::fs::rename(
+            Self::current_path(&self.config),
+            Path::new(&self.config.directory)
+                .join(format!("{}.{:020}.log", self.config.prefix, stamp)),
+        )?;
+        let rotated = Self::rotated_files(&self.config)?;
+        for path in &rotated[..rotated.len().saturating_sub(self.config.max_files)] {
+            std::fs::remove_file(path)?;
+        }
+        *self = Self::open(self.config.clone())?;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let too_large = self.config.max_bytes.map_or(false, |max| {
+            self.size > 0 && self.size + buf.len() as u64 > max
+        });
+        if too_large || current_period(self.config.rotation) != self.period {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directives() {
+        let config = LoggingConfig {
+            level: "warn".to_owned(),
+            modules: vec![
+                ("simperby_network::dms".to_owned(), "debug".to_owned()),
+                ("simperby_consensus".to_owned(), "trace".to_owned()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.directives(),
+            "warn,simperby_consensus=trace,simperby_network::dms=debug"
+        );
+        assert!(EnvFilter::try_new(config.directives()).is_ok());
+    }
+
+    #[test]
+    fn rotation() {
+        let directory = std::env::temp_dir().join(format!("simperby-logs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let config = LogFileConfig {
+            directory: directory.to_str().unwrap().to_owned(),
+            prefix: "simperby".to_owned(),
+            max_bytes: Some(10),
+            rotation: Rotation::Never,
+            max_files: 2,
+        };
+        let mut file = RotatingFile::open(config.clone()).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+        let rotated = RotatingFile::rotated_files(&config).unwrap();
+        // The first one has been removed.
+        assert_eq!(rotated.len(), 2);
+        assert_eq!(std::fs::read_to_string(&rotated[0]).unwrap(), "second\n");
+        assert_eq!(std::fs::read_to_string(&rotated[1]).unwrap(), "third\n");
+        assert_eq!(
+            std::fs::read_to_string(directory.join("simperby.log")).unwrap(),
+            "fourth\n"
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+//! The onboarding of the new members (see `simperby_common::membership`).
+//!
+//! The invitations and the applications are exchanged as JSON files
+//! (see `genesis::read_json()` and `genesis::write_json()`).
+use super::*;
+use simperby_common::membership::{Application, Invitation, SignedApplication, SignedInvitation};
+use simperby_network::signer::Signer;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+fn get_timestamp() -> Timestamp {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+    since_the_epoch.as_millis() as Timestamp
+}
+
+/// Parses the metadata of an application, given as `key=value`.
+pub fn parse_metadata(entries: &[String]) -> Result<BTreeMap<String, String>> {
+    entries
+        .iter()
+        .map(|entry| {
+            entry
+                .split_once('=')
+                .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+                .filter(|(key, _)| !key.is_empty())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("invalid metadata `{}`: expected `key=value`", entry)
+                })
+        })
+        .collect()
+}
+
+/// Invites a new member to the chain of the node, valid for the given duration.
+///
+/// It is signed by the signer of the node (see `keystore::signer()`).
+pub async fn invite(
+    config: &Config,
+    signer: &dyn Signer,
+    name: String,
+    governance_voting_power: VotingPower,
+    consensus_voting_power: VotingPower,
+    validity: Duration,
+) -> Result<SignedInvitation> {
+    if signer.public_key() != config.public_key {
+        return Err(anyhow::anyhow!(
+            "the signer doesn't match the configured public key"
+        ));
+    }
+    let invitation = Invitation {
+        chain_name: config.chain_name.clone(),
+        name,
+        governance_voting_power,
+        consensus_voting_power,
+        expires_at: get_timestamp() + validity.as_millis() as Timestamp,
+        inviter: config.public_key.clone(),
+    };
+    let signature = TypedSignature::new(
+        signer.sign(invitation.to_hash256()).await?,
+        signer.public_key(),
+    );
+    Ok(SignedInvitation {
+        invitation,
+        signature,
+    })
+}
+
+/// Applies to the invitation with the key of the applicant.
+pub fn apply(
+    invitation: SignedInvitation,
+    private_key: &PrivateKey,
+    metadata: BTreeMap<String, String>,
+) -> Result<SignedApplication> {
+    if get_timestamp() > invitation.invitation.expires_at {
+        return Err(anyhow::anyhow!("the invitation has expired"));
+    }
+    Application {
+        invitation,
+        public_key: private_key.public_key(),
+        bls_public_key: None,
+        metadata,
+        timestamp: get_timestamp(),
+    }
+    .sign(private_key)
+    .map_err(|e| anyhow::anyhow!(e))
+}
+
+/// Submits the application as a transaction adding the member, and then creates an agenda
+/// of the pending transactions for the members to vote on.
+///
+/// Returns the transaction commit and the agenda commit.
+pub async fn submit<R: RawRepository>(
+    config: &Config,
+    application: &SignedApplication,
+) -> Result<(CommitHash, CommitHash)> {
+    let mut repo = DistributedRepository::new(R::open(&config.repository_directory).await?).await?;
+    let reserved_state = repo.get_reserved_state().await?;
+    let height = repo.get_last_finalized_block_header().await?.height + 1;
+    let transaction = application
+        .to_transaction(
+            &reserved_state,
+            height,
+            config.public_key.clone(),
+            get_timestamp(),
+        )
+        .map_err(|e| anyhow::anyhow!("invalid application: {}", e))?;
+    let transaction_commit = repo.create_transaction(&transaction).await?;
+    let agenda_commit = repo.create_agenda(config.public_key.clone()).await?;
+    Ok((transaction_commit, agenda_commit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let metadata =
+            parse_metadata(&["contact = b@example.org".to_owned(), "team=ops".to_owned()]).unwrap();
+        assert_eq!(metadata["contact"], "b@example.org");
+        assert_eq!(metadata["team"], "ops");
+        assert!(parse_metadata(&["no-value".to_owned()]).is_err());
+        assert!(parse_metadata(&["=x".to_owned()]).is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! The mirrors of the finalized branch on the external git remotes (e.g., GitHub or GitLab),
+//! so that the chain can be browsed on a familiar forge.
+//!
+//! After each finalization, the finalized branch (and the tags, unless disabled) is pushed
+//! to every configured mirror. Each mirror has its own credentials and its own handle of the
+//! repository, and a failed or hung push is only logged and retried, never delaying consensus
+//! or the other mirrors. The branch is never force-pushed; a mirror that diverged is reported.
+use super::*;
+use events::{EventBus, NodeEvent};
+use simperby_repository::raw::{PushCredentials, RawRepository};
+use simperby_repository::FINALIZED_BRANCH_NAME;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// How long a mirror waits after a failed push before retrying.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MirrorConfig {
+    /// The name of the mirror in the logs (e.g., `github`).
+    pub name: String,
+    /// The URL of the repository (e.g., `https://github.com/dao/chain.git`).
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    /// The environment variable holding the password (e.g., an access token),
+    /// so that it stays out of the configuration file.
+    #[serde(default)]
+    pub password_env: Option<String>,
+    /// Whether the tags are pushed too.
+    #[serde(default = "MirrorConfig::default_tags")]
+    pub tags: bool,
+    /// The timeout of a single push.
+    #[serde(default = "MirrorConfig::default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl MirrorConfig {
+    fn default_tags() -> bool {
+        true
+    }
+
+    fn default_timeout_ms() -> u64 {
+        120_000
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.is_empty() {
+            return Err("a mirror has no name".to_owned());
+        }
+        if url::Url::parse(&self.url).is_err() {
+            return Err(format!("invalid URL `{}` of `{}`", self.url, self.name));
+        }
+        if self.username.is_some() != self.password_env.is_some() {
+            return Err(format!(
+                "`{}` needs both `username` and `password_env`, or neither",
+                self.name
+            ));
+        }
+        if self.timeout_ms == 0 {
+            return Err(format!("zero timeout of `{}`", self.name));
+        }
+        Ok(())
+    }
+
+    /// Reads the credentials, if any, from the environment.
+    ///
+    /// It is read on every push, so that a rotated token is picked up without a restart.
+    pub fn credentials(&self) -> Result<Option<PushCredentials>> {
+        match (&self.username, &self.password_env) {
+            (Some(username), Some(password_env)) => Ok(Some(PushCredentials {
+                username: username.clone(),
+                password: std::env::var(password_env).map_err(|_| {
+                    anyhow::anyhow!("the environment variable `{}` is not set", password_env)
+                })?,
+            })),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Returns the refspecs pushed to a mirror, updating the same references as the local ones.
+pub fn refspecs(tags: &[String]) -> Vec<String> {
+    std::iter::once(format!("refs/heads/{}", FINALIZED_BRANCH_NAME))
+        .chain(tags.iter().map(|tag| format!("refs/tags/{}", tag)))
+        .map(|reference| format!("{}:{}", reference, reference))
+        .collect()
+}
+
+/// Pushes the finalized branch and the tags to the mirror once.
+async fn publish<R: RawRepository>(
+    repository_directory: &str,
+    mirror: &MirrorConfig,
+) -> Result<()> {
+    let raw = R::open(repository_directory).await?;
+    let tags = if mirror.tags {
+        raw.list_tags().await?
+    } else {
+        Vec::new()
+    };
+    let credentials = mirror.credentials()?;
+    tokio::time::timeout(
+        Duration::from_millis(mirror.timeout_ms),
+        raw.push(&mirror.url, &refspecs(&tags), credentials.as_ref()),
+    )
+    .await
+    .map_err(|_| anyhow::anyhow!("timed out"))??;
+    Ok(())
+}
+
+async fn serve_mirror<R: RawRepository>(
+    repository_directory: String,
+    mirror: MirrorConfig,
+    mut events: broadcast::Receiver<NodeEvent>,
+) {
+    // Catches up with the blocks finalized while the node was down.
+    let mut pending = true;
+    loop {
+        if pending {
+            match publish::<R>(&repository_directory, &mirror).await {
+                Ok(()) => {
+                    log::debug!("mirrored to {}", mirror.name);
+                    pending = false;
+                }
+                Err(e) => log::warn!("failed to mirror to {}: {}", mirror.name, e),
+            }
+        }
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(NodeEvent::BlockFinalized { .. }) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                    pending = true
+                }
+                Ok(_) => (),
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            _ = tokio::time::sleep(RETRY_INTERVAL), if pending => (),
+        }
+    }
+}
+
+/// Mirrors the finalized branch to `Config::mirrors` indefinitely.
+pub async fn run<R: RawRepository>(config: Config, bus: EventBus) -> Result<()> {
+    futures::future::join_all(config.mirrors.iter().map(|mirror| {
+        serve_mirror::<R>(
+            config.repository_directory.clone(),
+            mirror.clone(),
+            bus.subscribe(),
+        )
+    }))
+    .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mirror() -> MirrorConfig {
+        toml::from_str(
+            r#"
+            name = "github"
+            url = "https://github.com/dao/chain.git"
+            username = "x-access-token"
+            password_env = "SIMPERBY_TEST_MIRROR_TOKEN"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn config() {
+        let mirror = mirror();
+        assert!(mirror.tags);
+        mirror.validate().unwrap();
+        MirrorConfig {
+            password_env: None,
+            ..mirror.clone()
+        }
+        .validate()
+        .unwrap_err();
+        MirrorConfig {
+            url: "not a url".to_owned(),
+            ..mirror
+        }
+        .validate()
+        .unwrap_err();
+    }
+
+    #[test]
+    fn credentials() {
+        let mirror = mirror();
+        std::env::remove_var("SIMPERBY_TEST_MIRROR_TOKEN");
+        mirror.credentials().unwrap_err();
+        std::env::set_var("SIMPERBY_TEST_MIRROR_TOKEN", "secret");
+        let credentials = mirror.credentials().unwrap().unwrap();
+        assert_eq!(credentials.username, "x-access-token");
+        assert_eq!(credentials.password, "secret");
+        assert!(!format!("{:?}", credentials).contains("secret"));
+        assert_eq!(
+            MirrorConfig {
+                username: None,
+                password_env: None,
+                ..mirror
+            }
+            .credentials()
+            .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn refspecs_of_tags() {
+        assert_eq!(
+            refspecs(&["vote-1".to_owned()]),
+            vec![
+                format!(
+                    "refs/heads/{}:refs/heads/{}",
+                    FINALIZED_BRANCH_NAME, FINALIZED_BRANCH_NAME
+                ),
+                "refs/tags/vote-1:refs/tags/vote-1".to_owned(),
+            ]
+        );
+    }
+}
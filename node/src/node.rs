@@ -1,26 +1,227 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use super::*;
+use crate::clock::SharedClock;
+use crate::events::EventBus;
+use crate::keystore::Keystore;
+use crate::review::{AgendaReview, BlockReview};
+use crate::runtime::Supervisor;
 use anyhow::anyhow;
-use simperby_network::dms::{Config as DmsConfig, DistributedMessageSet};
-use simperby_network::primitives::{GossipNetwork, Storage};
-use simperby_network::NetworkConfig;
+use futures::future;
+use simperby_chat::review::ReviewComment;
+use simperby_consensus::archive::{self, VoteArchive};
+use simperby_consensus::{Consensus, ProgressResult};
+use simperby_network::bandwidth::{BandwidthMeter, Subsystem};
+use simperby_network::clock::{system_clock, Clock};
+use simperby_network::dms::{Config as DmsConfig, DistributedMessageSet, MessageReader};
+use simperby_network::mdns::Advertisement;
+use simperby_network::peer_discovery::PeerDiscoveryImpl;
+use simperby_network::pipeline::Pipeline;
+use simperby_network::primitives::{GossipNetwork, MessageStore};
+use simperby_network::signer::Signer;
+use simperby_network::{NetworkConfig, PeerDiscovery};
 use simperby_repository::raw::RawRepository;
 use simperby_repository::DistributedRepository;
 
-pub struct Node<N: GossipNetwork, S: Storage, R: RawRepository> {
+pub struct Node<N: GossipNetwork, S: MessageStore, R: RawRepository> {
     config: Config,
+    signer: Arc<dyn Signer>,
+    /// The private key for reading the private chat channels,
+    /// which is not available with a remote signer.
+    private_key: Option<PrivateKey>,
+    bandwidth: BandwidthMeter,
+    pipeline: Pipeline,
+    events: EventBus,
+    clock: SharedClock,
+    /// The source of the time of the DMSes and the consensus.
+    time: Arc<dyn Clock>,
     _marker1: std::marker::PhantomData<N>,
     _marker2: std::marker::PhantomData<S>,
     _marker3: std::marker::PhantomData<R>,
 }
 
+impl<N: GossipNetwork, S: MessageStore, R: RawRepository> Node<N, S, R> {
+    /// Creates a node, unlocking its keystore with the passphrase.
+    pub async fn new(config: Config, passphrase: &str) -> Result<Self> {
+        let private_key = Keystore::load(&config.keystore_path)
+            .await?
+            .decrypt(passphrase)?;
+        Self::with_private_key(config, private_key)
+    }
+
+    /// Creates a node of the private key unlocked from its keystore (see `keystore::unlock()`).
+    pub fn with_private_key(config: Config, private_key: PrivateKey) -> Result<Self> {
+        let signer = keystore::signer(&config, private_key.clone());
+        let remote = config.remote_signer.is_some();
+        let mut node = Self::with_signer(config, signer)?;
+        if !remote {
+            node.private_key = Some(private_key);
+        }
+        Ok(node)
+    }
+
+    /// Creates a node that signs with the given signer (e.g., a `RemoteSigner`).
+    pub fn with_signer(config: Config, signer: Arc<dyn Signer>) -> Result<Self> {
+        if signer.public_key() != config.public_key {
+            return Err(anyhow!(
+                "the signer doesn't match the configured public key"
+            ));
+        }
+        Ok(Self {
+            bandwidth: BandwidthMeter::new(config.bandwidth_quota.clone()),
+            pipeline: Pipeline::new(config.pipeline.clone()),
+            events: EventBus::default(),
+            clock: SharedClock::default(),
+            time: system_clock(),
+            config,
+            signer,
+            private_key: None,
+            _marker1: std::marker::PhantomData,
+            _marker2: std::marker::PhantomData,
+            _marker3: std::marker::PhantomData,
+        })
+    }
+}
+
+impl<N: GossipNetwork, S: MessageStore, R: RawRepository> Node<N, S, R> {
+    /// Replaces the source of the time, which is the system clock by default
+    /// (e.g., with a `SimulatedClock` in a simulation).
+    pub fn with_time(mut self, time: Arc<dyn Clock>) -> Self {
+        self.time = time;
+        self
+    }
+
+    /// Returns the bus of the events of the node, which are published while `run()`.
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    /// Returns the estimated skew of the clock against the validators,
+    /// which is measured while `run()`.
+    pub fn clock(&self) -> SharedClock {
+        Arc::clone(&self.clock)
+    }
+
+    /// Registers the bootstrap peers to the peer discovery, on startup.
+    ///
+    /// Those in the reserved state come first, followed by the locally configured ones.
+    /// The endpoints that can't be resolved are skipped.
+    pub async fn add_bootstrap_peers(&self) -> Result<()> {
+        let repo = DistributedRepository::new(R::open(&self.config.repository_directory)?).await?;
+        let reserved_state = repo.get_reserved_state().await?;
+        let mut addresses = Vec::new();
+        for endpoint in merge_bootstrap_peers(
+            &reserved_state.bootstrap_peers,
+            &self.config.bootstrap_peers,
+        ) {
+            match tokio::net::lookup_host(&endpoint).await {
+                Ok(resolved) => addresses.extend(resolved.filter_map(|address| match address {
+                    SocketAddr::V4(address) => Some(address),
+                    SocketAddr::V6(_) => None,
+                })),
+                Err(e) => log::warn!("failed to resolve the bootstrap peer {}: {}", endpoint, e),
+            }
+        }
+        PeerDiscoveryImpl::add_bootstrap_addresses(&self.config.peer_directory, addresses).await
+    }
+
+    /// Sets up the NAT traversal for the port on startup, announcing the relay if it falls back to one.
+    pub async fn setup_nat(&self, port: u16) -> Result<NatStatus> {
+        let status = simperby_network::nat::setup(&self.config.nat, port).await?;
+        let relay = match &status {
+            NatStatus::Relayed { relay } => Some(relay.clone()),
+            _ => None,
+        };
+        PeerDiscoveryImpl::set_relay(&self.config.peer_directory, relay).await?;
+        Ok(status)
+    }
+
+    /// Advertises this node and finds the other nodes of the chain on the local network with mDNS,
+    /// only in the development mode (returns `None` otherwise).
+    pub async fn serve_local_discovery(
+        &self,
+        discovery_port: u16,
+        dms_port: u16,
+    ) -> Result<Option<tokio::task::JoinHandle<Result<()>>>> {
+        if !self.config.dev_mode {
+            return Ok(None);
+        }
+        let task = simperby_network::mdns::serve(
+            self.config.peer_directory.clone(),
+            Advertisement {
+                chain: self.config.chain_name.clone(),
+                public_key: self.config.public_key.clone(),
+                discovery_port,
+                dms_port: Some(dms_port),
+            },
+        )
+        .await?;
+        Ok(Some(task))
+    }
+}
+
+/// Merges the bootstrap peers in the order given, removing the duplicates.
+fn merge_bootstrap_peers(from_reserved_state: &[String], local: &[String]) -> Vec<String> {
+    let mut result = Vec::new();
+    for peer in from_reserved_state.iter().chain(local) {
+        if !result.contains(peer) {
+            result.push(peer.clone());
+        }
+    }
+    result
+}
+
 async fn create_network_config(_config: &Config) -> Result<NetworkConfig> {
     unimplemented!()
 }
 
+/// Measures the size of the DMS if the metrics are enabled, or waits forever.
+async fn observe_dms<S: MessageStore>(
+    enabled: bool,
+    name: &'static str,
+    reader: MessageReader<S>,
+) -> Result<()> {
+    if enabled {
+        telemetry::observe_dms(name, reader).await
+    } else {
+        future::pending().await
+    }
+}
+
+/// The configuration of the DMS, with the default retention
+/// which keeps every message until the height advances.
+fn dms_config(config: &Config) -> DmsConfig {
+    DmsConfig {
+        broadcast_interval: config.broadcast_interval_ms.map(Duration::from_millis),
+        fetch_interval: config.fetch_interval_ms.map(Duration::from_millis),
+        retention: Default::default(),
+        compaction_interval: None,
+        serve_as_relay: config.nat.serve_as_relay,
+        relay: config.nat.relay.clone(),
+        peer_score: config.peer_score.clone(),
+        gossip: config.gossip.clone(),
+        sentry: config.sentry.clone(),
+    }
+}
+
+impl<N: GossipNetwork, S: MessageStore, R: RawRepository> Node<N, S, R> {
+    async fn open_chat(&self) -> Result<Chat<N, S>> {
+        // The messages must be kept until they are recorded in a chat-log commit.
+        let mut chat_dms = DistributedMessageSet::open(
+            S::open(&self.config.chat_directory).await?,
+            dms_config(&self.config),
+        )
+        .await?;
+        chat_dms.set_bandwidth_meter(self.bandwidth.clone(), Subsystem::Chat);
+        chat_dms.set_clock(Arc::clone(&self.time));
+        Chat::<N, S>::open(chat_dms).await
+    }
+}
+
 #[async_trait]
-impl<N: GossipNetwork, S: Storage, R: RawRepository> SimperbyApi for Node<N, S, R> {
+impl<N: GossipNetwork, S: MessageStore, R: RawRepository> SimperbyApi for Node<N, S, R> {
     async fn genesis(&self) -> Result<()> {
         unimplemented!()
     }
@@ -29,8 +230,15 @@ impl<N: GossipNetwork, S: Storage, R: RawRepository> SimperbyApi for Node<N, S,
         unimplemented!()
     }
 
-    async fn sync(&self, _commmit: CommitHash) -> Result<()> {
-        unimplemented!()
+    async fn sync(&self, commmit: CommitHash) -> Result<()> {
+        let mut repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        let start = std::time::Instant::now();
+        let result = repo.sync(&commmit).await;
+        telemetry::record_sync(start.elapsed(), result.is_ok());
+        result?;
+        let height = repo.get_last_finalized_block_header().await?.height;
+        PeerDiscoveryImpl::set_height(&self.config.peer_directory, height).await
     }
 
     async fn clean(&self, _hard: bool) -> Result<()> {
@@ -45,39 +253,99 @@ impl<N: GossipNetwork, S: Storage, R: RawRepository> SimperbyApi for Node<N, S,
         unimplemented!()
     }
 
-    async fn create_extra_agenda_transaction(&self, _tx: ExtraAgendaTransaction) -> Result<()> {
-        unimplemented!()
+    async fn create_transaction(&self, tx: Transaction) -> Result<CommitHash> {
+        let mut repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        repo.create_transaction(&tx).await
+    }
+
+    async fn get_last_finalized_block_header(&self) -> Result<BlockHeader> {
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        repo.get_last_finalized_block_header().await
+    }
+
+    async fn get_reserved_state(&self) -> Result<ReservedState> {
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        repo.get_reserved_state().await
+    }
+
+    async fn get_reserved_state_at(&self, point: FinalizedPoint) -> Result<ReservedState> {
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        repo.reserved_state_at(point).await
+    }
+
+    async fn get_agendas(&self) -> Result<Vec<(CommitHash, Hash256)>> {
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        repo.get_agendas().await
+    }
+
+    async fn create_extra_agenda_transaction(&self, tx: ExtraAgendaTransaction) -> Result<()> {
+        let mut repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        repo.create_extra_agenda_transaction(&tx).await?;
+        Ok(())
     }
 
     async fn vote(&self, agenda_commit: CommitHash) -> Result<()> {
         let repo =
             DistributedRepository::new(R::open(&self.config.repository_directory)?).await?;
         let valid_agendas = repo.get_agendas().await?;
-        let agenda_hash = if let Some(x) = valid_agendas.iter().find(|(x, _)| *x == agenda_commit) {
-            x.1
-        } else {
+        if !valid_agendas.iter().any(|(x, _)| *x == agenda_commit) {
             return Err(anyhow!(
                 "the given commit hash {} is not one of the valid agendas",
                 agenda_commit
             ));
-        };
-        let governance_dms = DistributedMessageSet::open(
+        }
+        repo.verify_proposal_limits(&agenda_commit).await?;
+        if let Some((_, amendment)) = repo
+            .get_amendments()
+            .await?
+            .into_iter()
+            .find(|(superseded, _)| *superseded == agenda_commit)
+        {
+            return Err(anyhow!(
+                "the agenda {} is superseded by {}",
+                hex::encode(agenda_commit.hash),
+                hex::encode(amendment.hash)
+            ));
+        }
+        let agenda = repo.read_agenda(&agenda_commit).await?;
+        let last_finalized_height = repo.get_last_finalized_block_header().await?.height;
+        let mut governance_dms = DistributedMessageSet::open(
             S::open(&self.config.governance_directory).await?,
             DmsConfig {
                 broadcast_interval: self.config.broadcast_interval_ms.map(Duration::from_millis),
                 fetch_interval: self.config.fetch_interval_ms.map(Duration::from_millis),
+                // The votes must be kept until the agenda is approved.
+                retention: Default::default(),
+                compaction_interval: None,
+                serve_as_relay: self.config.nat.serve_as_relay,
+                relay: self.config.nat.relay.clone(),
+                peer_score: self.config.peer_score.clone(),
+                gossip: self.config.gossip.clone(),
+                sentry: self.config.sentry.clone(),
             },
         )
         .await?;
+        governance_dms.set_bandwidth_meter(self.bandwidth.clone(), Subsystem::Governance);
+        governance_dms.set_clock(Arc::clone(&self.time));
         let mut governance = Governance::<N, S>::open(governance_dms).await?;
         governance
             .vote(
                 &create_network_config(&self.config).await?,
                 &[],
-                agenda_hash,
-                &self.config.private_key,
+                &agenda,
+                last_finalized_height,
+                self.signer.as_ref(),
             )
             .await?;
+        let mut repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        repo.tag_decision("vote", &agenda_commit).await?;
         Ok(())
     }
 
@@ -85,12 +353,319 @@ impl<N: GossipNetwork, S: Storage, R: RawRepository> SimperbyApi for Node<N, S,
         unimplemented!()
     }
 
-    async fn veto_block(&self, _block_commit: CommitHash) -> Result<()> {
-        unimplemented!()
+    async fn veto_block(&self, block_commit: CommitHash) -> Result<()> {
+        let mut repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        if repo.untag_decision("veto", &block_commit).await? {
+            log::info!("removed the veto tag of the block");
+            return Ok(());
+        }
+        let header = repo.read_block(&block_commit).await?;
+        let mut dms = DistributedMessageSet::<N, S>::open(
+            S::open(&self.config.consensus_directory).await?,
+            dms_config(&self.config),
+        )
+        .await?;
+        dms.set_bandwidth_meter(self.bandwidth.clone(), Subsystem::Consensus);
+        let mut consensus = Consensus::new(dms).await?;
+        consensus.set_clock(Arc::clone(&self.time));
+        consensus
+            .veto_block(
+                create_network_config(&self.config).await?,
+                &[],
+                self.signer.as_ref(),
+                header.to_hash256(),
+            )
+            .await?;
+        repo.tag_decision("veto", &block_commit).await?;
+        Ok(())
+    }
+
+    async fn review_agenda(&self, agenda_commit: CommitHash) -> Result<AgendaReview> {
+        query::review_agenda::<S, R>(&self.config, agenda_commit).await
+    }
+
+    async fn review_block(&self, block_commit: CommitHash) -> Result<BlockReview> {
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        Ok(BlockReview {
+            commit: block_commit,
+            header: repo.read_block(&block_commit).await?,
+            diff: repo.show_commit(&block_commit).await?,
+        })
     }
 
     async fn run(&self) -> Result<()> {
-        unimplemented!()
+        telemetry::install(&self.config.metrics)?;
+        let metrics_enabled = self.config.metrics.enabled;
+
+        // 1. Verifies the repository, recovering it if the finalized branch is broken.
+        if let Some(problem) = recovery::detect::<R>(&self.config).await? {
+            if self.config.recovery_sources.is_empty() {
+                return Err(anyhow!(
+                    "{}; run `simperby recover`, or configure `recovery_sources`",
+                    problem
+                ));
+            }
+            log::warn!("{}; recovering from the sources", problem);
+            let recovery =
+                recovery::recover::<R>(&self.config, &self.config.recovery_sources, false).await?;
+            log::info!(
+                "recovered the repository at height {}, archiving the broken one to {:?}",
+                recovery.height,
+                recovery.archived
+            );
+        }
+
+        // 2. Opens it.
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        let last_header = repo.get_last_finalized_block_header().await?;
+        let reserved_state = repo.get_reserved_state().await?;
+        log::info!("opened the repository at height {}", last_header.height);
+        drop(repo);
+        recovery::update_watermark(
+            &self.config,
+            reserved_state.genesis_info.header.to_hash256(),
+            &last_header,
+        )
+        .await?;
+
+        // 3. Joins the network.
+        self.add_bootstrap_peers().await?;
+        let network_config = create_network_config(&self.config).await?;
+        let (peers, mut discovery) =
+            PeerDiscoveryImpl::serve(&self.config.peer_directory, &network_config).await?;
+        let mut supervisor = Supervisor::new(self.config.restart_policy.clone());
+        for (name, directory, port, subsystem) in [
+            (
+                "governance",
+                self.config.governance_directory.clone(),
+                self.config.ports.governance,
+                Subsystem::Governance,
+            ),
+            (
+                "chat",
+                self.config.chat_directory.clone(),
+                self.config.ports.chat,
+                Subsystem::Chat,
+            ),
+        ] {
+            let config = self.config.clone();
+            let network_config = network_config.clone();
+            let peers = peers.clone();
+            let bandwidth = self.bandwidth.clone();
+            let pipeline = self.pipeline.clone();
+            let reserved_state = reserved_state.clone();
+            let bus = self.events.clone();
+            let time = Arc::clone(&self.time);
+            supervisor.spawn(name, move || {
+                let (
+                    config,
+                    directory,
+                    network_config,
+                    peers,
+                    bandwidth,
+                    pipeline,
+                    reserved_state,
+                    bus,
+                    time,
+                ) = (
+                    config.clone(),
+                    directory.clone(),
+                    network_config.clone(),
+                    peers.clone(),
+                    bandwidth.clone(),
+                    pipeline.clone(),
+                    reserved_state.clone(),
+                    bus.clone(),
+                    Arc::clone(&time),
+                );
+                Box::pin(async move {
+                    let mut dms = DistributedMessageSet::<N, S>::open(
+                        S::open(&directory).await?,
+                        dms_config(&config),
+                    )
+                    .await?;
+                    dms.set_bandwidth_meter(bandwidth, subsystem);
+                    dms.set_pipeline(pipeline, subsystem);
+                    dms.set_clock(time);
+                    let reader = dms.reader();
+                    let task = dms.serve(network_config, port, peers).await?;
+                    let votes = async {
+                        if subsystem == Subsystem::Governance {
+                            events::observe_votes(reader.clone(), reserved_state, bus).await
+                        } else {
+                            future::pending::<Result<()>>().await
+                        }
+                    };
+                    tokio::select! {
+                        result = task => result?,
+                        result = votes => result,
+                        result = observe_dms(metrics_enabled, name, reader.clone()) => result,
+                    }
+                })
+            });
+        }
+
+        // 4. Starts the consensus.
+        let config = self.config.clone();
+        let signer = Arc::clone(&self.signer);
+        let bandwidth = self.bandwidth.clone();
+        let pipeline = self.pipeline.clone();
+        let peers_ = peers.clone();
+        let time = Arc::clone(&self.time);
+        supervisor.spawn("consensus", move || {
+            let (config, network_config, peers, signer, bandwidth, pipeline, time) = (
+                config.clone(),
+                network_config.clone(),
+                peers_.clone(),
+                Arc::clone(&signer),
+                bandwidth.clone(),
+                pipeline.clone(),
+                Arc::clone(&time),
+            );
+            Box::pin(async move {
+                let mut dms = DistributedMessageSet::<N, S>::open(
+                    S::open(&config.consensus_directory).await?,
+                    dms_config(&config),
+                )
+                .await?;
+                dms.set_bandwidth_meter(bandwidth, Subsystem::Consensus);
+                dms.set_pipeline(pipeline, Subsystem::Consensus);
+                let reader = dms.reader();
+                let (archive_reader, archive_directory, archive_time) = (
+                    reader.clone(),
+                    config.vote_archive_directory.clone(),
+                    Arc::clone(&time),
+                );
+                let archive = async move {
+                    match archive_directory {
+                        Some(directory) => {
+                            let vote_archive = VoteArchive::open(directory).await?;
+                            archive::observe(archive_reader, vote_archive, archive_time).await
+                        }
+                        None => future::pending::<Result<()>>().await,
+                    }
+                };
+                let mut consensus = Consensus::new(dms).await?;
+                consensus.set_clock(time);
+                let (mut results, task) = consensus.serve(network_config, peers, signer).await?;
+                let progress = async {
+                    while let Some(result) = results.recv().await {
+                        match result {
+                            ProgressResult::Finalized(_) => log::info!("finalized a block"),
+                            ProgressResult::CaughtUp(height) => {
+                                log::info!("caught up to height {}", height)
+                            }
+                            ProgressResult::EvidenceFound(_) => {
+                                log::warn!("found an evidence of a misbehavior")
+                            }
+                            _ => (),
+                        }
+                    }
+                };
+                tokio::select! {
+                    _ = progress => (),
+                    result = observe_dms(metrics_enabled, "consensus", reader) => return result,
+                    result = archive => return result,
+                }
+                task.await?
+            })
+        });
+
+        // 5. Publishes the events.
+        let repository_directory = self.config.repository_directory.clone();
+        let bus = self.events.clone();
+        let peers_ = peers.clone();
+        supervisor.spawn("events", move || {
+            Box::pin(events::observe::<R>(
+                repository_directory.clone(),
+                peers_.clone(),
+                bus.clone(),
+            ))
+        });
+        if let Some(port) = self.config.api.event_port {
+            let bus = self.events.clone();
+            supervisor.spawn("event-subscriptions", move || {
+                Box::pin(events::serve(port, bus.clone()))
+            });
+        }
+
+        if !self.config.notifiers.is_empty() {
+            let config = self.config.clone();
+            let bus = self.events.clone();
+            supervisor.spawn("notifications", move || {
+                let (config, bus) = (config.clone(), bus.clone());
+                Box::pin(async move {
+                    let subscriptions = notify::from_config(&config.notifiers)?;
+                    notify::dispatch::<R>(config, bus, subscriptions).await
+                })
+            });
+        }
+        if !self.config.mirrors.is_empty() {
+            let config = self.config.clone();
+            let bus = self.events.clone();
+            supervisor.spawn("mirrors", move || {
+                Box::pin(mirror::run::<R>(config.clone(), bus.clone()))
+            });
+        }
+        #[cfg(feature = "settlement")]
+        if let Some(settlement_config) = self.config.settlement.clone() {
+            let repository_directory = self.config.repository_directory.clone();
+            let public_key = self.config.public_key.clone();
+            let bus = self.events.clone();
+            supervisor.spawn("settlement", move || {
+                Box::pin(settlement::run::<R>(
+                    settlement_config.clone(),
+                    repository_directory.clone(),
+                    public_key.clone(),
+                    bus.clone(),
+                ))
+            });
+        }
+
+        // 6. Measures the clock skew.
+        let config = self.config.clone();
+        let clock = Arc::clone(&self.clock);
+        let peers_ = peers.clone();
+        supervisor.spawn("clock", move || {
+            Box::pin(clock::monitor::<R>(
+                config.clone(),
+                peers_.clone(),
+                Arc::clone(&clock),
+            ))
+        });
+
+        // 7. Measures the disk usage against the quotas.
+        let config = self.config.clone();
+        supervisor.spawn("storage", move || {
+            Box::pin(storage::observe(config.clone()))
+        });
+
+        // 8. Processes the messages received from the gossip network, by priority.
+        let pipeline = self.pipeline.clone();
+        supervisor.spawn("pipeline", move || {
+            let pipeline = pipeline.clone();
+            Box::pin(async move {
+                if metrics_enabled {
+                    tokio::select! {
+                        result = pipeline.clone().run() => result,
+                        result = telemetry::observe_pipeline(pipeline) => result,
+                    }
+                } else {
+                    pipeline.run().await
+                }
+            })
+        });
+
+        let result = tokio::select! {
+            result = runtime::wait_for_termination() => result,
+            result = &mut discovery => Err(anyhow!("the peer discovery stopped: {:?}", result)),
+        };
+        discovery.abort();
+        supervisor.shutdown().await;
+        result
     }
 
     async fn progress_for_consensus(&self) -> Result<String> {
@@ -105,6 +680,23 @@ impl<N: GossipNetwork, S: Storage, R: RawRepository> SimperbyApi for Node<N, S,
         unimplemented!()
     }
 
+    async fn add_peer(&self, address: String, public_key: PublicKey) -> Result<()> {
+        peers::add(&self.config, &address, public_key).await?;
+        Ok(())
+    }
+
+    async fn remove_peer(&self, public_key: PublicKey) -> Result<bool> {
+        peers::remove(&self.config, &public_key).await
+    }
+
+    async fn ping_peer(&self, target: String) -> Result<peers::PingResult> {
+        peers::ping::<R>(&self.config, &target).await
+    }
+
+    async fn get_bandwidth_usage(&self) -> Result<BandwidthReport> {
+        Ok(self.bandwidth.report().await)
+    }
+
     async fn relay(&self) -> Result<()> {
         unimplemented!()
     }
@@ -116,4 +708,131 @@ impl<N: GossipNetwork, S: Storage, R: RawRepository> SimperbyApi for Node<N, S,
     async fn notify_git_push(&self) -> Result<String> {
         unimplemented!()
     }
+
+    async fn blame(&self, commit: CommitHash, path: String) -> Result<Vec<BlameLine>> {
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        repo.blame_reserved_state(&path, &commit).await
+    }
+
+    async fn check_integrity(&self) -> Result<IntegrityReport> {
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        repo.check_integrity().await
+    }
+
+    async fn get_storage_usage(&self) -> Result<storage::StorageReport> {
+        storage::report::<R>(&self.config).await
+    }
+
+    async fn chat(&self, message: String, off_the_record: bool) -> Result<()> {
+        let mut chat = self.open_chat().await?;
+        chat.send(
+            &create_network_config(&self.config).await?,
+            &[],
+            message,
+            off_the_record,
+            self.signer.as_ref(),
+        )
+        .await
+    }
+
+    async fn read_chat(&self) -> Result<Vec<ChatMessage>> {
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        let reserved_state = repo.get_reserved_state().await?;
+        let chat = self.open_chat().await?;
+        Ok(chat
+            .read(&reserved_state)
+            .await?
+            .into_iter()
+            .map(|(message, _)| message)
+            .collect())
+    }
+
+    async fn chat_private(&self, members: Vec<PublicKey>, message: String) -> Result<()> {
+        let channel = simperby_chat::private::Channel::new(
+            members
+                .into_iter()
+                .chain(std::iter::once(self.config.public_key.clone())),
+        );
+        let mut chat = self.open_chat().await?;
+        chat.send_private(
+            &create_network_config(&self.config).await?,
+            &[],
+            channel,
+            message,
+            self.signer.as_ref(),
+        )
+        .await
+    }
+
+    async fn comment_on_agenda(
+        &self,
+        agenda_commit: CommitHash,
+        path: String,
+        line: u64,
+        text: String,
+        off_the_record: bool,
+    ) -> Result<()> {
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        let agenda = repo.read_agenda(&agenda_commit).await?;
+        if !repo
+            .read_agenda_changed_files(&agenda_commit)
+            .await?
+            .contains(&path)
+        {
+            return Err(anyhow!("{} is not changed by the agenda", path));
+        }
+        drop(repo);
+        let mut chat = self.open_chat().await?;
+        chat.comment(
+            &create_network_config(&self.config).await?,
+            &[],
+            ReviewComment {
+                agenda_hash: agenda.to_hash256(),
+                path,
+                line,
+                text,
+            },
+            off_the_record,
+            self.signer.as_ref(),
+        )
+        .await
+    }
+
+    async fn get_review_comments(
+        &self,
+        agenda_commit: CommitHash,
+    ) -> Result<Vec<SignedReviewComment>> {
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        let agenda = repo.read_agenda(&agenda_commit).await?;
+        let reserved_state = repo.get_reserved_state().await?;
+        drop(repo);
+        let chat = self.open_chat().await?;
+        chat.read_comments(&reserved_state, &agenda.to_hash256())
+            .await
+    }
+
+    async fn read_private_chat(&self) -> Result<Vec<PrivateChatMessage>> {
+        let private_key = self.private_key.as_ref().ok_or_else(|| {
+            anyhow!("the private chat can't be read without the local private key")
+        })?;
+        let repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        let reserved_state = repo.get_reserved_state().await?;
+        let chat = self.open_chat().await?;
+        chat.read_private(&reserved_state, private_key).await
+    }
+
+    async fn create_chat_commit(&self) -> Result<CommitHash> {
+        let mut repo =
+            DistributedRepository::new(R::open(&self.config.repository_directory).await?).await?;
+        let reserved_state = repo.get_reserved_state().await?;
+        let chat = self.open_chat().await?;
+        let chat_log = chat.create_chat_log(&reserved_state).await?;
+        repo.create_chat_commit(chat_log).await
+    }
 }
@@ -0,0 +1,389 @@
+//! The notifications of the events to the operators, through pluggable backends.
+//!
+//! A backend implements `Notifier`; the node has two built in, configured in `Config::notifiers`:
+//! the webhooks (see `webhook`) and the subprocesses (`CommandNotifier`), which run a command
+//! with the notification JSON on its stdin, so that any alerting system (e.g., email or Matrix)
+//! can be integrated by a script. An embedder can add its own backends to `dispatch()`.
+//!
+//! Each notifier has its own queue and retries, so a slow or failing one doesn't delay the others.
+use super::*;
+use events::{EventBus, NodeEvent};
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, mpsc};
+use webhook::{WebhookConfig, WebhookNotifier};
+
+/// The notifications queued for a notifier, beyond which the new ones are dropped.
+pub const QUEUE_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// A new agenda, if this node's member has the governance voting power.
+    AgendaNeedsVote,
+    BlockFinalized,
+    MemberAdded,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    pub chain_name: String,
+    pub kind: NotificationKind,
+    pub event: NodeEvent,
+    /// When the event was found, as a UNIX timestamp in milliseconds.
+    pub timestamp: Timestamp,
+}
+
+/// A backend of the notifications.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Describes the notifier for the logs (e.g., the URL of a webhook).
+    fn describe(&self) -> String;
+
+    /// Delivers the notification once; a failure is retried by `dispatch()`.
+    async fn notify(&self, notification: &Notification) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// The attempts of a delivery before it is given up.
+    pub max_attempts: u32,
+    /// The backoff after the first failed attempt, doubled after each of the others.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Returns the backoff after the given number of the failed attempts.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let backoff = self
+            .initial_backoff_ms
+            .saturating_mul(1 << failures.saturating_sub(1).min(32));
+        Duration::from_millis(backoff.min(self.max_backoff_ms))
+    }
+}
+
+/// Runs `program` with `args`, writing the notification JSON to its stdin.
+///
+/// It fails if the command exits with a non-zero status or doesn't finish in `timeout_ms`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandConfig {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "CommandConfig::default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl CommandConfig {
+    fn default_timeout_ms() -> u64 {
+        10_000
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackendConfig {
+    Webhook(WebhookConfig),
+    Command(CommandConfig),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotifierConfig {
+    #[serde(flatten)]
+    pub backend: BackendConfig,
+    pub events: Vec<NotificationKind>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+impl NotifierConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.backend {
+            BackendConfig::Webhook(webhook) => webhook.validate()?,
+            BackendConfig::Command(command) => {
+                if command.program.is_empty() || command.timeout_ms == 0 {
+                    return Err("the command has no program or a zero timeout".to_owned());
+                }
+            }
+        }
+        if self.events.is_empty() {
+            return Err("no event is configured".to_owned());
+        }
+        if self.retry.max_attempts == 0 || self.retry.initial_backoff_ms > self.retry.max_backoff_ms
+        {
+            return Err("invalid retries".to_owned());
+        }
+        Ok(())
+    }
+}
+
+pub struct CommandNotifier {
+    config: CommandConfig,
+}
+
+impl CommandNotifier {
+    pub fn new(config: CommandConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl Notifier for CommandNotifier {
+    fn describe(&self) -> String {
+        format!("command `{}`", self.config.program)
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        let mut child = tokio::process::Command::new(&self.config.program)
+            .args(&self.config.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let body = serde_json::to_vec(notification)?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let output = tokio::time::timeout(Duration::from_millis(self.config.timeout_ms), async {
+            stdin.write_all(&body).await?;
+            // Closes the stdin, so that the command sees the end of the input.
+            drop(stdin);
+            child.wait_with_output().await
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out"))??;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "{}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A notifier with the kinds of the notifications it takes.
+pub struct Subscription {
+    pub notifier: Arc<dyn Notifier>,
+    pub events: Vec<NotificationKind>,
+    pub retry: RetryConfig,
+}
+
+/// Creates the built-in notifiers of the configuration.
+pub fn from_config(configs: &[NotifierConfig]) -> Result<Vec<Subscription>> {
+    configs
+        .iter()
+        .map(|config| {
+            let notifier: Arc<dyn Notifier> = match &config.backend {
+                BackendConfig::Webhook(webhook) => Arc::new(WebhookNotifier::new(webhook.clone())?),
+                BackendConfig::Command(command) => Arc::new(CommandNotifier::new(command.clone())),
+            };
+            Ok(Subscription {
+                notifier,
+                events: config.events.clone(),
+                retry: config.retry.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Delivers the notification, retrying with the backoff.
+async fn deliver(
+    notifier: &dyn Notifier,
+    retry: &RetryConfig,
+    notification: &Notification,
+) -> Result<()> {
+    let mut failures = 0;
+    loop {
+        let error = match notifier.notify(notification).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        failures += 1;
+        if failures >= retry.max_attempts {
+            return Err(error);
+        }
+        log::debug!(
+            "failed to notify {} ({}); retrying",
+            notifier.describe(),
+            error
+        );
+        tokio::time::sleep(retry.backoff(failures)).await;
+    }
+}
+
+async fn serve_notifier(
+    notifier: Arc<dyn Notifier>,
+    retry: RetryConfig,
+    mut queue: mpsc::Receiver<Notification>,
+) {
+    while let Some(notification) = queue.recv().await {
+        if let Err(e) = deliver(&*notifier, &retry, &notification).await {
+            log::warn!("gave up notifying {}: {}", notifier.describe(), e);
+        }
+    }
+}
+
+/// Returns whether the member of the key has the governance voting power now.
+async fn is_voter<R: RawRepository>(config: &Config) -> Result<bool> {
+    let repo = DistributedRepository::new(R::open(&config.repository_directory).await?).await?;
+    Ok(repo
+        .get_reserved_state()
+        .await?
+        .governance_voting_powers()
+        .iter()
+        .any(|(member, power)| member.public_key == config.public_key && *power > 0))
+}
+
+/// Delivers the notifications of the events to the subscriptions indefinitely.
+pub async fn dispatch<R: RawRepository>(
+    config: Config,
+    bus: EventBus,
+    subscriptions: Vec<Subscription>,
+) -> Result<()> {
+    let mut events = bus.subscribe();
+    // The workers stop as the queues are dropped.
+    let queues: Vec<_> = subscriptions
+        .into_iter()
+        .map(|subscription| {
+            let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+            tokio::spawn(serve_notifier(
+                Arc::clone(&subscription.notifier),
+                subscription.retry.clone(),
+                receiver,
+            ));
+            (subscription, sender)
+        })
+        .collect();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                log::warn!("the notifications missed {} events", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        let kind = match &event {
+            NodeEvent::AgendaCreated { .. } => NotificationKind::AgendaNeedsVote,
+            NodeEvent::BlockFinalized { .. } => NotificationKind::BlockFinalized,
+            NodeEvent::MemberAdded { .. } => NotificationKind::MemberAdded,
+            _ => continue,
+        };
+        if !queues
+            .iter()
+            .any(|(subscription, _)| subscription.events.contains(&kind))
+            || (kind == NotificationKind::AgendaNeedsVote && !is_voter::<R>(&config).await?)
+        {
+            continue;
+        }
+        let notification = Notification {
+            chain_name: config.chain_name.clone(),
+            kind,
+            event,
+            timestamp: chrono::Utc::now().timestamp_millis() as Timestamp,
+        };
+        for (subscription, queue) in &queues {
+            if subscription.events.contains(&kind) && queue.try_send(notification.clone()).is_err()
+            {
+                log::warn!(
+                    "the queue of {} is full; dropped a notification",
+                    subscription.notifier.describe()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification() -> Notification {
+        Notification {
+            chain_name: "dao".to_owned(),
+            kind: NotificationKind::AgendaNeedsVote,
+            event: NodeEvent::Lagged { skipped: 1 },
+            timestamp: 7,
+        }
+    }
+
+    #[test]
+    fn notification_format() {
+        assert_eq!(
+            serde_json::to_string(&notification()).unwrap(),
+            r#"{"chain_name":"dao","kind":"agenda_needs_vote","event":{"type":"lagged","skipped":1},"timestamp":7}"#
+        );
+    }
+
+    #[test]
+    fn config() {
+        let config: NotifierConfig = toml::from_str(
+            r#"
+            type = "command"
+            program = "notify-matrix"
+            args = ["--room", "dao"]
+            events = ["block_finalized"]
+            [retry]
+            max_attempts = 3
+            initial_backoff_ms = 1000
+            max_backoff_ms = 5000
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        assert!(matches!(config.backend, BackendConfig::Command(_)));
+        let backoffs: Vec<_> = (1..6)
+            .map(|i| config.retry.backoff(i).as_millis())
+            .collect();
+        assert_eq!(backoffs, vec![1000, 2000, 4000, 5000, 5000]);
+
+        let config: NotifierConfig = toml::from_str(
+            r#"
+            type = "webhook"
+            url = "https://hooks.example.org/simperby"
+            events = []
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap_err();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn command() {
+        let path = std::env::temp_dir().join(format!("simperby-notify-{}", std::process::id()));
+        let notifier = CommandNotifier::new(CommandConfig {
+            program: "sh".to_owned(),
+            args: vec!["-c".to_owned(), format!("cat > {}", path.display())],
+            timeout_ms: 10_000,
+        });
+        notifier.notify(&notification()).await.unwrap();
+        let written: Notification = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(written, notification());
+        std::fs::remove_file(&path).unwrap();
+
+        let failing = CommandNotifier::new(CommandConfig {
+            program: "sh".to_owned(),
+            args: vec!["-c".to_owned(), "echo broken >&2; exit 3".to_owned()],
+            timeout_ms: 10_000,
+        });
+        let error = failing.notify(&notification()).await.unwrap_err();
+        assert!(error.to_string().contains("broken"), "{}", error);
+    }
+}
@@ -0,0 +1,116 @@
+//! The management of the known peers, persisted in the peer directory of the node
+//! (see `simperby_network::peer_discovery`).
+use super::*;
+use simperby_network::peer_discovery::{PeerDiscoveryImpl, StaticPeer};
+use simperby_network::{ChainId, PeerDiscovery};
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+use std::net::{SocketAddr, SocketAddrV4};
+
+/// The result of `ping()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PingResult {
+    pub public_key: String,
+    pub address: String,
+    pub round_trip_ms: u64,
+    /// The last finalized height that the peer reported, if any.
+    pub height: Option<BlockHeight>,
+    pub last_seen: Timestamp,
+}
+
+/// Returns the identity of the chain that the peers must be of.
+pub fn chain_id(reserved_state: &ReservedState) -> ChainId {
+    ChainId {
+        chain_name: reserved_state.genesis_info.chain_name.clone(),
+        genesis_hash: reserved_state.genesis_info.header.to_hash256(),
+    }
+}
+
+/// Parses a hex-encoded public key.
+pub fn parse_public_key(public_key: &str) -> Result<PublicKey> {
+    Ok(PublicKey::from_bytes(&hex::decode(public_key)?)?)
+}
+
+/// Resolves the discovery endpoint given as `host:port` or as a URL with them
+/// (e.g., `http://seed.example.org:9100`).
+pub async fn resolve(address: &str) -> Result<SocketAddrV4> {
+    let endpoint = match url::Url::parse(address) {
+        Ok(url) if url.has_host() => format!(
+            "{}:{}",
+            url.host_str().unwrap_or_default(),
+            url.port()
+                .ok_or_else(|| anyhow::anyhow!("{} has no port", address))?
+        ),
+        _ => address.to_owned(),
+    };
+    tokio::net::lookup_host(&endpoint)
+        .await
+        .map_err(|e| anyhow::anyhow!("failed to resolve {}: {}", address, e))?
+        .find_map(|address| match address {
+            SocketAddr::V4(address) => Some(address),
+            SocketAddr::V6(_) => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("{} has no IPv4 address", address))
+}
+
+/// Adds the peer to contact in every discovery round, until removed.
+pub async fn add(config: &Config, address: &str, public_key: PublicKey) -> Result<StaticPeer> {
+    let peer = StaticPeer {
+        public_key,
+        address: resolve(address).await?,
+    };
+    PeerDiscoveryImpl::add_peer(&config.peer_directory, peer.clone()).await?;
+    Ok(peer)
+}
+
+/// Removes the peer, returning whether it was known.
+pub async fn remove(config: &Config, public_key: &PublicKey) -> Result<bool> {
+    PeerDiscoveryImpl::remove_peer(&config.peer_directory, public_key).await
+}
+
+/// Pings the peer given by its public key (if known) or by its address.
+pub async fn ping<R: RawRepository>(config: &Config, target: &str) -> Result<PingResult> {
+    let address = match parse_public_key(target) {
+        Ok(public_key) => {
+            let known_peers = PeerDiscoveryImpl::read_known_peers(&config.peer_directory).await?;
+            let static_peers = PeerDiscoveryImpl::read_static_peers(&config.peer_directory).await?;
+            known_peers
+                .iter()
+                .find(|peer| peer.public_key == public_key)
+                .map(|peer| peer.address)
+                .or_else(|| {
+                    static_peers
+                        .iter()
+                        .find(|peer| peer.public_key == public_key)
+                        .map(|peer| peer.address)
+                })
+                .ok_or_else(|| anyhow::anyhow!("peer {} is not known", target))?
+        }
+        Err(_) => resolve(target).await?,
+    };
+    let reserved_state = DistributedRepository::new(R::open(&config.repository_directory).await?)
+        .await?
+        .get_reserved_state()
+        .await?;
+    let (record, round_trip) = PeerDiscoveryImpl::ping(address, &chain_id(&reserved_state)).await?;
+    Ok(PingResult {
+        public_key: hex::encode(&record.record.public_key),
+        address: address.to_string(),
+        round_trip_ms: round_trip.as_millis() as u64,
+        height: record.record.height,
+        last_seen: record.record.last_seen,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolve_endpoints() {
+        let expected: SocketAddrV4 = "127.0.0.1:9100".parse().unwrap();
+        assert_eq!(resolve("127.0.0.1:9100").await.unwrap(), expected);
+        assert_eq!(resolve("http://127.0.0.1:9100").await.unwrap(), expected);
+        assert!(resolve("http://127.0.0.1").await.is_err());
+    }
+}
@@ -0,0 +1,298 @@
+//! The read-only queries, answered from the storages of the node without joining the network.
+//!
+//! The results have stable field names, for the machine-readable output of the CLI;
+//! the commit hashes and the public keys are in hex.
+use super::*;
+use crate::review::{AgendaReview, Tally};
+use simperby_network::dms::MessageReader;
+use simperby_network::peer_discovery::{PeerDiscoveryImpl, LIVENESS_TIMEOUT};
+use simperby_network::primitives::MessageStore;
+use simperby_network::PeerDiscovery;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::{CommitKind, DistributedRepository};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainStatus {
+    pub chain_name: String,
+    pub protocol_version: String,
+    pub height: BlockHeight,
+    pub last_block_hash: Hash256,
+    pub last_block_timestamp: Timestamp,
+    pub validators: usize,
+    pub members: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgendaEntry {
+    pub commit: String,
+    pub hash: Hash256,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockEntry {
+    pub commit: String,
+    pub height: BlockHeight,
+    pub hash: Hash256,
+    pub author: String,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerEntry {
+    pub public_key: String,
+    pub address: String,
+    pub message: String,
+    pub recently_seen_timestamp: Timestamp,
+    /// Whether the peer was seen within `peer_discovery::LIVENESS_TIMEOUT`.
+    pub alive: bool,
+    /// The last finalized height that the peer reported, if any.
+    pub height: Option<BlockHeight>,
+    pub relay: Option<String>,
+    /// Whether the peer was added by the operator (see `peers::add()`).
+    pub pinned: bool,
+}
+
+/// A commit resolved by `show()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitEntry {
+    pub commit: String,
+    pub kind: CommitKind,
+    pub title: String,
+    pub author: Option<String>,
+    pub timestamp: Option<Timestamp>,
+    /// The height of a block.
+    pub height: Option<BlockHeight>,
+    /// The `(path, size)` of the files added or modified by the commit.
+    pub changed_files: Vec<(String, u64)>,
+    pub signers: Vec<String>,
+    pub finalized: bool,
+    /// The governance status of an agenda.
+    pub tally: Option<Tally>,
+}
+
+fn get_timestamp() -> Timestamp {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+    since_the_epoch.as_millis() as Timestamp
+}
+
+async fn open<R: RawRepository>(config: &Config) -> Result<DistributedRepository<R>> {
+    DistributedRepository::new(R::open(&config.repository_directory).await?).await
+}
+
+pub async fn status<R: RawRepository>(config: &Config) -> Result<ChainStatus> {
+    let repo = open::<R>(config).await?;
+    let header = repo.get_last_finalized_block_header().await?;
+    let reserved_state = repo.get_reserved_state().await?;
+    Ok(ChainStatus {
+        chain_name: reserved_state.genesis_info.chain_name.clone(),
+        protocol_version: reserved_state.version.clone(),
+        height: header.height,
+        last_block_hash: header.to_hash256(),
+        last_block_timestamp: header.timestamp,
+        validators: header.validator_set.len(),
+        members: reserved_state.members.len(),
+    })
+}
+
+pub async fn agendas<R: RawRepository>(config: &Config) -> Result<Vec<AgendaEntry>> {
+    Ok(open::<R>(config)
+        .await?
+        .get_agendas()
+        .await?
+        .into_iter()
+        .map(|(commit, hash)| AgendaEntry {
+            commit: hex::encode(commit.hash),
+            hash,
+        })
+        .collect())
+}
+
+/// Returns the finalized blocks from the latest, up to `limit` if given.
+pub async fn history<R: RawRepository>(
+    config: &Config,
+    limit: Option<usize>,
+) -> Result<Vec<BlockEntry>> {
+    Ok(open::<R>(config)
+        .await?
+        .get_finalized_blocks(limit)
+        .await?
+        .into_iter()
+        .map(|(commit, header)| BlockEntry {
+            commit: hex::encode(commit.hash),
+            height: header.height,
+            hash: header.to_hash256(),
+            author: hex::encode(&header.author),
+            timestamp: header.timestamp,
+        })
+        .collect())
+}
+
+/// Returns the known peers, followed by the added peers whose records are not known yet.
+pub async fn peers(config: &Config) -> Result<Vec<PeerEntry>> {
+    let known_peers = PeerDiscoveryImpl::read_known_peers(&config.peer_directory).await?;
+    let static_peers = PeerDiscoveryImpl::read_static_peers(&config.peer_directory).await?;
+    let now = get_timestamp();
+    let pinned = |public_key: &PublicKey| static_peers.iter().any(|x| &x.public_key == public_key);
+    let mut entries: Vec<_> = known_peers
+        .iter()
+        .map(|peer| PeerEntry {
+            public_key: hex::encode(&peer.public_key),
+            address: peer.address.to_string(),
+            message: peer.message.clone(),
+            recently_seen_timestamp: peer.recently_seen_timestamp,
+            alive: now.saturating_sub(peer.recently_seen_timestamp)
+                <= LIVENESS_TIMEOUT.as_millis() as Timestamp,
+            height: peer.height,
+            relay: peer.relay.as_ref().map(hex::encode),
+            pinned: pinned(&peer.public_key),
+        })
+        .collect();
+    entries.extend(
+        static_peers
+            .iter()
+            .filter(|x| {
+                !known_peers
+                    .iter()
+                    .any(|peer| peer.public_key == x.public_key)
+            })
+            .map(|x| PeerEntry {
+                public_key: hex::encode(&x.public_key),
+                address: x.address.to_string(),
+                message: String::new(),
+                recently_seen_timestamp: 0,
+                alive: false,
+                height: None,
+                relay: None,
+                pinned: true,
+            }),
+    );
+    Ok(entries)
+}
+
+/// Counts the votes in the governance DMS for the agenda.
+async fn tally<S: MessageStore>(
+    config: &Config,
+    reserved_state: &ReservedState,
+    agenda: &Agenda,
+) -> Result<Tally> {
+    let votes: Vec<_> = MessageReader::open(S::open(&config.governance_directory).await?)
+        .read_messages()
+        .await?
+        .iter()
+        .filter_map(|message| simperby_governance::message::decode(message, reserved_state).ok())
+        .collect();
+    Ok(Tally::count(reserved_state, &agenda.to_hash256(), &votes))
+}
+
+/// Reviews the agenda with the votes in the governance DMS (see `SimperbyApi::review_agenda()`).
+pub async fn review_agenda<S: MessageStore, R: RawRepository>(
+    config: &Config,
+    agenda_commit: CommitHash,
+) -> Result<AgendaReview> {
+    let repo = open::<R>(config).await?;
+    let agenda = repo.read_agenda(&agenda_commit).await?;
+    let diff = repo.show_commit(&agenda_commit).await?;
+    let reserved_state = repo.get_reserved_state().await?;
+    let superseded_by = repo
+        .get_amendments()
+        .await?
+        .into_iter()
+        .find(|(superseded, _)| *superseded == agenda_commit)
+        .map(|(_, amendment)| amendment);
+    drop(repo);
+    Ok(AgendaReview {
+        commit: agenda_commit,
+        tally: tally::<S>(config, &reserved_state, &agenda).await?,
+        agenda,
+        diff,
+        superseded_by,
+    })
+}
+
+/// Resolves the reference (a height, a branch, a tag, an agenda hash or a commit hash prefix;
+/// see `DistributedRepository::resolve()`) and inspects the commit.
+pub async fn show<S: MessageStore, R: RawRepository>(
+    config: &Config,
+    reference: &str,
+) -> Result<CommitEntry> {
+    let repo = open::<R>(config).await?;
+    let info = repo.inspect(&repo.resolve(reference).await?).await?;
+    let reserved_state = repo.get_reserved_state().await?;
+    drop(repo);
+    let (author, timestamp, height) = match &info.commit {
+        Some(Commit::Block(header)) => (
+            Some(&header.author),
+            Some(header.timestamp),
+            Some(header.height),
+        ),
+        Some(Commit::Agenda(agenda)) => (Some(&agenda.author), Some(agenda.timestamp), None),
+        Some(Commit::Transaction(transaction)) => {
+            (Some(&transaction.author), Some(transaction.timestamp), None)
+        }
+        _ => (None, None, None),
+    };
+    let tally = match &info.commit {
+        Some(Commit::Agenda(agenda)) => Some(tally::<S>(config, &reserved_state, agenda).await?),
+        _ => None,
+    };
+    Ok(CommitEntry {
+        commit: hex::encode(info.commit_hash.hash),
+        kind: info.kind,
+        title: info.title,
+        author: author.map(hex::encode),
+        timestamp,
+        height,
+        changed_files: info.changed_files,
+        signers: info.signers.iter().map(hex::encode).collect(),
+        finalized: info.finalized,
+        tally,
+    })
+}
+
+/// Resolves the reference to an agenda (see `DistributedRepository::resolve()`) and reads
+/// the review comments on it from the chat DMS (see `SimperbyApi::get_review_comments()`).
+pub async fn review_comments<S: MessageStore, R: RawRepository>(
+    config: &Config,
+    reference: &str,
+) -> Result<Vec<SignedReviewComment>> {
+    let repo = open::<R>(config).await?;
+    let agenda = repo.read_agenda(&repo.resolve(reference).await?).await?;
+    let reserved_state = repo.get_reserved_state().await?;
+    drop(repo);
+    let messages = MessageReader::open(S::open(&config.chat_directory).await?)
+        .read_messages()
+        .await?;
+    Ok(simperby_chat::review::read_comments(
+        &simperby_chat::message::read(&messages, &reserved_state),
+        &agenda.to_hash256(),
+    ))
+}
+
+/// Returns the per-line attribution of a reserved-state file as of the referenced commit.
+pub async fn blame<R: RawRepository>(
+    config: &Config,
+    path: &str,
+    reference: &str,
+) -> Result<Vec<simperby_repository::raw::BlameLine>> {
+    let repo = open::<R>(config).await?;
+    repo.blame_reserved_state(path, &repo.resolve(reference).await?)
+        .await
+}
+
+/// Exports the archived consensus votes of the heights in `from..=to`,
+/// with the equivocations found among them (see `Config::vote_archive_directory`).
+pub async fn votes(
+    config: &Config,
+    from: BlockHeight,
+    to: BlockHeight,
+) -> Result<Vec<simperby_consensus::archive::ArchivedHeight>> {
+    let directory = config
+        .vote_archive_directory
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("`vote_archive_directory` is not configured"))?;
+    simperby_consensus::archive::VoteArchive::open(directory)
+        .await?
+        .export(from, to)
+        .await
+}
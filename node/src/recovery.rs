@@ -0,0 +1,391 @@
+//! The recovery of a node whose finalized branch (`main`) got rewound or corrupted,
+//! e.g., by a disk failure or a mistake of the operator, without a manual re-clone.
+//!
+//! 1. `detect()` finds the problem locally: the repository is not intact, or its `main`
+//!    is below the height that this node has finalized before (the `Watermark`).
+//! 2. `recover()` clones the repository from each of the sources (the repository URLs
+//!    of the peers), verifying the history from the genesis, and requires a majority of them
+//!    to succeed and agree. A local `main` diverging from theirs is a problem too.
+//! 3. Then it moves the local repository aside as an archive, replaces it with the clone,
+//!    and verifies it again.
+use super::*;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+use std::fmt;
+
+/// The suffix of the watermark file, next to the repository directory.
+pub const WATERMARK_SUFFIX: &str = ".watermark.json";
+/// The suffix of the directory where the sources are cloned into.
+const RECOVERY_SUFFIX: &str = ".recovery";
+
+/// The highest finalized block that this node has seen on its `main`,
+/// kept outside of the repository so that it survives the repository.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Watermark {
+    pub genesis_hash: Hash256,
+    pub height: BlockHeight,
+    pub hash: Hash256,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Problem {
+    /// The repository can't be opened or is not intact.
+    Corrupted(String),
+    /// The `main` branch is below the watermark.
+    Rewound {
+        height: BlockHeight,
+        watermark: BlockHeight,
+    },
+    /// The block of `height` on the local `main` differs from that of the sources.
+    Diverged {
+        height: BlockHeight,
+        local: Hash256,
+        network: Hash256,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Corrupted(e) => write!(f, "the repository is corrupted: {}", e),
+            Self::Rewound { height, watermark } => write!(
+                f,
+                "the finalized branch is rewound to height {} from {}",
+                height, watermark
+            ),
+            Self::Diverged {
+                height,
+                local,
+                network,
+            } => write!(
+                f,
+                "the block of height {} is {}, but the network finalized {}",
+                height, local, network
+            ),
+        }
+    }
+}
+
+/// The result of `recover()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recovery {
+    /// The problem found; if none, nothing was changed.
+    pub problem: Option<Problem>,
+    /// The last finalized height of the repository after the recovery.
+    pub height: BlockHeight,
+    /// Where the local repository was moved to, if replaced.
+    pub archived: Option<String>,
+}
+
+fn get_timestamp() -> Timestamp {
+    let now = std::time::SystemTime::now();
+    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
+    since_the_epoch.as_millis() as Timestamp
+}
+
+fn watermark_path(config: &Config) -> String {
+    format!("{}{}", config.repository_directory, WATERMARK_SUFFIX)
+}
+
+pub async fn read_watermark(config: &Config) -> Result<Option<Watermark>> {
+    match tokio::fs::read_to_string(watermark_path(config)).await {
+        Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Raises the watermark to the given last finalized block, if higher.
+pub async fn update_watermark(
+    config: &Config,
+    genesis_hash: Hash256,
+    header: &BlockHeader,
+) -> Result<()> {
+    if let Some(watermark) = read_watermark(config).await? {
+        if watermark.height >= header.height {
+            return Ok(());
+        }
+    }
+    let watermark = Watermark {
+        genesis_hash,
+        height: header.height,
+        hash: header.to_hash256(),
+    };
+    let path = watermark_path(config);
+    let temporary = format!("{}.tmp", path);
+    tokio::fs::write(&temporary, serde_json::to_string_pretty(&watermark)?).await?;
+    tokio::fs::rename(&temporary, &path).await?;
+    Ok(())
+}
+
+/// Opens the repository and checks its integrity,
+/// returning the reserved state and the last finalized block.
+async fn open_intact<R: RawRepository>(directory: &str) -> Result<(ReservedState, BlockHeader)> {
+    let repo = DistributedRepository::new(R::open(directory).await?).await?;
+    let report = repo.check_integrity().await?;
+    if !report.is_ok() {
+        return Err(anyhow::anyhow!("not intact: {:?}", report));
+    }
+    Ok((
+        repo.get_reserved_state().await?,
+        repo.get_last_finalized_block_header().await?,
+    ))
+}
+
+/// Finds the problem of the local repository, without contacting the network.
+pub async fn detect<R: RawRepository>(config: &Config) -> Result<Option<Problem>> {
+    let header = match open_intact::<R>(&config.repository_directory).await {
+        Ok((_, header)) => header,
+        Err(e) => return Ok(Some(Problem::Corrupted(e.to_string()))),
+    };
+    if let Some(watermark) = read_watermark(config).await? {
+        if header.height < watermark.height {
+            return Ok(Some(Problem::Rewound {
+                height: header.height,
+                watermark: watermark.height,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// The hash of the finalized block of the height, among the blocks from the latest.
+fn hash_at(blocks: &[BlockHeader], height: BlockHeight) -> Option<Hash256> {
+    blocks
+        .iter()
+        .find(|header| header.height == height)
+        .map(|header| header.to_hash256())
+}
+
+/// Recovers the repository from the sources (the repository URLs of the peers)
+/// if a problem is found, or `force`d.
+///
+/// It fails without changing anything unless more than half of the sources
+/// serve a valid history of the same chain, agreeing with each other.
+pub async fn recover<R: RawRepository>(
+    config: &Config,
+    sources: &[String],
+    force: bool,
+) -> Result<Recovery> {
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("no source to recover from"));
+    }
+    let mut problem = detect::<R>(config).await?;
+    let watermark = read_watermark(config).await?;
+
+    let recovery_directory = format!("{}{}", config.repository_directory, RECOVERY_SUFFIX);
+    if tokio::fs::metadata(&recovery_directory).await.is_ok() {
+        tokio::fs::remove_dir_all(&recovery_directory).await?;
+    }
+    let mut clones = Vec::new();
+    for (i, url) in sources.iter().enumerate() {
+        let directory = format!("{}/{}", recovery_directory, i);
+        let blocks = match DistributedRepository::<R>::clone_from(&directory, url, None).await {
+            Ok(repo) => {
+                async {
+                    let genesis_hash = repo
+                        .get_reserved_state()
+                        .await?
+                        .genesis_info
+                        .header
+                        .to_hash256();
+                    let blocks: Vec<_> = repo
+                        .get_finalized_blocks(None)
+                        .await?
+                        .into_iter()
+                        .map(|(_, header)| header)
+                        .collect();
+                    Ok::<_, anyhow::Error>((genesis_hash, blocks))
+                }
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        match blocks {
+            Ok((genesis_hash, blocks)) if !blocks.is_empty() => {
+                clones.push((directory, genesis_hash, blocks))
+            }
+            Ok(_) => log::warn!("{} has no finalized block", url),
+            Err(e) => log::warn!("failed to fetch from {}: {}", url, e),
+        }
+    }
+    let result = async {
+        if clones.len() * 2 <= sources.len() {
+            return Err(anyhow::anyhow!(
+                "only {} of {} sources served a valid history",
+                clones.len(),
+                sources.len()
+            ));
+        }
+        // Every source is verified, so the highest one is the network's finality,
+        // as long as the others agree with it.
+        let (directory, genesis_hash, blocks) = clones
+            .iter()
+            .max_by_key(|(_, _, blocks)| blocks[0].height)
+            .expect("not empty");
+        for (other, other_genesis_hash, other_blocks) in &clones {
+            let last = &other_blocks[0];
+            if other_genesis_hash != genesis_hash
+                || hash_at(blocks, last.height) != Some(last.to_hash256())
+            {
+                return Err(anyhow::anyhow!(
+                    "the sources disagree at height {} ({} and {})",
+                    last.height,
+                    directory,
+                    other
+                ));
+            }
+        }
+        let network = &blocks[0];
+        if let Some(watermark) = &watermark {
+            if watermark.genesis_hash != *genesis_hash {
+                return Err(anyhow::anyhow!("the sources are of another chain"));
+            }
+            if watermark.height > network.height {
+                return Err(anyhow::anyhow!(
+                    "the sources are at height {}, below the watermark {}",
+                    network.height,
+                    watermark.height
+                ));
+            }
+            if hash_at(blocks, watermark.height) != Some(watermark.hash) {
+                problem = Some(Problem::Diverged {
+                    height: watermark.height,
+                    local: watermark.hash,
+                    network: hash_at(blocks, watermark.height).unwrap_or_else(Hash256::zero),
+                });
+            }
+        }
+        if problem.is_none() {
+            let (reserved_state, local) = open_intact::<R>(&config.repository_directory).await?;
+            let height = local.height.min(network.height);
+            let local_hash = if height == local.height {
+                Some(local.to_hash256())
+            } else {
+                DistributedRepository::new(R::open(&config.repository_directory).await?)
+                    .await?
+                    .get_finalized_blocks(None)
+                    .await?
+                    .into_iter()
+                    .find(|(_, header)| header.height == height)
+                    .map(|(_, header)| header.to_hash256())
+            };
+            let network_hash = hash_at(blocks, height);
+            if reserved_state.genesis_info.header.to_hash256() != *genesis_hash {
+                return Err(anyhow::anyhow!("the sources are of another chain"));
+            }
+            if local_hash != network_hash {
+                problem = Some(Problem::Diverged {
+                    height,
+                    local: local_hash.unwrap_or_else(Hash256::zero),
+                    network: network_hash.unwrap_or_else(Hash256::zero),
+                });
+            }
+        }
+        Ok::<_, anyhow::Error>((directory.clone(), network.clone()))
+    }
+    .await;
+    let (directory, network) = match result {
+        Ok(x) => x,
+        Err(e) => {
+            tokio::fs::remove_dir_all(&recovery_directory).await?;
+            return Err(e);
+        }
+    };
+    if problem.is_none() && !force {
+        tokio::fs::remove_dir_all(&recovery_directory).await?;
+        return Ok(Recovery {
+            problem,
+            height: network.height,
+            archived: None,
+        });
+    }
+    if let Some(problem) = &problem {
+        log::warn!("recovering from the problem: {}", problem);
+    }
+
+    let archived = if tokio::fs::metadata(&config.repository_directory)
+        .await
+        .is_ok()
+    {
+        let archived = format!(
+            "{}.archived-{}",
+            config.repository_directory,
+            get_timestamp()
+        );
+        tokio::fs::rename(&config.repository_directory, &archived).await?;
+        Some(archived)
+    } else {
+        None
+    };
+    tokio::fs::rename(&directory, &config.repository_directory).await?;
+    tokio::fs::remove_dir_all(&recovery_directory).await?;
+
+    let (reserved_state, header) = open_intact::<R>(&config.repository_directory)
+        .await
+        .map_err(|e| anyhow::anyhow!("the recovered repository is not valid: {}", e))?;
+    if header != network {
+        return Err(anyhow::anyhow!(
+            "the recovered repository is at {}, not {}",
+            header.to_hash256(),
+            network.to_hash256()
+        ));
+    }
+    update_watermark(
+        config,
+        reserved_state.genesis_info.header.to_hash256(),
+        &header,
+    )
+    .await?;
+    Ok(Recovery {
+        problem,
+        height: header.height,
+        archived,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn watermark() {
+        let directory =
+            std::env::temp_dir().join(format!("simperby-watermark-{}", get_timestamp()));
+        let mut config = config::tests::config();
+        config.repository_directory = directory.to_str().unwrap().to_owned();
+        assert_eq!(read_watermark(&config).await.unwrap(), None);
+
+        let genesis_hash = Hash256::hash("genesis");
+        let header = simperby_common::test_util::header;
+        update_watermark(&config, genesis_hash, &header(5))
+            .await
+            .unwrap();
+        update_watermark(&config, genesis_hash, &header(3))
+            .await
+            .unwrap();
+        assert_eq!(
+            read_watermark(&config).await.unwrap(),
+            Some(Watermark {
+                genesis_hash,
+                height: 5,
+                hash: header(5).to_hash256(),
+            })
+        );
+        tokio::fs::remove_file(watermark_path(&config))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            Problem::Rewound {
+                height: 3,
+                watermark: 5
+            }
+            .to_string(),
+            "the finalized branch is rewound to height 3 from 5"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+//! The reviews of agendas and blocks before voting and vetoing, for the interactive CLI flows.
+use super::*;
+use simperby_governance::Vote;
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tally {
+    /// The effective governance voting power that approved.
+    pub approving: VotingPower,
+    /// The effective governance voting power that vetoed.
+    pub vetoing: VotingPower,
+    pub total: VotingPower,
+    pub approved: bool,
+    pub vetoed: bool,
+}
+
+impl Tally {
+    /// Counts the votes for the agenda.
+    ///
+    /// A key that both voted for and vetoed the agenda is counted as neither.
+    pub fn count(reserved_state: &ReservedState, agenda_hash: &Hash256, votes: &[Vote]) -> Self {
+        let keys = |veto: bool| -> HashSet<PublicKey> {
+            votes
+                .iter()
+                .filter(|vote| vote.agenda_hash == *agenda_hash && vote.veto == veto)
+                .map(|vote| vote.voter.clone())
+                .collect()
+        };
+        let (all_voters, all_vetoers) = (keys(false), keys(true));
+        let voters: HashSet<_> = all_voters.difference(&all_vetoers).cloned().collect();
+        let vetoers: HashSet<_> = all_vetoers.difference(&all_voters).cloned().collect();
+        Self {
+            approving: simperby_governance::tally(reserved_state, &voters),
+            vetoing: simperby_governance::tally(reserved_state, &vetoers),
+            total: reserved_state
+                .governance_voting_powers()
+                .into_iter()
+                .map(|(_, power)| power)
+                .sum(),
+            approved: simperby_governance::is_approved(reserved_state, &voters),
+            vetoed: simperby_governance::is_vetoed(reserved_state, &vetoers),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgendaReview {
+    pub commit: CommitHash,
+    pub agenda: Agenda,
+    /// The diff of the agenda commit.
+    pub diff: String,
+    pub tally: Tally,
+    /// The amendment superseding the agenda, on which the votes are invalidated.
+    #[serde(default)]
+    pub superseded_by: Option<CommitHash>,
+}
+
+impl fmt::Display for AgendaReview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "agenda {}", hex::encode(self.commit.hash))?;
+        writeln!(f, "height: {}", self.agenda.height)?;
+        writeln!(f, "hash: {}", self.agenda.to_hash256())?;
+        if let Some(superseded) = &self.agenda.supersedes {
+            writeln!(f, "supersedes: {}", superseded)?;
+        }
+        if let Some(amendment) = &self.superseded_by {
+            writeln!(f, "superseded by: {}", hex::encode(amendment.hash))?;
+        }
+        writeln!(
+            f,
+            "tally: {} approving, {} vetoing, of {}{}",
+            self.tally.approving,
+            self.tally.vetoing,
+            self.tally.total,
+            if self.tally.approved {
+                " (approved)"
+            } else if self.tally.vetoed {
+                " (vetoed)"
+            } else {
+                ""
+            }
+        )?;
+        writeln!(f)?;
+        write!(f, "{}", self.diff)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockReview {
+    pub commit: CommitHash,
+    pub header: BlockHeader,
+    /// The diff of the block commit.
+    pub diff: String,
+}
+
+impl fmt::Display for BlockReview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "block {}", hex::encode(self.commit.hash))?;
+        writeln!(f, "height: {}", self.header.height)?;
+        writeln!(f, "author: {}", self.header.author)?;
+        writeln!(f, "hash: {}", self.header.to_hash256())?;
+        writeln!(f)?;
+        write!(f, "{}", self.diff)
+    }
+}
+
+/// Asks the yes-or-no question on the terminal; anything but `y` or `yes` is a no.
+pub async fn confirm(question: &str) -> Result<bool> {
+    let question = question.to_owned();
+    tokio::task::spawn_blocking(move || {
+        use std::io::Write;
+        print!("{} [y/N] ", question);
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+    })
+    .await?
+}
+
+/// Shows the review of the agenda and votes for it once confirmed (or if `yes`),
+/// returning whether it voted.
+pub async fn vote(
+    node: &(dyn SimperbyApi + Sync),
+    agenda_commit: CommitHash,
+    yes: bool,
+) -> Result<bool> {
+    println!("{}", node.review_agenda(agenda_commit).await?);
+    if !yes && !confirm("Vote for this agenda?").await? {
+        return Ok(false);
+    }
+    node.vote(agenda_commit).await?;
+    Ok(true)
+}
+
+/// Shows the review of the block and vetoes it once confirmed (or if `yes`),
+/// returning whether it vetoed. Without a block, it vetoes the current round.
+pub async fn veto(
+    node: &(dyn SimperbyApi + Sync),
+    block_commit: Option<CommitHash>,
+    yes: bool,
+) -> Result<bool> {
+    let question = match block_commit {
+        Some(block_commit) => {
+            println!("{}", node.review_block(block_commit).await?);
+            "Veto this block?"
+        }
+        None => "Veto the current round?",
+    };
+    if !yes && !confirm(question).await? {
+        return Ok(false);
+    }
+    match block_commit {
+        Some(block_commit) => node.veto_block(block_commit).await?,
+        None => node.veto_round().await?,
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_common::test_util::genesis;
+
+    #[test]
+    fn count() {
+        let keys: Vec<_> = ["a", "b", "c", "d"]
+            .iter()
+            .map(|x| generate_keypair(x).0)
+            .collect();
+        let reserved_state = genesis(&["a", "b", "c", "d"]);
+        let agenda_hash = Hash256::hash("agenda");
+        let vote = |i: usize, veto: bool| Vote {
+            agenda_hash,
+            voter: keys[i].clone(),
+            signature: Signature::sign(agenda_hash, &generate_keypair("x").1).unwrap(),
+            veto,
+        };
+        let votes = vec![vote(0, false), vote(1, false), vote(1, true), vote(2, true)];
+        let tally = Tally::count(&reserved_state, &agenda_hash, &votes);
+        assert_eq!(
+            tally,
+            Tally {
+                approving: 1,
+                vetoing: 1,
+                total: 4,
+                approved: false,
+                vetoed: false,
+            }
+        );
+    }
+}
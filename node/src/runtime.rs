@@ -0,0 +1,206 @@
+//! The supervision of the long-running tasks of the node.
+//!
+//! Each task is restarted with an exponential backoff when it fails or panics,
+//! until the shutdown, which is triggered by `SIGTERM` (or Ctrl-C) in `SimperbyApi::run()`.
+//! On the shutdown the tasks are notified through `Supervisor::shutdown_signal()`
+//! and given `RestartPolicy::shutdown_timeout` to finish before they are aborted.
+use super::*;
+use futures::future::BoxFuture;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestartPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Gives up a task after this many restarts; if none, restarts it forever.
+    pub max_restarts: Option<usize>,
+    /// How long the shutdown waits for each task before aborting it.
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            max_restarts: None,
+            shutdown_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+pub struct Supervisor {
+    policy: RestartPolicy,
+    shutdown: watch::Sender<bool>,
+    tasks: Vec<(String, JoinHandle<()>)>,
+}
+
+impl Supervisor {
+    pub fn new(policy: RestartPolicy) -> Self {
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            policy,
+            shutdown,
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Returns a receiver that turns `true` when the shutdown begins,
+    /// for the tasks that can finish gracefully.
+    pub fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    /// Spawns a supervised task, which is created by `factory` on every (re)start.
+    ///
+    /// A task that returns `Ok(())` is regarded as done and is not restarted.
+    pub fn spawn(
+        &mut self,
+        name: &str,
+        factory: impl Fn() -> BoxFuture<'static, Result<()>> + Send + Sync + 'static,
+    ) {
+        let name_ = name.to_owned();
+        let policy = self.policy.clone();
+        let mut shutdown = self.shutdown.subscribe();
+        let span = tracing::info_span!("task", name);
+        let handle = tokio::spawn(
+            async move {
+                let name = name_;
+                let mut restarts = 0;
+                let mut backoff = policy.initial_backoff;
+                loop {
+                    let mut task = tokio::spawn(factory().in_current_span());
+                    let result = tokio::select! {
+                        result = &mut task => result,
+                        _ = shutdown.changed() => {
+                            if tokio::time::timeout(policy.shutdown_timeout, &mut task)
+                                .await
+                                .is_err()
+                            {
+                                log::warn!("aborted the task {} on the shutdown", name);
+                                task.abort();
+                            }
+                            return;
+                        }
+                    };
+                    match result {
+                        Ok(Ok(())) => {
+                            log::info!("the task {} is done", name);
+                            return;
+                        }
+                        Ok(Err(e)) => log::error!("the task {} failed: {}", name, e),
+                        Err(e) if e.is_panic() => log::error!("the task {} panicked", name),
+                        Err(_) => return,
+                    }
+                    restarts += 1;
+                    if policy.max_restarts.map_or(false, |max| restarts > max) {
+                        log::error!("gave up the task {} after {} restarts", name, restarts - 1);
+                        return;
+                    }
+                    tokio::select! {
+                        _ = tokio::time::sleep(backoff) => (),
+                        _ = shutdown.changed() => return,
+                    }
+                    log::info!("restarting the task {} ({})", name, restarts);
+                    backoff = std::cmp::min(backoff * 2, policy.max_backoff);
+                }
+            }
+            .instrument(span),
+        );
+        self.tasks.push((name.to_owned(), handle));
+    }
+
+    /// Shuts down all the tasks, waiting for them to finish.
+    pub async fn shutdown(self) {
+        // It fails only if no task is running, which is fine.
+        let _ = self.shutdown.send(true);
+        for (name, handle) in self.tasks {
+            if let Err(e) = handle.await {
+                log::warn!(
+                    "the supervision of the task {} ended abnormally: {}",
+                    name,
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Waits for `SIGTERM` or Ctrl-C.
+pub async fn wait_for_termination() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            _ = sigterm.recv() => (),
+            result = tokio::signal::ctrl_c() => result?,
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    log::info!("shutting down");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn policy() -> RestartPolicy {
+        RestartPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+            max_restarts: None,
+            shutdown_timeout: Duration::from_millis(100),
+        }
+    }
+
+    #[tokio::test]
+    async fn restart_on_panic() {
+        let mut supervisor = Supervisor::new(policy());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs_ = Arc::clone(&runs);
+        supervisor.spawn("flaky", move || {
+            let runs = Arc::clone(&runs_);
+            Box::pin(async move {
+                match runs.fetch_add(1, Ordering::SeqCst) {
+                    0 => panic!("panicked"),
+                    1 => Err(anyhow::anyhow!("failed")),
+                    _ => Ok(()),
+                }
+            })
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        supervisor.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn graceful_shutdown() {
+        let mut supervisor = Supervisor::new(policy());
+        let finished = Arc::new(AtomicUsize::new(0));
+        let finished_ = Arc::clone(&finished);
+        let signal = supervisor.shutdown_signal();
+        supervisor.spawn("graceful", move || {
+            let finished = Arc::clone(&finished_);
+            let mut signal = signal.clone();
+            Box::pin(async move {
+                signal.changed().await?;
+                finished.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        });
+        supervisor.spawn("stuck", || {
+            Box::pin(futures::future::pending::<Result<()>>())
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        supervisor.shutdown().await;
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,759 @@
+//! The settlement of the finalized blocks on an EVM chain.
+//!
+//! The designated members relay the finalized block headers, with their finalization proofs,
+//! to a contract that follows the validator set of the chain (see `simperby_light_client::export`).
+//! A contract holding assets (e.g., a treasury) can then act only on what the Simperby
+//! governance has finalized.
+//!
+//! A relay is a transaction calling `submitHeader(bytes,bytes)`, or `submitAggregateHeader(bytes,bytes)`
+//! for an `AggregateFinalizationProof`, with the canonical encodings of the header and the proof.
+//! The member keys are Ed25519 and can't sign for an EVM account, so the transactions are signed
+//! by an external signer holding the key of `SettlementConfig::sender`
+//! (e.g., Clef or Web3Signer, through `eth_signTransaction`).
+//!
+//! The heights are assigned to the relayers in turn; a header changing the validator set
+//! is relayed by all of them, since the contract can't follow the chain without it.
+//!
+//! The client of the EVM chain and the task of the node need the `settlement` feature.
+use super::*;
+use anyhow::anyhow;
+use sha3::{Digest, Keccak256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+pub const SUBMIT_HEADER: &str = "submitHeader(bytes,bytes)";
+pub const SUBMIT_AGGREGATE_HEADER: &str = "submitAggregateHeader(bytes,bytes)";
+
+/// An address of an EVM account, written as `0x`-prefixed hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Address(pub [u8; 20]);
+
+impl FromStr for Address {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = hex::decode(s.strip_prefix("0x").unwrap_or(s))?;
+        Ok(Self(bytes.try_into().map_err(|_| {
+            anyhow!("an address must be 20 bytes: `{}`", s)
+        })?))
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Address> for String {
+    fn from(address: Address) -> Self {
+        address.to_string()
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettlementConfig {
+    /// The JSON-RPC endpoint of the EVM chain.
+    pub rpc_url: String,
+    /// The JSON-RPC endpoint of the signer holding the key of `sender`;
+    /// if none, `rpc_url` signs with its own account (`eth_sendTransaction`).
+    #[serde(default)]
+    pub signer_url: Option<String>,
+    pub chain_id: u64,
+    pub contract: Address,
+    /// The account paying for the relays.
+    pub sender: Address,
+    /// The members relaying the headers, in turn.
+    pub relayers: Vec<PublicKey>,
+    /// The depth of the EVM blocks after which a relay is final.
+    #[serde(default = "default_confirmations")]
+    pub confirmations: u64,
+    #[serde(default = "default_gas_limit")]
+    pub gas_limit: u64,
+    /// The EVM blocks to wait for a relay to be included
+    /// before replacing it with a higher gas price.
+    #[serde(default = "default_resubmit_after_blocks")]
+    pub resubmit_after_blocks: u64,
+}
+
+fn default_confirmations() -> u64 {
+    12
+}
+
+fn default_gas_limit() -> u64 {
+    1_000_000
+}
+
+fn default_resubmit_after_blocks() -> u64 {
+    20
+}
+
+impl SettlementConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.relayers.is_empty() {
+            return Err(anyhow!("no relayer is designated"));
+        }
+        if self.confirmations == 0 || self.resubmit_after_blocks == 0 {
+            return Err(anyhow!(
+                "`confirmations` and `resubmit_after_blocks` must be positive"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The first four bytes of the Keccak-256 hash of the function signature.
+pub fn selector(signature: &str) -> [u8; 4] {
+    let hash = Keccak256::digest(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn word(value: usize) -> [u8; 32] {
+    let mut word = [0; 32];
+    word[24..].copy_from_slice(&(value as u64).to_be_bytes());
+    word
+}
+
+/// Encodes a call of a function taking only `bytes` arguments, by the Solidity ABI.
+pub fn encode_call(signature: &str, arguments: &[&[u8]]) -> Vec<u8> {
+    let mut head = selector(signature).to_vec();
+    let mut tail = Vec::new();
+    for argument in arguments {
+        head.extend(word(32 * arguments.len() + tail.len()));
+        tail.extend(word(argument.len()));
+        tail.extend(*argument);
+        tail.resize((tail.len() + 31) / 32 * 32, 0);
+    }
+    head.extend(tail);
+    head
+}
+
+/// The proof accompanying a relayed header.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RelayProof {
+    Signatures(FinalizationProof),
+    Aggregate(AggregateFinalizationProof),
+}
+
+impl RelayProof {
+    /// Encodes the call of the contract relaying the header.
+    pub fn encode_call(&self, header: &BlockHeader) -> Result<Vec<u8>> {
+        let header = canonical::to_vec(header)?;
+        let (signature, proof) = match self {
+            Self::Signatures(proof) => (SUBMIT_HEADER, canonical::to_vec(proof)?),
+            Self::Aggregate(proof) => (SUBMIT_AGGREGATE_HEADER, canonical::to_vec(proof)?),
+        };
+        Ok(encode_call(signature, &[&header, &proof]))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionRequest {
+    pub from: Address,
+    pub to: Address,
+    pub nonce: u64,
+    pub gas: u64,
+    pub gas_price: u128,
+    pub data: Vec<u8>,
+    pub chain_id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    pub block_number: u64,
+    /// Whether the transaction succeeded, not reverted.
+    pub success: bool,
+}
+
+/// A `0x`-prefixed hex hash of an EVM transaction.
+pub type TransactionHash = String;
+
+/// The EVM chain, as far as the relayer needs.
+#[async_trait]
+pub trait EvmRpc: Send + Sync {
+    async fn block_number(&self) -> Result<u64>;
+    async fn gas_price(&self) -> Result<u128>;
+    /// Returns the next nonce of the account, counting the pending transactions.
+    async fn transaction_count(&self, address: &Address) -> Result<u64>;
+    /// Signs and broadcasts the transaction.
+    async fn send_transaction(&self, transaction: &TransactionRequest) -> Result<TransactionHash>;
+    async fn transaction_receipt(&self, hash: &TransactionHash) -> Result<Option<Receipt>>;
+}
+
+/// A relay not final yet.
+#[derive(Debug, Clone)]
+pub struct Submission {
+    pub transaction: TransactionRequest,
+    /// The hashes of the transaction and its replacements; any of them may be included.
+    pub hashes: Vec<TransactionHash>,
+    /// The EVM block when it was last sent.
+    pub sent_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayOutcome {
+    Confirmed {
+        height: BlockHeight,
+        hash: TransactionHash,
+    },
+    /// Rejected by the contract (e.g., it had been relayed by another member).
+    Reverted {
+        height: BlockHeight,
+        hash: TransactionHash,
+    },
+}
+
+/// Relays the headers, managing the nonces of `SettlementConfig::sender`
+/// and tracking the relays until they are final.
+pub struct Relayer<C: EvmRpc> {
+    config: SettlementConfig,
+    rpc: C,
+    public_key: PublicKey,
+    next_nonce: Option<u64>,
+    pending: BTreeMap<BlockHeight, Submission>,
+}
+
+impl<C: EvmRpc> Relayer<C> {
+    pub fn new(config: SettlementConfig, rpc: C, public_key: PublicKey) -> Self {
+        Self {
+            config,
+            rpc,
+            public_key,
+            next_nonce: None,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Returns whether this member relays the header, which is the one following `previous`.
+    pub fn is_designated(&self, previous: &BlockHeader, header: &BlockHeader) -> bool {
+        let relayers = &self.config.relayers;
+        if !relayers.contains(&self.public_key) {
+            return false;
+        }
+        header.validator_set != previous.validator_set
+            || relayers[header.height as usize % relayers.len()] == self.public_key
+    }
+
+    pub fn pending(&self) -> &BTreeMap<BlockHeight, Submission> {
+        &self.pending
+    }
+
+    /// Sends the relay of the header, returning the hash of the transaction.
+    pub async fn relay(
+        &mut self,
+        header: &BlockHeader,
+        proof: &RelayProof,
+    ) -> Result<TransactionHash> {
+        if self.pending.contains_key(&header.height) {
+            return Err(anyhow!("height {} is being relayed already", header.height));
+        }
+        let nonce = match self.next_nonce {
+            Some(nonce) => nonce,
+            None => self.rpc.transaction_count(&self.config.sender).await?,
+        };
+        let transaction = TransactionRequest {
+            from: self.config.sender,
+            to: self.config.contract,
+            nonce,
+            gas: self.config.gas_limit,
+            gas_price: self.rpc.gas_price().await?,
+            data: proof.encode_call(header)?,
+            chain_id: self.config.chain_id,
+        };
+        let sent_at = self.rpc.block_number().await?;
+        let hash = match self.rpc.send_transaction(&transaction).await {
+            Ok(hash) => hash,
+            Err(e) => {
+                // The nonce may have been taken; it's read again from the chain.
+                self.next_nonce = None;
+                return Err(e);
+            }
+        };
+        self.next_nonce = Some(nonce + 1);
+        self.pending.insert(
+            header.height,
+            Submission {
+                transaction,
+                hashes: vec![hash.clone()],
+                sent_at,
+            },
+        );
+        Ok(hash)
+    }
+
+    /// Checks the pending relays, replacing those stuck with a higher gas price,
+    /// and returns those that became final.
+    pub async fn poll(&mut self) -> Result<Vec<RelayOutcome>> {
+        let current = self.rpc.block_number().await?;
+        let mut outcomes = Vec::new();
+        for (height, submission) in self.pending.iter_mut() {
+            let mut included = None;
+            for hash in &submission.hashes {
+                if let Some(receipt) = self.rpc.transaction_receipt(hash).await? {
+                    included = Some((hash.clone(), receipt));
+                    break;
+                }
+            }
+            match included {
+                Some((hash, receipt))
+                    if current + 1 >= receipt.block_number + self.config.confirmations =>
+                {
+                    outcomes.push(if receipt.success {
+                        RelayOutcome::Confirmed {
+                            height: *height,
+                            hash,
+                        }
+                    } else {
+                        RelayOutcome::Reverted {
+                            height: *height,
+                            hash,
+                        }
+                    });
+                }
+                Some(_) => (),
+                None if current >= submission.sent_at + self.config.resubmit_after_blocks => {
+                    // Replaces it with the same nonce; nodes require a bump of at least 10%.
+                    let price = submission.transaction.gas_price;
+                    submission.transaction.gas_price =
+                        std::cmp::max(self.rpc.gas_price().await?, price + price / 8 + 1);
+                    let hash = self.rpc.send_transaction(&submission.transaction).await?;
+                    log::info!(
+                        "replaced the relay of height {} with {} (nonce {})",
+                        height,
+                        hash,
+                        submission.transaction.nonce
+                    );
+                    submission.hashes.push(hash);
+                    submission.sent_at = current;
+                }
+                None => (),
+            }
+        }
+        for outcome in &outcomes {
+            let (RelayOutcome::Confirmed { height, .. } | RelayOutcome::Reverted { height, .. }) =
+                outcome;
+            self.pending.remove(height);
+        }
+        Ok(outcomes)
+    }
+}
+
+/// The `EvmRpc` over the JSON-RPC of an Ethereum node, and optionally of an external signer.
+#[cfg(feature = "settlement")]
+pub struct JsonRpcClient {
+    client: reqwest::Client,
+    rpc_url: String,
+    signer_url: Option<String>,
+}
+
+#[cfg(feature = "settlement")]
+impl JsonRpcClient {
+    pub fn new(config: &SettlementConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            rpc_url: config.rpc_url.clone(),
+            signer_url: config.signer_url.clone(),
+        }
+    }
+
+    async fn call(
+        &self,
+        url: &str,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let mut response: serde_json::Value = self
+            .client
+            .post(url)
+            .json(&serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": method,
+                "params": params,
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("`{}` failed: {}", method, error));
+        }
+        Ok(response["result"].take())
+    }
+
+    async fn quantity(&self, method: &str, params: serde_json::Value) -> Result<u128> {
+        let result = self.call(&self.rpc_url, method, params).await?;
+        parse_quantity(&result)
+    }
+}
+
+#[cfg(feature = "settlement")]
+fn parse_quantity(value: &serde_json::Value) -> Result<u128> {
+    let text = value
+        .as_str()
+        .ok_or_else(|| anyhow!("expected a quantity, got {}", value))?;
+    Ok(u128::from_str_radix(
+        text.strip_prefix("0x").unwrap_or(text),
+        16,
+    )?)
+}
+
+#[cfg(feature = "settlement")]
+#[async_trait]
+impl EvmRpc for JsonRpcClient {
+    async fn block_number(&self) -> Result<u64> {
+        Ok(self
+            .quantity("eth_blockNumber", serde_json::json!([]))
+            .await? as u64)
+    }
+
+    async fn gas_price(&self) -> Result<u128> {
+        self.quantity("eth_gasPrice", serde_json::json!([])).await
+    }
+
+    async fn transaction_count(&self, address: &Address) -> Result<u64> {
+        Ok(self
+            .quantity(
+                "eth_getTransactionCount",
+                serde_json::json!([address.to_string(), "pending"]),
+            )
+            .await? as u64)
+    }
+
+    async fn send_transaction(&self, transaction: &TransactionRequest) -> Result<TransactionHash> {
+        let request = serde_json::json!([{
+            "from": transaction.from.to_string(),
+            "to": transaction.to.to_string(),
+            "nonce": format!("{:#x}", transaction.nonce),
+            "gas": format!("{:#x}", transaction.gas),
+            "gasPrice": format!("{:#x}", transaction.gas_price),
+            "data": format!("0x{}", hex::encode(&transaction.data)),
+            "chainId": format!("{:#x}", transaction.chain_id),
+        }]);
+        let hash = match &self.signer_url {
+            Some(signer_url) => {
+                let signed = self
+                    .call(signer_url, "eth_signTransaction", request)
+                    .await?;
+                // Clef and Geth return `{ raw, tx }`, the others the raw transaction only.
+                let raw = signed.get("raw").unwrap_or(&signed).clone();
+                self.call(
+                    &self.rpc_url,
+                    "eth_sendRawTransaction",
+                    serde_json::json!([raw]),
+                )
+                .await?
+            }
+            None => {
+                self.call(&self.rpc_url, "eth_sendTransaction", request)
+                    .await?
+            }
+        };
+        hash.as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("expected a transaction hash, got {}", hash))
+    }
+
+    async fn transaction_receipt(&self, hash: &TransactionHash) -> Result<Option<Receipt>> {
+        let receipt = self
+            .call(
+                &self.rpc_url,
+                "eth_getTransactionReceipt",
+                serde_json::json!([hash]),
+            )
+            .await?;
+        if receipt.is_null() || receipt["blockNumber"].is_null() {
+            return Ok(None);
+        }
+        Ok(Some(Receipt {
+            block_number: parse_quantity(&receipt["blockNumber"])? as u64,
+            success: parse_quantity(&receipt["status"])? == 1,
+        }))
+    }
+}
+
+/// Relays the finalized blocks indefinitely, from the one finalized after it starts.
+///
+/// The proof of a block is the one carried by the next block,
+/// so a block is relayed when the next one is finalized.
+#[cfg(feature = "settlement")]
+pub async fn run<R: RawRepository>(
+    config: SettlementConfig,
+    repository_directory: String,
+    public_key: PublicKey,
+    bus: events::EventBus,
+) -> Result<()> {
+    use events::NodeEvent;
+    use simperby_repository::raw::RawRepository;
+    use simperby_repository::DistributedRepository;
+    use tokio::sync::broadcast::error::RecvError;
+
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+    let mut events = bus.subscribe();
+    let mut relayer = Relayer::new(config.clone(), JsonRpcClient::new(&config), public_key);
+    let mut last_relayed = DistributedRepository::new(R::open(&repository_directory).await?)
+        .await?
+        .get_last_finalized_block_header()
+        .await?
+        .height
+        .saturating_sub(1);
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let height = match event {
+                    Ok(NodeEvent::BlockFinalized { height, .. }) => height,
+                    Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                if height <= last_relayed + 1 {
+                    continue;
+                }
+                let repo = DistributedRepository::new(R::open(&repository_directory).await?).await?;
+                let mut blocks = repo
+                    .get_finalized_blocks(Some((height - last_relayed + 1) as usize))
+                    .await?;
+                drop(repo);
+                blocks.reverse();
+                for window in blocks.windows(3) {
+                    let (previous, header, next) = (&window[0].1, &window[1].1, &window[2].1);
+                    if header.height <= last_relayed || !relayer.is_designated(previous, header) {
+                        continue;
+                    }
+                    let proof = RelayProof::Signatures(next.prev_block_finalization_proof.clone());
+                    match relayer.relay(header, &proof).await {
+                        Ok(hash) => log::info!("relaying height {} with {}", header.height, hash),
+                        Err(e) => log::warn!("failed to relay height {}: {}", header.height, e),
+                    }
+                }
+                last_relayed = height - 1;
+            }
+            _ = poll.tick() => {
+                match relayer.poll().await {
+                    Ok(outcomes) => {
+                        for outcome in outcomes {
+                            match outcome {
+                                RelayOutcome::Confirmed { height, hash } => {
+                                    log::info!("settled height {} with {}", height, hash)
+                                }
+                                RelayOutcome::Reverted { height, hash } => {
+                                    log::warn!("the relay of height {} reverted: {}", height, hash)
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => log::warn!("failed to check the relays: {}", e),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Chain {
+        block_number: u64,
+        gas_price: u128,
+        nonce: u64,
+        sent: Vec<TransactionRequest>,
+        receipts: BTreeMap<TransactionHash, Receipt>,
+    }
+
+    #[derive(Clone, Default)]
+    struct MockRpc(Arc<Mutex<Chain>>);
+
+    #[async_trait]
+    impl EvmRpc for MockRpc {
+        async fn block_number(&self) -> Result<u64> {
+            Ok(self.0.lock().unwrap().block_number)
+        }
+
+        async fn gas_price(&self) -> Result<u128> {
+            Ok(self.0.lock().unwrap().gas_price)
+        }
+
+        async fn transaction_count(&self, _address: &Address) -> Result<u64> {
+            Ok(self.0.lock().unwrap().nonce)
+        }
+
+        async fn send_transaction(
+            &self,
+            transaction: &TransactionRequest,
+        ) -> Result<TransactionHash> {
+            let mut chain = self.0.lock().unwrap();
+            chain.nonce = std::cmp::max(chain.nonce, transaction.nonce + 1);
+            chain.sent.push(transaction.clone());
+            Ok(format!("0x{:02x}", chain.sent.len()))
+        }
+
+        async fn transaction_receipt(&self, hash: &TransactionHash) -> Result<Option<Receipt>> {
+            Ok(self.0.lock().unwrap().receipts.get(hash).cloned())
+        }
+    }
+
+    fn config(relayers: Vec<PublicKey>) -> SettlementConfig {
+        SettlementConfig {
+            rpc_url: "http://localhost:8545".to_owned(),
+            signer_url: None,
+            chain_id: 1,
+            contract: "0x00000000000000000000000000000000000000aa"
+                .parse()
+                .unwrap(),
+            sender: "0x00000000000000000000000000000000000000bb"
+                .parse()
+                .unwrap(),
+            relayers,
+            confirmations: 3,
+            gas_limit: default_gas_limit(),
+            resubmit_after_blocks: 5,
+        }
+    }
+
+    fn header(height: BlockHeight, validator_set: Vec<(PublicKey, VotingPower)>) -> BlockHeader {
+        BlockHeader {
+            author: generate_keypair("author").0,
+            prev_block_finalization_proof: Vec::new(),
+            previous_hash: Hash256::zero(),
+            height,
+            timestamp: 0,
+            commit_hash: Hash256::zero(),
+            tx_merkle_root: Hash256::zero(),
+            chat_merkle_root: Hash256::zero(),
+            repository_merkle_root: Hash256::zero(),
+            validator_set,
+            version: "0.0.0".to_owned(),
+        }
+    }
+
+    #[test]
+    fn abi() {
+        // The selector of ERC-20 `transfer`, as widely known.
+        assert_eq!(
+            selector("transfer(address,uint256)"),
+            [0xa9, 0x05, 0x9c, 0xbb]
+        );
+        let data = encode_call(SUBMIT_HEADER, &[b"abc", &[7; 33]]);
+        assert_eq!(data.len(), 4 + 32 * 2 + (32 + 32) + (32 + 64));
+        assert_eq!(&data[4..36], &word(64));
+        assert_eq!(&data[36..68], &word(128));
+        assert_eq!(&data[68..100], &word(3));
+        assert_eq!(&data[100..103], b"abc");
+        assert_eq!(&data[132..164], &word(33));
+
+        let address: Address = "0x00000000000000000000000000000000000000AA"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            address.to_string(),
+            "0x00000000000000000000000000000000000000aa"
+        );
+        assert!("0x1234".parse::<Address>().is_err());
+    }
+
+    #[test]
+    fn designation() {
+        let (a, b) = (generate_keypair("a").0, generate_keypair("b").0);
+        let relayer_a = Relayer::new(config(vec![a.clone(), b.clone()]), MockRpc::default(), a);
+        let relayer_c = Relayer::new(
+            config(vec![b.clone()]),
+            MockRpc::default(),
+            generate_keypair("c").0,
+        );
+        let set = vec![(b.clone(), 1)];
+        assert!(relayer_a.is_designated(&header(1, set.clone()), &header(2, set.clone())));
+        assert!(!relayer_a.is_designated(&header(2, set.clone()), &header(3, set.clone())));
+        // A change of the validator set is relayed by all.
+        assert!(relayer_a.is_designated(&header(2, set.clone()), &header(3, Vec::new())));
+        assert!(!relayer_c.is_designated(&header(2, set), &header(3, Vec::new())));
+    }
+
+    #[tokio::test]
+    async fn nonces_and_confirmations() {
+        let rpc = MockRpc::default();
+        {
+            let mut chain = rpc.0.lock().unwrap();
+            chain.nonce = 7;
+            chain.gas_price = 100;
+        }
+        let mut relayer = Relayer::new(
+            config(vec![generate_keypair("a").0]),
+            rpc.clone(),
+            generate_keypair("a").0,
+        );
+        let proof = RelayProof::Signatures(Vec::new());
+        let first = relayer.relay(&header(1, Vec::new()), &proof).await.unwrap();
+        let second = relayer.relay(&header(2, Vec::new()), &proof).await.unwrap();
+        relayer
+            .relay(&header(2, Vec::new()), &proof)
+            .await
+            .unwrap_err();
+        let nonces: Vec<_> = rpc.0.lock().unwrap().sent.iter().map(|t| t.nonce).collect();
+        assert_eq!(nonces, vec![7, 8]);
+
+        // The first is included, but not deep enough yet; the second is stuck.
+        {
+            let mut chain = rpc.0.lock().unwrap();
+            chain.receipts.insert(
+                first.clone(),
+                Receipt {
+                    block_number: 1,
+                    success: true,
+                },
+            );
+            chain.block_number = 2;
+        }
+        assert!(relayer.poll().await.unwrap().is_empty());
+        rpc.0.lock().unwrap().block_number = 5;
+        assert_eq!(
+            relayer.poll().await.unwrap(),
+            vec![RelayOutcome::Confirmed {
+                height: 1,
+                hash: first
+            }]
+        );
+        {
+            let chain = rpc.0.lock().unwrap();
+            let replacement = chain.sent.last().unwrap();
+            assert_eq!(chain.sent.len(), 3);
+            assert_eq!(replacement.nonce, 8);
+            assert!(replacement.gas_price > 100);
+        }
+
+        // The replaced one is included after all, and reverted.
+        {
+            let mut chain = rpc.0.lock().unwrap();
+            chain.receipts.insert(
+                second.clone(),
+                Receipt {
+                    block_number: 6,
+                    success: false,
+                },
+            );
+            chain.block_number = 8;
+        }
+        assert_eq!(
+            relayer.poll().await.unwrap(),
+            vec![RelayOutcome::Reverted {
+                height: 2,
+                hash: second
+            }]
+        );
+        assert!(relayer.pending().is_empty());
+    }
+}
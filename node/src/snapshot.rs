@@ -0,0 +1,245 @@
+//! Portable archives of the chain state, for backups and fast provisioning of new machines.
+//!
+//! A snapshot holds the packfile of the finalized history, the reserved state,
+//! the finalization proofs of the finalized blocks and the entries of the DMSes.
+//! Everything but the DMS entries is verified again on import.
+use super::*;
+use simperby_network::peer_discovery::PeerDiscoveryImpl;
+use simperby_network::primitives::{MessageStore, StoreOperation};
+use simperby_repository::raw::RawRepository;
+use simperby_repository::DistributedRepository;
+
+/// The version of the snapshot format, which is checked on import.
+pub const SNAPSHOT_FORMAT_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Snapshot {
+    pub format_version: u64,
+    pub chain_name: String,
+    /// The last finalized block.
+    pub last_header: BlockHeader,
+    /// The commit of the last finalized block.
+    pub head: CommitHash,
+    pub reserved_state: ReservedState,
+    /// The finalized blocks with their finalization proofs, from the genesis.
+    ///
+    /// The proof of the last block is carried by the next block, so it is not included
+    /// unless the last block is the genesis.
+    pub finalization_proofs: Vec<(BlockHeader, FinalizationProof)>,
+    /// The entries of the DMSes, by the DMS key.
+    pub dms: Vec<(String, Vec<(String, String)>)>,
+    /// The hex-encoded packfile of the finalized history.
+    pub pack: String,
+    pub pack_hash: Hash256,
+}
+
+/// The storages of the DMSes, with their DMS keys.
+fn dms_directories(config: &Config) -> [(&str, &str); 3] {
+    [
+        (config.governance_directory.as_str(), "governance"),
+        (config.consensus_directory.as_str(), "consensus"),
+        (config.chat_directory.as_str(), simperby_chat::CHAT_DMS_KEY),
+    ]
+}
+
+/// Verifies the chain of the finalization proofs, from the genesis.
+///
+/// A proof is verified against the validator set of the previous block,
+/// and the one of the genesis against its own.
+pub fn verify_finalization_proofs(proofs: &[(BlockHeader, FinalizationProof)]) -> Result<()> {
+    let mut validator_set = match proofs.first() {
+        Some((header, _)) => &header.validator_set,
+        None => return Ok(()),
+    };
+    for (header, proof) in proofs {
+        verify::verify_finalization_proof(header, proof, validator_set).map_err(|e| {
+            anyhow::anyhow!(
+                "invalid finalization proof of block {}: {}",
+                header.height,
+                e
+            )
+        })?;
+        validator_set = &header.validator_set;
+    }
+    Ok(())
+}
+
+impl Snapshot {
+    /// Verifies the format, the packfile and the finalization proofs,
+    /// returning the decoded packfile.
+    ///
+    /// The history in the packfile is verified when it is imported.
+    pub fn verify(&self) -> Result<Vec<u8>> {
+        if self.format_version != SNAPSHOT_FORMAT_VERSION {
+            return Err(anyhow::anyhow!(
+                "unsupported snapshot format version {} (expected {})",
+                self.format_version,
+                SNAPSHOT_FORMAT_VERSION
+            ));
+        }
+        let pack = hex::decode(&self.pack)?;
+        if Hash256::hash(&pack) != self.pack_hash {
+            return Err(anyhow::anyhow!("the packfile doesn't match its hash"));
+        }
+        match self.finalization_proofs.first() {
+            Some((header, _)) if *header == self.reserved_state.genesis_info.header => {}
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "the finalization proofs don't start from the genesis"
+                ))
+            }
+        }
+        verify_finalization_proofs(&self.finalization_proofs)?;
+        Ok(pack)
+    }
+}
+
+/// Exports the snapshot of the node to the path, returning the last finalized block.
+pub async fn export<S: MessageStore, R: RawRepository>(
+    config: &Config,
+    path: &str,
+) -> Result<BlockHeader> {
+    let repo = DistributedRepository::new(R::open(&config.repository_directory).await?).await?;
+    let (head, pack) = repo.export_pack().await?;
+    let last_header = repo.get_last_finalized_block_header().await?;
+    let reserved_state = repo.get_reserved_state().await?;
+    let history = repo.read_finalized_history().await?;
+    drop(repo);
+
+    let mut previous = reserved_state.genesis_info.header.clone();
+    let mut finalization_proofs = Vec::new();
+    for entry in history {
+        if let Some(Commit::Block(header)) = entry.commit {
+            let proof = header.prev_block_finalization_proof.clone();
+            finalization_proofs.push((std::mem::replace(&mut previous, header), proof));
+        }
+    }
+    if finalization_proofs.is_empty() {
+        finalization_proofs.push((
+            reserved_state.genesis_info.header.clone(),
+            reserved_state.genesis_info.genesis_proof.clone(),
+        ));
+    }
+
+    let mut dms = Vec::new();
+    for (directory, dms_key) in dms_directories(config) {
+        dms.push((
+            dms_key.to_owned(),
+            S::open(directory).await?.read_all().await?,
+        ));
+    }
+
+    let snapshot = Snapshot {
+        format_version: SNAPSHOT_FORMAT_VERSION,
+        chain_name: reserved_state.genesis_info.chain_name.clone(),
+        last_header: last_header.clone(),
+        head,
+        reserved_state,
+        finalization_proofs,
+        dms,
+        pack_hash: Hash256::hash(&pack),
+        pack: hex::encode(&pack),
+    };
+    tokio::fs::write(path, serde_json::to_vec(&snapshot)?).await?;
+    Ok(last_header)
+}
+
+/// Restores a node from the snapshot into the directories of the configuration,
+/// which must not exist, returning the last finalized block.
+///
+/// The history is verified from the genesis and must agree with the snapshot.
+/// The storage of the peer discovery is created empty; the peers are discovered
+/// from the bootstrap peers as usual.
+pub async fn import<S: MessageStore, R: RawRepository>(
+    config: &Config,
+    path: &str,
+) -> Result<BlockHeader> {
+    let snapshot: Snapshot = serde_json::from_slice(&tokio::fs::read(path).await?)?;
+    let pack = snapshot.verify()?;
+    let repo = DistributedRepository::<R>::import_pack(
+        &config.repository_directory,
+        &pack,
+        &snapshot.head,
+    )
+    .await?;
+    let last_header = repo.get_last_finalized_block_header().await?;
+    if last_header != snapshot.last_header {
+        return Err(anyhow::anyhow!(
+            "the history ends at block {}, but the snapshot claims block {}",
+            last_header.height,
+            snapshot.last_header.height
+        ));
+    }
+    if repo.get_reserved_state().await? != snapshot.reserved_state {
+        return Err(anyhow::anyhow!(
+            "the reserved state of the history differs from the snapshot"
+        ));
+    }
+    drop(repo);
+
+    for (directory, dms_key) in dms_directories(config) {
+        let entries = snapshot
+            .dms
+            .iter()
+            .find(|(key, _)| key == dms_key)
+            .map(|(_, entries)| entries.clone())
+            .ok_or_else(|| anyhow::anyhow!("the snapshot has no entries of DMS {}", dms_key))?;
+        S::create(directory).await?;
+        S::open(directory)
+            .await?
+            .write_batch(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| StoreOperation::Put(key, value))
+                    .collect(),
+            )
+            .await?;
+    }
+    PeerDiscoveryImpl::create(&config.peer_directory).await?;
+    Ok(last_header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(height: BlockHeight, validator_set: Vec<(PublicKey, VotingPower)>) -> BlockHeader {
+        BlockHeader {
+            author: validator_set[0].0.clone(),
+            prev_block_finalization_proof: Vec::new(),
+            previous_hash: Hash256::zero(),
+            height,
+            timestamp: 0,
+            commit_hash: Hash256::zero(),
+            tx_merkle_root: Hash256::zero(),
+            chat_merkle_root: Hash256::zero(),
+            repository_merkle_root: Hash256::zero(),
+            validator_set,
+            version: "0.1.0".to_owned(),
+        }
+    }
+
+    #[test]
+    fn proof_chain() {
+        let (a, a_key) = generate_keypair("a");
+        let (b, b_key) = generate_keypair("b");
+        let genesis = header(0, vec![(a.clone(), 1)]);
+        // The validator set is handed over to `b` at block 1.
+        let block = header(1, vec![(b, 1)]);
+        let sign = |header: &BlockHeader, key| vec![TypedSignature::sign(header, key).unwrap()];
+
+        assert!(verify_finalization_proofs(&[]).is_ok());
+        assert!(verify_finalization_proofs(&[
+            (genesis.clone(), sign(&genesis, &a_key)),
+            (block.clone(), sign(&block, &a_key)),
+        ])
+        .is_ok());
+        assert!(verify_finalization_proofs(&[
+            (genesis.clone(), sign(&genesis, &a_key)),
+            (block.clone(), sign(&block, &b_key)),
+        ])
+        .is_err());
+        assert!(verify_finalization_proofs(&[(genesis.clone(), sign(&block, &a_key))]).is_err());
+        assert!(verify_finalization_proofs(&[(genesis, Vec::new())]).is_err());
+    }
+}
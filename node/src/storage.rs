@@ -0,0 +1,214 @@
+//! The disk usage of the node, with the quotas warning the operator before the disk fills.
+//!
+//! The sizes of the Git object store and the DMS stores are exported as metrics and checked
+//! against `StorageQuota`; a store past its warning threshold is logged and shown in the status
+//! of the API. The quotas never stop the node, which must keep up with the chain.
+use super::*;
+use simperby_repository::raw::RawRepository;
+use simperby_repository::{BranchUsage, DistributedRepository};
+use std::path::Path;
+use std::time::Duration;
+
+/// How often the disk usage is measured.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StorageQuota {
+    /// The quota of the Git object store of the repository.
+    #[serde(default)]
+    pub max_object_store_bytes: Option<u64>,
+    /// The quota of the DMS stores in total.
+    #[serde(default)]
+    pub max_dms_bytes: Option<u64>,
+    /// The fraction of a quota past which the usage is warned of.
+    #[serde(default = "StorageQuota::default_warning_ratio")]
+    pub warning_ratio: f64,
+}
+
+impl Default for StorageQuota {
+    fn default() -> Self {
+        Self {
+            max_object_store_bytes: None,
+            max_dms_bytes: None,
+            warning_ratio: Self::default_warning_ratio(),
+        }
+    }
+}
+
+impl StorageQuota {
+    fn default_warning_ratio() -> f64 {
+        0.8
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.warning_ratio > 0.0 && self.warning_ratio <= 1.0) {
+            return Err(format!(
+                "`warning_ratio` {} is not in (0, 1]",
+                self.warning_ratio
+            ));
+        }
+        if self.max_object_store_bytes == Some(0) || self.max_dms_bytes == Some(0) {
+            return Err("a quota is zero; omit it to disable".to_owned());
+        }
+        Ok(())
+    }
+
+    /// Returns the warnings of the usages past the thresholds of the quotas.
+    pub fn warnings(&self, usage: &DiskUsage) -> Vec<String> {
+        let dms_bytes: u64 = usage.dms_bytes.iter().map(|(_, bytes)| bytes).sum();
+        [
+            (
+                "object store",
+                usage.object_store_bytes,
+                self.max_object_store_bytes,
+            ),
+            ("DMS stores", dms_bytes, self.max_dms_bytes),
+        ]
+        .into_iter()
+        .filter_map(|(store, bytes, quota)| {
+            let quota = quota?;
+            if bytes > quota {
+                Some(format!(
+                    "the {} uses {} bytes, over the quota of {} bytes",
+                    store, bytes, quota
+                ))
+            } else if bytes as f64 >= quota as f64 * self.warning_ratio {
+                Some(format!(
+                    "the {} uses {} bytes, {:.0}% of the quota of {} bytes",
+                    store,
+                    bytes,
+                    bytes as f64 * 100.0 / quota as f64,
+                    quota
+                ))
+            } else {
+                None
+            }
+        })
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskUsage {
+    pub object_store_bytes: u64,
+    /// `(dms, bytes)` of the governance, the consensus and the chat.
+    pub dms_bytes: Vec<(String, u64)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageReport {
+    pub usage: DiskUsage,
+    pub branches: Vec<BranchUsage>,
+    /// See `StorageQuota::warnings()`.
+    pub warnings: Vec<String>,
+}
+
+/// Returns the total size of the files under the path, which may not exist.
+fn directory_size(path: &Path) -> std::io::Result<u64> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    let metadata = std::fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut total = 0;
+    for entry in std::fs::read_dir(path)? {
+        total += directory_size(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+/// Measures the sizes of the stores on the disk.
+pub async fn measure(config: &Config) -> Result<DiskUsage> {
+    let config = config.clone();
+    tokio::task::spawn_blocking(move || {
+        let object_store_bytes =
+            directory_size(&Path::new(&config.repository_directory).join(".git/objects"))?;
+        let dms_bytes = [
+            ("governance", &config.governance_directory),
+            ("consensus", &config.consensus_directory),
+            ("chat", &config.chat_directory),
+        ]
+        .into_iter()
+        .map(|(dms, directory)| Ok((dms.to_owned(), directory_size(Path::new(directory))?)))
+        .collect::<Result<_>>()?;
+        Ok(DiskUsage {
+            object_store_bytes,
+            dms_bytes,
+        })
+    })
+    .await?
+}
+
+/// Reports the disk usage with the history retained by each branch.
+pub async fn report<R: RawRepository>(config: &Config) -> Result<StorageReport> {
+    let usage = measure(config).await?;
+    let branches = DistributedRepository::new(R::open(&config.repository_directory).await?)
+        .await?
+        .get_branch_usage()
+        .await?;
+    Ok(StorageReport {
+        warnings: config.storage.warnings(&usage),
+        usage,
+        branches,
+    })
+}
+
+/// Measures the disk usage indefinitely, exporting it as metrics and logging the warnings.
+pub async fn observe(config: Config) -> Result<()> {
+    loop {
+        let usage = measure(&config).await?;
+        telemetry::record_storage(&usage);
+        for warning in config.storage.warnings(&usage) {
+            log::warn!("{}", warning);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warnings() {
+        let quota = StorageQuota {
+            max_object_store_bytes: Some(1000),
+            max_dms_bytes: Some(100),
+            ..Default::default()
+        };
+        quota.validate().unwrap();
+        let usage = |object_store_bytes, dms| DiskUsage {
+            object_store_bytes,
+            dms_bytes: vec![("governance".to_owned(), dms), ("chat".to_owned(), dms)],
+        };
+        assert!(quota.warnings(&usage(700, 30)).is_empty());
+        assert_eq!(
+            quota.warnings(&usage(850, 30)),
+            vec!["the object store uses 850 bytes, 85% of the quota of 1000 bytes".to_owned()]
+        );
+        assert_eq!(quota.warnings(&usage(1001, 60)).len(), 2);
+        assert!(StorageQuota::default()
+            .warnings(&usage(u64::MAX, 0))
+            .is_empty());
+        StorageQuota {
+            warning_ratio: 0.0,
+            ..Default::default()
+        }
+        .validate()
+        .unwrap_err();
+    }
+
+    #[test]
+    fn size_of_directory() {
+        let directory =
+            std::env::temp_dir().join(format!("simperby-storage-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("nested")).unwrap();
+        std::fs::write(directory.join("a"), [0; 10]).unwrap();
+        std::fs::write(directory.join("nested/b"), [0; 5]).unwrap();
+        assert_eq!(directory_size(&directory).unwrap(), 15);
+        assert_eq!(directory_size(&directory.join("missing")).unwrap(), 0);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+//! The Prometheus exporter of the node, serving the metrics in the text format at `/metrics`.
+//!
+//! Besides the metrics below, it exports those recorded by the other crates
+//! (e.g., `simperby_consensus::telemetry`, `simperby_repository::telemetry`).
+use super::*;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use simperby_network::dms::MessageReader;
+use simperby_network::pipeline::Pipeline;
+use simperby_network::primitives::MessageStore;
+use std::time::Duration;
+
+/// How often the sizes of the DMSes are measured.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A gauge of the known peers.
+pub const KNOWN_PEERS: &str = "simperby_node_known_peers";
+/// A gauge of the messages stored in a DMS, labeled by `dms`.
+pub const DMS_MESSAGES: &str = "simperby_node_dms_messages";
+/// A gauge of the messages queued in a lane of the pipeline, labeled by `lane`.
+pub const PIPELINE_DEPTH: &str = "simperby_node_pipeline_depth";
+/// A counter of the messages dropped from the pipeline, labeled by `lane` and `reason`.
+pub const PIPELINE_DROPPED: &str = "simperby_node_pipeline_dropped";
+/// A histogram of the time spent synchronizing the repository, labeled by `result`.
+pub const SYNC_SECONDS: &str = "simperby_node_sync_seconds";
+/// A gauge of the bytes of a store on the disk, labeled by `store`
+/// (`objects` for the Git object store, or the name of a DMS).
+pub const STORAGE_BYTES: &str = "simperby_node_storage_bytes";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// Whether to serve the metrics.
+    pub enabled: bool,
+    pub port: u16,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 9180,
+        }
+    }
+}
+
+/// Installs the exporter and serves it in the background, if enabled.
+///
+/// It must be called at most once in the process, within the Tokio runtime.
+pub fn install(config: &MetricsConfig) -> Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    PrometheusBuilder::new()
+        .with_http_listener(([0, 0, 0, 0], config.port))
+        .install()
+        .map_err(|e| anyhow::anyhow!("failed to install the metrics exporter: {}", e))
+}
+
+pub fn record_known_peers(count: usize) {
+    metrics::gauge!(KNOWN_PEERS, count as f64);
+}
+
+pub fn record_sync(duration: Duration, succeeded: bool) {
+    let result = if succeeded { "ok" } else { "error" };
+    metrics::histogram!(SYNC_SECONDS, duration.as_secs_f64(), "result" => result);
+}
+
+pub fn record_storage(usage: &storage::DiskUsage) {
+    metrics::gauge!(STORAGE_BYTES, usage.object_store_bytes as f64, "store" => "objects");
+    for (dms, bytes) in &usage.dms_bytes {
+        metrics::gauge!(STORAGE_BYTES, *bytes as f64, "store" => dms.clone());
+    }
+}
+
+/// Measures the size of the DMS indefinitely.
+pub async fn observe_dms<S: MessageStore>(
+    name: &'static str,
+    reader: MessageReader<S>,
+) -> Result<()> {
+    loop {
+        let messages = reader.read_messages().await?.len();
+        metrics::gauge!(DMS_MESSAGES, messages as f64, "dms" => name);
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Measures the lanes of the pipeline indefinitely.
+pub async fn observe_pipeline(pipeline: Pipeline) -> Result<()> {
+    loop {
+        for (subsystem, stats) in pipeline.stats().await {
+            let lane = format!("{:?}", subsystem).to_lowercase();
+            metrics::gauge!(PIPELINE_DEPTH, stats.depth as f64, "lane" => lane.clone());
+            for (reason, count) in [
+                ("merged", stats.merged),
+                ("displaced", stats.displaced),
+                ("stale", stats.stale),
+                ("failed", stats.failed),
+            ] {
+                metrics::absolute_counter!(
+                    PIPELINE_DROPPED,
+                    count,
+                    "lane" => lane.clone(),
+                    "reason" => reason
+                );
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,108 @@
+//! The webhook backend of the notifications (see `notify`), for the external services
+//! (e.g., Slack or Discord).
+//!
+//! A `Notification` is POSTed to the URL as JSON. With a secret, the body is signed
+//! by HMAC-SHA256 in the `X-Simperby-Signature` header as `sha256=<hex>`, so that the receiver
+//! can authenticate it.
+use super::*;
+use hmac::{Hmac, Mac};
+use notify::{Notification, Notifier};
+use sha2::Sha256;
+use std::time::Duration;
+
+/// The header of the signature of the body.
+pub const SIGNATURE_HEADER: &str = "X-Simperby-Signature";
+/// The timeout of a single attempt of a delivery.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// The key of the signature of the body, shared with the receiver.
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+impl WebhookConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if url::Url::parse(&self.url).map_or(true, |url| !matches!(url.scheme(), "http" | "https"))
+        {
+            return Err(format!("invalid URL `{}`", self.url));
+        }
+        Ok(())
+    }
+}
+
+/// Signs the body with the secret, as in `SIGNATURE_HEADER`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes a key of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    config: WebhookConfig,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: WebhookConfig) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+            config,
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn describe(&self) -> String {
+        format!("webhook {}", self.config.url)
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<()> {
+        let body = serde_json::to_vec(notification)?;
+        let mut request = self
+            .client
+            .post(&self.config.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(secret) = &self.config.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, &body));
+        }
+        let response = request.body(body).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("status {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn validation() {
+        let webhook = WebhookConfig {
+            url: "https://hooks.example.org/simperby".to_owned(),
+            secret: None,
+        };
+        webhook.validate().unwrap();
+        WebhookConfig {
+            url: "ftp://example.org".to_owned(),
+            ..webhook
+        }
+        .validate()
+        .unwrap_err();
+    }
+}
@@ -0,0 +1,88 @@
+//! Authoring a general transaction in the working tree of the `work` branch,
+//! as a protocol-aware replacement of `git add` and `git commit`.
+//!
+//! A draft is started with the head and the body of the transaction, the changes are staged,
+//! and then the transaction commit is created once the staged diff passes the checks.
+use super::*;
+use std::path::Path;
+use tokio::fs;
+
+/// The draft of the transaction being authored, in the repository directory.
+pub const DRAFT_FILE: &str = ".simperby/transaction.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionDraft {
+    pub author: PublicKey,
+    pub head: String,
+    pub body: String,
+}
+
+/// The staged transaction, as it would be committed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionPreview {
+    pub draft: TransactionDraft,
+    /// The `(path, size)` of the staged files; the size is `None` for a deleted file.
+    pub changes: Vec<(String, Option<u64>)>,
+    pub diff: String,
+    /// The reasons that the transaction can't be committed, if any.
+    pub problems: Vec<String>,
+}
+
+impl TransactionPreview {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Checks the staged changes against the size limits and the reserved directory,
+/// which only a reserved-state transaction may change.
+pub fn check_changes(changes: &[(String, Option<u64>)], size_limits: &SizeLimits) -> Vec<String> {
+    let mut problems = Vec::new();
+    if changes.is_empty() {
+        problems.push("nothing is staged".to_owned());
+    }
+    for (path, _) in changes {
+        if Path::new(path).starts_with(RESERVED_DIRECTORY) {
+            problems.push(format!(
+                "{} is in the reserved directory `{}`, which a general transaction can't change",
+                path, RESERVED_DIRECTORY
+            ));
+        }
+    }
+    let sizes: Vec<_> = changes
+        .iter()
+        .filter_map(|(path, size)| size.map(|size| (path.clone(), size)))
+        .collect();
+    if let Err(e) = size_limits.check(&sizes) {
+        problems.push(e);
+    }
+    problems
+}
+
+pub(crate) async fn read_draft(
+    repository_directory: &str,
+) -> Result<Option<TransactionDraft>, Error> {
+    let path = Path::new(repository_directory).join(DRAFT_FILE);
+    match fs::read_to_string(&path).await {
+        Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(crate) async fn write_draft(
+    repository_directory: &str,
+    draft: &TransactionDraft,
+) -> Result<(), Error> {
+    let path = Path::new(repository_directory).join(DRAFT_FILE);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(draft)?).await?;
+    Ok(())
+}
+
+pub(crate) async fn remove_draft(repository_directory: &str) -> Result<(), Error> {
+    fs::remove_file(Path::new(repository_directory).join(DRAFT_FILE)).await?;
+    Ok(())
+}
@@ -0,0 +1,304 @@
+//! A conformance test suite for the implementations of `RawRepository`,
+//! so that the backends stay behaviorally identical.
+//!
+//! `check()` applies a sequence of branch, tag and commit operations (see `operations()`)
+//! both to a repository and to a model of it, and compares the outcome of every operation
+//! and the resulting references. An implementation runs it as a property test:
+//!
+//! ```ignore
+//! proptest! {
+//!     #[test]
+//!     fn conformance(operations in conformance::operations(32)) {
+//!         // Opens a repository with a single commit on a single branch, checked out.
+//!         let repository = ...;
+//!         runtime.block_on(conformance::check(repository, &directory, &operations)).unwrap();
+//!     }
+//! }
+//! ```
+//!
+//! It is available with the `conformance` feature.
+use super::*;
+use proptest::prelude::*;
+use raw::RawRepository;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The names of the tags that the operations refer to, by index.
+///
+/// So are the branches, from index 1; the branch at index 0 is the one that the repository starts with.
+const NAMES: [&str; 3] = ["a", "b", "c"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    CreateBranch {
+        branch: usize,
+        commit: usize,
+    },
+    MoveBranch {
+        branch: usize,
+        commit: usize,
+    },
+    DeleteBranch {
+        branch: usize,
+    },
+    Checkout {
+        branch: usize,
+    },
+    CreateTag {
+        tag: usize,
+        commit: usize,
+    },
+    RemoveTag {
+        tag: usize,
+    },
+    /// Commits a new file on the checked out branch.
+    Commit,
+}
+
+/// Generates sequences of at most `max_length` operations.
+///
+/// The indices of the commits are taken modulo the number of the commits created so far.
+pub fn operations(max_length: usize) -> impl Strategy<Value = Vec<Operation>> {
+    let branch = 0..=NAMES.len();
+    let tag = 0..NAMES.len();
+    let operation = prop_oneof![
+        (branch.clone(), any::<usize>())
+            .prop_map(|(branch, commit)| Operation::CreateBranch { branch, commit }),
+        (branch.clone(), any::<usize>())
+            .prop_map(|(branch, commit)| Operation::MoveBranch { branch, commit }),
+        branch
+            .clone()
+            .prop_map(|branch| Operation::DeleteBranch { branch }),
+        branch.prop_map(|branch| Operation::Checkout { branch }),
+        (tag.clone(), any::<usize>())
+            .prop_map(|(tag, commit)| Operation::CreateTag { tag, commit }),
+        tag.prop_map(|tag| Operation::RemoveTag { tag }),
+        Just(Operation::Commit),
+    ];
+    prop::collection::vec(operation, 0..=max_length)
+}
+
+/// What `RawRepository` is expected to do.
+struct Model {
+    /// The branch that the repository starts with, at index 0.
+    initial_branch: Branch,
+    commits: Vec<CommitHash>,
+    branches: BTreeMap<Branch, CommitHash>,
+    tags: BTreeMap<Tag, CommitHash>,
+    head: Branch,
+}
+
+impl Model {
+    fn branch(&self, index: usize) -> Branch {
+        if index == 0 {
+            self.initial_branch.clone()
+        } else {
+            NAMES[index - 1].to_owned()
+        }
+    }
+
+    fn commit(&self, index: usize) -> CommitHash {
+        self.commits[index % self.commits.len()]
+    }
+
+    fn branch_names(&self) -> Vec<Branch> {
+        (0..=NAMES.len()).map(|index| self.branch(index)).collect()
+    }
+}
+
+/// Applies the operations to the repository, which must have a single commit on a single branch,
+/// checked out, failing on the first divergence from the model.
+///
+/// `directory` is the working directory of the repository, where `Operation::Commit` writes files.
+pub async fn check<R: RawRepository>(
+    mut repository: R,
+    directory: &str,
+    operations: &[Operation],
+) -> Result<(), Error> {
+    let branches = repository.list_branches().await?;
+    if branches.len() != 1 {
+        return Err(anyhow!(
+            "expected a single branch, but found {:?}",
+            branches
+        ));
+    }
+    let initial_branch = branches[0].clone();
+    let root = repository.locate_branch(&initial_branch).await?;
+    let mut model = Model {
+        initial_branch: initial_branch.clone(),
+        commits: vec![root],
+        branches: vec![(initial_branch.clone(), root)].into_iter().collect(),
+        tags: BTreeMap::new(),
+        head: initial_branch,
+    };
+
+    for (step, operation) in operations.iter().enumerate() {
+        let (result, expected) = match operation {
+            Operation::CreateBranch { branch, commit } => {
+                let (branch, commit) = (model.branch(*branch), model.commit(*commit));
+                let result = repository.create_branch(&branch, commit).await;
+                let expected = !model.branches.contains_key(&branch);
+                if expected {
+                    model.branches.insert(branch, commit);
+                }
+                (result, expected)
+            }
+            Operation::MoveBranch { branch, commit } => {
+                let (branch, commit) = (model.branch(*branch), model.commit(*commit));
+                let result = repository.move_branch(&branch, &commit).await;
+                let expected = model.branches.contains_key(&branch);
+                if expected {
+                    model.branches.insert(branch, commit);
+                }
+                (result, expected)
+            }
+            Operation::DeleteBranch { branch } => {
+                let branch = model.branch(*branch);
+                let result = repository.delete_branch(&branch).await;
+                let expected = model.branches.contains_key(&branch) && branch != model.head;
+                if expected {
+                    model.branches.remove(&branch);
+                }
+                (result, expected)
+            }
+            Operation::Checkout { branch } => {
+                let branch = model.branch(*branch);
+                let result = repository.checkout(&branch).await;
+                let expected = model.branches.contains_key(&branch);
+                if expected {
+                    model.head = branch;
+                }
+                (result, expected)
+            }
+            Operation::CreateTag { tag, commit } => {
+                let (tag, commit) = (NAMES[*tag].to_owned(), model.commit(*commit));
+                let result = repository.create_tag(&tag, &commit).await;
+                model.tags.insert(tag, commit);
+                (result, true)
+            }
+            Operation::RemoveTag { tag } => {
+                let tag = NAMES[*tag].to_owned();
+                let result = repository.remove_tag(&tag).await;
+                (result, model.tags.remove(&tag).is_some())
+            }
+            Operation::Commit => {
+                let path = format!("conformance-{}", step);
+                tokio::fs::write(Path::new(directory).join(&path), step.to_string()).await?;
+                repository.stage(&[path]).await?;
+                let result = repository
+                    .commit_staged(&format!("conformance {}", step))
+                    .await;
+                if let Ok(commit) = &result {
+                    model.commits.push(*commit);
+                    model.branches.insert(model.head.clone(), *commit);
+                }
+                (result.map(|_| ()), true)
+            }
+        };
+        if result.is_ok() != expected {
+            return Err(anyhow!(
+                "step {} ({:?}): expected {}, but got {:?}",
+                step,
+                operation,
+                if expected { "a success" } else { "a failure" },
+                result
+            ));
+        }
+        compare(&repository, &model)
+            .await
+            .map_err(|e| anyhow!("step {} ({:?}): {}", step, operation, e))?;
+    }
+    Ok(())
+}
+
+/// Compares the references of the repository with the model.
+async fn compare<R: RawRepository>(repository: &R, model: &Model) -> Result<(), Error> {
+    let mut branches = repository.list_branches().await?;
+    branches.sort();
+    if branches != model.branches.keys().cloned().collect::<Vec<_>>() {
+        return Err(anyhow!(
+            "expected the branches {:?}, but found {:?}",
+            model.branches.keys(),
+            branches
+        ));
+    }
+    for branch in model.branch_names() {
+        let located = repository.locate_branch(&branch).await.ok();
+        if located != model.branches.get(&branch).copied() {
+            return Err(anyhow!(
+                "expected the branch {} at {:?}, but found it at {:?}",
+                branch,
+                model.branches.get(&branch),
+                located
+            ));
+        }
+    }
+    let mut tags = repository.list_tags().await?;
+    tags.sort();
+    if tags != model.tags.keys().cloned().collect::<Vec<_>>() {
+        return Err(anyhow!(
+            "expected the tags {:?}, but found {:?}",
+            model.tags.keys(),
+            tags
+        ));
+    }
+    for tag in NAMES {
+        let located = repository.locate_tag(&tag.to_owned()).await.ok();
+        if located != model.tags.get(tag).copied() {
+            return Err(anyhow!(
+                "expected the tag {} at {:?}, but found it at {:?}",
+                tag,
+                model.tags.get(tag),
+                located
+            ));
+        }
+    }
+    let head = repository.get_head().await?;
+    if head != model.branches[&model.head] {
+        return Err(anyhow!(
+            "expected HEAD at {} (branch {}), but found it at {}",
+            model.branches[&model.head],
+            model.head,
+            head
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raw::RawRepositoryImpl;
+
+    /// Creates a repository with a single commit on its initial branch.
+    fn set_up(directory: &Path) {
+        let repo = git2::Repository::init(directory).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "simperby").unwrap();
+        config
+            .set_str("user.email", "simperby@example.com")
+            .unwrap();
+        let signature = repo.signature().unwrap();
+        let tree = repo
+            .find_tree(repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "root", &tree, &[])
+            .unwrap();
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        #[test]
+        fn git2_backend(operations in operations(24)) {
+            let directory = tempfile::TempDir::new().unwrap();
+            set_up(directory.path());
+            let path = directory.path().to_str().unwrap().to_owned();
+            let result = tokio::runtime::Runtime::new().unwrap().block_on(async {
+                let repository = RawRepositoryImpl::open(&path).await?;
+                check(repository, &path, &operations).await
+            });
+            prop_assert!(result.is_ok(), "{:?}", result);
+        }
+    }
+}
@@ -0,0 +1,57 @@
+//! The drafts of the agendas, which a member prepares locally over days before publishing.
+//!
+//! A draft is a sequence of transaction commits kept under `refs/simperby/drafts/<name>`,
+//! outside of the branches, so it is never fetched, pushed or packed for the peers,
+//! nor checked by `DistributedRepository::check_branch_conventions()`.
+//! The drafts form the local work queue: the transactions on the `work` branch are parked
+//! as a draft (`DistributedRepository::create_draft()`), brought back to be edited
+//! (`load_draft()`), and published as an agenda (`publish_draft()`), only after which
+//! they go through the usual paths to the network.
+use super::*;
+
+pub const DRAFT_REF_PREFIX: &str = "refs/simperby/drafts/";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Draft {
+    pub name: String,
+    /// The last commit of the draft.
+    pub head: CommitHash,
+    /// The finalized commit which the draft is on top of.
+    pub base: CommitHash,
+    /// The titles of the transactions, in order.
+    pub transactions: Vec<String>,
+    /// Whether a block has been finalized after `base`, so that the transactions
+    /// are replayed on top of the last finalized block when loaded.
+    pub outdated: bool,
+}
+
+/// Checks that the name consists of ASCII alphanumerics, `-` and `_`.
+pub fn validate_name(name: &str) -> Result<(), Error> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!("invalid draft name: {:?}", name));
+    }
+    Ok(())
+}
+
+/// Returns the full name of the reference of the draft.
+pub fn reference(name: &str) -> String {
+    format!("{}{}", DRAFT_REF_PREFIX, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        validate_name("fee-schedule_2").unwrap();
+        for name in ["", "a/b", "a b", "..", "a~1"] {
+            validate_name(name).unwrap_err();
+        }
+        assert_eq!(reference("fees"), "refs/simperby/drafts/fees");
+    }
+}
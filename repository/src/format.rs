@@ -1,24 +1,587 @@
+//! The format of the Simperby commits in the repository (see `docs/git.md`).
+//!
+//! - A transaction has its head as the title, and its body followed by the Simperby trailers
+//! (`Simperby-Author`, `Simperby-Timestamp` and `Simperby-Diff`) as the body.
+//! A transaction changing the reserved state also has the `Simperby-Reserved-Changes` trailer.
+//! - The other commits have `<type>: <height>/<hash>` as the title, and the JSON of the commit
+//! as the body.
+//!
+//! The commits come from the peers, so parsing never panics on a malformed one;
+//! it fails with a `FormatError` instead.
 use crate::raw::SemanticCommit;
+use crate::CommitHash;
+use serde::{Deserialize, Serialize};
+use simperby_common::reserved::ReservedState;
 use simperby_common::*;
+use thiserror::Error;
 
-pub fn to_semantic_commit(commit: &Commit, last_header: &BlockHeader) -> SemanticCommit {
+pub const AUTHOR_TRAILER: &str = "Simperby-Author";
+pub const TIMESTAMP_TRAILER: &str = "Simperby-Timestamp";
+pub const DIFF_TRAILER: &str = "Simperby-Diff";
+/// The trailer of a transaction listing the changes of the reserved state in JSON,
+/// whose hash is the one in `Simperby-Diff`.
+pub const RESERVED_CHANGES_TRAILER: &str = "Simperby-Reserved-Changes";
+/// The trailer of the genesis commit recording its `GenesisProvenance`.
+pub const IMPORTED_FROM_TRAILER: &str = "Simperby-Imported-From";
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    #[error("invalid title: {0}")]
+    InvalidTitle(String),
+    #[error("invalid body: {0}")]
+    InvalidBody(String),
+    #[error("invalid trailer: {0}")]
+    InvalidTrailer(String),
+    #[error("missing trailer: {0}")]
+    MissingTrailer(String),
+    /// The title doesn't match the body, or the last header.
+    #[error("mismatch: {0}")]
+    Mismatch(String),
+}
+
+/// The type in the title of each commit other than a transaction.
+fn commit_type(commit: &Commit) -> &'static str {
+    match commit {
+        Commit::Block(_) => "block",
+        Commit::Transaction(_) => "tx",
+        Commit::Agenda(_) => "agenda",
+        Commit::AgendaProof(_) => "agenda-proof",
+        Commit::ExtraAgendaTransaction(tx) => match tx {
+            ExtraAgendaTransaction::Delegate(_) => "tx-delegate",
+            ExtraAgendaTransaction::Undelegate(_) => "tx-undelegate",
+            ExtraAgendaTransaction::Report(_) => "tx-report",
+            ExtraAgendaTransaction::RotateKey(_) => "tx-rotate-key",
+            ExtraAgendaTransaction::RemoveMember(_) => "tx-remove-member",
+            ExtraAgendaTransaction::EmergencyExpel(_) => "tx-emergency-expel",
+            ExtraAgendaTransaction::Attest(_) => "tx-attest",
+        },
+        Commit::ChatLog(_) => "chat",
+    }
+}
+
+const COMMIT_TYPES: [&str; 11] = [
+    "block",
+    "agenda",
+    "agenda-proof",
+    "tx-delegate",
+    "tx-undelegate",
+    "tx-report",
+    "tx-rotate-key",
+    "tx-remove-member",
+    "tx-emergency-expel",
+    "tx-attest",
+    "chat",
+];
+
+fn commit_hash(commit: &Commit) -> Hash256 {
     match commit {
-        Commit::Agenda(agenda) => {
-            let title = format!("agenda: {}/{}", last_header.height + 1, agenda.to_hash256());
-            let body = serde_json::to_string(agenda).unwrap();
-            SemanticCommit {
-                title,
-                body,
-                reserved_state: None,
+        Commit::Block(header) => header.to_hash256(),
+        Commit::Transaction(transaction) => transaction.to_hash256(),
+        Commit::Agenda(agenda) => agenda.to_hash256(),
+        Commit::AgendaProof(proof) => proof.to_hash256(),
+        Commit::ExtraAgendaTransaction(tx) => tx.to_hash256(),
+        Commit::ChatLog(chat_log) => chat_log.to_hash256(),
+    }
+}
+
+pub fn to_semantic_commit(commit: &Commit, last_header: &BlockHeader) -> SemanticCommit {
+    let title = format!(
+        "{}: {}/{}",
+        commit_type(commit),
+        last_header.height + 1,
+        commit_hash(commit)
+    );
+    let body = match commit {
+        Commit::Block(header) => serde_json::to_string(header),
+        Commit::Transaction(transaction) => {
+            let mut trailers = vec![
+                (
+                    AUTHOR_TRAILER.to_owned(),
+                    serde_json::to_string(&transaction.author).unwrap(),
+                ),
+                (
+                    TIMESTAMP_TRAILER.to_owned(),
+                    transaction.timestamp.to_string(),
+                ),
+            ];
+            if let Some(hash) = transaction.diff.hash() {
+                trailers.push((DIFF_TRAILER.to_owned(), hash.to_string()));
             }
+            if let Diff::Reserved(_, changes) = &transaction.diff {
+                trailers.push((
+                    RESERVED_CHANGES_TRAILER.to_owned(),
+                    serde_json::to_string(changes).unwrap(),
+                ));
+            }
+            return SemanticCommit {
+                title: transaction.head.clone(),
+                body: append_trailers(&transaction.body, &trailers),
+                reserved_state: match &transaction.diff {
+                    Diff::Reserved(reserved_state, _) => Some(reserved_state.as_ref().clone()),
+                    _ => None,
+                },
+            };
         }
-        _ => todo!(),
+        Commit::Agenda(agenda) => serde_json::to_string(agenda),
+        Commit::AgendaProof(proof) => serde_json::to_string(proof),
+        Commit::ExtraAgendaTransaction(tx) => serde_json::to_string(tx),
+        Commit::ChatLog(chat_log) => serde_json::to_string(chat_log),
+    }
+    .unwrap();
+    SemanticCommit {
+        title,
+        body,
+        reserved_state: None,
+    }
+}
+
+/// Where the genesis working tree was imported from
+/// (see `DistributedRepository::init_from_existing()`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisProvenance {
+    /// The path or the URL of the repository.
+    pub source: String,
+    /// The commit of which the tree was imported, without its history.
+    pub commit: CommitHash,
+}
+
+/// Returns the root commit of the repository, carrying the genesis reserved state.
+///
+/// If the genesis working tree was imported, the body ends with
+/// the `Simperby-Imported-From: <commit> <source>` trailer.
+pub fn to_genesis_semantic_commit(
+    reserved_state: &ReservedState,
+    provenance: Option<&GenesisProvenance>,
+) -> SemanticCommit {
+    let genesis_info = &reserved_state.genesis_info;
+    let mut body = serde_json::to_string(genesis_info).unwrap();
+    if let Some(provenance) = provenance {
+        body.push_str(&format!(
+            "\n\n{}: {} {}",
+            IMPORTED_FROM_TRAILER,
+            hex::encode(provenance.commit.hash),
+            provenance.source
+        ));
+    }
+    SemanticCommit {
+        title: format!("genesis: {}", genesis_info.chain_name),
+        body,
+        reserved_state: Some(reserved_state.clone()),
+    }
+}
+
+/// Reads the `GenesisProvenance` from the body of the genesis commit, if it was imported.
+pub fn parse_genesis_provenance(body: &str) -> Result<Option<GenesisProvenance>, FormatError> {
+    let value = match body
+        .lines()
+        .last()
+        .and_then(|line| line.strip_prefix(IMPORTED_FROM_TRAILER))
+        .and_then(|rest| rest.strip_prefix(": "))
+    {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let (commit, source) = value
+        .split_once(' ')
+        .ok_or_else(|| FormatError::InvalidTrailer(format!("no source in {:?}", value)))?;
+    Ok(Some(GenesisProvenance {
+        source: source.to_owned(),
+        commit: commit
+            .parse()
+            .map_err(|e| FormatError::InvalidTrailer(format!("{}", e)))?,
+    }))
+}
+
+/// Parses `<type>: <height>/<hash>`, returning `None` if the title is not of a known type
+/// (i.e., of a transaction).
+pub fn parse_title(
+    title: &str,
+) -> Result<Option<(&'static str, BlockHeight, Hash256)>, FormatError> {
+    let (commit_type, rest) = match title.split_once(": ").and_then(|(commit_type, rest)| {
+        COMMIT_TYPES
+            .iter()
+            .find(|t| **t == commit_type)
+            .map(|t| (*t, rest))
+    }) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let (height, hash) = rest
+        .split_once('/')
+        .ok_or_else(|| FormatError::InvalidTitle(format!("no hash in {:?}", title)))?;
+    let height = height
+        .parse::<BlockHeight>()
+        .map_err(|e| FormatError::InvalidTitle(format!("invalid height in {:?}: {}", title, e)))?;
+    let hash = hex::decode(hash)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes.as_slice()).ok())
+        .map(|hash| Hash256 { hash })
+        .ok_or_else(|| FormatError::InvalidTitle(format!("invalid hash in {:?}", title)))?;
+    Ok(Some((commit_type, height, hash)))
+}
+
+fn is_trailer_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Splits the trailers (`Key: value` lines) off the last paragraph of the body.
+///
+/// If any line of the last paragraph is not a trailer, the body has no trailers.
+pub fn parse_trailers(body: &str) -> (&str, Vec<(&str, &str)>) {
+    let (text, paragraph) = match body.trim_end_matches('\n').rsplit_once("\n\n") {
+        Some((text, paragraph)) => (text, paragraph),
+        None => ("", body.trim_end_matches('\n')),
+    };
+    let trailers = paragraph
+        .lines()
+        .map(|line| line.split_once(": ").filter(|(key, _)| is_trailer_key(key)))
+        .collect::<Option<Vec<_>>>();
+    match trailers {
+        Some(trailers) if !trailers.is_empty() => (text, trailers),
+        _ => (body, Vec::new()),
+    }
+}
+
+fn append_trailers(text: &str, trailers: &[(String, String)]) -> String {
+    let trailers = trailers
+        .iter()
+        .map(|(key, value)| format!("{}: {}", key, value))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if text.is_empty() {
+        trailers
+    } else {
+        format!("{}\n\n{}", text, trailers)
     }
 }
 
+fn find_trailer<'a>(
+    trailers: &[(&str, &'a str)],
+    key: &str,
+) -> Result<Option<&'a str>, FormatError> {
+    let mut values = trailers.iter().filter(|(k, _)| *k == key);
+    let value = values.next().map(|(_, value)| *value);
+    if values.next().is_some() {
+        return Err(FormatError::InvalidTrailer(format!("duplicate {}", key)));
+    }
+    Ok(value)
+}
+
+fn parse_transaction(semantic_commit: SemanticCommit) -> Result<Transaction, FormatError> {
+    let (text, trailers) = parse_trailers(&semantic_commit.body);
+    let author = find_trailer(&trailers, AUTHOR_TRAILER)?
+        .ok_or_else(|| FormatError::MissingTrailer(AUTHOR_TRAILER.to_owned()))?;
+    let author: PublicKey = serde_json::from_str(author)
+        .map_err(|e| FormatError::InvalidTrailer(format!("{}: {}", AUTHOR_TRAILER, e)))?;
+    let timestamp = find_trailer(&trailers, TIMESTAMP_TRAILER)?
+        .ok_or_else(|| FormatError::MissingTrailer(TIMESTAMP_TRAILER.to_owned()))?
+        .parse::<Timestamp>()
+        .map_err(|e| FormatError::InvalidTrailer(format!("{}: {}", TIMESTAMP_TRAILER, e)))?;
+    let diff_hash = find_trailer(&trailers, DIFF_TRAILER)?
+        .map(|hash| {
+            hex::decode(hash)
+                .ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes.as_slice()).ok())
+                .map(|hash| Hash256 { hash })
+                .ok_or_else(|| FormatError::InvalidTrailer(format!("{}: {}", DIFF_TRAILER, hash)))
+        })
+        .transpose()?;
+    let diff = match (semantic_commit.reserved_state, diff_hash) {
+        (Some(reserved_state), Some(hash)) => {
+            let changes = find_trailer(&trailers, RESERVED_CHANGES_TRAILER)?
+                .ok_or_else(|| FormatError::MissingTrailer(RESERVED_CHANGES_TRAILER.to_owned()))?;
+            let changes = serde_json::from_str(changes).map_err(|e| {
+                FormatError::InvalidTrailer(format!("{}: {}", RESERVED_CHANGES_TRAILER, e))
+            })?;
+            let diff = Diff::Reserved(Box::new(reserved_state), changes);
+            if diff.hash() != Some(hash) {
+                return Err(FormatError::Mismatch(format!(
+                    "{} doesn't match {}",
+                    DIFF_TRAILER, RESERVED_CHANGES_TRAILER
+                )));
+            }
+            diff
+        }
+        (Some(_), None) => return Err(FormatError::MissingTrailer(DIFF_TRAILER.to_owned())),
+        (None, Some(hash)) => Diff::General(hash),
+        (None, None) => Diff::None,
+    };
+    Ok(Transaction {
+        author,
+        timestamp,
+        head: semantic_commit.title,
+        body: text.to_owned(),
+        diff,
+    })
+}
+
+/// Decodes the commit, checking its title against its body,
+/// and returns it with the height in the title (`None` for a transaction, whose title has none).
+pub fn decode_semantic_commit(
+    semantic_commit: SemanticCommit,
+) -> Result<(Commit, Option<BlockHeight>), FormatError> {
+    let (commit_type, height, hash) = match parse_title(&semantic_commit.title)? {
+        Some(x) => x,
+        None => {
+            return Ok((
+                Commit::Transaction(parse_transaction(semantic_commit)?),
+                None,
+            ))
+        }
+    };
+    let invalid_body = |e: serde_json::Error| FormatError::InvalidBody(e.to_string());
+    let body = semantic_commit.body.as_str();
+    let commit = match commit_type {
+        "block" => Commit::Block(serde_json::from_str(body).map_err(invalid_body)?),
+        "agenda" => Commit::Agenda(serde_json::from_str(body).map_err(invalid_body)?),
+        "agenda-proof" => Commit::AgendaProof(serde_json::from_str(body).map_err(invalid_body)?),
+        "chat" => Commit::ChatLog(serde_json::from_str(body).map_err(invalid_body)?),
+        _ => Commit::ExtraAgendaTransaction(serde_json::from_str(body).map_err(invalid_body)?),
+    };
+    if commit_type != self::commit_type(&commit) {
+        return Err(FormatError::Mismatch(format!(
+            "the title is of {}, but the body is of {}",
+            commit_type,
+            self::commit_type(&commit)
+        )));
+    }
+    if hash != commit_hash(&commit) {
+        return Err(FormatError::Mismatch(format!(
+            "the title has the hash {}, but the body has {}",
+            hash,
+            commit_hash(&commit)
+        )));
+    }
+    Ok((commit, Some(height)))
+}
+
 pub fn from_semantic_commit(
-    _semantic_commit: SemanticCommit,
-    _last_header: &BlockHeader,
-) -> Result<Commit, String> {
-    todo!()
+    semantic_commit: SemanticCommit,
+    last_header: &BlockHeader,
+) -> Result<Commit, FormatError> {
+    let (commit, height) = decode_semantic_commit(semantic_commit)?;
+    if let Some(height) = height {
+        if Some(height) != last_header.height.checked_add(1) {
+            return Err(FormatError::Mismatch(format!(
+                "the commit is at height {}, but the last block is at {}",
+                height, last_header.height
+            )));
+        }
+    }
+    Ok(commit)
+}
+
+/// Decodes a block commit without the last header,
+/// checking that the title is at the height of the block itself.
+///
+/// This is for reading a block anywhere in the history, where each was committed
+/// after a different last header.
+pub fn block_from_semantic_commit(
+    semantic_commit: SemanticCommit,
+) -> Result<BlockHeader, FormatError> {
+    match decode_semantic_commit(semantic_commit)? {
+        (Commit::Block(header), Some(height)) if height == header.height => Ok(header),
+        (Commit::Block(header), height) => Err(FormatError::Mismatch(format!(
+            "the block at height {} has the height {:?} in the title",
+            header.height, height
+        ))),
+        (commit, _) => Err(FormatError::Mismatch(format!(
+            "the commit is of {}, not a block",
+            commit_type(&commit)
+        ))),
+    }
+}
+
+/// Reads the genesis reserved state of the root commit (see `to_genesis_semantic_commit()`).
+pub fn genesis_from_semantic_commit(
+    semantic_commit: SemanticCommit,
+) -> Result<ReservedState, FormatError> {
+    let reserved_state = semantic_commit
+        .reserved_state
+        .ok_or_else(|| FormatError::InvalidBody("no reserved state".to_owned()))?;
+    let title = format!("genesis: {}", reserved_state.genesis_info.chain_name);
+    if semantic_commit.title != title {
+        return Err(FormatError::InvalidTitle(format!(
+            "{:?} is not {:?}",
+            semantic_commit.title, title
+        )));
+    }
+    Ok(reserved_state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_common::test_util::header;
+
+    #[test]
+    fn round_trip() {
+        let last_header = header(3);
+        let commits = vec![
+            Commit::Block(header(4)),
+            Commit::Transaction(Transaction {
+                author: generate_keypair("a").0,
+                timestamp: 1,
+                head: "Add a file".to_owned(),
+                body: "Some text.\n\nKey: a paragraph like trailers".to_owned(),
+                diff: Diff::General(Hash256::hash("diff")),
+            }),
+            Commit::Transaction(Transaction {
+                author: generate_keypair("b").0,
+                timestamp: 2,
+                head: "Empty".to_owned(),
+                body: String::new(),
+                diff: Diff::None,
+            }),
+            Commit::Transaction(Transaction {
+                author: generate_keypair("a").0,
+                timestamp: 3,
+                head: "Upgrade".to_owned(),
+                body: String::new(),
+                diff: Diff::Reserved(
+                    Box::new(simperby_common::test_util::genesis(&["a"])),
+                    vec![reserved::ReservedStateChange::BumpVersion(
+                        "0.2.0".to_owned(),
+                    )],
+                ),
+            }),
+            Commit::Agenda(Agenda {
+                author: generate_keypair("a").0,
+                timestamp: 3,
+                hash: Hash256::hash("agenda"),
+                expiration_height: None,
+                expiration_timestamp: None,
+                supersedes: Some(Hash256::hash("previous agenda")),
+                amendment_proof: Some(
+                    TypedSignature::sign(
+                        &(Hash256::hash("previous agenda"), Hash256::hash("agenda")),
+                        &generate_keypair("a").1,
+                    )
+                    .unwrap(),
+                ),
+            }),
+            Commit::ChatLog(ChatLog {
+                messages: Vec::new(),
+            }),
+        ];
+        for commit in commits {
+            let semantic_commit = to_semantic_commit(&commit, &last_header);
+            assert_eq!(
+                from_semantic_commit(semantic_commit, &last_header).unwrap(),
+                commit
+            );
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let last_header = header(3);
+        let chat = to_semantic_commit(
+            &Commit::ChatLog(ChatLog {
+                messages: Vec::new(),
+            }),
+            &last_header,
+        );
+        let parse = |title: &str, body: &str| {
+            from_semantic_commit(
+                SemanticCommit {
+                    title: title.to_owned(),
+                    body: body.to_owned(),
+                    reserved_state: None,
+                },
+                &last_header,
+            )
+        };
+        assert!(matches!(
+            parse("chat: 4", &chat.body),
+            Err(FormatError::InvalidTitle(_))
+        ));
+        assert!(matches!(
+            parse("chat: 99999999999999999999999/00", &chat.body),
+            Err(FormatError::InvalidTitle(_))
+        ));
+        assert!(matches!(
+            parse(&chat.title, "{"),
+            Err(FormatError::InvalidBody(_))
+        ));
+        assert!(matches!(
+            parse(&chat.title.replace("chat: 4", "chat: 5"), &chat.body),
+            Err(FormatError::Mismatch(_))
+        ));
+        assert!(matches!(
+            parse(&chat.title.replace("chat", "block"), &chat.body),
+            Err(FormatError::InvalidBody(_))
+        ));
+        assert!(matches!(
+            parse("A transaction", "No trailers"),
+            Err(FormatError::MissingTrailer(_))
+        ));
+        assert!(matches!(
+            parse("A transaction", "Simperby-Author: ?\nSimperby-Timestamp: 1"),
+            Err(FormatError::InvalidTrailer(_))
+        ));
+        assert_eq!(
+            parse_trailers("Text\n\nA: 1\nB-2: x y\n"),
+            ("Text", vec![("A", "1"), ("B-2", "x y")])
+        );
+        assert_eq!(parse_trailers("Text\n\nA: 1\nnot a trailer").1, vec![]);
+    }
+
+    #[test]
+    fn history() {
+        let block = to_semantic_commit(&Commit::Block(header(4)), &header(3));
+        assert_eq!(
+            block_from_semantic_commit(block.clone()).unwrap(),
+            header(4)
+        );
+        assert!(from_semantic_commit(block, &header(5)).is_err());
+        let misplaced = to_semantic_commit(&Commit::Block(header(4)), &header(4));
+        assert!(matches!(
+            block_from_semantic_commit(misplaced),
+            Err(FormatError::Mismatch(_))
+        ));
+        let chat = to_semantic_commit(
+            &Commit::ChatLog(ChatLog {
+                messages: Vec::new(),
+            }),
+            &header(3),
+        );
+        assert_eq!(decode_semantic_commit(chat.clone()).unwrap().1, Some(4));
+        assert!(matches!(
+            block_from_semantic_commit(chat),
+            Err(FormatError::Mismatch(_))
+        ));
+
+        let reserved_state = simperby_common::test_util::genesis(&["a"]);
+        let genesis = to_genesis_semantic_commit(&reserved_state, None);
+        assert_eq!(
+            genesis_from_semantic_commit(genesis.clone()).unwrap(),
+            reserved_state
+        );
+        genesis_from_semantic_commit(SemanticCommit {
+            reserved_state: None,
+            ..genesis
+        })
+        .unwrap_err();
+    }
+
+    #[test]
+    fn genesis_provenance() {
+        let commit = CommitHash { hash: [7; 20] };
+        let body = format!(
+            "{{}}\n\n{}: {} /srv/git/old project",
+            IMPORTED_FROM_TRAILER,
+            hex::encode(commit.hash)
+        );
+        assert_eq!(
+            parse_genesis_provenance(&body).unwrap(),
+            Some(GenesisProvenance {
+                source: "/srv/git/old project".to_owned(),
+                commit,
+            })
+        );
+        assert_eq!(parse_genesis_provenance("{}").unwrap(), None);
+        parse_genesis_provenance(&format!("{{}}\n\n{}: 1234 x", IMPORTED_FROM_TRAILER))
+            .unwrap_err();
+    }
 }
@@ -0,0 +1,150 @@
+//! A write-ahead journal for the high-level mutations of `DistributedRepository`.
+//!
+//! Before an operation that moves branches is performed, its intent is recorded
+//! under `.simperby/journal` together with the branch positions before the operation.
+//! The entry is removed once the operation is done, so every entry that
+//! survives a restart denotes an interrupted operation.
+use super::*;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+pub const JOURNAL_DIRECTORY: &str = ".simperby/journal";
+
+/// A high-level operation that is recorded in the journal.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Operation {
+    /// Creates an agenda commit on top of the `work` branch.
+    ///
+    /// `title` is the title of the agenda commit to be created.
+    CreateAgenda { title: String },
+    /// Creates a transaction commit on top of the `work` branch.
+    ///
+    /// `title` is the title of the transaction commit to be created.
+    CreateTransaction { title: String },
+    /// Creates a chat-log commit on top of the `work` branch.
+    ///
+    /// `title` is the title of the chat-log commit to be created.
+    CreateChatLog { title: String },
+    /// Creates an extra-agenda transaction commit on top of the `work` branch.
+    ///
+    /// `title` is the title of the extra-agenda transaction commit to be created.
+    CreateExtraAgendaTransaction { title: String },
+    /// Moves the `main` branch to the given block commit.
+    Finalize { block_commit_hash: CommitHash },
+    /// Parks the transactions of the `work` branch as the draft of the name,
+    /// which is created last.
+    CreateDraft { name: String },
+    /// Brings the draft of the name onto the `work` branch, which is removed last.
+    LoadDraft { name: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub operation: Operation,
+    /// The positions of the branches before the operation.
+    ///
+    /// These are restored when the operation is rolled back.
+    pub branches: Vec<(Branch, CommitHash)>,
+}
+
+/// The journal backed by the local file system.
+pub struct Journal {
+    directory: PathBuf,
+    next_sequence: u64,
+}
+
+fn entry_file_name(sequence: u64) -> String {
+    format!("{:020}.json", sequence)
+}
+
+impl Journal {
+    /// Opens the journal of the repository in the given directory, creating it if absent.
+    pub async fn open(repository_directory: &str) -> Result<Self, Error> {
+        let directory = Path::new(repository_directory).join(JOURNAL_DIRECTORY);
+        fs::create_dir_all(&directory).await?;
+        exclude_from_working_tree(repository_directory).await?;
+        let mut journal = Self {
+            directory,
+            next_sequence: 0,
+        };
+        journal.next_sequence = journal
+            .pending()
+            .await?
+            .last()
+            .map(|entry| entry.sequence + 1)
+            .unwrap_or(0);
+        Ok(journal)
+    }
+
+    /// Durably records the intent of the operation before it is performed.
+    pub async fn begin(
+        &mut self,
+        operation: Operation,
+        branches: Vec<(Branch, CommitHash)>,
+    ) -> Result<JournalEntry, Error> {
+        let entry = JournalEntry {
+            sequence: self.next_sequence,
+            operation,
+            branches,
+        };
+        let path = self.directory.join(entry_file_name(entry.sequence));
+        let temp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&temp_path).await?;
+        file.write_all(serde_json::to_string(&entry)?.as_bytes())
+            .await?;
+        file.sync_all().await?;
+        // The rename is atomic, so a torn entry is never observed.
+        fs::rename(&temp_path, &path).await?;
+        self.next_sequence += 1;
+        Ok(entry)
+    }
+
+    /// Marks the operation as done, removing its entry.
+    pub async fn complete(&mut self, entry: &JournalEntry) -> Result<(), Error> {
+        fs::remove_file(self.directory.join(entry_file_name(entry.sequence))).await?;
+        Ok(())
+    }
+
+    /// Returns the entries of the interrupted operations, in the order they were recorded.
+    pub async fn pending(&self) -> Result<Vec<JournalEntry>, Error> {
+        let mut entries = Vec::new();
+        let mut dir = fs::read_dir(&self.directory).await?;
+        while let Some(file) = dir.next_entry().await? {
+            let path = file.path();
+            if path.extension().and_then(|x| x.to_str()) == Some("json") {
+                entries.push(serde_json::from_str::<JournalEntry>(
+                    &fs::read_to_string(&path).await?,
+                )?);
+            } else {
+                // A leftover of an entry which was never recorded.
+                fs::remove_file(&path).await?;
+            }
+        }
+        entries.sort_by_key(|entry| entry.sequence);
+        Ok(entries)
+    }
+}
+
+/// Keeps `.simperby` out of the working tree so that cleaning it never removes the journal.
+async fn exclude_from_working_tree(repository_directory: &str) -> Result<(), Error> {
+    let exclude_file = Path::new(repository_directory).join(".git/info/exclude");
+    let content = fs::read_to_string(&exclude_file).await.unwrap_or_default();
+    if content.lines().any(|line| line.trim() == "/.simperby/") {
+        return Ok(());
+    }
+    if let Some(parent) = exclude_file.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&exclude_file)
+        .await?;
+    if !content.is_empty() && !content.ends_with('\n') {
+        file.write_all(b"\n").await?;
+    }
+    file.write_all(b"/.simperby/\n").await?;
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! The policy for large files in the repository.
+//!
+//! Every clone carries the whole history, so the size of the blobs a commit may add is limited.
+//! A payload exceeding the limit can instead be kept in the side-store, which is a
+//! content-addressed directory outside of Git; the commit then records only a pointer
+//! file holding the hash of the payload.
+use super::*;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+pub const SIDE_STORE_DIRECTORY: &str = ".simperby/side-store";
+/// The prefix of a pointer file that refers to a payload in the side-store.
+pub const POINTER_PREFIX: &str = "simperby-side-store:";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SizeLimits {
+    /// The maximum size of a single blob added or modified by a commit, in bytes.
+    pub max_blob_size: u64,
+    /// The maximum total size of the blobs added or modified by a commit, in bytes.
+    pub max_commit_diff_size: u64,
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        Self {
+            max_blob_size: 1024 * 1024,
+            max_commit_diff_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl SizeLimits {
+    /// Checks the `(path, size)` of the files changed by a commit.
+    pub fn check(&self, changed_files: &[(String, u64)]) -> Result<(), String> {
+        for (path, size) in changed_files {
+            if *size > self.max_blob_size {
+                return Err(format!(
+                    "file {} is {} bytes, exceeding the limit of {} bytes",
+                    path, size, self.max_blob_size
+                ));
+            }
+        }
+        let total: u64 = changed_files.iter().map(|(_, size)| size).sum();
+        if total > self.max_commit_diff_size {
+            return Err(format!(
+                "the diff is {} bytes, exceeding the limit of {} bytes",
+                total, self.max_commit_diff_size
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the content of the pointer file that refers to the payload of the given hash.
+pub fn to_pointer(hash: &Hash256) -> String {
+    format!("{}{}\n", POINTER_PREFIX, hash)
+}
+
+/// Returns the hash that the pointer file refers to, or `None` if it's not a pointer file.
+pub fn parse_pointer(content: &str) -> Option<Hash256> {
+    let hash = hex::decode(content.trim_end().strip_prefix(POINTER_PREFIX)?).ok()?;
+    Some(Hash256 {
+        hash: hash.try_into().ok()?,
+    })
+}
+
+/// The content-addressed store for the oversized payloads, backed by the local file system.
+pub struct SideStore {
+    directory: PathBuf,
+}
+
+impl SideStore {
+    /// Opens the side-store of the repository in the given directory, creating it if absent.
+    pub async fn open(repository_directory: &str) -> Result<Self, Error> {
+        let directory = Path::new(repository_directory).join(SIDE_STORE_DIRECTORY);
+        fs::create_dir_all(&directory).await?;
+        Ok(Self { directory })
+    }
+
+    /// Stores the payload and returns its hash, which is to be recorded with `to_pointer()`.
+    pub async fn put(&self, payload: &[u8]) -> Result<Hash256, Error> {
+        let hash = Hash256::hash(payload);
+        let path = self.directory.join(hash.to_string());
+        if fs::metadata(&path).await.is_err() {
+            let temp_path = path.with_extension("tmp");
+            fs::write(&temp_path, payload).await?;
+            fs::rename(&temp_path, &path).await?;
+        }
+        Ok(hash)
+    }
+
+    /// Loads the payload of the given hash, verifying its content.
+    pub async fn get(&self, hash: &Hash256) -> Result<Vec<u8>, Error> {
+        let payload = fs::read(self.directory.join(hash.to_string())).await?;
+        if Hash256::hash(&payload) != *hash {
+            return Err(anyhow!("payload {} in the side-store is corrupted", hash));
+        }
+        Ok(payload)
+    }
+
+    /// Returns whether the payload of the given hash exists.
+    pub async fn contains(&self, hash: &Hash256) -> bool {
+        fs::metadata(self.directory.join(hash.to_string()))
+            .await
+            .is_ok()
+    }
+}
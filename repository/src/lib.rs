@@ -1,21 +1,62 @@
+pub mod authoring;
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
+pub mod drafts;
 pub mod format;
+pub mod journal;
+pub mod large_file;
 pub mod raw;
+pub mod signature_cache;
+pub mod state_cache;
+pub mod telemetry;
 
 use anyhow::anyhow;
+use drafts::{Draft, DRAFT_REF_PREFIX};
 use format::*;
 use futures::prelude::*;
+use journal::{Journal, Operation};
+use large_file::{SideStore, SizeLimits};
 use raw::RawRepository;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use signature_cache::PersistedSignatureCache;
+use simperby_common::reserved::{ChainParameters, ReservedState};
 use simperby_common::verify::CommitSequenceVerifier;
 use simperby_common::*;
+use simperby_network::clock::{system_clock, Clock};
+use simperby_network::signer::Signer;
 use simperby_network::{NetworkConfig, Peer, SharedKnownPeers};
+use state_cache::StateCache;
 use std::fmt;
+use std::sync::Arc;
 
 pub type Branch = String;
 pub type Tag = String;
 
 pub const FINALIZED_BRANCH_NAME: &str = "main";
 pub const WORK_BRANCH_NAME: &str = "work";
+/// The block proposal of this node.
+pub const PROPOSAL_BRANCH_NAME: &str = "p";
+/// The remote of the repository that a repository is cloned from (see `clone_from()`).
+pub const CLONE_REMOTE_NAME: &str = "origin";
+/// The directory of the working tree where the reserved state is stored.
+pub const RESERVED_DIRECTORY: &str = "reserved";
+/// The namespace of the note on a superseded agenda commit, holding the commit of its amendment
+/// (see `DistributedRepository::amend_agenda()`).
+pub const SUPERSEDED_BY_NOTE_NAMESPACE: &str = "superseded-by";
+/// The number of the commits decoded and verified together in `verify_history()`.
+const VERIFICATION_BATCH_SIZE: usize = 1024;
+/// The number of the ancestors listed at once while walking back the history
+/// (see `DistributedRepository::walk_back()`).
+const HISTORY_CHUNK_SIZE: usize = 256;
+
+/// Returns the height of the block commit from its message, or `None` if it's not a block.
+fn block_height_in_title(message: &str) -> Option<BlockHeight> {
+    match parse_title(message.lines().next().unwrap_or_default()) {
+        Ok(Some(("block", height, _))) => Some(height),
+        _ => None,
+    }
+}
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone, Serialize, Deserialize, Hash)]
 pub struct CommitHash {
@@ -28,41 +69,1210 @@ impl fmt::Display for CommitHash {
     }
 }
 
+impl std::str::FromStr for CommitHash {
+    type Err = Error;
+
+    /// Parses the full hexadecimal commit hash.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|e| anyhow!("invalid commit hash {}: {}", s, e))?;
+        let hash = <[u8; 20]>::try_from(bytes.as_slice())
+            .map_err(|_| anyhow!("invalid commit hash {}: not 20 bytes", s))?;
+        Ok(CommitHash { hash })
+    }
+}
+
 pub type Error = anyhow::Error;
 
+/// The history retained by a branch (see `DistributedRepository::get_branch_usage()`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BranchUsage {
+    pub branch: Branch,
+    /// The commits reachable from the branch.
+    pub commits: usize,
+    /// The commits of the branch not on the `main` branch.
+    pub unfinalized_commits: usize,
+}
+
+/// The result of `DistributedRepository::check_integrity()`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    pub object_store: raw::ObjectStoreReport,
+    /// The violations of the Simperby-specific invariants
+    /// (e.g., a single root commit and the linear history of the `main` branch).
+    pub violations: Vec<String>,
+}
+
+impl IntegrityReport {
+    pub fn is_ok(&self) -> bool {
+        self.object_store.is_ok() && self.violations.is_empty()
+    }
+}
+
+/// The type of a Simperby commit (see `DistributedRepository::inspect()`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommitKind {
+    Genesis,
+    Block,
+    Transaction,
+    Agenda,
+    AgendaProof,
+    ExtraAgendaTransaction,
+    ChatLog,
+}
+
+impl From<&Commit> for CommitKind {
+    fn from(commit: &Commit) -> Self {
+        match commit {
+            Commit::Block(_) => CommitKind::Block,
+            Commit::Transaction(_) => CommitKind::Transaction,
+            Commit::Agenda(_) => CommitKind::Agenda,
+            Commit::AgendaProof(_) => CommitKind::AgendaProof,
+            Commit::ExtraAgendaTransaction(_) => CommitKind::ExtraAgendaTransaction,
+            Commit::ChatLog(_) => CommitKind::ChatLog,
+        }
+    }
+}
+
+/// The result of `DistributedRepository::inspect()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommitInfo {
+    pub commit_hash: CommitHash,
+    pub kind: CommitKind,
+    pub title: String,
+    /// The decoded commit; `None` for the genesis commit, which carries the reserved state only.
+    pub commit: Option<Commit>,
+    /// The `(path, size)` of the files added or modified by the commit.
+    pub changed_files: Vec<(String, u64)>,
+    /// The signers of the commit: the genesis proof, the finalization proof of a block
+    /// (once the next block carries it), the approvals of an agenda, the proofs of
+    /// an extra-agenda transaction, or the authors of a chat log.
+    pub signers: Vec<PublicKey>,
+    /// Whether the commit is in the history of the `main` branch.
+    pub finalized: bool,
+}
+
+/// A point of the finalized history, for `DistributedRepository::reserved_state_at()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FinalizedPoint {
+    /// The block of the height.
+    Height(BlockHeight),
+    /// The commit, which may be a non-block commit between two blocks.
+    Commit(CommitHash),
+}
+
+/// A commit of the `main` branch, read by `DistributedRepository::read_finalized_history()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub commit_hash: CommitHash,
+    pub title: String,
+    /// The decoded commit; `None` for the genesis commit.
+    pub commit: Option<Commit>,
+    /// The new reserved state, if the commit changed it (always for the genesis commit).
+    pub reserved_state: Option<ReservedState>,
+}
+
 /// The local Simperby blockchain data repository.
 ///
 /// It automatically locks the repository once created.
 ///
 /// - It **verifies** all the incoming changes and applies them to the local repository
 /// only if they are valid.
+/// - It journals the operations that move branches, and recovers the interrupted ones
+/// (e.g., by a power loss) when opened.
+/// - It enforces the size limits of the commits (see `large_file`).
+/// - It remembers the signatures it has verified (see `signature_cache`).
 pub struct DistributedRepository<T> {
     raw: T,
+    journal: Journal,
+    size_limits: SizeLimits,
+    side_store: SideStore,
+    state_cache: StateCache,
+    signature_cache: PersistedSignatureCache,
+    clock: Arc<dyn Clock>,
 }
 
-fn get_timestamp() -> Timestamp {
-    let now = std::time::SystemTime::now();
-    let since_the_epoch = now.duration_since(std::time::UNIX_EPOCH).unwrap();
-    since_the_epoch.as_millis() as Timestamp
+/// Checks that the reserved state on the tree of the applied commit is the one of the verifier.
+///
+/// Other than the transactions, which carry their reserved states in `Diff::Reserved`,
+/// only the extra-agenda transactions change the reserved state on the tree.
+fn verify_carried_reserved_state(
+    commit: &Commit,
+    carried: Option<&ReservedState>,
+    verifier: &CommitSequenceVerifier,
+) -> Result<(), Error> {
+    match (commit, carried) {
+        (Commit::Transaction(_), _) | (Commit::ExtraAgendaTransaction(_), None) => Ok(()),
+        (Commit::ExtraAgendaTransaction(_), Some(carried))
+            if carried == verifier.get_reserved_state() =>
+        {
+            Ok(())
+        }
+        (Commit::ExtraAgendaTransaction(_), Some(_)) => Err(anyhow!(
+            "the reserved state on the tree is not the one of the extra-agenda transaction"
+        )),
+        (_, None) => Ok(()),
+        (_, Some(_)) => Err(anyhow!("the commit changes the reserved state")),
+    }
+}
+
+/// Verifies the signatures of the commits in parallel, on a blocking thread.
+///
+/// If more than one commit has an invalid signature, the earliest is reported.
+/// The signatures in the cache are not verified again.
+async fn verify_signatures(
+    commits: Vec<(CommitHash, Vec<verify::SignatureCheck>)>,
+    cache: Arc<simperby_common::signature_cache::SignatureCache>,
+) -> Result<(), Error> {
+    tokio::task::spawn_blocking(move || {
+        let failure = commits
+            .par_iter()
+            .enumerate()
+            .filter_map(|(index, (hash, checks))| {
+                checks
+                    .iter()
+                    .find_map(|check| check.verify_cached(&cache).err())
+                    .map(|e| (index, *hash, e))
+            })
+            .min_by_key(|(index, _, _)| *index);
+        match failure {
+            Some((_, hash, e)) => {
+                telemetry::record_verification_failure("signature");
+                Err(anyhow!(
+                    "invalid signature in commit {}: {}",
+                    hex::encode(hash.hash),
+                    e
+                ))
+            }
+            None => Ok(()),
+        }
+    })
+    .await?
 }
 
 impl<T: RawRepository> DistributedRepository<T> {
-    pub async fn new(_raw: T) -> Result<Self, Error> {
-        unimplemented!()
+    pub async fn new(raw: T) -> Result<Self, Error> {
+        let directory = raw.get_working_directory_path().await?;
+        let journal = Journal::open(&directory).await?;
+        let side_store = SideStore::open(&directory).await?;
+        let state_cache = StateCache::open(&directory).await?;
+        let signature_cache = PersistedSignatureCache::open(&directory).await?;
+        let mut repository = Self {
+            raw,
+            journal,
+            size_limits: SizeLimits::default(),
+            side_store,
+            state_cache,
+            signature_cache,
+            clock: system_clock(),
+        };
+        repository.recover().await?;
+        Ok(repository)
+    }
+
+    /// Completes or rolls back the operations that were interrupted.
+    ///
+    /// An operation is regarded as completed if its result is already observed
+    /// in the repository; otherwise the branches are restored to the journaled positions.
+    async fn recover(&mut self) -> Result<(), Error> {
+        for entry in self.journal.pending().await? {
+            let completed = match &entry.operation {
+                Operation::Finalize { block_commit_hash } => {
+                    self.raw
+                        .locate_branch(&FINALIZED_BRANCH_NAME.into())
+                        .await?
+                        == *block_commit_hash
+                }
+                Operation::CreateAgenda { title }
+                | Operation::CreateTransaction { title }
+                | Operation::CreateChatLog { title }
+                | Operation::CreateExtraAgendaTransaction { title } => {
+                    let work_commit = self.raw.locate_branch(&WORK_BRANCH_NAME.into()).await?;
+                    let moved = entry.branches.iter().any(|(branch, commit_hash)| {
+                        branch == WORK_BRANCH_NAME && *commit_hash != work_commit
+                    });
+                    moved && self.raw.read_semantic_commit(&work_commit).await?.title == *title
+                }
+                Operation::CreateDraft { name } => self.draft_exists(name).await?,
+                Operation::LoadDraft { name } => !self.draft_exists(name).await?,
+            };
+            if completed {
+                log::info!("completed an interrupted operation: {:?}", entry.operation);
+            } else {
+                log::warn!(
+                    "rolling back an interrupted operation: {:?}",
+                    entry.operation
+                );
+                for (branch, commit_hash) in &entry.branches {
+                    self.raw.move_branch(branch, commit_hash).await?;
+                }
+                self.raw.checkout_clean().await?;
+            }
+            self.journal.complete(&entry).await?;
+        }
+        Ok(())
+    }
+
+    /// Overrides the default size limits of the commits.
+    pub fn set_size_limits(&mut self, size_limits: SizeLimits) {
+        self.size_limits = size_limits;
+    }
+
+    /// Overrides the clock that stamps the new commits and expires the agendas,
+    /// which is the system clock by default.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Overrides the default interval of the blocks at which the reserved states are cached
+    /// (see `reserved_state_at()`).
+    pub fn set_state_cache_interval(&mut self, interval: BlockHeight) {
+        self.state_cache.set_interval(interval);
+    }
+
+    /// Returns the cache of the signatures verified in this repository,
+    /// to be shared with the other verifications of the node.
+    pub fn signature_cache(&self) -> Arc<simperby_common::signature_cache::SignatureCache> {
+        self.signature_cache.cache()
+    }
+
+    /// Returns the content-addressed store for the payloads exceeding the size limits.
+    pub fn side_store(&self) -> &SideStore {
+        &self.side_store
+    }
+
+    /// Checks whether the given commit conforms to the size limits.
+    ///
+    /// This must be applied to every commit created locally and to every fetched commit
+    /// before it's accepted.
+    pub async fn verify_size_limits(&self, commit_hash: &CommitHash) -> Result<(), Error> {
+        let sizes = self.raw.read_changed_file_sizes(commit_hash).await?;
+        self.size_limits.check(&sizes).map_err(|e| {
+            telemetry::record_verification_failure("size_limits");
+            anyhow!("commit {} exceeds the size limits: {}", commit_hash, e)
+        })
+    }
+
+    /// Checks whether the agenda or the block of the given commit, proposed on top of
+    /// the last finalized block, conforms to the limits of the chain parameters
+    /// (see `ChainParameters::check_proposal()`).
+    ///
+    /// This must be applied to every proposal from the peers before it's voted for;
+    /// the ones created locally are checked when created.
+    pub async fn verify_proposal_limits(&self, commit_hash: &CommitHash) -> Result<(), Error> {
+        let last_header = self.get_last_finalized_block_header().await?;
+        let last_header_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let ancestors = self.raw.list_ancestors(commit_hash, Some(256)).await?;
+        let position = ancestors
+            .iter()
+            .position(|c| *c == last_header_commit)
+            .ok_or_else(|| {
+                anyhow!(
+                    "commit {} is not on top of branch {}",
+                    commit_hash,
+                    FINALIZED_BRANCH_NAME
+                )
+            })?;
+        let mut commits = Vec::with_capacity(position + 1);
+        for hash in ancestors
+            .iter()
+            .take(position)
+            .rev()
+            .chain(std::iter::once(commit_hash))
+        {
+            let commit =
+                from_semantic_commit(self.raw.read_semantic_commit(hash).await?, &last_header)
+                    .map_err(|e| anyhow!("failed to convert the commit {}: {}", hash, e))?;
+            commits.push((commit, *hash));
+        }
+        let parameters = self.get_reserved_state().await?.parameters;
+        self.check_proposal_limits(&parameters, &commits).await
+    }
+
+    /// Checks the commits since the last finalized block against the limits of the chain.
+    async fn check_proposal_limits(
+        &self,
+        parameters: &ChainParameters,
+        commits: &[(Commit, CommitHash)],
+    ) -> Result<(), Error> {
+        let mut transaction_diff_sizes = Vec::new();
+        let mut body_size = 0;
+        for (commit, hash) in commits {
+            let size: u64 = self
+                .raw
+                .read_changed_file_sizes(hash)
+                .await?
+                .iter()
+                .map(|(_, size)| size)
+                .sum();
+            if let Commit::Transaction(_) = commit {
+                transaction_diff_sizes.push(size);
+            }
+            body_size += size;
+        }
+        parameters
+            .check_proposal(&transaction_diff_sizes, body_size)
+            .map_err(|e| {
+                telemetry::record_verification_failure("proposal_limits");
+                anyhow!("the proposal exceeds the limits of the chain: {}", e)
+            })
+    }
+
+    /// Returns the paths of the files changed by the transactions of the agenda,
+    /// on top of the last finalized block, sorted and without duplicates.
+    ///
+    /// These are the files that the review comments on the agenda can be anchored to.
+    pub async fn read_agenda_changed_files(
+        &self,
+        agenda_commit: &CommitHash,
+    ) -> Result<Vec<String>, Error> {
+        let last_header_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let mut paths = std::collections::BTreeSet::new();
+        for commit_hash in self
+            .list_commits_on_top_of(agenda_commit, &last_header_commit)
+            .await?
+        {
+            for (path, _) in self.raw.read_changed_file_sizes(&commit_hash).await? {
+                paths.insert(path);
+            }
+        }
+        Ok(paths.into_iter().collect())
     }
 
     /// Initializes the genesis repository from the genesis working tree.
     pub async fn genesis(&mut self) -> Result<(), Error> {
         unimplemented!()
     }
-    /// Returns the block header from the `main` branch.
+
+    /// Creates the genesis commit of the genesis reserved state in an empty repository,
+    /// with the `main` and the `work` branches on it.
+    ///
+    /// The commit depends only on the reserved state, so that every participant of
+    /// the genesis ceremony (see `simperby_common::genesis`) creates the same one.
+    pub async fn create_genesis_commit(
+        &mut self,
+        reserved_state: &ReservedState,
+    ) -> Result<CommitHash, Error> {
+        if self.raw.get_initial_commit().await.is_ok() {
+            return Err(anyhow!("the repository is not empty"));
+        }
+        self.create_root_commit(reserved_state, None).await
+    }
+
+    /// Creates the genesis commit like `create_genesis_commit()`, with the tree of
+    /// the commit of an existing repository (a path or a URL) as the genesis working tree.
+    ///
+    /// Only the snapshot is imported, not the untrusted history of the repository;
+    /// the source and the commit are recorded in the body of the genesis commit
+    /// (see `format::parse_genesis_provenance()`). Every participant of the genesis ceremony
+    /// importing the same commit creates the same genesis commit.
+    pub async fn init_from_existing(
+        &mut self,
+        reserved_state: &ReservedState,
+        provenance: &GenesisProvenance,
+    ) -> Result<CommitHash, Error> {
+        if self.raw.get_initial_commit().await.is_ok() {
+            return Err(anyhow!("the repository is not empty"));
+        }
+        if provenance.source.contains('\n') {
+            return Err(anyhow!("the source has a line break"));
+        }
+        self.raw
+            .import_tree(&provenance.source, &provenance.commit)
+            .await?;
+        let files = self.raw.read_staged_changes().await?;
+        let reserved_prefix = format!("{}/", RESERVED_DIRECTORY);
+        if let Some((path, _)) = files
+            .iter()
+            .find(|(path, _)| path == RESERVED_DIRECTORY || path.starts_with(&reserved_prefix))
+        {
+            return Err(anyhow!(
+                "the imported tree has {}, which is reserved for the reserved state",
+                path
+            ));
+        }
+        let sizes: Vec<_> = files
+            .into_iter()
+            .filter_map(|(path, size)| size.map(|size| (path, size)))
+            .collect();
+        self.size_limits
+            .check(&sizes)
+            .map_err(|e| anyhow!("the imported tree exceeds the size limits: {}", e))?;
+        self.create_root_commit(reserved_state, Some(provenance))
+            .await
+    }
+
+    /// Creates the genesis commit of the index, after verifying the genesis proof.
+    async fn create_root_commit(
+        &mut self,
+        reserved_state: &ReservedState,
+        provenance: Option<&GenesisProvenance>,
+    ) -> Result<CommitHash, Error> {
+        let genesis_info = &reserved_state.genesis_info;
+        verify::verify_finalization_proof(
+            &genesis_info.header,
+            &genesis_info.genesis_proof,
+            &genesis_info.header.validator_set,
+        )
+        .map_err(|e| {
+            telemetry::record_verification_failure("genesis_proof");
+            anyhow!("invalid genesis proof: {}", e)
+        })?;
+        let commit_hash = self
+            .raw
+            .create_root_semantic_commit(
+                to_genesis_semantic_commit(reserved_state, provenance),
+                genesis_info.header.timestamp,
+            )
+            .await?;
+        let branches = self.raw.list_branches().await?;
+        for branch in [FINALIZED_BRANCH_NAME, WORK_BRANCH_NAME] {
+            if !branches.iter().any(|x| x == branch) {
+                self.raw.create_branch(&branch.into(), commit_hash).await?;
+            }
+        }
+        Ok(commit_hash)
+    }
+    /// Creates a repository at the directory by cloning the chain from the URL,
+    /// which is registered as the remote `origin`.
+    ///
+    /// It verifies the history of the remote `main` branch from the genesis, or from the given
+    /// checkpoint (a block commit that the user trusts) if any, and then sets up
+    /// the `main` and the `work` branches on it.
+    pub async fn clone_from(
+        directory: &str,
+        url: &str,
+        checkpoint: Option<CommitHash>,
+    ) -> Result<Self, Error> {
+        let mut raw = T::init(directory).await?;
+        raw.add_remote(CLONE_REMOTE_NAME, url).await?;
+        raw.fetch_all().await?;
+        let remote_main = raw
+            .locate_remote_branch(CLONE_REMOTE_NAME, &FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let mut repository = Self::new(raw).await?;
+        repository.verify_history(&remote_main, checkpoint).await?;
+        for branch in [FINALIZED_BRANCH_NAME, WORK_BRANCH_NAME] {
+            repository
+                .raw
+                .create_branch(&branch.into(), remote_main)
+                .await?;
+        }
+        repository
+            .raw
+            .checkout(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        Ok(repository)
+    }
+
+    /// Packs the finalized history (the `main` branch) into a packfile,
+    /// returning the last finalized commit with it.
+    pub async fn export_pack(&self) -> Result<(CommitHash, Vec<u8>), Error> {
+        let head = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let pack = self.raw.create_pack(&head).await?;
+        Ok((head, pack))
+    }
+
+    /// Initializes a repository in the directory from a packfile created by `export_pack()`.
+    ///
+    /// Like `clone_from()`, it verifies the history up to `head` from the genesis
+    /// before setting up the `main` and the `work` branches on it.
+    pub async fn import_pack(
+        directory: &str,
+        pack: &[u8],
+        head: &CommitHash,
+    ) -> Result<Self, Error> {
+        let mut raw = T::init(directory).await?;
+        raw.import_pack(pack).await?;
+        Self::set_up_imported(raw, head).await
+    }
+
+    /// Same as `export_pack()`, but streams the packfile into the file at the path,
+    /// so that the memory is bounded regardless of the size of the history.
+    ///
+    /// Returns the last finalized commit with the size and the hash of the packfile.
+    pub async fn export_pack_to(&self, path: &str) -> Result<(CommitHash, u64, Hash256), Error> {
+        let head = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let (size, hash) = self.raw.write_pack(&head, path).await?;
+        Ok((head, size, hash))
+    }
+
+    /// Same as `import_pack()`, but streams the packfile from the file at the path.
+    pub async fn import_pack_from(
+        directory: &str,
+        path: &str,
+        head: &CommitHash,
+    ) -> Result<Self, Error> {
+        let mut raw = T::init(directory).await?;
+        raw.import_pack_file(path).await?;
+        Self::set_up_imported(raw, head).await
+    }
+
+    /// Verifies the imported history up to `head` and sets up the branches on it.
+    async fn set_up_imported(raw: T, head: &CommitHash) -> Result<Self, Error> {
+        let mut repository = Self::new(raw).await?;
+        repository.verify_history(head, None).await?;
+        for branch in [FINALIZED_BRANCH_NAME, WORK_BRANCH_NAME] {
+            repository.raw.create_branch(&branch.into(), *head).await?;
+        }
+        repository
+            .raw
+            .checkout(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        Ok(repository)
+    }
+
+    /// Verifies the linear history up to the given commit,
+    /// trusting the genesis or the given checkpoint on it.
+    ///
+    /// It is staged for the cold sync of a long history; for each batch of the commits,
+    /// 1. it decodes them in order, since a commit is decoded against the last block header,
+    /// 2. verifies their signatures in parallel (see `verify::signature_checks()`), and
+    /// 3. applies them to the verifier in order.
+    ///
+    /// The commits after the last finalized block of this repository, if any, are verified
+    /// against its open agendas, like `verify_work_branch()`.
+    async fn verify_history(
+        &self,
+        last_commit: &CommitHash,
+        checkpoint: Option<CommitHash>,
+    ) -> Result<(), Error> {
+        let mut history = self.raw.list_ancestors(last_commit, None).await?;
+        history.reverse();
+        history.push(*last_commit);
+        let (genesis_commit, history) = history
+            .split_first()
+            .ok_or_else(|| anyhow!("the history is empty"))?;
+        let genesis_state = self
+            .raw
+            .read_semantic_commit(genesis_commit)
+            .await?
+            .reserved_state
+            .ok_or_else(|| {
+                anyhow!(
+                    "the root commit {} has no reserved state",
+                    hex::encode(genesis_commit.hash)
+                )
+            })?;
+        let genesis_info = genesis_state.genesis_info.clone();
+        let mut last_header = genesis_info.header.clone();
+        // The open agendas are on top of the last finalized block.
+        let finalized = if self
+            .raw
+            .list_branches()
+            .await?
+            .iter()
+            .any(|branch| branch == FINALIZED_BRANCH_NAME)
+        {
+            let commit_hash = self
+                .raw
+                .locate_branch(&FINALIZED_BRANCH_NAME.into())
+                .await?;
+            let open_agendas = self
+                .read_open_agendas()
+                .await?
+                .into_iter()
+                .map(|(_, agenda)| agenda)
+                .collect::<Vec<_>>();
+            Some((commit_hash, open_agendas))
+        } else {
+            None
+        };
+        let set_open_agendas =
+            |hash: &CommitHash, verifier: &mut Option<CommitSequenceVerifier>| {
+                if let (Some((commit_hash, open_agendas)), Some(verifier)) = (&finalized, verifier)
+                {
+                    if commit_hash == hash {
+                        verifier.set_open_agendas(open_agendas.clone());
+                    }
+                }
+            };
+        let mut verifier = match checkpoint {
+            Some(_) => None,
+            None => {
+                verify::verify_finalization_proof(
+                    &last_header,
+                    &genesis_info.genesis_proof,
+                    &last_header.validator_set,
+                )
+                .map_err(|e| {
+                    telemetry::record_verification_failure("genesis_proof");
+                    anyhow!("invalid genesis proof: {}", e)
+                })?;
+                Some(CommitSequenceVerifier::new(
+                    last_header.clone(),
+                    genesis_state.clone(),
+                )?)
+            }
+        };
+        if checkpoint == Some(*genesis_commit) {
+            verifier = Some(CommitSequenceVerifier::new(
+                last_header.clone(),
+                genesis_state,
+            )?);
+        }
+        set_open_agendas(genesis_commit, &mut verifier);
+        let mut decoding_header = last_header.clone();
+        let mut verifying = verifier.is_some();
+        for batch in history.chunks(VERIFICATION_BATCH_SIZE) {
+            // 1. Decodes, marking the commits after the checkpoint to verify.
+            let mut commits = Vec::with_capacity(batch.len());
+            for hash in batch {
+                let semantic_commit = self.raw.read_semantic_commit(hash).await?;
+                let carried = semantic_commit.reserved_state.clone();
+                let commit =
+                    from_semantic_commit(semantic_commit, &decoding_header).map_err(|e| {
+                        anyhow!(
+                            "failed to convert the commit {}: {}",
+                            hex::encode(hash.hash),
+                            e
+                        )
+                    })?;
+                if let Commit::Block(header) = &commit {
+                    decoding_header = header.clone();
+                }
+                commits.push((*hash, commit, carried, verifying));
+                if checkpoint == Some(*hash) {
+                    verifying = true;
+                }
+            }
+
+            // 2. Verifies the signatures in parallel.
+            let checks = commits
+                .iter()
+                .filter(|(_, _, _, verifying)| *verifying)
+                .map(|(hash, commit, _, _)| (*hash, verify::signature_checks(commit)))
+                .collect();
+            verify_signatures(checks, self.signature_cache.cache()).await?;
+
+            // 3. Applies in order, without verifying the signatures again.
+            for (hash, commit, carried, signatures_checked) in commits {
+                if let Some(verifier) = &mut verifier {
+                    self.verify_size_limits(&hash).await?;
+                    let result = if signatures_checked {
+                        verifier.apply_checked_commit(&commit)
+                    } else {
+                        verifier.apply_commit(&commit)
+                    };
+                    result.map_err(|e| {
+                        telemetry::record_verification_failure("commit_sequence");
+                        anyhow!(
+                            "verification error on commit {}: {}",
+                            hex::encode(hash.hash),
+                            e
+                        )
+                    })?;
+                    verify_carried_reserved_state(&commit, carried.as_ref(), verifier).map_err(
+                        |e| {
+                            anyhow!(
+                                "verification error on commit {}: {}",
+                                hex::encode(hash.hash),
+                                e
+                            )
+                        },
+                    )?;
+                }
+                if let Commit::Block(header) = commit {
+                    last_header = header;
+                    if checkpoint == Some(hash) {
+                        let reserved_state = self.read_reserved_state(&hash).await?;
+                        verifier = Some(CommitSequenceVerifier::new(
+                            last_header.clone(),
+                            reserved_state,
+                        )?);
+                    }
+                } else if checkpoint == Some(hash) {
+                    return Err(anyhow!(
+                        "the checkpoint {} is not a block",
+                        hex::encode(hash.hash)
+                    ));
+                }
+                set_open_agendas(&hash, &mut verifier);
+            }
+        }
+        if verifier.is_none() {
+            return Err(anyhow!("the checkpoint is not in the history"));
+        }
+        self.signature_cache.save().await?;
+        Ok(())
+    }
+
+    /// Returns the block header from the `main` branch,
+    /// which is the genesis header if no block has been finalized yet.
     pub async fn get_last_finalized_block_header(&self) -> Result<BlockHeader, Error> {
-        unimplemented!()
+        let commit_hash = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let semantic_commit = self.raw.read_semantic_commit(&commit_hash).await?;
+        let is_root = self.raw.read_commits_bulk(&[commit_hash]).await?[0]
+            .parents
+            .is_empty();
+        if is_root {
+            return genesis_from_semantic_commit(semantic_commit)
+                .map(|reserved_state| reserved_state.genesis_info.header)
+                .map_err(|e| anyhow!("invalid genesis commit: {}", e));
+        }
+        block_from_semantic_commit(semantic_commit).map_err(|e| {
+            anyhow!(
+                "branch {} is not on a block ({}): {}",
+                FINALIZED_BRANCH_NAME,
+                hex::encode(commit_hash.hash),
+                e
+            )
+        })
+    }
+
+    /// Walks back the history from the commit (inclusive), newest first,
+    /// until `stop` holds for a commit (exclusive) or the root commit is reached.
+    ///
+    /// The ancestors are listed in chunks, so a walk that stops early doesn't list the whole history.
+    async fn walk_back(
+        &self,
+        from: &CommitHash,
+        mut stop: impl FnMut(&raw::CommitInfo) -> bool,
+    ) -> Result<Vec<raw::CommitInfo>, Error> {
+        let mut visited = Vec::new();
+        let mut chunk = vec![*from];
+        while !chunk.is_empty() {
+            for info in self.raw.read_commits_bulk(&chunk).await? {
+                if stop(&info) {
+                    return Ok(visited);
+                }
+                visited.push(info);
+            }
+            let last = chunk[chunk.len() - 1];
+            chunk = self
+                .raw
+                .list_ancestors(&last, Some(HISTORY_CHUNK_SIZE))
+                .await?;
+        }
+        Ok(visited)
+    }
+
+    /// Returns the commit of the finalized block of the height,
+    /// walking back the `main` branch no further than the block.
+    ///
+    /// The height 0 is of the genesis commit.
+    pub async fn locate_finalized_block(&self, height: BlockHeight) -> Result<CommitHash, Error> {
+        if height == 0 {
+            return Ok(self.raw.get_initial_commit().await?);
+        }
+        let last_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let mut found = None;
+        self.walk_back(&last_commit, |info| {
+            match block_height_in_title(&info.message) {
+                Some(block_height) if block_height <= height => {
+                    if block_height == height {
+                        found = Some(info.hash);
+                    }
+                    true
+                }
+                _ => false,
+            }
+        })
+        .await?;
+        found.ok_or_else(|| anyhow!("block {} is not finalized", height))
+    }
+
+    /// Returns the reserved state of the last finalized block.
+    pub async fn get_reserved_state(&self) -> Result<ReservedState, Error> {
+        let commit_hash = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        self.reserved_state_at(FinalizedPoint::Commit(commit_hash))
+            .await
+    }
+
+    /// Returns the reserved state as of the given point of the finalized history,
+    /// which is the one of the last commit carrying a reserved state at or before it.
+    ///
+    /// The states as of the blocks on the way are materialized at every interval of heights
+    /// (see `state_cache`), so that a later lookup reads at most an interval of the history.
+    pub async fn reserved_state_at(&self, point: FinalizedPoint) -> Result<ReservedState, Error> {
+        let commit_hash = match point {
+            FinalizedPoint::Height(height) => self.locate_finalized_block(height).await?,
+            FinalizedPoint::Commit(commit_hash) => {
+                let last_commit = self
+                    .raw
+                    .locate_branch(&FINALIZED_BRANCH_NAME.into())
+                    .await?;
+                if self.raw.find_merge_base(&commit_hash, &last_commit).await? != commit_hash {
+                    return Err(anyhow!(
+                        "commit {} is not finalized",
+                        hex::encode(commit_hash.hash)
+                    ));
+                }
+                commit_hash
+            }
+        };
+        self.read_reserved_state(&commit_hash).await
+    }
+
+    /// Reads the reserved state as of the commit, which is the one of the last commit
+    /// carrying a reserved state at or before it.
+    ///
+    /// The states as of the checkpoint blocks on the way are cached.
+    async fn read_reserved_state(&self, commit_hash: &CommitHash) -> Result<ReservedState, Error> {
+        let mut checkpoints = Vec::new();
+        let mut reserved_state = None;
+        let mut chunk = vec![*commit_hash];
+        'walk: while !chunk.is_empty() {
+            for commit_hash in &chunk {
+                if let Some(cached) = self.state_cache.get(commit_hash).await? {
+                    reserved_state = Some(cached);
+                    break 'walk;
+                }
+                let mut semantic_commit = self.raw.read_semantic_commit(commit_hash).await?;
+                if let Some(carried) = semantic_commit.reserved_state.take() {
+                    reserved_state = Some(carried);
+                    break 'walk;
+                }
+                if let Some(height) = block_height_in_title(&semantic_commit.title) {
+                    if self.state_cache.is_checkpoint(height) {
+                        checkpoints.push(*commit_hash);
+                    }
+                }
+            }
+            let last = chunk[chunk.len() - 1];
+            chunk = self
+                .raw
+                .list_ancestors(&last, Some(HISTORY_CHUNK_SIZE))
+                .await?;
+        }
+        let reserved_state = reserved_state.ok_or_else(|| {
+            anyhow!(
+                "no reserved state found in the history of {}",
+                hex::encode(commit_hash.hash)
+            )
+        })?;
+        for commit_hash in checkpoints {
+            self.state_cache.put(&commit_hash, &reserved_state).await?;
+        }
+        Ok(reserved_state)
+    }
+
+    /// Reads the agenda of the given agenda commit.
+    pub async fn read_agenda(&self, agenda_commit_hash: &CommitHash) -> Result<Agenda, Error> {
+        let last_header = self.get_last_finalized_block_header().await?;
+        let semantic_commit = self.raw.read_semantic_commit(agenda_commit_hash).await?;
+        match from_semantic_commit(semantic_commit, &last_header)
+            .map_err(|e| anyhow!("failed to convert the commit {}: {}", agenda_commit_hash, e))?
+        {
+            Commit::Agenda(agenda) => Ok(agenda),
+            _ => Err(anyhow!("commit {} is not an agenda", agenda_commit_hash)),
+        }
+    }
+
+    /// Reads the block header of the given block commit, anywhere in the history.
+    pub async fn read_block(&self, block_commit_hash: &CommitHash) -> Result<BlockHeader, Error> {
+        let semantic_commit = self.raw.read_semantic_commit(block_commit_hash).await?;
+        block_from_semantic_commit(semantic_commit).map_err(|e| {
+            anyhow!(
+                "commit {} is not a block: {}",
+                hex::encode(block_commit_hash.hash),
+                e
+            )
+        })
+    }
+
+    /// Returns the finalized blocks on the `main` branch from the latest, up to `max` if given.
+    pub async fn get_finalized_blocks(
+        &self,
+        max: Option<usize>,
+    ) -> Result<Vec<(CommitHash, BlockHeader)>, Error> {
+        let last_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let mut count = 0;
+        let commits = self
+            .walk_back(&last_commit, |info| {
+                if max.map_or(false, |max| count >= max) {
+                    return true;
+                }
+                if block_height_in_title(&info.message).is_some() {
+                    count += 1;
+                }
+                false
+            })
+            .await?;
+        let mut blocks = Vec::with_capacity(count);
+        for info in commits {
+            if block_height_in_title(&info.message).is_some() {
+                blocks.push((info.hash, self.read_block(&info.hash).await?));
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Resolves a reference given by a user to a commit, trying in order:
+    /// a finalized block height, a branch (e.g., the agenda `a-3`), a tag,
+    /// the hash of a pending agenda, and a commit hash prefix.
+    pub async fn resolve(&self, reference: &str) -> Result<CommitHash, Error> {
+        if let Ok(height) = reference.parse::<BlockHeight>() {
+            if let Ok(commit_hash) = self.locate_finalized_block(height).await {
+                return Ok(commit_hash);
+            }
+        }
+        let branches = self.raw.list_branches().await?;
+        if branches.iter().any(|branch| branch == reference) {
+            return Ok(self.raw.locate_branch(&reference.to_owned()).await?);
+        }
+        if self
+            .raw
+            .list_tags()
+            .await?
+            .iter()
+            .any(|tag| tag == reference)
+        {
+            return Ok(self.raw.locate_tag(&reference.to_owned()).await?);
+        }
+        if reference.len() == 64 {
+            for branch in branches.iter().filter(|branch| branch.starts_with("a-")) {
+                let commit_hash = self.raw.locate_branch(branch).await?;
+                if let Ok(agenda) = self.read_agenda(&commit_hash).await {
+                    if hex::encode(agenda.to_hash256()) == reference.to_lowercase() {
+                        return Ok(commit_hash);
+                    }
+                }
+            }
+        }
+        self.raw
+            .resolve_commit_prefix(reference)
+            .await
+            .map_err(|e| anyhow!("can't resolve {}: {}", reference, e))
+    }
+
+    /// Classifies the commit by its Simperby commit type and collects what is known about it.
+    pub async fn inspect(&self, commit_hash: &CommitHash) -> Result<CommitInfo, Error> {
+        let semantic_commit = self.raw.read_semantic_commit(commit_hash).await?;
+        let title = semantic_commit.title.clone();
+        let last_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let finalized = self.raw.find_merge_base(commit_hash, &last_commit).await? == *commit_hash;
+        let changed_files = self.raw.read_changed_file_sizes(commit_hash).await?;
+
+        if self.raw.read_commits_bulk(&[*commit_hash]).await?[0]
+            .parents
+            .is_empty()
+        {
+            let signers = semantic_commit
+                .reserved_state
+                .map(|x| x.genesis_info.genesis_proof)
+                .unwrap_or_default()
+                .iter()
+                .map(|x| x.signer().clone())
+                .collect();
+            return Ok(CommitInfo {
+                commit_hash: *commit_hash,
+                kind: CommitKind::Genesis,
+                title,
+                commit: None,
+                changed_files,
+                signers,
+                finalized,
+            });
+        }
+
+        let (commit, _) = decode_semantic_commit(semantic_commit)
+            .map_err(|e| anyhow!("failed to convert the commit {}: {}", commit_hash, e))?;
+        let signers = match &commit {
+            Commit::Block(header) if finalized => {
+                // The finalization proof of a block is in the next block.
+                match self.locate_finalized_block(header.height + 1).await {
+                    Ok(next) => self
+                        .read_block(&next)
+                        .await?
+                        .prev_block_finalization_proof
+                        .iter()
+                        .map(|x| x.signer().clone())
+                        .collect(),
+                    Err(_) => Vec::new(),
+                }
+            }
+            Commit::AgendaProof(agenda_proof) => agenda_proof
+                .proof
+                .iter()
+                .map(|(public_key, _)| public_key.clone())
+                .collect(),
+            Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Delegate(tx)) => {
+                vec![tx.proof.signer().clone()]
+            }
+            Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Undelegate(tx)) => {
+                vec![tx.proof.signer().clone()]
+            }
+            Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::RotateKey(tx)) => {
+                vec![tx.proof.signer().clone(), tx.new_key_proof.signer().clone()]
+            }
+            Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::RemoveMember(tx)) => tx
+                .approvals
+                .iter()
+                .map(|approval| approval.signer().clone())
+                .collect(),
+            Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::EmergencyExpel(tx)) => tx
+                .approvals
+                .iter()
+                .map(|approval| approval.signer().clone())
+                .collect(),
+            Commit::ExtraAgendaTransaction(ExtraAgendaTransaction::Attest(tx)) => {
+                vec![tx.proof.signer().clone()]
+            }
+            Commit::ChatLog(chat_log) => chat_log
+                .messages
+                .iter()
+                .map(|(_, signature)| signature.signer().clone())
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(CommitInfo {
+            commit_hash: *commit_hash,
+            kind: CommitKind::from(&commit),
+            title,
+            commit: Some(commit),
+            changed_files,
+            signers,
+            finalized,
+        })
+    }
+
+    /// Reads the commits of the `main` branch from the genesis commit, in order.
+    pub async fn read_finalized_history(&self) -> Result<Vec<HistoryEntry>, Error> {
+        let last_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let mut commits = self.raw.list_ancestors(&last_commit, None).await?;
+        commits.reverse();
+        commits.push(last_commit);
+        let mut history = Vec::with_capacity(commits.len());
+        for (i, commit_hash) in commits.into_iter().enumerate() {
+            let semantic_commit = self.raw.read_semantic_commit(&commit_hash).await?;
+            let title = semantic_commit.title.clone();
+            let reserved_state = semantic_commit.reserved_state.clone();
+            let commit = if i == 0 {
+                None
+            } else {
+                Some(
+                    decode_semantic_commit(semantic_commit)
+                        .map_err(|e| {
+                            anyhow!("failed to convert the commit {}: {}", commit_hash, e)
+                        })?
+                        .0,
+                )
+            };
+            history.push(HistoryEntry {
+                commit_hash,
+                title,
+                commit,
+                reserved_state,
+            });
+        }
+        Ok(history)
+    }
+
+    /// Returns the diff of the given commit, for a review.
+    pub async fn show_commit(&self, commit_hash: &CommitHash) -> Result<String, Error> {
+        Ok(self.raw.show_commit(commit_hash).await?)
+    }
+
+    /// Returns the tag recording a local decision (`vote` or `veto`) on the commit,
+    /// which is the kind followed by the first 8 hex digits of the commit hash.
+    pub fn decision_tag(kind: &str, commit_hash: &CommitHash) -> Tag {
+        format!("{}-{}", kind, hex::encode(&commit_hash.hash[..4]))
+    }
+
+    /// Leaves the tag of a local decision on the commit (see `decision_tag()`).
+    ///
+    /// Returns `false` if it was already there.
+    pub async fn tag_decision(
+        &mut self,
+        kind: &str,
+        commit_hash: &CommitHash,
+    ) -> Result<bool, Error> {
+        let tag = Self::decision_tag(kind, commit_hash);
+        if self.raw.list_tags().await?.contains(&tag) {
+            return Ok(false);
+        }
+        self.raw.create_tag(&tag, commit_hash).await?;
+        Ok(true)
+    }
+
+    /// Removes the tag of a local decision from the commit (see `decision_tag()`).
+    ///
+    /// Returns `false` if it wasn't there.
+    pub async fn untag_decision(
+        &mut self,
+        kind: &str,
+        commit_hash: &CommitHash,
+    ) -> Result<bool, Error> {
+        let tag = Self::decision_tag(kind, commit_hash);
+        if !self.raw.list_tags().await?.contains(&tag) {
+            return Ok(false);
+        }
+        self.raw.remove_tag(&tag).await?;
+        Ok(true)
+    }
+
+    /// Archives the agenda branches (`a-<number>`) whose agendas have expired.
+    ///
+    /// Each archived agenda commit is tagged as `expired-<number>` so that it stays
+    /// reachable, and then its branch is deleted. Approved agendas
+    /// (whose branches point to `agenda-proof` commits) are left untouched.
+    ///
+    /// Returns the archived branches.
+    pub async fn archive_expired_agendas(&mut self) -> Result<Vec<Branch>, Error> {
+        let last_header = self.get_last_finalized_block_header().await?;
+        let now = self.clock.now();
+        let mut archived = Vec::new();
+        for branch in self.raw.list_branches().await? {
+            let number = if let Some(x) = branch.strip_prefix("a-") {
+                x.to_owned()
+            } else {
+                continue;
+            };
+            let commit_hash = self.raw.locate_branch(&branch).await?;
+            let semantic_commit = self.raw.read_semantic_commit(&commit_hash).await?;
+            let agenda = match from_semantic_commit(semantic_commit, &last_header) {
+                Ok(Commit::Agenda(agenda)) => agenda,
+                _ => continue,
+            };
+            if !agenda.is_expired(last_header.height, now) {
+                continue;
+            }
+            let tag = format!("expired-{}", number);
+            if !self.raw.list_tags().await?.contains(&tag) {
+                self.raw.create_tag(&tag, &commit_hash).await?;
+            }
+            self.raw.delete_branch(&branch).await?;
+            log::info!("archived the expired agenda {} as {}", branch, tag);
+            archived.push(branch);
+        }
+        Ok(archived)
     }
 
     /// Fetches new commits from the network.
     /// It **verifies** all the incoming changes and applies them to the local repository
     /// only if they are valid.
+    ///
+    /// The incoming commits exceeding the size limits are rejected (see `verify_size_limits()`).
     pub async fn fetch(
         &mut self,
         _network_config: &NetworkConfig,
@@ -71,6 +1281,17 @@ impl<T: RawRepository> DistributedRepository<T> {
         unimplemented!()
     }
 
+    /// Fetches the remotes of the peers concurrently, reporting the outcome of each
+    /// (see `RawRepository::fetch_all_with()`).
+    ///
+    /// The fetched commits are only in the remote tracking branches, not verified yet.
+    pub async fn fetch_remotes(
+        &mut self,
+        policy: &raw::FetchPolicy,
+    ) -> Result<raw::FetchReport, Error> {
+        Ok(self.raw.fetch_all_with(policy).await?)
+    }
+
     /// Notifies there was a push for the given repository.
     pub async fn notify_push(
         &mut self,
@@ -101,6 +1322,114 @@ impl<T: RawRepository> DistributedRepository<T> {
         unimplemented!()
     }
 
+    /// Returns the history retained by every branch, so that the operator can tell
+    /// which branches (e.g., the stale agendas) keep the unfinalized commits.
+    pub async fn get_branch_usage(&self) -> Result<Vec<BranchUsage>, Error> {
+        let main = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let mut usages = Vec::new();
+        for branch in self.raw.list_branches().await? {
+            let head = self.raw.locate_branch(&branch).await?;
+            let commits = self.raw.list_ancestors(&head, None).await?.len() + 1;
+            let merge_base = self.raw.find_merge_base(&head, &main).await?;
+            let shared = self.raw.list_ancestors(&merge_base, None).await?.len() + 1;
+            usages.push(BranchUsage {
+                branch,
+                commits,
+                unfinalized_commits: commits.saturating_sub(shared),
+            });
+        }
+        Ok(usages)
+    }
+
+    /// Checks the integrity of the repository storage.
+    ///
+    /// Unlike `check()`, it doesn't verify the semantics of the commits; it validates
+    /// the object store (dangling references, corrupt objects, broken parent links)
+    /// and the structural invariants of a Simperby repository.
+    pub async fn check_integrity(&self) -> Result<IntegrityReport, Error> {
+        let object_store = self.raw.check_object_store().await?;
+        let mut violations = Vec::new();
+        if object_store.root_commits.len() != 1 {
+            violations.push(format!(
+                "expected a single root commit, but found {}",
+                object_store.root_commits.len()
+            ));
+        }
+        match self.raw.locate_branch(&FINALIZED_BRANCH_NAME.into()).await {
+            Ok(finalized_commit) => {
+                if let Err(e) = self.raw.list_ancestors(&finalized_commit, None).await {
+                    violations.push(format!(
+                        "the history of branch {} is not linear: {}",
+                        FINALIZED_BRANCH_NAME, e
+                    ));
+                }
+            }
+            Err(e) => violations.push(format!(
+                "failed to locate branch {}: {}",
+                FINALIZED_BRANCH_NAME, e
+            )),
+        }
+        Ok(IntegrityReport {
+            object_store,
+            violations,
+        })
+    }
+
+    /// Checks the branches against the conventions of a Simperby repository (see `docs/git.md`),
+    /// returning the violations found.
+    ///
+    /// The `main` and the `work` branches must exist, the other branches must be
+    /// the proposal (`p`), agendas (`a-<number>`) or blocks (`b-<number>`),
+    /// and every branch must be on top of the `main` branch.
+    pub async fn check_branch_conventions(&self) -> Result<Vec<String>, Error> {
+        let branches = self.raw.list_branches().await?;
+        let mut violations = Vec::new();
+        for required in [FINALIZED_BRANCH_NAME, WORK_BRANCH_NAME] {
+            if !branches.iter().any(|branch| branch == required) {
+                violations.push(format!("branch {} is missing", required));
+            }
+        }
+        if !branches
+            .iter()
+            .any(|branch| branch == FINALIZED_BRANCH_NAME)
+        {
+            return Ok(violations);
+        }
+        let last_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let is_numbered = |branch: &str, prefix: &str| {
+            branch.strip_prefix(prefix).map_or(false, |number| {
+                !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+            })
+        };
+        for branch in &branches {
+            if branch == FINALIZED_BRANCH_NAME {
+                continue;
+            }
+            if !(branch == WORK_BRANCH_NAME
+                || branch == PROPOSAL_BRANCH_NAME
+                || is_numbered(branch, "a-")
+                || is_numbered(branch, "b-"))
+            {
+                violations.push(format!("branch {} is not managed by Simperby", branch));
+                continue;
+            }
+            let commit_hash = self.raw.locate_branch(branch).await?;
+            if self.raw.find_merge_base(&last_commit, &commit_hash).await? != last_commit {
+                violations.push(format!(
+                    "branch {} is not on top of branch {}",
+                    branch, FINALIZED_BRANCH_NAME
+                ));
+            }
+        }
+        Ok(violations)
+    }
+
     /// Synchronizes the `main` branch to the given commit.
     ///
     /// This will verify every commit along the way.
@@ -113,38 +1442,328 @@ impl<T: RawRepository> DistributedRepository<T> {
     pub async fn sync(&mut self, _block_commit: &CommitHash) -> Result<(), Error> {
         unimplemented!()
     }
+    /// Returns the per-line attribution of a reserved-state file as of the given commit.
+    ///
+    /// `path` is relative to the root of the working tree and must be in the reserved directory.
+    pub async fn blame_reserved_state(
+        &self,
+        path: &str,
+        at_commit: &CommitHash,
+    ) -> Result<Vec<raw::BlameLine>, Error> {
+        if !std::path::Path::new(path).starts_with(RESERVED_DIRECTORY) {
+            return Err(anyhow!(
+                "{} is not in the reserved directory `{}`",
+                path,
+                RESERVED_DIRECTORY
+            ));
+        }
+        Ok(self.raw.blame(path, at_commit).await?)
+    }
+
+    /// Attaches local, non-consensus metadata (e.g., the verification result or the peer of origin)
+    /// to the commit, without altering the history.
+    pub async fn add_note(
+        &mut self,
+        commit_hash: &CommitHash,
+        namespace: &str,
+        content: &str,
+    ) -> Result<(), Error> {
+        Ok(self.raw.add_note(commit_hash, namespace, content).await?)
+    }
+
+    /// Reads the local metadata attached to the commit, as `(namespace, content)`.
+    pub async fn read_notes(
+        &self,
+        commit_hash: &CommitHash,
+    ) -> Result<Vec<(String, String)>, Error> {
+        Ok(self.raw.read_notes(commit_hash).await?)
+    }
+
     /// Returns the currently valid and height-acceptable agendas in the repository.
+    ///
+    /// An agenda exceeding the limits of the chain is not valid (see `verify_proposal_limits()`),
+    /// nor is one superseded by an amendment (see `get_amendments()`).
     pub async fn get_agendas(&self) -> Result<Vec<(CommitHash, Hash256)>, Error> {
         unimplemented!()
     }
 
-    /// Returns the currently valid and height-acceptable blocks in the repository.
-    pub async fn get_blocks(&self) -> Result<Vec<(CommitHash, Hash256)>, Error> {
-        unimplemented!()
+    /// Returns the currently valid and height-acceptable blocks in the repository.
+    ///
+    /// A block exceeding the limits of the chain is not valid (see `verify_proposal_limits()`).
+    pub async fn get_blocks(&self) -> Result<Vec<(CommitHash, Hash256)>, Error> {
+        unimplemented!()
+    }
+
+    /// Finalizes a single block and moves the `main` branch to it.
+    ///
+    /// It will verify the finalization proof and the commits from the last finalized block
+    /// up to the block, which must be on top of it.
+    pub async fn finalize(
+        &mut self,
+        block_commit_hash: &CommitHash,
+        proof: &FinalizationProof,
+    ) -> Result<(), Error> {
+        let last_header = self.get_last_finalized_block_header().await?;
+        let last_header_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        if self
+            .raw
+            .find_merge_base(&last_header_commit, block_commit_hash)
+            .await?
+            != last_header_commit
+        {
+            return Err(anyhow!(
+                "block {} is not on top of branch {}",
+                hex::encode(block_commit_hash.hash),
+                FINALIZED_BRANCH_NAME
+            ));
+        }
+        let block_commit = self.raw.read_semantic_commit(block_commit_hash).await?;
+        let header = match from_semantic_commit(block_commit, &last_header).map_err(|e| {
+            anyhow!(
+                "failed to convert the commit {}: {}",
+                hex::encode(block_commit_hash.hash),
+                e
+            )
+        })? {
+            Commit::Block(header) => header,
+            _ => {
+                return Err(anyhow!(
+                    "commit {} is not a block",
+                    hex::encode(block_commit_hash.hash)
+                ))
+            }
+        };
+        verify::verify_header_to_header(&last_header, &header).map_err(|e| {
+            telemetry::record_verification_failure("header");
+            e
+        })?;
+        verify::verify_finalization_proof(&header, proof, &last_header.validator_set).map_err(
+            |e| {
+                telemetry::record_verification_failure("finalization_proof");
+                e
+            },
+        )?;
+        let mut verifier =
+            CommitSequenceVerifier::new(last_header.clone(), self.get_reserved_state().await?)?;
+        for hash in self
+            .list_commits_on_top_of(block_commit_hash, &last_header_commit)
+            .await?
+        {
+            let semantic_commit = self.raw.read_semantic_commit(&hash).await?;
+            let carried = semantic_commit.reserved_state.clone();
+            let commit = from_semantic_commit(semantic_commit, &last_header).map_err(|e| {
+                anyhow!(
+                    "failed to convert the commit {}: {}",
+                    hex::encode(hash.hash),
+                    e
+                )
+            })?;
+            verifier.apply_commit(&commit).map_err(|e| {
+                telemetry::record_verification_failure("commit_sequence");
+                anyhow!(
+                    "verification error on commit {}: {}",
+                    hex::encode(hash.hash),
+                    e
+                )
+            })?;
+            verify_carried_reserved_state(&commit, carried.as_ref(), &verifier).map_err(|e| {
+                anyhow!(
+                    "verification error on commit {}: {}",
+                    hex::encode(hash.hash),
+                    e
+                )
+            })?;
+        }
+
+        let entry = self
+            .journal
+            .begin(
+                Operation::Finalize {
+                    block_commit_hash: *block_commit_hash,
+                },
+                vec![(FINALIZED_BRANCH_NAME.into(), last_header_commit)],
+            )
+            .await?;
+        self.raw
+            .move_branch(&FINALIZED_BRANCH_NAME.into(), block_commit_hash)
+            .await?;
+        self.journal.complete(&entry).await?;
+        Ok(())
+    }
+
+    /// Informs that the given agenda has been approved,
+    /// creating the agenda-proof commit on top of it.
+    ///
+    /// The proof is verified against the reserved state, and the agenda must not be
+    /// superseded by an amendment (see `get_amendments()`).
+    /// The agenda branches on the agenda are moved to the agenda-proof commit.
+    pub async fn approve(
+        &mut self,
+        agenda_commit_hash: &CommitHash,
+        proof: Vec<(PublicKey, TypedSignature<Agenda>)>,
+    ) -> Result<CommitHash, Error> {
+        let agenda = self.read_agenda(agenda_commit_hash).await?;
+        if let Some((_, amendment)) = self
+            .get_amendments()
+            .await?
+            .into_iter()
+            .find(|(superseded, _)| superseded == agenda_commit_hash)
+        {
+            return Err(anyhow!(
+                "the agenda {} is superseded by {}",
+                hex::encode(agenda_commit_hash.hash),
+                hex::encode(amendment.hash)
+            ));
+        }
+        let agenda_proof = AgendaProof {
+            agenda_hash: agenda.to_hash256(),
+            proof,
+        };
+        verify::verify_agenda_proof(&agenda, &agenda_proof, &self.get_reserved_state().await?)
+            .map_err(|e| {
+                telemetry::record_verification_failure("agenda_proof");
+                anyhow!("invalid agenda proof: {}", e)
+            })?;
+        let last_header = self.get_last_finalized_block_header().await?;
+        let semantic_commit = to_semantic_commit(&Commit::AgendaProof(agenda_proof), &last_header);
+        self.raw.checkout_clean().await?;
+        self.raw.checkout_detach(agenda_commit_hash).await?;
+        let result = self.raw.create_semantic_commit(semantic_commit).await?;
+        for branch in self.raw.list_branches().await? {
+            if branch.starts_with("a-")
+                && self.raw.locate_branch(&branch).await? == *agenda_commit_hash
+            {
+                self.raw.move_branch(&branch, &result).await?;
+            }
+        }
+        self.raw.checkout(&FINALIZED_BRANCH_NAME.into()).await?;
+        Ok(result)
+    }
+
+    /// Creates an agenda commit on top of the `work` branch.
+    ///
+    /// The expiration of the agenda follows the governance parameters of the reserved state,
+    /// and the transactions must conform to the limits of its chain parameters.
+    pub async fn create_agenda(&mut self, author: PublicKey) -> Result<CommitHash, Error> {
+        self.create_agenda_commit(author, None).await
+    }
+
+    /// Creates an agenda commit on top of the `work` branch like `create_agenda()`,
+    /// amending the open agenda of the given commit by the same author (see `Agenda::supersedes`).
+    ///
+    /// The signer must be of the author, who signs the amendment (see `Agenda::amendment_proof`).
+    ///
+    /// The superseded agenda is linked to the amendment with a note
+    /// (see `SUPERSEDED_BY_NOTE_NAMESPACE`), as well as by `get_amendments()`.
+    pub async fn amend_agenda(
+        &mut self,
+        signer: &dyn Signer,
+        superseded_commit: &CommitHash,
+    ) -> Result<CommitHash, Error> {
+        let author = signer.public_key();
+        let superseded = self.read_agenda(superseded_commit).await?;
+        if superseded.author != author {
+            return Err(anyhow!(
+                "only the author {} of the agenda can amend it",
+                superseded.author
+            ));
+        }
+        let last_header = self.get_last_finalized_block_header().await?;
+        let last_header_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        if self
+            .raw
+            .find_merge_base(&last_header_commit, superseded_commit)
+            .await?
+            != last_header_commit
+        {
+            return Err(anyhow!(
+                "agenda {} is not open on top of branch {}",
+                hex::encode(superseded_commit.hash),
+                FINALIZED_BRANCH_NAME
+            ));
+        }
+        if superseded.is_expired(last_header.height, self.clock.now()) {
+            return Err(anyhow!(
+                "agenda {} has expired; create a new one instead",
+                hex::encode(superseded_commit.hash)
+            ));
+        }
+        if let Some((_, amendment)) = self
+            .get_amendments()
+            .await?
+            .into_iter()
+            .find(|(x, _)| x == superseded_commit)
+        {
+            return Err(anyhow!(
+                "agenda {} is already superseded by {}",
+                hex::encode(superseded_commit.hash),
+                hex::encode(amendment.hash)
+            ));
+        }
+        let result = self
+            .create_agenda_commit(author, Some((superseded.to_hash256(), signer)))
+            .await?;
+        self.raw
+            .add_note(
+                superseded_commit,
+                SUPERSEDED_BY_NOTE_NAMESPACE,
+                &hex::encode(result.hash),
+            )
+            .await?;
+        Ok(result)
+    }
+
+    /// Returns the open agendas superseded by the others as `(superseded, amendment)`,
+    /// linking the two for auditing.
+    ///
+    /// An agenda claiming to supersede another without the signature of its author
+    /// is not an amendment (see `Agenda::is_amendment_of()`).
+    pub async fn get_amendments(&self) -> Result<Vec<(CommitHash, CommitHash)>, Error> {
+        let agendas = self.read_open_agendas().await?;
+        Ok(agendas
+            .iter()
+            .filter_map(|(amendment, agenda)| {
+                agendas
+                    .iter()
+                    .find(|(_, x)| agenda.is_amendment_of(x))
+                    .map(|(commit_hash, _)| (*commit_hash, *amendment))
+            })
+            .collect())
     }
 
-    /// Finalizes a single block and moves the `main` branch to it.
-    ///
-    /// It will verify the finalization proof and the commits.
-    pub async fn finalize(
-        &mut self,
-        _block_commit_hash: &CommitHash,
-        _proof: &FinalizationProof,
-    ) -> Result<(), Error> {
-        unimplemented!()
+    /// Reads the agendas at the heads of the agenda branches and the `work` branch.
+    async fn read_open_agendas(&self) -> Result<Vec<(CommitHash, Agenda)>, Error> {
+        let mut heads = Vec::new();
+        for branch in self.raw.list_branches().await? {
+            if branch.starts_with("a-") || branch == WORK_BRANCH_NAME {
+                let commit_hash = self.raw.locate_branch(&branch).await?;
+                if !heads.contains(&commit_hash) {
+                    heads.push(commit_hash);
+                }
+            }
+        }
+        let mut agendas = Vec::new();
+        for commit_hash in heads {
+            if let Ok(agenda) = self.read_agenda(&commit_hash).await {
+                agendas.push((commit_hash, agenda));
+            }
+        }
+        Ok(agendas)
     }
 
-    /// Informs that the given agenda has been approved.
-    pub async fn approve(
+    /// Creates the agenda commit, which amends the agenda of the hash if given,
+    /// signed by the signer.
+    async fn create_agenda_commit(
         &mut self,
-        _agenda_commit_hash: &CommitHash,
-        _proof: Vec<(PublicKey, TypedSignature<Agenda>)>,
+        author: PublicKey,
+        amendment: Option<(Hash256, &dyn Signer)>,
     ) -> Result<CommitHash, Error> {
-        unimplemented!()
-    }
-    
-    /// Creates an agenda commit on top of the `work` branch.
-    pub async fn create_agenda(&mut self, author: PublicKey) -> Result<CommitHash, Error> {
         let last_header = self.get_last_finalized_block_header().await?;
         let work_commit = self.raw.locate_branch(&WORK_BRANCH_NAME.into()).await?;
         let last_header_commit = self
@@ -182,6 +1801,9 @@ impl<T: RawRepository> DistributedRepository<T> {
         .collect::<Vec<_>>()
         .await;
         let commits = commits.into_iter().collect::<Result<Vec<_>, _>>()?;
+        for (_, hash) in commits.iter() {
+            self.verify_size_limits(hash).await?;
+        }
         let commits = commits
             .into_iter()
             .map(|(commit, hash)| {
@@ -193,13 +1815,17 @@ impl<T: RawRepository> DistributedRepository<T> {
             .map_err(|(error, hash)| anyhow!("failed to convert the commit {}: {}", hash, error))?;
 
         // Check the validity of the commit sequence
-        let mut verifier = CommitSequenceVerifier::new(last_header.clone())
+        let reserved_state = self.get_reserved_state().await?;
+        let mut verifier = CommitSequenceVerifier::new(last_header.clone(), reserved_state.clone())
             .map_err(|e| anyhow!("verification error on commit {}: {}", last_header_commit, e))?;
         for (commit, hash) in commits.iter() {
-            verifier
-                .apply_commit(commit)
-                .map_err(|e| anyhow!("verification error on commit {}: {}", hash, e))?;
+            verifier.apply_commit(commit).map_err(|e| {
+                telemetry::record_verification_failure("commit_sequence");
+                anyhow!("verification error on commit {}: {}", hash, e)
+            })?;
         }
+        self.check_proposal_limits(&reserved_state.parameters, &commits)
+            .await?;
 
         // Check whether the commit sequence is in the transaction phase.
         let mut transactions = Vec::new();
@@ -215,29 +1841,1017 @@ impl<T: RawRepository> DistributedRepository<T> {
             }
         }
 
+        let timestamp = self.clock.now();
+        let (expiration_height, expiration_timestamp) = reserved_state
+            .governance_params
+            .agenda_expiration(last_header.height, timestamp);
+        let hash = Agenda::calculate_hash(last_header.height + 1, &transactions);
+        let (supersedes, amendment_proof) = match amendment {
+            Some((superseded, signer)) => {
+                let data = (superseded, hash);
+                let signature = signer.sign(data.to_hash256()).await?;
+                (
+                    Some(superseded),
+                    Some(TypedSignature::new(signature, signer.public_key())),
+                )
+            }
+            None => (None, None),
+        };
         let agenda_commit = Commit::Agenda(Agenda {
             author,
-            timestamp: get_timestamp(),
-            hash: Agenda::calculate_hash(last_header.height + 1, &transactions),
+            timestamp,
+            hash,
+            expiration_height,
+            expiration_timestamp,
+            supersedes,
+            amendment_proof,
         });
         let semantic_commit = to_semantic_commit(&agenda_commit, &last_header);
 
+        let entry = self
+            .journal
+            .begin(
+                Operation::CreateAgenda {
+                    title: semantic_commit.title.clone(),
+                },
+                vec![(WORK_BRANCH_NAME.into(), work_commit)],
+            )
+            .await?;
+        self.raw.checkout_clean().await?;
+        self.raw.checkout(&WORK_BRANCH_NAME.into()).await?;
+        let result = self.raw.create_semantic_commit(semantic_commit).await?;
+        self.journal.complete(&entry).await?;
+        Ok(result)
+    }
+
+    /// Creates a transaction commit on top of the `work` branch.
+    ///
+    /// Only the transactions without a diff or changing the reserved state are supported,
+    /// since the diff of a general transaction is not carried by `Transaction`;
+    /// such a transaction is authored with `begin_transaction()` instead.
+    pub async fn create_transaction(
+        &mut self,
+        transaction: &Transaction,
+    ) -> Result<CommitHash, Error> {
+        if let Diff::General(_) = transaction.diff {
+            return Err(anyhow!(
+                "a transaction with a general diff must be authored with `begin_transaction()`"
+            ));
+        }
+        let last_header = self.get_last_finalized_block_header().await?;
+        let work_commit = self.raw.locate_branch(&WORK_BRANCH_NAME.into()).await?;
+        let semantic_commit =
+            to_semantic_commit(&Commit::Transaction(transaction.clone()), &last_header);
+
+        let entry = self
+            .journal
+            .begin(
+                Operation::CreateTransaction {
+                    title: semantic_commit.title.clone(),
+                },
+                vec![(WORK_BRANCH_NAME.into(), work_commit)],
+            )
+            .await?;
+        self.raw.checkout_clean().await?;
+        self.raw.checkout(&WORK_BRANCH_NAME.into()).await?;
+        let result = self.raw.create_semantic_commit(semantic_commit).await?;
+        self.journal.complete(&entry).await?;
+        Ok(result)
+    }
+
+    /// Starts authoring a general transaction on the `work` branch (see `authoring`).
+    ///
+    /// It checks out the `work` branch, so that the changes are made on top of it.
+    pub async fn begin_transaction(
+        &mut self,
+        author: PublicKey,
+        head: String,
+        body: String,
+    ) -> Result<(), Error> {
+        let directory = self.raw.get_working_directory_path().await?;
+        if authoring::read_draft(&directory).await?.is_some() {
+            return Err(anyhow!(
+                "a transaction is already being authored; commit or abort it first"
+            ));
+        }
+        self.raw.checkout_clean().await?;
+        self.raw.checkout(&WORK_BRANCH_NAME.into()).await?;
+        authoring::write_draft(
+            &directory,
+            &authoring::TransactionDraft { author, head, body },
+        )
+        .await
+    }
+
+    /// Stages the changes of the given paths for the transaction being authored.
+    pub async fn stage_transaction(&mut self, paths: &[String]) -> Result<(), Error> {
+        self.read_transaction_draft().await?;
+        Ok(self.raw.stage(paths).await?)
+    }
+
+    /// Returns the transaction being authored with the staged diff and the problems found.
+    pub async fn preview_transaction(&self) -> Result<authoring::TransactionPreview, Error> {
+        let draft = self.read_transaction_draft().await?;
+        let changes = self.raw.read_staged_changes().await?;
+        let diff = self.raw.show_staged_diff().await?;
+        let mut problems = authoring::check_changes(&changes, &self.size_limits);
+        // A transaction must fit in a proposal by itself.
+        let diff_size = changes.iter().filter_map(|(_, size)| *size).sum();
+        if let Err(e) = self
+            .get_reserved_state()
+            .await?
+            .parameters
+            .check_proposal(&[diff_size], diff_size)
+        {
+            problems.push(e);
+        }
+        Ok(authoring::TransactionPreview {
+            draft,
+            changes,
+            diff,
+            problems,
+        })
+    }
+
+    /// Creates the transaction commit of the staged changes on top of the `work` branch,
+    /// finishing the authoring.
+    ///
+    /// It fails if any problem is found in the preview (see `preview_transaction()`).
+    pub async fn commit_transaction(&mut self) -> Result<(CommitHash, Transaction), Error> {
+        let preview = self.preview_transaction().await?;
+        if !preview.is_ok() {
+            return Err(anyhow!(
+                "can't commit the transaction: {}",
+                preview.problems.join("; ")
+            ));
+        }
+        let transaction = Transaction {
+            author: preview.draft.author,
+            timestamp: self.clock.now(),
+            head: preview.draft.head,
+            body: preview.draft.body,
+            diff: Diff::General(Hash256::hash(&preview.diff)),
+        };
+        let last_header = self.get_last_finalized_block_header().await?;
+        let semantic_commit =
+            to_semantic_commit(&Commit::Transaction(transaction.clone()), &last_header);
+        let work_commit = self.raw.locate_branch(&WORK_BRANCH_NAME.into()).await?;
+
+        let entry = self
+            .journal
+            .begin(
+                Operation::CreateTransaction {
+                    title: semantic_commit.title.clone(),
+                },
+                vec![(WORK_BRANCH_NAME.into(), work_commit)],
+            )
+            .await?;
+        let result = self
+            .raw
+            .commit_staged(&format!(
+                "{}\n\n{}",
+                semantic_commit.title, semantic_commit.body
+            ))
+            .await?;
+        self.journal.complete(&entry).await?;
+        authoring::remove_draft(&self.raw.get_working_directory_path().await?).await?;
+        Ok((result, transaction))
+    }
+
+    /// Abandons the transaction being authored, discarding the changes in the working tree.
+    pub async fn abort_transaction(&mut self) -> Result<(), Error> {
+        self.read_transaction_draft().await?;
+        self.raw.checkout_clean().await?;
+        authoring::remove_draft(&self.raw.get_working_directory_path().await?).await
+    }
+
+    async fn read_transaction_draft(&self) -> Result<authoring::TransactionDraft, Error> {
+        authoring::read_draft(&self.raw.get_working_directory_path().await?)
+            .await?
+            .ok_or_else(|| anyhow!("no transaction is being authored"))
+    }
+
+    async fn ensure_no_transaction_authored(&self) -> Result<(), Error> {
+        if authoring::read_draft(&self.raw.get_working_directory_path().await?)
+            .await?
+            .is_some()
+        {
+            return Err(anyhow!(
+                "a transaction is being authored; commit or abort it first"
+            ));
+        }
+        Ok(())
+    }
+
+    async fn draft_exists(&self, name: &str) -> Result<bool, Error> {
+        let reference = drafts::reference(name);
+        Ok(self
+            .raw
+            .list_references(&reference)
+            .await?
+            .iter()
+            .any(|(x, _)| *x == reference))
+    }
+
+    /// Lists the commits after `base` up to `head`, in order.
+    async fn list_commits_on_top_of(
+        &self,
+        head: &CommitHash,
+        base: &CommitHash,
+    ) -> Result<Vec<CommitHash>, Error> {
+        if head == base {
+            return Ok(Vec::new());
+        }
+        let ancestors = self.raw.list_ancestors(head, Some(256)).await?;
+        let position = ancestors.iter().position(|c| c == base).ok_or_else(|| {
+            anyhow!(
+                "commit {} is not within 256 commits on top of {}",
+                head,
+                base
+            )
+        })?;
+        Ok(ancestors[..position]
+            .iter()
+            .rev()
+            .copied()
+            .chain(std::iter::once(*head))
+            .collect())
+    }
+
+    /// Returns the drafts of the agendas, in the order of the names (see `drafts`).
+    pub async fn list_drafts(&self) -> Result<Vec<Draft>, Error> {
+        let last_header_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let mut result = Vec::new();
+        for (reference, head) in self.raw.list_references(DRAFT_REF_PREFIX).await? {
+            let base = self.raw.find_merge_base(&head, &last_header_commit).await?;
+            let mut transactions = Vec::new();
+            for commit_hash in self.list_commits_on_top_of(&head, &base).await? {
+                transactions.push(self.raw.read_semantic_commit(&commit_hash).await?.title);
+            }
+            result.push(Draft {
+                name: reference.trim_start_matches(DRAFT_REF_PREFIX).to_owned(),
+                head,
+                base,
+                transactions,
+                outdated: base != last_header_commit,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Parks the transactions on the `work` branch as a new draft, resetting the `work` branch
+    /// to the last finalized block so that another agenda can be prepared.
+    pub async fn create_draft(&mut self, name: &str) -> Result<Draft, Error> {
+        drafts::validate_name(name)?;
+        if self.draft_exists(name).await? {
+            return Err(anyhow!("draft {} already exists", name));
+        }
+        self.ensure_no_transaction_authored().await?;
+        let last_header = self.get_last_finalized_block_header().await?;
+        let last_header_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let work_commit = self.raw.locate_branch(&WORK_BRANCH_NAME.into()).await?;
+        let commits = self
+            .list_commits_on_top_of(&work_commit, &last_header_commit)
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "branch {} should be rebased on {}: {}",
+                    WORK_BRANCH_NAME,
+                    FINALIZED_BRANCH_NAME,
+                    e
+                )
+            })?;
+        let mut transactions = Vec::with_capacity(commits.len());
+        for commit_hash in &commits {
+            let semantic_commit = self.raw.read_semantic_commit(commit_hash).await?;
+            let title = semantic_commit.title.clone();
+            match from_semantic_commit(semantic_commit, &last_header) {
+                Ok(Commit::Transaction(_)) => transactions.push(title),
+                _ => {
+                    return Err(anyhow!(
+                        "commit {} of branch {} is not a transaction",
+                        commit_hash,
+                        WORK_BRANCH_NAME
+                    ))
+                }
+            }
+        }
+
+        let entry = self
+            .journal
+            .begin(
+                Operation::CreateDraft {
+                    name: name.to_owned(),
+                },
+                vec![(WORK_BRANCH_NAME.into(), work_commit)],
+            )
+            .await?;
+        self.raw.checkout_clean().await?;
+        self.raw.checkout_detach(&last_header_commit).await?;
+        self.raw
+            .move_branch(&WORK_BRANCH_NAME.into(), &last_header_commit)
+            .await?;
+        self.raw.checkout(&WORK_BRANCH_NAME.into()).await?;
+        self.raw
+            .set_reference(&drafts::reference(name), &work_commit)
+            .await?;
+        self.journal.complete(&entry).await?;
+        Ok(Draft {
+            name: name.to_owned(),
+            head: work_commit,
+            base: last_header_commit,
+            transactions,
+            outdated: false,
+        })
+    }
+
+    /// Brings the draft back onto the `work` branch to be edited, removing it from the drafts.
+    ///
+    /// The transactions of an outdated draft are replayed on top of the last finalized block,
+    /// which fails if they conflict with it. The `work` branch must have no commit
+    /// on top of the last finalized block; such ones are to be parked first.
+    pub async fn load_draft(&mut self, name: &str) -> Result<CommitHash, Error> {
+        let draft = self
+            .list_drafts()
+            .await?
+            .into_iter()
+            .find(|draft| draft.name == name)
+            .ok_or_else(|| anyhow!("draft {} does not exist", name))?;
+        self.ensure_no_transaction_authored().await?;
+        let last_header_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let work_commit = self.raw.locate_branch(&WORK_BRANCH_NAME.into()).await?;
+        if work_commit != last_header_commit {
+            return Err(anyhow!(
+                "branch {} has commits on top of {}; park them as a draft first",
+                WORK_BRANCH_NAME,
+                FINALIZED_BRANCH_NAME
+            ));
+        }
+
+        let entry = self
+            .journal
+            .begin(
+                Operation::LoadDraft {
+                    name: name.to_owned(),
+                },
+                vec![(WORK_BRANCH_NAME.into(), work_commit)],
+            )
+            .await?;
+        self.raw.checkout_clean().await?;
+        self.raw.checkout(&WORK_BRANCH_NAME.into()).await?;
+        if draft.outdated {
+            for commit_hash in self
+                .list_commits_on_top_of(&draft.head, &draft.base)
+                .await?
+            {
+                if let Err(e) = self.raw.cherry_pick(&commit_hash).await {
+                    self.raw.checkout_clean().await?;
+                    self.raw
+                        .move_branch(&WORK_BRANCH_NAME.into(), &work_commit)
+                        .await?;
+                    self.raw.checkout(&WORK_BRANCH_NAME.into()).await?;
+                    self.journal.complete(&entry).await?;
+                    return Err(anyhow!(
+                        "failed to replay draft {} on the last finalized block: {}",
+                        name,
+                        e
+                    ));
+                }
+            }
+        } else {
+            self.raw
+                .move_branch(&WORK_BRANCH_NAME.into(), &draft.head)
+                .await?;
+            self.raw.checkout(&WORK_BRANCH_NAME.into()).await?;
+        }
+        self.raw.delete_reference(&drafts::reference(name)).await?;
+        self.journal.complete(&entry).await?;
+        Ok(self.raw.locate_branch(&WORK_BRANCH_NAME.into()).await?)
+    }
+
+    /// Publishes the draft as an agenda, loading it onto the `work` branch
+    /// and creating the agenda commit on top of it (see `create_agenda()`),
+    /// or the amendment of the given agenda (see `amend_agenda()`).
+    ///
+    /// If the agenda can't be created (e.g., exceeding the limits of the chain),
+    /// the transactions are left on the `work` branch.
+    pub async fn publish_draft(
+        &mut self,
+        name: &str,
+        signer: &dyn Signer,
+        supersedes: Option<&CommitHash>,
+    ) -> Result<CommitHash, Error> {
+        self.load_draft(name).await?;
+        match supersedes {
+            Some(superseded_commit) => self.amend_agenda(signer, superseded_commit).await,
+            None => self.create_agenda(signer.public_key()).await,
+        }
+    }
+
+    /// Creates a chat-log commit on top of the `work` branch.
+    ///
+    /// The log should have been collected from the chat of the height following
+    /// the last finalized block.
+    pub async fn create_chat_commit(&mut self, chat_log: ChatLog) -> Result<CommitHash, Error> {
+        let last_header = self.get_last_finalized_block_header().await?;
+        let work_commit = self.raw.locate_branch(&WORK_BRANCH_NAME.into()).await?;
+        let semantic_commit = to_semantic_commit(&Commit::ChatLog(chat_log), &last_header);
+
+        let entry = self
+            .journal
+            .begin(
+                Operation::CreateChatLog {
+                    title: semantic_commit.title.clone(),
+                },
+                vec![(WORK_BRANCH_NAME.into(), work_commit)],
+            )
+            .await?;
         self.raw.checkout_clean().await?;
         self.raw.checkout(&WORK_BRANCH_NAME.into()).await?;
         let result = self.raw.create_semantic_commit(semantic_commit).await?;
+        self.journal.complete(&entry).await?;
         Ok(result)
     }
 
+    /// Checks whether the given finalized block conflicts with the last finalized block,
+    /// returning the evidence if so.
+    ///
+    /// `last_proof` is the finalization proof of the last finalized block.
+    /// The evidence is not verified here; see `verify::verify_evidence()`.
+    pub async fn detect_fork(
+        &self,
+        last_proof: &FinalizationProof,
+        header: &BlockHeader,
+        proof: &FinalizationProof,
+    ) -> Result<Option<Evidence>, Error> {
+        let last_header = self.get_last_finalized_block_header().await?;
+        let evidence =
+            verify::find_conflicting_finalization((&last_header, last_proof), (header, proof));
+        if evidence.is_some() {
+            log::warn!(
+                "detected a block conflicting with the last finalized one at height {}",
+                header.height
+            );
+        }
+        Ok(evidence)
+    }
+
+    /// Creates a report transaction of the evidence on top of the `work` branch,
+    /// so that it's included in the next block.
+    pub async fn report_evidence(&mut self, evidence: Evidence) -> Result<CommitHash, Error> {
+        self.create_extra_agenda_transaction(&ExtraAgendaTransaction::Report(TxReport { evidence }))
+            .await
+    }
+
     /// Creates a block commit on top of the `work` branch.
     pub async fn create_block(&mut self, _author: PublicKey) -> Result<CommitHash, Error> {
         unimplemented!()
     }
 
-    /// Creates an agenda commit on top of the `work` branch.
+    /// Creates an extra-agenda transaction commit on top of the `work` branch,
+    /// carrying the reserved state changed by the transaction.
+    ///
+    /// The transaction takes effect once the block including it is finalized.
     pub async fn create_extra_agenda_transaction(
         &mut self,
-        _transaction: &ExtraAgendaTransaction,
+        transaction: &ExtraAgendaTransaction,
     ) -> Result<CommitHash, Error> {
-        unimplemented!()
+        let last_header = self.get_last_finalized_block_header().await?;
+        let work_commit = self.raw.locate_branch(&WORK_BRANCH_NAME.into()).await?;
+        let mut verifier = self.verify_work_branch().await?;
+        let commit = Commit::ExtraAgendaTransaction(transaction.clone());
+        verifier
+            .apply_commit(&commit)
+            .map_err(|e| anyhow!("invalid extra-agenda transaction: {}", e))?;
+        let mut semantic_commit = to_semantic_commit(&commit, &last_header);
+        semantic_commit.reserved_state = Some(verifier.get_reserved_state().clone());
+
+        let entry = self
+            .journal
+            .begin(
+                Operation::CreateExtraAgendaTransaction {
+                    title: semantic_commit.title.clone(),
+                },
+                vec![(WORK_BRANCH_NAME.into(), work_commit)],
+            )
+            .await?;
+        self.raw.checkout_clean().await?;
+        self.raw.checkout(&WORK_BRANCH_NAME.into()).await?;
+        let result = self.raw.create_semantic_commit(semantic_commit).await?;
+        self.journal.complete(&entry).await?;
+        Ok(result)
+    }
+
+    /// Verifies the commits of the `work` branch on top of the last finalized block,
+    /// returning the verifier with them applied.
+    async fn verify_work_branch(&self) -> Result<CommitSequenceVerifier, Error> {
+        let last_header = self.get_last_finalized_block_header().await?;
+        let last_header_commit = self
+            .raw
+            .locate_branch(&FINALIZED_BRANCH_NAME.into())
+            .await?;
+        let work_commit = self.raw.locate_branch(&WORK_BRANCH_NAME.into()).await?;
+        let mut verifier =
+            CommitSequenceVerifier::new(last_header.clone(), self.get_reserved_state().await?)?;
+        verifier.set_open_agendas(
+            self.read_open_agendas()
+                .await?
+                .into_iter()
+                .map(|(_, agenda)| agenda)
+                .collect(),
+        );
+        for hash in self
+            .list_commits_on_top_of(&work_commit, &last_header_commit)
+            .await?
+        {
+            let semantic_commit = self.raw.read_semantic_commit(&hash).await?;
+            let carried = semantic_commit.reserved_state.clone();
+            let commit = from_semantic_commit(semantic_commit, &last_header)
+                .map_err(|e| anyhow!("failed to convert the commit {}: {}", hash, e))?;
+            verifier
+                .apply_commit(&commit)
+                .map_err(|e| anyhow!("verification error on commit {}: {}", hash, e))?;
+            verify_carried_reserved_state(&commit, carried.as_ref(), &verifier)
+                .map_err(|e| anyhow!("verification error on commit {}: {}", hash, e))?;
+        }
+        Ok(verifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raw::RawRepositoryImpl;
+    use simperby_common::test_util;
+    use tempfile::TempDir;
+
+    /// Creates a repository of the genesis of the members `a`, `b` and `c`,
+    /// with `main` checked out.
+    async fn genesis(directory: &TempDir) -> DistributedRepository<RawRepositoryImpl> {
+        let path = directory.path().to_str().unwrap();
+        let mut repository =
+            DistributedRepository::new(RawRepositoryImpl::init(path).await.unwrap())
+                .await
+                .unwrap();
+        repository
+            .create_genesis_commit(&test_util::genesis(&["a", "b", "c"]))
+            .await
+            .unwrap();
+        let mut config = git2::Repository::open(path).unwrap().config().unwrap();
+        config.set_str("user.name", "simperby").unwrap();
+        config
+            .set_str("user.email", "simperby@example.com")
+            .unwrap();
+        repository
+    }
+
+    /// Commits on the checked out branch, after the last header.
+    async fn commit(
+        repository: &mut DistributedRepository<RawRepositoryImpl>,
+        commit: &Commit,
+        last_header: &BlockHeader,
+    ) -> CommitHash {
+        repository
+            .raw
+            .create_semantic_commit(to_semantic_commit(commit, last_header))
+            .await
+            .unwrap()
+    }
+
+    fn transaction(head: &str, diff: Diff) -> Transaction {
+        Transaction {
+            author: generate_keypair("a").0,
+            timestamp: 0,
+            head: head.to_owned(),
+            body: String::new(),
+            diff,
+        }
+    }
+
+    /// Finalizes the blocks of the heights on `main`, each after a transaction,
+    /// returning the block commits.
+    async fn finalize_blocks(
+        repository: &mut DistributedRepository<RawRepositoryImpl>,
+        heights: std::ops::RangeInclusive<BlockHeight>,
+    ) -> Vec<CommitHash> {
+        let mut last_header = repository.get_last_finalized_block_header().await.unwrap();
+        let mut blocks = Vec::new();
+        for height in heights {
+            let tx = Commit::Transaction(transaction(&format!("Update {}", height), Diff::None));
+            commit(repository, &tx, &last_header).await;
+            let header = BlockHeader {
+                previous_hash: last_header.to_hash256(),
+                ..test_util::header(height)
+            };
+            blocks.push(commit(repository, &Commit::Block(header.clone()), &last_header).await);
+            last_header = header;
+        }
+        blocks
+    }
+
+    /// Commits the commits and their block on a new branch off the base,
+    /// returning the block commit and its finalization proof.
+    async fn propose_block(
+        repository: &mut DistributedRepository<RawRepositoryImpl>,
+        branch: &str,
+        base: &CommitHash,
+        last_header: &BlockHeader,
+        commits: &[Commit],
+    ) -> (CommitHash, FinalizationProof) {
+        repository
+            .raw
+            .create_branch(&branch.into(), *base)
+            .await
+            .unwrap();
+        repository.raw.checkout(&branch.into()).await.unwrap();
+        let reserved_state = repository.get_reserved_state().await.unwrap();
+        let signers = ["a", "b", "c"];
+        let header = test_util::next_header(last_header, &reserved_state, commits, &signers);
+        for x in commits {
+            commit(repository, x, last_header).await;
+        }
+        let block = commit(repository, &Commit::Block(header.clone()), last_header).await;
+        let proof = signers
+            .iter()
+            .map(|name| TypedSignature::sign(&header, &generate_keypair(name).1).unwrap())
+            .collect();
+        (block, proof)
+    }
+
+    #[tokio::test]
+    async fn finalize() {
+        let directory = TempDir::new().unwrap();
+        let mut repository = genesis(&directory).await;
+        let genesis_header = repository.get_last_finalized_block_header().await.unwrap();
+        let genesis_commit = repository.raw.get_initial_commit().await.unwrap();
+
+        // An agenda proof without the agenda.
+        let invalid = Commit::AgendaProof(AgendaProof {
+            agenda_hash: Hash256::zero(),
+            proof: Vec::new(),
+        });
+        let (block, proof) = propose_block(
+            &mut repository,
+            "invalid",
+            &genesis_commit,
+            &genesis_header,
+            &[invalid],
+        )
+        .await;
+        let error = repository.finalize(&block, &proof).await.unwrap_err();
+        assert!(error.to_string().starts_with("verification error"));
+
+        let tx = Commit::Transaction(transaction("Update", Diff::None));
+        let (block, proof) = propose_block(
+            &mut repository,
+            "valid",
+            &genesis_commit,
+            &genesis_header,
+            &[tx],
+        )
+        .await;
+        repository.finalize(&block, &proof).await.unwrap();
+        assert_eq!(
+            repository
+                .raw
+                .locate_branch(&FINALIZED_BRANCH_NAME.into())
+                .await
+                .unwrap(),
+            block
+        );
+
+        // Another block of the height, not on top of the finalized one.
+        let (block, proof) = propose_block(
+            &mut repository,
+            "fork",
+            &genesis_commit,
+            &genesis_header,
+            &[],
+        )
+        .await;
+        let error = repository.finalize(&block, &proof).await.unwrap_err();
+        assert!(error.to_string().contains("is not on top of"));
+    }
+
+    #[tokio::test]
+    async fn reserved_state_at() {
+        let directory = TempDir::new().unwrap();
+        let mut repository = genesis(&directory).await;
+        repository.state_cache.set_interval(2);
+        let genesis_state = repository.get_reserved_state().await.unwrap();
+        assert_eq!(
+            repository.get_last_finalized_block_header().await.unwrap(),
+            genesis_state.genesis_info.header
+        );
+
+        // The reserved state changes in the transactions of the block 3.
+        let mut blocks = vec![repository.raw.get_initial_commit().await.unwrap()];
+        blocks.extend(finalize_blocks(&mut repository, 1..=2).await);
+        let mut upgraded = genesis_state.clone();
+        upgraded.version = "0.2.0".to_owned();
+        let last_header = repository.get_last_finalized_block_header().await.unwrap();
+        let upgrade = Commit::Transaction(transaction(
+            "Upgrade",
+            Diff::Reserved(
+                Box::new(upgraded.clone()),
+                vec![reserved::ReservedStateChange::BumpVersion(
+                    "0.2.0".to_owned(),
+                )],
+            ),
+        ));
+        let upgrade = commit(&mut repository, &upgrade, &last_header).await;
+        blocks.extend(finalize_blocks(&mut repository, 3..=5).await);
+
+        assert_eq!(
+            repository
+                .get_last_finalized_block_header()
+                .await
+                .unwrap()
+                .height,
+            5
+        );
+        let heights = |blocks: Vec<(CommitHash, BlockHeader)>| {
+            blocks
+                .into_iter()
+                .map(|(_, header)| header.height)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            heights(repository.get_finalized_blocks(None).await.unwrap()),
+            vec![5, 4, 3, 2, 1]
+        );
+        assert_eq!(
+            heights(repository.get_finalized_blocks(Some(2)).await.unwrap()),
+            vec![5, 4]
+        );
+        // Twice, the second time from the cached states.
+        for _ in 0..2 {
+            for (height, block) in blocks.iter().enumerate() {
+                let expected = if height < 3 {
+                    &genesis_state
+                } else {
+                    &upgraded
+                };
+                let height = height as BlockHeight;
+                assert_eq!(
+                    repository.locate_finalized_block(height).await.unwrap(),
+                    *block
+                );
+                assert_eq!(
+                    &repository
+                        .reserved_state_at(FinalizedPoint::Height(height))
+                        .await
+                        .unwrap(),
+                    expected
+                );
+                assert_eq!(
+                    &repository
+                        .reserved_state_at(FinalizedPoint::Commit(*block))
+                        .await
+                        .unwrap(),
+                    expected
+                );
+            }
+        }
+        assert_eq!(
+            repository
+                .reserved_state_at(FinalizedPoint::Commit(upgrade))
+                .await
+                .unwrap(),
+            upgraded
+        );
+        assert_eq!(repository.get_reserved_state().await.unwrap(), upgraded);
+        repository
+            .reserved_state_at(FinalizedPoint::Height(6))
+            .await
+            .unwrap_err();
+
+        // Not finalized.
+        repository
+            .raw
+            .checkout(&WORK_BRANCH_NAME.into())
+            .await
+            .unwrap();
+        let last_header = repository.read_block(&blocks[5]).await.unwrap();
+        let pending = commit(
+            &mut repository,
+            &Commit::Transaction(transaction("Pending", Diff::None)),
+            &last_header,
+        )
+        .await;
+        repository
+            .reserved_state_at(FinalizedPoint::Commit(pending))
+            .await
+            .unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn extra_agenda_transaction() {
+        let directory = TempDir::new().unwrap();
+        let mut repository = genesis(&directory).await;
+        let genesis_header = repository.get_last_finalized_block_header().await.unwrap();
+        let (a, a_key) = generate_keypair("a");
+        let b = generate_keypair("b").0;
+        let delegate = |height: BlockHeight| {
+            ExtraAgendaTransaction::Delegate(TxDelegate {
+                delegator: a.clone(),
+                delegatee: b.clone(),
+                governance: true,
+                proof: TypedSignature::sign(&(a.clone(), b.clone(), true, height), &a_key).unwrap(),
+            })
+        };
+
+        // Signed for another height.
+        repository
+            .create_extra_agenda_transaction(&delegate(2))
+            .await
+            .unwrap_err();
+        let commit_hash = repository
+            .create_extra_agenda_transaction(&delegate(1))
+            .await
+            .unwrap();
+        let reserved_state = repository
+            .raw
+            .read_semantic_commit(&commit_hash)
+            .await
+            .unwrap()
+            .reserved_state
+            .unwrap();
+        assert_eq!(
+            reserved_state.members[0].governance_delegations,
+            Some(b.clone())
+        );
+        assert_eq!(
+            repository
+                .verify_work_branch()
+                .await
+                .unwrap()
+                .get_reserved_state(),
+            &reserved_state
+        );
+        // Nothing is finalized yet.
+        assert_ne!(
+            repository.get_reserved_state().await.unwrap(),
+            reserved_state
+        );
+
+        // A reserved state on the tree that the transaction doesn't make.
+        let mut tampered = to_semantic_commit(
+            &Commit::ExtraAgendaTransaction(delegate(1)),
+            &genesis_header,
+        );
+        let mut upgraded = reserved_state.clone();
+        upgraded.version = "0.2.0".to_owned();
+        tampered.reserved_state = Some(upgraded);
+        let work_commit = repository
+            .raw
+            .locate_branch(&WORK_BRANCH_NAME.into())
+            .await
+            .unwrap();
+        let genesis_commit = repository.raw.get_initial_commit().await.unwrap();
+        repository
+            .raw
+            .move_branch(&WORK_BRANCH_NAME.into(), &genesis_commit)
+            .await
+            .unwrap();
+        repository
+            .raw
+            .checkout(&WORK_BRANCH_NAME.into())
+            .await
+            .unwrap();
+        repository
+            .raw
+            .create_semantic_commit(tampered)
+            .await
+            .unwrap();
+        repository.verify_work_branch().await.unwrap_err();
+        repository
+            .raw
+            .move_branch(&WORK_BRANCH_NAME.into(), &work_commit)
+            .await
+            .unwrap();
+        repository.verify_work_branch().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn amendment() {
+        let directory = TempDir::new().unwrap();
+        let mut repository = genesis(&directory).await;
+        let signer =
+            |name: &str| simperby_network::signer::LocalSigner::new(generate_keypair(name).1);
+        let transaction = repository
+            .create_transaction(&transaction("Update", Diff::None))
+            .await
+            .unwrap();
+        let agenda_commit = repository
+            .create_agenda(generate_keypair("a").0)
+            .await
+            .unwrap();
+        repository
+            .raw
+            .create_branch(&"a-1".into(), agenda_commit)
+            .await
+            .unwrap();
+        repository
+            .raw
+            .move_branch(&WORK_BRANCH_NAME.into(), &transaction)
+            .await
+            .unwrap();
+
+        // Only the author can amend it.
+        repository
+            .amend_agenda(&signer("b"), &agenda_commit)
+            .await
+            .unwrap_err();
+        let amendment_commit = repository
+            .amend_agenda(&signer("a"), &agenda_commit)
+            .await
+            .unwrap();
+        let agenda = repository.read_agenda(&agenda_commit).await.unwrap();
+        let amendment = repository.read_agenda(&amendment_commit).await.unwrap();
+        assert!(amendment.is_amendment_of(&agenda));
+        assert_eq!(
+            repository.get_amendments().await.unwrap(),
+            vec![(agenda_commit, amendment_commit)]
+        );
+
+        let approve = |agenda: &Agenda| {
+            ["a", "b", "c"]
+                .iter()
+                .map(|name| {
+                    let (public_key, private_key) = generate_keypair(name);
+                    (
+                        public_key,
+                        TypedSignature::sign(agenda, &private_key).unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        // Superseded.
+        repository
+            .approve(&agenda_commit, approve(&agenda))
+            .await
+            .unwrap_err();
+        // Not of the agenda.
+        repository
+            .approve(&amendment_commit, approve(&agenda))
+            .await
+            .unwrap_err();
+        let proof_commit = repository
+            .approve(&amendment_commit, approve(&amendment))
+            .await
+            .unwrap();
+        assert_eq!(
+            repository
+                .raw
+                .read_commits_bulk(&[proof_commit])
+                .await
+                .unwrap()[0]
+                .parents,
+            vec![amendment_commit]
+        );
+
+        // Nor does the history with a proof of the superseded agenda verify.
+        let last_header = repository.get_last_finalized_block_header().await.unwrap();
+        repository.raw.checkout_clean().await.unwrap();
+        repository
+            .raw
+            .checkout_detach(&agenda_commit)
+            .await
+            .unwrap();
+        let superseded_proof_commit = repository
+            .raw
+            .create_semantic_commit(to_semantic_commit(
+                &Commit::AgendaProof(AgendaProof {
+                    agenda_hash: agenda.to_hash256(),
+                    proof: approve(&agenda),
+                }),
+                &last_header,
+            ))
+            .await
+            .unwrap();
+        repository
+            .raw
+            .checkout(&FINALIZED_BRANCH_NAME.into())
+            .await
+            .unwrap();
+        let error = repository
+            .verify_history(&superseded_proof_commit, None)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("superseded"), "{}", error);
+        repository
+            .verify_history(&proof_commit, None)
+            .await
+            .unwrap();
     }
-}
\ No newline at end of file
+}
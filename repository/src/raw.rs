@@ -11,23 +11,215 @@ use anyhow::Result;
 
 #[derive(Error, Debug)]
 pub enum Error {
+    /// When the requested object (e.g., commit, branch, tag or remote) does not exist.
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// When the object to create already exists.
+    #[error("already exists: {0}")]
+    AlreadyExists(String),
+    /// When a reference can't be updated because it is not a fast-forward.
+    #[error("not a fast-forward: {0}")]
+    NotFastForward(String),
+    /// When the operation conflicts with the current state of the repository
+    /// (e.g., deleting the branch which is checked out).
+    #[error("conflict: {0}")]
+    Conflict(String),
+    /// When a remote rejects the credentials.
+    #[error("authentication failed: {0}")]
+    AuthFailed(String),
+    /// When a remote does not respond in time, or the connection is reset.
+    #[error("network timeout: {0}")]
+    NetworkTimeout(String),
+    /// When a remote fails otherwise (e.g., the connection is refused,
+    /// the server responds with an HTTP error, or the TLS handshake fails).
+    #[error("network error: {0}")]
+    Network(String),
+    /// When the repository is malformed,
+    /// or the assumption of the method (e.g., there is no merge commit) is violated.
+    #[error("corrupt repository: {0}")]
+    Corrupt(String),
+    /// When the given argument (e.g., a reference name) is malformed.
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    /// Any other git2 error that doesn't fall into the above.
     #[error("git2 error: {0}")]
     Git2Error(git2::Error),
-    /// When the assumption of the method (e.g., there is no merge commit) is violated.
-    #[error("the repository is invalid: {0}")]
-    InvalidRepository(String),
-    #[error("unknown error: {0}")]
-    Unknown(String),
+}
+
+impl Error {
+    /// Returns a stable identifier of the error kind, which can be used
+    /// by the CLI or other programs instead of matching on the messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NotFound(_) => "not_found",
+            Error::AlreadyExists(_) => "already_exists",
+            Error::NotFastForward(_) => "not_fast_forward",
+            Error::Conflict(_) => "conflict",
+            Error::AuthFailed(_) => "auth_failed",
+            Error::NetworkTimeout(_) => "network_timeout",
+            Error::Network(_) => "network",
+            Error::Corrupt(_) => "corrupt",
+            Error::InvalidArgument(_) => "invalid_argument",
+            Error::Git2Error(_) => "git2",
+        }
+    }
+
+    /// Returns whether the same operation may succeed if retried later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::NetworkTimeout(_) => true,
+            Error::Git2Error(e) => e.code() == git2::ErrorCode::Locked,
+            _ => false,
+        }
+    }
 }
 
 impl From<git2::Error> for Error {
     fn from(e: git2::Error) -> Self {
-        Error::Git2Error(e)
+        use git2::{ErrorClass, ErrorCode};
+        let message = e.message().to_owned();
+        match (e.code(), e.class()) {
+            (ErrorCode::NotFound, _) => Error::NotFound(message),
+            (ErrorCode::Exists, _) => Error::AlreadyExists(message),
+            (ErrorCode::NotFastForward, _) => Error::NotFastForward(message),
+            (
+                ErrorCode::Conflict
+                | ErrorCode::MergeConflict
+                | ErrorCode::Modified
+                | ErrorCode::Uncommitted
+                | ErrorCode::Unmerged,
+                _,
+            ) => Error::Conflict(message),
+            (ErrorCode::Auth | ErrorCode::Certificate, _) => Error::AuthFailed(message),
+            (ErrorCode::InvalidSpec, _) => Error::InvalidArgument(message),
+            (_, ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssh | ErrorClass::Ssl) => {
+                if is_transient_network_error(&e) {
+                    Error::NetworkTimeout(message)
+                } else {
+                    Error::Network(message)
+                }
+            }
+            (ErrorCode::HashsumMismatch, _) | (_, ErrorClass::Odb | ErrorClass::Zlib) => {
+                Error::Corrupt(message)
+            }
+            _ => Error::Git2Error(e),
+        }
+    }
+}
+
+/// Whether the network error is a timeout or a reset connection, which may not recur.
+///
+/// libgit2 has no error code for them, so they are told by the messages of the OS.
+fn is_transient_network_error(e: &git2::Error) -> bool {
+    let message = e.message().to_lowercase();
+    e.code() == git2::ErrorCode::Eof
+        || ["timed out", "timeout", "connection reset", "reset by peer", "broken pipe"]
+            .iter()
+            .any(|pattern| message.contains(pattern))
+}
+
+fn invalid_object_id() -> Error {
+    Error::Corrupt("object id is not 20 bytes long".to_string())
+}
+
+fn to_commit_hash(oid: Oid) -> Result<CommitHash, Error> {
+    let hash = <[u8; 20]>::try_from(oid.as_bytes()).map_err(|_| invalid_object_id())?;
+    Ok(CommitHash { hash })
+}
+
+/// Where the reserved state is stored in the tree of a commit, as pretty-printed JSON.
+pub const RESERVED_STATE_PATH: &str = "reserved/state.json";
+
+/// The branch checked out by `init()`, which the root commit is created on.
+pub const INITIAL_BRANCH: &str = "main";
+
+/// The identity of the root commit, which must not depend on the local configuration.
+const ROOT_COMMIT_AUTHOR: (&str, &str) = ("simperby", "simperby@localhost");
+
+/// Formats the message of a semantic commit; the body, if any, follows an empty line.
+fn semantic_commit_message(commit: &SemanticCommit) -> String {
+    if commit.body.is_empty() {
+        commit.title.clone()
+    } else {
+        format!("{}\n\n{}", commit.title, commit.body)
+    }
+}
+
+/// Returns the tree with the reserved state written at `RESERVED_STATE_PATH`.
+fn write_reserved_state<'a>(repo: &'a Repository, tree: &git2::Tree, reserved_state: &ReservedState)
+    -> Result<git2::Tree<'a>, Error> {
+    let data = serde_json::to_vec_pretty(reserved_state)
+        .map_err(|e| Error::InvalidArgument(format!("failed to encode the reserved state: {}", e)))?;
+    let blob = repo.blob(&data)
+        .map_err(|e| Error::from(e))?;
+    let oid = git2::build::TreeUpdateBuilder::new()
+        .upsert(RESERVED_STATE_PATH, blob, git2::FileMode::Blob)
+        .create_updated(repo, tree)
+        .map_err(|e| Error::from(e))?;
+    repo.find_tree(oid).map_err(|e| Error::from(e))
+}
+
+/// Returns the blob of the reserved state in the tree of the commit, if any.
+fn reserved_state_blob(commit: &git2::Commit) -> Result<Option<Oid>, Error> {
+    let tree = commit.tree()
+        .map_err(|e| Error::from(e))?;
+    match tree.get_path(std::path::Path::new(RESERVED_STATE_PATH)) {
+        Ok(entry) => Ok(Some(entry.id())),
+        Err(e) if e.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(e) => Err(Error::from(e)),
+    }
+}
+
+/// The result of validating the object store.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectStoreReport {
+    /// The references that point to missing objects.
+    pub dangling_references: Vec<String>,
+    /// The objects (in hex) that can't be read or don't match their hashes.
+    pub corrupt_objects: Vec<String>,
+    /// `(commit, missing parent)` for every parent that is missing.
+    pub broken_parent_links: Vec<(CommitHash, CommitHash)>,
+    /// The commits without parents that are reachable from the references.
+    pub root_commits: Vec<CommitHash>,
+}
+
+impl ObjectStoreReport {
+    /// Returns whether no problem was found in the object store itself.
+    pub fn is_ok(&self) -> bool {
+        self.dangling_references.is_empty()
+            && self.corrupt_objects.is_empty()
+            && self.broken_parent_links.is_empty()
     }
 }
 
+/// A line of a file, attributed to the commit that last changed it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlameLine {
+    /// The line number, starting from 1.
+    pub line_number: usize,
+    pub content: String,
+    pub commit_hash: CommitHash,
+    /// The name of the author of the commit.
+    pub author: String,
+    /// The time of the commit.
+    pub timestamp: Timestamp,
+}
+
+/// The metadata of a commit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitInfo {
+    pub hash: CommitHash,
+    pub parents: Vec<CommitHash>,
+    /// The whole commit message, including the title.
+    pub message: String,
+    /// The name of the author of the commit.
+    pub author: String,
+    /// The time of the commit.
+    pub timestamp: Timestamp,
+}
+
 /// A commit without any diff on non-reserved area.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SemanticCommit {
     pub title: String,
     pub body: String,
@@ -39,10 +231,73 @@ pub struct Git2Repository {
     repo: Cell<Repository>
 }
 
+/// How `fetch_all_with()` fetches the remotes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FetchPolicy {
+    /// The maximum number of the remotes fetched at the same time.
+    pub concurrency: usize,
+    /// The deadline of each remote; a remote not done by then is reported as timed out.
+    pub timeout: std::time::Duration,
+}
+
+impl Default for FetchPolicy {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            timeout: std::time::Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteFetch {
+    pub remote: String,
+    pub received_objects: usize,
+    pub duration: std::time::Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteFailure {
+    pub remote: String,
+    /// See `Error::code()`.
+    pub code: String,
+    pub message: String,
+}
+
+/// The outcome of fetching each remote, in the order of the remotes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FetchReport {
+    pub fetched: Vec<RemoteFetch>,
+    pub failed: Vec<RemoteFailure>,
+}
+
+impl FetchReport {
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// The credentials offered by `push()` as the username and the password
+/// (e.g., an access token of a forge over HTTPS).
+#[derive(Clone, PartialEq, Eq)]
+pub struct PushCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for PushCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PushCredentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
 #[async_trait]
 pub trait RawRepository {
     /// Initialize the genesis repository from the genesis working tree.
     ///
+    /// The new repository has `INITIAL_BRANCH` checked out, without any commit.
     /// Fails if there is already a repository.
     async fn init(directory: &str) -> Result<Self, Error>
     where
@@ -99,6 +354,41 @@ pub trait RawRepository {
     /// Removes the tag.
     async fn remove_tag(&mut self, tag: &Tag) -> Result<(), Error>;
 
+    // --------------------
+    // Note-related methods
+    // --------------------
+
+    /// Attaches a note to the commit under the given namespace, overwriting the existing one.
+    ///
+    /// Notes are stored in `refs/notes/<namespace>`, so they are local, non-consensus metadata
+    /// that never alter the commit or the history.
+    async fn add_note(
+        &mut self,
+        commit_hash: &CommitHash,
+        namespace: &str,
+        content: &str,
+    ) -> Result<(), Error>;
+
+    /// Reads all the notes attached to the commit.
+    ///
+    /// Returns `(namespace, content)`.
+    async fn read_notes(&self, commit_hash: &CommitHash) -> Result<Vec<(String, String)>, Error>;
+
+    // -------------------------
+    // Reference-related methods
+    // -------------------------
+
+    /// Lists the references of which the full names start with the prefix (e.g., `refs/simperby/`).
+    ///
+    /// Returns `(full_name, commit_hash)`.
+    async fn list_references(&self, prefix: &str) -> Result<Vec<(String, CommitHash)>, Error>;
+
+    /// Creates or moves the reference of the full name to the commit.
+    async fn set_reference(&mut self, name: &str, commit_hash: &CommitHash) -> Result<(), Error>;
+
+    /// Deletes the reference of the full name.
+    async fn delete_reference(&mut self, name: &str) -> Result<(), Error>;
+
     // ----------------------
     // Commit-related methods
     // ----------------------
@@ -111,16 +401,65 @@ pub trait RawRepository {
     ) -> Result<CommitHash, Error>;
 
     /// Creates a semantic commit from the currently checked out branch.
+    ///
+    /// The reserved state, if any, replaces the one at `RESERVED_STATE_PATH` of the tree.
     async fn create_semantic_commit(&mut self, commit: SemanticCommit)
         -> Result<CommitHash, Error>;
 
-    /// Reads the reserved state from the current working tree.
+    /// Creates the root commit of an empty repository, on the currently checked out branch.
+    ///
+    /// The tree is the index (e.g., imported by `import_tree()`) with the reserved state.
+    /// The given time is used as both the author time and the committer time
+    /// with a fixed identity, so that the commit hash depends only on the arguments and the index.
+    async fn create_root_semantic_commit(&mut self, commit: SemanticCommit, timestamp: Timestamp)
+        -> Result<CommitHash, Error>;
+
+    /// Copies the tree of the commit of the repository at `source` (a path or a URL)
+    /// into the index and the working tree of an empty repository.
+    ///
+    /// Only the objects of the tree are copied, not the commit nor its ancestors.
+    /// It fails if the tree has a submodule, which can't be copied.
+    async fn import_tree(&mut self, source: &str, commit_hash: &CommitHash) -> Result<(), Error>;
+
+    /// Reads the semantic commit, with the reserved state if the commit changed it
+    /// (or it is a root commit).
     async fn read_semantic_commit(&self, commit_hash: &CommitHash)
         -> Result<SemanticCommit, Error>;
 
+    /// Reads the given commits in a single pass, in the same order.
+    ///
+    /// Prefer this to reading the commits one by one, which takes the lock for each.
+    async fn read_commits_bulk(&self, commit_hashes: &[CommitHash])
+        -> Result<Vec<CommitInfo>, Error>;
+
     /// Removes orphaned commits. Same as `git gc --prune=now --aggressive`
     async fn run_garbage_collection(&mut self) -> Result<(), Error>;
 
+    // -----------------------
+    // Staging-related methods
+    // -----------------------
+
+    /// Stages the changes of the given paths (relative to the working tree) in the index,
+    /// including the deletions. Same as `git add -A <paths>`.
+    async fn stage(&mut self, paths: &[String]) -> Result<(), Error>;
+
+    /// Returns the `(path, size)` of the files staged in the index, compared to `HEAD`.
+    ///
+    /// The size is `None` for a deleted file.
+    async fn read_staged_changes(&self) -> Result<Vec<(String, Option<u64>)>, Error>;
+
+    /// Returns the diff staged in the index, compared to `HEAD`. Same as `git diff --cached`.
+    async fn show_staged_diff(&self) -> Result<String, Error>;
+
+    /// Creates a commit of the index on top of the currently checked out branch.
+    async fn commit_staged(&mut self, commit_message: &str) -> Result<CommitHash, Error>;
+
+    /// Applies the changes of the commit on top of the currently checked out branch,
+    /// keeping its message and its author. Same as `git cherry-pick <commit>`.
+    ///
+    /// Fails with `Error::Conflict` if the changes don't apply cleanly.
+    async fn cherry_pick(&mut self, commit_hash: &CommitHash) -> Result<CommitHash, Error>;
+
     // ----------------------------
     // Working-tree-related methods
     // ----------------------------
@@ -135,6 +474,17 @@ pub trait RawRepository {
     /// Checkouts to the commit and make `HEAD` in a detached mode.
     async fn checkout_detach(&mut self, commit_hash: &CommitHash) -> Result<(), Error>;
 
+    /// Creates a linked working tree at `path` with the branch checked out,
+    /// sharing the object store with this repository.
+    ///
+    /// The worktree is named after the branch. Same as `git worktree add <path> <branch>`.
+    async fn create_worktree(&mut self, branch: &Branch, path: &str) -> Result<(), Error>;
+
+    /// Removes the linked working tree of the branch, including its files.
+    ///
+    /// Same as `git worktree remove --force <path>`.
+    async fn remove_worktree(&mut self, branch: &Branch) -> Result<(), Error>;
+
     // ---------------
     // Various queries
     // ---------------
@@ -142,14 +492,40 @@ pub trait RawRepository {
     /// Returns the commit hash of the current HEAD.
     async fn get_head(&self) -> Result<CommitHash, Error>;
 
+    /// Returns the path of the working directory of the repository.
+    async fn get_working_directory_path(&self) -> Result<String, Error>;
+
     /// Returns the commit hash of the initial commit.
     ///
     /// Fails if the repository is empty.
     async fn get_initial_commit(&self) -> Result<CommitHash, Error>;
 
+    /// Returns the commit whose hash starts with the given hex prefix (at least 4 digits).
+    ///
+    /// Fails with `InvalidArgument` if the prefix is malformed or matches several commits.
+    async fn resolve_commit_prefix(&self, prefix: &str) -> Result<CommitHash, Error>;
+
+    /// Validates the object store, visiting every object and every commit reachable from the references.
+    ///
+    /// Same as `git fsck --no-dangling`. Note that it fails only if the validation itself
+    /// can't be performed; the problems found are reported in the result.
+    async fn check_object_store(&self) -> Result<ObjectStoreReport, Error>;
+
+    /// Returns the `(path, size)` of the files added or modified by the given commit,
+    /// compared to its parent.
+    async fn read_changed_file_sizes(
+        &self,
+        commit_hash: &CommitHash,
+    ) -> Result<Vec<(String, u64)>, Error>;
+
     /// Returns the diff of the given commit.
     async fn show_commit(&self, commit_hash: &CommitHash) -> Result<String, Error>;
 
+    /// Returns the per-line attribution of the file as of the given commit.
+    ///
+    /// `path` is relative to the root of the working tree.
+    async fn blame(&self, path: &str, at_commit: &CommitHash) -> Result<Vec<BlameLine>, Error>;
+
     /// Lists the ancestor commits of the given commit (The first element is the direct parent).
     ///
     /// It fails if there is a merge commit.
@@ -191,19 +567,69 @@ pub trait RawRepository {
     async fn remove_remote(&mut self, remote_name: &str) -> Result<(), Error>;
 
     /// Fetches the remote repository. Same as `git fetch --all -j <LARGE NUMBER>`.
+    ///
+    /// It fetches with the default `FetchPolicy`, and fails only if every remote failed
+    /// (see `fetch_all_with()`); the other failures are logged.
     async fn fetch_all(&mut self) -> Result<(), Error>;
 
+    /// Fetches the remotes concurrently, each within the deadline of the policy,
+    /// reporting the outcome of every remote instead of stopping at the first failure.
+    ///
+    /// A remote past its deadline is abandoned: its fetch is aborted in the background
+    /// as soon as it transfers anything, without holding up the others.
+    async fn fetch_all_with(&mut self, policy: &FetchPolicy) -> Result<FetchReport, Error>;
+
     /// Lists all the remote repositories.
     ///
     /// Returns `(remote_name, remote_url)`.
     async fn list_remotes(&self) -> Result<Vec<(String, String)>, Error>;
 
+    /// Pushes the references to the URL, which needn't be a configured remote.
+    ///
+    /// Same as `git push <remote_url> <refspec>...`.
+    /// It fails if the remote rejects any of the references (e.g., not a fast-forward).
+    async fn push(
+        &self,
+        remote_url: &str,
+        refspecs: &[String],
+        credentials: Option<&PushCredentials>,
+    ) -> Result<(), Error>;
+
     /// Lists all the remote tracking branches.
     ///
     /// Returns `(remote_name, remote_url, commit_hash)`
     async fn list_remote_tracking_branches(
         &self,
     ) -> Result<Vec<(String, String, CommitHash)>, Error>;
+
+    /// Gets the commit that the remote tracking branch points to.
+    ///
+    /// Same as `git rev-parse <remote_name>/<branch>`.
+    async fn locate_remote_branch(&self, remote_name: &str, branch: &Branch)
+        -> Result<CommitHash, Error>;
+
+    // --------------------
+    // Pack-related methods
+    // --------------------
+
+    /// Creates a packfile of the commit and all its ancestors, with their trees and blobs.
+    ///
+    /// Same as `git pack-objects --revs --stdout` with `<commit_hash>` as the input.
+    async fn create_pack(&self, commit_hash: &CommitHash) -> Result<Vec<u8>, Error>;
+
+    /// Writes the objects of the packfile into the object database.
+    ///
+    /// No reference is created or moved. Same as `git index-pack --stdin`.
+    async fn import_pack(&mut self, pack: &[u8]) -> Result<(), Error>;
+
+    /// Same as `create_pack()`, but writes the packfile to the file at the path
+    /// chunk by chunk, never holding the whole of it in memory.
+    ///
+    /// Returns the size and the hash of the packfile.
+    async fn write_pack(&self, commit_hash: &CommitHash, path: &str) -> Result<(u64, Hash256), Error>;
+
+    /// Same as `import_pack()`, but reads the packfile from the file at the path chunk by chunk.
+    async fn import_pack_file(&mut self, path: &str) -> Result<(), Error>;
 }
 
 pub struct CurRepository {
@@ -218,9 +644,11 @@ impl CurRepository {
     where
         Self: Sized {
             match Repository::open(directory) {
-                Ok(_repo) => Err(Error::InvalidRepository("There is an already existing repository".to_string())),
+                Ok(_repo) => Err(Error::AlreadyExists("There is an already existing repository".to_string())),
                 Err(_e) => {
-                    let repo = Repository::init(directory)
+                    let mut options = git2::RepositoryInitOptions::new();
+                    options.initial_head(INITIAL_BRANCH);
+                    let repo = Repository::init_opts(directory, &options)
                         .map_err(|e| Error::from(e))?;
                     let repo = Cell::new(repo);
                     let repo = Git2Repository{ repo };
@@ -256,7 +684,7 @@ impl CurRepository {
                 .0.name()
                 .map_err(|e| Error::from(e))?
                 .map(|name| name.to_string())
-                .ok_or(Error::Unknown("err".to_string()))?;
+                .ok_or_else(|| Error::Corrupt("branch name is not valid UTF-8".to_string()))?;
 
             Ok(branch_name)
         }).collect::<Result<Vec<Branch>, Error>>();
@@ -293,9 +721,9 @@ impl CurRepository {
             BranchType::Local
         ).map_err(|e| Error::from(e))?;
         let oid = branch.get().target()
-            .ok_or(Error::Unknown("err".to_string()))?; //TODO: error message
+            .ok_or_else(|| Error::Corrupt("branch is a symbolic reference".to_string()))?;
         let hash = <[u8; 20]>::try_from(oid.as_bytes())
-            .map_err(|_| Error::Unknown("err".to_string()))?; //TODO: error message
+            .map_err(|_| invalid_object_id())?;
         
         Ok(CommitHash{ hash })
     }
@@ -313,7 +741,7 @@ impl CurRepository {
 b
                 .map_err(|&e| Error::from(e))?
                 .0.get().target()
-                .ok_or(Error::Unknown("err".to_string()))? 
+                .ok_or(Error::Corrupt("branch is a symbolic reference".to_string()))? 
                 == git2::Oid::from_bytes(&commit_hash.hash)
                 .map_err(|e| Error::from(e))?
         ).collect::<Result<Vec<(git2::Branch, BranchType)>, git2::Error>>()?.iter()
@@ -321,7 +749,7 @@ b
             let name = branch.0.name()
                 .map_err(|e| Error::from(e))?
                 .map(|name| name.to_string())
-                .ok_or(Error::Unknown("err".to_string()))?;
+                .ok_or(Error::Corrupt("branch name is not valid UTF-8".to_string()))?;
 
             Ok(name)
         }).collect::<Result<Vec<Branch>, Error>>();
@@ -358,11 +786,11 @@ b
         let current_branch = repo.head()
             .map_err(|e| Error::from(e))?
             .shorthand()
-            .ok_or(Error::Unknown("err".to_string()))?
+            .ok_or_else(|| Error::Corrupt("HEAD name is not valid UTF-8".to_string()))?
             .to_string();
         
         let res = if &current_branch == branch {
-            Err(Error::InvalidRepository(("Given branch is currently checkout branch").to_string()))
+            Err(Error::Conflict(("Given branch is currently checkout branch").to_string()))
         }else{
             git2_branch.delete().map_err(|e| Error::from(e))
         };
@@ -383,7 +811,9 @@ b
             .map_err(|e| Error::from(e))?;
 
         let tag_list = tag_array.iter().map(|tag| {
-            let tag_name = tag.ok_or(Error::Unknown("err".to_string()))?.to_string();
+            let tag_name = tag
+                .ok_or_else(|| Error::Corrupt("tag name is not valid UTF-8".to_string()))?
+                .to_string();
 
             Ok(tag_name)
         }).collect::<Result<Vec<Tag>, Error>>();
@@ -436,7 +866,7 @@ b
         
         let oid = object.id();
         let hash = <[u8; 20]>::try_from(oid.as_bytes())
-            .map_err(|e| Error::Unknown("abc".to_string()))?; //TODO: error message
+            .map_err(|_| invalid_object_id())?;
         let commit_hash = CommitHash{ hash }; 
         Ok(commit_hash)
     }
@@ -485,6 +915,122 @@ b
         let repo = self.repo.repo.into_inner();
         repo.tag_delete(tag.as_str()).map_err(|e| Error::from(e))
     }
+
+    // --------------------
+    // Note-related methods
+    // --------------------
+
+    /// Attaches a note to the commit under the given namespace, overwriting the existing one.
+    fn add_note(
+        &mut self,
+        commit_hash: &CommitHash,
+        namespace: &str,
+        content: &str,
+    ) -> Result<(), Error>{
+        let repo = self.repo.repo.into_inner();
+        let notes_ref = format!("refs/notes/{}", namespace);
+        if !git2::Reference::is_valid_name(&notes_ref) {
+            return Err(Error::InvalidArgument(format!("invalid note namespace: {}", namespace)));
+        }
+        let oid = Oid::from_bytes(&commit_hash.hash)
+            .map_err(|e| Error::from(e))?;
+        // Notes are local, so they are signed by the node rather than the user.
+        let sig = git2::Signature::now("simperby", "simperby@localhost")
+            .map_err(|e| Error::from(e))?;
+
+        let _note = repo.note(
+            &sig,
+            &sig,
+            Some(notes_ref.as_str()),
+            oid,
+            content,
+            true
+        ).map_err(|e| Error::from(e))?;
+
+        Ok(())
+    }
+
+    /// Reads all the notes attached to the commit.
+    ///
+    /// Returns `(namespace, content)`.
+    fn read_notes(&self, commit_hash: &CommitHash) -> Result<Vec<(String, String)>, Error>{
+        let repo = self.repo.repo.into_inner();
+        let oid = Oid::from_bytes(&commit_hash.hash)
+            .map_err(|e| Error::from(e))?;
+        let references = repo.references_glob("refs/notes/*")
+            .map_err(|e| Error::from(e))?;
+
+        let mut notes = Vec::new();
+        for reference in references {
+            let reference = reference.map_err(|e| Error::from(e))?;
+            let notes_ref = reference.name()
+                .ok_or_else(|| Error::Corrupt("note reference is not valid UTF-8".to_string()))?;
+            let note = match repo.find_note(Some(notes_ref), oid) {
+                Ok(note) => note,
+                Err(e) if e.code() == git2::ErrorCode::NotFound => continue,
+                Err(e) => return Err(Error::from(e)),
+            };
+            let content = note.message()
+                .ok_or_else(|| Error::Corrupt("note is not valid UTF-8".to_string()))?
+                .to_owned();
+            notes.push((notes_ref.trim_start_matches("refs/notes/").to_owned(), content));
+        }
+
+        Ok(notes)
+    }
+
+    // -------------------------
+    // Reference-related methods
+    // -------------------------
+
+    /// Lists the references of which the full names start with the prefix (e.g., `refs/simperby/`).
+    ///
+    /// Returns `(full_name, commit_hash)`.
+    fn list_references(&self, prefix: &str) -> Result<Vec<(String, CommitHash)>, Error>{
+        let repo = self.repo.repo.into_inner();
+        let references = repo.references()
+            .map_err(|e| Error::from(e))?;
+
+        let mut result = Vec::new();
+        for reference in references {
+            let reference = reference.map_err(|e| Error::from(e))?;
+            let name = match reference.name() {
+                Some(name) if name.starts_with(prefix) => name.to_owned(),
+                _ => continue,
+            };
+            let commit = reference.peel_to_commit()
+                .map_err(|e| Error::from(e))?;
+            result.push((name, to_commit_hash(commit.id())?));
+        }
+        result.sort();
+        Ok(result)
+    }
+
+    /// Creates or moves the reference of the full name to the commit.
+    fn set_reference(&mut self, name: &str, commit_hash: &CommitHash) -> Result<(), Error>{
+        let repo = self.repo.repo.into_inner();
+        if !git2::Reference::is_valid_name(name) {
+            return Err(Error::InvalidArgument(format!("invalid reference name: {}", name)));
+        }
+        let oid = Oid::from_bytes(&commit_hash.hash)
+            .map_err(|e| Error::from(e))?;
+
+        repo.reference(name, oid, true, "simperby: set reference")
+            .map_err(|e| Error::from(e))?;
+        Ok(())
+    }
+
+    /// Deletes the reference of the full name.
+    fn delete_reference(&mut self, name: &str) -> Result<(), Error>{
+        let repo = self.repo.repo.into_inner();
+        let mut reference = repo.find_reference(name)
+            .map_err(|e| Error::from(e))?;
+
+        reference.delete()
+            .map_err(|e| Error::from(e))?;
+        Ok(())
+    }
+
     // ----------------------
     // Commit-related methods
     // ----------------------
@@ -539,88 +1085,433 @@ b
     /// Creates a semantic commit from the currently checked out branch.
     fn create_semantic_commit(&mut self, commit: SemanticCommit)
         -> Result<CommitHash, Error>{
-         /*   pub title: String,
-            pub body: String,
-            /// (If this commit made any change) the new reserved state.
-            pub reserved_state: Option<ReservedState>,*/
-        
-            //commit message만 달라짐
-            unimplemented!()    
-        }
-
-    /// Reads the reserved state from the current working tree.
-    fn read_semantic_commit(&self, commit_hash: &CommitHash)
-        -> Result<SemanticCommit, Error>{
-            unimplemented!()
-        }
-
-    /// Removes orphaned commits. Same as `git gc --prune=now --aggressive`
-    fn run_garbage_collection(&mut self) -> Result<(), Error>{
-        unimplemented!()
-        //TODO: check all of the references and identify orphaned commits
-    }
-
-    // ----------------------------
-    // Working-tree-related methods
-    // ----------------------------
+        let repo = self.repo.repo.into_inner();
+        let parent = repo.head()
+            .and_then(|head| head.peel_to_commit())
+            .map_err(|e| Error::from(e))?;
+        let tree = parent.tree()
+            .map_err(|e| Error::from(e))?;
+        let tree = match &commit.reserved_state {
+            Some(reserved_state) => write_reserved_state(&repo, &tree, reserved_state)?,
+            None => tree,
+        };
+        let signature = repo.signature()
+            .map_err(|e| Error::from(e))?;
 
-    /// Checkouts and cleans the current working tree.
-    /// This is same as `git checkout . && git clean -fd`.
-    fn checkout_clean(&mut self) -> Result<(), Error>{
-        unimplemented!()
-        //reset unstaged files and remove untracked files including directory
-        //TODO: check repo.statues() and statusOption
+        let oid = repo.commit(Some("HEAD"), &signature, &signature, &semantic_commit_message(&commit), &tree, &[&parent])
+            .map_err(|e| Error::from(e))?;
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.force();
+        repo.checkout_head(Some(&mut checkout))
+            .map_err(|e| Error::from(e))?;
+        to_commit_hash(oid)
     }
 
-    /// Checkouts to the branch.
-    fn checkout(&mut self, branch: &Branch) -> Result<(), Error>{
+    /// Creates the root commit of an empty repository, on the currently checked out branch.
+    fn create_root_semantic_commit(&mut self, commit: SemanticCommit, timestamp: Timestamp)
+        -> Result<CommitHash, Error>{
         let repo = self.repo.repo.into_inner();
-        let obj = repo.revparse_single(
-            &("refs/heads/".to_owned() + branch)
-        ).map_err(|e| Error::from(e))?;
-
-        repo.checkout_tree(
-            &obj,
-            None
-        ).map_err(|e| Error::from(e));
-
-        repo.set_head(
-            &("refs/heads/".to_owned() + branch)
-        ).map_err(|e| Error::from(e))?;
+        match repo.head() {
+            Err(e) if e.code() == git2::ErrorCode::UnbornBranch => {}
+            Err(e) => return Err(Error::from(e)),
+            Ok(_) => return Err(Error::Conflict("the current branch already has a commit".to_string())),
+        }
+        let reserved_state = commit.reserved_state.as_ref()
+            .ok_or_else(|| Error::InvalidArgument("the root commit has no reserved state".to_string()))?;
+        let mut index = repo.index()
+            .map_err(|e| Error::from(e))?;
+        let tree_oid = index.write_tree()
+            .map_err(|e| Error::from(e))?;
+        let tree = repo.find_tree(tree_oid)
+            .map_err(|e| Error::from(e))?;
+        let tree = write_reserved_state(&repo, &tree, reserved_state)?;
+        let time = git2::Time::new((timestamp / 1000) as i64, 0);
+        let signature = git2::Signature::new(ROOT_COMMIT_AUTHOR.0, ROOT_COMMIT_AUTHOR.1, &time)
+            .map_err(|e| Error::from(e))?;
 
-        Ok(())
+        let oid = repo.commit(Some("HEAD"), &signature, &signature, &semantic_commit_message(&commit), &tree, &[])
+            .map_err(|e| Error::from(e))?;
+        index.read_tree(&tree)
+            .map_err(|e| Error::from(e))?;
+        index.write()
+            .map_err(|e| Error::from(e))?;
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.force();
+        repo.checkout_head(Some(&mut checkout))
+            .map_err(|e| Error::from(e))?;
+        to_commit_hash(oid)
     }
 
-    /// Checkouts to the commit and make `HEAD` in a detached mode.
-    fn checkout_detach(&mut self, commit_hash: &CommitHash) -> Result<(), Error>{
+    /// Copies the tree of the commit of the repository at `source` into the index
+    /// and the working tree, without the history.
+    fn import_tree(&mut self, source: &str, commit_hash: &CommitHash) -> Result<(), Error>{
         let repo = self.repo.repo.into_inner();
+        // The source is cloned aside, so that none of its history enters this repository.
+        let staging = tempfile::TempDir::new()
+            .map_err(|e| Error::Corrupt(format!("failed to create a directory: {}", e)))?;
+        let source_repo = git2::build::RepoBuilder::new()
+            .bare(true)
+            .clone(source, staging.path())
+            .map_err(|e| Error::from(e))?;
         let oid = Oid::from_bytes(&commit_hash.hash)
             .map_err(|e| Error::from(e))?;
+        let tree = source_repo.find_commit(oid)
+            .map_err(|e| Error::from(e))?
+            .tree()
+            .map_err(|e| Error::from(e))?;
 
-        repo.set_head_detached(oid)
-            .map_err(|e| Error::from(e));
+        let source_odb = source_repo.odb()
+            .map_err(|e| Error::from(e))?;
+        let odb = repo.odb()
+            .map_err(|e| Error::from(e))?;
+        let copy = |oid: Oid| -> Result<(), Error> {
+            let object = source_odb.read(oid)
+                .map_err(|e| Error::from(e))?;
+            odb.write(object.kind(), object.data())
+                .map_err(|e| Error::from(e))?;
+            Ok(())
+        };
+        copy(tree.id())?;
+        let mut result = Ok(());
+        tree.walk(git2::TreeWalkMode::PreOrder, |directory, entry| {
+            let copied = match entry.kind() {
+                Some(ObjectType::Tree) | Some(ObjectType::Blob) => copy(entry.id()),
+                _ => Err(Error::InvalidArgument(format!(
+                    "{}{} is a submodule",
+                    directory,
+                    entry.name().unwrap_or_default()
+                ))),
+            };
+            match copied {
+                Ok(()) => git2::TreeWalkResult::Ok,
+                Err(e) => {
+                    result = Err(e);
+                    git2::TreeWalkResult::Abort
+                }
+            }
+        }).map_err(|e| Error::from(e))?;
+        result?;
 
+        let tree = repo.find_tree(tree.id())
+            .map_err(|e| Error::from(e))?;
+        let mut index = repo.index()
+            .map_err(|e| Error::from(e))?;
+        index.read_tree(&tree)
+            .map_err(|e| Error::from(e))?;
+        index.write()
+            .map_err(|e| Error::from(e))?;
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.force();
+        repo.checkout_index(Some(&mut index), Some(&mut checkout))
+            .map_err(|e| Error::from(e))?;
         Ok(())
-        //https://stackoverflow.com/questions/55141013/how-to-get-the-behaviour-of-git-checkout-in-rust-git2
     }
 
-    // ---------------
-    // Various queries
-    // ---------------
-
-    /// Returns the commit hash of the current HEAD.
-    fn get_head(&self) -> Result<CommitHash, Error>{
+    /// Reads the semantic commit, with the reserved state if the commit changed it
+    /// (or it is a root commit).
+    fn read_semantic_commit(&self, commit_hash: &CommitHash)
+        -> Result<SemanticCommit, Error>{
         let repo = self.repo.repo.into_inner();
-        let ref_head = repo.head()
+        let oid = Oid::from_bytes(&commit_hash.hash)
+            .map_err(|e| Error::from(e))?;
+        let commit = repo.find_commit(oid)
+            .map_err(|e| Error::from(e))?;
+        let message = commit.message()
+            .ok_or_else(|| Error::Corrupt(format!("the message of commit {} is not valid UTF-8", oid)))?;
+        let (title, body) = message.split_once("\n\n").unwrap_or((message, ""));
+
+        let blob = reserved_state_blob(&commit)?;
+        let changed = match commit.parents().next() {
+            Some(parent) => reserved_state_blob(&parent)? != blob,
+            None => true,
+        };
+        let reserved_state = match blob {
+            Some(blob) if changed => {
+                let blob = repo.find_blob(blob)
+                    .map_err(|e| Error::from(e))?;
+                Some(ReservedState::from_json(blob.content())
+                    .map_err(|e| Error::Corrupt(format!("the reserved state of commit {}: {}", oid, e)))?)
+            }
+            _ => None,
+        };
+        Ok(SemanticCommit {
+            title: title.to_owned(),
+            body: body.to_owned(),
+            reserved_state,
+        })
+    }
+
+    /// Reads the given commits in a single pass, in the same order.
+    fn read_commits_bulk(&self, commit_hashes: &[CommitHash])
+        -> Result<Vec<CommitInfo>, Error>{
+        let repo = self.repo.repo.into_inner();
+        commit_hashes.iter().map(|commit_hash| {
+            let oid = Oid::from_bytes(&commit_hash.hash)
+                .map_err(|e| Error::from(e))?;
+            let commit = repo.find_commit(oid)
+                .map_err(|e| Error::from(e))?;
+            let parents = commit.parent_ids()
+                .map(to_commit_hash)
+                .collect::<Result<Vec<CommitHash>, Error>>()?;
+            let message = commit.message()
+                .ok_or_else(|| Error::Corrupt("commit message is not valid UTF-8".to_string()))?
+                .to_owned();
+            let author = commit.author();
+
+            Ok(CommitInfo {
+                hash: *commit_hash,
+                parents,
+                message,
+                author: author.name().unwrap_or_default().to_owned(),
+                timestamp: author.when().seconds() as Timestamp * 1000,
+            })
+        }).collect::<Result<Vec<CommitInfo>, Error>>()
+    }
+
+    /// Removes orphaned commits. Same as `git gc --prune=now --aggressive`
+    fn run_garbage_collection(&mut self) -> Result<(), Error>{
+        unimplemented!()
+        //TODO: check all of the references and identify orphaned commits
+    }
+
+    // -----------------------
+    // Staging-related methods
+    // -----------------------
+
+    /// Stages the changes of the given paths in the index, including the deletions.
+    fn stage(&mut self, paths: &[String]) -> Result<(), Error>{
+        let repo = self.repo.repo.into_inner();
+        let workdir = repo.workdir()
+            .ok_or_else(|| Error::Conflict("repository is bare".to_string()))?
+            .to_owned();
+        let mut index = repo.index()
+            .map_err(|e| Error::from(e))?;
+
+        for path in paths {
+            let result = if workdir.join(path).exists() {
+                index.add_path(std::path::Path::new(path))
+            } else {
+                index.remove_path(std::path::Path::new(path))
+            };
+            result.map_err(|e| Error::from(e))?;
+        }
+        index.write()
+            .map_err(|e| Error::from(e))?;
+
+        Ok(())
+    }
+
+    /// Returns the `(path, size)` of the files staged in the index, compared to `HEAD`.
+    fn read_staged_changes(&self) -> Result<Vec<(String, Option<u64>)>, Error>{
+        let repo = self.repo.repo.into_inner();
+        let head_tree = repo.head()
+            .and_then(|head| head.peel_to_tree())
+            .map_err(|e| Error::from(e))?;
+        let diff = repo.diff_tree_to_index(Some(&head_tree), None, None)
+            .map_err(|e| Error::from(e))?;
+
+        let mut changes = Vec::new();
+        for delta in diff.deltas() {
+            let path = delta.new_file().path()
+                .or_else(|| delta.old_file().path())
+                .and_then(|path| path.to_str())
+                .ok_or_else(|| Error::Corrupt("path is not valid UTF-8".to_string()))?;
+            let size = match delta.status() {
+                git2::Delta::Deleted => None,
+                _ => {
+                    let blob = repo.find_blob(delta.new_file().id())
+                        .map_err(|e| Error::from(e))?;
+                    Some(blob.size() as u64)
+                }
+            };
+            changes.push((path.to_owned(), size));
+        }
+
+        Ok(changes)
+    }
+
+    /// Returns the diff staged in the index, compared to `HEAD`.
+    fn show_staged_diff(&self) -> Result<String, Error>{
+        let repo = self.repo.repo.into_inner();
+        let head_tree = repo.head()
+            .and_then(|head| head.peel_to_tree())
+            .map_err(|e| Error::from(e))?;
+        let diff = repo.diff_tree_to_index(Some(&head_tree), None, None)
+            .map_err(|e| Error::from(e))?;
+
+        let mut patch = String::new();
+        diff.print(git2::DiffFormat::Patch, |_, _, line| {
+            if matches!(line.origin(), '+' | '-' | ' ') {
+                patch.push(line.origin());
+            }
+            patch.push_str(str::from_utf8(line.content()).unwrap_or("(binary)\n"));
+            true
+        }).map_err(|e| Error::from(e))?;
+
+        Ok(patch)
+    }
+
+    /// Creates a commit of the index on top of the currently checked out branch.
+    fn commit_staged(&mut self, commit_message: &str) -> Result<CommitHash, Error>{
+        let repo = self.repo.repo.into_inner();
+        let mut index = repo.index()
+            .map_err(|e| Error::from(e))?;
+        let tree_oid = index.write_tree()
+            .map_err(|e| Error::from(e))?;
+        let tree = repo.find_tree(tree_oid)
+            .map_err(|e| Error::from(e))?;
+        let parent = repo.head()
+            .and_then(|head| head.peel_to_commit())
+            .map_err(|e| Error::from(e))?;
+        let signature = repo.signature()
+            .map_err(|e| Error::from(e))?;
+
+        let oid = repo.commit(Some("HEAD"), &signature, &signature, commit_message, &tree, &[&parent])
+            .map_err(|e| Error::from(e))?;
+        to_commit_hash(oid)
+    }
+
+    /// Applies the changes of the commit on top of the currently checked out branch,
+    /// keeping its message and its author. Same as `git cherry-pick <commit>`.
+    fn cherry_pick(&mut self, commit_hash: &CommitHash) -> Result<CommitHash, Error>{
+        let repo = self.repo.repo.into_inner();
+        let oid = Oid::from_bytes(&commit_hash.hash)
+            .map_err(|e| Error::from(e))?;
+        let commit = repo.find_commit(oid)
+            .map_err(|e| Error::from(e))?;
+        let parent = repo.head()
+            .and_then(|head| head.peel_to_commit())
+            .map_err(|e| Error::from(e))?;
+
+        let mut index = repo.cherrypick_commit(&commit, &parent, 0, None)
+            .map_err(|e| Error::from(e))?;
+        if index.has_conflicts() {
+            return Err(Error::Conflict(format!(
+                "commit {} does not apply on {}",
+                oid,
+                parent.id()
+            )));
+        }
+        let tree_oid = index.write_tree_to(&repo)
+            .map_err(|e| Error::from(e))?;
+        let tree = repo.find_tree(tree_oid)
+            .map_err(|e| Error::from(e))?;
+        let message = commit.message()
+            .ok_or_else(|| Error::Corrupt(format!("the message of commit {} is not valid UTF-8", oid)))?;
+
+        let new_oid = repo.commit(Some("HEAD"), &commit.author(), &commit.committer(), message, &tree, &[&parent])
+            .map_err(|e| Error::from(e))?;
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout.force();
+        repo.checkout_head(Some(&mut checkout))
+            .map_err(|e| Error::from(e))?;
+        to_commit_hash(new_oid)
+    }
+
+    // ----------------------------
+    // Working-tree-related methods
+    // ----------------------------
+
+    /// Checkouts and cleans the current working tree.
+    /// This is same as `git checkout . && git clean -fd`.
+    fn checkout_clean(&mut self) -> Result<(), Error>{
+        unimplemented!()
+        //reset unstaged files and remove untracked files including directory
+        //TODO: check repo.statues() and statusOption
+    }
+
+    /// Checkouts to the branch.
+    fn checkout(&mut self, branch: &Branch) -> Result<(), Error>{
+        let repo = self.repo.repo.into_inner();
+        let obj = repo.revparse_single(
+            &("refs/heads/".to_owned() + branch)
+        ).map_err(|e| Error::from(e))?;
+
+        repo.checkout_tree(
+            &obj,
+            None
+        ).map_err(|e| Error::from(e));
+
+        repo.set_head(
+            &("refs/heads/".to_owned() + branch)
+        ).map_err(|e| Error::from(e))?;
+
+        Ok(())
+    }
+
+    /// Checkouts to the commit and make `HEAD` in a detached mode.
+    fn checkout_detach(&mut self, commit_hash: &CommitHash) -> Result<(), Error>{
+        let repo = self.repo.repo.into_inner();
+        let oid = Oid::from_bytes(&commit_hash.hash)
+            .map_err(|e| Error::from(e))?;
+
+        repo.set_head_detached(oid)
+            .map_err(|e| Error::from(e));
+
+        Ok(())
+        //https://stackoverflow.com/questions/55141013/how-to-get-the-behaviour-of-git-checkout-in-rust-git2
+    }
+
+    /// Creates a linked working tree at `path` with the branch checked out.
+    fn create_worktree(&mut self, branch: &Branch, path: &str) -> Result<(), Error>{
+        let repo = self.repo.repo.into_inner();
+        let reference = repo.find_branch(branch, BranchType::Local)
+            .map_err(|e| Error::from(e))?
+            .into_reference();
+
+        let mut options = git2::WorktreeAddOptions::new();
+        options.reference(Some(&reference));
+        repo.worktree(branch, std::path::Path::new(path), Some(&options))
+            .map_err(|e| Error::from(e))?;
+
+        Ok(())
+    }
+
+    /// Removes the linked working tree of the branch, including its files.
+    fn remove_worktree(&mut self, branch: &Branch) -> Result<(), Error>{
+        let repo = self.repo.repo.into_inner();
+        let worktree = repo.find_worktree(branch)
+            .map_err(|e| Error::from(e))?;
+
+        worktree.prune(Some(
+            git2::WorktreePruneOptions::new()
+                .valid(true)
+                .locked(false)
+                .working_tree(true)
+        )).map_err(|e| Error::from(e))?;
+
+        Ok(())
+    }
+
+    // ---------------
+    // Various queries
+    // ---------------
+
+    /// Returns the commit hash of the current HEAD.
+    fn get_head(&self) -> Result<CommitHash, Error>{
+        let repo = self.repo.repo.into_inner();
+        let ref_head = repo.head()
             .map_err(|e| Error::from(e))?;
         let oid = ref_head.target()
-            .ok_or(Error::Unknown("err".to_string()))?;
+            .ok_or_else(|| Error::Corrupt("HEAD is a symbolic reference".to_string()))?;
         let hash = <[u8; 20]>::try_from(oid.as_bytes())
-            .map_err(|_| Error::Unknown("abc".to_string()))?; //TODO: error message
+            .map_err(|_| invalid_object_id())?;
     
         Ok(CommitHash{ hash })
     }
 
+    /// Returns the path of the working directory of the repository.
+    fn get_working_directory_path(&self) -> Result<String, Error>{
+        let repo = self.repo.repo.into_inner();
+        let path = repo.workdir()
+            .ok_or_else(|| Error::NotFound("the repository is bare".to_string()))?
+            .to_str()
+            .ok_or_else(|| Error::Corrupt("the path is not valid UTF-8".to_string()))?
+            .to_owned();
+
+        Ok(path)
+    }
+
     /// Returns the commit hash of the initial commit.
     ///
     /// Fails if the repository is empty.
@@ -630,7 +1521,7 @@ b
         //TODO: is this right?
         
         let _head = repo.head()
-            .map_err(|_| Error::InvalidRepository("Repository is empty".to_string()))?;
+            .map_err(|_| Error::NotFound("Repository is empty".to_string()))?;
 
         //TODO: A revwalk allows traversal of the commit graph defined by including one or
         //      more leaves and excluding one or more roots.
@@ -647,15 +1538,140 @@ b
         let oids: Vec<Oid> = revwalk.by_ref()
             .collect::<Result<Vec<Oid>, git2::Error>>()
             .map_err(|e| Error::from(e))?; //TODO: is this right?
+        telemetry::record_revwalk("get_initial_commit", oids.len());
 
-        //TODO: what if oids[0] not exist?
-        let hash = <[u8; 20]>::try_from(oids[0].as_bytes()).map_err(|_| Error::Unknown("abc".to_string()))?; //TODO: error message
+        let initial_oid = oids.first()
+            .ok_or_else(|| Error::NotFound("Repository is empty".to_string()))?;
+        let hash = <[u8; 20]>::try_from(initial_oid.as_bytes()).map_err(|_| invalid_object_id())?;
         
         Ok(CommitHash{ hash }) //TODO: oid -> CommitHash
 
         //https://users.rust-lang.org/t/make-sure-git2-revwalk-is-linear/25560/3
     }
 
+    /// Returns the commit whose hash starts with the given hex prefix (at least 4 digits).
+    fn resolve_commit_prefix(&self, prefix: &str) -> Result<CommitHash, Error>{
+        if prefix.len() < 4 || prefix.len() > 40 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::InvalidArgument(format!("not a commit hash prefix: {}", prefix)));
+        }
+        let repo = self.repo.repo.into_inner();
+        // Only hex digits are given, so it can't be parsed as a reference name.
+        let object = repo.revparse_single(prefix)
+            .map_err(|e| match e.code() {
+                git2::ErrorCode::Ambiguous => Error::InvalidArgument(format!("ambiguous commit hash prefix: {}", prefix)),
+                _ => Error::from(e),
+            })?;
+        let commit = object.peel_to_commit()
+            .map_err(|e| Error::from(e))?;
+
+        to_commit_hash(commit.id())
+    }
+
+    /// Validates the object store, visiting every object and every commit reachable from the references.
+    fn check_object_store(&self) -> Result<ObjectStoreReport, Error>{
+        let repo = self.repo.repo.into_inner();
+        let odb = repo.odb()
+            .map_err(|e| Error::from(e))?;
+        let mut report = ObjectStoreReport::default();
+
+        let mut oids = Vec::new();
+        odb.foreach(|oid| {
+            oids.push(*oid);
+            true
+        }).map_err(|e| Error::from(e))?;
+        for oid in &oids {
+            // libgit2 verifies the hash of the object on read.
+            if odb.read(*oid).is_err() {
+                report.corrupt_objects.push(oid.to_string());
+            }
+        }
+
+        let mut stack = Vec::new();
+        let references = repo.references()
+            .map_err(|e| Error::from(e))?;
+        for reference in references {
+            let reference = reference.map_err(|e| Error::from(e))?;
+            // Symbolic references (e.g., `HEAD`) are checked through their targets.
+            let target = match reference.target() {
+                Some(target) => target,
+                None => continue,
+            };
+            if !odb.exists(target) {
+                report.dangling_references.push(
+                    reference.name().unwrap_or("<invalid UTF-8>").to_owned()
+                );
+                continue;
+            }
+            if let Ok(commit) = reference.peel_to_commit() {
+                stack.push(commit.id());
+            }
+        }
+
+        // Walks manually instead of `revwalk`, which stops at the first missing parent.
+        let mut visited = std::collections::HashSet::new();
+        while let Some(oid) = stack.pop() {
+            if !visited.insert(oid) {
+                continue;
+            }
+            let commit = match repo.find_commit(oid) {
+                Ok(commit) => commit,
+                // Already reported as a corrupt object.
+                Err(_) => continue,
+            };
+            if commit.parent_count() == 0 {
+                report.root_commits.push(to_commit_hash(oid)?);
+            }
+            for parent in commit.parent_ids() {
+                if odb.exists(parent) {
+                    stack.push(parent);
+                } else {
+                    report.broken_parent_links.push((to_commit_hash(oid)?, to_commit_hash(parent)?));
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Returns the `(path, size)` of the files added or modified by the given commit.
+    fn read_changed_file_sizes(
+        &self,
+        commit_hash: &CommitHash,
+    ) -> Result<Vec<(String, u64)>, Error>{
+        let repo = self.repo.repo.into_inner();
+        let oid = Oid::from_bytes(&commit_hash.hash)
+            .map_err(|e| Error::from(e))?;
+        let commit = repo.find_commit(oid)
+            .map_err(|e| Error::from(e))?;
+        let tree = commit.tree()
+            .map_err(|e| Error::from(e))?;
+        let parent_tree = if commit.parent_count() == 0 {
+            None
+        } else {
+            Some(commit.parent(0).and_then(|parent| parent.tree())
+                .map_err(|e| Error::from(e))?)
+        };
+
+        let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)
+            .map_err(|e| Error::from(e))?;
+        let mut sizes = Vec::new();
+        for delta in diff.deltas() {
+            let new_file = delta.new_file();
+            // Deleted files have a zero id.
+            if new_file.id().is_zero() {
+                continue;
+            }
+            let blob = repo.find_blob(new_file.id())
+                .map_err(|e| Error::from(e))?;
+            let path = new_file.path()
+                .and_then(|path| path.to_str())
+                .ok_or_else(|| Error::Corrupt("path is not valid UTF-8".to_string()))?;
+            sizes.push((path.to_owned(), blob.size() as u64));
+        }
+
+        Ok(sizes)
+    }
+
     /// Returns the diff of the given commit.
     fn show_commit(&self, commit_hash: &CommitHash) -> Result<String, Error>{
         unimplemented!()
@@ -668,6 +1684,46 @@ b
 
     }
 
+    /// Returns the per-line attribution of the file as of the given commit.
+    ///
+    /// `path` is relative to the root of the working tree.
+    fn blame(&self, path: &str, at_commit: &CommitHash) -> Result<Vec<BlameLine>, Error>{
+        let repo = self.repo.repo.into_inner();
+        let oid = Oid::from_bytes(&at_commit.hash)
+            .map_err(|e| Error::from(e))?;
+        let commit = repo.find_commit(oid)
+            .map_err(|e| Error::from(e))?;
+        let entry = commit.tree()
+            .map_err(|e| Error::from(e))?
+            .get_path(std::path::Path::new(path))
+            .map_err(|e| Error::from(e))?;
+        let blob = repo.find_blob(entry.id())
+            .map_err(|e| Error::from(e))?;
+        let content = str::from_utf8(blob.content())
+            .map_err(|_| Error::Corrupt(format!("{} is not valid UTF-8", path)))?;
+
+        let mut options = git2::BlameOptions::new();
+        options.newest_commit(oid);
+        let blame = repo.blame_file(std::path::Path::new(path), Some(&mut options))
+            .map_err(|e| Error::from(e))?;
+
+        content.lines().enumerate().map(|(index, line)| {
+            let hunk = blame.get_line(index + 1)
+                .ok_or_else(|| Error::Corrupt(format!("line {} of {} is not attributed", index + 1, path)))?;
+            let hash = <[u8; 20]>::try_from(hunk.final_commit_id().as_bytes())
+                .map_err(|_| invalid_object_id())?;
+            let signature = hunk.final_signature();
+
+            Ok(BlameLine {
+                line_number: index + 1,
+                content: line.to_owned(),
+                commit_hash: CommitHash{ hash },
+                author: signature.name().unwrap_or_default().to_owned(),
+                timestamp: signature.when().seconds() as Timestamp * 1000,
+            })
+        }).collect::<Result<Vec<BlameLine>, Error>>()
+    }
+
     /// Lists the ancestor commits of the given commit (The first element is the direct parent).
     ///
     /// It fails if there is a merge commit.
@@ -680,60 +1736,25 @@ b
         let repo = self.repo.repo.into_inner();
         let oid = Oid::from_bytes(&commit_hash.hash)
             .map_err(|e| Error::from(e))?;
-        let mut revwalk = repo.revwalk()?;
-
-        revwalk.push(oid)
+        let mut commit = repo.find_commit(oid)
             .map_err(|e| Error::from(e))?;
-        revwalk.set_sorting(git2::Sort::TIME | git2::Sort::TOPOLOGICAL); //TODO: should be tested
 
-        //compare max and ancestor's size
-        let oids: Vec<Oid> = revwalk.by_ref()
-            .collect::<Result<Vec<Oid>, git2::Error>>()
-            .map_err(|e| Error::from(e))?; 
-        
-        let oids = oids[1..oids.len()].to_vec();
-
-        let oids_ancestor = if let Some(num_max) = max{
-            for n in 0..num_max {
-                //TODO: Check first one should be commit_hash
-                let commit = repo.find_commit(oids[n])
-                    .map_err(|e| Error::from(e))?;
-                let num_parents = commit.parents().len();
-                
-                if num_parents > 1 {
-                    return Err(Error::InvalidRepository("There exists a merge commit".to_string()));
-                }
-                //TODO: should check current commit's parent == oids[next]
+        // The history is linear, so it follows the parents one by one,
+        // reading no further than `max`.
+        let mut ancestors = Vec::new();
+        while max.map_or(true, |max| ancestors.len() < max) {
+            if commit.parent_count() > 1 {
+                return Err(Error::Corrupt("There exists a merge commit".to_string()));
             }
-            oids[0..num_max].to_vec()
-        }else{ //if max==None
-            let mut i = 0;
-            
-            loop{
-                //TODO: Check first one should be commit_hash
-                let commit = repo.find_commit(oids[i])
-                    .map_err(|e| Error::from(e))?;
-                let num_parents = commit.parents().len();
-                
-                if num_parents > 1 {
-                    return Err(Error::InvalidRepository("There exists a merge commit".to_string()));
-                }
-                //TODO: should check current commit's parent == oids[next]
-                if num_parents == 0{
-                    break;
-                }
-                i = i + 1;
-            }
-            oids
-        };
-
-        let ancestors = oids_ancestor.iter().map(|&oid|{
-            let hash: [u8; 20] = oid.as_bytes().try_into()
-                .map_err(|_| Error::Unknown("abc".to_string()))?; 
-            Ok(CommitHash{ hash })
-        }).collect::<Result<Vec<CommitHash>, Error>>();
-
-        ancestors
+            let parent = commit.parents().next();
+            commit = match parent {
+                Some(parent) => parent,
+                None => break,
+            };
+            ancestors.push(to_commit_hash(commit.id())?);
+        }
+        telemetry::record_revwalk("list_ancestors", ancestors.len());
+        Ok(ancestors)
     }
 
     /// Lists the descendant commits of the given commit (The first element is the direct child).
@@ -766,7 +1787,7 @@ b
         let oid_merge = repo.merge_base(oid1, oid2)
             .map_err(|e| Error::from(e))?;
         let commit_hash_merge: [u8; 20] = oid_merge.as_bytes().try_into()
-            .map_err(|_| Error::Unknown("abc".to_string()))?; 
+            .map_err(|_| invalid_object_id())?;
 
         Ok(CommitHash{hash: commit_hash_merge})
     }
@@ -798,10 +1819,104 @@ b
 
     /// Fetches the remote repository. Same as `git fetch --all -j <LARGE NUMBER>`.
     fn fetch_all(&mut self) -> Result<(), Error>{
-        //1. get all of remote repository name and its branches which are used below
-        //git fetch origin/main == repo.find_remote("origin")?.fetch(&["main"], None, None)
-        //TODO: &["*"] works? or should find (remote, branch) ...
-        unimplemented!()
+        let report = self.fetch_all_with(&FetchPolicy::default())?;
+        for failure in &report.failed {
+            log::warn!("failed to fetch {}: {}", failure.remote, failure.message);
+        }
+        if report.fetched.is_empty() && !report.failed.is_empty() {
+            let message = format!(
+                "failed to fetch every remote ({})",
+                report.failed.iter().map(|f| f.remote.as_str()).collect::<Vec<_>>().join(", ")
+            );
+            // Worth retrying only if every remote may succeed later.
+            return Err(if report.failed.iter().all(|f| f.code == "network_timeout") {
+                Error::NetworkTimeout(message)
+            } else {
+                Error::Network(message)
+            });
+        }
+        Ok(())
+    }
+
+    /// Fetches the remotes concurrently, each within the deadline of the policy.
+    fn fetch_all_with(&mut self, policy: &FetchPolicy) -> Result<FetchReport, Error>{
+        use std::collections::{BTreeMap, HashMap, VecDeque};
+        use std::sync::mpsc::{self, RecvTimeoutError};
+        use std::time::Instant;
+
+        let repo = self.repo.repo.into_inner();
+        let path = repo.path().to_owned();
+        let remote_names = repo.remotes()
+            .map_err(|e| Error::from(e))?
+            .iter()
+            .flatten()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+
+        let (sender, receiver) = mpsc::channel();
+        let mut pending: VecDeque<String> = remote_names.iter().cloned().collect();
+        let mut in_flight: HashMap<String, Instant> = HashMap::new();
+        let mut outcomes: BTreeMap<String, Result<RemoteFetch, Error>> = BTreeMap::new();
+        loop {
+            while in_flight.len() < policy.concurrency.max(1) {
+                let remote_name = match pending.pop_front() {
+                    Some(x) => x,
+                    None => break,
+                };
+                let (path, sender, timeout) = (path.clone(), sender.clone(), policy.timeout);
+                in_flight.insert(remote_name.clone(), Instant::now());
+                // Each fetch has its own handle of the repository, since it is not `Sync`.
+                std::thread::spawn(move || {
+                    let result = fetch_remote(&path, &remote_name, timeout);
+                    // The receiver is gone if the fetch was abandoned and the others are done.
+                    let _ = sender.send((remote_name, result));
+                });
+            }
+            let first_deadline = match in_flight.values().min() {
+                Some(start) => *start + policy.timeout,
+                None => break,
+            };
+            match receiver.recv_timeout(first_deadline.saturating_duration_since(Instant::now())) {
+                Ok((remote_name, result)) => {
+                    if in_flight.remove(&remote_name).is_some() {
+                        outcomes.insert(remote_name, result);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let now = Instant::now();
+                    let expired = in_flight
+                        .iter()
+                        .filter(|(_, start)| **start + policy.timeout <= now)
+                        .map(|(remote_name, _)| remote_name.clone())
+                        .collect::<Vec<_>>();
+                    for remote_name in expired {
+                        in_flight.remove(&remote_name);
+                        outcomes.insert(
+                            remote_name.clone(),
+                            Err(Error::NetworkTimeout(format!(
+                                "{} didn't respond in {:?}",
+                                remote_name, policy.timeout
+                            ))),
+                        );
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => unreachable!("the sender is held"),
+            }
+        }
+
+        let mut report = FetchReport::default();
+        for remote_name in remote_names {
+            match outcomes.remove(&remote_name) {
+                Some(Ok(fetch)) => report.fetched.push(fetch),
+                Some(Err(e)) => report.failed.push(RemoteFailure {
+                    remote: remote_name,
+                    code: e.code().to_owned(),
+                    message: e.to_string(),
+                }),
+                None => (),
+            }
+        }
+        Ok(report)
     }
 
     /// Lists all the remote repositories.
@@ -814,7 +1929,7 @@ b
 
         let remote_name_list = remote_array.iter().map(|remote| {
             let remote_name = remote
-                .ok_or_else(|| Error::Unknown("unable to get remote".to_string()))?
+                .ok_or_else(|| Error::Corrupt("remote name is not valid UTF-8".to_string()))?
                 .to_string();
             
             Ok(remote_name)
@@ -826,7 +1941,7 @@ b
             ).map_err(|e| Error::from(e))?;
 
             let url = remote.url()
-                .ok_or_else(|| Error::Unknown("unable to get valid url".to_string()))?;
+                .ok_or_else(|| Error::Corrupt(format!("url of remote {} is not valid UTF-8", name)))?;
 
             Ok((name.clone(), url.to_string()))
         }).collect::<Result<Vec<(String, String)>, Error>>();
@@ -834,45 +1949,241 @@ b
         res
     }
 
-    /// Lists all the remote tracking branches.
-    ///
-    /// Returns `(remote_name, remote_url, commit_hash)`
-    fn list_remote_tracking_branches(
-        &self,
-    ) -> Result<Vec<(String, String, CommitHash)>, Error>{
-        unimplemented!()
-        //let remote_list = self.list_remotes()
-          //  .map_err(|e| Error::from(e))?;
+    /// Pushes the references to the URL, which needn't be a configured remote.
+    fn push(
+        &self,
+        remote_url: &str,
+        refspecs: &[String],
+        credentials: Option<&PushCredentials>,
+    ) -> Result<(), Error>{
+        let repo = self.repo.repo.into_inner();
+        let mut remote = repo.remote_anonymous(remote_url)
+            .map_err(|e| Error::from(e))?;
+        let mut rejected = Vec::new();
+        let result = {
+            let mut callbacks = git2::RemoteCallbacks::new();
+            if let Some(credentials) = credentials {
+                callbacks.credentials(move |_url, _username, _allowed| {
+                    git2::Cred::userpass_plaintext(&credentials.username, &credentials.password)
+                });
+            }
+            callbacks.push_update_reference(|reference, status| {
+                if let Some(status) = status {
+                    rejected.push(format!("{} ({})", reference, status));
+                }
+                Ok(())
+            });
+            let mut options = git2::PushOptions::new();
+            options.remote_callbacks(callbacks);
+            remote.push(refspecs, Some(&mut options))
+        };
+        result.map_err(|e| Error::from(e))?;
+        if !rejected.is_empty() {
+            return Err(Error::Conflict(format!(
+                "{} rejected {}",
+                remote_url,
+                rejected.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// Lists all the remote tracking branches.
+    ///
+    /// Returns `(remote_name, remote_url, commit_hash)`
+    fn list_remote_tracking_branches(
+        &self,
+    ) -> Result<Vec<(String, String, CommitHash)>, Error>{
+        unimplemented!()
+        //let remote_list = self.list_remotes()
+          //  .map_err(|e| Error::from(e))?;
+
+            //repo.branch_remote_name(refname: branch) -> remote name
+            //branch경우 branches(type::remote)로 가능
+
+            //or reference_glob(refname)으로 remote name 주고 branch들 가져오기?
+
+        //unimplemented!()
+        //TODO: remote_name - branch ??
+        //1. get (remote_name, remote_url) from list_remotes
+        //2. can get commit object from rev_single but don't know what remote contains what branches
+        //branches by type remote can get remote branches but don't know each branches' remote name
+    }
+
+    /// Gets the commit that the remote tracking branch points to.
+    fn locate_remote_branch(&self, remote_name: &str, branch: &Branch) -> Result<CommitHash, Error>{
+        let repo = self.repo.repo.into_inner();
+        let branch = repo.find_branch(
+            &format!("{}/{}", remote_name, branch),
+            BranchType::Remote
+        ).map_err(|e| Error::from(e))?;
+        let oid = branch.get().target()
+            .ok_or_else(|| Error::Corrupt("branch is a symbolic reference".to_string()))?;
+        let hash = <[u8; 20]>::try_from(oid.as_bytes())
+            .map_err(|_| invalid_object_id())?;
+
+        Ok(CommitHash{ hash })
+    }
+
+    // --------------------
+    // Pack-related methods
+    // --------------------
+
+    /// Creates a packfile of the commit and all its ancestors, with their trees and blobs.
+    fn create_pack(&self, commit_hash: &CommitHash) -> Result<Vec<u8>, Error>{
+        let repo = self.repo.repo.into_inner();
+        let oid = Oid::from_bytes(&commit_hash.hash)
+            .map_err(|e| Error::from(e))?;
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(oid)
+            .map_err(|e| Error::from(e))?;
+
+        let mut builder = repo.packbuilder()
+            .map_err(|e| Error::from(e))?;
+        builder.insert_walk(&mut revwalk)
+            .map_err(|e| Error::from(e))?;
+        let mut buf = git2::Buf::new();
+        builder.write_buf(&mut buf)
+            .map_err(|e| Error::from(e))?;
+        Ok(buf.to_vec())
+    }
+
+    /// Writes the objects of the packfile into the object database.
+    fn import_pack(&mut self, pack: &[u8]) -> Result<(), Error>{
+        let repo = self.repo.repo.into_inner();
+        let odb = repo.odb()
+            .map_err(|e| Error::from(e))?;
+        let mut writer = odb.packwriter()
+            .map_err(|e| Error::from(e))?;
+        std::io::Write::write_all(&mut writer, pack)
+            .map_err(|e| Error::Corrupt(format!("failed to write the pack: {}", e)))?;
+        writer.commit()
+            .map_err(|e| Error::from(e))?;
+        Ok(())
+    }
 
-            //repo.branch_remote_name(refname: branch) -> remote name
-            //branch경우 branches(type::remote)로 가능
+    /// Writes the packfile of the commit to the file, chunk by chunk.
+    fn write_pack(&self, commit_hash: &CommitHash, path: &str) -> Result<(u64, Hash256), Error>{
+        let repo = self.repo.repo.into_inner();
+        let oid = Oid::from_bytes(&commit_hash.hash)
+            .map_err(|e| Error::from(e))?;
+        let mut revwalk = repo.revwalk()?;
+        revwalk.push(oid)
+            .map_err(|e| Error::from(e))?;
 
-            //or reference_glob(refname)으로 remote name 주고 branch들 가져오기?
+        let mut builder = repo.packbuilder()
+            .map_err(|e| Error::from(e))?;
+        builder.insert_walk(&mut revwalk)
+            .map_err(|e| Error::from(e))?;
+        let mut file = std::fs::File::create(path)
+            .map_err(|e| Error::InvalidArgument(format!("failed to create {}: {}", path, e)))?;
+        let mut hasher = Hash256Hasher::new();
+        let mut size = 0;
+        let mut io_error = None;
+        let result = builder.foreach(|chunk| {
+            hasher.update(chunk);
+            size += chunk.len() as u64;
+            match std::io::Write::write_all(&mut file, chunk) {
+                Ok(()) => true,
+                Err(e) => {
+                    io_error = Some(e);
+                    false
+                }
+            }
+        });
+        if let Some(e) = io_error {
+            return Err(Error::Corrupt(format!("failed to write the pack: {}", e)));
+        }
+        result.map_err(|e| Error::from(e))?;
+        file.sync_all()
+            .map_err(|e| Error::Corrupt(format!("failed to write the pack: {}", e)))?;
+        Ok((size, hasher.finalize()))
+    }
 
-        //unimplemented!()
-        //TODO: remote_name - branch ??
-        //1. get (remote_name, remote_url) from list_remotes
-        //2. can get commit object from rev_single but don't know what remote contains what branches
-        //branches by type remote can get remote branches but don't know each branches' remote name
+    /// Writes the objects of the packfile in the file into the object database, chunk by chunk.
+    fn import_pack_file(&mut self, path: &str) -> Result<(), Error>{
+        let repo = self.repo.repo.into_inner();
+        let odb = repo.odb()
+            .map_err(|e| Error::from(e))?;
+        let mut file = std::fs::File::open(path)
+            .map_err(|e| Error::InvalidArgument(format!("failed to open {}: {}", path, e)))?;
+        let mut writer = odb.packwriter()
+            .map_err(|e| Error::from(e))?;
+        std::io::copy(&mut file, &mut writer)
+            .map_err(|e| Error::Corrupt(format!("failed to write the pack: {}", e)))?;
+        writer.commit()
+            .map_err(|e| Error::from(e))?;
+        Ok(())
     }
 }
 
+/// Fetches a remote with a new handle of the repository at the path,
+/// aborting the transfer once past the deadline.
+fn fetch_remote(
+    path: &std::path::Path,
+    remote_name: &str,
+    timeout: std::time::Duration,
+) -> Result<RemoteFetch, Error> {
+    let start = std::time::Instant::now();
+    let repo = Repository::open(path)
+        .map_err(|e| Error::from(e))?;
+    let mut remote = repo.find_remote(remote_name)
+        .map_err(|e| Error::from(e))?;
+    let mut callbacks = git2::RemoteCallbacks::new();
+    callbacks.transfer_progress(|_| start.elapsed() < timeout);
+    callbacks.sideband_progress(|_| start.elapsed() < timeout);
+    let mut options = git2::FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    // An empty refspec list means the configured (default) refspecs of the remote.
+    let result = remote.fetch(&[] as &[&str], Some(&mut options), None);
+    if start.elapsed() >= timeout {
+        return Err(Error::NetworkTimeout(format!(
+            "{} didn't finish in {:?}",
+            remote_name, timeout
+        )));
+    }
+    result.map_err(|e| Error::from(e))?;
+    let received_objects = remote.stats().received_objects();
+    telemetry::record_fetch(remote_name, start.elapsed(), received_objects);
+    Ok(RemoteFetch {
+        remote: remote_name.to_owned(),
+        received_objects,
+        duration: start.elapsed(),
+    })
+}
+
 pub struct RawRepositoryImpl {
     inner: tokio::sync::Mutex<Option<CurRepository>>,
 }
 
+impl RawRepositoryImpl {
+    /// Acquires the inner repository, recording the time spent waiting for the lock.
+    async fn lock_inner(
+        &self,
+        method: &'static str,
+    ) -> tokio::sync::MutexGuard<'_, Option<CurRepository>> {
+        let start = std::time::Instant::now();
+        let lock = self.inner.lock().await;
+        telemetry::record_lock_wait(method, start.elapsed());
+        lock
+    }
+}
+
 #[async_trait]
 impl RawRepository for RawRepositoryImpl {
    /// Initialize the genesis repository from the genesis working tree.
     ///
     /// Fails if there is already a repository.
+    #[tracing::instrument(level = "debug")]
     async fn init(directory: &str) -> Result<Self, Error>
     where
         Self: Sized{
             match Repository::open(directory) {
-                Ok(_repo) => Err(Error::InvalidRepository("There is an already existing repository".to_string())),
+                Ok(_repo) => Err(Error::AlreadyExists("There is an already existing repository".to_string())),
                 Err(_e) => {
-                    let repo = Repository::init(directory)
+                    let mut options = git2::RepositoryInitOptions::new();
+                    options.initial_head(INITIAL_BRANCH);
+                    let repo = Repository::init_opts(directory, &options)
                         .map_err(|e| Error::from(e))?;
                     let repo = Cell::new(repo);
                     let repo = Git2Repository{ repo };
@@ -885,6 +2196,7 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     // Loads an exisitng repository.
+    #[tracing::instrument(level = "debug")]
     async fn open(directory: &str) -> Result<Self, Error>
     where
         Self: Sized{
@@ -902,8 +2214,9 @@ impl RawRepository for RawRepositoryImpl {
     // ----------------------
 
     /// Returns the list of branches.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn list_branches(&self) -> Result<Vec<Branch>, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("list_branches").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.list_branches(), inner))
             .await
@@ -913,12 +2226,13 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Creates a branch on the commit.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn create_branch(
         &self,
         branch_name: &Branch,
         commit_hash: CommitHash,
     ) -> Result<(), Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("create_branch").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.create_branch(branch_name, commit_hash), inner))
             .await
@@ -928,8 +2242,9 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Gets the commit that the branch points to.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn locate_branch(&self, branch: &Branch) -> Result<CommitHash, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("locate_branch").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.locate_branch(branch), inner))
             .await
@@ -939,8 +2254,9 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Gets the list of branches from the commit.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn get_branches(&self, commit_hash: &CommitHash) -> Result<Vec<Branch>, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("get_branches").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.get_branches(commit_hash), inner))
             .await
@@ -950,9 +2266,10 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Moves the branch.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn move_branch(&mut self, branch: &Branch, commit_hash: &CommitHash)
         -> Result<(), Error>{
-            let mut lock = self.inner.lock().await;
+            let mut lock = self.lock_inner("move_branch").await;
             let mut inner = lock.take().expect("RawRepoImpl invariant violated");
             let (result, inner) = tokio::task::spawn_blocking(move || (inner.move_branch(branch, commit_hash), inner))
                 .await
@@ -962,8 +2279,9 @@ impl RawRepository for RawRepositoryImpl {
         }
 
     /// Deletes the branch.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn delete_branch(&mut self, branch: &Branch) -> Result<(), Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("delete_branch").await;
         let mut inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.delete_branch(branch), inner))
             .await
@@ -977,8 +2295,9 @@ impl RawRepository for RawRepositoryImpl {
     // -------------------
 
     /// Returns the list of tags.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn list_tags(&self) -> Result<Vec<Tag>, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("list_tags").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.list_tags(), inner))
             .await
@@ -988,8 +2307,9 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Creates a tag on the given commit.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn create_tag(&mut self, tag: &Tag, commit_hash: &CommitHash) -> Result<(), Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("create_tag").await;
         let mut inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.create_tag(tag, commit_hash), inner))
             .await
@@ -999,8 +2319,9 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Gets the commit that the tag points to.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn locate_tag(&self, tag: &Tag) -> Result<CommitHash, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("locate_tag").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.locate_tag(tag), inner))
             .await
@@ -1010,8 +2331,9 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Gets the tags on the given commit.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn get_tag(&self, commit_hash: &CommitHash) -> Result<Vec<Tag>, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("get_tag").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.get_tag(commit_hash), inner))
             .await
@@ -1021,8 +2343,9 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Removes the tag.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn remove_tag(&mut self, tag: &Tag) -> Result<(), Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("remove_tag").await;
         let mut inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.remove_tag(tag), inner))
             .await
@@ -1031,17 +2354,98 @@ impl RawRepository for RawRepositoryImpl {
         result
     }
 
+    // --------------------
+    // Note-related methods
+    // --------------------
+
+    /// Attaches a note to the commit under the given namespace, overwriting the existing one.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn add_note(
+        &mut self,
+        commit_hash: &CommitHash,
+        namespace: &str,
+        content: &str,
+    ) -> Result<(), Error>{
+        let mut lock = self.lock_inner("add_note").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.add_note(commit_hash, namespace, content), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Reads all the notes attached to the commit.
+    ///
+    /// Returns `(namespace, content)`.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn read_notes(&self, commit_hash: &CommitHash) -> Result<Vec<(String, String)>, Error>{
+        let mut lock = self.lock_inner("read_notes").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.read_notes(commit_hash), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    // -------------------------
+    // Reference-related methods
+    // -------------------------
+
+    /// Lists the references of which the full names start with the prefix (e.g., `refs/simperby/`).
+    ///
+    /// Returns `(full_name, commit_hash)`.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn list_references(&self, prefix: &str) -> Result<Vec<(String, CommitHash)>, Error>{
+        let prefix = prefix.to_owned();
+        let mut lock = self.lock_inner("list_references").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.list_references(&prefix), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Creates or moves the reference of the full name to the commit.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn set_reference(&mut self, name: &str, commit_hash: &CommitHash) -> Result<(), Error>{
+        let (name, commit_hash) = (name.to_owned(), *commit_hash);
+        let mut lock = self.lock_inner("set_reference").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.set_reference(&name, &commit_hash), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Deletes the reference of the full name.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn delete_reference(&mut self, name: &str) -> Result<(), Error>{
+        let name = name.to_owned();
+        let mut lock = self.lock_inner("delete_reference").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.delete_reference(&name), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
     // ----------------------
     // Commit-related methods
     // ----------------------
 
     /// Creates a commit from the currently checked out branch.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn create_commit(
         &mut self,
         commit_message: &str,
         diff: Option<&str>,
     ) -> Result<CommitHash, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("create_commit").await;
         let mut inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.create_commit(commit_message, diff), inner))
             .await
@@ -1051,9 +2455,10 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Creates a semantic commit from the currently checked out branch.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn create_semantic_commit(&mut self, commit: SemanticCommit)
         -> Result<CommitHash, Error>{
-            let mut lock = self.inner.lock().await;
+            let mut lock = self.lock_inner("create_semantic_commit").await;
             let mut inner = lock.take().expect("RawRepoImpl invariant violated");
             let (result, inner) = tokio::task::spawn_blocking(move || (inner.create_semantic_commit(commit), inner))
                 .await
@@ -1062,21 +2467,68 @@ impl RawRepository for RawRepositoryImpl {
             result
         }
 
-    /// Reads the reserved state from the current working tree.
+    /// Creates the root commit of an empty repository, on the currently checked out branch.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn create_root_semantic_commit(&mut self, commit: SemanticCommit, timestamp: Timestamp)
+        -> Result<CommitHash, Error>{
+            let mut lock = self.lock_inner("create_root_semantic_commit").await;
+            let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+            let (result, inner) = tokio::task::spawn_blocking(move || (inner.create_root_semantic_commit(commit, timestamp), inner))
+                .await
+                .unwrap();
+            lock.replace(inner);
+            result
+        }
+
+    /// Copies the tree of the commit of the repository at `source` into the index
+    /// and the working tree, without the history.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn import_tree(&mut self, source: &str, commit_hash: &CommitHash) -> Result<(), Error>{
+        let source = source.to_owned();
+        let commit_hash = *commit_hash;
+        let mut lock = self.lock_inner("import_tree").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.import_tree(&source, &commit_hash), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Reads the semantic commit, with the reserved state if the commit changed it
+    /// (or it is a root commit).
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn read_semantic_commit(&self, commit_hash: &CommitHash)
         -> Result<SemanticCommit, Error>{
-            let mut lock = self.inner.lock().await;
+            let commit_hash = *commit_hash;
+            let mut lock = self.lock_inner("read_semantic_commit").await;
             let inner = lock.take().expect("RawRepoImpl invariant violated");
-            let (result, inner) = tokio::task::spawn_blocking(move || (inner.read_semantic_commit(commit_hash), inner))
+            let (result, inner) = tokio::task::spawn_blocking(move || (inner.read_semantic_commit(&commit_hash), inner))
                 .await
                 .unwrap();
             lock.replace(inner);
             result
         }
 
+    /// Reads the given commits in a single pass, in the same order.
+    ///
+    /// Prefer this to reading the commits one by one, which takes the lock for each.
+    #[tracing::instrument(level = "debug", skip(self, commit_hashes), fields(count = commit_hashes.len()))]
+    async fn read_commits_bulk(&self, commit_hashes: &[CommitHash])
+        -> Result<Vec<CommitInfo>, Error>{
+        let mut lock = self.lock_inner("read_commits_bulk").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.read_commits_bulk(commit_hashes), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
     /// Removes orphaned commits. Same as `git gc --prune=now --aggressive`
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn run_garbage_collection(&mut self) -> Result<(), Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("run_garbage_collection").await;
         let mut inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.run_garbage_collection(), inner))
             .await
@@ -1085,14 +2537,86 @@ impl RawRepository for RawRepositoryImpl {
         result
     }
 
+    // -----------------------
+    // Staging-related methods
+    // -----------------------
+
+    /// Stages the changes of the given paths (relative to the working tree) in the index,
+    /// including the deletions. Same as `git add -A <paths>`.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn stage(&mut self, paths: &[String]) -> Result<(), Error>{
+        let paths = paths.to_vec();
+        let mut lock = self.lock_inner("stage").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.stage(&paths), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Returns the `(path, size)` of the files staged in the index, compared to `HEAD`.
+    ///
+    /// The size is `None` for a deleted file.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn read_staged_changes(&self) -> Result<Vec<(String, Option<u64>)>, Error>{
+        let mut lock = self.lock_inner("read_staged_changes").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.read_staged_changes(), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Returns the diff staged in the index, compared to `HEAD`. Same as `git diff --cached`.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn show_staged_diff(&self) -> Result<String, Error>{
+        let mut lock = self.lock_inner("show_staged_diff").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.show_staged_diff(), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Creates a commit of the index on top of the currently checked out branch.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn commit_staged(&mut self, commit_message: &str) -> Result<CommitHash, Error>{
+        let commit_message = commit_message.to_owned();
+        let mut lock = self.lock_inner("commit_staged").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.commit_staged(&commit_message), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Applies the changes of the commit on top of the currently checked out branch,
+    /// keeping its message and its author. Same as `git cherry-pick <commit>`.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn cherry_pick(&mut self, commit_hash: &CommitHash) -> Result<CommitHash, Error>{
+        let commit_hash = *commit_hash;
+        let mut lock = self.lock_inner("cherry_pick").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.cherry_pick(&commit_hash), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
     // ----------------------------
     // Working-tree-related methods
     // ----------------------------
 
     /// Checkouts and cleans the current working tree.
     /// This is same as `git checkout . && git clean -fd`.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn checkout_clean(&mut self) -> Result<(), Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("checkout_clean").await;
         let mut inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.checkout_clean(), inner))
             .await
@@ -1102,8 +2626,9 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Checkouts to the branch.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn checkout(&mut self, branch: &Branch) -> Result<(), Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("checkout").await;
         let mut inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.checkout(branch), inner))
             .await
@@ -1113,8 +2638,9 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Checkouts to the commit and make `HEAD` in a detached mode.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn checkout_detach(&mut self, commit_hash: &CommitHash) -> Result<(), Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("checkout_detach").await;
         let mut inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.checkout_detach(commit_hash), inner))
             .await
@@ -1123,13 +2649,43 @@ impl RawRepository for RawRepositoryImpl {
         result
     }
 
+    /// Creates a linked working tree at `path` with the branch checked out,
+    /// sharing the object store with this repository.
+    ///
+    /// The worktree is named after the branch. Same as `git worktree add <path> <branch>`.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn create_worktree(&mut self, branch: &Branch, path: &str) -> Result<(), Error>{
+        let mut lock = self.lock_inner("create_worktree").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.create_worktree(branch, path), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Removes the linked working tree of the branch, including its files.
+    ///
+    /// Same as `git worktree remove --force <path>`.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn remove_worktree(&mut self, branch: &Branch) -> Result<(), Error>{
+        let mut lock = self.lock_inner("remove_worktree").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.remove_worktree(branch), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
     // ---------------
     // Various queries
     // ---------------
 
     /// Returns the commit hash of the current HEAD.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn get_head(&self) -> Result<CommitHash, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("get_head").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.get_head(), inner))
             .await
@@ -1138,11 +2694,24 @@ impl RawRepository for RawRepositoryImpl {
         result
     }
 
+    /// Returns the path of the working directory of the repository.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn get_working_directory_path(&self) -> Result<String, Error>{
+        let mut lock = self.lock_inner("get_working_directory_path").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.get_working_directory_path(), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
     /// Returns the commit hash of the initial commit.
     ///
     /// Fails if the repository is empty.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn get_initial_commit(&self) -> Result<CommitHash, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("get_initial_commit").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.get_initial_commit(), inner))
             .await
@@ -1151,9 +2720,56 @@ impl RawRepository for RawRepositoryImpl {
         result
     }
 
+    /// Returns the commit whose hash starts with the given hex prefix (at least 4 digits).
+    ///
+    /// Fails with `InvalidArgument` if the prefix is malformed or matches several commits.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn resolve_commit_prefix(&self, prefix: &str) -> Result<CommitHash, Error>{
+        let prefix = prefix.to_owned();
+        let mut lock = self.lock_inner("resolve_commit_prefix").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.resolve_commit_prefix(&prefix), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Validates the object store, visiting every object and every commit reachable from the references.
+    ///
+    /// Same as `git fsck --no-dangling`. Note that it fails only if the validation itself
+    /// can't be performed; the problems found are reported in the result.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn check_object_store(&self) -> Result<ObjectStoreReport, Error>{
+        let mut lock = self.lock_inner("check_object_store").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.check_object_store(), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Returns the `(path, size)` of the files added or modified by the given commit,
+    /// compared to its parent.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn read_changed_file_sizes(
+        &self,
+        commit_hash: &CommitHash,
+    ) -> Result<Vec<(String, u64)>, Error>{
+        let mut lock = self.lock_inner("read_changed_file_sizes").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.read_changed_file_sizes(commit_hash), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
     /// Returns the diff of the given commit.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn show_commit(&self, commit_hash: &CommitHash) -> Result<String, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("show_commit").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.show_commit(commit_hash), inner))
             .await
@@ -1162,16 +2778,31 @@ impl RawRepository for RawRepositoryImpl {
         result
     }
 
+    /// Returns the per-line attribution of the file as of the given commit.
+    ///
+    /// `path` is relative to the root of the working tree.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn blame(&self, path: &str, at_commit: &CommitHash) -> Result<Vec<BlameLine>, Error>{
+        let mut lock = self.lock_inner("blame").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.blame(path, at_commit), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
     /// Lists the ancestor commits of the given commit (The first element is the direct parent).
     ///
     /// It fails if there is a merge commit.
     /// * `max`: the maximum number of entries to be returned.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn list_ancestors(
         &self,
         commit_hash: &CommitHash,
         max: Option<usize>,
     ) -> Result<Vec<CommitHash>, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("list_ancestors").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.list_ancestors(commit_hash, max), inner))
             .await
@@ -1184,12 +2815,13 @@ impl RawRepository for RawRepositoryImpl {
     ///
     /// It fails if there are diverged commits (i.e., having multiple children commit)
     /// * `max`: the maximum number of entries to be returned.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn list_descendants(
         &self,
         commit_hash: &CommitHash,
         max: Option<usize>,
     ) -> Result<Vec<CommitHash>, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("list_descendants").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.list_descendants(commit_hash, max), inner))
             .await
@@ -1199,8 +2831,9 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Returns the children commits of the given commit.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn list_children(&self, commit_hash: &CommitHash) -> Result<Vec<CommitHash>, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("list_children").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.list_children(commit_hash), inner))
             .await
@@ -1210,12 +2843,13 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Returns the merge base of the two commits.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn find_merge_base(
         &self,
         commit_hash1: &CommitHash,
         commit_hash2: &CommitHash,
     ) -> Result<CommitHash, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("find_merge_base").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.find_merge_base(commit_hash1, commit_hash2), inner))
             .await
@@ -1229,8 +2863,9 @@ impl RawRepository for RawRepositoryImpl {
     // ----------------------------
 
     /// Adds a remote repository.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn add_remote(&mut self, remote_name: &str, remote_url: &str) -> Result<(), Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("add_remote").await;
         let mut inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.add_remote(remote_name, remote_url), inner))
             .await
@@ -1240,8 +2875,9 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Removes a remote repository.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn remove_remote(&mut self, remote_name: &str) -> Result<(), Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("remove_remote").await;
         let mut inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.remove_remote(remote_name), inner))
             .await
@@ -1251,8 +2887,9 @@ impl RawRepository for RawRepositoryImpl {
     }
 
     /// Fetches the remote repository. Same as `git fetch --all -j <LARGE NUMBER>`.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn fetch_all(&mut self) -> Result<(), Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("fetch_all").await;
         let mut inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.fetch_all(), inner))
             .await
@@ -1261,11 +2898,25 @@ impl RawRepository for RawRepositoryImpl {
         result
     }
 
+    /// Fetches the remotes concurrently, each within the deadline of the policy.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn fetch_all_with(&mut self, policy: &FetchPolicy) -> Result<FetchReport, Error>{
+        let policy = policy.clone();
+        let mut lock = self.lock_inner("fetch_all_with").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.fetch_all_with(&policy), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
     /// Lists all the remote repositories.
     ///
     /// Returns `(remote_name, remote_url)`.
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn list_remotes(&self) -> Result<Vec<(String, String)>, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("list_remotes").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.list_remotes(), inner))
             .await
@@ -1274,13 +2925,36 @@ impl RawRepository for RawRepositoryImpl {
         result
     }
 
+    /// Pushes the references to the URL, which needn't be a configured remote.
+    #[tracing::instrument(level = "debug", skip(self, credentials))]
+    async fn push(
+        &self,
+        remote_url: &str,
+        refspecs: &[String],
+        credentials: Option<&PushCredentials>,
+    ) -> Result<(), Error>{
+        let remote_url = remote_url.to_owned();
+        let refspecs = refspecs.to_vec();
+        let credentials = credentials.cloned();
+        let mut lock = self.lock_inner("push").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || {
+            (inner.push(&remote_url, &refspecs, credentials.as_ref()), inner)
+        })
+        .await
+        .unwrap();
+        lock.replace(inner);
+        result
+    }
+
     /// Lists all the remote tracking branches.
     ///
     /// Returns `(remote_name, remote_url, commit_hash)`
+    #[tracing::instrument(level = "debug", skip(self))]
     async fn list_remote_tracking_branches(
         &self,
     ) -> Result<Vec<(String, String, CommitHash)>, Error>{
-        let mut lock = self.inner.lock().await;
+        let mut lock = self.lock_inner("list_remote_tracking_branches").await;
         let inner = lock.take().expect("RawRepoImpl invariant violated");
         let (result, inner) = tokio::task::spawn_blocking(move || (inner.list_remote_tracking_branches(), inner))
             .await
@@ -1288,6 +2962,77 @@ impl RawRepository for RawRepositoryImpl {
         lock.replace(inner);
         result
     }
+
+    /// Gets the commit that the remote tracking branch points to.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn locate_remote_branch(&self, remote_name: &str, branch: &Branch)
+        -> Result<CommitHash, Error>{
+        let (remote_name, branch) = (remote_name.to_owned(), branch.clone());
+        let mut lock = self.lock_inner("locate_remote_branch").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.locate_remote_branch(&remote_name, &branch), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    // --------------------
+    // Pack-related methods
+    // --------------------
+
+    /// Creates a packfile of the commit and all its ancestors, with their trees and blobs.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn create_pack(&self, commit_hash: &CommitHash) -> Result<Vec<u8>, Error>{
+        let commit_hash = *commit_hash;
+        let mut lock = self.lock_inner("create_pack").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.create_pack(&commit_hash), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Writes the objects of the packfile into the object database.
+    #[tracing::instrument(level = "debug", skip(self, pack))]
+    async fn import_pack(&mut self, pack: &[u8]) -> Result<(), Error>{
+        let pack = pack.to_vec();
+        let mut lock = self.lock_inner("import_pack").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.import_pack(&pack), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Writes the packfile of the commit to the file, chunk by chunk.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn write_pack(&self, commit_hash: &CommitHash, path: &str) -> Result<(u64, Hash256), Error>{
+        let commit_hash = *commit_hash;
+        let path = path.to_owned();
+        let mut lock = self.lock_inner("write_pack").await;
+        let inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.write_pack(&commit_hash, &path), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
+
+    /// Writes the objects of the packfile in the file into the object database.
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn import_pack_file(&mut self, path: &str) -> Result<(), Error>{
+        let path = path.to_owned();
+        let mut lock = self.lock_inner("import_pack_file").await;
+        let mut inner = lock.take().expect("RawRepoImpl invariant violated");
+        let (result, inner) = tokio::task::spawn_blocking(move || (inner.import_pack_file(&path), inner))
+            .await
+            .unwrap();
+        lock.replace(inner);
+        result
+    }
 }
 /*
 #[cfg(test)]
@@ -0,0 +1,50 @@
+//! The signature cache of the repository (see `simperby_common::signature_cache`),
+//! persisted under `.simperby/signature-cache.json` so that a restart or a repeated sync
+//! doesn't verify the history again.
+//!
+//! The file is written only by this node after its own verification, so it is trusted as is.
+//! It is loaded into the shared cache of the process (see `signature_cache::shared()`).
+use super::*;
+use simperby_common::signature_cache::{self, SignatureCache, VerifiedSignature};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+
+pub const SIGNATURE_CACHE_FILE: &str = ".simperby/signature-cache.json";
+
+pub struct PersistedSignatureCache {
+    path: PathBuf,
+    cache: Arc<SignatureCache>,
+}
+
+impl PersistedSignatureCache {
+    /// Opens the cache of the repository in the given directory, empty if absent or unreadable.
+    pub async fn open(repository_directory: &str) -> Result<Self, Error> {
+        let path = Path::new(repository_directory).join(SIGNATURE_CACHE_FILE);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let cache = signature_cache::shared();
+        match fs::read(&path).await {
+            Ok(content) => match serde_json::from_slice::<Vec<VerifiedSignature>>(&content) {
+                Ok(entries) => cache.extend(entries),
+                Err(e) => log::warn!("discarded the corrupted signature cache: {}", e),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Self { path, cache })
+    }
+
+    pub fn cache(&self) -> Arc<SignatureCache> {
+        Arc::clone(&self.cache)
+    }
+
+    /// Writes the cache to the file.
+    pub async fn save(&self) -> Result<(), Error> {
+        let temp_path = self.path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_vec(&self.cache.entries())?).await?;
+        fs::rename(&temp_path, &self.path).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+//! A cache of the reserved states materialized at the finalized blocks,
+//! so that the historical lookups don't read the whole history.
+//!
+//! A state is materialized at every block whose height is a multiple of the interval,
+//! under `.simperby/reserved-states`. Since the finalized history never changes,
+//! the entries are never invalidated.
+use super::*;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+pub const STATE_CACHE_DIRECTORY: &str = ".simperby/reserved-states";
+pub const DEFAULT_STATE_CACHE_INTERVAL: BlockHeight = 100;
+
+pub struct StateCache {
+    directory: PathBuf,
+    interval: BlockHeight,
+}
+
+impl StateCache {
+    /// Opens the cache of the repository in the given directory, creating it if absent.
+    pub async fn open(repository_directory: &str) -> Result<Self, Error> {
+        let directory = Path::new(repository_directory).join(STATE_CACHE_DIRECTORY);
+        fs::create_dir_all(&directory).await?;
+        Ok(Self {
+            directory,
+            interval: DEFAULT_STATE_CACHE_INTERVAL,
+        })
+    }
+
+    pub fn set_interval(&mut self, interval: BlockHeight) {
+        self.interval = interval.max(1);
+    }
+
+    /// Returns whether the state as of the block of the given height is to be materialized.
+    pub fn is_checkpoint(&self, height: BlockHeight) -> bool {
+        height % self.interval == 0
+    }
+
+    /// Loads the state as of the given block commit, if materialized.
+    pub async fn get(
+        &self,
+        block_commit_hash: &CommitHash,
+    ) -> Result<Option<ReservedState>, Error> {
+        match fs::read(
+            self.directory
+                .join(format!("{}.json", hex::encode(block_commit_hash.hash))),
+        )
+        .await
+        {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Materializes the state as of the given block commit.
+    pub async fn put(
+        &self,
+        block_commit_hash: &CommitHash,
+        reserved_state: &ReservedState,
+    ) -> Result<(), Error> {
+        let path = self
+            .directory
+            .join(format!("{}.json", hex::encode(block_commit_hash.hash)));
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_vec(reserved_state)?).await?;
+        fs::rename(&temp_path, &path).await?;
+        Ok(())
+    }
+}
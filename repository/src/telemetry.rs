@@ -0,0 +1,32 @@
+//! Metrics of the raw repository, reported through the `metrics` facade.
+//!
+//! This crate only records them; installing an exporter is up to the node.
+use std::time::Duration;
+
+/// A histogram of the time spent waiting for the repository lock, labeled by `method`.
+pub const LOCK_WAIT_SECONDS: &str = "simperby_repository_lock_wait_seconds";
+/// A histogram of the time spent fetching from a remote, labeled by `remote`.
+pub const FETCH_SECONDS: &str = "simperby_repository_fetch_seconds";
+/// A counter of the objects received from remotes, labeled by `remote`.
+pub const RECEIVED_OBJECTS: &str = "simperby_repository_received_objects_total";
+/// A histogram of the number of commits visited by a single revision walk, labeled by `method`.
+pub const REVWALK_LENGTH: &str = "simperby_repository_revwalk_length";
+/// A counter of the commits and proofs that failed the verification, labeled by `kind`.
+pub const VERIFICATION_FAILURES: &str = "simperby_repository_verification_failures_total";
+
+pub(crate) fn record_lock_wait(method: &'static str, wait: Duration) {
+    metrics::histogram!(LOCK_WAIT_SECONDS, wait.as_secs_f64(), "method" => method);
+}
+
+pub(crate) fn record_fetch(remote: &str, duration: Duration, received_objects: usize) {
+    metrics::histogram!(FETCH_SECONDS, duration.as_secs_f64(), "remote" => remote.to_owned());
+    metrics::counter!(RECEIVED_OBJECTS, received_objects as u64, "remote" => remote.to_owned());
+}
+
+pub(crate) fn record_revwalk(method: &'static str, length: usize) {
+    metrics::histogram!(REVWALK_LENGTH, length as f64, "method" => method);
+}
+
+pub(crate) fn record_verification_failure(kind: &'static str) {
+    metrics::increment_counter!(VERIFICATION_FAILURES, "kind" => kind);
+}
@@ -0,0 +1,319 @@
+//! A cluster of nodes in a single process, over `SimulatedNetwork` and `MemoryStorage`.
+//!
+//! There is no in-memory `RawRepository`, so each node has a Git repository
+//! in a temporary directory, which is removed when the cluster is dropped.
+//!
+//! The chain name is used as the network id (see `unique_network_id()`),
+//! so that the concurrent tests don't hear each other.
+//!
+//! The nodes share a `SimulatedClock`, which moves only by `Cluster::advance()`
+//! (and while `finalize_block()`), so the timeouts don't depend on the speed of the machine.
+use crate::network::{unique_network_id, SimulatedNetwork};
+use crate::store::MemoryStorage;
+use anyhow::{anyhow, Result};
+use simperby_common::crypto::*;
+use simperby_common::genesis::GenesisProposal;
+use simperby_common::test_util;
+use simperby_common::*;
+use simperby_network::clock::{Clock, SimulatedClock};
+use simperby_network::dms::DistributedMessageSet;
+use simperby_network::peer_discovery::PeerDiscoveryImpl;
+use simperby_network::primitives::Storage;
+use simperby_network::signer::LocalSigner;
+use simperby_network::PeerDiscovery;
+use simperby_node::node::Node;
+use simperby_node::{Config, Ports, SimperbyApi};
+use simperby_repository::raw::RawRepositoryImpl;
+use simperby_repository::CommitHash;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+
+pub type TestNode = Node<SimulatedNetwork, MemoryStorage, RawRepositoryImpl>;
+
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    /// The number of the nodes, all of which are the founding members with the same voting power.
+    pub size: usize,
+    /// The prefix of the chain name.
+    pub chain_name: String,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            size: 4,
+            chain_name: "cluster".to_owned(),
+        }
+    }
+}
+
+pub struct Cluster {
+    /// Holds the repositories and the peer storages of the nodes.
+    directory: TempDir,
+    chain_name: String,
+    members: Vec<(PublicKey, PrivateKey)>,
+    nodes: Vec<Arc<TestNode>>,
+    clock: Arc<SimulatedClock>,
+}
+
+/// How much the clock advances in each step of `Cluster::finalize_block()`.
+pub const STEP: Duration = Duration::from_millis(50);
+
+/// Finds a port that is free at the moment, for the peer discovery.
+fn free_port() -> Result<u16> {
+    Ok(std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+        .local_addr()?
+        .port())
+}
+
+impl Cluster {
+    /// Creates the nodes from a genesis approved by all of them.
+    pub async fn genesis(cluster_config: ClusterConfig) -> Result<Self> {
+        let directory = TempDir::new()?;
+        let chain_name = unique_network_id(&cluster_config.chain_name);
+        let members: Vec<_> = (0..cluster_config.size)
+            .map(|i| generate_keypair(format!("{}-{}", chain_name, i)))
+            .collect();
+        let proposal = GenesisProposal {
+            chain_name: chain_name.clone(),
+            members: members
+                .iter()
+                .enumerate()
+                .map(|(i, (public_key, _))| Member {
+                    public_key: public_key.clone(),
+                    ..test_util::member(&format!("member-{}", i))
+                })
+                .collect(),
+            ..test_util::genesis_proposal(&[])
+        };
+        let mut approvals = Vec::new();
+        for (_, private_key) in &members {
+            approvals.push(
+                simperby_node::genesis::approve(&proposal, &LocalSigner::new(private_key.clone()))
+                    .await?,
+            );
+        }
+
+        let ports = (0..members.len())
+            .map(|_| free_port())
+            .collect::<Result<Vec<_>>>()?;
+        let clock = Arc::new(SimulatedClock::new(1));
+        let mut nodes = Vec::new();
+        for (i, (public_key, private_key)) in members.iter().enumerate() {
+            let path = |name: &str| -> Result<String> {
+                let path = directory.path().join(format!("node-{}", i)).join(name);
+                path.to_str()
+                    .map(str::to_owned)
+                    .ok_or_else(|| anyhow!("non-UTF-8 path {:?}", path))
+            };
+            let config = Config {
+                public_key: public_key.clone(),
+                keystore_path: path("keystore.json")?,
+                remote_signer: None,
+                chain_name: chain_name.clone(),
+                peer_directory: path("peer")?,
+                governance_directory: path("governance")?,
+                chat_directory: path("chat")?,
+                consensus_directory: path("consensus")?,
+                repository_directory: path("repository")?,
+                vote_archive_directory: None,
+                recovery_sources: Vec::new(),
+                broadcast_interval_ms: Some(100),
+                fetch_interval_ms: None,
+                bootstrap_peers: Vec::new(),
+                nat: Default::default(),
+                peer_score: Default::default(),
+                sentry: Default::default(),
+                gossip: None,
+                dev_mode: false,
+                bandwidth_quota: Default::default(),
+                pipeline: Default::default(),
+                ports: Ports {
+                    peer_discovery: ports[i],
+                    ..Default::default()
+                },
+                restart_policy: Default::default(),
+                api: Default::default(),
+                metrics: Default::default(),
+                logging: Default::default(),
+                settlement: None,
+                notifiers: Vec::new(),
+                mirrors: Vec::new(),
+                checkpoint: None,
+                storage: Default::default(),
+            };
+            std::fs::create_dir_all(&config.repository_directory)?;
+            simperby_node::genesis::finalize::<RawRepositoryImpl>(
+                &proposal,
+                &approvals,
+                &config.repository_directory,
+                None,
+            )
+            .await?;
+            for (directory, dms_key) in [
+                (&config.governance_directory, "governance"),
+                (&config.consensus_directory, "consensus"),
+                (&config.chat_directory, simperby_chat::CHAT_DMS_KEY),
+            ] {
+                MemoryStorage::create(directory).await?;
+                DistributedMessageSet::<(), MemoryStorage>::create(
+                    MemoryStorage::open(directory).await?,
+                    0,
+                    dms_key.to_owned(),
+                )
+                .await?;
+            }
+            PeerDiscoveryImpl::create(&config.peer_directory).await?;
+            PeerDiscoveryImpl::add_bootstrap_addresses(
+                &config.peer_directory,
+                ports
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, _)| *j != i)
+                    .map(|(_, port)| SocketAddrV4::new(Ipv4Addr::LOCALHOST, *port))
+                    .collect(),
+            )
+            .await?;
+            nodes.push(Arc::new(
+                TestNode::with_signer(config, Arc::new(LocalSigner::new(private_key.clone())))?
+                    .with_time(Arc::clone(&clock) as Arc<dyn Clock>),
+            ));
+        }
+        Ok(Self {
+            directory,
+            chain_name,
+            members,
+            nodes,
+            clock,
+        })
+    }
+
+    /// The chain name, which is also the id of the simulated network.
+    pub fn chain_name(&self) -> &str {
+        &self.chain_name
+    }
+
+    /// The temporary directory that holds the nodes' data.
+    pub fn directory(&self) -> &std::path::Path {
+        self.directory.path()
+    }
+
+    pub fn members(&self) -> &[(PublicKey, PrivateKey)] {
+        &self.members
+    }
+
+    pub fn nodes(&self) -> &[Arc<TestNode>] {
+        &self.nodes
+    }
+
+    /// The clock of the nodes.
+    pub fn clock(&self) -> &Arc<SimulatedClock> {
+        &self.clock
+    }
+
+    /// Advances the clock of the nodes, letting the tasks woken by it run.
+    pub async fn advance(&self, duration: Duration) {
+        self.clock.advance(duration);
+        tokio::task::yield_now().await;
+    }
+
+    /// Runs every node in the background (see `SimperbyApi::run()`).
+    pub fn spawn_all(&self) -> Vec<tokio::task::JoinHandle<Result<()>>> {
+        self.nodes
+            .iter()
+            .map(|node| {
+                let node = Arc::clone(node);
+                tokio::spawn(async move { node.run().await })
+            })
+            .collect()
+    }
+
+    pub async fn fetch_all(&self) -> Result<()> {
+        for node in &self.nodes {
+            node.fetch().await?;
+        }
+        Ok(())
+    }
+
+    /// Proposes an agenda of the transactions by the node at `proposer`,
+    /// and has every node vote for it.
+    pub async fn approve_agenda(
+        &self,
+        proposer: usize,
+        transactions: Vec<Transaction>,
+    ) -> Result<CommitHash> {
+        let node = &self.nodes[proposer];
+        for transaction in transactions {
+            node.create_transaction(transaction).await?;
+        }
+        node.create_agenda().await?;
+        self.fetch_all().await?;
+        let (agenda, _) = node
+            .get_agendas()
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow!("the agenda is not found after creating it"))?;
+        for node in &self.nodes {
+            node.vote(agenda).await?;
+        }
+        self.fetch_all().await?;
+        Ok(agenda)
+    }
+
+    /// Proposes a block by the node at `proposer`, and drives the consensus
+    /// until every node finalizes it, or the timeout of the simulated clock.
+    pub async fn finalize_block(&self, proposer: usize, timeout: Duration) -> Result<BlockHeight> {
+        let height = self.nodes[proposer]
+            .get_last_finalized_block_header()
+            .await?
+            .height;
+        self.nodes[proposer].create_block().await?;
+        let deadline = self.clock.now() + timeout.as_millis() as Timestamp;
+        loop {
+            let mut finalized = true;
+            for node in &self.nodes {
+                node.progress_for_consensus().await?;
+                node.fetch().await?;
+                finalized &= node.get_last_finalized_block_header().await?.height > height;
+            }
+            if finalized {
+                return Ok(height + 1);
+            }
+            if self.clock.now() > deadline {
+                return Err(anyhow!(
+                    "the block at height {} is not finalized by every node in {:?}",
+                    height + 1,
+                    timeout
+                ));
+            }
+            self.advance(STEP).await;
+        }
+    }
+
+    /// Checks that every node has the same last finalized block and reserved state.
+    pub async fn assert_identical_histories(&self) -> Result<()> {
+        let header = self.nodes[0].get_last_finalized_block_header().await?;
+        let reserved_state = self.nodes[0].get_reserved_state().await?;
+        for (i, node) in self.nodes.iter().enumerate().skip(1) {
+            let other = node.get_last_finalized_block_header().await?;
+            if other != header {
+                return Err(anyhow!(
+                    "node {} has finalized {:?}, but node 0 has finalized {:?}",
+                    i,
+                    other,
+                    header
+                ));
+            }
+            if node.get_reserved_state().await? != reserved_state {
+                return Err(anyhow!(
+                    "node {} has a different reserved state at height {}",
+                    i,
+                    header.height
+                ));
+            }
+        }
+        Ok(())
+    }
+}
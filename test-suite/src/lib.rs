@@ -1 +1,13 @@
-
+//! A framework for the end-to-end tests of multiple Simperby nodes in a single process,
+//! available with the `test-util` feature so that the downstream applications can reuse it.
+//!
+//! - `network`: a gossip network simulated in memory.
+//! - `store`: an in-memory message store for the DMSes.
+//! - `cluster`: spins up the nodes over them, drives them through the genesis, agendas
+//!   and block finalizations, and checks that their finalized histories are identical.
+#[cfg(feature = "test-util")]
+pub mod cluster;
+#[cfg(feature = "test-util")]
+pub mod network;
+#[cfg(feature = "test-util")]
+pub mod store;
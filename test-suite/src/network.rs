@@ -0,0 +1,496 @@
+//! A gossip network simulated in memory (see `GossipNetwork`), with injectable faults.
+//!
+//! `GossipNetwork` has no instance, so the networks are kept in a global registry
+//! by `NetworkConfig::network_id`; each test should use its own (see `unique_network_id()`).
+//! A node joins by `serve()` and leaves by dropping the receiver, or by `disconnect()`.
+//!
+//! The faults of a network (see `Faults`) are set per test with `set_faults()`,
+//! and the partitions with `partition()` and `heal()`. They apply to the messages sent
+//! after, and the messages in flight are delivered as scheduled.
+//!
+//! Every message is scheduled to arrive at an instant, drawn from a random generator
+//! seeded by `Faults::seed`, and is delivered in the order of the instants.
+//! So the same sends in the same order give the same deliveries, which makes a run
+//! reproducible on a paused clock (`#[tokio::test(start_paused = true)]`).
+use async_trait::async_trait;
+use simperby_common::crypto::PublicKey;
+use simperby_network::primitives::GossipNetwork;
+use simperby_network::{Error, NetworkConfig, Peer, SharedKnownPeers};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// The number of the messages that a node may leave unreceived before the delivery waits.
+pub const INBOX_CAPACITY: usize = 1024;
+
+/// The distribution of the latency of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Latency {
+    None,
+    Fixed(Duration),
+    Uniform { min: Duration, max: Duration },
+    Exponential { mean: Duration },
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::None
+    }
+}
+
+impl Latency {
+    fn sample(&self, rng: &mut Rng) -> Duration {
+        match self {
+            Latency::None => Duration::ZERO,
+            Latency::Fixed(latency) => *latency,
+            Latency::Uniform { min, max } => {
+                *min + max.saturating_sub(*min).mul_f64(rng.next_f64())
+            }
+            Latency::Exponential { mean } => mean.mul_f64(-(1.0 - rng.next_f64()).ln()),
+        }
+    }
+}
+
+/// The faults of a network, which has none by default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Faults {
+    pub latency: Latency,
+    /// The probability that a message may overtake the earlier ones between the same nodes,
+    /// which are otherwise delivered in order.
+    pub reorder: f64,
+    /// The probability that a message is lost.
+    pub loss: f64,
+    /// The bytes per second that a node can send in total, if capped.
+    ///
+    /// A broadcast takes it once per recipient.
+    pub bandwidth: Option<u64>,
+    pub seed: u64,
+}
+
+/// SplitMix64, which is enough for the faults and stable across the versions of anything.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.next_f64() < probability
+    }
+}
+
+/// A message to deliver at the instant, ordered by the sequence number among the same instants.
+type Scheduled = (Instant, u64, Vec<u8>);
+
+struct Network {
+    /// The inbox of each node, with an id to tell whether it has rejoined.
+    inboxes: HashMap<PublicKey, (u64, mpsc::UnboundedSender<Scheduled>)>,
+    faults: Faults,
+    rng: Rng,
+    /// The groups of the nodes that can reach each other, if partitioned.
+    partition: Option<Vec<HashSet<PublicKey>>>,
+    /// The last arrival between each pair of the nodes, for the in-order delivery.
+    links: HashMap<(PublicKey, PublicKey), Instant>,
+    /// Until when each node is busy sending, under the bandwidth cap.
+    busy: HashMap<PublicKey, Instant>,
+    sequence: u64,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Self {
+            inboxes: HashMap::new(),
+            faults: Faults::default(),
+            rng: Rng(0),
+            partition: None,
+            links: HashMap::new(),
+            busy: HashMap::new(),
+            sequence: 0,
+        }
+    }
+}
+
+impl Network {
+    fn reachable(&self, from: &PublicKey, to: &PublicKey) -> bool {
+        self.partition.as_ref().map_or(true, |groups| {
+            groups
+                .iter()
+                .any(|group| group.contains(from) && group.contains(to))
+        })
+    }
+
+    /// Schedules the message to the known peers that are online and reachable.
+    fn send(&mut self, from: &PublicKey, known_peers: &[Peer], message: &[u8]) {
+        let now = Instant::now();
+        for peer in known_peers {
+            let to = &peer.public_key;
+            if to == from || !self.reachable(from, to) {
+                continue;
+            }
+            let inbox = match self.inboxes.get(to) {
+                Some((_, inbox)) => inbox.clone(),
+                None => continue,
+            };
+            if self.rng.chance(self.faults.loss) {
+                continue;
+            }
+            let mut arrival = now;
+            if let Some(bandwidth) = self.faults.bandwidth {
+                let busy = self.busy.entry(from.clone()).or_insert(now);
+                *busy = (*busy).max(now)
+                    + Duration::from_secs_f64(message.len() as f64 / bandwidth.max(1) as f64);
+                arrival = *busy;
+            }
+            arrival += self.faults.latency.sample(&mut self.rng);
+            if !self.rng.chance(self.faults.reorder) {
+                let last = self
+                    .links
+                    .entry((from.clone(), to.clone()))
+                    .or_insert(arrival);
+                arrival = arrival.max(*last);
+                *last = arrival;
+            }
+            self.sequence += 1;
+            // The node may have gone offline meanwhile, which is fine for a gossip.
+            let _ = inbox.send((arrival, self.sequence, message.to_vec()));
+        }
+    }
+}
+
+static NETWORKS: Mutex<Option<HashMap<String, Network>>> = Mutex::new(None);
+
+fn with_network<T>(network_id: &str, f: impl FnOnce(&mut Network) -> T) -> T {
+    let mut networks = NETWORKS.lock().expect("poisoned");
+    f(networks
+        .get_or_insert_with(HashMap::new)
+        .entry(network_id.to_owned())
+        .or_default())
+}
+
+/// Returns a network id that no other test in the process uses.
+pub fn unique_network_id(prefix: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    format!(
+        "{}-{}-{}",
+        prefix,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// Removes the node from the network, as if it went offline, dropping the messages in flight to it.
+pub fn disconnect(network_id: &str, public_key: &PublicKey) {
+    with_network(network_id, |network| network.inboxes.remove(public_key));
+}
+
+/// Returns the nodes in the network.
+pub fn online_nodes(network_id: &str) -> Vec<PublicKey> {
+    with_network(network_id, |network| {
+        network.inboxes.keys().cloned().collect()
+    })
+}
+
+/// Sets the faults of the network, restarting its random generator from `Faults::seed`.
+pub fn set_faults(network_id: &str, faults: Faults) {
+    with_network(network_id, |network| {
+        network.rng = Rng(faults.seed);
+        network.faults = faults;
+    });
+}
+
+/// Partitions the network into the groups, which can't reach each other.
+///
+/// The nodes in none of the groups are isolated.
+pub fn partition(network_id: &str, groups: Vec<Vec<PublicKey>>) {
+    with_network(network_id, |network| {
+        network.partition = Some(
+            groups
+                .into_iter()
+                .map(|group| group.into_iter().collect())
+                .collect(),
+        )
+    });
+}
+
+/// Heals the partition of the network.
+pub fn heal(network_id: &str) {
+    with_network(network_id, |network| network.partition = None);
+}
+
+async fn wait_until(instant: Option<Instant>) {
+    match instant {
+        Some(instant) => tokio::time::sleep_until(instant).await,
+        None => futures::future::pending().await,
+    }
+}
+
+/// Delivers the scheduled messages to the inbox in the order of their instants,
+/// until the node is disconnected or its receiver is dropped.
+async fn deliver(mut scheduled: mpsc::UnboundedReceiver<Scheduled>, inbox: mpsc::Sender<Vec<u8>>) {
+    enum Event {
+        Scheduled(Option<Scheduled>),
+        Due,
+        Closed,
+    }
+    let mut queue: BinaryHeap<(Reverse<(Instant, u64)>, Vec<u8>)> = BinaryHeap::new();
+    loop {
+        let next = queue.peek().map(|(Reverse((instant, _)), _)| *instant);
+        let event = tokio::select! {
+            biased;
+            _ = inbox.closed() => Event::Closed,
+            message = scheduled.recv() => Event::Scheduled(message),
+            _ = wait_until(next) => Event::Due,
+        };
+        match event {
+            Event::Scheduled(Some((instant, sequence, message))) => {
+                queue.push((Reverse((instant, sequence)), message))
+            }
+            Event::Scheduled(None) | Event::Closed => return,
+            Event::Due => {
+                let (_, message) = queue.pop().expect("a message is due");
+                if inbox.send(message).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+pub struct SimulatedNetwork;
+
+#[async_trait]
+impl GossipNetwork for SimulatedNetwork {
+    /// Sends the message to the known peers that are online and reachable, subject to the faults.
+    async fn broadcast(
+        config: &NetworkConfig,
+        known_peers: &[Peer],
+        message: Vec<u8>,
+    ) -> Result<(), Error> {
+        with_network(&config.network_id, |network| {
+            network.send(&config.public_key, known_peers, &message)
+        });
+        Ok(())
+    }
+
+    async fn serve(
+        config: NetworkConfig,
+        _peers: SharedKnownPeers,
+    ) -> Result<
+        (
+            mpsc::Receiver<Vec<u8>>,
+            tokio::task::JoinHandle<Result<(), Error>>,
+        ),
+        Error,
+    > {
+        let (sender, receiver) = mpsc::channel(INBOX_CAPACITY);
+        let (scheduler, scheduled) = mpsc::unbounded_channel();
+        let id = with_network(&config.network_id, |network| {
+            network.sequence += 1;
+            network
+                .inboxes
+                .insert(config.public_key.clone(), (network.sequence, scheduler));
+            network.sequence
+        });
+        let handle = tokio::spawn(async move {
+            deliver(scheduled, sender).await;
+            with_network(&config.network_id, |network| {
+                // Unless it has rejoined with another inbox.
+                if network
+                    .inboxes
+                    .get(&config.public_key)
+                    .map_or(false, |(current, _)| *current == id)
+                {
+                    network.inboxes.remove(&config.public_key);
+                }
+            });
+            Ok(())
+        });
+        Ok((receiver, handle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_common::crypto::generate_keypair;
+    use simperby_network::ChainId;
+
+    fn config(network_id: &str, name: &str) -> NetworkConfig {
+        let (public_key, private_key) = generate_keypair(name);
+        NetworkConfig {
+            network_id: network_id.to_owned(),
+            chain_id: ChainId::default(),
+            port: None,
+            members: Vec::new(),
+            public_key,
+            private_key,
+        }
+    }
+
+    fn peer(config: &NetworkConfig) -> Peer {
+        Peer {
+            public_key: config.public_key.clone(),
+            address: "127.0.0.1:0".parse().unwrap(),
+            ports: Default::default(),
+            message: String::new(),
+            recently_seen_timestamp: 0,
+            relay: None,
+            height: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn broadcast() {
+        let network_id = unique_network_id("broadcast");
+        let configs: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|name| config(&network_id, name))
+            .collect();
+        let peers: Vec<_> = configs.iter().map(peer).collect();
+        let mut receivers = Vec::new();
+        for config in &configs[..2] {
+            let (receiver, _) =
+                SimulatedNetwork::serve(config.clone(), SharedKnownPeers::new(peers.clone()))
+                    .await
+                    .unwrap();
+            receivers.push(receiver);
+        }
+        SimulatedNetwork::broadcast(&configs[0], &peers, b"hello".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(receivers[1].recv().await.unwrap(), b"hello".to_vec());
+        // Not to the sender itself.
+        assert!(receivers[0].try_recv().is_err());
+
+        disconnect(&network_id, &configs[1].public_key);
+        assert_eq!(
+            online_nodes(&network_id),
+            vec![configs[0].public_key.clone()]
+        );
+    }
+
+    async fn join(
+        network_id: &str,
+        names: &[&str],
+    ) -> (Vec<NetworkConfig>, Vec<Peer>, Vec<mpsc::Receiver<Vec<u8>>>) {
+        let configs: Vec<_> = names.iter().map(|name| config(network_id, name)).collect();
+        let peers: Vec<_> = configs.iter().map(peer).collect();
+        let mut receivers = Vec::new();
+        for config in &configs {
+            let (receiver, _) =
+                SimulatedNetwork::serve(config.clone(), SharedKnownPeers::new(peers.clone()))
+                    .await
+                    .unwrap();
+            receivers.push(receiver);
+        }
+        (configs, peers, receivers)
+    }
+
+    async fn try_receive(receiver: &mut mpsc::Receiver<Vec<u8>>) -> Option<Vec<u8>> {
+        tokio::time::timeout(Duration::from_secs(10), receiver.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn partition_and_heal() {
+        let network_id = unique_network_id("partition");
+        let (configs, peers, mut receivers) = join(&network_id, &["a", "b", "c"]).await;
+        partition(
+            &network_id,
+            vec![
+                vec![configs[0].public_key.clone(), configs[1].public_key.clone()],
+                vec![configs[2].public_key.clone()],
+            ],
+        );
+        SimulatedNetwork::broadcast(&configs[0], &peers, b"1".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(try_receive(&mut receivers[1]).await, Some(b"1".to_vec()));
+        assert_eq!(try_receive(&mut receivers[2]).await, None);
+
+        heal(&network_id);
+        SimulatedNetwork::broadcast(&configs[0], &peers, b"2".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(try_receive(&mut receivers[2]).await, Some(b"2".to_vec()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn latency_and_bandwidth() {
+        let network_id = unique_network_id("latency");
+        let (configs, peers, mut receivers) = join(&network_id, &["a", "b"]).await;
+        set_faults(
+            &network_id,
+            Faults {
+                latency: Latency::Fixed(Duration::from_millis(100)),
+                bandwidth: Some(10_000),
+                ..Default::default()
+            },
+        );
+        let start = Instant::now();
+        for _ in 0..2 {
+            SimulatedNetwork::broadcast(&configs[0], &peers, vec![0; 1000])
+                .await
+                .unwrap();
+        }
+        // 100ms to transmit each, after the previous one, and then 100ms on the wire.
+        for expected in [200, 300] {
+            try_receive(&mut receivers[1]).await.unwrap();
+            assert_eq!(start.elapsed(), Duration::from_millis(expected));
+        }
+    }
+
+    /// Returns the order in which the messages arrive, under the given seed.
+    async fn arrivals(seed: u64) -> Vec<u8> {
+        let network_id = unique_network_id("reorder");
+        let (configs, peers, mut receivers) = join(&network_id, &["a", "b"]).await;
+        set_faults(
+            &network_id,
+            Faults {
+                latency: Latency::Uniform {
+                    min: Duration::from_millis(10),
+                    max: Duration::from_millis(500),
+                },
+                reorder: 0.5,
+                loss: 0.2,
+                seed,
+                ..Default::default()
+            },
+        );
+        for i in 0..20 {
+            SimulatedNetwork::broadcast(&configs[0], &peers, vec![i])
+                .await
+                .unwrap();
+        }
+        let mut arrivals = Vec::new();
+        while let Some(message) = try_receive(&mut receivers[1]).await {
+            arrivals.push(message[0]);
+        }
+        arrivals
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn deterministic_faults() {
+        let first = arrivals(7).await;
+        assert_eq!(first, arrivals(7).await);
+        assert!(first.len() < 20);
+        assert!(first.windows(2).any(|x| x[0] > x[1]));
+        assert_ne!(first, arrivals(8).await);
+    }
+}
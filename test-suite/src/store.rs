@@ -0,0 +1,118 @@
+//! An in-memory storage for the DMSes (a `MessageStore` through `Storage`).
+//!
+//! `Storage` has no instance to share, so the storages are kept in a global registry
+//! by the directory, which is only a name here.
+use async_trait::async_trait;
+use simperby_network::primitives::{Storage, StorageError};
+use std::collections::{BTreeMap, HashMap};
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+
+type Files = Arc<Mutex<BTreeMap<String, String>>>;
+
+static STORAGES: Mutex<Option<HashMap<String, Files>>> = Mutex::new(None);
+
+fn not_found(name: &str) -> StorageError {
+    StorageError::new(ErrorKind::NotFound, format!("{} not found", name))
+}
+
+pub struct MemoryStorage {
+    files: Files,
+}
+
+impl MemoryStorage {
+    fn files(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, String>> {
+        self.files.lock().expect("poisoned")
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn create(storage_directory: &str) -> Result<(), StorageError> {
+        STORAGES
+            .lock()
+            .expect("poisoned")
+            .get_or_insert_with(HashMap::new)
+            .insert(storage_directory.to_owned(), Default::default());
+        Ok(())
+    }
+
+    async fn open(storage_directory: &str) -> Result<Self, StorageError> {
+        let files = STORAGES
+            .lock()
+            .expect("poisoned")
+            .get_or_insert_with(HashMap::new)
+            .get(storage_directory)
+            .cloned()
+            .ok_or_else(|| not_found(storage_directory))?;
+        Ok(Self { files })
+    }
+
+    async fn list_files(&self) -> Result<Vec<String>, StorageError> {
+        Ok(self.files().keys().cloned().collect())
+    }
+
+    async fn add_or_overwrite_file(
+        &mut self,
+        name: &str,
+        content: String,
+    ) -> Result<(), StorageError> {
+        self.files().insert(name.to_owned(), content);
+        Ok(())
+    }
+
+    async fn read_file(&self, name: &str) -> Result<String, StorageError> {
+        self.files()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| not_found(name))
+    }
+
+    async fn remove_file(&mut self, name: &str) -> Result<(), StorageError> {
+        self.files()
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| not_found(name))
+    }
+
+    async fn remove_all_files(&mut self) -> Result<(), StorageError> {
+        self.files().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simperby_network::primitives::{MessageStore, StoreOperation};
+
+    #[tokio::test]
+    async fn message_store() {
+        <MemoryStorage as MessageStore>::create("store-test")
+            .await
+            .unwrap();
+        let mut store = <MemoryStorage as MessageStore>::open("store-test")
+            .await
+            .unwrap();
+        store
+            .write_batch(vec![
+                StoreOperation::Put("a".to_owned(), "1".to_owned()),
+                StoreOperation::Put("b".to_owned(), "2".to_owned()),
+                StoreOperation::Remove("a".to_owned()),
+            ])
+            .await
+            .unwrap();
+        // Shared with another handle of the same directory.
+        let other = <MemoryStorage as MessageStore>::open("store-test")
+            .await
+            .unwrap();
+        assert_eq!(
+            other.read_all().await.unwrap(),
+            vec![("b".to_owned(), "2".to_owned())]
+        );
+        assert_eq!(other.read("a").await.unwrap(), None);
+        assert!(<MemoryStorage as MessageStore>::open("store-missing")
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,32 @@
+use simperby_node::SimperbyApi;
+use simperby_test_suite::cluster::{Cluster, ClusterConfig};
+use std::time::Duration;
+
+#[tokio::test]
+async fn genesis() {
+    let cluster = Cluster::genesis(ClusterConfig::default()).await.unwrap();
+    let mut reserved_states = Vec::new();
+    for node in cluster.nodes() {
+        reserved_states.push(node.get_reserved_state().await.unwrap());
+    }
+    assert_eq!(reserved_states.len(), 4);
+    assert!(reserved_states.iter().all(|x| *x == reserved_states[0]));
+}
+
+#[tokio::test]
+#[ignore = "the node doesn't implement the agenda, the block and the consensus yet"]
+async fn finalize_blocks() {
+    let cluster = Cluster::genesis(ClusterConfig::default()).await.unwrap();
+    for height in 1..=3 {
+        let proposer = height as usize % cluster.nodes().len();
+        cluster.approve_agenda(proposer, Vec::new()).await.unwrap();
+        assert_eq!(
+            cluster
+                .finalize_block(proposer, Duration::from_secs(30))
+                .await
+                .unwrap(),
+            height
+        );
+        cluster.assert_identical_histories().await.unwrap();
+    }
+}
@@ -1,7 +1,10 @@
 mod progress;
+mod proposer;
+pub mod trace;
+pub mod wal;
 
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 /// An index of the validator, which is for a single height. (Mapping from the actual public key to the index may differ for different heights.)
 pub type ValidatorIndex = usize;
@@ -16,8 +19,103 @@ pub type Timestamp = i64;
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ConsensusParams {
-    pub timeout_ms: u64,
+    /// The timeout of the propose step in round 0.
+    pub timeout_propose_ms: u64,
+    /// The timeout of the prevote step in round 0.
+    pub timeout_prevote_ms: u64,
+    /// The timeout of the precommit step in round 0.
+    pub timeout_precommit_ms: u64,
+    /// How much the timeouts increase for each round, in percent.
+    ///
+    /// For example, `50` makes the timeouts of round `r` be `1.5^r` times those of round 0.
+    pub timeout_increase_percent: u64,
     pub repeat_round_for_first_leader: usize,
+    pub proposer_selection: ProposerSelection,
+}
+
+/// The policy to select the proposer of each round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ProposerSelection {
+    /// The validators take turns in the leader order, regardless of the voting power.
+    RoundRobin,
+    /// The validators take turns as many times as their voting powers,
+    /// interleaved as evenly as possible. The starting point is chosen by the seed.
+    WeightedRoundRobin,
+    /// The proposer of each round is drawn from the seed with probability
+    /// proportional to the voting power.
+    StakeProportional,
+}
+
+impl ConsensusParams {
+    pub fn timeout_propose(&self, round: Round) -> u64 {
+        self.increase(self.timeout_propose_ms, round)
+    }
+
+    pub fn timeout_prevote(&self, round: Round) -> u64 {
+        self.increase(self.timeout_prevote_ms, round)
+    }
+
+    pub fn timeout_precommit(&self, round: Round) -> u64 {
+        self.increase(self.timeout_precommit_ms, round)
+    }
+
+    fn increase(&self, timeout: u64, round: Round) -> u64 {
+        let mut timeout = timeout;
+        for _ in 0..round {
+            let next = (timeout as u128 * (100 + self.timeout_increase_percent as u128) / 100)
+                .min(u64::MAX as u128) as u64;
+            // Stop once it saturates (or it doesn't increase at all).
+            if next <= timeout {
+                break;
+            }
+            timeout = next;
+        }
+        timeout
+    }
+}
+
+/// Adjusts the timeouts to the observed round-trip latencies between the validators.
+///
+/// It is kept by the lower layer across heights; `adjust()` gives the parameters
+/// for the `HeightInfo` of the next height.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub struct AdaptiveTimeout {
+    /// The exponential moving average of the observed round-trip latencies.
+    average_latency_ms: Option<u64>,
+}
+
+impl AdaptiveTimeout {
+    /// How many times of the average latency a timeout must be, at least.
+    pub const LATENCY_MULTIPLIER: u64 = 3;
+
+    /// Records an observed round-trip latency.
+    pub fn observe(&mut self, latency_ms: u64) {
+        self.average_latency_ms = Some(match self.average_latency_ms {
+            // The weight of the new observation is 1/8.
+            Some(average) => (average.saturating_mul(7)).saturating_add(latency_ms) / 8,
+            None => latency_ms,
+        });
+    }
+
+    pub fn average_latency_ms(&self) -> Option<u64> {
+        self.average_latency_ms
+    }
+
+    /// Returns the parameters with the timeouts raised to cover the observed latencies.
+    ///
+    /// The timeouts are never lowered below the given ones, which serve as the minimums.
+    pub fn adjust(&self, params: &ConsensusParams) -> ConsensusParams {
+        let minimum = self
+            .average_latency_ms
+            .unwrap_or(0)
+            .saturating_mul(Self::LATENCY_MULTIPLIER);
+        ConsensusParams {
+            timeout_propose_ms: params.timeout_propose_ms.max(minimum),
+            timeout_prevote_ms: params.timeout_prevote_ms.max(minimum),
+            timeout_precommit_ms: params.timeout_precommit_ms.max(minimum),
+            ..params.clone()
+        }
+    }
 }
 
 /// An event that (potentially) triggers a state transition of `StateMachine`.
@@ -81,6 +179,20 @@ pub enum ConsensusEvent {
 }
 
 impl ConsensusEvent {
+    /// Returns the sender and the round, if this is a message from another validator.
+    fn round_message(&self) -> Option<(ValidatorIndex, Round)> {
+        match self {
+            ConsensusEvent::BlockProposalReceived {
+                proposer, round, ..
+            } => Some((*proposer, *round)),
+            ConsensusEvent::Prevote { signer, round, .. }
+            | ConsensusEvent::Precommit { signer, round, .. }
+            | ConsensusEvent::NilPrevote { signer, round, .. }
+            | ConsensusEvent::NilPrecommit { signer, round, .. } => Some((*signer, *round)),
+            _ => None,
+        }
+    }
+
     fn time(&self) -> Timestamp {
         match self {
             ConsensusEvent::Start { time, .. } => *time,
@@ -144,11 +256,15 @@ pub struct HeightInfo {
     /// The timestamp of the beginning of the round 0.
     pub timestamp: Timestamp,
 
+    /// The seed for the proposer selection, which must be derived from the previous block hash
+    /// so that every validator makes the same choice.
+    pub seed: u64,
+
     /// The consensus parameters
     pub consensus_params: ConsensusParams,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 enum ConsensusStep {
     Initial,
     Propose,
@@ -156,15 +272,62 @@ enum ConsensusStep {
     Precommit,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 struct Votes {
-    prevotes_total: VotingPower,
-    prevotes_favor: BTreeMap<BlockIdentifier, VotingPower>,
-    // TODO: add precommits
+    /// The prevote of each validator; `None` for a nil prevote.
+    prevotes: BTreeMap<ValidatorIndex, Option<BlockIdentifier>>,
+    /// The precommit of each validator; `None` for a nil precommit.
+    precommits: BTreeMap<ValidatorIndex, Option<BlockIdentifier>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum MessageKind {
+    Proposal,
+    Prevote,
+    Precommit,
+}
+
+/// A message that this node has broadcasted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SentMessage {
+    kind: MessageKind,
+    round: Round,
+    /// `None` for a nil vote.
+    proposal: Option<BlockIdentifier>,
+}
+
+impl SentMessage {
+    fn from_response(response: &ConsensusResponse) -> Option<Self> {
+        let (kind, round, proposal) = match response {
+            ConsensusResponse::BroadcastProposal { proposal, round } => {
+                (MessageKind::Proposal, *round, Some(*proposal))
+            }
+            ConsensusResponse::BroadcastPrevote { proposal, round } => {
+                (MessageKind::Prevote, *round, Some(*proposal))
+            }
+            ConsensusResponse::BroadcastPrecommit { proposal, round } => {
+                (MessageKind::Precommit, *round, Some(*proposal))
+            }
+            ConsensusResponse::BroadcastNilPrevote { round } => {
+                (MessageKind::Prevote, *round, None)
+            }
+            ConsensusResponse::BroadcastNilPrecommit { round } => {
+                (MessageKind::Precommit, *round, None)
+            }
+            _ => return None,
+        };
+        Some(SentMessage {
+            kind,
+            round,
+            proposal,
+        })
+    }
 }
 
 /// The state of the consensus during a single height.
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// It is serializable so that it can be persisted and recovered after a crash (see `wal`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ConsensusState {
     step: ConsensusStep,
     round: Round,
@@ -173,9 +336,20 @@ pub struct ConsensusState {
     valid_value: Option<BlockIdentifier>,
     valid_round: Option<Round>,
     timeout_propose: Option<Timestamp>,
+    timeout_precommit: Option<Timestamp>,
 
+    /// The proposal of each round, received from its proposer.
+    proposals: BTreeMap<Round, BlockIdentifier>,
+    /// Whether this node is in favor of each proposal.
+    favors: BTreeMap<BlockIdentifier, bool>,
     votes: BTreeMap<Round, Votes>,
     waiting_for_proposal_creation: bool,
+    /// The validators who sent messages for each future round.
+    future_round_messages: BTreeMap<Round, BTreeSet<ValidatorIndex>>,
+    /// The messages that this node has broadcasted, which must never be contradicted.
+    sent_messages: Vec<SentMessage>,
+    /// The finalized block, after which the state machine stops.
+    decision: Option<BlockIdentifier>,
 }
 
 impl ConsensusState {
@@ -189,29 +363,87 @@ impl ConsensusState {
             valid_value: None,
             valid_round: None,
             timeout_propose: None,
+            timeout_precommit: None,
+            proposals: Default::default(),
+            favors: Default::default(),
             votes: Default::default(),
             waiting_for_proposal_creation: false,
+            future_round_messages: Default::default(),
+            sent_messages: Vec::new(),
+            decision: None,
         }
     }
 
+    /// Returns the round in progress.
+    pub fn round(&self) -> Round {
+        self.round
+    }
+
     /// Makes a progress of the state machine with the given event.
     ///
     /// It returns `None` if the state machine is not ready to process the event.
     /// It returns `Some(Vec![])` if the state machine processed the event but did not emit any response.
+    ///
+    /// It never emits a message that conflicts with the one this node has already broadcasted
+    /// (e.g., a prevote for a different block in the same round); such a message is dropped.
     pub fn progress(
         &mut self,
         height_info: &HeightInfo,
         event: ConsensusEvent,
     ) -> Option<Vec<ConsensusResponse>> {
-        progress::progress(height_info, self, event)
+        let responses = progress::progress(height_info, self, event)?;
+        Some(
+            responses
+                .into_iter()
+                .filter(|response| self.record_sent_message(response))
+                .collect(),
+        )
+    }
+
+    /// Records the message to be broadcasted, returning `false` if it conflicts with a sent one.
+    fn record_sent_message(&mut self, response: &ConsensusResponse) -> bool {
+        let message = if let Some(x) = SentMessage::from_response(response) {
+            x
+        } else {
+            return true;
+        };
+        if let Some(sent) = self
+            .sent_messages
+            .iter()
+            .find(|sent| sent.kind == message.kind && sent.round == message.round)
+        {
+            if sent.proposal != message.proposal {
+                log::warn!(
+                    "refused to emit {:?}, which conflicts with the sent one {:?}",
+                    message,
+                    sent
+                );
+                return false;
+            }
+            return true;
+        }
+        self.sent_messages.push(message);
+        true
     }
 }
 
+/// Decides the proposer of the round, following `ConsensusParams::proposer_selection`.
+///
+/// The first leader proposes for the first `repeat_round_for_first_leader` rounds.
 pub fn decide_proposer(round: usize, height_info: &HeightInfo) -> ValidatorIndex {
-    if round < height_info.consensus_params.repeat_round_for_first_leader {
+    let round = if round < height_info.consensus_params.repeat_round_for_first_leader {
         0
     } else {
-        (round - height_info.consensus_params.repeat_round_for_first_leader + 1)
-            % height_info.validators.len()
+        round - height_info.consensus_params.repeat_round_for_first_leader + 1
+    };
+    let validators = &height_info.validators;
+    match height_info.consensus_params.proposer_selection {
+        ProposerSelection::RoundRobin => round % validators.len(),
+        ProposerSelection::WeightedRoundRobin => {
+            proposer::weighted_round_robin(round, validators, height_info.seed)
+        }
+        ProposerSelection::StakeProportional => {
+            proposer::stake_proportional(round, validators, height_info.seed)
+        }
     }
 }
@@ -1,102 +1,126 @@
 use super::*;
 
-/// TODO: we have to implement the following missing logics
-/// - on-proposal
-/// - on-4f-favor-prevote-propose-step
-/// - on-4f-favor-prevote-prevote-step
-/// - on-4f-nil-prevote
-/// - on-5f-precommit
-/// - on-4f-favor-precommit
-/// - OnTimeoutPrecommit
+/// Makes a progress of the state machine, following the algorithm in `docs/vetomint-spec.pdf`
+/// where the total voting power is regarded as `6f + 1`.
+///
+/// The event is recorded in the state first, and then the rules of the algorithm
+/// are applied until none of their conditions newly holds.
 pub(super) fn progress(
     height_info: &HeightInfo,
     state: &mut ConsensusState,
     event: ConsensusEvent,
 ) -> Option<Vec<ConsensusResponse>> {
-    let result = if state.waiting_for_proposal_creation {
-        if let ConsensusEvent::BlockProposalCreated {
+    if state.decision.is_some() {
+        return Some(Vec::new());
+    }
+    let time = event.time();
+    // A node that has fallen behind must catch up even while waiting for its own proposal.
+    let mut responses = skip_to_future_round(height_info, state, &event);
+    match event {
+        ConsensusEvent::Start { time } => {
+            if state.step != ConsensusStep::Initial {
+                return None;
+            }
+            responses.extend(start_round(height_info, state, 0, time));
+        }
+        ConsensusEvent::BlockProposalCreated {
             proposal, round, ..
-        } = event
-        {
-            if state.round != round {
+        } => {
+            if !state.waiting_for_proposal_creation || state.round != round {
                 return None;
             }
             state.waiting_for_proposal_creation = false;
-            return Some(vec![ConsensusResponse::BroadcastProposal {
-                proposal,
-                round: state.round,
-            }]);
-        } else {
-            // Nothing to do; this state is waiting for a `BlockProposalCreated`.
-            return None;
+            state.proposals.insert(round, proposal);
+            state.favors.insert(proposal, true);
+            responses.push(ConsensusResponse::BroadcastProposal { proposal, round });
         }
-    } else {
-        match event {
-            ConsensusEvent::Start { time } => match start_round(height_info, state, 0, time) {
-                StartRoundResponse::Normal(r) => r,
-                StartRoundResponse::Pending { .. } => {
-                    state.waiting_for_proposal_creation = true;
-                    Vec::new()
-                }
-            },
-            ConsensusEvent::BlockProposalCreated { .. } => return None,
-            // Time-trigger events are handled later
-            ConsensusEvent::Timer { .. } => Vec::new(),
-            ConsensusEvent::Prevote {
-                proposal,
-                signer,
-                round,
-                ..
-            } => {
-                let total_voting_power = height_info.validators.iter().sum::<VotingPower>();
-                if round != state.round {
-                    return None;
+        ConsensusEvent::BlockProposalReceived {
+            proposal,
+            proposer,
+            round,
+            ..
+        } => {
+            if proposer != decide_proposer(round, height_info) {
+                return Some(responses);
+            }
+            match state.proposals.get(&round) {
+                Some(previous) if *previous != proposal => {
+                    responses.push(ConsensusResponse::ViolationReport {
+                        violator: proposer,
+                        description: format!(
+                            "proposed both {} and {} in round {}",
+                            previous, proposal, round
+                        ),
+                    })
                 }
-                let voting_power = height_info.validators[signer as usize];
-                state.votes.insert(round, {
-                    let mut votes = state
-                        .votes
-                        .get(&round)
-                        .unwrap_or(&Default::default())
-                        .clone();
-                    votes.prevotes_total += voting_power;
-                    votes.prevotes_favor.insert(
-                        proposal,
-                        votes.prevotes_favor.get(&proposal).unwrap_or(&0) + voting_power,
-                    );
-                    votes
-                });
-                if state.votes[&round].prevotes_total * 6 > total_voting_power * 5
-                    && state.step == ConsensusStep::Prevote
-                {
-                    on_5f_prevote(height_info, state, round)
-                } else {
-                    Vec::new()
+                Some(_) => (),
+                None => {
+                    state.proposals.insert(round, proposal);
                 }
             }
-            _ => unimplemented!(),
         }
-    };
+        ConsensusEvent::ProposalFavor {
+            proposal, favor, ..
+        } => {
+            state.favors.insert(proposal, favor);
+        }
+        ConsensusEvent::Prevote {
+            proposal,
+            signer,
+            round,
+            ..
+        } => responses.extend(record_vote(
+            height_info,
+            state,
+            MessageKind::Prevote,
+            signer,
+            round,
+            Some(proposal),
+        )),
+        ConsensusEvent::NilPrevote { signer, round, .. } => responses.extend(record_vote(
+            height_info,
+            state,
+            MessageKind::Prevote,
+            signer,
+            round,
+            None,
+        )),
+        ConsensusEvent::Precommit {
+            proposal,
+            signer,
+            round,
+            ..
+        } => responses.extend(record_vote(
+            height_info,
+            state,
+            MessageKind::Precommit,
+            signer,
+            round,
+            Some(proposal),
+        )),
+        ConsensusEvent::NilPrecommit { signer, round, .. } => responses.extend(record_vote(
+            height_info,
+            state,
+            MessageKind::Precommit,
+            signer,
+            round,
+            None,
+        )),
+        // Time-trigger events are handled by the rules.
+        ConsensusEvent::Timer { .. } => (),
+    }
 
-    if !result.is_empty() {
-        Some(result)
-    // Handle timeout
-    } else {
-        let time = event.time();
-        let mut responses = Vec::new();
-        if let Some(timeout_propose) = state.timeout_propose {
-            if time >= timeout_propose {
-                responses.append(&mut on_timeout_propose(height_info, state, state.round));
+    if state.step != ConsensusStep::Initial {
+        // Every rule that emits a response also changes the state, so this terminates.
+        loop {
+            let before = state.clone();
+            responses.extend(apply_rules(height_info, state, time));
+            if *state == before {
+                break;
             }
         }
-        Some(responses)
     }
-}
-
-enum StartRoundResponse {
-    Normal(Vec<ConsensusResponse>),
-    /// Emits a `CreateProposal`.
-    Pending,
+    Some(responses)
 }
 
 fn start_round(
@@ -104,53 +128,308 @@ fn start_round(
     state: &mut ConsensusState,
     round: usize,
     time: Timestamp,
-) -> StartRoundResponse {
+) -> Vec<ConsensusResponse> {
     state.round = round;
     state.step = ConsensusStep::Propose;
+    state.timeout_precommit = None;
+    state.waiting_for_proposal_creation = false;
     let proposer = decide_proposer(round, height_info);
     if proposer == height_info.this_node_index {
+        state.timeout_propose = None;
         if let Some(valid_value) = state.valid_value {
-            StartRoundResponse::Normal(vec![ConsensusResponse::BroadcastProposal {
+            state.proposals.entry(round).or_insert(valid_value);
+            vec![ConsensusResponse::BroadcastProposal {
                 proposal: valid_value,
                 round,
-            }])
+            }]
         } else {
-            StartRoundResponse::Pending
+            state.waiting_for_proposal_creation = true;
+            vec![ConsensusResponse::CreateProposal { round }]
         }
     } else {
-        state.timeout_propose = Some(time + height_info.consensus_params.timeout_ms as i64);
-        StartRoundResponse::Normal(Vec::new())
+        state.timeout_propose =
+            Some(time + height_info.consensus_params.timeout_propose(round) as i64);
+        Vec::new()
     }
 }
 
-fn on_5f_prevote(
+/// Handles a message of a future round.
+///
+/// Once the messages of a future round come from validators of more than 1/3 of
+/// the voting power (so at least one of them is honest), this node has fallen behind;
+/// it skips directly to that round instead of going through the rounds in between.
+fn skip_to_future_round(
     height_info: &HeightInfo,
     state: &mut ConsensusState,
-    round: Round,
+    event: &ConsensusEvent,
 ) -> Vec<ConsensusResponse> {
+    let (signer, round) = match event.round_message() {
+        Some(x) => x,
+        None => return Vec::new(),
+    };
+    if round <= state.round || state.step == ConsensusStep::Initial {
+        return Vec::new();
+    }
+    let signers = state.future_round_messages.entry(round).or_default();
+    signers.insert(signer);
+    let power = signers
+        .iter()
+        .map(|signer| height_info.validators[*signer])
+        .sum::<VotingPower>();
     let total_voting_power = height_info.validators.iter().sum::<VotingPower>();
+    if power * 3 <= total_voting_power {
+        return Vec::new();
+    }
+    state.future_round_messages = state.future_round_messages.split_off(&(round + 1));
+    start_round(height_info, state, round, event.time())
+}
+
+/// Records a vote of any round, reporting the signer if it conflicts with the one received.
+fn record_vote(
+    height_info: &HeightInfo,
+    state: &mut ConsensusState,
+    kind: MessageKind,
+    signer: ValidatorIndex,
+    round: Round,
+    proposal: Option<BlockIdentifier>,
+) -> Vec<ConsensusResponse> {
+    if signer >= height_info.validators.len() {
+        return Vec::new();
+    }
+    let votes = state.votes.entry(round).or_default();
+    let votes = if kind == MessageKind::Prevote {
+        &mut votes.prevotes
+    } else {
+        &mut votes.precommits
+    };
+    match votes.get(&signer) {
+        Some(previous) if *previous != proposal => vec![ConsensusResponse::ViolationReport {
+            violator: signer,
+            description: format!(
+                "sent {:?}s for both {:?} and {:?} in round {}",
+                kind, previous, proposal, round
+            ),
+        }],
+        Some(_) => Vec::new(),
+        None => {
+            votes.insert(signer, proposal);
+            Vec::new()
+        }
+    }
+}
+
+/// Sums the voting power of the votes that satisfy the condition.
+fn voting_power(
+    height_info: &HeightInfo,
+    votes: &BTreeMap<ValidatorIndex, Option<BlockIdentifier>>,
+    condition: impl Fn(Option<BlockIdentifier>) -> bool,
+) -> VotingPower {
+    votes
+        .iter()
+        .filter(|(_, vote)| condition(**vote))
+        .map(|(signer, _)| height_info.validators[*signer])
+        .sum()
+}
+
+/// Whether the voting power is at least `4f + 1`.
+fn is_4f_plus_1(height_info: &HeightInfo, power: VotingPower) -> bool {
+    power * 3 > height_info.validators.iter().sum::<VotingPower>() * 2
+}
+
+/// Whether the voting power is at least `5f + 1`.
+fn is_5f_plus_1(height_info: &HeightInfo, power: VotingPower) -> bool {
+    power * 6 > height_info.validators.iter().sum::<VotingPower>() * 5
+}
+
+fn votes(state: &ConsensusState, round: Round) -> Votes {
+    state.votes.get(&round).cloned().unwrap_or_default()
+}
+
+fn apply_rules(
+    height_info: &HeightInfo,
+    state: &mut ConsensusState,
+    time: Timestamp,
+) -> Vec<ConsensusResponse> {
+    if state.decision.is_some() {
+        return Vec::new();
+    }
+    let decision = on_4f_favor_precommit(height_info, state);
+    if !decision.is_empty() {
+        return decision;
+    }
+    if matches!(state.timeout_precommit, Some(timeout) if time >= timeout) {
+        return on_timeout_precommit(height_info, state, time);
+    }
+    let mut responses = Vec::new();
+    if matches!(state.timeout_propose, Some(timeout) if time >= timeout) {
+        responses.extend(on_timeout_propose(height_info, state));
+    }
+    responses.extend(on_proposal(height_info, state));
+    responses.extend(on_4f_favor_prevote(height_info, state));
+    responses.extend(on_4f_nil_prevote(height_info, state));
+    responses.extend(on_5f_prevote(height_info, state));
+    on_5f_precommit(height_info, state, time);
+    responses
+}
+
+/// Covers both on-proposal and on-4f-favor-prevote-propose-step.
+///
+/// The proposal carries no valid round, so the latest round in which this node saw `4f + 1`
+/// prevotes for the proposal is used instead; this node still never prevotes for a block
+/// other than the locked one unless it got such prevotes after locking.
+fn on_proposal(height_info: &HeightInfo, state: &mut ConsensusState) -> Vec<ConsensusResponse> {
+    let round = state.round;
+    let proposal = match state.proposals.get(&round) {
+        Some(proposal) if state.step == ConsensusStep::Propose => *proposal,
+        _ => return Vec::new(),
+    };
+    let locked = state.locked_value == Some(proposal);
+    let favor = match state.favors.get(&proposal) {
+        Some(favor) => *favor,
+        None if locked => false,
+        // Waits for the `ProposalFavor`.
+        None => return Vec::new(),
+    };
+    let valid_round = (0..round).rev().find(|round| {
+        is_4f_plus_1(
+            height_info,
+            voting_power(height_info, &votes(state, *round).prevotes, |vote| {
+                vote == Some(proposal)
+            }),
+        )
+    });
+    let prevote = locked
+        || favor
+            && match (state.locked_round, valid_round) {
+                (None, _) => true,
+                (Some(locked_round), Some(valid_round)) => locked_round < valid_round,
+                (Some(_), None) => false,
+            };
+    state.step = ConsensusStep::Prevote;
+    state.timeout_propose = None;
+    if prevote {
+        vec![ConsensusResponse::BroadcastPrevote { proposal, round }]
+    } else {
+        vec![ConsensusResponse::BroadcastNilPrevote { round }]
+    }
+}
+
+/// on-4f-favor-prevote-prevote-step, which also updates the valid value in the precommit step.
+fn on_4f_favor_prevote(
+    height_info: &HeightInfo,
+    state: &mut ConsensusState,
+) -> Vec<ConsensusResponse> {
+    let round = state.round;
+    let proposal = match state.proposals.get(&round) {
+        Some(proposal)
+            if state.step != ConsensusStep::Propose && state.valid_round != Some(round) =>
+        {
+            *proposal
+        }
+        _ => return Vec::new(),
+    };
+    let power = voting_power(height_info, &votes(state, round).prevotes, |vote| {
+        vote == Some(proposal)
+    });
+    if !is_4f_plus_1(height_info, power) {
+        return Vec::new();
+    }
+    state.valid_value = Some(proposal);
+    state.valid_round = Some(round);
+    if state.step == ConsensusStep::Prevote {
+        state.locked_value = Some(proposal);
+        state.locked_round = Some(round);
+        state.step = ConsensusStep::Precommit;
+        vec![ConsensusResponse::BroadcastPrecommit { proposal, round }]
+    } else {
+        Vec::new()
+    }
+}
+
+fn on_4f_nil_prevote(
+    height_info: &HeightInfo,
+    state: &mut ConsensusState,
+) -> Vec<ConsensusResponse> {
+    let round = state.round;
+    let power = voting_power(height_info, &votes(state, round).prevotes, |vote| {
+        vote.is_none()
+    });
+    if state.step == ConsensusStep::Prevote && is_4f_plus_1(height_info, power) {
+        state.step = ConsensusStep::Precommit;
+        vec![ConsensusResponse::BroadcastNilPrecommit { round }]
+    } else {
+        Vec::new()
+    }
+}
+
+fn on_5f_prevote(height_info: &HeightInfo, state: &mut ConsensusState) -> Vec<ConsensusResponse> {
+    let round = state.round;
+    let prevotes = votes(state, round).prevotes;
+    if state.step != ConsensusStep::Prevote
+        || !is_5f_plus_1(height_info, voting_power(height_info, &prevotes, |_| true))
+    {
+        return Vec::new();
+    }
     state.step = ConsensusStep::Precommit;
-    for (proposal, prevotes_favor) in &state.votes[&round].prevotes_favor {
-        if prevotes_favor * 3 > total_voting_power * 2 {
+    for proposal in prevotes.values().flatten() {
+        let power = voting_power(height_info, &prevotes, |vote| vote == Some(*proposal));
+        if is_4f_plus_1(height_info, power) {
             return vec![ConsensusResponse::BroadcastPrecommit {
                 proposal: *proposal,
-                round: state.round,
+                round,
             }];
         }
     }
-    vec![ConsensusResponse::BroadcastNilPrecommit { round: state.round }]
+    vec![ConsensusResponse::BroadcastNilPrecommit { round }]
+}
+
+fn on_5f_precommit(height_info: &HeightInfo, state: &mut ConsensusState, time: Timestamp) {
+    let round = state.round;
+    let power = voting_power(height_info, &votes(state, round).precommits, |_| true);
+    if state.timeout_precommit.is_none() && is_5f_plus_1(height_info, power) {
+        state.timeout_precommit =
+            Some(time + height_info.consensus_params.timeout_precommit(round) as i64);
+    }
+}
+
+/// Finalizes a proposal of any round that got `4f + 1` precommits.
+fn on_4f_favor_precommit(
+    height_info: &HeightInfo,
+    state: &mut ConsensusState,
+) -> Vec<ConsensusResponse> {
+    let decision = state.proposals.iter().find(|(round, proposal)| {
+        let power = voting_power(height_info, &votes(state, **round).precommits, |vote| {
+            vote == Some(**proposal)
+        });
+        is_4f_plus_1(height_info, power)
+    });
+    if let Some((_, proposal)) = decision {
+        let proposal = *proposal;
+        state.decision = Some(proposal);
+        vec![ConsensusResponse::FinalizeBlock { proposal }]
+    } else {
+        Vec::new()
+    }
 }
 
 fn on_timeout_propose(
     _height_info: &HeightInfo,
     state: &mut ConsensusState,
-    round: usize,
 ) -> Vec<ConsensusResponse> {
-    if state.round == round && state.step == ConsensusStep::Propose {
+    state.timeout_propose = None;
+    if state.step == ConsensusStep::Propose {
         state.step = ConsensusStep::Prevote;
-        state.timeout_propose = None;
-        vec![ConsensusResponse::BroadcastNilPrevote { round }]
+        vec![ConsensusResponse::BroadcastNilPrevote { round: state.round }]
     } else {
         Vec::new()
     }
 }
+
+fn on_timeout_precommit(
+    height_info: &HeightInfo,
+    state: &mut ConsensusState,
+    time: Timestamp,
+) -> Vec<ConsensusResponse> {
+    state.timeout_precommit = None;
+    start_round(height_info, state, state.round + 1, time)
+}
@@ -0,0 +1,60 @@
+use super::*;
+
+/// The smooth weighted round-robin: in each turn, every validator gains its voting power
+/// as its credit, and the one with the most credit proposes, paying the total voting power.
+///
+/// Over `total voting power` consecutive rounds, each validator proposes exactly as many times
+/// as its voting power. Ties are broken by the leader order rotated by the seed.
+pub(crate) fn weighted_round_robin(
+    round: Round,
+    validators: &[VotingPower],
+    seed: u64,
+) -> ValidatorIndex {
+    let n = validators.len();
+    let total = validators.iter().map(|v| *v as i128).sum::<i128>();
+    let offset = (seed % n as u64) as usize;
+    let mut credits = vec![0i128; n];
+    let mut selected = offset;
+    for _ in 0..=round {
+        for (credit, power) in credits.iter_mut().zip(validators) {
+            *credit += *power as i128;
+        }
+        selected = offset;
+        for i in (0..n).map(|i| (i + offset) % n) {
+            if credits[i] > credits[selected] {
+                selected = i;
+            }
+        }
+        credits[selected] -= total;
+    }
+    selected
+}
+
+/// Draws the proposer with probability proportional to the voting power,
+/// using a pseudo-random number generated from the seed and the round.
+pub(crate) fn stake_proportional(
+    round: Round,
+    validators: &[VotingPower],
+    seed: u64,
+) -> ValidatorIndex {
+    let total = validators.iter().map(|v| *v as u128).sum::<u128>();
+    if total == 0 {
+        return 0;
+    }
+    let mut target = splitmix64(seed ^ splitmix64(round as u64)) as u128 % total;
+    for (index, power) in validators.iter().enumerate() {
+        if target < *power as u128 {
+            return index;
+        }
+        target -= *power as u128;
+    }
+    unreachable!("target must be less than the total voting power")
+}
+
+/// The SplitMix64 mixing function, which is enough for a deterministic and well-spread choice.
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
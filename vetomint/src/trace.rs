@@ -0,0 +1,123 @@
+//! A structured trace of the state machine, for observability.
+//!
+//! The trace is derived from each progress by comparing the state before and after it,
+//! so it never affects the consensus itself.
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Step {
+    Propose,
+    Prevote,
+    Precommit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TraceEvent {
+    RoundStarted {
+        round: Round,
+    },
+    ProposalReceived {
+        proposal: BlockIdentifier,
+        proposer: ValidatorIndex,
+        round: Round,
+    },
+    /// This node broadcasted a proposal or a vote; `proposal` is `None` for a nil vote.
+    Broadcasted {
+        step: Step,
+        proposal: Option<BlockIdentifier>,
+        round: Round,
+    },
+    /// The precommits for a proposal reached the quorum.
+    PrecommitQuorum {
+        proposal: BlockIdentifier,
+        round: Round,
+    },
+    HeightFinalized {
+        proposal: BlockIdentifier,
+    },
+    TimeoutFired {
+        step: Step,
+        round: Round,
+    },
+}
+
+impl ConsensusState {
+    /// Makes a progress like `progress()`, also returning the trace of it.
+    pub fn progress_traced(
+        &mut self,
+        height_info: &HeightInfo,
+        event: ConsensusEvent,
+    ) -> (Option<Vec<ConsensusResponse>>, Vec<TraceEvent>) {
+        let mut trace = Vec::new();
+        let (round, step, timeout_propose, timeout_precommit) = (
+            self.round,
+            self.step.clone(),
+            self.timeout_propose,
+            self.timeout_precommit,
+        );
+        if let ConsensusEvent::BlockProposalReceived {
+            proposal,
+            proposer,
+            round,
+            ..
+        } = &event
+        {
+            trace.push(TraceEvent::ProposalReceived {
+                proposal: *proposal,
+                proposer: *proposer,
+                round: *round,
+            });
+        }
+        let time = event.time();
+        let responses = self.progress(height_info, event);
+
+        if (step == ConsensusStep::Initial && self.step != ConsensusStep::Initial)
+            || self.round != round
+        {
+            trace.push(TraceEvent::RoundStarted { round: self.round });
+        }
+        if let Some(timeout) = timeout_propose {
+            if time >= timeout && self.timeout_propose.is_none() {
+                trace.push(TraceEvent::TimeoutFired {
+                    step: Step::Propose,
+                    round,
+                });
+            }
+        }
+        if let Some(timeout) = timeout_precommit {
+            if time >= timeout && self.timeout_precommit.is_none() {
+                trace.push(TraceEvent::TimeoutFired {
+                    step: Step::Precommit,
+                    round,
+                });
+            }
+        }
+        for response in responses.iter().flatten() {
+            match response {
+                ConsensusResponse::FinalizeBlock { proposal } => {
+                    trace.push(TraceEvent::PrecommitQuorum {
+                        proposal: *proposal,
+                        round: self.round,
+                    });
+                    trace.push(TraceEvent::HeightFinalized {
+                        proposal: *proposal,
+                    });
+                }
+                response => {
+                    if let Some(sent) = SentMessage::from_response(response) {
+                        trace.push(TraceEvent::Broadcasted {
+                            step: match sent.kind {
+                                MessageKind::Proposal => Step::Propose,
+                                MessageKind::Prevote => Step::Prevote,
+                                MessageKind::Precommit => Step::Precommit,
+                            },
+                            proposal: sent.proposal,
+                            round: sent.round,
+                        });
+                    }
+                }
+            }
+        }
+        (responses, trace)
+    }
+}
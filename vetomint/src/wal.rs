@@ -0,0 +1,89 @@
+//! A write-ahead log of the consensus state.
+//!
+//! A validator that crashed in the middle of a height must not forget what it has voted for;
+//! otherwise it could double-sign after the restart.
+//! The state is persisted **before** the responses of each progress are handled
+//! (i.e., broadcasted), and it is reloaded on startup.
+use super::*;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub type Error = anyhow::Error;
+
+pub const WAL_FILE_NAME: &str = "vetomint-wal.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Record {
+    height: u64,
+    state: ConsensusState,
+}
+
+/// The write-ahead log stored in a single file, which is atomically replaced on every write.
+pub struct WriteAheadLog {
+    path: PathBuf,
+}
+
+impl WriteAheadLog {
+    /// Opens the log in the given directory (e.g., the consensus directory of the node).
+    pub fn open(directory: impl AsRef<Path>) -> Result<Self, Error> {
+        fs::create_dir_all(directory.as_ref())?;
+        Ok(Self {
+            path: directory.as_ref().join(WAL_FILE_NAME),
+        })
+    }
+
+    /// Persists the state of the given height.
+    pub fn save(&self, height: u64, state: &ConsensusState) -> Result<(), Error> {
+        let record = Record {
+            height,
+            state: state.clone(),
+        };
+        let temp_path = self.path.with_extension("json.tmp");
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(serde_json::to_string(&record)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Loads the last persisted state, with its height.
+    pub fn load(&self) -> Result<Option<(u64, ConsensusState)>, Error> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let record: Record = serde_json::from_str(&fs::read_to_string(&self.path)?)?;
+        Ok(Some((record.height, record.state)))
+    }
+
+    /// Recovers the state of the given height, or prepares a new one if there is no state
+    /// persisted for the height.
+    pub fn recover(&self, height: u64, height_info: HeightInfo) -> Result<ConsensusState, Error> {
+        match self.load()? {
+            Some((persisted_height, state)) if persisted_height == height => {
+                log::info!("recovered the consensus state of height {}", height);
+                Ok(state)
+            }
+            Some((persisted_height, _)) if persisted_height > height => Err(anyhow::anyhow!(
+                "the persisted consensus state is of a higher height: {} > {}",
+                persisted_height,
+                height
+            )),
+            _ => Ok(ConsensusState::new(height_info)),
+        }
+    }
+
+    /// Makes a progress of the state machine and persists the resulting state,
+    /// before returning the responses to be handled.
+    pub fn progress(
+        &self,
+        height: u64,
+        state: &mut ConsensusState,
+        height_info: &HeightInfo,
+        event: ConsensusEvent,
+    ) -> Result<Option<Vec<ConsensusResponse>>, Error> {
+        let responses = state.progress(height_info, event);
+        self.save(height, state)?;
+        Ok(responses)
+    }
+}
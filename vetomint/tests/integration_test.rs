@@ -7,9 +7,14 @@ fn success_trivial_1() {
         validators: vec![1, 1, 1, 1, 1, 1, 1],
         this_node_index: 6,
         timestamp: 0,
+        seed: 0,
         consensus_params: ConsensusParams {
-            timeout_ms: 1000,
+            timeout_propose_ms: 1000,
+            timeout_prevote_ms: 1000,
+            timeout_precommit_ms: 1000,
+            timeout_increase_percent: 0,
             repeat_round_for_first_leader: 1,
+            proposer_selection: ProposerSelection::RoundRobin,
         },
     };
     let mut state = ConsensusState::new(height_info.clone());
@@ -86,3 +91,167 @@ fn success_trivial_1() {
         vec![ConsensusResponse::FinalizeBlock { proposal: 0 }]
     );
 }
+
+#[test]
+fn write_ahead_log() {
+    let height_info = HeightInfo {
+        validators: vec![1, 1, 1, 1],
+        this_node_index: 3,
+        timestamp: 0,
+        seed: 0,
+        consensus_params: ConsensusParams {
+            timeout_propose_ms: 1000,
+            timeout_prevote_ms: 1000,
+            timeout_precommit_ms: 1000,
+            timeout_increase_percent: 0,
+            repeat_round_for_first_leader: 1,
+            proposer_selection: ProposerSelection::RoundRobin,
+        },
+    };
+    let directory = std::env::temp_dir().join(format!("vetomint-wal-{}", std::process::id()));
+    let wal = wal::WriteAheadLog::open(&directory).unwrap();
+    assert!(wal.load().unwrap().is_none());
+
+    let mut state = wal.recover(1, height_info.clone()).unwrap();
+    assert_eq!(state, ConsensusState::new(height_info.clone()));
+    wal.progress(
+        1,
+        &mut state,
+        &height_info,
+        ConsensusEvent::Start { time: 0 },
+    )
+    .unwrap();
+    let response = wal
+        .progress(
+            1,
+            &mut state,
+            &height_info,
+            ConsensusEvent::Timer { time: 1000 },
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        response,
+        vec![ConsensusResponse::BroadcastNilPrevote { round: 0 }]
+    );
+
+    // Restarted after a crash.
+    let wal = wal::WriteAheadLog::open(&directory).unwrap();
+    assert_eq!(wal.recover(1, height_info.clone()).unwrap(), state);
+    assert_eq!(
+        wal.recover(2, height_info.clone()).unwrap(),
+        ConsensusState::new(height_info.clone())
+    );
+    wal.recover(0, height_info).unwrap_err();
+    std::fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn timeout_schedule() {
+    let params = ConsensusParams {
+        timeout_propose_ms: 1000,
+        timeout_prevote_ms: 500,
+        timeout_precommit_ms: 500,
+        timeout_increase_percent: 50,
+        repeat_round_for_first_leader: 1,
+        proposer_selection: ProposerSelection::RoundRobin,
+    };
+    assert_eq!(params.timeout_propose(0), 1000);
+    assert_eq!(params.timeout_propose(2), 2250);
+    assert_eq!(params.timeout_prevote(1), 750);
+    assert_eq!(params.timeout_precommit(1000), u64::MAX);
+
+    let mut adaptive = AdaptiveTimeout::default();
+    assert_eq!(adaptive.adjust(&params), params);
+    adaptive.observe(400);
+    adaptive.observe(400);
+    assert_eq!(adaptive.average_latency_ms(), Some(400));
+    let adjusted = adaptive.adjust(&params);
+    assert_eq!(adjusted.timeout_propose_ms, 1200);
+    assert_eq!(adjusted.timeout_prevote_ms, 1200);
+    assert_eq!(adjusted.timeout_increase_percent, 50);
+}
+
+#[test]
+fn proposer_selection() {
+    let height_info = |proposer_selection, seed| HeightInfo {
+        validators: vec![3, 1, 0, 2],
+        this_node_index: 0,
+        timestamp: 0,
+        seed,
+        consensus_params: ConsensusParams {
+            timeout_propose_ms: 1000,
+            timeout_prevote_ms: 1000,
+            timeout_precommit_ms: 1000,
+            timeout_increase_percent: 0,
+            repeat_round_for_first_leader: 1,
+            proposer_selection,
+        },
+    };
+    let proposers = |height_info: &HeightInfo, rounds| {
+        (0..rounds)
+            .map(|round| decide_proposer(round, height_info))
+            .collect::<Vec<_>>()
+    };
+
+    let round_robin = height_info(ProposerSelection::RoundRobin, 0);
+    assert_eq!(proposers(&round_robin, 5), vec![0, 1, 2, 3, 0]);
+
+    // Each validator proposes as many times as its voting power.
+    for seed in 0..4 {
+        let weighted = height_info(ProposerSelection::WeightedRoundRobin, seed);
+        let mut counts = vec![0; 4];
+        for proposer in proposers(&weighted, 6) {
+            counts[proposer] += 1;
+        }
+        assert_eq!(counts, vec![3, 1, 0, 2]);
+    }
+
+    let stake = height_info(ProposerSelection::StakeProportional, 42);
+    let result = proposers(&stake, 100);
+    assert!(result.iter().all(|proposer| *proposer != 2));
+    assert_eq!(result, proposers(&stake, 100));
+    assert_ne!(
+        result,
+        proposers(&height_info(ProposerSelection::StakeProportional, 43), 100)
+    );
+}
+
+#[test]
+fn trace() {
+    use trace::*;
+    let height_info = HeightInfo {
+        validators: vec![1, 1, 1, 1],
+        this_node_index: 3,
+        timestamp: 0,
+        seed: 0,
+        consensus_params: ConsensusParams {
+            timeout_propose_ms: 1000,
+            timeout_prevote_ms: 1000,
+            timeout_precommit_ms: 1000,
+            timeout_increase_percent: 0,
+            repeat_round_for_first_leader: 1,
+            proposer_selection: ProposerSelection::RoundRobin,
+        },
+    };
+    let mut state = ConsensusState::new(height_info.clone());
+    let (_, trace) = state.progress_traced(&height_info, ConsensusEvent::Start { time: 0 });
+    assert_eq!(trace, vec![TraceEvent::RoundStarted { round: 0 }]);
+    let (_, trace) = state.progress_traced(&height_info, ConsensusEvent::Timer { time: 999 });
+    assert!(trace.is_empty());
+    let (_, trace) = state.progress_traced(&height_info, ConsensusEvent::Timer { time: 1000 });
+    assert_eq!(
+        trace,
+        vec![
+            TraceEvent::TimeoutFired {
+                step: Step::Propose,
+                round: 0
+            },
+            TraceEvent::Broadcasted {
+                step: Step::Prevote,
+                proposal: None,
+                round: 0
+            }
+        ]
+    );
+}
@@ -0,0 +1,179 @@
+//! Malicious validators for the simulation, each implementing a known attack.
+use super::*;
+
+/// Follows the protocol, but whenever it broadcasts a proposal or a vote,
+/// it sends a conflicting one to the other half of the validators.
+pub struct Equivocator {
+    pub inner: HonestValidator,
+    /// The block that the conflicting messages are for.
+    pub conflicting_block: BlockIdentifier,
+    forged: Vec<Message>,
+}
+
+impl Equivocator {
+    pub fn new(height_info: HeightInfo, conflicting_block: BlockIdentifier) -> Self {
+        Self {
+            inner: HonestValidator::new(height_info),
+            conflicting_block,
+            forged: Vec::new(),
+        }
+    }
+
+    fn conflicting(&self, message: &Message) -> Message {
+        let conflicting = Some(self.conflicting_block);
+        match message.clone() {
+            Message::Proposal {
+                proposer, round, ..
+            } => Message::Proposal {
+                proposal: self.conflicting_block,
+                proposer,
+                round,
+            },
+            Message::Prevote { signer, round, .. } => Message::Prevote {
+                proposal: conflicting,
+                signer,
+                round,
+            },
+            Message::Precommit { signer, round, .. } => Message::Precommit {
+                proposal: conflicting,
+                signer,
+                round,
+            },
+        }
+    }
+}
+
+impl Validator for Equivocator {
+    fn on_event(&mut self, event: ConsensusEvent) -> Vec<ConsensusResponse> {
+        let this = self.inner.height_info.this_node_index;
+        let mut responses = Vec::new();
+        for response in self.inner.on_event(event) {
+            // The messages are sent by `forge()` instead.
+            match Message::from_response(this, &response) {
+                Some(message) => self.forged.push(message),
+                None => responses.push(response),
+            }
+        }
+        responses
+    }
+
+    fn is_honest(&self) -> bool {
+        false
+    }
+
+    fn forge(&mut self, _event: &ConsensusEvent) -> Vec<(ValidatorIndex, Message)> {
+        let n = self.inner.height_info.validators.len();
+        let mut result = Vec::new();
+        for message in std::mem::take(&mut self.forged) {
+            let conflicting = self.conflicting(&message);
+            for to in 0..n {
+                if to % 2 == 0 {
+                    result.push((to, message.clone()));
+                } else {
+                    result.push((to, conflicting.clone()));
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Never sends anything, as if it crashed.
+pub struct Silent;
+
+impl Validator for Silent {
+    fn on_event(&mut self, _event: ConsensusEvent) -> Vec<ConsensusResponse> {
+        Vec::new()
+    }
+
+    fn is_honest(&self) -> bool {
+        false
+    }
+}
+
+/// Floods the validators with prevotes for rounds far ahead.
+pub struct FutureRoundSpammer {
+    pub this_node_index: ValidatorIndex,
+    pub validators: usize,
+    /// How many rounds ahead the votes are for.
+    pub rounds_ahead: Round,
+    pub block: BlockIdentifier,
+}
+
+impl Validator for FutureRoundSpammer {
+    fn on_event(&mut self, _event: ConsensusEvent) -> Vec<ConsensusResponse> {
+        Vec::new()
+    }
+
+    fn is_honest(&self) -> bool {
+        false
+    }
+
+    fn forge(&mut self, event: &ConsensusEvent) -> Vec<(ValidatorIndex, Message)> {
+        if !matches!(event, ConsensusEvent::Timer { .. }) {
+            return Vec::new();
+        }
+        let mut result = Vec::new();
+        for round in 1..=self.rounds_ahead {
+            for to in 0..self.validators {
+                result.push((
+                    to,
+                    Message::Prevote {
+                        proposal: Some(self.block),
+                        signer: self.this_node_index,
+                        round,
+                    },
+                ));
+            }
+        }
+        result
+    }
+}
+
+/// Proposes a block that honest validators regard as invalid, in every round it leads.
+pub struct InvalidProposer {
+    pub inner: HonestValidator,
+    /// Must not be in `Simulation::valid_blocks`.
+    pub invalid_block: BlockIdentifier,
+    forged: Vec<Message>,
+}
+
+impl InvalidProposer {
+    pub fn new(height_info: HeightInfo, invalid_block: BlockIdentifier) -> Self {
+        Self {
+            inner: HonestValidator::new(height_info),
+            invalid_block,
+            forged: Vec::new(),
+        }
+    }
+}
+
+impl Validator for InvalidProposer {
+    fn on_event(&mut self, event: ConsensusEvent) -> Vec<ConsensusResponse> {
+        let this = self.inner.height_info.this_node_index;
+        let mut responses = Vec::new();
+        for response in self.inner.on_event(event) {
+            match Message::from_response(this, &response) {
+                Some(Message::Proposal { round, .. }) => self.forged.push(Message::Proposal {
+                    proposal: self.invalid_block,
+                    proposer: this,
+                    round,
+                }),
+                _ => responses.push(response),
+            }
+        }
+        responses
+    }
+
+    fn is_honest(&self) -> bool {
+        false
+    }
+
+    fn forge(&mut self, _event: &ConsensusEvent) -> Vec<(ValidatorIndex, Message)> {
+        let n = self.inner.height_info.validators.len();
+        std::mem::take(&mut self.forged)
+            .into_iter()
+            .flat_map(|message| (0..n).map(move |to| (to, message.clone())))
+            .collect()
+    }
+}
@@ -0,0 +1,557 @@
+//! A deterministic simulation of multiple vetomint instances.
+//!
+//! Everything that could be non-deterministic in a real network is driven by the simulation:
+//! the clock is virtual, and the delays, drops and duplications of the messages
+//! are drawn from a seeded random number generator.
+//! Therefore a run is fully reproducible from its seed, and a problematic schedule
+//! (e.g., from a real incident) can be kept as a regression case.
+#![allow(dead_code)]
+
+pub mod byzantine;
+
+use std::collections::BTreeMap;
+use vetomint::*;
+
+/// A seedable pseudo-random number generator (xorshift64*).
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            // The state must not be zero.
+            state: seed ^ 0x9e3779b97f4a7c15,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Returns a number in `[low, high]`.
+    pub fn range(&mut self, low: u64, high: u64) -> u64 {
+        low + self.next_u64() % (high - low + 1)
+    }
+
+    /// Returns `true` with the given probability in per mille.
+    pub fn chance(&mut self, per_mille: u64) -> bool {
+        self.next_u64() % 1000 < per_mille
+    }
+}
+
+/// The conditions of the simulated network.
+#[derive(Debug, Clone)]
+pub struct NetworkConditions {
+    pub min_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// The probability that a message is dropped, in per mille.
+    pub drop_per_mille: u64,
+    /// The probability that a message is delivered twice, in per mille.
+    pub duplicate_per_mille: u64,
+}
+
+impl Default for NetworkConditions {
+    fn default() -> Self {
+        Self {
+            min_delay_ms: 10,
+            max_delay_ms: 100,
+            drop_per_mille: 0,
+            duplicate_per_mille: 0,
+        }
+    }
+}
+
+/// A consensus message in flight, which becomes `ConsensusEvent`s on delivery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Proposal {
+        proposal: BlockIdentifier,
+        proposer: ValidatorIndex,
+        round: Round,
+    },
+    Prevote {
+        proposal: Option<BlockIdentifier>,
+        signer: ValidatorIndex,
+        round: Round,
+    },
+    Precommit {
+        proposal: Option<BlockIdentifier>,
+        signer: ValidatorIndex,
+        round: Round,
+    },
+}
+
+impl Message {
+    /// Converts the response of a validator into the message to broadcast, if any.
+    pub fn from_response(signer: ValidatorIndex, response: &ConsensusResponse) -> Option<Self> {
+        Some(match response {
+            ConsensusResponse::BroadcastProposal { proposal, round } => Message::Proposal {
+                proposal: *proposal,
+                proposer: signer,
+                round: *round,
+            },
+            ConsensusResponse::BroadcastPrevote { proposal, round } => Message::Prevote {
+                proposal: Some(*proposal),
+                signer,
+                round: *round,
+            },
+            ConsensusResponse::BroadcastNilPrevote { round } => Message::Prevote {
+                proposal: None,
+                signer,
+                round: *round,
+            },
+            ConsensusResponse::BroadcastPrecommit { proposal, round } => Message::Precommit {
+                proposal: Some(*proposal),
+                signer,
+                round: *round,
+            },
+            ConsensusResponse::BroadcastNilPrecommit { round } => Message::Precommit {
+                proposal: None,
+                signer,
+                round: *round,
+            },
+            _ => return None,
+        })
+    }
+
+    /// Converts the message into the events for the receiver.
+    ///
+    /// `valid` tells whether the receiver is in favor of the proposal.
+    pub fn into_events(self, time: Timestamp, valid: bool) -> Vec<ConsensusEvent> {
+        match self {
+            Message::Proposal {
+                proposal,
+                proposer,
+                round,
+            } => vec![
+                ConsensusEvent::BlockProposalReceived {
+                    proposal,
+                    proposer,
+                    round,
+                    time,
+                },
+                ConsensusEvent::ProposalFavor {
+                    proposal,
+                    favor: valid,
+                    time,
+                },
+            ],
+            Message::Prevote {
+                proposal: Some(proposal),
+                signer,
+                round,
+            } => vec![ConsensusEvent::Prevote {
+                proposal,
+                signer,
+                round,
+                time,
+            }],
+            Message::Prevote {
+                proposal: None,
+                signer,
+                round,
+            } => vec![ConsensusEvent::NilPrevote {
+                signer,
+                round,
+                time,
+            }],
+            Message::Precommit {
+                proposal: Some(proposal),
+                signer,
+                round,
+            } => vec![ConsensusEvent::Precommit {
+                proposal,
+                signer,
+                round,
+                time,
+            }],
+            Message::Precommit {
+                proposal: None,
+                signer,
+                round,
+            } => vec![ConsensusEvent::NilPrecommit {
+                signer,
+                round,
+                time,
+            }],
+        }
+    }
+}
+
+/// A participant of the simulation.
+pub trait Validator {
+    /// Handles an event and returns the responses.
+    fn on_event(&mut self, event: ConsensusEvent) -> Vec<ConsensusResponse>;
+
+    /// Whether this validator follows the protocol; the assertions apply only to honest ones.
+    fn is_honest(&self) -> bool {
+        true
+    }
+
+    /// Returns the round in progress, if it runs the state machine.
+    fn round(&self) -> Option<Round> {
+        None
+    }
+
+    /// Makes the messages to send beyond the protocol, on the given event.
+    ///
+    /// Only byzantine validators forge messages (see `byzantine`).
+    fn forge(&mut self, _event: &ConsensusEvent) -> Vec<(ValidatorIndex, Message)> {
+        Vec::new()
+    }
+}
+
+/// A validator running the actual state machine.
+pub struct HonestValidator {
+    pub height_info: HeightInfo,
+    pub state: ConsensusState,
+}
+
+impl HonestValidator {
+    pub fn new(height_info: HeightInfo) -> Self {
+        Self {
+            state: ConsensusState::new(height_info.clone()),
+            height_info,
+        }
+    }
+}
+
+impl Validator for HonestValidator {
+    fn on_event(&mut self, event: ConsensusEvent) -> Vec<ConsensusResponse> {
+        self.state
+            .progress(&self.height_info, event)
+            .unwrap_or_default()
+    }
+
+    fn round(&self) -> Option<Round> {
+        Some(self.state.round())
+    }
+}
+
+/// Creates the `HeightInfo` of each validator with the given voting powers.
+pub fn height_infos(validators: &[VotingPower], params: ConsensusParams) -> Vec<HeightInfo> {
+    (0..validators.len())
+        .map(|this_node_index| HeightInfo {
+            validators: validators.to_vec(),
+            this_node_index,
+            timestamp: 0,
+            seed: 0,
+            consensus_params: params.clone(),
+        })
+        .collect()
+}
+
+pub fn default_params() -> ConsensusParams {
+    ConsensusParams {
+        timeout_propose_ms: 1000,
+        timeout_prevote_ms: 1000,
+        timeout_precommit_ms: 1000,
+        timeout_increase_percent: 0,
+        repeat_round_for_first_leader: 1,
+        proposer_selection: ProposerSelection::RoundRobin,
+    }
+}
+
+/// Something that happened in the simulation, recorded in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    Delivered {
+        time: Timestamp,
+        to: ValidatorIndex,
+        message: Message,
+    },
+    Dropped {
+        time: Timestamp,
+        to: ValidatorIndex,
+        message: Message,
+    },
+    Responded {
+        time: Timestamp,
+        from: ValidatorIndex,
+        response: ConsensusResponse,
+    },
+}
+
+enum Scheduled {
+    Deliver {
+        to: ValidatorIndex,
+        message: Message,
+    },
+    ProposalCreated {
+        to: ValidatorIndex,
+        round: Round,
+    },
+}
+
+pub struct Simulation {
+    pub validators: Vec<Box<dyn Validator>>,
+    pub conditions: NetworkConditions,
+    /// The interval of the `Timer` events given to every validator.
+    pub tick_ms: u64,
+    /// The blocks that honest validators regard as valid.
+    pub valid_blocks: Vec<BlockIdentifier>,
+    pub rng: Rng,
+    pub time: Timestamp,
+    pub records: Vec<Record>,
+    /// The finalized block of each validator, if any.
+    pub finalized: Vec<Option<BlockIdentifier>>,
+    queue: BTreeMap<(Timestamp, u64), Scheduled>,
+    sequence: u64,
+}
+
+impl Simulation {
+    pub fn new(validators: Vec<Box<dyn Validator>>, seed: u64) -> Self {
+        let n = validators.len();
+        Self {
+            validators,
+            conditions: NetworkConditions::default(),
+            tick_ms: 100,
+            // The block identifier of a proposal is its proposer by default.
+            valid_blocks: (0..n).collect(),
+            rng: Rng::new(seed),
+            time: 0,
+            records: Vec::new(),
+            finalized: vec![None; n],
+            queue: BTreeMap::new(),
+            sequence: 0,
+        }
+    }
+
+    /// Creates a simulation of honest validators with the given voting powers.
+    pub fn honest(validators: &[VotingPower], params: ConsensusParams, seed: u64) -> Self {
+        Self::new(
+            height_infos(validators, params)
+                .into_iter()
+                .map(|height_info| {
+                    Box::new(HonestValidator::new(height_info)) as Box<dyn Validator>
+                })
+                .collect(),
+            seed,
+        )
+    }
+
+    fn schedule(&mut self, time: Timestamp, item: Scheduled) {
+        self.queue.insert((time, self.sequence), item);
+        self.sequence += 1;
+    }
+
+    /// Sends the message to every validator (including the sender itself),
+    /// under the network conditions.
+    pub fn broadcast(&mut self, message: Message) {
+        for to in 0..self.validators.len() {
+            self.send(to, message.clone());
+        }
+    }
+
+    /// Sends the message to a single validator, under the network conditions.
+    pub fn send(&mut self, to: ValidatorIndex, message: Message) {
+        if self.rng.chance(self.conditions.drop_per_mille) {
+            self.records.push(Record::Dropped {
+                time: self.time,
+                to,
+                message,
+            });
+            return;
+        }
+        let copies = if self.rng.chance(self.conditions.duplicate_per_mille) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let delay = self
+                .rng
+                .range(self.conditions.min_delay_ms, self.conditions.max_delay_ms);
+            self.schedule(
+                self.time + delay as Timestamp,
+                Scheduled::Deliver {
+                    to,
+                    message: message.clone(),
+                },
+            );
+        }
+    }
+
+    fn handle(&mut self, from: ValidatorIndex, event: ConsensusEvent) {
+        let responses = self.validators[from].on_event(event.clone());
+        for (to, message) in self.validators[from].forge(&event) {
+            self.send(to, message);
+        }
+        for response in responses {
+            self.records.push(Record::Responded {
+                time: self.time,
+                from,
+                response: response.clone(),
+            });
+            match &response {
+                ConsensusResponse::CreateProposal { round } => {
+                    // The proposal is created right away.
+                    self.schedule(
+                        self.time,
+                        Scheduled::ProposalCreated {
+                            to: from,
+                            round: *round,
+                        },
+                    );
+                }
+                ConsensusResponse::FinalizeBlock { proposal } => {
+                    self.finalized[from] = Some(*proposal);
+                }
+                response => {
+                    if let Some(message) = Message::from_response(from, response) {
+                        self.broadcast(message);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Starts every validator at the current time.
+    pub fn start(&mut self) {
+        for index in 0..self.validators.len() {
+            self.handle(index, ConsensusEvent::Start { time: self.time });
+        }
+    }
+
+    /// Runs the simulation until the given time, ticking the timers of the validators.
+    pub fn run_until(&mut self, until: Timestamp) {
+        let mut next_tick = self.time + self.tick_ms as Timestamp;
+        loop {
+            let next_event = self.queue.keys().next().map(|(time, _)| *time);
+            match next_event {
+                Some(time) if time <= next_tick && time <= until => {
+                    let key = *self.queue.keys().next().unwrap();
+                    let item = self.queue.remove(&key).unwrap();
+                    self.time = time;
+                    match item {
+                        Scheduled::Deliver { to, message } => {
+                            self.records.push(Record::Delivered {
+                                time,
+                                to,
+                                message: message.clone(),
+                            });
+                            let valid = match &message {
+                                Message::Proposal { proposal, .. } => {
+                                    self.valid_blocks.contains(proposal)
+                                }
+                                _ => true,
+                            };
+                            for event in message.into_events(time, valid) {
+                                self.handle(to, event);
+                            }
+                        }
+                        Scheduled::ProposalCreated { to, round } => self.handle(
+                            to,
+                            ConsensusEvent::BlockProposalCreated {
+                                proposal: to,
+                                round,
+                                time,
+                            },
+                        ),
+                    }
+                }
+                _ if next_tick <= until => {
+                    self.time = next_tick;
+                    for index in 0..self.validators.len() {
+                        self.handle(index, ConsensusEvent::Timer { time: next_tick });
+                    }
+                    next_tick += self.tick_ms as Timestamp;
+                }
+                _ => {
+                    self.time = until;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Asserts that no two honest validators finalized different blocks.
+    pub fn assert_safety(&self) {
+        let finalized = self
+            .finalized
+            .iter()
+            .zip(&self.validators)
+            .filter(|(_, validator)| validator.is_honest())
+            .filter_map(|(block, _)| *block)
+            .collect::<Vec<_>>();
+        assert!(
+            finalized.windows(2).all(|pair| pair[0] == pair[1]),
+            "honest validators finalized different blocks: {:?}",
+            finalized
+        );
+    }
+
+    /// Returns the validators who signed conflicting messages that any honest validator received,
+    /// which are to be reported as evidence.
+    pub fn detected_equivocations(&self) -> Vec<ValidatorIndex> {
+        let mut received: BTreeMap<(ValidatorIndex, Round, u8), Vec<Option<BlockIdentifier>>> =
+            BTreeMap::new();
+        for record in &self.records {
+            if let Record::Delivered { to, message, .. } = record {
+                if !self.validators[*to].is_honest() {
+                    continue;
+                }
+                let (key, proposal) = match message {
+                    Message::Proposal {
+                        proposal,
+                        proposer,
+                        round,
+                    } => ((*proposer, *round, 0), Some(*proposal)),
+                    Message::Prevote {
+                        proposal,
+                        signer,
+                        round,
+                    } => ((*signer, *round, 1), *proposal),
+                    Message::Precommit {
+                        proposal,
+                        signer,
+                        round,
+                    } => ((*signer, *round, 2), *proposal),
+                };
+                received.entry(key).or_default().push(proposal);
+            }
+        }
+        let mut equivocators = received
+            .into_iter()
+            .filter(|(_, proposals)| proposals.iter().any(|p| *p != proposals[0]))
+            .map(|((signer, _, _), _)| signer)
+            .collect::<Vec<_>>();
+        equivocators.dedup();
+        equivocators
+    }
+
+    /// Returns the violators reported by the honest validators.
+    pub fn reported_violators(&self) -> Vec<ValidatorIndex> {
+        let mut violators = self
+            .records
+            .iter()
+            .filter_map(|record| match record {
+                Record::Responded {
+                    from,
+                    response: ConsensusResponse::ViolationReport { violator, .. },
+                    ..
+                } if self.validators[*from].is_honest() => Some(*violator),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        violators.sort_unstable();
+        violators.dedup();
+        violators
+    }
+
+    /// Asserts that every honest validator finalized a block.
+    pub fn assert_liveness(&self) {
+        for (index, (block, validator)) in self.finalized.iter().zip(&self.validators).enumerate() {
+            assert!(
+                !validator.is_honest() || block.is_some(),
+                "honest validator {} did not finalize",
+                index
+            );
+        }
+    }
+}
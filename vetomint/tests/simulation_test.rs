@@ -0,0 +1,173 @@
+mod simulation;
+
+use simulation::*;
+use vetomint::*;
+
+fn run(seed: u64) -> Simulation {
+    let mut simulation = Simulation::honest(&[1, 1, 1, 1], default_params(), seed);
+    simulation.conditions = NetworkConditions {
+        min_delay_ms: 10,
+        max_delay_ms: 500,
+        drop_per_mille: 100,
+        duplicate_per_mille: 0,
+    };
+    simulation.start();
+    simulation.run_until(3000);
+    simulation
+}
+
+#[test]
+fn reproducible() {
+    let first = run(7);
+    let second = run(7);
+    assert_eq!(first.records, second.records);
+    assert_eq!(first.time, 3000);
+    // Some messages are dropped, but the round still makes progress.
+    assert!(first
+        .records
+        .iter()
+        .any(|record| matches!(record, Record::Dropped { .. })));
+    assert!(first.finalized.iter().any(Option::is_some));
+    first.assert_safety();
+}
+
+#[test]
+fn finalize_under_delays_and_duplications() {
+    for seed in 0..16 {
+        let mut simulation = Simulation::honest(&[1, 1, 1, 1], default_params(), seed);
+        simulation.conditions.duplicate_per_mille = 100;
+        simulation.start();
+        simulation.run_until(10_000);
+        simulation.assert_safety();
+        simulation.assert_liveness();
+    }
+}
+
+fn with_byzantine(validator: Box<dyn Validator>, seed: u64) -> Simulation {
+    let mut validators = height_infos(&[1, 1, 1, 1], default_params())
+        .into_iter()
+        .take(3)
+        .map(|height_info| Box::new(HonestValidator::new(height_info)) as Box<dyn Validator>)
+        .collect::<Vec<_>>();
+    validators.push(validator);
+    Simulation::new(validators, seed)
+}
+
+#[test]
+fn future_round_spammer() {
+    let mut simulation = with_byzantine(
+        Box::new(byzantine::FutureRoundSpammer {
+            this_node_index: 3,
+            validators: 4,
+            rounds_ahead: 10,
+            block: 0,
+        }),
+        1,
+    );
+    simulation.start();
+    // Before any timeout fires.
+    simulation.run_until(900);
+    simulation.assert_safety();
+    assert!(simulation.detected_equivocations().is_empty());
+    // The spam for the future rounds must not trigger any vote for them.
+    assert!(!simulation.records.iter().any(|record| matches!(
+        record,
+        Record::Responded { from, response, .. } if *from < 3 && matches!(
+            Message::from_response(*from, response),
+            Some(Message::Prevote { round, .. } | Message::Precommit { round, .. }) if round != 0
+        )
+    )));
+}
+
+fn with_equivocator(seed: u64) -> Simulation {
+    let height_info = height_infos(&[1, 1, 1, 1], default_params()).remove(3);
+    let mut simulation =
+        with_byzantine(Box::new(byzantine::Equivocator::new(height_info, 1)), seed);
+    simulation.start();
+    simulation.run_until(10_000);
+    simulation
+}
+
+#[test]
+fn equivocator() {
+    for seed in 0..16 {
+        let simulation = with_equivocator(seed);
+        simulation.assert_safety();
+        simulation.assert_liveness();
+        assert_eq!(simulation.detected_equivocations(), vec![3]);
+    }
+}
+
+#[ignore = "the equivocator sends each version to a different half, and honest validators \
+            don't relay the messages, so none of them receives both to report"]
+#[test]
+fn equivocator_reported() {
+    for seed in 0..16 {
+        let simulation = with_equivocator(seed);
+        simulation.assert_safety();
+        assert_eq!(simulation.reported_violators(), vec![3]);
+    }
+}
+
+#[test]
+fn silent_node() {
+    for seed in 0..16 {
+        let mut simulation = with_byzantine(Box::new(byzantine::Silent), seed);
+        simulation.start();
+        simulation.run_until(10_000);
+        simulation.assert_safety();
+        simulation.assert_liveness();
+    }
+}
+
+#[test]
+fn invalid_proposer() {
+    for seed in 0..16 {
+        let mut params = default_params();
+        params.repeat_round_for_first_leader = 0;
+        let height_info = height_infos(&[1, 1, 1, 1], params).remove(3);
+        let mut simulation = with_byzantine(
+            Box::new(byzantine::InvalidProposer::new(height_info, 100)),
+            seed,
+        );
+        simulation.start();
+        simulation.run_until(10_000);
+        simulation.assert_safety();
+        simulation.assert_liveness();
+        assert!(simulation.finalized.iter().all(|block| *block != Some(100)));
+    }
+}
+
+#[test]
+fn catch_up_to_later_round() {
+    let spammer = |this_node_index| {
+        Box::new(byzantine::FutureRoundSpammer {
+            this_node_index,
+            validators: 4,
+            rounds_ahead: 10,
+            block: 0,
+        }) as Box<dyn Validator>
+    };
+    let height_infos = height_infos(&[1, 1, 1, 1], default_params());
+    let validators = vec![
+        spammer(0),
+        Box::new(HonestValidator::new(height_infos[1].clone())) as Box<dyn Validator>,
+        Box::new(HonestValidator::new(height_infos[2].clone())),
+        spammer(3),
+    ];
+    let mut simulation = Simulation::new(validators, 3);
+    simulation.start();
+    simulation.run_until(900);
+    // More than 1/3 of the voting power is in round 10, so the honest ones skip to it
+    // without waiting for the timeouts of the rounds in between.
+    assert_eq!(simulation.validators[1].round(), Some(10));
+    assert_eq!(simulation.validators[2].round(), Some(10));
+    assert!(!simulation.records.iter().any(|record| matches!(
+        record,
+        Record::Responded {
+            from,
+            response: ConsensusResponse::BroadcastNilPrevote { .. },
+            ..
+        } if *from == 1 || *from == 2
+    )));
+}